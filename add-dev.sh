@@ -1,12 +1,21 @@
 #!/bin/bash
 
+# Usage: add-dev.sh [slot]
+# Slot 0 (the default) is the primary device, /dev/adxl345; the other devices bound by the
+# driver are /dev/adxl345-1 to /dev/adxl345-3, the minor number is their slot
+
 # Variables
-DEVICE_NAME="adxl345"  # Replace with your device name
+SLOT=${1:-0}
+if [ "$SLOT" -eq 0 ]; then
+    DEVICE_NAME="adxl345"
+else
+    DEVICE_NAME="adxl345-$SLOT"
+fi
 DEVICE_PATH="/dev/$DEVICE_NAME"
-MINOR_NUMBER=0  # Replace with the desired minor number
+MINOR_NUMBER=$SLOT
 
-# Check if the device name is provided in /proc/devices
-MAJOR_NUMBER=$(grep "$DEVICE_NAME" /proc/devices | awk '{print $1}')
+# Check if the device name is provided in /proc/devices, each device has its own major
+MAJOR_NUMBER=$(awk -v name="$DEVICE_NAME" '$2 == name {print $1}' /proc/devices)
 
 if [ -z "$MAJOR_NUMBER" ]; then
     echo "Error: Major number for device '$DEVICE_NAME' not found in /proc/devices."
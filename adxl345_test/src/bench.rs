@@ -0,0 +1,306 @@
+//! Latency benchmark of the read path.
+//!
+//! Streams from the device for a fixed time in each read mode and with several read sizes, and
+//! prints one JSON object per run, so reports of two driver versions can be compared with `jq` or
+//! a spreadsheet. Each run measures the latency of every read syscall, the achieved sample rate
+//! and the samples dropped by the driver, taken from debugfs when it is mounted.
+//!
+//! The high-rate run checks that the driver keeps up with the highest output data rates, 3200
+//! and 1600 Hz: it streams with large reads, as a capture does, and compares the samples
+//! received with the ones the rate promises over the same time. The bus bytes each sample cost
+//! give the highest rate a 400 kHz I2C bus could carry, the wire time alone.
+
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::mem;
+use std::time::{Duration, Instant};
+
+use libadxl345::abi::*;
+use libadxl345::{Adxl345Device, Config, Param};
+
+/// Debugfs directory of the driver.
+const DEBUGFS_DIR: &str = "/sys/kernel/debug/adxl345";
+
+/// Rates of the high-rate runs, in mHz.
+const HIGH_RATES_MHZ: [u32; 2] = [3_200_000, 1_600_000];
+
+/// Records per read of the high-rate runs, enough for the reader to drain the device itself.
+const HIGH_RATE_READ_RECORDS: usize = 256;
+
+/// Share of the promised samples a high-rate run must deliver.
+const HIGH_RATE_DELIVERY: f64 = 0.999;
+
+/// Bytes per second on a 400 kHz I2C bus, 9 bits per byte with its ACK.
+const I2C_400KHZ_BYTES_PER_S: f64 = 400_000.0 / 9.0;
+
+/// Read sizes, in records.
+const READ_RECORDS: [usize; 3] = [1, 16, 128];
+
+/// Read modes.
+#[derive(Clone, Copy)]
+enum Mode {
+    Blocking,
+    Nonblocking,
+    Poll,
+    Mmap,
+}
+
+impl Mode {
+    const ALL: [Mode; 4] = [Mode::Blocking, Mode::Nonblocking, Mode::Poll, Mode::Mmap];
+
+    fn name(self) -> &'static str {
+        match self {
+            Mode::Blocking => "blocking",
+            Mode::Nonblocking => "nonblocking",
+            Mode::Poll => "poll",
+            Mode::Mmap => "mmap",
+        }
+    }
+}
+
+/// Result of a run.
+#[derive(Default)]
+struct Run {
+    reads: u64,
+    again: u64,
+    records: u64,
+    samples: u64,
+    elapsed: Duration,
+    latencies_ns: Vec<u64>,
+    dropped: Option<u64>,
+}
+
+/// Reads a debugfs counter of the driver, `None` if debugfs is not available.
+fn counter(name: &str) -> Option<u64> {
+    fs::read_to_string(format!("{}/{}", DEBUGFS_DIR, name)).ok()?.trim().parse().ok()
+}
+
+/// Reads the debugfs drop counter, `None` if debugfs is not available.
+fn dropped() -> Option<u64> {
+    counter("samples_dropped")
+}
+
+/// Returns the increase of a debugfs counter since `before`, `null` if it isn't available.
+fn increase(name: &str, before: Option<u64>) -> String {
+    match (before, counter(name)) {
+        (Some(before), Some(after)) => after.saturating_sub(before).to_string(),
+        _ => "null".to_string(),
+    }
+}
+
+/// Reads the bus bytes counted since the bus usage was reset, `None` without debugfs.
+fn bus_bytes() -> Option<u64> {
+    let text = fs::read_to_string(format!("{}/bus_usage", DEBUGFS_DIR)).ok()?;
+    text.lines().find_map(|line| line.strip_prefix("bus_bytes ")?.trim().parse().ok())
+}
+
+/// Counts the samples of `records`, markers aside.
+fn count_samples(records: &[Adxl345Sample]) -> u64 {
+    records.iter().filter(|s| s.x != ADXL345_MARKER_TAG).count() as u64
+}
+
+/// Returns the value at `pct` percent of the sorted latencies.
+pub fn percentile(sorted: &[u64], pct: usize) -> u64 {
+    if sorted.is_empty() {
+        return 0;
+    }
+    sorted[(sorted.len() - 1) * pct / 100]
+}
+
+/// Streams for `window` in the given mode, `records` records per read.
+fn run_once(path: &CString, mode: Mode, records: usize, window: Duration) -> Result<Run, String> {
+    let flags = match mode {
+        Mode::Nonblocking | Mode::Poll => libc::O_RDONLY | libc::O_NONBLOCK,
+        _ => libc::O_RDONLY,
+    };
+    let fd = unsafe { libc::open(path.as_ptr(), flags) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error().to_string());
+    }
+
+    let result = (|| {
+        // Start every run from an empty buffer
+        if unsafe { libc::ioctl(fd, ADXL345_IOC_FLUSH as _) } < 0 {
+            return Err(format!("FLUSH failed: {}", io::Error::last_os_error()));
+        }
+
+        let mut buf = vec![Adxl345Sample::default(); records];
+        let mut run = Run::default();
+        let dropped_before = dropped();
+        let start = Instant::now();
+
+        while start.elapsed() < window {
+            if let Mode::Poll = mode {
+                let mut pollfd = libc::pollfd { fd, events: libc::POLLIN, revents: 0 };
+                let ret = unsafe { libc::poll(&mut pollfd, 1, 1000) };
+                if ret < 0 {
+                    return Err(format!("poll failed: {}", io::Error::last_os_error()));
+                }
+                if ret == 0 {
+                    continue;
+                }
+            }
+
+            let begin = Instant::now();
+            let ret = unsafe {
+                libc::read(fd, buf.as_mut_ptr() as *mut libc::c_void, mem::size_of_val(buf.as_slice()))
+            };
+            run.latencies_ns.push(begin.elapsed().as_nanos() as u64);
+            run.reads += 1;
+
+            if ret < 0 {
+                match io::Error::last_os_error().raw_os_error() {
+                    Some(libc::EAGAIN) => run.again += 1,
+                    _ => return Err(format!("read failed: {}", io::Error::last_os_error())),
+                }
+                continue;
+            }
+
+            let n = ret as usize / mem::size_of::<Adxl345Sample>();
+            run.records += n as u64;
+            run.samples += count_samples(&buf[..n]);
+        }
+
+        run.elapsed = start.elapsed();
+        run.dropped = match (dropped_before, dropped()) {
+            (Some(before), Some(after)) => Some(after.saturating_sub(before)),
+            _ => None,
+        };
+        Ok(run)
+    })();
+
+    unsafe { libc::close(fd) };
+    result
+}
+
+/// Runs the benchmark matrix on the device at `file_path`, each run lasting `window`.
+///
+/// Returns false if any run failed.
+pub fn run(file_path: &str, window: Duration) -> bool {
+    let path = match CString::new(file_path) {
+        Ok(path) => path,
+        Err(_) => return false,
+    };
+    let mut ok = true;
+
+    for mode in Mode::ALL {
+        for records in READ_RECORDS {
+            let prefix = format!("{{\"mode\":\"{}\",\"read_records\":{}", mode.name(), records);
+
+            // The driver doesn't implement mmap, the run is reported so reports stay comparable
+            if let Mode::Mmap = mode {
+                println!("{},\"status\":\"skipped\",\"reason\":\"mmap not supported by the driver\"}}", prefix);
+                continue;
+            }
+
+            match run_once(&path, mode, records, window) {
+                Ok(mut run) => {
+                    run.latencies_ns.sort_unstable();
+                    let secs = run.elapsed.as_secs_f64();
+                    let dropped = run.dropped.map(|d| d.to_string()).unwrap_or_else(|| "null".to_string());
+                    println!(
+                        "{},\"status\":\"ok\",\"seconds\":{:.3},\"reads\":{},\"eagain\":{},\"records\":{},\"samples\":{},\"rate_hz\":{:.1},\"dropped\":{},\"latency_ns\":{{\"min\":{},\"p50\":{},\"p99\":{},\"max\":{}}}}}",
+                        prefix, secs, run.reads, run.again, run.records, run.samples,
+                        run.samples as f64 / secs, dropped,
+                        percentile(&run.latencies_ns, 0), percentile(&run.latencies_ns, 50),
+                        percentile(&run.latencies_ns, 99), percentile(&run.latencies_ns, 100)
+                    );
+                }
+                Err(e) => {
+                    ok = false;
+                    println!("{},\"status\":\"failed\",\"reason\":\"{}\"}}", prefix, e.replace('"', "'"));
+                }
+            }
+        }
+    }
+    ok
+}
+
+/// Streams at `rate_mhz` for `window` with large reads and prints the report line of the run.
+///
+/// Returns `Ok(true)` if the run delivered enough samples.
+fn high_rate_once(device: &Adxl345Device, rate_mhz: u32, window: Duration) -> io::Result<bool> {
+    device.configure(&Config::new().rate_mhz(rate_mhz))?;
+    let rate_hz = device.param(Param::Rate)? as f64 / 1000.0;
+    let before = ["samples_dropped", "samples_filtered", "fifo_full"].map(counter);
+    let _ = fs::write(format!("{}/bus_usage", DEBUGFS_DIR), "0");
+
+    // Count from an empty buffer and an empty FIFO
+    device.flush()?;
+    let start = Instant::now();
+    let mut buf = vec![Adxl345Sample::default(); HIGH_RATE_READ_RECORDS];
+    let mut samples = 0;
+    while start.elapsed() < window {
+        let n = device.read_records(&mut buf)?;
+        samples += count_samples(&buf[..n]);
+    }
+
+    // Collect everything acquired up to now, still in the device or in the kernel buffer
+    let elapsed = start.elapsed();
+    device.drain()?;
+    while device.readable_bytes()? > 0 {
+        let n = device.read_records(&mut buf)?;
+        samples += count_samples(&buf[..n]);
+    }
+
+    // Samples discarded by the read filter were delivered by the driver all the same
+    let filtered = match (before[1], counter("samples_filtered")) {
+        (Some(before), Some(after)) => after.saturating_sub(before),
+        _ => 0,
+    };
+    let expected = rate_hz * elapsed.as_secs_f64();
+    let delivery = (samples + filtered) as f64 / expected;
+    let passed = delivery >= HIGH_RATE_DELIVERY;
+
+    // The wire time of the bus traffic, to tell how close a 400 kHz I2C bus is to its ceiling
+    let (per_sample, ceiling) = match bus_bytes() {
+        Some(bytes) if samples > 0 => {
+            let per_sample = bytes as f64 / (samples + filtered) as f64;
+            (format!("{:.2}", per_sample), format!("{:.0}", I2C_400KHZ_BYTES_PER_S / per_sample))
+        }
+        _ => ("null".to_string(), "null".to_string()),
+    };
+
+    println!(
+        "{{\"mode\":\"high_rate\",\"rate_hz\":{:.1},\"read_records\":{},\"status\":\"ok\",\"seconds\":{:.3},\"samples\":{},\"expected\":{:.0},\"filtered\":{},\"dropped\":{},\"fifo_full\":{},\"delivery_pct\":{:.3},\"bus_bytes_per_sample\":{},\"i2c_400khz_ceiling_hz\":{},\"pass\":{}}}",
+        rate_hz, HIGH_RATE_READ_RECORDS, elapsed.as_secs_f64(), samples, expected,
+        increase("samples_filtered", before[1]), increase("samples_dropped", before[0]),
+        increase("fifo_full", before[2]), delivery * 100.0, per_sample, ceiling, passed
+    );
+    Ok(passed)
+}
+
+/// Runs the high-rate benchmark on the device at `file_path`, each rate for `window`, and
+/// restores the rate it found.
+///
+/// Returns false if a run failed or delivered less than 99.9% of the samples.
+pub fn high_rate(file_path: &str, window: Duration) -> bool {
+    let device = match Adxl345Device::open(file_path) {
+        Ok(device) => device,
+        Err(e) => {
+            println!("{{\"mode\":\"high_rate\",\"status\":\"failed\",\"reason\":\"{}\"}}", e.to_string().replace('"', "'"));
+            return false;
+        }
+    };
+    let rate = device.param(Param::Rate).ok();
+    let mut ok = true;
+
+    for rate_mhz in HIGH_RATES_MHZ {
+        match high_rate_once(&device, rate_mhz, window) {
+            Ok(passed) => ok &= passed,
+            Err(e) => {
+                ok = false;
+                println!(
+                    "{{\"mode\":\"high_rate\",\"rate_hz\":{:.1},\"status\":\"failed\",\"reason\":\"{}\"}}",
+                    rate_mhz as f64 / 1000.0, e.to_string().replace('"', "'")
+                );
+            }
+        }
+    }
+
+    if let Some(rate) = rate {
+        let _ = device.set_param(Param::Rate, rate);
+    }
+    ok
+}
@@ -0,0 +1,280 @@
+//! Raw capture to a file and decoding of captures to CSV.
+//!
+//! A capture is the byte stream returned by the device, saved as is: 6-byte records, samples
+//! and markers, little endian like the targets the driver runs on. The same format is produced by
+//! `dd if=/dev/adxl345 of=capture.bin bs=96`.
+//!
+//! While capturing, the main thread only reads from the device. Full chunks are handed over to a
+//! writer thread, and two chunks are recycled between them, so a slow disk write doesn't delay the
+//! next read and the kernel buffer doesn't overflow.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::mem;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use libadxl345::{Adxl345Device, Adxl345Header, Adxl345Sample, Record, StreamDecoder, RECORD_SIZE};
+use libadxl345::integrity::verify_batch;
+
+/// Records requested by each read.
+const READ_RECORDS: usize = 64;
+
+/// Size of a chunk handed to the writer thread: about 3 s of data at 3200 Hz.
+const CHUNK_SIZE: usize = 4096 * RECORD_SIZE;
+
+/// Number of chunks recycled between the reader and the writer.
+const CHUNKS: usize = 2;
+
+/// Parses a duration like `60s`, `500ms` or `2m`, a plain number is in seconds.
+pub fn parse_duration(text: &str) -> Option<Duration> {
+    let (value, unit) = match text.find(|c: char| !c.is_ascii_digit()) {
+        Some(pos) => text.split_at(pos),
+        None => (text, "s"),
+    };
+    let value: u64 = value.parse().ok()?;
+    match unit {
+        "ms" => Some(Duration::from_millis(value)),
+        "s" => Some(Duration::from_secs(value)),
+        "m" => Some(Duration::from_secs(value * 60)),
+        "h" => Some(Duration::from_secs(value * 3600)),
+        _ => None,
+    }
+}
+
+/// Copies the stream of `device` into the file at `path`, until `duration` elapses or forever.
+///
+/// Returns the number of bytes captured.
+pub fn record(device: &Adxl345Device, path: &str, duration: Option<Duration>) -> io::Result<u64> {
+    let mut output = File::create(path)?;
+
+    // Full chunks go to the writer, which sends them back empty
+    let (full_tx, full_rx) = mpsc::sync_channel::<Vec<u8>>(CHUNKS);
+    let (free_tx, free_rx) = mpsc::channel::<Vec<u8>>();
+    for _ in 0..CHUNKS {
+        free_tx.send(Vec::with_capacity(CHUNK_SIZE)).unwrap();
+    }
+
+    let writer = thread::spawn(move || -> io::Result<()> {
+        for mut chunk in full_rx {
+            output.write_all(&chunk)?;
+            chunk.clear();
+            // The reader is gone once the last chunk is sent
+            let _ = free_tx.send(chunk);
+        }
+        output.sync_all()
+    });
+
+    let deadline = duration.map(|d| Instant::now() + d);
+    let mut buf = [Adxl345Sample::default(); READ_RECORDS];
+    let mut chunk = free_rx.recv().unwrap();
+    let mut total = 0u64;
+
+    let result = loop {
+        if deadline.is_some_and(|d| Instant::now() >= d) {
+            break Ok(());
+        }
+
+        let n = match device.read_records(&mut buf) {
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => break Err(e),
+        };
+        if verify_batch(&buf[..n]) == Some(false) {
+            break Err(io::Error::new(io::ErrorKind::InvalidData, "batch CRC mismatch"));
+        }
+        for record in &buf[..n] {
+            chunk.extend_from_slice(&record.to_ne_bytes());
+        }
+        total += (n * RECORD_SIZE) as u64;
+
+        if chunk.len() + READ_RECORDS * RECORD_SIZE > CHUNK_SIZE {
+            // Both fail only if the writer stopped, its error is reported below
+            if full_tx.send(mem::take(&mut chunk)).is_err() {
+                break Ok(());
+            }
+            chunk = match free_rx.recv() {
+                Ok(chunk) => chunk,
+                Err(_) => break Ok(()),
+            };
+        }
+    };
+
+    // Hand over what is left and wait for the writer to drain
+    if !chunk.is_empty() {
+        let _ = full_tx.send(chunk);
+    }
+    drop(full_tx);
+    writer.join().map_err(|_| io::Error::other("writer thread panicked"))??;
+    result?;
+    Ok(total)
+}
+
+/// Records of a capture, in the order they were read from the device.
+///
+/// In real time, each sample is returned when it was acquired, at the rate of the last session
+/// header; before the first header, or without pacing, records are returned as fast as possible.
+pub struct Replay {
+    data: Vec<u8>,
+    pos: usize,
+    decoder: StreamDecoder,
+    realtime: bool,
+    /// Rate of the session in mHz, start of the session and samples replayed since.
+    pace: Option<(u32, Instant, u64)>,
+}
+
+impl Replay {
+    /// Loads the capture at `path`.
+    pub fn open(path: &str, realtime: bool) -> io::Result<Self> {
+        let data = std::fs::read(path)?;
+        if !data.len().is_multiple_of(RECORD_SIZE) {
+            eprintln!("{}: ignoring {} trailing bytes", path, data.len() % RECORD_SIZE);
+        }
+        Ok(Replay { data, pos: 0, decoder: StreamDecoder::new(), realtime, pace: None })
+    }
+}
+
+impl Iterator for Replay {
+    type Item = io::Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let bytes = self.data.get(self.pos..self.pos + RECORD_SIZE)?;
+            self.pos += RECORD_SIZE;
+            let record = match self.decoder.push(Adxl345Sample::from_le_bytes(bytes.try_into().unwrap())) {
+                Some(record) => record,
+                None => continue,
+            };
+
+            match record {
+                Record::Header(header) if self.realtime && header.rate_mhz > 0 => {
+                    self.pace = Some((header.rate_mhz, Instant::now(), 0));
+                }
+                Record::Sample(_) => {
+                    if let Some((rate_mhz, start, index)) = &mut self.pace {
+                        let due = start.checked_add(Duration::from_nanos(*index * 1_000_000_000_000 / *rate_mhz as u64));
+                        if let Some(wait) = due.and_then(|due| due.checked_duration_since(Instant::now())) {
+                            thread::sleep(wait);
+                        }
+                        *index += 1;
+                    }
+                }
+                _ => {}
+            }
+            return Some(Ok(record));
+        }
+    }
+}
+
+/// Converts a capture into CSV, written to `output` or to stdout.
+///
+/// Every sample becomes a row. `session` counts the session headers seen so far, `index` restarts
+/// at each of them. `time_ns` is derived from the start timestamp and the rate of the header, so
+/// it is empty for captures without one (stream version 0). `sync` holds the sequence number of
+/// the sync pulse marked just before the sample, if any. `clip` holds the mask of the clipped axes
+/// (bit 0 x, bit 1 y, bit 2 z) when the sample sits on a rail of the range.
+pub fn decode(input: &str, output: Option<&str>) -> io::Result<()> {
+    let data = std::fs::read(input)?;
+    if !data.len().is_multiple_of(RECORD_SIZE) {
+        eprintln!("{}: ignoring {} trailing bytes", input, data.len() % RECORD_SIZE);
+    }
+
+    let out: Box<dyn Write> = match output {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(io::stdout().lock()),
+    };
+    let mut out = BufWriter::new(out);
+    writeln!(out, "session,index,time_ns,x,y,z,sync,clip")?;
+
+    let mut decoder = StreamDecoder::new();
+    let mut session = 0u32;
+    let mut header: Option<Adxl345Header> = None;
+    let mut index = 0u64;
+    let mut sync: Option<u16> = None;
+    let mut clip: Option<u8> = None;
+    let mut unknown = 0u64;
+
+    for bytes in data.chunks_exact(RECORD_SIZE) {
+        let record = match decoder.push(Adxl345Sample::from_le_bytes(bytes.try_into().unwrap())) {
+            Some(record) => record,
+            None => continue,
+        };
+
+        let sample = match record {
+            Record::Sample(sample) => sample,
+            Record::Sync(sequence) => {
+                sync = Some(sequence);
+                continue;
+            }
+            Record::Clip(axes) => {
+                clip = Some(axes);
+                continue;
+            }
+            Record::Range(range) => {
+                writeln!(out, "# range {} g from index {}", range, index)?;
+                continue;
+            }
+            Record::Error(errno) => {
+                writeln!(out, "# bus error {} before index {}, samples may be missing", errno, index)?;
+                continue;
+            }
+            Record::BurstEnd(samples) => {
+                writeln!(out, "# burst of {} samples ends before index {}", samples, index)?;
+                continue;
+            }
+            Record::Event(id) => {
+                writeln!(out, "# event {} before index {}", id, index)?;
+                continue;
+            }
+            Record::Limit(axes) => {
+                writeln!(out, "# limit reached on axes {:#05b} at index {}", axes, index)?;
+                continue;
+            }
+            Record::Tap { double, axes, .. } => {
+                let kind = if double { "double tap" } else { "tap" };
+                writeln!(out, "# {} on axes {:#05b} before index {}", kind, axes, index)?;
+                continue;
+            }
+            Record::Header(decoded) => {
+                session += 1;
+                writeln!(
+                    out,
+                    "# session {}: stream v{}, {} g, {} mHz, clock {}, started at {} ns, filter {}",
+                    session, decoded.version, decoded.range_g, decoded.rate_mhz,
+                    decoded.clock, decoded.start_ns, decoded.filter
+                )?;
+                header = Some(decoded);
+                index = 0;
+                continue;
+            }
+            // Batch boundaries are not saved, the CRC is checked live only
+            Record::Crc(_) => continue,
+            Record::Unknown { .. } => {
+                unknown += 1;
+                continue;
+            }
+        };
+
+        let time_ns = match header {
+            Some(h) if h.rate_mhz > 0 => {
+                let offset = index as u128 * 1_000_000_000_000 / h.rate_mhz as u128;
+                (h.start_ns as u128 + offset).to_string()
+            }
+            _ => String::new(),
+        };
+        let sync_text = sync.take().map(|s| s.to_string()).unwrap_or_default();
+        let clip_text = clip.take().map(|axes| axes.to_string()).unwrap_or_default();
+        writeln!(
+            out,
+            "{},{},{},{},{},{},{},{}",
+            session, index, time_ns, sample.x, sample.y, sample.z, sync_text, clip_text
+        )?;
+        index += 1;
+    }
+
+    if unknown > 0 {
+        eprintln!("{}: skipped {} markers of unknown kind", input, unknown);
+    }
+    out.flush()
+}
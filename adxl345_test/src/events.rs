@@ -0,0 +1,125 @@
+//! Event monitor: taps, double taps, free falls and activity, as the driver reports them.
+//!
+//! It is the reference user of the event interface. The taps come in the record stream as tap
+//! markers, each one after an event marker whose ID `ADXL345_IOC_GET_EVENT` turns into the time
+//! it was flagged. The motion events (activity, inactivity, free fall) raise `POLLPRI` on the
+//! file until `ADXL345_IOC_GET_MOTION_EVENT` fetches them, with their own timestamp. A single
+//! `poll` on `POLLIN | POLLPRI` waits for both.
+
+use std::io;
+use std::os::fd::AsRawFd;
+
+use libadxl345::abi::*;
+use libadxl345::{Adxl345Device, Adxl345Sample, Record, StreamDecoder};
+
+/// ACT_INACT_CTL written by the monitor: activity on x, y and z, dc coupled.
+const ACT_INACT_CTL: u8 = 0x70;
+
+/// Axes taking part in tap detection: x, y and z.
+const TAP_AXES: u32 = 0b111;
+
+/// Records read per batch.
+const BATCH: usize = 64;
+
+/// Formats a timestamp in ns of the clock of the samples as seconds.
+fn seconds(timestamp_ns: u64) -> String {
+    format!("{}.{:09}", timestamp_ns / 1_000_000_000, timestamp_ns % 1_000_000_000)
+}
+
+/// Returns the axes of a mask (bit 0 x, bit 1 y, bit 2 z) as letters, e.g. "xz".
+fn axes(mask: u8) -> String {
+    ["x", "y", "z"].iter().enumerate().filter(|&(i, _)| mask & 1 << i != 0).map(|(_, a)| *a).collect()
+}
+
+/// Prints a motion event, and the events lost since `last_sequence`.
+fn print_motion(event: &Adxl345MotionEvent, last_sequence: &mut u32) {
+    if *last_sequence != 0 && event.sequence > *last_sequence + 1 {
+        println!("{:>20}  {} motion events missed", "", event.sequence - *last_sequence - 1);
+    }
+    *last_sequence = event.sequence;
+
+    let time = seconds(event.timestamp_ns);
+    if event.events & ADXL345_MOTION_FREE_FALL != 0 {
+        println!("{:>20}  free fall", time);
+    }
+    if event.events & ADXL345_MOTION_ACTIVITY != 0 {
+        println!("{:>20}  activity", time);
+    }
+    if event.events & ADXL345_MOTION_INACTIVITY != 0 {
+        println!("{:>20}  inactivity", time);
+    }
+}
+
+/// Reads the records buffered and prints their taps, until the read would block.
+///
+/// `tap_time` carries the timestamp of the last tap event marker to the tap marker after it.
+fn drain_taps(
+    device: &Adxl345Device,
+    decoder: &mut StreamDecoder,
+    buf: &mut [Adxl345Sample],
+    tap_time: &mut Option<u64>,
+) -> io::Result<()> {
+    loop {
+        let len = match device.read_records(buf) {
+            Ok(len) => len,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        for &raw in &buf[..len] {
+            match decoder.push(raw) {
+                Some(Record::Event(id)) => {
+                    // Forgotten once the driver logged newer events, the tap is then shown without time
+                    *tap_time = device
+                        .event(id)
+                        .ok()
+                        .filter(|info| info.events & ADXL345_EVENT_TAP != 0)
+                        .map(|info| info.timestamp_ns);
+                }
+                Some(Record::Tap { double, axes: mask, .. }) => {
+                    let time = tap_time.take().map_or_else(|| "?".to_string(), seconds);
+                    let kind = if double { "double tap" } else { "tap" };
+                    println!("{:>20}  {} on {}", time, kind, axes(mask));
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Arms tap, double tap, activity and free-fall detection on the device at `file_path` and
+/// prints the events as they come, until interrupted.
+///
+/// The thresholds and timings are the ones configured in the driver (`thresh_tap`, `dur`,
+/// `latent`, `window`, `thresh_act`, `thresh_ff`, `time_ff`), e.g. set with `--set` beforehand.
+pub fn run(file_path: &str) -> io::Result<()> {
+    let device = Adxl345Device::open_nonblocking(file_path)?;
+    device.set_tap(ADXL345_TAP_SINGLE | ADXL345_TAP_DOUBLE, TAP_AXES)?;
+    device.set_motion(ADXL345_MOTION_ACTIVITY | ADXL345_MOTION_FREE_FALL, ACT_INACT_CTL)?;
+    println!("{:>20}  event", "time (s)");
+
+    let mut decoder = StreamDecoder::new();
+    let mut buf = vec![Adxl345Sample::default(); BATCH];
+    let mut tap_time = None;
+    let mut last_sequence = 0;
+    let mut fd = libc::pollfd { fd: device.as_raw_fd(), events: libc::POLLIN | libc::POLLPRI, revents: 0 };
+    loop {
+        if unsafe { libc::poll(&mut fd, 1, -1) } < 0 {
+            let e = io::Error::last_os_error();
+            if e.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(e);
+        }
+        if fd.revents & libc::POLLPRI != 0 {
+            print_motion(&device.motion_event()?, &mut last_sequence);
+        }
+        if fd.revents & libc::POLLIN != 0 {
+            drain_taps(&device, &mut decoder, &mut buf, &mut tap_time)?;
+        }
+        // Removed device
+        if fd.revents & libc::POLLHUP != 0 {
+            return Err(io::Error::from_raw_os_error(libc::ENODEV));
+        }
+    }
+}
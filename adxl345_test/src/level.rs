@@ -0,0 +1,120 @@
+//! Spirit level demo: pitch and roll computed from the stream, shown as a bubble level.
+//!
+//! At rest the sensor measures only gravity, so the tilt follows from the direction of the
+//! acceleration vector and its magnitude must be close to 1 g. A wrong magnitude or a bubble
+//! that moves the wrong way points to a scaling or axis problem anywhere in the pipeline.
+
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+use libadxl345::{Adxl345Device, Adxl345Sample, Record};
+
+/// Redraw period.
+const REFRESH: Duration = Duration::from_millis(100);
+
+/// Weight of a new sample in the low-pass filter, which hides vibrations and noise.
+const SMOOTHING: f64 = 0.1;
+
+/// Tilt at the edge of the level, in degrees.
+const MAX_TILT_DEG: f64 = 30.0;
+
+/// Tilt under which the level reads as level, in degrees.
+const LEVEL_DEG: f64 = 1.0;
+
+/// Half sizes of the level, in characters. Terminal cells are about twice as high as wide.
+const HALF_COLS: i32 = 20;
+const HALF_ROWS: i32 = 10;
+
+/// Low-passed acceleration in mg and redraw timing.
+pub struct Level {
+    filtered: Option<[f64; 3]>,
+    next_refresh: Instant,
+}
+
+impl Level {
+    pub fn new() -> Self {
+        // Start from a clean screen, frames then overwrite it in place
+        print!("\x1b[2J");
+        Level { filtered: None, next_refresh: Instant::now() }
+    }
+
+    /// Adds a sample to the filter.
+    pub fn push(&mut self, sample: &Adxl345Sample) {
+        let new = sample.to_mg();
+        self.filtered = Some(match self.filtered {
+            Some(old) => [0, 1, 2].map(|i| old[i] + SMOOTHING * (new[i] - old[i])),
+            None => new,
+        });
+    }
+
+    /// Redraws, once per refresh period.
+    pub fn refresh(&mut self) -> io::Result<()> {
+        let now = Instant::now();
+        if now < self.next_refresh {
+            return Ok(());
+        }
+        self.next_refresh = now + REFRESH;
+
+        match self.filtered {
+            Some(acc) => self.draw(acc),
+            None => Ok(()),
+        }
+    }
+
+    fn draw(&self, [x, y, z]: [f64; 3]) -> io::Result<()> {
+        // Pitch raises the +x side, roll the +y side, both 0 with z pointing up
+        let pitch = x.atan2((y * y + z * z).sqrt()).to_degrees();
+        let roll = y.atan2(z).to_degrees();
+        let magnitude_g = (x * x + y * y + z * z).sqrt() / 1000.0;
+
+        // Like in a spirit level the bubble moves to the raised side: +x right, +y up
+        let bubble_col = (pitch / MAX_TILT_DEG * HALF_COLS as f64).round() as i32;
+        let bubble_row = (-roll / MAX_TILT_DEG * HALF_ROWS as f64).round() as i32;
+        let bubble_col = bubble_col.clamp(-HALF_COLS, HALF_COLS);
+        let bubble_row = bubble_row.clamp(-HALF_ROWS, HALF_ROWS);
+
+        let mut frame = String::from("\x1b[H");
+        for row in -HALF_ROWS..=HALF_ROWS {
+            for col in -HALF_COLS..=HALF_COLS {
+                // Distance from the center, normalized so the outline is a circle on screen
+                let r = ((col as f64 / HALF_COLS as f64).powi(2) + (row as f64 / HALF_ROWS as f64).powi(2)).sqrt();
+                frame.push(if row == bubble_row && col == bubble_col {
+                    'O'
+                } else if (r - 1.0).abs() < 0.05 {
+                    '.'
+                } else if row == 0 && col == 0 {
+                    '+'
+                } else if row == 0 || col == 0 {
+                    '\u{b7}'
+                } else {
+                    ' '
+                });
+            }
+            frame.push_str("\x1b[K\n");
+        }
+
+        let level = pitch.abs() < LEVEL_DEG && roll.abs() < LEVEL_DEG;
+        frame.push_str(&format!(
+            "pitch {:+6.1}\u{b0}   roll {:+6.1}\u{b0}   |a| {:.3} g   {}\x1b[K\n",
+            pitch, roll, magnitude_g, if level { "LEVEL" } else { "" }
+        ));
+
+        let mut out = io::stdout().lock();
+        out.write_all(frame.as_bytes())?;
+        out.flush()
+    }
+}
+
+/// Shows the level for the device at `file_path`, until interrupted.
+pub fn run(file_path: &str) -> io::Result<()> {
+    let device = Adxl345Device::open(file_path)?;
+    let mut level = Level::new();
+
+    for record in device.samples() {
+        if let Record::Sample(sample) = record? {
+            level.push(&sample);
+            level.refresh()?;
+        }
+    }
+    Ok(())
+}
@@ -1,69 +1,514 @@
 use std::env;
+use std::fs;
 use std::io::{self};
-use std::os::unix::io::{FromRawFd,AsRawFd};
 use std::process::exit;
-use std::mem;
-use libc::{open, read, O_RDONLY};
-#[repr(C)]
-#[derive(Debug)]
-struct Adxl345Sample {
-    x: i16,
-    y: i16,
-    z: i16,
+use std::time::{Duration, Instant};
+use libadxl345::{Adxl345Device, Clock, Param, Record};
+
+mod bench;
+mod capture;
+mod events;
+mod level;
+mod plot;
+mod selftest;
+mod spsc_bench;
+#[allow(dead_code)] // The axes are only copied by the queue
+mod structures;
+mod verify;
+#[allow(dead_code)] // The side of the registry used by the driver
+#[path = "../../src/sysfs_abi.rs"]
+mod sysfs_abi;
+#[allow(dead_code)] // The side of the queue used by the driver only
+#[path = "../../src/spsc.rs"]
+mod spsc;
+#[cfg(test)] // Only its tests run on the host
+#[path = "../../src/sample_math.rs"]
+mod sample_math;
+
+/// Default full scale of the plot, in mg.
+const PLOT_SCALE_MG: u32 = 2000;
+
+/// Default duration of each benchmark run.
+const BENCH_WINDOW: Duration = Duration::from_secs(5);
+
+/// Default duration of each high-rate run.
+const HIGH_RATE_WINDOW: Duration = Duration::from_secs(10);
+
+/// Default duration of the integrity check.
+const VERIFY_WINDOW: Duration = Duration::from_secs(10);
+
+/// Options accepted on the command line.
+struct Options {
+    file_path: String,
+    selftest: bool,
+    flush: bool,
+    header: bool,
+    auto_range: bool,
+    verify: bool,
+    output: Option<String>,
+    duration: Option<Duration>,
+    plot: Option<u32>,
+    clock: Option<Clock>,
+    sync_gpio: Option<u32>,
+    preset: Option<String>,
+    save_preset: Option<String>,
+    params: Vec<(Param, u32)>,
+    raw_params: Vec<(Param, u32)>,
 }
 
-const BUFLEN: usize = 16;
+/// Parses a `name=value` configuration parameter.
+fn parse_param(text: &str) -> Option<(Param, u32)> {
+    let (name, value) = text.split_once('=')?;
+    Some((Param::from_name(name)?, value.parse().ok()?))
+}
 
-fn main() -> io::Result<()> {
-    // Check for the device file argument
-    let args: Vec<String> = env::args().collect();
+fn usage(program: &str) -> ! {
+    eprintln!("Usage: {} <device file> [--selftest] [--flush] [--header] [--auto-range] [--verify] [--output <file>] [--duration <time>] [--plot] [--scale <mg>] [--clock monotonic|boottime|realtime] [--sync <gpio>] [--preset <name>] [--set <param>=<value>]... [--set-raw <param>=<lsb>]... [--save-preset <name>]", program);
+    eprintln!("       {} decode <capture file> [<csv file>]", program);
+    eprintln!("       {} replay <capture file> [--plot] [--scale <mg>] [--fast]", program);
+    eprintln!("       {} bench <device file> [<time per run>]", program);
+    eprintln!("       {} highrate <device file> [<time per run>]", program);
+    eprintln!("       {} spsc-bench [<time per run>]", program);
+    eprintln!("       {} level <device file>", program);
+    eprintln!("       {} events <device file>", program);
+    eprintln!("       {} verify <device file> [<time>]", program);
+    eprintln!("       {} abi-doc [--check <file>]", program);
+    eprintln!("Parameters: {}", Param::ALL.map(Param::name).join(", "));
+    eprintln!("--selftest walks the feature matrix of the driver and prints a pass/fail report");
+    eprintln!("--flush discards the samples buffered before the run starts, after the configuration is applied");
+    eprintln!("--header restarts the session so the stream begins with a header describing it");
+    eprintln!("--auto-range lets the driver change the range when clipping persists or the signal fits a lower one");
+    eprintln!("--verify makes the driver end every read with a CRC of its records and checks it, stopping at the first mismatch");
+    eprintln!("--output saves the raw stream instead of printing it, decode converts it to CSV");
+    eprintln!("replay shows a capture like a live stream, paced at the rate of its session headers unless --fast");
+    eprintln!("events arms tap, double tap, activity and free-fall detection and prints the events with their timestamps");
+    eprintln!("verify checks the self-checking pattern of the emulator (waveform 6) end to end, for the given time (default {} s)", VERIFY_WINDOW.as_secs());
+    eprintln!("spsc-bench compares the SPSC queue of the driver with the locked ring it replaced at 3200 Hz, on the host");
+    eprintln!("abi-doc prints the Documentation/ABI entries of the sysfs attributes of the driver, --check compares them with a file");
+    eprintln!("--duration stops after the given time (e.g. 500ms, 60s, 2m)");
+    eprintln!("--plot draws the axes and the magnitude in the terminal, --scale sets its full scale (default {} mg)", PLOT_SCALE_MG);
+    eprintln!("--preset applies a preset saved in the driver before the parameters, --save-preset saves the resulting configuration");
+    eprintln!("--set takes human units (rate in mHz, range in g, thresholds in mg, durations in us), --set-raw register LSBs");
+    exit(1);
+}
+
+/// Parses the command line, exiting with the usage message on errors.
+fn parse_args(args: &[String]) -> Options {
     if args.len() < 2 {
-        eprintln!("Usage: {} <device file>", args[0]);
-        exit(1);
+        usage(&args[0]);
     }
 
-    let file_path = &args[1];
-    let c_file_path = std::ffi::CString::new(file_path.as_str()).unwrap();
+    let mut options = Options {
+        file_path: args[1].clone(),
+        selftest: false,
+        flush: false,
+        header: false,
+        auto_range: false,
+        verify: false,
+        output: None,
+        duration: None,
+        plot: None,
+        clock: None,
+        sync_gpio: None,
+        preset: None,
+        save_preset: None,
+        params: Vec::new(),
+        raw_params: Vec::new(),
+    };
 
-    // Open the device file using libc::open
-    let fd = unsafe { open(c_file_path.as_ptr(), O_RDONLY) };
-    if fd < 0 {
-        eprintln!("Failed to open {}: {}", file_path, io::Error::last_os_error());
+    let mut i = 2;
+    while i < args.len() {
+        if args[i] == "--selftest" {
+            options.selftest = true;
+            i += 1;
+            continue;
+        }
+        if args[i] == "--flush" {
+            options.flush = true;
+            i += 1;
+            continue;
+        }
+        if args[i] == "--header" {
+            options.header = true;
+            i += 1;
+            continue;
+        }
+        if args[i] == "--auto-range" {
+            options.auto_range = true;
+            i += 1;
+            continue;
+        }
+        if args[i] == "--verify" {
+            options.verify = true;
+            i += 1;
+            continue;
+        }
+        if args[i] == "--plot" {
+            options.plot.get_or_insert(PLOT_SCALE_MG);
+            i += 1;
+            continue;
+        }
+        let value = match args.get(i + 1) {
+            Some(value) => value,
+            None => usage(&args[0]),
+        };
+        match args[i].as_str() {
+            "--clock" => {
+                options.clock = match Clock::from_name(value) {
+                    Some(clock) => Some(clock),
+                    None => {
+                        eprintln!("Unknown clock: {}", value);
+                        exit(1);
+                    }
+                };
+            }
+            "--sync" => {
+                options.sync_gpio = match value.parse() {
+                    Ok(gpio) => Some(gpio),
+                    Err(_) => {
+                        eprintln!("Invalid GPIO number: {}", value);
+                        exit(1);
+                    }
+                };
+            }
+            "--output" => options.output = Some(value.clone()),
+            "--preset" => options.preset = Some(value.clone()),
+            "--save-preset" => options.save_preset = Some(value.clone()),
+            "--scale" => {
+                options.plot = match value.parse() {
+                    Ok(scale) if scale > 0 => Some(scale),
+                    _ => {
+                        eprintln!("Invalid plot scale: {}", value);
+                        exit(1);
+                    }
+                };
+            }
+            "--duration" => {
+                options.duration = match capture::parse_duration(value) {
+                    Some(duration) => Some(duration),
+                    None => {
+                        eprintln!("Invalid duration: {}", value);
+                        exit(1);
+                    }
+                };
+            }
+            "--set" | "--set-raw" => {
+                match parse_param(value) {
+                    Some(param) if args[i] == "--set" => options.params.push(param),
+                    Some(param) => options.raw_params.push(param),
+                    None => {
+                        eprintln!("Invalid parameter: {}", value);
+                        exit(1);
+                    }
+                }
+            }
+            _ => usage(&args[0]),
+        }
+        i += 2;
+    }
+
+    options
+}
+
+/// Exits with a message if a device operation failed.
+fn check<T>(result: io::Result<T>, what: &str) -> T {
+    result.unwrap_or_else(|e| {
+        eprintln!("Failed to {}: {}", what, e);
+        if e.raw_os_error() == Some(libc::ERANGE) {
+            eprintln!("See /sys/kernel/debug/adxl345/config_error for the reason");
+        }
         exit(1);
+    })
+}
+
+/// Prints the records, or plots them, until they end or `duration` elapses.
+///
+/// Live streams and replayed captures go through the same path. The batches whose CRC was
+/// checked (see `--verify`) are counted and reported at the end.
+fn show(records: impl Iterator<Item = io::Result<Record>>, plot: Option<u32>, duration: Option<Duration>) -> io::Result<()> {
+    let deadline = duration.map(|d| Instant::now() + d);
+    let mut plot = plot.map(plot::Plot::new);
+    let mut verified = 0u64;
+
+    for record in records {
+        if deadline.is_some_and(|d| Instant::now() >= d) {
+            break;
+        }
+
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => {
+                eprintln!("Failed to read from device: {}", e);
+                exit(1);
+            }
+        };
+
+        if let Record::Crc(_) = record {
+            verified += 1;
+            continue;
+        }
+
+        if let Some(plot) = &mut plot {
+            if let Record::Sample(sample) = record {
+                plot.push(&sample);
+            }
+            plot.refresh()?;
+            continue;
+        }
+
+        match record {
+            Record::Sample(sample) => {
+                let a = sample.acceleration();
+                println!("x -> {:8.1}, y -> {:8.1}, z -> {:8.1} (mg)", a.x.0, a.y.0, a.z.0)
+            }
+            Record::Sync(sequence) => println!("---- sync pulse #{} ----", sequence),
+            Record::Header(header) => println!(
+                "---- session v{}: {} g, {} mHz, clock {}, started at {} ns, filter {} ----",
+                header.version, header.range_g, header.rate_mhz, header.clock, header.start_ns, header.filter
+            ),
+            Record::Clip(axes) => println!("---- clipped on axes {:#05b} ----", axes),
+            Record::Range(range) => println!("---- range now {} g ----", range),
+            Record::Crc(_) => {}
+            Record::Error(errno) => println!("---- bus error {}, samples may be missing ----", errno),
+            Record::BurstEnd(samples) => println!("---- burst of {} samples ends ----", samples),
+            Record::Event(id) => println!("---- event {} ----", id),
+            Record::Limit(axes) => println!("---- limit reached on axes {:#05b} ----", axes),
+            Record::Tap { double, axes, .. } => {
+                println!("---- {} on axes {:#05b} ----", if double { "double tap" } else { "tap" }, axes)
+            }
+            Record::Unknown { kind, .. } => println!("---- unknown marker {} ----", kind),
+        }
+    }
+    if verified > 0 {
+        println!("{} batches verified", verified);
+    }
+    Ok(())
+}
+
+/// Replays a capture as if it was read live, exiting with the usage message on bad options.
+fn replay(args: &[String]) -> io::Result<()> {
+    let input = args.get(2).unwrap_or_else(|| usage(&args[0]));
+    let mut plot = None;
+    let mut realtime = true;
+    let mut i = 3;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--plot" => {
+                plot.get_or_insert(PLOT_SCALE_MG);
+            }
+            "--fast" => realtime = false,
+            "--scale" => {
+                i += 1;
+                plot = Some(args.get(i).and_then(|v| v.parse().ok()).filter(|&s| s > 0).unwrap_or_else(|| usage(&args[0])));
+            }
+            _ => usage(&args[0]),
+        }
+        i += 1;
+    }
+
+    let records = match capture::Replay::open(input, realtime) {
+        Ok(records) => records,
+        Err(e) => {
+            eprintln!("Failed to open {}: {}", input, e);
+            exit(1);
+        }
+    };
+    show(records, plot, None)
+}
+
+/// Prints the ABI documentation of the sysfs attributes, or with `--check` compares it with a
+/// file and exits with 1 if they differ.
+fn abi_doc(args: &[String]) -> ! {
+    let mut text = String::new();
+    sysfs_abi::adxl345_abi_doc(&mut text).expect("formatting to a String");
+    match args.get(2).map(String::as_str) {
+        None => {
+            print!("{}", text);
+            exit(0);
+        }
+        Some("--check") => {
+            let path = args.get(3).unwrap_or_else(|| usage(&args[0]));
+            match fs::read_to_string(path) {
+                Ok(current) if current == text => exit(0),
+                Ok(_) => eprintln!("{} is out of date, regenerate it with make abi-doc", path),
+                Err(e) => eprintln!("Failed to read {}: {}", path, e),
+            }
+            exit(1);
+        }
+        Some(_) => usage(&args[0]),
     }
+}
 
-    // SAFETY: Wrap the raw fd in a File to ensure proper closure when dropped
-    let file = unsafe { std::fs::File::from_raw_fd(fd) };
+fn main() -> io::Result<()> {
+    // Check for the device file argument
+    let args: Vec<String> = env::args().collect();
+
+    // Convert a raw capture to CSV, the device is not needed
+    if args.get(1).map(String::as_str) == Some("decode") {
+        let input = args.get(2).unwrap_or_else(|| usage(&args[0]));
+        if let Err(e) = capture::decode(input, args.get(3).map(String::as_str)) {
+            eprintln!("Failed to decode {}: {}", input, e);
+            exit(1);
+        }
+        return Ok(());
+    }
+
+    // Feed a capture through the live display paths, no hardware needed
+    if args.get(1).map(String::as_str) == Some("replay") {
+        return replay(&args);
+    }
+
+    // Benchmark the read path, one JSON report line per mode and read size
+    if args.get(1).map(String::as_str) == Some("bench") {
+        let device = args.get(2).unwrap_or_else(|| usage(&args[0]));
+        let window = match args.get(3) {
+            Some(text) => capture::parse_duration(text).unwrap_or_else(|| usage(&args[0])),
+            None => BENCH_WINDOW,
+        };
+        exit(if bench::run(device, window) { 0 } else { 1 });
+    }
 
-    // Define buffer for reading data
-    let mut buf: [Adxl345Sample; BUFLEN] = unsafe { mem::zeroed() };
+    // Kernel buffer designs compared on the host, one JSON report line per design and reader
+    if args.get(1).map(String::as_str) == Some("spsc-bench") {
+        let window = match args.get(2) {
+            Some(text) => capture::parse_duration(text).unwrap_or_else(|| usage(&args[0])),
+            None => BENCH_WINDOW,
+        };
+        spsc_bench::run(window);
+        exit(0);
+    }
 
-    loop {
-        // Attempt to read data from the device
-        let ret = unsafe {
-            read(
-                file.as_raw_fd(),
-                buf.as_mut_ptr() as *mut libc::c_void,
-                mem::size_of::<Adxl345Sample>() * BUFLEN as libc::size_t,
-            )
+    // Sample delivery at 3200 and 1600 Hz, one JSON report line per rate
+    if args.get(1).map(String::as_str) == Some("highrate") {
+        let device = args.get(2).unwrap_or_else(|| usage(&args[0]));
+        let window = match args.get(3) {
+            Some(text) => capture::parse_duration(text).unwrap_or_else(|| usage(&args[0])),
+            None => HIGH_RATE_WINDOW,
         };
+        exit(if bench::high_rate(device, window) { 0 } else { 1 });
+    }
+
+    // Spirit level demo, pitch and roll from the stream
+    if args.get(1).map(String::as_str) == Some("level") {
+        let device = args.get(2).unwrap_or_else(|| usage(&args[0]));
+        if let Err(e) = level::run(device) {
+            eprintln!("Level on {} failed: {}", device, e);
+            exit(1);
+        }
+        return Ok(());
+    }
 
-        // Check for read errors
-        if ret == -1 {
-            eprintln!("Failed to read from device: {}", io::Error::last_os_error());
+    // Tap, free-fall and activity monitor, on the event interface
+    if args.get(1).map(String::as_str) == Some("events") {
+        let device = args.get(2).unwrap_or_else(|| usage(&args[0]));
+        if let Err(e) = events::run(device) {
+            eprintln!("Events on {} failed: {}", device, e);
             exit(1);
         }
+        return Ok(());
+    }
+
+    // End-to-end integrity check, on the emulator pattern
+    if args.get(1).map(String::as_str) == Some("verify") {
+        let device = args.get(2).unwrap_or_else(|| usage(&args[0]));
+        let window = match args.get(3) {
+            Some(text) => capture::parse_duration(text).unwrap_or_else(|| usage(&args[0])),
+            None => VERIFY_WINDOW,
+        };
+        match verify::run(device, window) {
+            Ok(passed) => exit(if passed { 0 } else { 1 }),
+            Err(e) => {
+                eprintln!("Verify on {} failed: {}", device, e);
+                exit(1);
+            }
+        }
+    }
+
+    // Documentation of the sysfs attributes, from the registry of the driver
+    if args.get(1).map(String::as_str) == Some("abi-doc") {
+        abi_doc(&args);
+    }
+
+    let options = parse_args(&args);
 
-        // Ensure read result is aligned with sample size
-        if ret as usize % mem::size_of::<Adxl345Sample>() != 0 {
-            eprintln!("Unexpected read size: {}", ret);
+    let file_path = &options.file_path;
+
+    // Run the hardware-in-the-loop self test instead of streaming samples
+    if options.selftest {
+        exit(if selftest::run(file_path) { 0 } else { 1 });
+    }
+
+    let device = match Adxl345Device::open(file_path) {
+        Ok(device) => device,
+        Err(e) => {
+            eprintln!("Failed to open {}: {}", file_path, e);
             exit(1);
         }
+    };
+
+    // Select the timestamp clock if requested
+    if let Some(clock) = options.clock {
+        check(device.set_clock(clock), "set the timestamp clock");
+    }
+
+    // Attach the external sync input if requested
+    if let Some(gpio) = options.sync_gpio {
+        check(device.set_sync(Some(gpio)), "set the sync input");
+    }
+
+    // Start from a saved preset, the parameters below are applied on top of it
+    if let Some(name) = &options.preset {
+        check(device.apply_preset(name), &format!("apply preset {}", name));
+    }
+
+    // Apply the configuration parameters, the driver validates each of them
+    for &(param, value) in &options.raw_params {
+        check(device.set_param_raw(param, value), &format!("set {} = {}", param.name(), value));
+    }
+    for &(param, value) in &options.params {
+        let achieved = check(device.set_param(param, value), &format!("set {} = {}", param.name(), value));
+        let unit = param.unit();
+        println!("{} = {} {} (requested {} {})", param.name(), achieved, unit, value, unit);
+    }
+
+    // Keep the resulting configuration for later runs
+    if let Some(name) = &options.save_preset {
+        check(device.save_preset(name), &format!("save preset {}", name));
+    }
+
+    // Let the driver follow the amplitude of the signal
+    if options.auto_range {
+        check(device.set_auto_range(true), "enable auto-ranging");
+    }
+
+    // End every read with a CRC, checked as the stream is read: a mismatch is a read error
+    if options.verify {
+        check(device.set_batch_crc(true), "enable the batch CRC");
+    }
 
-        // Process each sample in the buffer
-        let samples_read = ret as usize / mem::size_of::<Adxl345Sample>();
-        for sample in &buf[..samples_read] {
-            println!("x -> {:6}, y -> {:6}, z -> {:6} (mg)", sample.x, sample.y, sample.z);
+    // Start from a clean buffer, the samples acquired with the old configuration are discarded
+    if options.flush {
+        check(device.flush(), "flush the buffered samples");
+    }
+
+    // Restart the session, the new one begins with its header
+    if options.header {
+        check(device.set_header(true), "enable the session header");
+        check(device.stop(), "stop the session");
+        check(device.start(), "start the session");
+    }
+
+    // Save the raw stream, decoded later with the decode subcommand
+    if let Some(output) = &options.output {
+        match capture::record(&device, output, options.duration) {
+            Ok(bytes) => println!("Captured {} records into {}", bytes / libadxl345::RECORD_SIZE as u64, output),
+            Err(e) => {
+                eprintln!("Capture to {} failed: {}", output, e);
+                exit(1);
+            }
         }
+        return Ok(());
     }
-}
\ No newline at end of file
+
+    show(device.samples(), options.plot, options.duration)
+}
@@ -0,0 +1,169 @@
+//! Scrolling terminal plot of the three axes and of the magnitude.
+//!
+//! Each column covers one refresh period and shows the span between the smallest and the largest
+//! value seen in it, so vibrations faster than the refresh rate stay visible. The screen is
+//! redrawn in place with ANSI escape sequences, which works over SSH without any extra library.
+
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+use libadxl345::Adxl345Sample;
+
+/// Time covered by a column, and redraw period.
+const REFRESH: Duration = Duration::from_millis(50);
+
+/// Terminal size used when it can't be queried.
+const DEFAULT_SIZE: (usize, usize) = (80, 24);
+
+/// Smallest height of a trace, in rows.
+const MIN_TRACE_ROWS: usize = 3;
+
+/// Columns taken by the labels on the left of each trace.
+const LABEL_COLS: usize = 8;
+
+/// Traces drawn, top to bottom.
+const TRACES: [&str; 4] = ["x", "y", "z", "|a|"];
+
+/// Span of values seen during a refresh period, per trace.
+#[derive(Clone, Copy)]
+struct Column {
+    min: [i32; 4],
+    max: [i32; 4],
+}
+
+impl Column {
+    fn new(values: [i32; 4]) -> Self {
+        Column { min: values, max: values }
+    }
+
+    fn add(&mut self, values: [i32; 4]) {
+        for (i, value) in values.into_iter().enumerate() {
+            self.min[i] = self.min[i].min(value);
+            self.max[i] = self.max[i].max(value);
+        }
+    }
+}
+
+/// Plot state: the visible history and the column being filled.
+pub struct Plot {
+    scale: i32,
+    width: usize,
+    rows: usize,
+    history: VecDeque<Column>,
+    current: Option<Column>,
+    last: [i32; 4],
+    next_refresh: Instant,
+}
+
+/// Returns the terminal size as (columns, rows).
+fn terminal_size() -> (usize, usize) {
+    let mut size = libc::winsize { ws_row: 0, ws_col: 0, ws_xpixel: 0, ws_ypixel: 0 };
+    let ret = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) };
+    if ret < 0 || size.ws_col == 0 || size.ws_row == 0 {
+        return DEFAULT_SIZE;
+    }
+    (size.ws_col as usize, size.ws_row as usize)
+}
+
+impl Plot {
+    /// Creates a plot sized to the terminal, axes span `-scale..scale` and the magnitude
+    /// `0..scale`, in mg.
+    pub fn new(scale: u32) -> Self {
+        let (cols, rows) = terminal_size();
+        // Each trace has a title line, one line is left for the cursor
+        let rows = ((rows.saturating_sub(1)) / TRACES.len()).saturating_sub(1).max(MIN_TRACE_ROWS);
+        let width = cols.saturating_sub(LABEL_COLS).max(10);
+
+        // Start from a clean screen, frames then overwrite it in place
+        print!("\x1b[2J");
+        Plot {
+            scale: scale.clamp(1, i32::MAX as u32) as i32,
+            width,
+            rows,
+            history: VecDeque::with_capacity(width),
+            current: None,
+            last: [0; 4],
+            next_refresh: Instant::now() + REFRESH,
+        }
+    }
+
+    /// Adds a sample to the column being filled.
+    pub fn push(&mut self, sample: &Adxl345Sample) {
+        let a = sample.acceleration();
+        let values = [a.x, a.y, a.z, a.magnitude()].map(|mg| mg.0.round() as i32);
+        self.last = values;
+        match &mut self.current {
+            Some(column) => column.add(values),
+            None => self.current = Some(Column::new(values)),
+        }
+    }
+
+    /// Closes the current column and redraws, once per refresh period.
+    pub fn refresh(&mut self) -> io::Result<()> {
+        let now = Instant::now();
+        if now < self.next_refresh {
+            return Ok(());
+        }
+        self.next_refresh = now + REFRESH;
+
+        // With no sample in the period, the last value is repeated
+        let column = self.current.take().unwrap_or(Column::new(self.last));
+        if self.history.len() == self.width {
+            self.history.pop_front();
+        }
+        self.history.push_back(column);
+        self.draw()
+    }
+
+    /// Maps a value to a row of a trace, 0 being the top one.
+    fn row_of(&self, trace: usize, value: i32) -> usize {
+        let (low, high) = if trace == 3 { (0, self.scale) } else { (-self.scale, self.scale) };
+        let value = value.clamp(low, high);
+        let span = (high - low) as i64;
+        ((high - value) as i64 * (self.rows as i64 - 1) / span) as usize
+    }
+
+    fn draw(&self) -> io::Result<()> {
+        let mut frame = String::from("\x1b[H");
+        for (trace, name) in TRACES.iter().enumerate() {
+            let (low, high) = if trace == 3 { (0, self.scale) } else { (-self.scale, self.scale) };
+            frame.push_str(&format!(
+                "\x1b[1m{:<4}\x1b[0m {:>6} mg   [{} .. {}]\x1b[K\n",
+                name, self.last[trace], low, high
+            ));
+
+            let zero = self.row_of(trace, 0);
+            for row in 0..self.rows {
+                // Only the bounds are labelled
+                let label = match row {
+                    0 => Some(high),
+                    r if r == self.rows - 1 => Some(low),
+                    _ => None,
+                };
+                match label {
+                    Some(value) => frame.push_str(&format!("{:>6} ", value)),
+                    None => frame.push_str(&" ".repeat(LABEL_COLS - 1)),
+                }
+                frame.push('|');
+
+                for column in &self.history {
+                    let top = self.row_of(trace, column.max[trace]);
+                    let bottom = self.row_of(trace, column.min[trace]);
+                    frame.push(if row >= top && row <= bottom {
+                        '*'
+                    } else if row == zero && trace != 3 {
+                        '-'
+                    } else {
+                        ' '
+                    });
+                }
+                frame.push_str("\x1b[K\n");
+            }
+        }
+
+        let mut out = io::stdout().lock();
+        out.write_all(frame.as_bytes())?;
+        out.flush()
+    }
+}
@@ -0,0 +1,851 @@
+//! Hardware-in-the-loop self test.
+//!
+//! Walks the feature matrix of the driver against a real sensor and prints a pass/fail report.
+//! The configuration found at start (rate, range, watermark) is restored at the end.
+
+use std::ffi::CString;
+use std::io;
+use std::mem;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use libadxl345::abi::*;
+
+/// Tolerance on the measured output data rate, in percent.
+const RATE_TOLERANCE_PCT: u64 = 10;
+
+/// Time spent reading at each rate to estimate it.
+const RATE_WINDOW: Duration = Duration::from_secs(2);
+
+/// Rates, in mHz, whose accuracy is measured. Higher rates are limited by the I2C bus.
+const MEASURED_RATES_MHZ: [u32; 3] = [25_000, 50_000, 100_000];
+
+/// Rate, in mHz, at which two readers with very different batch sizes share the device.
+const MIXED_READERS_RATE_MHZ: u32 = 1_600_000;
+
+/// Device and output rates, in mHz, of the resampling check.
+const RESAMPLE_DEVICE_MHZ: u32 = 400_000;
+const RESAMPLE_OUTPUT_MHZ: u32 = 100_000;
+
+/// Smallest share of the samples, in percent, each of the mixed readers must receive.
+const MIXED_READERS_MIN_PCT: u64 = 20;
+
+/// Outcome of every check run so far.
+#[derive(Default)]
+struct Report {
+    passed: u32,
+    failed: u32,
+    skipped: u32,
+}
+
+impl Report {
+    /// Records and prints the outcome of a check.
+    fn check(&mut self, name: &str, result: Result<(), String>) {
+        match result {
+            Ok(()) => {
+                self.passed += 1;
+                println!("[PASS] {}", name);
+            }
+            Err(reason) => {
+                self.failed += 1;
+                println!("[FAIL] {}: {}", name, reason);
+            }
+        }
+    }
+
+    /// Records a check that can't be run on this driver or setup.
+    fn skip(&mut self, name: &str, reason: &str) {
+        self.skipped += 1;
+        println!("[SKIP] {}: {}", name, reason);
+    }
+}
+
+/// Describes an errno for the report.
+fn errno_str(errno: i32) -> String {
+    io::Error::from_raw_os_error(errno).to_string()
+}
+
+/// Opens the device with `flags`, returning the fd or the errno.
+fn open_device(path: &CString, flags: i32) -> Result<i32, i32> {
+    let fd = unsafe { libc::open(path.as_ptr(), flags) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error().raw_os_error().unwrap_or(0));
+    }
+    Ok(fd)
+}
+
+/// Reads records from the device into `buf`, returning how many were read or the errno.
+fn read_records(fd: i32, buf: &mut [Adxl345Sample]) -> Result<usize, i32> {
+    let ret = unsafe {
+        libc::read(fd, buf.as_mut_ptr() as *mut libc::c_void, mem::size_of_val(buf))
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error().raw_os_error().unwrap_or(0));
+    }
+    if !(ret as usize).is_multiple_of(mem::size_of::<Adxl345Sample>()) {
+        return Err(libc::EIO);
+    }
+    Ok(ret as usize / mem::size_of::<Adxl345Sample>())
+}
+
+/// Expects `result` to fail with `errno`.
+fn expect_errno<T>(result: Result<T, i32>, errno: i32) -> Result<(), String> {
+    match result {
+        Ok(_) => Err(format!("succeeded, expected {}", errno_str(errno))),
+        Err(e) if e == errno => Ok(()),
+        Err(e) => Err(format!("failed with {}, expected {}", errno_str(e), errno_str(errno))),
+    }
+}
+
+fn get_param(fd: i32, param: u32) -> Result<u32, i32> {
+    let mut arg = Adxl345ParamArg { param, value: 0 };
+    ioctl_ptr(fd, ADXL345_IOC_GET_PARAM, &mut arg)?;
+    Ok(arg.value)
+}
+
+fn set_param(fd: i32, param: u32, value: u32) -> Result<(), i32> {
+    let mut arg = Adxl345ParamArg { param, value };
+    ioctl_ptr(fd, ADXL345_IOC_SET_PARAM, &mut arg)
+}
+
+fn set_param_scaled(fd: i32, param: u32, value: u32) -> Result<u32, i32> {
+    let mut arg = Adxl345ParamArg { param, value };
+    ioctl_ptr(fd, ADXL345_IOC_SET_PARAM_SCALED, &mut arg)?;
+    Ok(arg.value)
+}
+
+/// Sets a parameter and checks that the device reports it back unchanged.
+fn param_roundtrip(fd: i32, param: u32, value: u32) -> Result<(), String> {
+    set_param(fd, param, value).map_err(|e| format!("set failed: {}", errno_str(e)))?;
+    match get_param(fd, param) {
+        Ok(read) if read == value => Ok(()),
+        Ok(read) => Err(format!("read back {}, expected {}", read, value)),
+        Err(e) => Err(format!("get failed: {}", errno_str(e))),
+    }
+}
+
+/// Sets the filter threshold and checks that the driver reports it back unchanged.
+fn filter_roundtrip(fd: i32, mut threshold: u32) -> Result<(), String> {
+    let expected = threshold;
+    ioctl_ptr(fd, ADXL345_IOC_SET_FILTER, &mut threshold).map_err(|e| format!("set failed: {}", errno_str(e)))?;
+    let mut read = 0u32;
+    match ioctl_ptr(fd, ADXL345_IOC_GET_FILTER, &mut read) {
+        Ok(()) if read == expected => Ok(()),
+        Ok(()) => Err(format!("read back {}, expected {}", read, expected)),
+        Err(e) => Err(format!("get failed: {}", errno_str(e))),
+    }
+}
+
+/// Checks that the motion events armed are read back, then disarms them.
+fn motion_roundtrip(fd: i32, mut arg: Adxl345MotionArg) -> Result<(), String> {
+    let expected = arg;
+    ioctl_ptr(fd, ADXL345_IOC_SET_MOTION, &mut arg).map_err(|e| format!("set failed: {}", errno_str(e)))?;
+    let mut read = Adxl345MotionArg::default();
+    let result = match ioctl_ptr(fd, ADXL345_IOC_GET_MOTION, &mut read) {
+        Ok(()) if read == expected => Ok(()),
+        Ok(()) => Err(format!("read back {:?}, expected {:?}", read, expected)),
+        Err(e) => Err(format!("get failed: {}", errno_str(e))),
+    };
+    let _ = ioctl_ptr(fd, ADXL345_IOC_SET_MOTION, &mut Adxl345MotionArg::default());
+    result
+}
+
+/// Checks that the power modes set are read back, then goes back to full power.
+fn power_roundtrip(fd: i32, mut arg: Adxl345PowerArg) -> Result<(), String> {
+    let expected = arg;
+    ioctl_ptr(fd, ADXL345_IOC_SET_POWER, &mut arg).map_err(|e| format!("set failed: {}", errno_str(e)))?;
+    let mut read = Adxl345PowerArg::default();
+    let result = match ioctl_ptr(fd, ADXL345_IOC_GET_POWER, &mut read) {
+        Ok(()) if read == expected => Ok(()),
+        Ok(()) => Err(format!("read back {:?}, expected {:?}", read, expected)),
+        Err(e) => Err(format!("get failed: {}", errno_str(e))),
+    };
+    let _ = ioctl_ptr(fd, ADXL345_IOC_SET_POWER, &mut Adxl345PowerArg::default());
+    result
+}
+
+/// Checks that the offsets set are read back, then restores the ones found.
+fn offsets_roundtrip(fd: i32, mut arg: Adxl345OffsetArg) -> Result<(), String> {
+    let mut saved = Adxl345OffsetArg::default();
+    ioctl_ptr(fd, ADXL345_IOC_GET_OFFSETS, &mut saved).map_err(|e| format!("get failed: {}", errno_str(e)))?;
+    let expected = arg;
+    ioctl_ptr(fd, ADXL345_IOC_SET_OFFSETS, &mut arg).map_err(|e| format!("set failed: {}", errno_str(e)))?;
+    let mut read = Adxl345OffsetArg::default();
+    let result = match ioctl_ptr(fd, ADXL345_IOC_GET_OFFSETS, &mut read) {
+        Ok(()) if read == expected => Ok(()),
+        Ok(()) => Err(format!("read back {:?}, expected {:?}", read, expected)),
+        Err(e) => Err(format!("get failed: {}", errno_str(e))),
+    };
+    let _ = ioctl_ptr(fd, ADXL345_IOC_SET_OFFSETS, &mut saved);
+    result
+}
+
+/// Checks that the capacity of the kernel buffer set is read back, then restores the one found.
+fn buffer_roundtrip(fd: i32, capacity: u32) -> Result<(), String> {
+    let mut saved = Adxl345BufferArg::default();
+    ioctl_ptr(fd, ADXL345_IOC_GET_BUFFER, &mut saved).map_err(|e| format!("get failed: {}", errno_str(e)))?;
+    let mut arg = capacity;
+    ioctl_ptr(fd, ADXL345_IOC_SET_BUFFER, &mut arg).map_err(|e| format!("set failed: {}", errno_str(e)))?;
+    let mut read = Adxl345BufferArg::default();
+    let result = match ioctl_ptr(fd, ADXL345_IOC_GET_BUFFER, &mut read) {
+        Ok(()) if read.capacity != capacity => Err(format!("read back {}, expected {}", read.capacity, capacity)),
+        Ok(()) if read.overruns < saved.overruns => Err(format!("overruns went from {} to {}", saved.overruns, read.overruns)),
+        Ok(()) => Ok(()),
+        Err(e) => Err(format!("get failed: {}", errno_str(e))),
+    };
+    let _ = ioctl_ptr(fd, ADXL345_IOC_SET_BUFFER, &mut saved.capacity);
+    result
+}
+
+/// Checks that the stages of the read filter set are read back, then restores the ones found.
+fn pipeline_roundtrip(fd: i32, mut arg: Adxl345PipelineArg) -> Result<(), String> {
+    let mut saved = Adxl345PipelineArg::default();
+    ioctl_ptr(fd, ADXL345_IOC_GET_PIPELINE, &mut saved).map_err(|e| format!("get failed: {}", errno_str(e)))?;
+    let expected = arg;
+    ioctl_ptr(fd, ADXL345_IOC_SET_PIPELINE, &mut arg).map_err(|e| format!("set failed: {}", errno_str(e)))?;
+    let mut read = Adxl345PipelineArg::default();
+    let result = match ioctl_ptr(fd, ADXL345_IOC_GET_PIPELINE, &mut read) {
+        Ok(()) if read == expected => Ok(()),
+        Ok(()) => Err(format!("read back {:?}, expected {:?}", read, expected)),
+        Err(e) => Err(format!("get failed: {}", errno_str(e))),
+    };
+    let _ = ioctl_ptr(fd, ADXL345_IOC_SET_PIPELINE, &mut saved);
+    result
+}
+
+/// Reads a batch in the µg format and checks that it holds whole scaled records with samples
+/// within 16 g, then reads raw records again.
+fn scaled_output(fd: i32) -> Result<(), String> {
+    let mut mode = ADXL345_OUTPUT_MICRO_G;
+    ioctl_ptr(fd, ADXL345_IOC_SET_OUTPUT, &mut mode).map_err(|e| format!("set failed: {}", errno_str(e)))?;
+    let mut buf = [Adxl345ScaledSample::default(); 64];
+    let ret = unsafe { libc::read(fd, buf.as_mut_ptr() as *mut libc::c_void, mem::size_of_val(&buf)) };
+    let mut raw = ADXL345_OUTPUT_RAW;
+    let _ = ioctl_ptr(fd, ADXL345_IOC_SET_OUTPUT, &mut raw);
+    if ret < 0 {
+        return Err(format!("read failed: {}", errno_str(io::Error::last_os_error().raw_os_error().unwrap_or(0))));
+    }
+    let size = mem::size_of::<Adxl345ScaledSample>();
+    if !(ret as usize).is_multiple_of(size) {
+        return Err(format!("read {} bytes, not whole records of {}", ret, size));
+    }
+    let records = &buf[..ret as usize / size];
+    match records.iter().find(|r| r.x != ADXL345_SCALED_MARKER_TAG && [r.x, r.y, r.z].iter().any(|v| v.abs() > 16_000_000)) {
+        Some(r) => Err(format!("sample {:?} beyond 16 g", r)),
+        None => Ok(()),
+    }
+}
+
+/// Reads a batch in the timestamped format and checks that the samples are stamped, in order.
+fn stamped_output(fd: i32) -> Result<(), String> {
+    let mut mode = ADXL345_OUTPUT_TIMESTAMPED;
+    ioctl_ptr(fd, ADXL345_IOC_SET_OUTPUT, &mut mode).map_err(|e| format!("set failed: {}", errno_str(e)))?;
+    let mut buf = [Adxl345StampedSample::default(); 64];
+    let ret = unsafe { libc::read(fd, buf.as_mut_ptr() as *mut libc::c_void, mem::size_of_val(&buf)) };
+    let mut raw = ADXL345_OUTPUT_RAW;
+    let _ = ioctl_ptr(fd, ADXL345_IOC_SET_OUTPUT, &mut raw);
+    if ret < 0 {
+        return Err(format!("read failed: {}", errno_str(io::Error::last_os_error().raw_os_error().unwrap_or(0))));
+    }
+    let size = mem::size_of::<Adxl345StampedSample>();
+    if !(ret as usize).is_multiple_of(size) {
+        return Err(format!("read {} bytes, not whole records of {}", ret, size));
+    }
+    let mut last = 0;
+    for record in buf[..ret as usize / size].iter().filter(|r| r.x != ADXL345_MARKER_TAG) {
+        if record.timestamp_ns == 0 || record.timestamp_ns < last {
+            return Err(format!("sample stamped {} ns after one stamped {} ns", record.timestamp_ns, last));
+        }
+        last = record.timestamp_ns;
+    }
+    Ok(())
+}
+
+/// Checks that a scaled value is achieved within half an LSB of the request.
+fn scaled_roundtrip(fd: i32, param: u32, value: u32, lsb: u32) -> Result<(), String> {
+    let achieved = set_param_scaled(fd, param, value).map_err(|e| format!("set failed: {}", errno_str(e)))?;
+    if achieved.abs_diff(value) * 2 > lsb {
+        return Err(format!("achieved {} {}, requested {}", achieved, PARAM_UNITS[param as usize], value));
+    }
+    Ok(())
+}
+
+/// Reads with `readv` into three segments of one buffer, the first two smaller than a record,
+/// and checks that the records written across them are whole and within bounds.
+fn vectored_read(fd: i32) -> Result<(), String> {
+    let mut buf = [Adxl345Sample::default(); 8];
+    let base = buf.as_mut_ptr() as *mut u8;
+    let total = mem::size_of_val(&buf);
+    let segments = [(0, 4), (4, 8), (12, total - 12)];
+    let iov = segments.map(|(offset, len)| libc::iovec { iov_base: unsafe { base.add(offset) } as *mut libc::c_void, iov_len: len });
+    let ret = unsafe { libc::readv(fd, iov.as_ptr(), iov.len() as i32) };
+    if ret < 0 {
+        return Err(format!("readv failed: {}", errno_str(io::Error::last_os_error().raw_os_error().unwrap_or(0))));
+    }
+    let size = mem::size_of::<Adxl345Sample>();
+    if !(ret as usize).is_multiple_of(size) {
+        return Err(format!("read {} bytes, not whole records of {}", ret, size));
+    }
+    check_bounds(&buf[..ret as usize / size])
+}
+
+/// Checks that every sample lies within the physical limits of the device.
+fn check_bounds(records: &[Adxl345Sample]) -> Result<(), String> {
+    let limit = ADXL345_SAMPLE_LIMIT as i32;
+    for sample in records.iter().filter(|s| s.x != ADXL345_MARKER_TAG) {
+        for value in [sample.x, sample.y, sample.z] {
+            if (value as i32).abs() > limit {
+                return Err(format!("sample {:?} exceeds +/-{}", sample, limit));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Reads for `RATE_WINDOW` and checks the data rate against the configured one.
+///
+/// The driver drops samples that barely change, so on a still sensor only the upper bound is
+/// meaningful: more samples than the configured rate allows is always an error.
+fn check_rate(fd: i32, rate_mhz: u32) -> Result<(), String> {
+    let mut buf = [Adxl345Sample::default(); 64];
+    let mut count = 0u64;
+    let start = Instant::now();
+    while start.elapsed() < RATE_WINDOW {
+        let n = read_records(fd, &mut buf).map_err(|e| format!("read failed: {}", errno_str(e)))?;
+        count += buf[..n].iter().filter(|s| s.x != ADXL345_MARKER_TAG).count() as u64;
+    }
+    let elapsed_ms = start.elapsed().as_millis() as u64;
+    let measured_mhz = count * 1_000_000 / elapsed_ms.max(1);
+    let limit_mhz = rate_mhz as u64 * (100 + RATE_TOLERANCE_PCT) / 100;
+    if measured_mhz > limit_mhz {
+        return Err(format!("measured {} mHz, configured {} mHz", measured_mhz, rate_mhz));
+    }
+    println!("       measured {} mHz at {} mHz (filtered samples are not counted)", measured_mhz, rate_mhz);
+    Ok(())
+}
+
+/// Resamples the stream of `fd` to `output_mhz` with the device at `device_mhz`, checks the rate
+/// read and turns resampling off again.
+fn resampled_rate(fd: i32, device_mhz: u32, output_mhz: u32) -> Result<(), String> {
+    param_roundtrip(fd, PARAM_RATE, device_mhz)?;
+    let mut rate = output_mhz;
+    ioctl_ptr(fd, ADXL345_IOC_SET_RESAMPLE, &mut rate).map_err(|e| format!("set failed: {}", errno_str(e)))?;
+    let result = check_rate(fd, output_mhz);
+    let _ = ioctl_ptr(fd, ADXL345_IOC_SET_RESAMPLE, &mut 0u32);
+    result
+}
+
+/// Restarts the session with the header enabled and checks that the stream begins with it.
+fn session_header(fd: i32) -> Result<(), String> {
+    let mut enable: u32 = 1;
+    ioctl_ptr(fd, ADXL345_IOC_SET_HEADER, &mut enable).map_err(errno_str)?;
+    let result = (|| {
+        for cmd in [ADXL345_IOC_STOP, ADXL345_IOC_START] {
+            if unsafe { libc::ioctl(fd, cmd as _) } < 0 {
+                return Err(io::Error::last_os_error().to_string());
+            }
+        }
+
+        let mut buf = [Adxl345Sample::default(); ADXL345_HEADER_WORDS];
+        let n = read_records(fd, &mut buf).map_err(errno_str)?;
+        let words: Vec<u16> = buf[..n]
+            .iter()
+            .take_while(|r| r.x == ADXL345_MARKER_TAG && r.y == ADXL345_MARKER_HEADER)
+            .map(|r| r.z as u16)
+            .collect();
+        match Adxl345Header::decode(&words) {
+            Some(header) if header.version == 1 && header.rate_mhz > 0 => Ok(()),
+            Some(header) => Err(format!("unexpected header {:?}", header)),
+            None => Err(format!("stream starts with {} header words", words.len())),
+        }
+    })();
+
+    let mut disable: u32 = 0;
+    let _ = ioctl_ptr(fd, ADXL345_IOC_SET_HEADER, &mut disable);
+    result
+}
+
+/// Stops the session with data buffered, so no batch arrives, and checks that in edge mode poll
+/// reports it once while in level mode it keeps reporting it.
+fn poll_modes(fd: i32) -> Result<(), String> {
+    let poll_now = || {
+        let mut pollfd = libc::pollfd { fd, events: libc::POLLIN, revents: 0 };
+        match unsafe { libc::poll(&mut pollfd, 1, 0) } {
+            r if r < 0 => Err(io::Error::last_os_error().to_string()),
+            r => Ok(r == 1 && pollfd.revents & libc::POLLIN != 0),
+        }
+    };
+    let set_mode = |mut mode: u32| ioctl_ptr(fd, ADXL345_IOC_SET_POLL_MODE, &mut mode).map_err(errno_str);
+
+    let mut pollfd = libc::pollfd { fd, events: libc::POLLIN, revents: 0 };
+    if unsafe { libc::poll(&mut pollfd, 1, 1000) } != 1 {
+        return Err("no data to poll".to_string());
+    }
+    if unsafe { libc::ioctl(fd, ADXL345_IOC_STOP as _) } < 0 {
+        return Err(io::Error::last_os_error().to_string());
+    }
+    let result = (|| {
+        set_mode(ADXL345_POLL_EDGE)?;
+        let edge = (poll_now()?, poll_now()?);
+        set_mode(ADXL345_POLL_LEVEL)?;
+        let level = (poll_now()?, poll_now()?);
+        match (edge, level) {
+            ((true, false), (true, true)) => Ok(()),
+            _ => Err(format!("edge polls {:?}, level polls {:?}", edge, level)),
+        }
+    })();
+
+    let _ = set_mode(ADXL345_POLL_LEVEL);
+    if unsafe { libc::ioctl(fd, ADXL345_IOC_START as _) } < 0 {
+        return Err(io::Error::last_os_error().to_string());
+    }
+    result
+}
+
+/// Turns `O_ASYNC` on and waits for the `SIGIO` of the next drain, with the signal blocked so
+/// its default action (terminating the process) never runs.
+fn sigio(fd: i32) -> Result<(), String> {
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags < 0 {
+        return Err(io::Error::last_os_error().to_string());
+    }
+    let mut set: libc::sigset_t = unsafe { mem::zeroed() };
+    unsafe {
+        libc::sigemptyset(&mut set);
+        libc::sigaddset(&mut set, libc::SIGIO);
+        libc::pthread_sigmask(libc::SIG_BLOCK, &set, std::ptr::null_mut());
+    }
+
+    let result = (|| {
+        let mut threshold: u32 = 1;
+        ioctl_ptr(fd, ADXL345_IOC_SET_SIGIO_THRESHOLD, &mut threshold).map_err(errno_str)?;
+        if unsafe { libc::fcntl(fd, libc::F_SETOWN, libc::getpid()) } < 0
+            || unsafe { libc::fcntl(fd, libc::F_SETFL, flags | libc::O_ASYNC) } < 0
+        {
+            return Err(io::Error::last_os_error().to_string());
+        }
+        let timeout = libc::timespec { tv_sec: 1, tv_nsec: 0 };
+        match unsafe { libc::sigtimedwait(&set, std::ptr::null_mut(), &timeout) } {
+            libc::SIGIO => Ok(()),
+            _ => Err("no SIGIO within 1 s".to_string()),
+        }
+    })();
+
+    // Consume the signals sent before O_ASYNC was cleared, then unblock
+    unsafe { libc::fcntl(fd, libc::F_SETFL, flags) };
+    let zero = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+    while unsafe { libc::sigtimedwait(&set, std::ptr::null_mut(), &zero) } == libc::SIGIO {}
+    unsafe { libc::pthread_sigmask(libc::SIG_UNBLOCK, &set, std::ptr::null_mut()) };
+    result
+}
+
+/// Stops the session, checks that no sample arrives, then starts it again and waits for data.
+fn stop_start(fd: i32) -> Result<(), String> {
+    let ioctl_none = |cmd: u32| {
+        if unsafe { libc::ioctl(fd, cmd as _) } < 0 {
+            Err(io::Error::last_os_error().to_string())
+        } else {
+            Ok(())
+        }
+    };
+
+    ioctl_none(ADXL345_IOC_STOP)?;
+    ioctl_none(ADXL345_IOC_FLUSH)?;
+    thread::sleep(Duration::from_millis(100));
+    let mut queued: libc::c_int = -1;
+    if let Err(e) = ioctl_ptr(fd, libc::FIONREAD as u32, &mut queued) {
+        return Err(format!("FIONREAD failed: {}", errno_str(e)));
+    }
+    let stopped = queued;
+
+    ioctl_none(ADXL345_IOC_START)?;
+    let mut pollfd = libc::pollfd { fd, events: libc::POLLIN, revents: 0 };
+    let ret = unsafe { libc::poll(&mut pollfd, 1, 1000) };
+
+    match (stopped, ret) {
+        (0, 1) => Ok(()),
+        (0, _) => Err("no data after START".to_string()),
+        _ => Err(format!("{} bytes queued while stopped", stopped)),
+    }
+}
+
+/// Reads for `RATE_WINDOW` from `fd` in large batches while a second file on `path` reads one
+/// record at a time, and checks that neither of them is starved.
+fn mixed_readers(path: &CString, fd: i32) -> Result<(), String> {
+    let count_samples = |records: &[Adxl345Sample]| {
+        records.iter().filter(|s| s.x != ADXL345_MARKER_TAG).count() as u64
+    };
+    let small_fd = open_device(path, libc::O_RDONLY).map_err(errno_str)?;
+
+    let small = thread::spawn(move || {
+        let mut buf = [Adxl345Sample::default(); 1];
+        let mut count = 0u64;
+        let start = Instant::now();
+        while start.elapsed() < RATE_WINDOW {
+            let n = read_records(small_fd, &mut buf).map_err(errno_str)?;
+            count += count_samples(&buf[..n]);
+        }
+        Ok::<u64, String>(count)
+    });
+
+    let mut buf = [Adxl345Sample::default(); 128];
+    let mut large = 0u64;
+    let start = Instant::now();
+    let result = loop {
+        if start.elapsed() >= RATE_WINDOW {
+            break Ok(());
+        }
+        match read_records(fd, &mut buf) {
+            Ok(n) => large += count_samples(&buf[..n]),
+            Err(e) => break Err(errno_str(e)),
+        }
+    };
+    let small = small.join().map_err(|_| "small reader panicked".to_string())?;
+    unsafe { libc::close(small_fd) };
+    result?;
+    let small = small?;
+
+    let total = large + small;
+    println!("       large batches got {} samples, single records got {}", large, small);
+    if total == 0 {
+        return Err("no samples".to_string());
+    }
+    if large.min(small) * 100 < total * MIXED_READERS_MIN_PCT {
+        return Err(format!("large batches got {} of {} samples", large, total));
+    }
+    Ok(())
+}
+
+/// Writes `text` to `fd`, returning the errno on failure.
+fn write_text(fd: i32, text: &str) -> Result<(), i32> {
+    let ret = unsafe { libc::write(fd, text.as_ptr() as *const libc::c_void, text.len()) };
+    if ret < 0 {
+        return Err(io::Error::last_os_error().raw_os_error().unwrap_or(0));
+    }
+    Ok(())
+}
+
+/// Sets a parameter through the text control channel of a write-only file, checks it with `fd`
+/// and checks that an invalid write is rejected whole.
+fn write_control(path: &CString, fd: i32) -> Result<(), String> {
+    let control = open_device(path, libc::O_WRONLY).map_err(|e| format!("open write-only: {}", errno_str(e)))?;
+    let saved = get_param(fd, PARAM_THRESH_TAP).map_err(errno_str);
+    let result = saved.clone().and_then(|_| {
+        // Writes never block, so the control file is always writable
+        let mut pfd = libc::pollfd { fd: control, events: libc::POLLOUT, revents: 0 };
+        if unsafe { libc::poll(&mut pfd, 1, 0) } != 1 || pfd.revents & libc::POLLOUT == 0 {
+            return Err("poll doesn't report the control file writable".to_string());
+        }
+
+        // 1000 mg is 16 LSB of 62.5 mg
+        write_text(control, "# tap\nthresh_tap 1000\n").map_err(|e| format!("write failed: {}", errno_str(e)))?;
+        let value = get_param(fd, PARAM_THRESH_TAP).map_err(errno_str)?;
+        if value != 16 {
+            return Err(format!("thresh_tap is {} LSB, expected 16", value));
+        }
+        expect_errno(write_text(control, "thresh_tap 2000\nbogus 1\n"), libc::EINVAL)?;
+        let value = get_param(fd, PARAM_THRESH_TAP).map_err(errno_str)?;
+        if value != 16 {
+            return Err(format!("an invalid write changed thresh_tap to {} LSB", value));
+        }
+        Ok(())
+    });
+    if let Ok(value) = saved {
+        let _ = set_param(fd, PARAM_THRESH_TAP, value);
+    }
+    unsafe { libc::close(control) };
+    result
+}
+
+/// Runs the whole self test on `file_path`.
+///
+/// # Returns
+/// `true` if no check failed.
+pub fn run(file_path: &str) -> bool {
+    let path = CString::new(file_path).unwrap();
+    let mut report = Report::default();
+
+    println!("ADXL345 self test on {}", file_path);
+
+    // Open modes, write access is checked once the capabilities are known
+    let fd = match open_device(&path, libc::O_RDONLY) {
+        Ok(fd) => fd,
+        Err(e) => {
+            report.check("open read-only", Err(errno_str(e)));
+            return false;
+        }
+    };
+    report.check("open read-only", Ok(()));
+
+    // Version
+    let mut version = Adxl345Version::default();
+    let mut caps = 0u64;
+    report.check("driver serves the ABI of the library", match ioctl_ptr(fd, ADXL345_IOC_GET_VERSION, &mut version) {
+        Ok(()) if version.abi >= ADXL345_ABI_VERSION => {
+            println!("       driver {}.{}.{}, ABI {}", version.major, version.minor, version.patch, version.abi);
+            Ok(())
+        }
+        Ok(()) => Err(format!("driver ABI {}, library ABI {}", version.abi, ADXL345_ABI_VERSION)),
+        Err(e) => Err(errno_str(e)),
+    });
+    report.check("capabilities are reported", match ioctl_ptr(fd, ADXL345_IOC_GET_CAPS, &mut caps) {
+        Ok(()) if caps & ADXL345_CAP_FIFO != 0 => {
+            let names: Vec<&str> = CAP_NAMES.iter().enumerate().filter(|(bit, _)| caps & (1 << bit) != 0).map(|(_, name)| *name).collect();
+            println!("       {}", names.join(" "));
+            Ok(())
+        }
+        Ok(()) => Err(format!("capabilities {:#x} without fifo", caps)),
+        Err(e) => Err(errno_str(e)),
+    });
+    if caps & ADXL345_CAP_WRITE_CONTROL != 0 {
+        report.check("write control channel", write_control(&path, fd));
+    } else {
+        report.check("open write-only is denied", expect_errno(open_device(&path, libc::O_WRONLY), libc::EPERM));
+        report.check("open read-write is denied", expect_errno(open_device(&path, libc::O_RDWR), libc::EPERM));
+    }
+
+    // Remember the configuration to restore it at the end
+    let saved: Vec<(u32, Option<u32>)> = [PARAM_RATE, PARAM_RANGE, PARAM_WATERMARK]
+        .iter()
+        .map(|&param| (param, get_param(fd, param).ok()))
+        .collect();
+
+    // Timestamp clock ioctls
+    for (name, id) in [
+        ("realtime", libc::CLOCK_REALTIME),
+        ("monotonic", libc::CLOCK_MONOTONIC),
+        ("boottime", libc::CLOCK_BOOTTIME),
+    ] {
+        let mut value = id as u32;
+        let result = ioctl_ptr(fd, ADXL345_IOC_SET_CLOCK, &mut value)
+            .map_err(|e| format!("set failed: {}", errno_str(e)))
+            .and_then(|_| {
+                let mut read = u32::MAX;
+                ioctl_ptr(fd, ADXL345_IOC_GET_CLOCK, &mut read).map_err(|e| format!("get failed: {}", errno_str(e)))?;
+                if read != id as u32 {
+                    return Err(format!("read back {}, expected {}", read, id));
+                }
+                Ok(())
+            });
+        report.check(&format!("clock {}", name), result);
+    }
+    let mut bogus_clock = 42u32;
+    report.check("unknown clock is rejected", expect_errno(ioctl_ptr(fd, ADXL345_IOC_SET_CLOCK, &mut bogus_clock), libc::EINVAL));
+    let mut monotonic = libc::CLOCK_MONOTONIC as u32;
+    let _ = ioctl_ptr(fd, ADXL345_IOC_SET_CLOCK, &mut monotonic);
+
+    // Output data rates
+    for rate in RATES_MHZ {
+        report.check(&format!("rate {} mHz", rate), param_roundtrip(fd, PARAM_RATE, rate));
+    }
+    report.check("unsupported rate is rejected", expect_errno(set_param(fd, PARAM_RATE, 1_000), libc::ERANGE));
+
+    // Measurement ranges, with samples checked against the device limits
+    for range in RANGES_G {
+        let result = param_roundtrip(fd, PARAM_RANGE, range).and_then(|_| {
+            let mut buf = [Adxl345Sample::default(); 16];
+            let n = read_records(fd, &mut buf).map_err(|e| format!("read failed: {}", errno_str(e)))?;
+            check_bounds(&buf[..n])
+        });
+        report.check(&format!("range {} g", range), result);
+    }
+    report.check("unsupported range is rejected", expect_errno(set_param(fd, PARAM_RANGE, 3), libc::ERANGE));
+    report.check("readv fills scattered buffers", vectored_read(fd));
+
+    // FIFO watermark
+    report.check("watermark 1", param_roundtrip(fd, PARAM_WATERMARK, 1));
+    report.check("watermark 31", param_roundtrip(fd, PARAM_WATERMARK, 31));
+    report.check("watermark 0 is rejected", expect_errno(set_param(fd, PARAM_WATERMARK, 0), libc::ERANGE));
+    report.check("watermark 32 is rejected", expect_errno(set_param(fd, PARAM_WATERMARK, 32), libc::ERANGE));
+    report.skip("FIFO modes", "FIFO mode selection is not exposed by the driver");
+
+    // Scaled parameters
+    report.check("thresh_tap 1000 mg", scaled_roundtrip(fd, PARAM_THRESH_TAP, 1000, 63));
+    report.check("dur 10000 us", scaled_roundtrip(fd, PARAM_DUR, 10_000, 625));
+    report.check("out of range threshold is rejected", expect_errno(set_param_scaled(fd, PARAM_THRESH_TAP, 20_000), libc::ERANGE));
+    report.check("thresh_tap 15938 mg, the largest", scaled_roundtrip(fd, PARAM_THRESH_TAP, 15_938, 63));
+    report.check("threshold above 15938 mg is rejected", expect_errno(set_param_scaled(fd, PARAM_THRESH_TAP, 15_939), libc::ERANGE));
+
+    // Read semantics
+    let mut small = [0u8; 4];
+    let ret = unsafe { libc::read(fd, small.as_mut_ptr() as *mut libc::c_void, small.len()) };
+    let result = if ret < 0 { Err(io::Error::last_os_error().raw_os_error().unwrap_or(0)) } else { Ok(ret) };
+    report.check("read shorter than a record is rejected", expect_errno(result, libc::EINVAL));
+
+    // The device is a stream: no offsets, so seeking and positioned I/O fail with ESPIPE
+    let ret = unsafe { libc::lseek(fd, 0, libc::SEEK_SET) };
+    let result = if ret < 0 { Err(io::Error::last_os_error().raw_os_error().unwrap_or(0)) } else { Ok(ret) };
+    report.check("lseek is rejected", expect_errno(result, libc::ESPIPE));
+
+    let mut record = [Adxl345Sample::default(); 1];
+    let ret = unsafe { libc::pread(fd, record.as_mut_ptr() as *mut libc::c_void, mem::size_of_val(&record), 0) };
+    let result = if ret < 0 { Err(io::Error::last_os_error().raw_os_error().unwrap_or(0)) } else { Ok(ret) };
+    report.check("pread is rejected", expect_errno(result, libc::ESPIPE));
+
+    let ret = unsafe { libc::pwrite(fd, record.as_ptr() as *const libc::c_void, mem::size_of_val(&record), 0) };
+    let result = if ret < 0 { Err(io::Error::last_os_error().raw_os_error().unwrap_or(0)) } else { Ok(ret) };
+    report.check("pwrite is rejected", expect_errno(result, libc::ESPIPE));
+
+    let mut pollfd = libc::pollfd { fd, events: libc::POLLIN, revents: 0 };
+    let ret = unsafe { libc::poll(&mut pollfd, 1, 1000) };
+    report.check("poll reports readable", match ret {
+        1 if pollfd.revents & libc::POLLIN != 0 => Ok(()),
+        r if r < 0 => Err(io::Error::last_os_error().to_string()),
+        _ => Err(format!("poll returned {} with revents {:#x}", ret, pollfd.revents)),
+    });
+
+    let mut queued: libc::c_int = -1;
+    let ret = unsafe { libc::ioctl(fd, libc::FIONREAD, &mut queued) };
+    report.check("FIONREAD returns whole records", match ret {
+        0 if queued >= 0 && (queued as usize).is_multiple_of(mem::size_of::<Adxl345Sample>()) => Ok(()),
+        0 => Err(format!("FIONREAD returned {} bytes", queued)),
+        _ => Err(io::Error::last_os_error().to_string()),
+    });
+
+    let ret = unsafe { libc::fsync(fd) };
+    report.check("fsync flushes the device", if ret == 0 { Ok(()) } else { Err(io::Error::last_os_error().to_string()) });
+
+    report.check("edge poll reports a batch once", poll_modes(fd));
+    report.check("O_ASYNC delivers SIGIO on new data", sigio(fd));
+    let mut policy = ADXL345_ERRORS_BEST_EFFORT;
+    let mut bogus_policy = 2u32;
+    report.check("error policy is selected", ioctl_ptr(fd, ADXL345_IOC_SET_ERROR_POLICY, &mut policy).map_err(errno_str));
+    report.check("unknown error policy is rejected", expect_errno(ioctl_ptr(fd, ADXL345_IOC_SET_ERROR_POLICY, &mut bogus_policy), libc::EINVAL));
+    let mut policy = ADXL345_ERRORS_FAIL_FAST;
+    let _ = ioctl_ptr(fd, ADXL345_IOC_SET_ERROR_POLICY, &mut policy);
+
+    // Records in µg, timestamped, then raw again
+    if caps & ADXL345_CAP_SCALED_OUTPUT != 0 {
+        report.check("scaled output reads whole records", scaled_output(fd));
+        report.check("unknown output format is rejected", expect_errno(ioctl_ptr(fd, ADXL345_IOC_SET_OUTPUT, &mut 4u32), libc::EINVAL));
+    }
+    if caps & ADXL345_CAP_TIMESTAMPS != 0 {
+        report.check("timestamped samples are in order", stamped_output(fd));
+    }
+
+    // The filter threshold goes through the snapshot, restore the default of the driver
+    if caps & ADXL345_CAP_FILTER != 0 {
+        report.check("filter threshold 100", filter_roundtrip(fd, 100));
+        report.check("filter threshold 32768 is rejected", expect_errno(ioctl_ptr(fd, ADXL345_IOC_SET_FILTER, &mut 32_768u32), libc::ERANGE));
+        let _ = filter_roundtrip(fd, 50);
+    } else {
+        report.check("filter ioctl is absent", expect_errno(ioctl_ptr(fd, ADXL345_IOC_SET_FILTER, &mut 0u32), libc::ENOTTY));
+    }
+
+    // A stopped session delivers nothing new, a restarted one delivers data again
+    report.check("STOP/START restarts the session", stop_start(fd));
+    report.check("START emits the session header", session_header(fd));
+
+    let ret = unsafe { libc::ioctl(fd, ADXL345_IOC_FLUSH as _) };
+    report.check("FLUSH discards the buffered samples", if ret == 0 { Ok(()) } else { Err(io::Error::last_os_error().to_string()) });
+
+    match open_device(&path, libc::O_RDONLY | libc::O_NONBLOCK) {
+        Ok(nfd) => {
+            let mut buf = [Adxl345Sample::default(); 16];
+            let mut result = Ok(());
+            for _ in 0..100 {
+                match read_records(nfd, &mut buf) {
+                    Ok(n) => {
+                        if let Err(e) = check_bounds(&buf[..n]) {
+                            result = Err(e);
+                            break;
+                        }
+                    }
+                    Err(libc::EAGAIN) => {}
+                    Err(e) => {
+                        result = Err(format!("read failed: {}", errno_str(e)));
+                        break;
+                    }
+                }
+            }
+            report.check("nonblocking read returns data or EAGAIN", result);
+            unsafe { libc::close(nfd) };
+        }
+        Err(e) => report.check("nonblocking open", Err(errno_str(e))),
+    }
+
+    // Rate accuracy
+    for rate in MEASURED_RATES_MHZ {
+        let result = param_roundtrip(fd, PARAM_RATE, rate).and_then(|_| check_rate(fd, rate));
+        report.check(&format!("data rate at {} mHz", rate), result);
+    }
+
+    // Resampling to a rate below the device rate, for this file only
+    if caps & ADXL345_CAP_RESAMPLE != 0 {
+        let result = resampled_rate(fd, RESAMPLE_DEVICE_MHZ, RESAMPLE_OUTPUT_MHZ);
+        report.check(&format!("resampling {} mHz to {} mHz", RESAMPLE_DEVICE_MHZ, RESAMPLE_OUTPUT_MHZ), result);
+        report.check("resample rate 3200001 is rejected", expect_errno(ioctl_ptr(fd, ADXL345_IOC_SET_RESAMPLE, &mut 3_200_001u32), libc::ERANGE));
+    }
+
+    // Motion events are armed and read back, then disarmed
+    if caps & ADXL345_CAP_MOTION != 0 {
+        let arg = Adxl345MotionArg { events: ADXL345_MOTION_ACTIVITY | ADXL345_MOTION_FREE_FALL, act_inact_ctl: 0x70 };
+        report.check("motion events are armed", motion_roundtrip(fd, arg));
+        let mut bogus = Adxl345MotionArg { events: 1 << 3, act_inact_ctl: 0 };
+        report.check("unknown motion event is rejected", expect_errno(ioctl_ptr(fd, ADXL345_IOC_SET_MOTION, &mut bogus), libc::EINVAL));
+    }
+
+    // Low power and auto sleep are set and read back, then cleared
+    if caps & ADXL345_CAP_POWER != 0 {
+        let arg = Adxl345PowerArg { flags: ADXL345_POWER_LOW_POWER | ADXL345_POWER_AUTO_SLEEP, wakeup_hz: 2 };
+        report.check("low power and auto sleep are set", power_roundtrip(fd, arg));
+        let mut bogus = Adxl345PowerArg { flags: ADXL345_POWER_AUTO_SLEEP, wakeup_hz: 3 };
+        report.check("sleep rate 3 Hz is rejected", expect_errno(ioctl_ptr(fd, ADXL345_IOC_SET_POWER, &mut bogus), libc::EINVAL));
+    }
+
+    // ID 0 is never given to an event marker
+    if caps & ADXL345_CAP_CORRELATION != 0 {
+        let mut unknown = Adxl345EventInfo::default();
+        report.check("unknown event ID is rejected", expect_errno(ioctl_ptr(fd, ADXL345_IOC_GET_EVENT, &mut unknown), libc::ENOENT));
+    }
+
+    // Raw offsets are written and read back, then restored; calibration itself needs the device
+    // held still
+    if caps & ADXL345_CAP_CALIBRATION != 0 {
+        report.check("offsets are set", offsets_roundtrip(fd, Adxl345OffsetArg { x: 3, y: -2, z: -128 }));
+        let mut bogus = Adxl345OffsetArg { x: 128, y: 0, z: 0 };
+        report.check("offset 128 is rejected", expect_errno(ioctl_ptr(fd, ADXL345_IOC_SET_OFFSETS, &mut bogus), libc::EINVAL));
+        let mut bogus = Adxl345CalibrateArg { samples: 1001, ..Default::default() };
+        report.check("calibration over 1000 samples is rejected", expect_errno(ioctl_ptr(fd, ADXL345_IOC_CALIBRATE, &mut bogus), libc::EINVAL));
+    }
+
+    // Stages of the read filter are set and read back, then restored
+    if caps & ADXL345_CAP_PIPELINE != 0 {
+        let mut arg = Adxl345PipelineArg { len: 3, ..Default::default() };
+        arg.stages[0] = Adxl345StageArg { kind: ADXL345_STAGE_AVERAGE, param: 4 };
+        arg.stages[1] = Adxl345StageArg { kind: ADXL345_STAGE_DECIMATE, param: 10 };
+        arg.stages[2] = Adxl345StageArg { kind: ADXL345_STAGE_LIMIT, param: 200 };
+        report.check("read filter stages are set", pipeline_roundtrip(fd, arg));
+        let mut bogus = Adxl345PipelineArg { len: 2, ..Default::default() };
+        bogus.stages[0] = Adxl345StageArg { kind: ADXL345_STAGE_THRESHOLD, param: 0 };
+        bogus.stages[1] = Adxl345StageArg { kind: ADXL345_STAGE_THRESHOLD, param: 0 };
+        report.check("stage given twice is rejected", expect_errno(ioctl_ptr(fd, ADXL345_IOC_SET_PIPELINE, &mut bogus), libc::EINVAL));
+    }
+
+    // The kernel buffer is resized and restored, the bounds are enforced
+    if caps & ADXL345_CAP_BUFFER != 0 {
+        report.check("buffer capacity is set", buffer_roundtrip(fd, ADXL345_BUFFER_MAX));
+        let mut bogus = ADXL345_BUFFER_MIN - 1;
+        report.check("buffer below its minimum is rejected", expect_errno(ioctl_ptr(fd, ADXL345_IOC_SET_BUFFER, &mut bogus), libc::EINVAL));
+    }
+
+    // Fairness between readers of different batch sizes
+    let result = param_roundtrip(fd, PARAM_RATE, MIXED_READERS_RATE_MHZ).and_then(|_| mixed_readers(&path, fd));
+    report.check(&format!("mixed readers at {} mHz are both served", MIXED_READERS_RATE_MHZ), result);
+
+    // Restore the configuration found at start
+    for (param, value) in saved {
+        if let Some(value) = value {
+            if let Err(e) = set_param(fd, param, value) {
+                eprintln!("Failed to restore {}: {}", PARAM_NAMES[param as usize], errno_str(e));
+            }
+        }
+    }
+    unsafe { libc::close(fd) };
+
+    println!(
+        "{} passed, {} failed, {} skipped",
+        report.passed, report.failed, report.skipped
+    );
+    report.failed == 0
+}
@@ -0,0 +1,170 @@
+//! Host comparison of the two designs of the kernel buffer at 3200 Hz: the lock-free SPSC queue of
+//! the driver (src/spsc.rs, built here unchanged) and the ring behind a lock it replaced.
+//!
+//! A producer thread plays the drain: every 5 ms, half the FIFO at 3200 Hz, it queues the 16
+//! samples acquired, one push per sample as the drain reads them, and times each push. A consumer
+//! thread plays a reader issuing reads of 128 records, taking the samples one at a time. It runs
+//! steady, then stalling 60 ms twice a second, as a reader preempted or faulting on its buffer
+//! does. Both buffers hold 128 samples, 40 ms at 3200 Hz, as the ring did.
+//!
+//! Each run prints one JSON line with the push latency and the samples dropped because the buffer
+//! was full. These are host figures, with `std::sync::Mutex` standing for the spinlock; the
+//! in-kernel ones are `push_max_ns` and `samples_dropped` in debugfs.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::bench::percentile;
+use crate::spsc::Adxl345Spsc;
+use crate::structures::Adxl345Sample;
+
+/// Samples the buffers hold.
+const CAPACITY: usize = 128;
+
+/// Interval between two pushes of the producer, the drain period at 3200 Hz.
+const PERIOD: Duration = Duration::from_millis(5);
+
+/// Samples pushed every period.
+const BATCH: usize = 16;
+
+/// Records per read of the consumer.
+const READ_RECORDS: usize = 128;
+
+/// Sleep of the consumer when the buffer is empty, for the wait queue.
+const IDLE: Duration = Duration::from_millis(1);
+
+/// Stall of the stalling consumer, and the interval between two stalls.
+const STALL: Duration = Duration::from_millis(60);
+const STALL_EVERY: Duration = Duration::from_millis(500);
+
+/// The ring the SPSC queue replaced, locked for every push and every pop.
+struct Ring {
+    samples: [Adxl345Sample; CAPACITY],
+    head: usize,   // Index of the oldest sample
+    len: usize,    // Number of queued samples
+}
+
+/// The two sides of a buffer design, each used by one thread.
+trait Buffer: Sync {
+    fn push(&self, sample: Adxl345Sample) -> bool;
+    fn pop(&self) -> Option<Adxl345Sample>;
+}
+
+impl Buffer for Mutex<Ring> {
+    fn push(&self, sample: Adxl345Sample) -> bool {
+        let mut ring = self.lock().unwrap();
+        if ring.len == CAPACITY {
+            return false;
+        }
+        let index = (ring.head + ring.len) % CAPACITY;
+        ring.samples[index] = sample;
+        ring.len += 1;
+        true
+    }
+
+    fn pop(&self) -> Option<Adxl345Sample> {
+        let mut ring = self.lock().unwrap();
+        if ring.len == 0 {
+            return None;
+        }
+        let sample = ring.samples[ring.head];
+        ring.head = (ring.head + 1) % CAPACITY;
+        ring.len -= 1;
+        Some(sample)
+    }
+}
+
+impl Buffer for Adxl345Spsc<CAPACITY> {
+    fn push(&self, sample: Adxl345Sample) -> bool {
+        // SAFETY: Only the producer thread pushes.
+        unsafe { self.push_all(&[sample], 0) }
+    }
+
+    fn pop(&self) -> Option<Adxl345Sample> {
+        // SAFETY: Only the consumer thread pops.
+        unsafe { Adxl345Spsc::pop(self) }.map(|(sample, _)| sample)
+    }
+}
+
+/// Result of a run.
+struct Run {
+    latencies_ns: Vec<u64>,
+    dropped: u64,
+    delivered: u64,
+}
+
+/// Runs the producer and a consumer on `buffer` for `window`.
+fn run_once(buffer: &dyn Buffer, stalling: bool, window: Duration) -> Run {
+    let done = AtomicBool::new(false);
+    thread::scope(|scope| {
+        let consumer = scope.spawn(|| {
+            let mut records = Vec::with_capacity(READ_RECORDS);
+            let mut delivered = 0;
+            let mut next_stall = Instant::now() + STALL_EVERY;
+            while !done.load(Ordering::Relaxed) {
+                if stalling && Instant::now() >= next_stall {
+                    thread::sleep(STALL);
+                    next_stall += STALL_EVERY;
+                }
+                records.clear();
+                while records.len() < READ_RECORDS {
+                    match buffer.pop() {
+                        Some(sample) => records.push(sample),
+                        None => break,
+                    }
+                }
+                if records.is_empty() {
+                    thread::sleep(IDLE);
+                }
+                delivered += records.len() as u64;
+            }
+            delivered
+        });
+
+        let periods = (window.as_nanos() / PERIOD.as_nanos()) as u32;
+        let mut latencies_ns = Vec::with_capacity(periods as usize * BATCH);
+        let mut dropped = 0;
+        let start = Instant::now();
+        for period in 1..=periods {
+            for i in 0..BATCH {
+                let sample = Adxl345Sample::new(i as i16, 0, 256);
+                let begin = Instant::now();
+                let pushed = buffer.push(sample);
+                latencies_ns.push(begin.elapsed().as_nanos() as u64);
+                if !pushed {
+                    dropped += 1;
+                }
+            }
+            if let Some(wait) = (start + PERIOD * period).checked_duration_since(Instant::now()) {
+                thread::sleep(wait);
+            }
+        }
+        done.store(true, Ordering::Relaxed);
+
+        let delivered = consumer.join().unwrap();
+        Run { latencies_ns, dropped, delivered }
+    })
+}
+
+/// Runs both designs with both consumers, `window` each, and prints one JSON line per run.
+pub fn run(window: Duration) {
+    for stalling in [false, true] {
+        for name in ["mutex_ring", "spsc"] {
+            // A new buffer per run, so none starts with the samples left by the previous one
+            let ring = Mutex::new(Ring { samples: [Adxl345Sample::new(0, 0, 0); CAPACITY], head: 0, len: 0 });
+            let spsc = Adxl345Spsc::<CAPACITY>::new();
+            let buffer: &dyn Buffer = if name == "spsc" { &spsc } else { &ring };
+            let mut run = run_once(buffer, stalling, window);
+            run.latencies_ns.sort_unstable();
+            println!(
+                "{{\"mode\":\"spsc_bench\",\"buffer\":\"{}\",\"reader\":\"{}\",\"rate_hz\":3200,\"capacity\":{},\"seconds\":{:.3},\"pushed\":{},\"dropped\":{},\"delivered\":{},\"push_ns\":{{\"p50\":{},\"p99\":{},\"max\":{}}}}}",
+                name, if stalling { "stalling" } else { "steady" }, CAPACITY, window.as_secs_f64(),
+                run.latencies_ns.len(), run.dropped, run.delivered,
+                percentile(&run.latencies_ns, 50), percentile(&run.latencies_ns, 99),
+                percentile(&run.latencies_ns, 100)
+            );
+        }
+    }
+}
@@ -0,0 +1,23 @@
+//! The sample record of the driver, as the driver modules built here (spsc.rs) expect it at
+//! `crate::structures`.
+
+/// Marker tag in `x`, `ADXL345_MARKER_TAG` in src/constant.rs.
+const MARKER_TAG: i16 = i16::MIN;
+
+/// A record of the kernel buffer, three axes or a marker.
+#[derive(Copy, Clone)]
+pub struct Adxl345Sample {
+    pub x: i16,
+    pub y: i16,
+    pub z: i16,
+}
+
+impl Adxl345Sample {
+    pub const fn new(x: i16, y: i16, z: i16) -> Self {
+        Adxl345Sample { x, y, z }
+    }
+
+    pub const fn is_marker(&self) -> bool {
+        self.x == MARKER_TAG
+    }
+}
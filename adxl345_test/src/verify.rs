@@ -0,0 +1,69 @@
+//! End-to-end integrity check against the emulator.
+//!
+//! With the emulator generating its self-checking pattern (waveform 6), every sample carries its
+//! index and a check word (see `libadxl345::integrity`). Reading the stream for a while then
+//! proves that nothing between the data registers and this program corrupts, reorders or loses
+//! samples, except the drops the driver accounts for in debugfs.
+
+use std::fs;
+use std::io;
+use std::time::{Duration, Instant};
+
+use libadxl345::integrity::IntegrityChecker;
+use libadxl345::{Adxl345Device, Record};
+
+/// Debugfs counter of the samples dropped because the kernel buffer was full.
+const DROPPED_PATH: &str = "/sys/kernel/debug/adxl345/samples_dropped";
+
+/// Reads the debugfs drop counter, `None` if debugfs is not available.
+fn dropped() -> Option<u64> {
+    fs::read_to_string(DROPPED_PATH).ok()?.trim().parse().ok()
+}
+
+/// Checks the stream of `path` for `duration`, prints the report and returns true if it passed.
+pub fn run(path: &str, duration: Duration) -> io::Result<bool> {
+    let device = Adxl345Device::open(path)?;
+    let mut checker = IntegrityChecker::new();
+    let dropped_before = dropped();
+    let start = Instant::now();
+
+    for record in device.samples() {
+        match record? {
+            Record::Sample(sample) => checker.push(&sample),
+            Record::Header(_) => checker.restart(),
+            _ => {}
+        }
+        if start.elapsed() >= duration {
+            break;
+        }
+    }
+
+    let report = checker.report();
+    let dropped = dropped_before.zip(dropped()).map(|(before, after)| after - before);
+    println!("valid:        {}", report.valid);
+    println!("corrupt:      {}", report.corrupt);
+    println!("out of order: {}", report.out_of_order);
+    println!("missing:      {}", report.missing);
+    match dropped {
+        Some(dropped) => println!("dropped:      {} (driver)", dropped),
+        None => println!("dropped:      unknown, debugfs not mounted"),
+    }
+
+    if report.valid == 0 {
+        println!("FAIL: no pattern sample, select it with 'echo 6 > /sys/kernel/debug/adxl345_emul/waveform'");
+        return Ok(false);
+    }
+    if !report.is_clean() {
+        println!("FAIL: the stream is corrupted or reordered");
+        return Ok(false);
+    }
+    // Drops after the last sample read are not missing yet, so missing can only be lower
+    if let Some(dropped) = dropped {
+        if report.missing > dropped {
+            println!("FAIL: {} samples lost without being counted as dropped", report.missing - dropped);
+            return Ok(false);
+        }
+    }
+    println!("PASS");
+    Ok(true)
+}
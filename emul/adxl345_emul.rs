@@ -0,0 +1,273 @@
+// SPDX-License-Identifier: GPL-2.0-only
+
+//! Emulator of the ADXL345 accelerometer, for testing the driver without the hardware.
+
+/* 
+ * Copyright 2024 Luca Saverio Esposito, Università di Roma, Tor Vergata 
+ * email: <lucasaverioesposito@gmail.com>
+ *
+ * This file is part of "Rust Linux driver for the ADXL345 device".
+ *
+ * This driver is free software: you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * This driver is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with Foobar.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+// adxl345_emul.rs
+
+// Register the module
+module! {
+    type: Adxl345EmulModule,
+    name: "adxl345_emul",
+    author: "Luca Saverio Esposito",
+    description: "ADXL345 emulator on a virtual I2C adapter",
+    license: "GPL",
+    params: {
+        addr: u16 {
+            default: 0x1D,
+            permissions: 0o444,
+            description: "I2C address the emulated device answers to",
+        },
+    },
+}
+
+mod pattern;
+#[path = "../src/constant.rs"]
+mod constant;
+
+use kernel::prelude::*;
+use kernel::c_str;
+use kernel::i2c::{I2CAdapterRegistration, I2CAlgorithm};
+use kernel::time::ktime_get_ns;
+use core::sync::atomic::{AtomicBool, AtomicU8, AtomicU64, Ordering};
+use crate::constant::*;
+use crate::pattern::{ADXL345_EMUL_PATTERN, adxl345_emul_debugfs_create};
+
+/// Number of registers of the device.
+const ADXL345_REG_COUNT: usize = 0x40;
+
+/// Samples held by the device outside of bypass mode: the FIFO and the data registers.
+const ADXL345_HELD_MAX: u64 = 33;
+
+/// Picoseconds per second times 1000, divided by a rate in mHz it gives the sample period.
+const PS_PER_SEC_MHZ: u64 = 1_000_000_000_000_000;
+
+/// Output data rates, in mHz, indexed by the BW_RATE rate code.
+const ADXL345_RATES_MHZ: [u64; 16] = [
+    100, 200, 390, 780, 1_560, 3_130, 6_250, 12_500,
+    25_000, 50_000, 100_000, 200_000, 400_000, 800_000, 1_600_000, 3_200_000,
+];
+
+#[allow(clippy::declare_interior_mutable_const)]
+const REG_INIT: AtomicU8 = AtomicU8::new(0);
+
+/// The emulated device: register map and data timing, samples come from the pattern generator.
+///
+/// Sample `n` is acquired `n + 1` periods after the measurement starts. The device holds the
+/// samples not read yet, one in bypass mode and 33 otherwise (the FIFO as in stream mode); the
+/// older ones are overwritten, and skipped in the pattern, so they are missing from the stream.
+///
+/// Transfers are serialized by the I2C core, atomics are only used to share the state.
+struct Adxl345Emul {
+    addr: u16,
+    regs: [AtomicU8; ADXL345_REG_COUNT],
+    start_ns: AtomicU64,  // Start of the measurement, at the last POWER_CTL, BW_RATE or FIFO_CTL write
+    consumed: AtomicU64,  // Samples read or overwritten since the start
+    overrun: AtomicBool,  // Set when a sample was overwritten, cleared by reading INT_SOURCE
+}
+
+impl Adxl345Emul {
+    /// Creates the device with the power-on values of the registers.
+    fn new(addr: u16) -> Self {
+        let emul = Self {
+            addr,
+            regs: [REG_INIT; ADXL345_REG_COUNT],
+            start_ns: AtomicU64::new(0),
+            consumed: AtomicU64::new(0),
+            overrun: AtomicBool::new(false),
+        };
+        emul.store(ADXL345_REG_DEVID, ADXL345_DEVID);
+        emul.store(ADXL345_REG_BW_RATE, 0x0A);
+        emul.store(ADXL345_REG_INT_SOURCE, 0x02);
+        emul
+    }
+
+    fn load(&self, reg: u8) -> u8 {
+        self.regs[reg as usize].load(Ordering::Relaxed)
+    }
+
+    fn store(&self, reg: u8, value: u8) {
+        self.regs[reg as usize].store(value, Ordering::Relaxed)
+    }
+
+    /// Returns the output data rate selected in BW_RATE, in mHz.
+    fn rate_mhz(&self) -> u64 {
+        ADXL345_RATES_MHZ[(self.load(ADXL345_REG_BW_RATE) & 0x0F) as usize]
+    }
+
+    /// Restarts the measurement, after a change of the power mode, the rate or the FIFO mode.
+    fn restart(&self) {
+        self.start_ns.store(ktime_get_ns(), Ordering::Relaxed);
+        self.consumed.store(0, Ordering::Relaxed);
+    }
+
+    /// Returns the number of samples acquired and not read yet, overwriting the ones beyond
+    /// what the device holds.
+    fn held(&self) -> u64 {
+        if self.load(ADXL345_REG_POWER_CTL) & (1 << 3) == 0 {
+            return 0;
+        }
+        let elapsed_ns = ktime_get_ns().saturating_sub(self.start_ns.load(Ordering::Relaxed));
+        let acquired = elapsed_ns * 1000 / (PS_PER_SEC_MHZ / self.rate_mhz());
+        let consumed = self.consumed.load(Ordering::Relaxed);
+        let capacity = match self.load(ADXL345_REG_FIFO_CTL) >> 6 {
+            0 => 1,
+            _ => ADXL345_HELD_MAX,
+        };
+
+        let held = acquired.saturating_sub(consumed);
+        if held > capacity {
+            ADXL345_EMUL_PATTERN.skip((held - capacity) as u32);
+            self.consumed.store(acquired - capacity, Ordering::Relaxed);
+            self.overrun.store(true, Ordering::Relaxed);
+            return capacity;
+        }
+        held
+    }
+
+    /// Converts an acceleration in mg into the data register format selected in DATA_FORMAT.
+    fn to_lsb(&self, mg: i32) -> i16 {
+        let format = self.load(ADXL345_REG_DATA_FORMAT);
+        let range_g = 2i32 << (format & 0x03);
+        if format & (1 << 3) != 0 {
+            // Full resolution, 3.9 mg/LSB whatever the range
+            let limit = range_g * 256;
+            (mg * 256 / 1000).clamp(-limit, limit - 1) as i16
+        } else {
+            // 10-bit resolution over the selected range
+            (mg * 512 / (range_g * 1000)).clamp(-512, 511) as i16
+        }
+    }
+
+    /// Latches the next sample of the pattern into the data registers, once it was acquired:
+    /// reading faster than the rate reads the same sample again.
+    fn latch_sample(&self) {
+        if self.held() == 0 {
+            return;
+        }
+        self.consumed.fetch_add(1, Ordering::Relaxed);
+
+        let rate_mhz = self.rate_mhz();
+        let sample = ADXL345_EMUL_PATTERN.next(rate_mhz, |mg| self.to_lsb(mg));
+
+        for (offset, lsb) in sample.iter().enumerate() {
+            let bytes = lsb.to_le_bytes();
+            let reg = ADXL345_REG_DATAX0 + 2 * offset as u8;
+            self.store(reg, bytes[0]);
+            self.store(reg + 1, bytes[1]);
+        }
+    }
+}
+
+impl I2CAlgorithm for Adxl345Emul {
+    fn read_byte_data(&self, addr: u16, command: u8) -> Result<u8> {
+        if addr != self.addr {
+            return Err(ENXIO);
+        }
+        match command {
+            ADXL345_REG_INT_SOURCE => {
+                let ready = if self.held() > 0 { 0x80 } else { 0 };
+                let overrun = if self.overrun.swap(false, Ordering::Relaxed) { 0x01 } else { 0 };
+                Ok(self.load(command) | ready | overrun)
+            }
+            // The entries of the FIFO, the data registers hold one more sample
+            ADXL345_REG_FIFO_STATUS => {
+                let entries = match self.load(ADXL345_REG_FIFO_CTL) >> 6 {
+                    0 => 0,
+                    _ => self.held().saturating_sub(1),
+                };
+                Ok(entries as u8)
+            }
+            // Reading DATAX0 starts a new data read, as the device does for multi-byte reads
+            ADXL345_REG_DATAX0 => {
+                self.latch_sample();
+                Ok(self.load(command))
+            }
+            _ if (command as usize) < ADXL345_REG_COUNT => Ok(self.load(command)),
+            _ => Err(EIO),
+        }
+    }
+
+    fn write_byte_data(&self, addr: u16, command: u8, value: u8) -> Result {
+        if addr != self.addr {
+            return Err(ENXIO);
+        }
+        match command {
+            // Read-only registers ignore writes
+            ADXL345_REG_DEVID
+            | ADXL345_REG_ACT_TAP_STATUS
+            | ADXL345_REG_INT_SOURCE
+            | ADXL345_REG_DATAX0..=ADXL345_REG_DATAZ1
+            | ADXL345_REG_FIFO_STATUS => Ok(()),
+            ADXL345_REG_POWER_CTL | ADXL345_REG_BW_RATE | ADXL345_REG_FIFO_CTL => {
+                self.store(command, value);
+                self.restart();
+                Ok(())
+            }
+            _ if (command as usize) < ADXL345_REG_COUNT => {
+                self.store(command, value);
+                Ok(())
+            }
+            _ => Err(EIO),
+        }
+    }
+}
+
+struct Adxl345EmulModule {
+    _adapter: Pin<Box<I2CAdapterRegistration<Adxl345Emul>>>,
+    _debugfs: Option<kernel::debugfs::Dir>,
+}
+
+impl kernel::Module for Adxl345EmulModule {
+    fn init(_name: &'static CStr, module: &'static ThisModule) -> Result<Self> {
+        let adapter = I2CAdapterRegistration::new_pinned(
+            c_str!("adxl345-emul"),
+            Adxl345Emul::new(*addr.read()),
+            module,
+        )?;
+
+        pr_info!(
+            "ADXL345 emulated at 0x{:02x} on i2c-{}, load adxl345 with i2c_bus={}\n",
+            *addr.read(),
+            adapter.bus_number(),
+            adapter.bus_number()
+        );
+
+        // Without debugfs the generator keeps its default waveform
+        let debugfs = match adxl345_emul_debugfs_create() {
+            Ok(dir) => Some(dir),
+            Err(e) => {
+                pr_warn!("Pattern generator controls not available: {:?}\n", e);
+                None
+            }
+        };
+
+        Ok(Adxl345EmulModule { _adapter: adapter, _debugfs: debugfs })
+    }
+}
+
+impl Drop for Adxl345EmulModule {
+    fn drop(&mut self) {
+        // The adapter is deleted when the registration is dropped
+        pr_info!("ADXL345 emulator unloaded\n");
+    }
+}
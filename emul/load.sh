@@ -0,0 +1,40 @@
+#!/bin/bash
+
+# Loads the ADXL345 emulator and the driver on top of it, then creates the device file.
+# Usage: ./emul/load.sh [unload]
+# Run from the repository root, after building both modules (make ADXL345_EMUL=1).
+
+EMUL_NAME="adxl345-emul"
+
+if [ "$1" == "unload" ]; then
+    # The driver must go first, it owns a client on the emulated adapter
+    rmmod adxl345
+    rmmod adxl345_emul
+    exit 0
+fi
+
+if [ ! -f adxl345_emul.ko ]; then
+    echo "Error: adxl345_emul.ko not found, build it with make ADXL345_EMUL=1."
+    exit 1
+fi
+
+insmod adxl345_emul.ko || exit 1
+
+# Find the bus number assigned to the emulated adapter
+BUS=""
+for name in /sys/bus/i2c/devices/i2c-*/name; do
+    if [ "$(cat "$name")" == "$EMUL_NAME" ]; then
+        BUS=$(basename "$(dirname "$name")" | cut -d- -f2)
+    fi
+done
+
+if [ -z "$BUS" ]; then
+    echo "Error: adapter '$EMUL_NAME' not found."
+    rmmod adxl345_emul
+    exit 1
+fi
+
+echo "Emulated adapter on i2c-$BUS"
+insmod adxl345.ko i2c_bus="$BUS" || exit 1
+
+"$(dirname "$0")/../add-dev.sh"
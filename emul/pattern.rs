@@ -0,0 +1,303 @@
+/*
+ * Copyright 2024 Luca Saverio Esposito, Università di Roma, Tor Vergata
+ * email: <lucasaverioesposito@gmail.com>
+ *
+ * This file is part of "Rust Linux driver for the ADXL345 device".
+ *
+ * This driver is free software: you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * This driver is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with Foobar.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+
+// pattern.rs
+
+//! Pattern generator feeding the data registers of the emulator.
+//!
+//! The generator is driven from userspace through `/sys/kernel/debug/adxl345_emul/`, so tests
+//! can compute the exact samples the driver must return. Every sample is a function of its index
+//! only (and of the output data rate for periodic waveforms): the device lies flat (1 g on z)
+//! and the selected waveform is added on one axis.
+//!
+//! The integrity waveform is not an acceleration: it writes a self-checking pattern straight into
+//! the data registers, for end-to-end checks of the stack (see `libadxl345::integrity`).
+
+use kernel::prelude::*;
+use kernel::c_str;
+use kernel::debugfs::Dir;
+use kernel::file::{File, Operations};
+use kernel::io_buffer::IoBufferReader;
+use kernel::ForeignOwnable;
+use core::sync::atomic::{AtomicU16, AtomicU32, Ordering};
+
+/// Maximum number of samples of the replay buffer.
+pub (crate) const ADXL345_EMUL_REPLAY_LEN: usize = 4096;
+
+/// Size of a replay record: x, y and z in mg, as native `i16`.
+const ADXL345_EMUL_RECORD_SIZE: usize = 6;
+
+/// Number of distinct indexes of the integrity pattern.
+const INTEGRITY_PERIOD: u32 = 1 << 24;
+
+/// Quarter of a sine wave in Q15, sampled 64 times (256 steps per period).
+const SINE_QUARTER: [i32; 65] = [
+    0, 804, 1608, 2410, 3212, 4011, 4808, 5602,
+    6393, 7179, 7962, 8739, 9512, 10278, 11039, 11793,
+    12539, 13279, 14010, 14732, 15446, 16151, 16846, 17530,
+    18204, 18868, 19519, 20159, 20787, 21403, 22005, 22594,
+    23170, 23731, 24279, 24811, 25329, 25832, 26319, 26790,
+    27245, 27683, 28105, 28510, 28898, 29268, 29621, 29956,
+    30273, 30571, 30852, 31113, 31356, 31580, 31785, 31971,
+    32137, 32285, 32412, 32521, 32609, 32678, 32728, 32757,
+    32767,
+];
+
+/// Waveforms of the generator, selected by writing their code to `waveform`.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub (crate) enum Adxl345Waveform {
+    /// Only gravity.
+    Flat = 0,
+    /// Triangle between -amplitude and +amplitude.
+    Triangle = 1,
+    /// Sine of the given amplitude.
+    Sine = 2,
+    /// 0 before sample `delay`, amplitude from it on.
+    Step = 3,
+    /// Uniform noise in [-amplitude, amplitude], repeatable for a given `seed`.
+    Noise = 4,
+    /// Samples written to `replay`, played in a loop on all axes.
+    Replay = 5,
+    /// Self-checking pattern carrying the sample index, in raw register counts.
+    Integrity = 6,
+}
+
+impl Adxl345Waveform {
+    fn from_raw(raw: u32) -> Self {
+        match raw {
+            1 => Self::Triangle,
+            2 => Self::Sine,
+            3 => Self::Step,
+            4 => Self::Noise,
+            5 => Self::Replay,
+            6 => Self::Integrity,
+            _ => Self::Flat,
+        }
+    }
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const REPLAY_INIT: AtomicU16 = AtomicU16::new(0);
+
+/// Parameters of the generator, each of them is a debugfs knob.
+pub (crate) struct Adxl345EmulPattern {
+    waveform: AtomicU32,
+    axis: AtomicU32,
+    amplitude_mg: AtomicU32,
+    frequency_mhz: AtomicU32,
+    delay: AtomicU32,
+    seed: AtomicU32,
+    sample: AtomicU32,
+    replay: [AtomicU16; 3 * ADXL345_EMUL_REPLAY_LEN],
+    replay_len: AtomicU32,
+}
+
+/// Global generator, there is a single emulated device.
+pub (crate) static ADXL345_EMUL_PATTERN: Adxl345EmulPattern = Adxl345EmulPattern::new();
+
+impl Adxl345EmulPattern {
+    const fn new() -> Self {
+        Self {
+            waveform: AtomicU32::new(Adxl345Waveform::Triangle as u32),
+            axis: AtomicU32::new(0),
+            amplitude_mg: AtomicU32::new(1000),
+            frequency_mhz: AtomicU32::new(2500),
+            delay: AtomicU32::new(0),
+            seed: AtomicU32::new(1),
+            sample: AtomicU32::new(0),
+            replay: [REPLAY_INIT; 3 * ADXL345_EMUL_REPLAY_LEN],
+            replay_len: AtomicU32::new(0),
+        }
+    }
+
+    /// Returns the next sample, in data register counts for x, y and z.
+    ///
+    /// # Parameters
+    /// - `rate_mhz`: The output data rate, periodic waveforms are sampled at this rate.
+    /// - `to_lsb`: Converts an acceleration in mg into data register counts.
+    pub (crate) fn next(&self, rate_mhz: u64, to_lsb: impl Fn(i32) -> i16) -> [i16; 3] {
+        let index = self.sample.fetch_add(1, Ordering::Relaxed);
+        match Adxl345Waveform::from_raw(self.waveform.load(Ordering::Relaxed)) {
+            Adxl345Waveform::Integrity => integrity_sample(index),
+            Adxl345Waveform::Replay => self.replay_sample(index).map(to_lsb),
+            waveform => self.waveform_sample(waveform, index, rate_mhz).map(to_lsb),
+        }
+    }
+
+    /// Skips `count` samples, the ones the device overwrote before they were read.
+    pub (crate) fn skip(&self, count: u32) {
+        self.sample.fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Returns sample `index` of a waveform added to gravity, in mg for x, y and z.
+    fn waveform_sample(&self, waveform: Adxl345Waveform, index: u32, rate_mhz: u64) -> [i32; 3] {
+        let amplitude = self.amplitude_mg.load(Ordering::Relaxed) as i32;
+
+        // Position in the period, in 1/256 of period
+        let phase = (index as u64 * self.frequency_mhz.load(Ordering::Relaxed) as u64 * 256
+            / rate_mhz.max(1)) as u32 % 256;
+
+        let value = match waveform {
+            Adxl345Waveform::Flat | Adxl345Waveform::Replay | Adxl345Waveform::Integrity => 0,
+            Adxl345Waveform::Triangle => {
+                let phase = phase as i32;
+                if phase < 128 {
+                    -amplitude + amplitude * phase / 64
+                } else {
+                    3 * amplitude - amplitude * phase / 64
+                }
+            }
+            Adxl345Waveform::Sine => amplitude * sine_q15(phase) / 32767,
+            Adxl345Waveform::Step => {
+                if index >= self.delay.load(Ordering::Relaxed) {
+                    amplitude
+                } else {
+                    0
+                }
+            }
+            Adxl345Waveform::Noise => {
+                let random = hash(index ^ self.seed.load(Ordering::Relaxed));
+                (random % (2 * amplitude as u32 + 1)) as i32 - amplitude
+            }
+        };
+
+        let mut sample = [0, 0, 1000];
+        let axis = core::cmp::min(self.axis.load(Ordering::Relaxed), 2) as usize;
+        sample[axis] += value;
+        sample
+    }
+
+    /// Returns sample `index` of the replay buffer, looping over it.
+    fn replay_sample(&self, index: u32) -> [i32; 3] {
+        let len = self.replay_len.load(Ordering::Acquire) as usize;
+        if len == 0 {
+            return [0, 0, 1000];
+        }
+        let base = 3 * (index as usize % len);
+        [0, 1, 2].map(|axis| self.replay[base + axis].load(Ordering::Relaxed) as i16 as i32)
+    }
+
+    /// Appends replay records starting at byte `offset` of the replay file.
+    fn write_replay(&self, reader: &mut impl IoBufferReader, offset: u64) -> Result<usize> {
+        let len = reader.len();
+        if offset as usize % ADXL345_EMUL_RECORD_SIZE != 0 || len % ADXL345_EMUL_RECORD_SIZE != 0 {
+            return Err(EINVAL);
+        }
+        let first = offset as usize / ADXL345_EMUL_RECORD_SIZE;
+        let count = len / ADXL345_EMUL_RECORD_SIZE;
+        if first + count > ADXL345_EMUL_REPLAY_LEN {
+            return Err(ENOSPC);
+        }
+
+        // A new recording replaces the previous one
+        if first == 0 {
+            self.replay_len.store(0, Ordering::Release);
+        }
+
+        let mut record = [0u8; ADXL345_EMUL_RECORD_SIZE];
+        for n in first..first + count {
+            reader.read_slice(&mut record)?;
+            for axis in 0..3 {
+                let value = u16::from_ne_bytes([record[2 * axis], record[2 * axis + 1]]);
+                self.replay[3 * n + axis].store(value, Ordering::Relaxed);
+            }
+        }
+        self.replay_len.fetch_max((first + count) as u32, Ordering::AcqRel);
+        Ok(len)
+    }
+}
+
+/// Returns sample `index` of the integrity pattern, in data register counts.
+///
+/// Three 12-bit words offset by 2048: x holds the low 12 bits of the index scrambled (times 2731
+/// modulo 4096, so consecutive samples are never discarded by the read filter of the driver), y
+/// the low 12 bits of the hash of the index and z its bits 12 to 23. It must match
+/// `libadxl345/src/integrity.rs`.
+fn integrity_sample(index: u32) -> [i16; 3] {
+    let n = index % INTEGRITY_PERIOD;
+    let word = |w: u32| ((w & 0xFFF) as i32 - 2048) as i16;
+    [word(n.wrapping_mul(2731)), word(hash(n)), word(n >> 12)]
+}
+
+/// Returns the sine of `phase` (in 1/256 of period) in Q15.
+fn sine_q15(phase: u32) -> i32 {
+    let quarter = (phase % 64) as usize;
+    match phase / 64 {
+        0 => SINE_QUARTER[quarter],
+        1 => SINE_QUARTER[64 - quarter],
+        2 => -SINE_QUARTER[quarter],
+        _ => -SINE_QUARTER[64 - quarter],
+    }
+}
+
+/// Mixes the bits of `value` (lowbias32), so noise samples are independent of each other.
+fn hash(mut value: u32) -> u32 {
+    value ^= value >> 16;
+    value = value.wrapping_mul(0x7feb352d);
+    value ^= value >> 15;
+    value = value.wrapping_mul(0x846ca68b);
+    value ^ (value >> 16)
+}
+
+/// Write-only `replay` file, filled with the samples of the replay waveform.
+struct Adxl345EmulReplayFile;
+
+impl Operations for Adxl345EmulReplayFile {
+    type Data = ();
+    type OpenData = ();
+
+    const HAS_WRITE: bool = true;
+    // Required constant to indicate that the vtable should be used
+    const USE_VTABLE_ATTR: () = ();
+
+    fn open(_context: &Self::OpenData, _file: &File) -> Result<Self::Data> {
+        Ok(())
+    }
+
+    fn write(
+        _data: <Self::Data as ForeignOwnable>::Borrowed<'_>,
+        _file: &File,
+        reader: &mut impl IoBufferReader,
+        offset: u64,
+    ) -> Result<usize> {
+        ADXL345_EMUL_PATTERN.write_replay(reader, offset)
+    }
+}
+
+/// Creates the debugfs control interface of the generator.
+///
+/// The entries are removed when the returned `Dir` is dropped.
+pub (crate) fn adxl345_emul_debugfs_create() -> Result<Dir> {
+    let dir = Dir::new(c_str!("adxl345_emul"), None)?;
+    let pattern = &ADXL345_EMUL_PATTERN;
+
+    dir.create_u32(c_str!("waveform"), 0o644, &pattern.waveform);
+    dir.create_u32(c_str!("axis"), 0o644, &pattern.axis);
+    dir.create_u32(c_str!("amplitude_mg"), 0o644, &pattern.amplitude_mg);
+    dir.create_u32(c_str!("frequency_mhz"), 0o644, &pattern.frequency_mhz);
+    dir.create_u32(c_str!("delay"), 0o644, &pattern.delay);
+    dir.create_u32(c_str!("seed"), 0o644, &pattern.seed);
+    dir.create_u32(c_str!("sample"), 0o644, &pattern.sample);
+    dir.create_u32(c_str!("replay_len"), 0o444, &pattern.replay_len);
+    dir.create_file::<Adxl345EmulReplayFile>(c_str!("replay"), 0o200, &())?;
+
+    Ok(dir)
+}
@@ -0,0 +1,99 @@
+#!/bin/bash
+
+# Stress test of the driver teardown, on the emulator.
+# Usage: ./emul/teardown_stress.sh [iterations] [readers]
+# Run as root from the repository root, after building both modules. The driver and the
+# emulator must not be loaded.
+#
+# Every iteration loads the emulator and the driver, streams at 3200 Hz with blocking and
+# non-blocking readers plus fsync() calls, so the FIFO is mid-drain and a drain work item is
+# pending, then:
+#   1. unbinds the device under the readers: each of them must fail with ENODEV within 2 s,
+#   2. binds it again and streams, then stops the readers and unloads right away, with the
+#      drain still pending from the last release.
+# The kernel log is checked for oopses, warnings and lockdep reports after every iteration.
+
+ITERATIONS=${1:-50}
+READERS=${2:-4}
+DEVICE="/dev/adxl345"
+DRIVER_DIR="/sys/bus/i2c/drivers/adxl345"
+TEST="./adxl345_test/target/release/adxl345_test"
+LOG=$(mktemp -d)
+
+fail() {
+    echo "FAIL (iteration $i): $1"
+    dmesg | tail -n 50
+    ./emul/load.sh unload 2>/dev/null
+    exit 1
+}
+
+# Starts the readers in the background, their pids in READER_PIDS
+start_readers() {
+    READER_PIDS=""
+    for r in $(seq "$READERS"); do
+        if [ $((r % 2)) -eq 0 ]; then
+            # Blocking, large reads
+            dd if="$DEVICE" of=/dev/null bs=384 2>"$LOG/reader$r" &
+        else
+            # Non-blocking, single records, with fsync() between reads
+            python3 -c '
+import os, sys
+fd = os.open(sys.argv[1], os.O_RDONLY | os.O_NONBLOCK)
+while True:
+    try:
+        os.read(fd, 6)
+        os.fsync(fd)
+    except BlockingIOError:
+        pass
+' "$DEVICE" 2>"$LOG/reader$r" &
+        fi
+        READER_PIDS="$READER_PIDS $!"
+    done
+}
+
+# Waits up to 2 s for the readers to exit on their own
+wait_readers() {
+    for _ in $(seq 20); do
+        local alive=0
+        for pid in $READER_PIDS; do
+            kill -0 "$pid" 2>/dev/null && alive=1
+        done
+        [ $alive -eq 0 ] && return 0
+        sleep 0.1
+    done
+    return 1
+}
+
+dmesg -C
+for i in $(seq "$ITERATIONS"); do
+    ./emul/load.sh >/dev/null || fail "load"
+    CLIENT=$(basename "$(readlink -f "$DRIVER_DIR"/*-001d)")
+
+    # Highest rate, the drain can't keep the device empty between two work items
+    "$TEST" "$DEVICE" --set rate=3200000 --duration 10ms >/dev/null || fail "configure"
+
+    # 1. Remove under the readers
+    start_readers
+    sleep "$(printf "0.%03d" $((RANDOM % 300)))"
+    echo "$CLIENT" > "$DRIVER_DIR/unbind" || fail "unbind"
+    wait_readers || fail "readers still blocked after remove"
+    for r in $(seq "$READERS"); do
+        grep -q "No such device" "$LOG/reader$r" || fail "reader $r: $(cat "$LOG/reader$r")"
+    done
+
+    # 2. Probe again, then unload with the drain of the last release still pending
+    echo "$CLIENT" > "$DRIVER_DIR/bind" || fail "bind"
+    start_readers
+    sleep "$(printf "0.%03d" $((RANDOM % 300)))"
+    kill $READER_PIDS
+    wait $READER_PIDS 2>/dev/null
+    ./emul/load.sh unload || fail "unload"
+
+    if dmesg | grep -E -q "BUG|WARNING|Oops|panic|possible .* deadlock|circular locking"; then
+        fail "kernel log"
+    fi
+    echo "iteration $i: ok"
+done
+
+rm -rf "$LOG"
+echo "PASS: $ITERATIONS iterations, $READERS readers"
@@ -0,0 +1,61 @@
+#!/bin/bash
+
+# Regression test: the driver module can't be unloaded while the device is open.
+# Usage: ./emul/unload_busy.sh
+# Run as root from the repository root, after building both modules. The driver and the
+# emulator must not be loaded.
+#
+# With a reader blocked in read() (the session is stopped, so no data arrives) and one streaming,
+# rmmod must fail cleanly: the module stays loaded with a reference per open file and the
+# readers keep working. Once they are closed rmmod must succeed.
+
+DEVICE="/dev/adxl345"
+REFCNT="/sys/module/adxl345/refcnt"
+
+fail() {
+    echo "FAIL: $1"
+    kill $BLOCKED $STREAMING 2>/dev/null
+    wait $BLOCKED $STREAMING 2>/dev/null
+    ./emul/load.sh unload 2>/dev/null
+    exit 1
+}
+
+./emul/load.sh >/dev/null || exit 1
+dmesg -C
+BASE=$(cat "$REFCNT")
+
+# Blocked reader: stops the session (ADXL345_IOC_STOP), then waits in read() for data that
+# never comes
+python3 -c '
+import fcntl, os, sys
+fd = os.open(sys.argv[1], os.O_RDONLY)
+fcntl.ioctl(fd, 0x410B)
+os.read(fd, 6)
+' "$DEVICE" &
+BLOCKED=$!
+
+# Streaming reader, on its own file
+dd if="$DEVICE" of=/dev/null bs=384 2>/dev/null &
+STREAMING=$!
+sleep 1
+
+# Two references per open file: the one of the driver and the one the VFS holds on the owner of
+# the char device
+[ "$(cat "$REFCNT")" -eq $((BASE + 4)) ] || fail "refcnt $(cat "$REFCNT"), expected $((BASE + 4))"
+
+ERR=$(rmmod adxl345 2>&1) && fail "rmmod succeeded with the device open"
+echo "rmmod refused: $ERR"
+grep -q "^adxl345 " /proc/modules || fail "module gone"
+kill -0 $BLOCKED 2>/dev/null || fail "blocked reader exited"
+kill -0 $STREAMING 2>/dev/null || fail "streaming reader exited"
+
+kill $BLOCKED $STREAMING
+wait $BLOCKED $STREAMING 2>/dev/null
+[ "$(cat "$REFCNT")" -eq "$BASE" ] || fail "refcnt $(cat "$REFCNT") after close, expected $BASE"
+
+./emul/load.sh unload || fail "unload after close"
+if dmesg | grep -E -q "BUG|WARNING|Oops|panic"; then
+    dmesg | tail -n 50
+    fail "kernel log"
+fi
+echo "PASS"
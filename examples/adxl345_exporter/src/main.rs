@@ -0,0 +1,209 @@
+//! Example Prometheus exporter of the sensor readings and of the driver counters.
+//!
+//! A reader thread consumes the stream and keeps the last acceleration, the measured sample rate
+//! and the fill of the kernel buffer (`FIONREAD` before each read). On every scrape of `/metrics`
+//! the driver counters are read from debugfs, so ops teams can alert on a sensor that stopped,
+//! slowed down, overruns its buffer or fails on the bus. Without debugfs the counters are left
+//! out and `adxl345_debugfs_available` is 0.
+
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::process::exit;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use libadxl345::{Adxl345Device, Adxl345Sample, Param, RECORD_SIZE};
+
+/// Default listening address.
+const DEFAULT_LISTEN: &str = "0.0.0.0:9345";
+
+/// Directory of the driver debugfs entries.
+const DEBUGFS_DIR: &str = "/sys/kernel/debug/adxl345";
+
+/// Period over which the sample rate is measured.
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// Debugfs counters exported, with their metric name and help.
+const COUNTERS: [(&str, &str, &str); 6] = [
+    ("samples_drained", "adxl345_samples_drained_total", "Samples moved from the device into the kernel buffer."),
+    ("samples_dropped", "adxl345_samples_dropped_total", "Samples lost because the kernel buffer was full (overruns)."),
+    ("samples_delivered", "adxl345_samples_delivered_total", "Samples copied to userspace."),
+    ("samples_filtered", "adxl345_samples_filtered_total", "Samples discarded by the threshold filter."),
+    ("markers", "adxl345_markers_total", "Markers embedded in the stream."),
+    ("bus_errors", "adxl345_i2c_errors_total", "Failed I2C register transactions."),
+];
+
+/// Readings kept by the reader thread.
+#[derive(Default)]
+struct Readings {
+    up: bool,
+    acceleration_mg: Option<[f64; 3]>,
+    last_sample: Option<Instant>,
+    rate_hz: f64,
+    configured_rate_hz: Option<f64>,
+    buffer_records: u64,
+}
+
+/// Reads the stream forever, updating `readings`.
+fn read_loop(device: Adxl345Device, readings: &Mutex<Readings>) {
+    let mut window_start = Instant::now();
+    let mut window_samples = 0u64;
+
+    loop {
+        // Sample the buffer fill before the read empties it
+        if let Ok(bytes) = device.readable_bytes() {
+            readings.lock().unwrap().buffer_records = (bytes / RECORD_SIZE) as u64;
+        }
+
+        let mut batch = [Adxl345Sample::default(); 64];
+        let n = match device.read_records(&mut batch) {
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => {
+                eprintln!("Failed to read from device: {}", e);
+                readings.lock().unwrap().up = false;
+                return;
+            }
+        };
+
+        let mut last = None;
+        for raw in &batch[..n] {
+            if !raw.is_marker() {
+                last = Some(raw.to_mg());
+                window_samples += 1;
+            }
+        }
+
+        let mut readings = readings.lock().unwrap();
+        readings.up = true;
+        if last.is_some() {
+            readings.acceleration_mg = last;
+            readings.last_sample = Some(Instant::now());
+        }
+        let elapsed = window_start.elapsed();
+        if elapsed >= RATE_WINDOW {
+            readings.rate_hz = window_samples as f64 / elapsed.as_secs_f64();
+            readings.configured_rate_hz = device.param(Param::Rate).ok().map(|mhz| mhz as f64 / 1000.0);
+            window_start = Instant::now();
+            window_samples = 0;
+        }
+    }
+}
+
+/// Appends a metric with its help and type lines.
+fn metric(out: &mut String, name: &str, kind: &str, help: &str, samples: &[(&str, f64)]) {
+    out.push_str(&format!("# HELP {} {}\n# TYPE {} {}\n", name, help, name, kind));
+    for (labels, value) in samples {
+        out.push_str(&format!("{}{} {}\n", name, labels, value));
+    }
+}
+
+/// Renders the exposition of the current readings and counters.
+fn render(readings: &Readings) -> String {
+    let mut out = String::new();
+
+    metric(&mut out, "adxl345_up", "gauge", "1 while the stream is being read.", &[("", readings.up as u8 as f64)]);
+    if let Some([x, y, z]) = readings.acceleration_mg {
+        metric(
+            &mut out,
+            "adxl345_acceleration_mg",
+            "gauge",
+            "Last acceleration sample, in mg.",
+            &[("{axis=\"x\"}", x), ("{axis=\"y\"}", y), ("{axis=\"z\"}", z)],
+        );
+    }
+    // The rate is measured on reads, a stream that stopped would keep its last rate otherwise
+    let stalled = readings.last_sample.is_none_or(|t| t.elapsed() > 2 * RATE_WINDOW);
+    let rate = if stalled { 0.0 } else { readings.rate_hz };
+    metric(&mut out, "adxl345_sample_rate_hz", "gauge", "Samples read per second, measured.", &[("", rate)]);
+    if let Some(rate) = readings.configured_rate_hz {
+        metric(&mut out, "adxl345_configured_rate_hz", "gauge", "Output data rate configured in the device.", &[("", rate)]);
+    }
+    metric(
+        &mut out,
+        "adxl345_buffer_records",
+        "gauge",
+        "Records waiting in the kernel buffer before the last read.",
+        &[("", readings.buffer_records as f64)],
+    );
+
+    let mut available = false;
+    for (file, name, help) in COUNTERS {
+        let value = fs::read_to_string(format!("{}/{}", DEBUGFS_DIR, file)).ok().and_then(|s| s.trim().parse::<u64>().ok());
+        if let Some(value) = value {
+            available = true;
+            metric(&mut out, name, "counter", help, &[("", value as f64)]);
+        }
+    }
+    let push_max = fs::read_to_string(format!("{}/push_max_ns", DEBUGFS_DIR)).ok().and_then(|s| s.trim().parse::<u64>().ok());
+    if let Some(ns) = push_max {
+        metric(
+            &mut out,
+            "adxl345_push_max_seconds",
+            "gauge",
+            "Longest time the drain took to queue a sample.",
+            &[("", ns as f64 / 1e9)],
+        );
+    }
+    metric(&mut out, "adxl345_debugfs_available", "gauge", "1 if the driver counters could be read.", &[("", available as u8 as f64)]);
+
+    out
+}
+
+/// Answers a scrape, `/metrics` only.
+fn handle(stream: TcpStream, readings: &Mutex<Readings>) -> io::Result<()> {
+    let mut request = String::new();
+    BufReader::new(&stream).read_line(&mut request)?;
+    let path = request.split_whitespace().nth(1).unwrap_or("");
+
+    let mut stream = stream;
+    if path != "/metrics" {
+        return stream.write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+    }
+    let body = render(&readings.lock().unwrap());
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        body.len(),
+        body
+    )
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 2 || args.len() > 3 {
+        eprintln!("Usage: {} <device file> [<listen address:port>]", args[0]);
+        eprintln!("Serves /metrics on {} by default", DEFAULT_LISTEN);
+        exit(1);
+    }
+    let listen = args.get(2).map(String::as_str).unwrap_or(DEFAULT_LISTEN);
+
+    let device = match Adxl345Device::open(&args[1]) {
+        Ok(device) => device,
+        Err(e) => {
+            eprintln!("Failed to open {}: {}", args[1], e);
+            exit(1);
+        }
+    };
+    let listener = TcpListener::bind(listen).unwrap_or_else(|e| {
+        eprintln!("Failed to listen on {}: {}", listen, e);
+        exit(1);
+    });
+
+    let readings = Arc::new(Mutex::new(Readings::default()));
+    let reader = readings.clone();
+    thread::spawn(move || read_loop(device, &reader));
+
+    for stream in listener.incoming() {
+        let result = stream.and_then(|stream| {
+            stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+            handle(stream, &readings)
+        });
+        if let Err(e) = result {
+            eprintln!("Scrape failed: {}", e);
+        }
+    }
+}
@@ -0,0 +1,178 @@
+//! Example publisher of windowed statistics to an MQTT broker, for IoT telemetry.
+//!
+//! Publishing every sample would cost a message per sample. Instead, the samples of each interval
+//! are reduced to per-axis RMS, minimum and maximum (in mg), plus the RMS of the magnitude, and
+//! published as one JSON message on `<topic>/stats`. The driver has no aggregation ioctls, so the
+//! reduction runs here, on the stream read from the device.
+//!
+//! Publishing never blocks the reader: while the broker is unreachable the messages are dropped
+//! and counted, rather than letting the kernel buffer overflow.
+
+use std::env;
+use std::process::exit;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use libadxl345::{Adxl345Device, Adxl345Sample, Record};
+use rumqttc::{Client, MqttOptions, QoS};
+
+/// Default base topic, the statistics go to `<topic>/stats`.
+const DEFAULT_TOPIC: &str = "adxl345";
+
+/// Default window of each message.
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Default broker port.
+const MQTT_PORT: u16 = 1883;
+
+/// Messages queued towards the broker before new ones are dropped.
+const QUEUE: usize = 16;
+
+/// Statistics of the samples of a window, in mg.
+struct Window {
+    samples: u64,
+    sum_sq: [f64; 3],
+    sum_sq_magnitude: f64,
+    min: [f64; 3],
+    max: [f64; 3],
+}
+
+impl Window {
+    fn new() -> Self {
+        Window {
+            samples: 0,
+            sum_sq: [0.0; 3],
+            sum_sq_magnitude: 0.0,
+            min: [f64::INFINITY; 3],
+            max: [f64::NEG_INFINITY; 3],
+        }
+    }
+
+    fn push(&mut self, sample: &Adxl345Sample) {
+        let mg = sample.to_mg();
+        for (axis, value) in mg.into_iter().enumerate() {
+            self.sum_sq[axis] += value * value;
+            self.min[axis] = self.min[axis].min(value);
+            self.max[axis] = self.max[axis].max(value);
+        }
+        self.sum_sq_magnitude += mg.iter().map(|v| v * v).sum::<f64>();
+        self.samples += 1;
+    }
+
+    /// Returns the JSON message of the window, `None` if it has no samples.
+    fn to_json(&self, seconds: f64) -> Option<String> {
+        if self.samples == 0 {
+            return None;
+        }
+        let n = self.samples as f64;
+        let triple = |v: [f64; 3]| format!("[{:.1},{:.1},{:.1}]", v[0], v[1], v[2]);
+        Some(format!(
+            "{{\"samples\":{},\"seconds\":{:.3},\"rms_mg\":{},\"min_mg\":{},\"max_mg\":{},\"rms_magnitude_mg\":{:.1}}}",
+            self.samples,
+            seconds,
+            triple(self.sum_sq.map(|s| (s / n).sqrt())),
+            triple(self.min),
+            triple(self.max),
+            (self.sum_sq_magnitude / n).sqrt()
+        ))
+    }
+}
+
+fn usage(program: &str) -> ! {
+    eprintln!("Usage: {} <device file> --broker <host[:port]> [--topic <topic>] [--interval <seconds>] [--qos 0|1] [--client-id <id>]", program);
+    eprintln!("Publishes the RMS, minimum and maximum of each axis over every interval (default {} s) on <topic>/stats (default topic {})", DEFAULT_INTERVAL.as_secs(), DEFAULT_TOPIC);
+    exit(1);
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 2 {
+        usage(&args[0]);
+    }
+
+    let mut broker = None;
+    let mut topic = DEFAULT_TOPIC.to_string();
+    let mut interval = DEFAULT_INTERVAL;
+    let mut qos = QoS::AtMostOnce;
+    let mut client_id = format!("adxl345-{}", std::process::id());
+    let mut i = 2;
+    while i < args.len() {
+        let value = args.get(i + 1).unwrap_or_else(|| usage(&args[0]));
+        match args[i].as_str() {
+            "--broker" => broker = Some(value.clone()),
+            "--topic" => topic = value.trim_end_matches('/').to_string(),
+            "--interval" => {
+                interval = match value.parse::<f64>() {
+                    Ok(secs) if secs > 0.0 => Duration::from_secs_f64(secs),
+                    _ => usage(&args[0]),
+                }
+            }
+            "--qos" => {
+                qos = match value.as_str() {
+                    "0" => QoS::AtMostOnce,
+                    "1" => QoS::AtLeastOnce,
+                    _ => usage(&args[0]),
+                }
+            }
+            "--client-id" => client_id = value.clone(),
+            _ => usage(&args[0]),
+        }
+        i += 2;
+    }
+    let broker = broker.unwrap_or_else(|| usage(&args[0]));
+    let (host, port) = match broker.rsplit_once(':') {
+        Some((host, port)) => (host.to_string(), port.parse().unwrap_or_else(|_| usage(&args[0]))),
+        None => (broker.clone(), MQTT_PORT),
+    };
+
+    let device = match Adxl345Device::open(&args[1]) {
+        Ok(device) => device,
+        Err(e) => {
+            eprintln!("Failed to open {}: {}", args[1], e);
+            exit(1);
+        }
+    };
+
+    let mut options = MqttOptions::new(client_id, host, port);
+    options.set_keep_alive(Duration::from_secs(30));
+    let (client, mut connection) = Client::new(options, QUEUE);
+
+    // The connection makes progress only while iterated, it reconnects after errors
+    thread::spawn(move || {
+        for event in connection.iter() {
+            if let Err(e) = event {
+                eprintln!("MQTT connection: {}, retrying", e);
+                thread::sleep(Duration::from_secs(1));
+            }
+        }
+    });
+
+    let stats_topic = format!("{}/stats", topic);
+    let mut window = Window::new();
+    let mut start = Instant::now();
+    let mut dropped = 0u64;
+
+    for record in device.samples() {
+        match record {
+            Ok(Record::Sample(sample)) => window.push(&sample),
+            Ok(_) => {}
+            Err(e) => {
+                eprintln!("Failed to read from device: {}", e);
+                exit(1);
+            }
+        }
+
+        let elapsed = start.elapsed();
+        if elapsed < interval {
+            continue;
+        }
+        if let Some(message) = window.to_json(elapsed.as_secs_f64()) {
+            if client.try_publish(stats_topic.as_str(), qos, false, message).is_err() {
+                dropped += 1;
+                eprintln!("Broker not keeping up, {} messages dropped so far", dropped);
+            }
+        }
+        window = Window::new();
+        start = Instant::now();
+    }
+}
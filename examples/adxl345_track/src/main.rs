@@ -0,0 +1,129 @@
+//! Example dead reckoning: integrates the stream into velocity and displacement and prints them.
+//!
+//! The sensor must be at rest for the first `--calibrate` seconds: their mean (gravity and the
+//! bias of the sensor) is subtracted from every following sample. The drift corrections of
+//! `libadxl345::motion::Integrator` are chosen on the command line, `--high-pass` for
+//! oscillations, `--zupt` for stop-and-go motion. Without any, expect the displacement to drift
+//! by meters within a minute.
+
+use std::env;
+use std::process::exit;
+use std::time::{Duration, Instant};
+
+use libadxl345::abi::ADXL345_CAP_FILTER;
+use libadxl345::motion::Integrator;
+use libadxl345::{Adxl345Device, Param, Record};
+
+/// Default time spent at rest to measure the offset.
+const DEFAULT_CALIBRATE: Duration = Duration::from_secs(2);
+
+/// Default period of the printed lines.
+const DEFAULT_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Default samples within the threshold before a zero-velocity update.
+const ZUPT_HOLD: usize = 10;
+
+fn usage(program: &str) -> ! {
+    eprintln!("Usage: {} <device file> [--calibrate <seconds>] [--high-pass <Hz>] [--zupt <mg>] [--hold <samples>] [--interval <seconds>]", program);
+    eprintln!("Keep the sensor still for the calibration (default {} s), then move it", DEFAULT_CALIBRATE.as_secs());
+    eprintln!("--high-pass removes the motion slower than the cutoff with the drift, for oscillations");
+    eprintln!("--zupt resets the velocity while the magnitude stays within the threshold of 1 g for --hold samples (default {})", ZUPT_HOLD);
+    exit(1);
+}
+
+/// Parses a positive number of seconds.
+fn seconds(value: &str, program: &str) -> Duration {
+    match value.parse::<f64>() {
+        Ok(secs) if secs > 0.0 => Duration::from_secs_f64(secs),
+        _ => usage(program),
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 2 {
+        usage(&args[0]);
+    }
+
+    let mut calibrate = DEFAULT_CALIBRATE;
+    let mut interval = DEFAULT_INTERVAL;
+    let mut high_pass = None;
+    let mut zupt = None;
+    let mut hold = ZUPT_HOLD;
+    let mut i = 2;
+    while i < args.len() {
+        let value = args.get(i + 1).unwrap_or_else(|| usage(&args[0]));
+        match args[i].as_str() {
+            "--calibrate" => calibrate = seconds(value, &args[0]),
+            "--interval" => interval = seconds(value, &args[0]),
+            "--high-pass" => high_pass = Some(value.parse::<f64>().unwrap_or_else(|_| usage(&args[0]))),
+            "--zupt" => zupt = Some(value.parse::<f64>().unwrap_or_else(|_| usage(&args[0]))),
+            "--hold" => hold = value.parse().unwrap_or_else(|_| usage(&args[0])),
+            _ => usage(&args[0]),
+        }
+        i += 2;
+    }
+
+    let device = match Adxl345Device::open(&args[1]) {
+        Ok(device) => device,
+        Err(e) => {
+            eprintln!("Failed to open {}: {}", args[1], e);
+            exit(1);
+        }
+    };
+    let rate_hz = match device.param(Param::Rate) {
+        Ok(mhz) => mhz as f64 / 1000.0,
+        Err(e) => {
+            eprintln!("Failed to read the rate: {}", e);
+            exit(1);
+        }
+    };
+    if device.capabilities().map_or(true, |caps| caps & ADXL345_CAP_FILTER != 0) {
+        eprintln!("Warning: the driver may drop samples that barely change, the integration then runs slow");
+    }
+
+    let mut samples = device.samples();
+    let mut next_sample = || loop {
+        match samples.next() {
+            Some(Ok(Record::Sample(sample))) => return sample,
+            Some(Ok(_)) => {}
+            Some(Err(e)) => {
+                eprintln!("Failed to read from device: {}", e);
+                exit(1);
+            }
+            None => exit(0),
+        }
+    };
+
+    eprintln!("Calibrating at {} Hz, keep the sensor still", rate_hz);
+    let mut rest = Vec::new();
+    let start = Instant::now();
+    while start.elapsed() < calibrate {
+        rest.push(next_sample());
+    }
+    let mut integrator = Integrator::new(rate_hz).calibrate(&rest);
+    if let Some(cutoff) = high_pass {
+        integrator = integrator.high_pass(cutoff);
+    }
+    if let Some(threshold) = zupt {
+        integrator = integrator.zero_velocity(threshold, hold);
+    }
+    eprintln!("Calibrated on {} samples, tracking", rest.len());
+
+    println!("seconds,vx,vy,vz,dx,dy,dz,still");
+    let start = Instant::now();
+    let mut last_print = Instant::now();
+    loop {
+        let state = integrator.push(&next_sample());
+        if last_print.elapsed() < interval {
+            continue;
+        }
+        last_print = Instant::now();
+        let [vx, vy, vz] = state.velocity;
+        let [dx, dy, dz] = state.displacement;
+        println!(
+            "{:.3},{:.3},{:.3},{:.3},{:.3},{:.3},{:.3},{}",
+            start.elapsed().as_secs_f64(), vx, vy, vz, dx, dy, dz, state.stationary as u8
+        );
+    }
+}
@@ -0,0 +1,293 @@
+//! Example daemon: a single reader of the device republishing the stream to many socket clients.
+//!
+//! The driver serves one stream, every reader consumes records the others won't see. The daemon
+//! is that single reader and copies each batch to the clients connected on a UNIX socket, a TCP
+//! port or both. Clients receive the raw record stream, exactly as read from the device, so they
+//! decode it with `libadxl345::StreamDecoder` (or save it and use `adxl345_test decode`).
+//!
+//! Backpressure: the device can't be slowed down, so each client has a bounded queue of batches.
+//! A client that falls further behind than its queue is disconnected, rather than delaying the
+//! other clients or letting the kernel buffer overflow. A client that connects in the middle of
+//! a session first receives the last session header, if there was one.
+//!
+//! Run as a systemd service, the daemon applies the configuration given with `--set` and keeps
+//! the device open, and serves the sockets passed by socket activation (see `examples/systemd`).
+//!
+//! Bus errors don't stop the daemon: it reads in best-effort mode, the driver reports them as
+//! error markers in the stream the clients receive.
+
+use std::env;
+use std::io::{self, Write};
+use std::mem;
+use std::net::TcpListener;
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::os::unix::net::UnixListener;
+use std::process::{self, exit};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use libadxl345::abi::ADXL345_MARKER_HEADER;
+use libadxl345::{Adxl345Device, Adxl345Sample, Config, ErrorPolicy, Param, Record, StreamDecoder, RECORD_SIZE};
+
+/// Records read from the device at a time, one batch sent to the clients.
+const BATCH_RECORDS: usize = 128;
+
+/// Default number of batches a client may lag behind before it is disconnected.
+const DEFAULT_QUEUE: usize = 64;
+
+/// First file descriptor passed by systemd socket activation.
+const SD_LISTEN_FDS_START: RawFd = 3;
+
+/// A batch of raw records, shared by all the clients.
+type Batch = Arc<[u8]>;
+
+/// A connected client, fed by its writer thread.
+struct Client {
+    name: String,
+    queue: SyncSender<Batch>,
+}
+
+/// State shared by the reader and the listeners.
+struct Hub {
+    clients: Mutex<Vec<Client>>,
+    /// Raw records of the last session header, sent first to new clients.
+    header: Mutex<Option<Batch>>,
+    queue: usize,
+}
+
+impl Hub {
+    /// Registers a client and starts the thread writing its queue to `stream`.
+    fn attach(&self, name: String, mut stream: impl Write + Send + 'static) {
+        let (queue, batches) = mpsc::sync_channel::<Batch>(self.queue);
+        if let Some(header) = self.header.lock().unwrap().clone() {
+            // The queue is empty, the header always fits
+            let _ = queue.try_send(header);
+        }
+
+        let thread_name = name.clone();
+        thread::spawn(move || {
+            for batch in batches {
+                if let Err(e) = stream.write_all(&batch) {
+                    eprintln!("{}: {}", thread_name, e);
+                    break;
+                }
+            }
+            // The stream is closed when dropped here
+        });
+
+        eprintln!("{}: connected", name);
+        self.clients.lock().unwrap().push(Client { name, queue });
+    }
+
+    /// Sends a batch to every client, dropping the ones that are gone or too slow.
+    fn publish(&self, batch: Batch) {
+        self.clients.lock().unwrap().retain(|client| match client.queue.try_send(batch.clone()) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                eprintln!("{}: more than {} batches behind, disconnected", client.name, self.queue);
+                false
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                eprintln!("{}: disconnected", client.name);
+                false
+            }
+        });
+    }
+}
+
+/// Returns the raw bytes of `records`, as read from the device.
+fn to_bytes(records: &[Adxl345Sample]) -> Batch {
+    records.iter().flat_map(|r| r.to_ne_bytes()).collect()
+}
+
+/// Reads the device forever, publishing each batch and remembering the session headers.
+fn serve(device: &Adxl345Device, hub: &Hub) -> io::Result<()> {
+    let mut buf = [Adxl345Sample::default(); BATCH_RECORDS];
+    let mut decoder = StreamDecoder::new();
+    let mut header = Vec::new();
+
+    loop {
+        let n = match device.read_records(&mut buf) {
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        // The device never ends the stream, a FIFO fed with a capture does
+        if n == 0 {
+            return Ok(());
+        }
+
+        // Keep the raw header records, a header may span two batches
+        for &raw in &buf[..n] {
+            if raw.is_marker() && raw.y == ADXL345_MARKER_HEADER {
+                header.push(raw);
+            }
+            if let Some(Record::Header(_)) = decoder.push(raw) {
+                *hub.header.lock().unwrap() = Some(to_bytes(&header));
+                header.clear();
+            }
+        }
+
+        hub.publish(to_bytes(&buf[..n]));
+    }
+}
+
+/// Accepts UNIX clients on `listener` forever.
+fn listen_unix(listener: UnixListener, hub: Arc<Hub>) {
+    thread::spawn(move || {
+        for (n, stream) in listener.incoming().enumerate() {
+            match stream {
+                Ok(stream) => hub.attach(format!("unix#{}", n), stream),
+                Err(e) => eprintln!("unix: accept failed: {}", e),
+            }
+        }
+    });
+}
+
+/// Accepts TCP clients on `listener` forever.
+fn listen_tcp(listener: TcpListener, hub: Arc<Hub>) {
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let _ = stream.set_nodelay(true);
+                    let name = stream.peer_addr().map(|a| a.to_string()).unwrap_or_else(|_| "tcp".to_string());
+                    hub.attach(name, stream);
+                }
+                Err(e) => eprintln!("tcp: accept failed: {}", e),
+            }
+        }
+    });
+}
+
+/// Starts accepting on the sockets passed by systemd socket activation.
+///
+/// Returns the number of sockets, 0 when the daemon was not socket activated.
+fn listen_activated(hub: &Arc<Hub>) -> usize {
+    // The variables are meant for this process only, not for one that inherited them
+    let pid_matches = env::var("LISTEN_PID").ok().and_then(|pid| pid.parse::<u32>().ok()) == Some(process::id());
+    let count = env::var("LISTEN_FDS").ok().and_then(|n| n.parse::<RawFd>().ok()).unwrap_or(0);
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+    if !pid_matches {
+        return 0;
+    }
+
+    for fd in SD_LISTEN_FDS_START..SD_LISTEN_FDS_START + count {
+        let mut address: libc::sockaddr_storage = unsafe { mem::zeroed() };
+        let mut len = mem::size_of_val(&address) as libc::socklen_t;
+        if unsafe { libc::getsockname(fd, &mut address as *mut _ as *mut libc::sockaddr, &mut len) } < 0 {
+            eprintln!("fd {}: not a socket, ignored", fd);
+            continue;
+        }
+        // SAFETY: systemd passes the sockets to this process only, each is owned once
+        match address.ss_family as libc::c_int {
+            libc::AF_UNIX => listen_unix(unsafe { UnixListener::from_raw_fd(fd) }, hub.clone()),
+            libc::AF_INET | libc::AF_INET6 => listen_tcp(unsafe { TcpListener::from_raw_fd(fd) }, hub.clone()),
+            family => eprintln!("fd {}: unsupported address family {}, ignored", fd, family),
+        }
+    }
+    count as usize
+}
+
+fn usage(program: &str) -> ! {
+    eprintln!("Usage: {} <device file> [--unix <path>] [--tcp <address:port>] [--queue <batches>] [--set <param>=<value>]...", program);
+    eprintln!("Clients receive the raw record stream, {} bytes per record", RECORD_SIZE);
+    eprintln!("--queue sets how many batches of {} records a client may lag behind (default {})", BATCH_RECORDS, DEFAULT_QUEUE);
+    eprintln!("--set applies a parameter in human units at startup, e.g. --set rate=100000 --set range=4");
+    eprintln!("Under systemd socket activation the sockets passed by systemd are used as well");
+    exit(1);
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 2 {
+        usage(&args[0]);
+    }
+
+    let mut unix = None;
+    let mut tcp = None;
+    let mut queue = DEFAULT_QUEUE;
+    let mut config = Config::new();
+    let mut i = 2;
+    while i < args.len() {
+        let value = args.get(i + 1).unwrap_or_else(|| usage(&args[0]));
+        match args[i].as_str() {
+            "--unix" => unix = Some(value.clone()),
+            "--tcp" => tcp = Some(value.clone()),
+            "--queue" => queue = value.parse().ok().filter(|&q| q > 0).unwrap_or_else(|| usage(&args[0])),
+            "--set" => {
+                let param = value.split_once('=').and_then(|(name, value)| Some((Param::from_name(name)?, value.parse().ok()?)));
+                match param {
+                    Some((param, value)) => config = config.set(param, value),
+                    None => {
+                        eprintln!("Invalid parameter: {}", value);
+                        exit(1);
+                    }
+                }
+            }
+            _ => usage(&args[0]),
+        }
+        i += 2;
+    }
+
+    let device = match Adxl345Device::open(&args[1]) {
+        Ok(device) => device,
+        Err(e) => {
+            eprintln!("Failed to open {}: {}", args[1], e);
+            exit(1);
+        }
+    };
+
+    // The daemon keeps the device open, so the configuration holds for all the clients
+    match device.configure(&config) {
+        Ok(achieved) => {
+            for (param, value) in achieved {
+                eprintln!("{} = {} {}", param.name(), value, param.unit());
+            }
+        }
+        Err(e) => {
+            eprintln!("Failed to apply the configuration: {}", e);
+            exit(1);
+        }
+    }
+
+    if let Err(e) = device.set_error_policy(ErrorPolicy::BestEffort) {
+        eprintln!("Warning: the driver can't report bus errors in the stream, the first one stops the daemon: {}", e);
+    }
+
+    let hub = Arc::new(Hub { clients: Mutex::new(Vec::new()), header: Mutex::new(None), queue });
+    let activated = listen_activated(&hub);
+
+    if let Some(path) = unix {
+        // A stale socket of a previous run would make bind fail
+        let _ = std::fs::remove_file(&path);
+        match UnixListener::bind(&path) {
+            Ok(listener) => listen_unix(listener, hub.clone()),
+            Err(e) => {
+                eprintln!("Failed to listen on {}: {}", path, e);
+                exit(1);
+            }
+        }
+    } else if tcp.is_none() && activated == 0 {
+        eprintln!("Nothing to listen on, give --unix or --tcp or start from a systemd socket");
+        usage(&args[0]);
+    }
+
+    if let Some(address) = tcp {
+        match TcpListener::bind(&address) {
+            Ok(listener) => listen_tcp(listener, hub.clone()),
+            Err(e) => {
+                eprintln!("Failed to listen on {}: {}", address, e);
+                exit(1);
+            }
+        }
+    }
+
+    if let Err(e) = serve(&device, &hub) {
+        eprintln!("Failed to read from device: {}", e);
+        exit(1);
+    }
+}
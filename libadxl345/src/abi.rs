@@ -0,0 +1,454 @@
+//! Raw definitions shared with the driver: record layout, stream markers and ioctl commands.
+//! They must match the ones defined in the driver (src/constant.rs, src/config.rs, src/ioctl.rs,
+//! src/session.rs, src/clip.rs, src/auto_range.rs, src/preset.rs, src/batch_crc.rs, src/poll.rs, src/version.rs, src/capabilities.rs, src/fasync.rs, src/tap.rs, src/burst.rs, src/motion.rs, src/power.rs, src/correlation.rs, src/calibration.rs, src/filter.rs, src/output.rs, src/drain.rs). Most applications should use [`crate::Adxl345Device`] instead.
+
+use std::mem;
+
+/// A record read from the device, either a sample or a marker.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Adxl345Sample {
+    pub x: i16,
+    pub y: i16,
+    pub z: i16,
+}
+
+/// A record read in the µg or µm/s² output format, either a sample or a marker.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Adxl345ScaledSample {
+    pub x: i32,
+    pub y: i32,
+    pub z: i32,
+}
+
+/// A record read in the timestamped output format: the raw record and, for a sample, the time
+/// it was read from the device in ns of the clock of the samples (0 for a marker).
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Adxl345StampedSample {
+    pub x: i16,
+    pub y: i16,
+    pub z: i16,
+    pub reserved: u16,
+    pub timestamp_ns: u64,
+}
+
+// Stream markers
+pub const ADXL345_MARKER_TAG: i16 = i16::MIN;
+/// Marker tag of a scaled record, in its x field; the kind and value follow in y and z.
+pub const ADXL345_SCALED_MARKER_TAG: i32 = i32::MIN;
+pub const ADXL345_MARKER_SYNC: i16 = 1;
+pub const ADXL345_MARKER_HEADER: i16 = 2;
+pub const ADXL345_MARKER_CLIP: i16 = 3;
+pub const ADXL345_MARKER_RANGE: i16 = 4;
+pub const ADXL345_MARKER_CRC: i16 = 5;
+pub const ADXL345_MARKER_ERROR: i16 = 6;
+pub const ADXL345_MARKER_TAP: i16 = 7;
+pub const ADXL345_MARKER_BURST: i16 = 8;
+pub const ADXL345_MARKER_EVENT: i16 = 9;
+pub const ADXL345_MARKER_LIMIT: i16 = 10;
+
+/// Flags of a tap marker, above the mask of the axes.
+pub const ADXL345_TAP_MARKER_SINGLE: i16 = 1 << 8;
+pub const ADXL345_TAP_MARKER_DOUBLE: i16 = 1 << 9;
+
+/// Number of `ADXL345_MARKER_CRC` markers ending a batch, least significant word first.
+pub const ADXL345_CRC_WORDS: usize = 2;
+
+/// Session header, carried by `ADXL345_MARKER_HEADER` markers one 16-bit word at a time.
+#[derive(Debug, Clone, Copy)]
+pub struct Adxl345Header {
+    pub version: u16,
+    pub range_g: u16,
+    pub clock: u16,
+    pub rate_mhz: u32,
+    pub start_ns: u64,
+    /// Threshold of the read filter, -1 if the driver is built without it.
+    pub filter: i16,
+}
+
+/// Number of words in a version 1 header.
+pub const ADXL345_HEADER_WORDS: usize = 11;
+
+impl Adxl345Header {
+    /// Decodes the words collected from the header markers.
+    ///
+    /// Returns `None` until all the words announced by the header are collected.
+    pub fn decode(words: &[u16]) -> Option<Self> {
+        if words.len() < 2 || words.len() < words[1] as usize || words.len() < ADXL345_HEADER_WORDS {
+            return None;
+        }
+        Some(Adxl345Header {
+            version: words[0],
+            range_g: words[2],
+            clock: words[3],
+            rate_mhz: words[4] as u32 | (words[5] as u32) << 16,
+            start_ns: (0..4).fold(0, |ns, i| ns | (words[6 + i] as u64) << (16 * i)),
+            filter: words[10] as i16,
+        })
+    }
+}
+
+/// Scale of a sample: full resolution data is 3.9 mg per LSB, shifted by 2.
+pub const ADXL345_MG_PER_UNIT: f64 = 3.9 / 4.0;
+
+/// Largest absolute value of a sample: 13-bit full resolution data shifted by 2.
+pub const ADXL345_SAMPLE_LIMIT: i16 = 4096 << 2;
+
+// ioctl commands
+const ADXL345_IOC_MAGIC: u32 = b'A' as u32;
+
+/// Equivalent to the `_IO` C macro for the ADXL345 magic number.
+const fn io(nr: u32) -> u32 {
+    (ADXL345_IOC_MAGIC << 8) | nr
+}
+
+/// Equivalent to the `_IOW` C macro for the ADXL345 magic number.
+const fn iow<T>(nr: u32) -> u32 {
+    (1 << 30) | ((mem::size_of::<T>() as u32) << 16) | (ADXL345_IOC_MAGIC << 8) | nr
+}
+
+/// Equivalent to the `_IOR` C macro for the ADXL345 magic number.
+const fn ior<T>(nr: u32) -> u32 {
+    (2 << 30) | ((mem::size_of::<T>() as u32) << 16) | (ADXL345_IOC_MAGIC << 8) | nr
+}
+
+/// Equivalent to the `_IOWR` C macro for the ADXL345 magic number.
+const fn iowr<T>(nr: u32) -> u32 {
+    (3 << 30) | ((mem::size_of::<T>() as u32) << 16) | (ADXL345_IOC_MAGIC << 8) | nr
+}
+
+pub const ADXL345_IOC_SET_CLOCK: u32 = iow::<u32>(0x01);
+pub const ADXL345_IOC_GET_CLOCK: u32 = ior::<u32>(0x02);
+pub const ADXL345_IOC_SET_SYNC: u32 = iow::<u32>(0x03);
+pub const ADXL345_IOC_GET_SYNC: u32 = ior::<Adxl345SyncInfo>(0x04);
+pub const ADXL345_IOC_SET_PARAM: u32 = iow::<Adxl345ParamArg>(0x05);
+pub const ADXL345_IOC_GET_PARAM: u32 = iowr::<Adxl345ParamArg>(0x06);
+pub const ADXL345_IOC_SET_PARAM_SCALED: u32 = iowr::<Adxl345ParamArg>(0x07);
+pub const ADXL345_IOC_GET_PARAM_SCALED: u32 = iowr::<Adxl345ParamArg>(0x08);
+pub const ADXL345_IOC_FLUSH: u32 = io(0x09);
+pub const ADXL345_IOC_START: u32 = io(0x0A);
+pub const ADXL345_IOC_STOP: u32 = io(0x0B);
+pub const ADXL345_IOC_SET_HEADER: u32 = iow::<u32>(0x0C);
+pub const ADXL345_IOC_SET_AUTO_RANGE: u32 = iow::<u32>(0x0D);
+pub const ADXL345_IOC_PRESET_SAVE: u32 = iow::<Adxl345PresetName>(0x0E);
+pub const ADXL345_IOC_PRESET_APPLY: u32 = iow::<Adxl345PresetName>(0x0F);
+pub const ADXL345_IOC_PRESET_DELETE: u32 = iow::<Adxl345PresetName>(0x10);
+pub const ADXL345_IOC_SET_CRC: u32 = iow::<u32>(0x11);
+pub const ADXL345_IOC_SET_POLL_MODE: u32 = iow::<u32>(0x12);
+pub const ADXL345_IOC_GET_VERSION: u32 = ior::<Adxl345Version>(0x13);
+pub const ADXL345_IOC_GET_CAPS: u32 = ior::<u64>(0x14);
+pub const ADXL345_IOC_SET_SIGIO_THRESHOLD: u32 = iow::<u32>(0x15);
+pub const ADXL345_IOC_SET_ERROR_POLICY: u32 = iow::<u32>(0x16);
+pub const ADXL345_IOC_SET_FILTER: u32 = iow::<u32>(0x17);
+pub const ADXL345_IOC_GET_FILTER: u32 = ior::<u32>(0x18);
+pub const ADXL345_IOC_SET_RESAMPLE: u32 = iow::<u32>(0x19);
+pub const ADXL345_IOC_SET_TAP: u32 = iow::<Adxl345TapArg>(0x1A);
+pub const ADXL345_IOC_GET_TAP: u32 = ior::<Adxl345TapArg>(0x1B);
+pub const ADXL345_IOC_SET_BURST: u32 = iow::<Adxl345BurstArg>(0x1C);
+pub const ADXL345_IOC_GET_BURST: u32 = ior::<Adxl345BurstArg>(0x1D);
+pub const ADXL345_IOC_SET_MOTION: u32 = iow::<Adxl345MotionArg>(0x1E);
+pub const ADXL345_IOC_GET_MOTION: u32 = ior::<Adxl345MotionArg>(0x1F);
+pub const ADXL345_IOC_GET_MOTION_EVENT: u32 = ior::<Adxl345MotionEvent>(0x20);
+pub const ADXL345_IOC_SET_POWER: u32 = iow::<Adxl345PowerArg>(0x21);
+pub const ADXL345_IOC_GET_POWER: u32 = ior::<Adxl345PowerArg>(0x22);
+pub const ADXL345_IOC_GET_EVENT: u32 = iowr::<Adxl345EventInfo>(0x23);
+pub const ADXL345_IOC_CALIBRATE: u32 = iowr::<Adxl345CalibrateArg>(0x24);
+pub const ADXL345_IOC_SET_OFFSETS: u32 = iow::<Adxl345OffsetArg>(0x25);
+pub const ADXL345_IOC_GET_OFFSETS: u32 = ior::<Adxl345OffsetArg>(0x26);
+pub const ADXL345_IOC_SET_PIPELINE: u32 = iow::<Adxl345PipelineArg>(0x27);
+pub const ADXL345_IOC_GET_PIPELINE: u32 = ior::<Adxl345PipelineArg>(0x28);
+pub const ADXL345_IOC_SET_OUTPUT: u32 = iow::<u32>(0x29);
+pub const ADXL345_IOC_GET_OUTPUT: u32 = ior::<u32>(0x2A);
+pub const ADXL345_IOC_SET_BUFFER: u32 = iow::<u32>(0x2B);
+pub const ADXL345_IOC_GET_BUFFER: u32 = ior::<Adxl345BufferArg>(0x2C);
+pub const ADXL345_IOC_SET_RATE: u32 = iow::<u32>(0x2D);
+pub const ADXL345_IOC_GET_RATE: u32 = ior::<u32>(0x2E);
+pub const ADXL345_IOC_SET_RANGE: u32 = iow::<u32>(0x2F);
+pub const ADXL345_IOC_GET_RANGE: u32 = ior::<u32>(0x30);
+
+/// ABI version these definitions match. A driver serves every lower version too.
+pub const ADXL345_ABI_VERSION: u32 = 17;
+
+// Capability bits, returned by `ADXL345_IOC_GET_CAPS`
+pub const ADXL345_CAP_FIFO: u64 = 1 << 0;
+pub const ADXL345_CAP_SYNC_IRQ: u64 = 1 << 1;
+pub const ADXL345_CAP_UEVENTS: u64 = 1 << 2;
+pub const ADXL345_CAP_AUTO_RANGE: u64 = 1 << 3;
+pub const ADXL345_CAP_FILTER: u64 = 1 << 4;
+pub const ADXL345_CAP_SESSION_HEADER: u64 = 1 << 5;
+pub const ADXL345_CAP_PRESETS: u64 = 1 << 6;
+pub const ADXL345_CAP_BATCH_CRC: u64 = 1 << 7;
+pub const ADXL345_CAP_POLL_EDGE: u64 = 1 << 8;
+pub const ADXL345_CAP_RT_MUTEX: u64 = 1 << 9;
+pub const ADXL345_CAP_DEBUGFS: u64 = 1 << 10;
+pub const ADXL345_CAP_CONFIGFS: u64 = 1 << 11;
+pub const ADXL345_CAP_DRY_RUN: u64 = 1 << 12;
+pub const ADXL345_CAP_FASYNC: u64 = 1 << 13;
+pub const ADXL345_CAP_WRITE_CONTROL: u64 = 1 << 14;
+pub const ADXL345_CAP_ERROR_POLICY: u64 = 1 << 15;
+pub const ADXL345_CAP_DATA_IRQ: u64 = 1 << 16;
+pub const ADXL345_CAP_THERMAL_GUARD: u64 = 1 << 17;
+pub const ADXL345_CAP_ALARM_GPIO: u64 = 1 << 18;
+pub const ADXL345_CAP_RESAMPLE: u64 = 1 << 19;
+pub const ADXL345_CAP_SPI: u64 = 1 << 20;
+pub const ADXL345_CAP_TAP: u64 = 1 << 21;
+pub const ADXL345_CAP_BURST: u64 = 1 << 22;
+pub const ADXL345_CAP_MOTION: u64 = 1 << 23;
+pub const ADXL345_CAP_POWER: u64 = 1 << 24;
+pub const ADXL345_CAP_CORRELATION: u64 = 1 << 25;
+pub const ADXL345_CAP_CALIBRATION: u64 = 1 << 26;
+pub const ADXL345_CAP_PIPELINE: u64 = 1 << 27;
+pub const ADXL345_CAP_SCALED_OUTPUT: u64 = 1 << 28;
+pub const ADXL345_CAP_TIMESTAMPS: u64 = 1 << 29;
+pub const ADXL345_CAP_BUFFER: u64 = 1 << 30;
+
+/// Capability names, indexed by bit.
+pub const CAP_NAMES: [&str; 31] = [
+    "fifo", "sync_irq", "uevents", "auto_range", "filter", "session_header", "presets",
+    "batch_crc", "poll_edge", "rt_mutex", "debugfs", "configfs", "dry_run", "fasync",
+    "write_control", "error_policy", "data_irq", "thermal_guard",
+    "alarm_gpio", "resample", "spi", "tap", "burst", "motion", "power",
+    "correlation", "calibration", "pipeline", "scaled_output", "timestamps",
+    "buffer",
+];
+
+/// Arguments of `ADXL345_IOC_SET_POLL_MODE`.
+pub const ADXL345_POLL_LEVEL: u32 = 0;
+pub const ADXL345_POLL_EDGE: u32 = 1;
+
+/// Arguments of `ADXL345_IOC_SET_OUTPUT`.
+pub const ADXL345_OUTPUT_RAW: u32 = 0;
+pub const ADXL345_OUTPUT_MICRO_G: u32 = 1;
+pub const ADXL345_OUTPUT_MICRO_MS2: u32 = 2;
+pub const ADXL345_OUTPUT_TIMESTAMPED: u32 = 3;
+
+/// Arguments of `ADXL345_IOC_SET_ERROR_POLICY`.
+pub const ADXL345_ERRORS_FAIL_FAST: u32 = 0;
+pub const ADXL345_ERRORS_BEST_EFFORT: u32 = 1;
+
+/// Events of `ADXL345_IOC_SET_TAP`.
+pub const ADXL345_TAP_SINGLE: u32 = 1 << 0;
+pub const ADXL345_TAP_DOUBLE: u32 = 1 << 1;
+
+/// Argument of `ADXL345_IOC_SET_TAP` and `ADXL345_IOC_GET_TAP`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Adxl345TapArg {
+    /// `ADXL345_TAP_*` bits, 0 reports no tap.
+    pub events: u32,
+    /// Axes taking part, bit 0 x, bit 1 y, bit 2 z.
+    pub axes: u32,
+}
+
+/// Argument of `ADXL345_IOC_SET_BURST` and `ADXL345_IOC_GET_BURST`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Adxl345BurstArg {
+    /// Time from the start of a burst to the start of the next one, in ms.
+    pub period_ms: u32,
+    /// Length of a burst in ms, 0 measures continuously.
+    pub length_ms: u32,
+}
+
+/// Events of `ADXL345_IOC_SET_MOTION` and of a motion event.
+pub const ADXL345_MOTION_ACTIVITY: u32 = 1 << 0;
+pub const ADXL345_MOTION_INACTIVITY: u32 = 1 << 1;
+pub const ADXL345_MOTION_FREE_FALL: u32 = 1 << 2;
+
+/// Argument of `ADXL345_IOC_SET_MOTION` and `ADXL345_IOC_GET_MOTION`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Adxl345MotionArg {
+    /// `ADXL345_MOTION_*` bits, 0 arms none.
+    pub events: u32,
+    /// Value of the ACT_INACT_CTL register: axes and coupling of activity (bits 7-4) and
+    /// inactivity (bits 3-0).
+    pub act_inact_ctl: u32,
+}
+
+/// Last motion event, returned by `ADXL345_IOC_GET_MOTION_EVENT`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Adxl345MotionEvent {
+    /// Number of events since the driver was loaded, 0 if none.
+    pub sequence: u32,
+    /// `ADXL345_MOTION_*` bits flagged by the event.
+    pub events: u32,
+    /// Timestamp of the event, in the clock of the samples.
+    pub timestamp_ns: u64,
+}
+
+/// Modes of `ADXL345_IOC_SET_POWER`.
+pub const ADXL345_POWER_LOW_POWER: u32 = 1 << 0;
+pub const ADXL345_POWER_AUTO_SLEEP: u32 = 1 << 1;
+
+/// Argument of `ADXL345_IOC_SET_POWER` and `ADXL345_IOC_GET_POWER`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Adxl345PowerArg {
+    /// `ADXL345_POWER_*` bits, 0 is full power.
+    pub flags: u32,
+    /// Sampling rate while asleep with auto sleep: 8, 4, 2 or 1 Hz, 0 for 8 Hz.
+    pub wakeup_hz: u32,
+}
+
+/// Events of an event marker.
+pub const ADXL345_EVENT_TAP: u32 = 1 << 0;
+pub const ADXL345_EVENT_THRESHOLD: u32 = 1 << 1;
+pub const ADXL345_EVENT_OVERRUN: u32 = 1 << 2;
+pub const ADXL345_EVENT_MOTION: u32 = 1 << 3;
+
+/// Argument of `ADXL345_IOC_GET_EVENT`: the ID is given, the rest is returned.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Adxl345EventInfo {
+    /// Correlation ID, carried by the event marker.
+    pub id: u32,
+    /// `ADXL345_EVENT_*` bits of the events at the marker.
+    pub events: u32,
+    /// Time the first of them was flagged, in the clock of the samples.
+    pub timestamp_ns: u64,
+}
+
+/// Argument of `ADXL345_IOC_SET_OFFSETS` and `ADXL345_IOC_GET_OFFSETS`: the OFSX, OFSY and OFSZ
+/// registers, from -128 to 127 at 15.6 mg per unit.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Adxl345OffsetArg {
+    pub x: i32,
+    pub y: i32,
+    pub z: i32,
+}
+
+/// Capacity of `ADXL345_IOC_SET_BUFFER`, in samples.
+pub const ADXL345_BUFFER_MIN: u32 = 64;
+pub const ADXL345_BUFFER_MAX: u32 = 1024;
+
+/// Argument of `ADXL345_IOC_GET_BUFFER`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Adxl345BufferArg {
+    /// Records the kernel buffer holds at most.
+    pub capacity: u32,
+    /// Records buffered when it was read.
+    pub buffered: u32,
+    /// Samples dropped because the buffer was full, since the device was probed.
+    pub overruns: u64,
+}
+
+/// Argument of `ADXL345_IOC_CALIBRATE`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Adxl345CalibrateArg {
+    /// Samples averaged, at most 1000, 0 for 100.
+    pub samples: u32,
+    /// Offsets written, returned.
+    pub offsets: Adxl345OffsetArg,
+}
+
+/// Kinds of stages of `Adxl345PipelineArg`.
+pub const ADXL345_STAGE_THRESHOLD: u32 = 1;
+pub const ADXL345_STAGE_AVERAGE: u32 = 2;
+pub const ADXL345_STAGE_DECIMATE: u32 = 3;
+pub const ADXL345_STAGE_SCALE: u32 = 4;
+pub const ADXL345_STAGE_LIMIT: u32 = 5;
+
+/// Longest list of stages.
+pub const ADXL345_PIPELINE_LEN: usize = 8;
+
+/// A stage of `Adxl345PipelineArg`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Adxl345StageArg {
+    /// `ADXL345_STAGE_*`.
+    pub kind: u32,
+    /// Threshold stage: unused. Average: window, 2 to 16. Decimate: factor, 2 to 1000. Scale:
+    /// factor in thousandths, 1 to 100000. Limit: absolute value flagged, 1 to 32767.
+    pub param: u32,
+}
+
+/// Argument of `ADXL345_IOC_SET_PIPELINE` and `ADXL345_IOC_GET_PIPELINE`: the stages in the order
+/// they run, the ones past `len` are ignored.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Adxl345PipelineArg {
+    pub len: u32,
+    pub stages: [Adxl345StageArg; ADXL345_PIPELINE_LEN],
+}
+
+/// Argument of the parameter ioctls.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Adxl345ParamArg {
+    pub param: u32,
+    pub value: u32,
+}
+
+/// Longest preset name, in bytes.
+pub const PRESET_NAME_LEN: usize = 16;
+
+/// Argument of the preset ioctls: the name padded with NUL bytes.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Adxl345PresetName {
+    pub name: [u8; PRESET_NAME_LEN],
+}
+
+/// Last sync pulse, returned by `ADXL345_IOC_GET_SYNC`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Adxl345SyncInfo {
+    pub sequence: u32,
+    pub gpio: u32,
+    pub timestamp_ns: u64,
+}
+
+/// Driver and ABI versions, returned by `ADXL345_IOC_GET_VERSION`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Adxl345Version {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+    pub abi: u32,
+}
+
+/// Configuration parameter names, indexed by parameter id.
+pub const PARAM_NAMES: [&str; 12] = [
+    "rate", "range", "watermark", "thresh_tap", "dur", "latent",
+    "window", "thresh_act", "thresh_inact", "time_inact", "thresh_ff", "time_ff",
+];
+
+/// Human units of the configuration parameters, indexed by parameter id.
+pub const PARAM_UNITS: [&str; 12] = [
+    "mHz", "g", "entries", "mg", "us", "us", "us", "mg", "mg", "us", "mg", "us",
+];
+
+// Parameter ids
+pub const PARAM_RATE: u32 = 0;
+pub const PARAM_RANGE: u32 = 1;
+pub const PARAM_WATERMARK: u32 = 2;
+pub const PARAM_THRESH_TAP: u32 = 3;
+pub const PARAM_DUR: u32 = 4;
+
+/// Output data rates supported by the device, in mHz.
+pub const RATES_MHZ: [u32; 16] = [
+    100, 200, 390, 780, 1_560, 3_130, 6_250, 12_500,
+    25_000, 50_000, 100_000, 200_000, 400_000, 800_000, 1_600_000, 3_200_000,
+];
+
+/// Measurement ranges supported by the device, in g.
+pub const RANGES_G: [u32; 4] = [2, 4, 8, 16];
+
+/// Issues an ioctl whose argument is a pointer to `arg`.
+///
+/// Returns the errno on failure.
+pub fn ioctl_ptr<T>(fd: i32, cmd: u32, arg: &mut T) -> Result<(), i32> {
+    let ret = unsafe { libc::ioctl(fd, cmd as _, arg as *mut T) };
+    if ret < 0 {
+        return Err(std::io::Error::last_os_error().raw_os_error().unwrap_or(0));
+    }
+    Ok(())
+}
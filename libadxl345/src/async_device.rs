@@ -0,0 +1,90 @@
+//! Async access to the character device, on the tokio reactor.
+//!
+//! The device is opened nonblocking and registered with [`AsyncFd`]: the driver's poll reports it
+//! readable when records are buffered, so a task awaits data without a blocking thread per sensor.
+
+use std::io;
+use std::path::Path;
+
+use tokio::io::unix::AsyncFd;
+
+use crate::abi::Adxl345Sample;
+use crate::device::Adxl345Device;
+use crate::integrity::verify_batch;
+use crate::stream::{Record, StreamDecoder};
+
+/// Records requested by each read of [`AsyncAdxl345Device::next_batch`].
+const BATCH_RECORDS: usize = 64;
+
+/// An open ADXL345 character device, read asynchronously.
+///
+/// Configuration ioctls don't block, they are issued on [`get_ref`](Self::get_ref).
+///
+/// ```no_run
+/// use libadxl345::{AsyncAdxl345Device, Record};
+///
+/// async fn print(path: &str) -> std::io::Result<()> {
+///     let mut device = AsyncAdxl345Device::open(path)?;
+///     loop {
+///         for record in device.next_batch().await? {
+///             if let Record::Sample(sample) = record {
+///                 println!("{:?} mg", sample.to_mg());
+///             }
+///         }
+///     }
+/// }
+/// ```
+#[derive(Debug)]
+pub struct AsyncAdxl345Device {
+    inner: AsyncFd<Adxl345Device>,
+    decoder: StreamDecoder,
+    buf: Vec<Adxl345Sample>,
+}
+
+impl AsyncAdxl345Device {
+    /// Opens the device and registers it with the reactor of the current runtime.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::new(Adxl345Device::open_nonblocking(path)?)
+    }
+
+    /// Wraps a device opened with [`Adxl345Device::open_nonblocking`].
+    pub fn new(device: Adxl345Device) -> io::Result<Self> {
+        Ok(AsyncAdxl345Device {
+            inner: AsyncFd::new(device)?,
+            decoder: StreamDecoder::new(),
+            buf: vec![Adxl345Sample::default(); BATCH_RECORDS],
+        })
+    }
+
+    /// Returns the device, to configure it.
+    pub fn get_ref(&self) -> &Adxl345Device {
+        self.inner.get_ref()
+    }
+
+    /// Waits for data and reads raw records into `buf`, returns the number of records read.
+    pub async fn read_records(&self, buf: &mut [Adxl345Sample]) -> io::Result<usize> {
+        loop {
+            let mut guard = self.inner.readable().await?;
+            match guard.try_io(|inner| inner.get_ref().read_records(buf)) {
+                Ok(Err(e)) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Ok(result) => return result,
+                Err(_would_block) => continue,
+            }
+        }
+    }
+
+    /// Waits for data and returns the records decoded from the next read.
+    ///
+    /// The batch is empty only when the read ended in the middle of a header, the rest of which
+    /// comes with the next batch.
+    pub async fn next_batch(&mut self) -> io::Result<Vec<Record>> {
+        let mut buf = std::mem::take(&mut self.buf);
+        let result = self.read_records(&mut buf).await.and_then(|n| match verify_batch(&buf[..n]) {
+            Some(false) => Err(io::Error::new(io::ErrorKind::InvalidData, "batch CRC mismatch")),
+            _ => Ok(n),
+        });
+        let batch = result.map(|n| buf[..n].iter().filter_map(|&raw| self.decoder.push(raw)).collect());
+        self.buf = buf;
+        batch
+    }
+}
@@ -0,0 +1,764 @@
+//! Typed access to the character device.
+
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::mem;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::abi::*;
+use crate::integrity::verify_batch;
+use crate::stream::{Record, StreamDecoder};
+
+/// Records requested by each read of the [`Samples`] iterator.
+const SAMPLES_BATCH: usize = 64;
+
+/// Clock used by the driver to timestamp samples, sync pulses and session headers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Clock {
+    Realtime,
+    Monotonic,
+    Boottime,
+}
+
+impl Clock {
+    /// Returns the `CLOCK_*` id passed to the driver.
+    pub fn id(self) -> u32 {
+        match self {
+            Clock::Realtime => libc::CLOCK_REALTIME as u32,
+            Clock::Monotonic => libc::CLOCK_MONOTONIC as u32,
+            Clock::Boottime => libc::CLOCK_BOOTTIME as u32,
+        }
+    }
+
+    /// Maps a `CLOCK_*` id back to the clock.
+    pub fn from_id(id: u32) -> Option<Self> {
+        [Clock::Realtime, Clock::Monotonic, Clock::Boottime].into_iter().find(|c| c.id() == id)
+    }
+
+    /// Parses `realtime`, `monotonic` or `boottime`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "realtime" => Some(Clock::Realtime),
+            "monotonic" => Some(Clock::Monotonic),
+            "boottime" => Some(Clock::Boottime),
+            _ => None,
+        }
+    }
+}
+
+/// When poll (and epoll, tokio's `AsyncFd`) reports the device readable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PollMode {
+    /// While a read would not block, the default.
+    Level,
+    /// Once per new batch of data, even if the previous one is still partly buffered.
+    Edge,
+}
+
+/// What a read does when the bus fails under the driver.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorPolicy {
+    /// The read fails with `EIO`, the default.
+    FailFast,
+    /// The read returns the samples gathered so far, the error comes as [`crate::Record::Error`]
+    /// in the stream where samples may be missing.
+    BestEffort,
+}
+
+/// Format of the records read from a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// [`Adxl345Sample`] records in shifted counts of 0.975 mg, the default.
+    Raw,
+    /// [`Adxl345ScaledSample`] records in µg.
+    MicroG,
+    /// [`Adxl345ScaledSample`] records in µm/s².
+    MicroMs2,
+    /// [`Adxl345StampedSample`] records, raw with the time each sample was read.
+    Timestamped,
+}
+
+/// Configuration parameter of the device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Param {
+    Rate,
+    Range,
+    Watermark,
+    ThreshTap,
+    Dur,
+    Latent,
+    Window,
+    ThreshAct,
+    ThreshInact,
+    TimeInact,
+    ThreshFf,
+    TimeFf,
+}
+
+impl Param {
+    /// All the parameters, in id order.
+    pub const ALL: [Param; 12] = [
+        Param::Rate, Param::Range, Param::Watermark, Param::ThreshTap, Param::Dur, Param::Latent,
+        Param::Window, Param::ThreshAct, Param::ThreshInact, Param::TimeInact, Param::ThreshFf,
+        Param::TimeFf,
+    ];
+
+    /// Returns the id passed to the driver.
+    pub fn id(self) -> u32 {
+        self as u32
+    }
+
+    /// Returns the name used on command lines, e.g. `thresh_tap`.
+    pub fn name(self) -> &'static str {
+        PARAM_NAMES[self as usize]
+    }
+
+    /// Returns the human unit of the scaled value, e.g. `mg`.
+    pub fn unit(self) -> &'static str {
+        PARAM_UNITS[self as usize]
+    }
+
+    /// Looks a parameter up by name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Param::ALL.into_iter().find(|p| p.name() == name)
+    }
+}
+
+/// A set of parameters applied together by [`Adxl345Device::configure`].
+///
+/// Values are in human units: rate in mHz, range in g, thresholds in mg, durations in µs.
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    settings: Vec<(Param, u32)>,
+}
+
+impl Config {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets a parameter, in human units.
+    pub fn set(mut self, param: Param, value: u32) -> Self {
+        self.settings.push((param, value));
+        self
+    }
+
+    /// Sets the output data rate, in mHz.
+    pub fn rate_mhz(self, rate: u32) -> Self {
+        self.set(Param::Rate, rate)
+    }
+
+    /// Sets the measurement range, in g.
+    pub fn range_g(self, range: u32) -> Self {
+        self.set(Param::Range, range)
+    }
+
+    /// Sets the FIFO watermark, in entries: 1 to 31, the field of FIFO_CTL is 5 bits wide.
+    pub fn watermark(self, entries: u32) -> Self {
+        self.set(Param::Watermark, entries)
+    }
+}
+
+/// An open ADXL345 character device.
+///
+/// The device is opened read-only, as the driver requires. Errors are the ones returned by the
+/// driver: `ERANGE` for out of range parameters (the reason is in debugfs, `config_error`),
+/// `ENODEV` when the device is not probed.
+#[derive(Debug)]
+pub struct Adxl345Device {
+    file: File,
+}
+
+impl Adxl345Device {
+    /// Opens the device, reads block until data is available.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Adxl345Device { file: File::open(path)? })
+    }
+
+    /// Opens the device, reads fail with `WouldBlock` when no data is available.
+    pub fn open_nonblocking<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).custom_flags(libc::O_NONBLOCK).open(path)?;
+        Ok(Adxl345Device { file })
+    }
+
+    /// Issues an ioctl whose argument is a pointer to `arg`.
+    fn ioctl<T>(&self, cmd: u32, arg: &mut T) -> io::Result<()> {
+        ioctl_ptr(self.file.as_raw_fd(), cmd, arg).map_err(io::Error::from_raw_os_error)
+    }
+
+    /// Issues an ioctl without argument.
+    fn ioctl_none(&self, cmd: u32) -> io::Result<()> {
+        if unsafe { libc::ioctl(self.file.as_raw_fd(), cmd as _) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Selects the clock used for timestamps.
+    pub fn set_clock(&self, clock: Clock) -> io::Result<()> {
+        self.ioctl(ADXL345_IOC_SET_CLOCK, &mut clock.id())
+    }
+
+    /// Returns the clock used for timestamps.
+    pub fn clock(&self) -> io::Result<Clock> {
+        let mut id = 0u32;
+        self.ioctl(ADXL345_IOC_GET_CLOCK, &mut id)?;
+        Clock::from_id(id).ok_or_else(|| io::Error::from_raw_os_error(libc::EINVAL))
+    }
+
+    /// Attaches the given GPIO line as sync input, or detaches the current one with `None`.
+    pub fn set_sync(&self, gpio: Option<u32>) -> io::Result<()> {
+        self.ioctl(ADXL345_IOC_SET_SYNC, &mut gpio.unwrap_or(u32::MAX))
+    }
+
+    /// Returns the last sync pulse.
+    pub fn sync_info(&self) -> io::Result<Adxl345SyncInfo> {
+        let mut info = Adxl345SyncInfo::default();
+        self.ioctl(ADXL345_IOC_GET_SYNC, &mut info)?;
+        Ok(info)
+    }
+
+    /// Returns the versions of the driver and of its ABI.
+    ///
+    /// Drivers older than the version ioctl fail with `ENOTTY`, see [`Self::abi_version`].
+    pub fn version(&self) -> io::Result<Adxl345Version> {
+        let mut version = Adxl345Version::default();
+        self.ioctl(ADXL345_IOC_GET_VERSION, &mut version)?;
+        Ok(version)
+    }
+
+    /// Returns the ABI version of the driver, 0 for a driver older than the version ioctl.
+    ///
+    /// A feature introduced at ABI version N can be used if this is at least N; the library
+    /// itself matches `abi::ADXL345_ABI_VERSION`.
+    pub fn abi_version(&self) -> io::Result<u32> {
+        match self.version() {
+            Ok(version) => Ok(version.abi),
+            Err(e) if e.raw_os_error() == Some(libc::ENOTTY) => Ok(0),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Returns the capability bits of the driver (`abi::ADXL345_CAP_*`) available on this device:
+    /// the data interrupt, alarm line and thermal guard bits are those of this device.
+    ///
+    /// Drivers before ABI version 2 fail with `ENOTTY`.
+    pub fn capabilities(&self) -> io::Result<u64> {
+        let mut caps = 0u64;
+        self.ioctl(ADXL345_IOC_GET_CAPS, &mut caps)?;
+        Ok(caps)
+    }
+
+    /// Sets a parameter in register LSBs (rate in mHz and range in g, as in human units).
+    pub fn set_param_raw(&self, param: Param, value: u32) -> io::Result<()> {
+        self.ioctl(ADXL345_IOC_SET_PARAM, &mut Adxl345ParamArg { param: param.id(), value })
+    }
+
+    /// Reads a parameter in register LSBs.
+    pub fn param_raw(&self, param: Param) -> io::Result<u32> {
+        let mut arg = Adxl345ParamArg { param: param.id(), value: 0 };
+        self.ioctl(ADXL345_IOC_GET_PARAM, &mut arg)?;
+        Ok(arg.value)
+    }
+
+    /// Sets a parameter in human units, returns the value achieved after rounding.
+    ///
+    /// Durations are in µs, since DUR has a 625 µs resolution and LATENT and WINDOW 1.25 ms.
+    /// Values above the one of 255 LSB, 15938 mg for thresholds, fail with `ERANGE`.
+    pub fn set_param(&self, param: Param, value: u32) -> io::Result<u32> {
+        let mut arg = Adxl345ParamArg { param: param.id(), value };
+        self.ioctl(ADXL345_IOC_SET_PARAM_SCALED, &mut arg)?;
+        Ok(arg.value)
+    }
+
+    /// Reads a parameter in human units.
+    pub fn param(&self, param: Param) -> io::Result<u32> {
+        let mut arg = Adxl345ParamArg { param: param.id(), value: 0 };
+        self.ioctl(ADXL345_IOC_GET_PARAM_SCALED, &mut arg)?;
+        Ok(arg.value)
+    }
+
+    /// Sets the output data rate, in mHz (100 to 3200000, doubling from 100). Drivers before ABI
+    /// version 17 fail it with `ENOTTY`, `set_param(Param::Rate, ..)` works with any.
+    pub fn set_rate(&self, mut rate_mhz: u32) -> io::Result<()> {
+        self.ioctl(ADXL345_IOC_SET_RATE, &mut rate_mhz)
+    }
+
+    /// Returns the output data rate, in mHz.
+    pub fn rate(&self) -> io::Result<u32> {
+        let mut rate_mhz = 0u32;
+        self.ioctl(ADXL345_IOC_GET_RATE, &mut rate_mhz)?;
+        Ok(rate_mhz)
+    }
+
+    /// Sets the measurement range, in g (2, 4, 8 or 16). Drivers before ABI version 17 fail it
+    /// with `ENOTTY`, `set_param(Param::Range, ..)` works with any.
+    pub fn set_range(&self, mut range_g: u32) -> io::Result<()> {
+        self.ioctl(ADXL345_IOC_SET_RANGE, &mut range_g)
+    }
+
+    /// Returns the measurement range, in g.
+    pub fn range(&self) -> io::Result<u32> {
+        let mut range_g = 0u32;
+        self.ioctl(ADXL345_IOC_GET_RANGE, &mut range_g)?;
+        Ok(range_g)
+    }
+
+    /// Applies a configuration in order, stopping at the first error.
+    ///
+    /// Returns the achieved value of each parameter.
+    pub fn configure(&self, config: &Config) -> io::Result<Vec<(Param, u32)>> {
+        config
+            .settings
+            .iter()
+            .map(|&(param, value)| Ok((param, self.set_param(param, value)?)))
+            .collect()
+    }
+
+    /// Discards the samples buffered in the kernel and in the device.
+    pub fn flush(&self) -> io::Result<()> {
+        self.ioctl_none(ADXL345_IOC_FLUSH)
+    }
+
+    /// Moves the samples still in the device into the kernel buffer, discarding nothing.
+    pub fn drain(&self) -> io::Result<()> {
+        self.file.sync_all()
+    }
+
+    /// Starts a measurement session, a no-op if one is running.
+    pub fn start(&self) -> io::Result<()> {
+        self.ioctl_none(ADXL345_IOC_START)
+    }
+
+    /// Stops the measurement session, the configuration is kept.
+    pub fn stop(&self) -> io::Result<()> {
+        self.ioctl_none(ADXL345_IOC_STOP)
+    }
+
+    /// Makes the sessions started from now on begin with a header.
+    pub fn set_header(&self, enabled: bool) -> io::Result<()> {
+        self.ioctl(ADXL345_IOC_SET_HEADER, &mut (enabled as u32))
+    }
+
+    /// Lets the driver change the range when clipping persists or the signal fits a lower one.
+    ///
+    /// Changes are reported in the stream as [`crate::Record::Range`].
+    pub fn set_auto_range(&self, enabled: bool) -> io::Result<()> {
+        self.ioctl(ADXL345_IOC_SET_AUTO_RANGE, &mut (enabled as u32))
+    }
+
+    /// Makes every read end with the CRC32 of the records it returned, for every reader.
+    ///
+    /// Reads need room for two more records. [`Adxl345Device::samples`] checks each batch and
+    /// fails with `InvalidData` on a mismatch; the CRC is also reported as [`crate::Record::Crc`].
+    pub fn set_batch_crc(&self, enabled: bool) -> io::Result<()> {
+        self.ioctl(ADXL345_IOC_SET_CRC, &mut (enabled as u32))
+    }
+
+    /// Selects when poll reports this file readable, the other open files keep their mode.
+    pub fn set_poll_mode(&self, mode: PollMode) -> io::Result<()> {
+        let mut arg = match mode {
+            PollMode::Level => ADXL345_POLL_LEVEL,
+            PollMode::Edge => ADXL345_POLL_EDGE,
+        };
+        self.ioctl(ADXL345_IOC_SET_POLL_MODE, &mut arg)
+    }
+
+    /// Selects what the reads of this file do on a bus error, the other files keep theirs.
+    /// Drivers before ABI version 4 fail it with `ENOTTY` and always fail fast.
+    pub fn set_error_policy(&self, policy: ErrorPolicy) -> io::Result<()> {
+        let mut arg = match policy {
+            ErrorPolicy::FailFast => ADXL345_ERRORS_FAIL_FAST,
+            ErrorPolicy::BestEffort => ADXL345_ERRORS_BEST_EFFORT,
+        };
+        self.ioctl(ADXL345_IOC_SET_ERROR_POLICY, &mut arg)
+    }
+
+    /// Selects the format of the records read from this file, the other files keep theirs. The
+    /// scaled formats are converted by the driver and read with [`Adxl345Device::read_scaled`];
+    /// [`Adxl345Device::read_records`] and [`Adxl345Device::samples`] need the raw format, the
+    /// timestamped one is read with [`Adxl345Device::read_stamped`]. Drivers before ABI version 14
+    /// fail it with `ENOTTY`, before 15 they fail the timestamped format with `EINVAL`.
+    pub fn set_output(&self, format: OutputFormat) -> io::Result<()> {
+        let mut arg = match format {
+            OutputFormat::Raw => ADXL345_OUTPUT_RAW,
+            OutputFormat::MicroG => ADXL345_OUTPUT_MICRO_G,
+            OutputFormat::MicroMs2 => ADXL345_OUTPUT_MICRO_MS2,
+            OutputFormat::Timestamped => ADXL345_OUTPUT_TIMESTAMPED,
+        };
+        self.ioctl(ADXL345_IOC_SET_OUTPUT, &mut arg)
+    }
+
+    /// Returns the format of the records read from this file.
+    pub fn output(&self) -> io::Result<OutputFormat> {
+        let mut arg = 0u32;
+        self.ioctl(ADXL345_IOC_GET_OUTPUT, &mut arg)?;
+        match arg {
+            ADXL345_OUTPUT_RAW => Ok(OutputFormat::Raw),
+            ADXL345_OUTPUT_MICRO_G => Ok(OutputFormat::MicroG),
+            ADXL345_OUTPUT_MICRO_MS2 => Ok(OutputFormat::MicroMs2),
+            ADXL345_OUTPUT_TIMESTAMPED => Ok(OutputFormat::Timestamped),
+            _ => Err(io::Error::from_raw_os_error(libc::EINVAL)),
+        }
+    }
+
+    /// Sets the capacity of the kernel buffer, from `ADXL345_BUFFER_MIN` to `ADXL345_BUFFER_MAX`
+    /// samples (256 by default), for every reader. A reader that may fall behind for longer than
+    /// the buffer lasts at the rate loses samples, counted in [`Adxl345BufferArg::overruns`].
+    /// Drivers before ABI version 16 fail it with `ENOTTY`.
+    pub fn set_buffer_capacity(&self, capacity: u32) -> io::Result<()> {
+        let mut arg = capacity;
+        self.ioctl(ADXL345_IOC_SET_BUFFER, &mut arg)
+    }
+
+    /// Returns the capacity of the kernel buffer, what it holds and the samples dropped so far
+    /// because it was full.
+    pub fn buffer(&self) -> io::Result<Adxl345BufferArg> {
+        let mut arg = Adxl345BufferArg::default();
+        self.ioctl(ADXL345_IOC_GET_BUFFER, &mut arg)?;
+        Ok(arg)
+    }
+
+    /// Resamples the samples read from this file to `rate_mhz` by linear interpolation, whatever
+    /// the rate of the device; `None` reads the device samples again. The other files keep the
+    /// device rate. The read filter doesn't apply to a resampled file. Drivers before ABI
+    /// version 6 fail it with `ENOTTY`.
+    pub fn set_resample_rate(&self, rate_mhz: Option<u32>) -> io::Result<()> {
+        let mut arg = rate_mhz.unwrap_or(0);
+        self.ioctl(ADXL345_IOC_SET_RESAMPLE, &mut arg)
+    }
+
+    /// Reports the taps detected by the device in the stream, as [`crate::Record::Tap`], for
+    /// every reader: `events` holds `ADXL345_TAP_SINGLE` and `ADXL345_TAP_DOUBLE`, `axes` the
+    /// axes taking part (bit 0 x, bit 1 y, bit 2 z). The thresholds and timings are the
+    /// `thresh_tap`, `dur`, `latent` and `window` parameters. Events without any axis fail with
+    /// `EINVAL`, drivers before ABI version 7 fail it with `ENOTTY`.
+    pub fn set_tap(&self, events: u32, axes: u32) -> io::Result<()> {
+        self.ioctl(ADXL345_IOC_SET_TAP, &mut Adxl345TapArg { events, axes })
+    }
+
+    /// Returns the tap events reported and the axes taking part.
+    pub fn tap(&self) -> io::Result<Adxl345TapArg> {
+        let mut arg = Adxl345TapArg::default();
+        self.ioctl(ADXL345_IOC_GET_TAP, &mut arg)?;
+        Ok(arg)
+    }
+
+    /// Samples in bursts rather than continuously, for every reader: the device measures for
+    /// `length_ms` every `period_ms` and sleeps in between, e.g. `set_burst(60_000, 1_000)` for
+    /// 1 s every minute at the configured rate. Each burst starts with a [`crate::Record::Header`]
+    /// holding its start time and ends with a [`crate::Record::BurstEnd`]. A length of 0 measures
+    /// continuously again; a burst as long as its period fails with `EINVAL`, a period above a
+    /// day with `ERANGE`, drivers before ABI version 8 with `ENOTTY`.
+    pub fn set_burst(&self, period_ms: u32, length_ms: u32) -> io::Result<()> {
+        self.ioctl(ADXL345_IOC_SET_BURST, &mut Adxl345BurstArg { period_ms, length_ms })
+    }
+
+    /// Returns the schedule of burst sampling, a length of 0 when measuring continuously.
+    pub fn burst(&self) -> io::Result<Adxl345BurstArg> {
+        let mut arg = Adxl345BurstArg::default();
+        self.ioctl(ADXL345_IOC_GET_BURST, &mut arg)?;
+        Ok(arg)
+    }
+
+    /// Arms the activity, inactivity and free-fall events of `events` (`ADXL345_MOTION_*`) and
+    /// writes ACT_INACT_CTL, for every reader. The thresholds and times are the `thresh_act`,
+    /// `thresh_inact`, `time_inact`, `thresh_ff` and `time_ff` parameters. Drivers before ABI
+    /// version 9 fail it with `ENOTTY`.
+    pub fn set_motion(&self, events: u32, act_inact_ctl: u8) -> io::Result<()> {
+        let mut arg = Adxl345MotionArg { events, act_inact_ctl: act_inact_ctl as u32 };
+        self.ioctl(ADXL345_IOC_SET_MOTION, &mut arg)
+    }
+
+    /// Returns the armed motion events and ACT_INACT_CTL.
+    pub fn motion(&self) -> io::Result<Adxl345MotionArg> {
+        let mut arg = Adxl345MotionArg::default();
+        self.ioctl(ADXL345_IOC_GET_MOTION, &mut arg)?;
+        Ok(arg)
+    }
+
+    /// Returns the last motion event. Poll reports `POLLPRI` on this file from a new event
+    /// until it is fetched here; the sequence skips when events came faster than they were
+    /// fetched.
+    pub fn motion_event(&self) -> io::Result<Adxl345MotionEvent> {
+        let mut event = Adxl345MotionEvent::default();
+        self.ioctl(ADXL345_IOC_GET_MOTION_EVENT, &mut event)?;
+        Ok(event)
+    }
+
+    /// Waits for a motion event not fetched yet by this file, up to `timeout` (forever with
+    /// `None`), and fetches it.
+    ///
+    /// Returns `None` if the timeout expired first.
+    pub fn wait_motion_event(&self, timeout: Option<Duration>) -> io::Result<Option<Adxl345MotionEvent>> {
+        let timeout_ms = timeout.map_or(-1, |t| t.as_millis().min(i32::MAX as u128) as i32);
+        let mut fd = libc::pollfd { fd: self.file.as_raw_fd(), events: libc::POLLPRI, revents: 0 };
+        loop {
+            let ret = unsafe { libc::poll(&mut fd, 1, timeout_ms) };
+            if ret < 0 {
+                let e = io::Error::last_os_error();
+                if e.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(e);
+            }
+            if ret == 0 {
+                return Ok(None);
+            }
+            if fd.revents & libc::POLLPRI != 0 {
+                return self.motion_event().map(Some);
+            }
+            // Removed device
+            if fd.revents & libc::POLLHUP != 0 {
+                return Err(io::Error::from_raw_os_error(libc::ENODEV));
+            }
+        }
+    }
+
+    /// Sets the power modes of `flags` (`ADXL345_POWER_*`), for every reader:
+    /// `ADXL345_POWER_LOW_POWER` lowers the current from 12.5 to 400 Hz for more noise,
+    /// `ADXL345_POWER_AUTO_SLEEP` samples at `wakeup_hz` (8, 4, 2 or 1) while the device is
+    /// inactive, with the `thresh_inact` and `time_inact` parameters and the inactivity axes of
+    /// [`Adxl345Device::set_motion`]. 0 is full power. Drivers before ABI version 10 fail it with
+    /// `ENOTTY`.
+    pub fn set_power(&self, flags: u32, wakeup_hz: u32) -> io::Result<()> {
+        self.ioctl(ADXL345_IOC_SET_POWER, &mut Adxl345PowerArg { flags, wakeup_hz })
+    }
+
+    /// Returns the power modes and the sleep rate.
+    pub fn power(&self) -> io::Result<Adxl345PowerArg> {
+        let mut arg = Adxl345PowerArg::default();
+        self.ioctl(ADXL345_IOC_GET_POWER, &mut arg)?;
+        Ok(arg)
+    }
+
+    /// Returns the events of the [`crate::Record::Event`] marker of `id` and the time they were
+    /// flagged. The driver keeps the last 16 markers, older IDs fail with `ENOENT`; drivers
+    /// before ABI version 11 fail it with `ENOTTY`.
+    pub fn event(&self, id: u16) -> io::Result<Adxl345EventInfo> {
+        let mut arg = Adxl345EventInfo { id: id as u32, ..Default::default() };
+        self.ioctl(ADXL345_IOC_GET_EVENT, &mut arg)?;
+        Ok(arg)
+    }
+
+    /// Calibrates the offsets from `samples` samples (at most 1000, 0 for 100) taken at the
+    /// current rate, with the device held still and one axis along gravity: that axis then
+    /// reads ±1 g and the others 0. Returns the OFSX, OFSY and OFSZ values written, which the
+    /// driver keeps. Fails with `EBUSY` while a session runs (see [`Adxl345Device::stop`]),
+    /// with `EAGAIN` if the device moved; drivers before ABI version 12 fail it with `ENOTTY`.
+    pub fn calibrate(&self, samples: u32) -> io::Result<[i8; 3]> {
+        let mut arg = Adxl345CalibrateArg { samples, ..Default::default() };
+        self.ioctl(ADXL345_IOC_CALIBRATE, &mut arg)?;
+        Ok([arg.offsets.x, arg.offsets.y, arg.offsets.z].map(|offset| offset as i8))
+    }
+
+    /// Writes the OFSX, OFSY and OFSZ registers, 15.6 mg per unit, for offsets computed
+    /// elsewhere. The driver keeps them.
+    pub fn set_offsets(&self, offsets: [i8; 3]) -> io::Result<()> {
+        let [x, y, z] = offsets.map(|offset| offset as i32);
+        self.ioctl(ADXL345_IOC_SET_OFFSETS, &mut Adxl345OffsetArg { x, y, z })
+    }
+
+    /// Returns the OFSX, OFSY and OFSZ registers.
+    pub fn offsets(&self) -> io::Result<[i8; 3]> {
+        let mut arg = Adxl345OffsetArg::default();
+        self.ioctl(ADXL345_IOC_GET_OFFSETS, &mut arg)?;
+        Ok([arg.x, arg.y, arg.z].map(|offset| offset as i8))
+    }
+
+    /// Sets the threshold of the read filter, for every reader: a sample is dropped when no axis
+    /// changed by more than it since the previous one. Fails with `ENOTTY` if the driver is built
+    /// without the filter (`ADXL345_CAP_FILTER` clear) or older than ABI version 5.
+    pub fn set_filter(&self, mut threshold: u32) -> io::Result<()> {
+        self.ioctl(ADXL345_IOC_SET_FILTER, &mut threshold)
+    }
+
+    /// Returns the threshold of the read filter.
+    pub fn filter(&self) -> io::Result<u32> {
+        let mut threshold = 0u32;
+        self.ioctl(ADXL345_IOC_GET_FILTER, &mut threshold)?;
+        Ok(threshold)
+    }
+
+    /// Sets the stages of the read filter, in the order they run, for every reader: e.g.
+    /// averaging over 4 samples, then keeping one of every 10. The threshold stage
+    /// (`ADXL345_STAGE_THRESHOLD`) uses the threshold of [`Adxl345Device::set_filter`]; an empty
+    /// list delivers every sample. More than `ADXL345_PIPELINE_LEN` stages, a parameter out of its
+    /// range or a kind given twice fail with `EINVAL`, drivers built without the filter or before
+    /// ABI version 13 with `ENOTTY`.
+    pub fn set_pipeline(&self, stages: &[Adxl345StageArg]) -> io::Result<()> {
+        if stages.len() > ADXL345_PIPELINE_LEN {
+            return Err(io::Error::from_raw_os_error(libc::EINVAL));
+        }
+        let mut arg = Adxl345PipelineArg { len: stages.len() as u32, ..Default::default() };
+        arg.stages[..stages.len()].copy_from_slice(stages);
+        self.ioctl(ADXL345_IOC_SET_PIPELINE, &mut arg)
+    }
+
+    /// Returns the stages of the read filter, in the order they run.
+    pub fn pipeline(&self) -> io::Result<Vec<Adxl345StageArg>> {
+        let mut arg = Adxl345PipelineArg::default();
+        self.ioctl(ADXL345_IOC_GET_PIPELINE, &mut arg)?;
+        Ok(arg.stages[..(arg.len as usize).min(ADXL345_PIPELINE_LEN)].to_vec())
+    }
+
+    /// Sets the records the driver must have buffered before it sends `SIGIO` for new data, to
+    /// the files opened with `O_ASYNC`; 1 (the default) signals every drain. The threshold is
+    /// the same for every file, from 1 to the size of the kernel buffer (256 records).
+    pub fn set_sigio_threshold(&self, mut records: u32) -> io::Result<()> {
+        self.ioctl(ADXL345_IOC_SET_SIGIO_THRESHOLD, &mut records)
+    }
+
+    /// Saves the configuration in use as a named preset, replacing the one with the same name.
+    ///
+    /// Names are 1 to 16 printable ASCII characters other than space. The driver keeps at most
+    /// 8 presets, saving more fails with `ENOSPC`.
+    pub fn save_preset(&self, name: &str) -> io::Result<()> {
+        self.ioctl(ADXL345_IOC_PRESET_SAVE, &mut preset_name(name)?)
+    }
+
+    /// Applies a preset saved with [`Adxl345Device::save_preset`], `ENOENT` if there is none.
+    pub fn apply_preset(&self, name: &str) -> io::Result<()> {
+        self.ioctl(ADXL345_IOC_PRESET_APPLY, &mut preset_name(name)?)
+    }
+
+    /// Deletes a preset, `ENOENT` if there is none.
+    pub fn delete_preset(&self, name: &str) -> io::Result<()> {
+        self.ioctl(ADXL345_IOC_PRESET_DELETE, &mut preset_name(name)?)
+    }
+
+    /// Returns the number of bytes a read can return without blocking.
+    pub fn readable_bytes(&self) -> io::Result<usize> {
+        let mut bytes: libc::c_int = 0;
+        self.ioctl(libc::FIONREAD as u32, &mut bytes)?;
+        Ok(bytes as usize)
+    }
+
+    /// Reads raw records, samples and markers, into `buf`.
+    ///
+    /// Returns the number of records read. The driver rejects buffers smaller than a record, and
+    /// smaller than a header while one is pending.
+    pub fn read_records(&self, buf: &mut [Adxl345Sample]) -> io::Result<usize> {
+        let ret = unsafe {
+            libc::read(self.file.as_raw_fd(), buf.as_mut_ptr() as *mut libc::c_void, mem::size_of_val(buf))
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(ret as usize / mem::size_of::<Adxl345Sample>())
+    }
+
+    /// Reads raw records into `bufs` in order with a single `readv`, as [`Adxl345Device::read_records`]
+    /// into one buffer: the driver fills each buffer before the next one.
+    ///
+    /// Returns the number of records read, over all the buffers.
+    pub fn read_records_vectored(&self, bufs: &mut [&mut [Adxl345Sample]]) -> io::Result<usize> {
+        let iov: Vec<libc::iovec> = bufs
+            .iter_mut()
+            .map(|buf| libc::iovec { iov_base: buf.as_mut_ptr() as *mut libc::c_void, iov_len: mem::size_of_val(*buf) })
+            .collect();
+        let ret = unsafe { libc::readv(self.file.as_raw_fd(), iov.as_ptr(), iov.len() as libc::c_int) };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(ret as usize / mem::size_of::<Adxl345Sample>())
+    }
+
+    /// Reads scaled records, samples and markers, into `buf`, once the file reads a scaled format
+    /// (see [`Adxl345Device::set_output`]).
+    ///
+    /// Returns the number of records read. [`Adxl345ScaledSample::to_marker`] turns the markers
+    /// into raw records for a [`StreamDecoder`].
+    pub fn read_scaled(&self, buf: &mut [Adxl345ScaledSample]) -> io::Result<usize> {
+        let ret = unsafe {
+            libc::read(self.file.as_raw_fd(), buf.as_mut_ptr() as *mut libc::c_void, mem::size_of_val(buf))
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(ret as usize / mem::size_of::<Adxl345ScaledSample>())
+    }
+
+    /// Reads timestamped records, samples and markers, into `buf`, once the file reads the
+    /// timestamped format (see [`Adxl345Device::set_output`]).
+    ///
+    /// Returns the number of records read. [`Adxl345StampedSample::record`] gives the raw record
+    /// for a [`StreamDecoder`].
+    pub fn read_stamped(&self, buf: &mut [Adxl345StampedSample]) -> io::Result<usize> {
+        let ret = unsafe {
+            libc::read(self.file.as_raw_fd(), buf.as_mut_ptr() as *mut libc::c_void, mem::size_of_val(buf))
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(ret as usize / mem::size_of::<Adxl345StampedSample>())
+    }
+
+    /// Returns an iterator over the decoded stream. It ends only on errors, `WouldBlock` included
+    /// for a nonblocking device.
+    pub fn samples(&self) -> Samples<'_> {
+        Samples {
+            device: self,
+            decoder: StreamDecoder::new(),
+            buf: vec![Adxl345Sample::default(); SAMPLES_BATCH],
+            pos: 0,
+            len: 0,
+        }
+    }
+}
+
+/// Packs a preset name for the driver, `InvalidInput` if it can't be one.
+fn preset_name(name: &str) -> io::Result<Adxl345PresetName> {
+    if name.is_empty() || name.len() > PRESET_NAME_LEN || !name.bytes().all(|c| c.is_ascii_graphic()) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("invalid preset name: {:?}", name)));
+    }
+    let mut arg = Adxl345PresetName::default();
+    arg.name[..name.len()].copy_from_slice(name.as_bytes());
+    Ok(arg)
+}
+
+impl AsRawFd for Adxl345Device {
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+}
+
+/// Iterator over the decoded stream, see [`Adxl345Device::samples`].
+pub struct Samples<'a> {
+    device: &'a Adxl345Device,
+    decoder: StreamDecoder,
+    buf: Vec<Adxl345Sample>,
+    pos: usize,
+    len: usize,
+}
+
+impl Iterator for Samples<'_> {
+    type Item = io::Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            while self.pos < self.len {
+                let raw = self.buf[self.pos];
+                self.pos += 1;
+                if let Some(record) = self.decoder.push(raw) {
+                    return Some(Ok(record));
+                }
+            }
+
+            match self.device.read_records(&mut self.buf) {
+                Ok(len) if verify_batch(&self.buf[..len]) == Some(false) => {
+                    return Some(Err(io::Error::new(io::ErrorKind::InvalidData, "batch CRC mismatch")));
+                }
+                Ok(len) => {
+                    self.pos = 0;
+                    self.len = len;
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
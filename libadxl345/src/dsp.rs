@@ -0,0 +1,308 @@
+//! Signal processing on the sample stream, for vibration analysis.
+//!
+//! Everything works on values in mg, as returned by [`Adxl345Sample::to_mg`]:
+//! - [`Biquad`] and [`Butterworth`]: IIR low-pass and high-pass filters.
+//! - [`AxisFilter`]: one filter per axis, fed with samples.
+//! - [`rms`], [`peak`] and [`PeakDetector`]: level and shock detection.
+//! - `spectrum` (feature `fft`): single-sided amplitude spectrum.
+//!
+//! The sample rate is the one of the stream, e.g. from the session header (`rate_mhz / 1000`).
+
+use std::f64::consts::PI;
+
+use crate::abi::Adxl345Sample;
+
+/// Response of a filter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pass {
+    Low,
+    High,
+}
+
+/// A second order IIR section, in transposed direct form II.
+#[derive(Debug, Clone)]
+pub struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    state: [f64; 2],
+}
+
+impl Biquad {
+    /// Designs a section with the bilinear transform, `cutoff_hz` below half `rate_hz`.
+    ///
+    /// `q` sets the resonance, `1/sqrt(2)` is the maximally flat (Butterworth) response.
+    pub fn new(pass: Pass, cutoff_hz: f64, rate_hz: f64, q: f64) -> Self {
+        let w = 2.0 * PI * cutoff_hz / rate_hz;
+        let alpha = w.sin() / (2.0 * q);
+        let cos = w.cos();
+        let a0 = 1.0 + alpha;
+        let b = match pass {
+            Pass::Low => [(1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0],
+            Pass::High => [(1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0],
+        };
+        Biquad { b: b.map(|v| v / a0), a: [-2.0 * cos / a0, (1.0 - alpha) / a0], state: [0.0; 2] }
+    }
+
+    /// A first order section, as the odd stage of a Butterworth filter.
+    fn first_order(pass: Pass, cutoff_hz: f64, rate_hz: f64) -> Self {
+        let k = (PI * cutoff_hz / rate_hz).tan();
+        let a1 = (k - 1.0) / (k + 1.0);
+        let b = match pass {
+            Pass::Low => [k / (k + 1.0), k / (k + 1.0), 0.0],
+            Pass::High => [1.0 / (k + 1.0), -1.0 / (k + 1.0), 0.0],
+        };
+        Biquad { b, a: [a1, 0.0], state: [0.0; 2] }
+    }
+
+    /// Filters the next value.
+    pub fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.state[0];
+        self.state[0] = self.b[1] * x - self.a[0] * y + self.state[1];
+        self.state[1] = self.b[2] * x - self.a[1] * y;
+        y
+    }
+
+    /// Clears the history, e.g. after a gap in the stream.
+    pub fn reset(&mut self) {
+        self.state = [0.0; 2];
+    }
+}
+
+/// A Butterworth filter of any order, as a cascade of sections.
+#[derive(Debug, Clone)]
+pub struct Butterworth {
+    sections: Vec<Biquad>,
+}
+
+impl Butterworth {
+    /// Designs a filter of `order` (at least 1), `cutoff_hz` below half `rate_hz`.
+    pub fn new(pass: Pass, order: usize, cutoff_hz: f64, rate_hz: f64) -> Self {
+        let order = order.max(1);
+        let mut sections: Vec<Biquad> = (0..order / 2)
+            .map(|k| {
+                // Each pair of poles of the analog prototype gives the Q of one section
+                let q = 1.0 / (2.0 * (PI * (2 * k + 1) as f64 / (2 * order) as f64).sin());
+                Biquad::new(pass, cutoff_hz, rate_hz, q)
+            })
+            .collect();
+        if order % 2 == 1 {
+            sections.push(Biquad::first_order(pass, cutoff_hz, rate_hz));
+        }
+        Butterworth { sections }
+    }
+
+    /// Filters the next value.
+    pub fn process(&mut self, x: f64) -> f64 {
+        self.sections.iter_mut().fold(x, |x, section| section.process(x))
+    }
+
+    /// Clears the history, e.g. after a gap in the stream.
+    pub fn reset(&mut self) {
+        self.sections.iter_mut().for_each(Biquad::reset);
+    }
+}
+
+/// The same filter on the three axes.
+#[derive(Debug, Clone)]
+pub struct AxisFilter {
+    axes: [Butterworth; 3],
+}
+
+impl AxisFilter {
+    pub fn new(filter: Butterworth) -> Self {
+        AxisFilter { axes: [filter.clone(), filter.clone(), filter] }
+    }
+
+    /// Filters a sample, returns the three axes in mg.
+    pub fn process(&mut self, sample: &Adxl345Sample) -> [f64; 3] {
+        let mg = sample.to_mg();
+        [0, 1, 2].map(|i| self.axes[i].process(mg[i]))
+    }
+
+    /// Clears the history, e.g. at a new session header.
+    pub fn reset(&mut self) {
+        self.axes.iter_mut().for_each(Butterworth::reset);
+    }
+}
+
+/// Returns the root mean square of `values`, 0 if there are none.
+pub fn rms(values: &[f64]) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    (values.iter().map(|v| v * v).sum::<f64>() / values.len() as f64).sqrt()
+}
+
+/// Returns the largest absolute value of `values`, 0 if there are none.
+pub fn peak(values: &[f64]) -> f64 {
+    values.iter().fold(0.0, |max, v| v.abs().max(max))
+}
+
+/// Detects peaks of a signal above a threshold, e.g. shocks on the magnitude of a high-passed
+/// stream.
+///
+/// A peak starts when the absolute value exceeds `threshold` and ends when it falls below
+/// `threshold - hysteresis`, so noise around the threshold doesn't report many peaks.
+#[derive(Debug, Clone)]
+pub struct PeakDetector {
+    threshold: f64,
+    release: f64,
+    current: Option<f64>,
+}
+
+impl PeakDetector {
+    pub fn new(threshold: f64, hysteresis: f64) -> Self {
+        PeakDetector { threshold, release: threshold - hysteresis.abs(), current: None }
+    }
+
+    /// Feeds the next value, returns the height of a peak when it ends.
+    pub fn push(&mut self, value: f64) -> Option<f64> {
+        let value = value.abs();
+        match self.current {
+            None if value > self.threshold => {
+                self.current = Some(value);
+                None
+            }
+            Some(height) if value < self.release => {
+                self.current = None;
+                Some(height)
+            }
+            Some(height) => {
+                self.current = Some(height.max(value));
+                None
+            }
+            None => None,
+        }
+    }
+}
+
+/// Returns the single-sided amplitude spectrum of `values`, as `(frequency in Hz, amplitude)`.
+///
+/// A Hann window is applied and compensated, so a sine of amplitude A in the middle of a bin reads
+/// about A. The mean is removed first, gravity would otherwise dominate the first bins.
+#[cfg(feature = "fft")]
+pub fn spectrum(values: &[f64], rate_hz: f64) -> Vec<(f64, f64)> {
+    use rustfft::num_complex::Complex;
+    use rustfft::FftPlanner;
+
+    let n = values.len();
+    if n < 2 {
+        return Vec::new();
+    }
+    let mean = values.iter().sum::<f64>() / n as f64;
+    let mut buffer: Vec<Complex<f64>> = values
+        .iter()
+        .enumerate()
+        .map(|(i, v)| {
+            let window = 0.5 - 0.5 * (2.0 * PI * i as f64 / (n - 1) as f64).cos();
+            Complex::new((v - mean) * window, 0.0)
+        })
+        .collect();
+    FftPlanner::new().plan_fft_forward(n).process(&mut buffer);
+
+    // The Hann window halves the amplitude, the single side doubles it again
+    let gain = 4.0 / n as f64;
+    buffer[..n / 2 + 1]
+        .iter()
+        .enumerate()
+        .map(|(i, c)| (i as f64 * rate_hz / n as f64, c.norm() * if i == 0 { gain / 2.0 } else { gain }))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the largest absolute output of `filter` over the last second of a 2 s sine of
+    /// amplitude 1, once the filter settled.
+    fn sine_peak(filter: &mut Butterworth, frequency_hz: f64, rate_hz: f64) -> f64 {
+        let n = (2.0 * rate_hz) as usize;
+        let out: Vec<f64> =
+            (0..n).map(|i| filter.process((2.0 * PI * frequency_hz * i as f64 / rate_hz).sin())).collect();
+        peak(&out[n / 2..])
+    }
+
+    #[test]
+    fn low_pass_keeps_dc_and_cuts_above_cutoff() {
+        for order in 1..=4 {
+            let mut filter = Butterworth::new(Pass::Low, order, 5.0, 200.0);
+            let settled = (0..1000).map(|_| filter.process(1.0)).last().unwrap();
+            assert!((settled - 1.0).abs() < 1e-6, "order {}: dc gain {}", order, settled);
+
+            let mut filter = Butterworth::new(Pass::Low, order, 5.0, 200.0);
+            let stop = sine_peak(&mut filter, 50.0, 200.0);
+            assert!(stop < 0.15, "order {}: 50 Hz kept at {}", order, stop);
+        }
+    }
+
+    #[test]
+    fn high_pass_removes_dc_and_keeps_above_cutoff() {
+        let mut filter = Butterworth::new(Pass::High, 2, 1.0, 1000.0);
+        let settled = (0..5000).map(|_| filter.process(1000.0)).last().unwrap();
+        assert!(settled.abs() < 1e-3, "dc left at {}", settled);
+
+        let mut filter = Butterworth::new(Pass::High, 2, 1.0, 1000.0);
+        let pass = sine_peak(&mut filter, 20.0, 1000.0);
+        assert!((pass - 1.0).abs() < 0.02, "20 Hz kept at {}", pass);
+    }
+
+    #[test]
+    fn butterworth_is_3_db_down_at_cutoff() {
+        let mut filter = Butterworth::new(Pass::Low, 4, 10.0, 1000.0);
+        let gain = sine_peak(&mut filter, 10.0, 1000.0);
+        assert!((gain - std::f64::consts::FRAC_1_SQRT_2).abs() < 0.01, "gain {}", gain);
+    }
+
+    #[test]
+    fn reset_clears_the_history() {
+        let mut filter = Butterworth::new(Pass::Low, 3, 5.0, 100.0);
+        let first = filter.process(1.0);
+        (0..10).for_each(|_| {
+            filter.process(1.0);
+        });
+        filter.reset();
+        assert_eq!(filter.process(1.0), first);
+    }
+
+    #[test]
+    fn axis_filter_works_in_mg() {
+        let mut filter = AxisFilter::new(Butterworth::new(Pass::Low, 2, 5.0, 100.0));
+        let sample = Adxl345Sample { x: 1024, y: -1024, z: 0 };
+        let mut out = [0.0; 3];
+        for _ in 0..500 {
+            out = filter.process(&sample);
+        }
+        let mg = sample.to_mg();
+        (0..3).for_each(|i| assert!((out[i] - mg[i]).abs() < 1e-6));
+    }
+
+    #[test]
+    fn rms_and_peak() {
+        assert_eq!(rms(&[]), 0.0);
+        assert_eq!(peak(&[]), 0.0);
+        assert!((rms(&[3.0, -4.0]) - 12.5f64.sqrt()).abs() < 1e-12);
+        assert_eq!(peak(&[1.0, -7.5, 3.0]), 7.5);
+    }
+
+    #[test]
+    fn peak_detector_reports_one_peak_with_hysteresis() {
+        let mut detector = PeakDetector::new(100.0, 10.0);
+        let peaks: Vec<f64> =
+            [50.0, 120.0, 95.0, 101.0, -150.0, 91.0, 89.0, 105.0, 80.0].iter().filter_map(|&v| detector.push(v)).collect();
+        assert_eq!(peaks, [150.0, 105.0]);
+    }
+
+    #[cfg(feature = "fft")]
+    #[test]
+    fn spectrum_reads_the_amplitude_of_a_sine() {
+        let (n, rate_hz, frequency_hz, amplitude) = (1024, 1024.0, 64.0, 2.0);
+        let values: Vec<f64> =
+            (0..n).map(|i| 1000.0 + amplitude * (2.0 * PI * frequency_hz * i as f64 / rate_hz).sin()).collect();
+        let spectrum = spectrum(&values, rate_hz);
+        assert_eq!(spectrum.len(), n / 2 + 1);
+        let (frequency, level) = spectrum.iter().copied().fold((0.0, 0.0), |max, bin| if bin.1 > max.1 { bin } else { max });
+        assert_eq!(frequency, frequency_hz);
+        assert!((level - amplitude).abs() < 0.05, "amplitude {}", level);
+        assert!(spectrum[0].1 < 1e-6, "mean left at {}", spectrum[0].1);
+    }
+}
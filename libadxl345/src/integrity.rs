@@ -0,0 +1,255 @@
+//! End-to-end integrity check of the sample stream.
+//!
+//! The emulator (waveform `6`, see `emul/README.md`) can feed the data registers with a
+//! self-checking pattern instead of an acceleration: every sample carries its own index and a
+//! check word, so a reader can tell whether what reaches userspace is exactly what the device
+//! produced, in order. Corruption, byte swaps or torn records introduced anywhere in the stack
+//! (bus, drain, kernel buffer, read path, decoding) show up as invalid samples; lost, duplicated
+//! or reordered samples as breaks in the sequence.
+//!
+//! Sample `n` holds three 12-bit words, in raw counts of the data registers offset by 2048:
+//! - x: `(n * 2731) mod 4096`, the low 12 bits of the index, scrambled so two consecutive samples
+//!   always differ by more than the read filter threshold and are never discarded by it.
+//! - y: the low 12 bits of [`check_word`] of the index.
+//! - z: bits 12 to 23 of the index.
+//!
+//! The index wraps every 2^24 samples, over an hour at the highest rate.
+//!
+//! Independently of the pattern, the driver can end every read with the CRC32 of the records it
+//! returned (see [`crate::Adxl345Device::set_batch_crc`]); [`verify_batch`] checks it, on any
+//! stream.
+
+use crate::abi::{Adxl345Sample, ADXL345_CRC_WORDS, ADXL345_MARKER_CRC};
+
+/// Number of distinct indexes.
+pub const INTEGRITY_PERIOD: u32 = 1 << 24;
+
+/// Multiplier scrambling the low bits of the index in x, 3 is its inverse modulo 4096.
+const SCRAMBLE: u32 = 2731;
+const UNSCRAMBLE: u32 = 3;
+
+/// Offset of the words, so they fit the signed data registers.
+const OFFSET: i32 = 2048;
+
+/// Returns the check word of index `n` (lowbias32 hash), the same as the emulator.
+pub fn check_word(n: u32) -> u32 {
+    let mut value = n;
+    value ^= value >> 16;
+    value = value.wrapping_mul(0x7feb352d);
+    value ^= value >> 15;
+    value = value.wrapping_mul(0x846ca68b);
+    value ^ (value >> 16)
+}
+
+/// Returns sample `n` of the pattern, as read from the device (shifted by 2).
+pub fn encode(n: u32) -> Adxl345Sample {
+    let word = |w: u32| (((w & 0xFFF) as i32 - OFFSET) << 2) as i16;
+    Adxl345Sample { x: word(n.wrapping_mul(SCRAMBLE)), y: word(check_word(n & (INTEGRITY_PERIOD - 1))), z: word(n >> 12) }
+}
+
+/// Returns the index carried by `sample`, `None` if the sample is not a valid pattern sample.
+pub fn decode(sample: &Adxl345Sample) -> Option<u32> {
+    let word = |v: i16| -> Option<u32> {
+        // The two low bits of a sample are always 0, a set bit is corruption
+        if v & 0x3 != 0 {
+            return None;
+        }
+        let w = (v >> 2) as i32 + OFFSET;
+        (0..4096).contains(&w).then_some(w as u32)
+    };
+    let low = word(sample.x)?.wrapping_mul(UNSCRAMBLE) & 0xFFF;
+    let n = word(sample.z)? << 12 | low;
+    (word(sample.y)? == check_word(n) & 0xFFF).then_some(n)
+}
+
+/// Returns the standard CRC-32 (IEEE 802.3, as zlib) of `bytes`, the one used by the driver.
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { crc >> 1 ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+/// Checks the CRC ending `batch`, the records returned by a single read.
+///
+/// Returns `None` if the batch doesn't end with a CRC, the batch CRC is disabled, and whether the
+/// CRC matches the records before it otherwise.
+pub fn verify_batch(batch: &[Adxl345Sample]) -> Option<bool> {
+    let split = batch.len().checked_sub(ADXL345_CRC_WORDS)?;
+    let (records, trailer) = batch.split_at(split);
+    if !trailer.iter().all(|r| r.is_marker() && r.y == ADXL345_MARKER_CRC) {
+        return None;
+    }
+    let expected = trailer[0].z as u16 as u32 | (trailer[1].z as u16 as u32) << 16;
+    let bytes: Vec<u8> = records.iter().flat_map(|r| r.to_ne_bytes()).collect();
+    Some(crc32(&bytes) == expected)
+}
+
+/// Outcome of the check so far.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IntegrityReport {
+    /// Samples with a valid index and check word.
+    pub valid: u64,
+    /// Samples failing the check, the stream is corrupted.
+    pub corrupt: u64,
+    /// Samples missing between two valid ones, explained by drops or flushes.
+    pub missing: u64,
+    /// Valid samples repeating or going back in the sequence, the stream is reordered.
+    pub out_of_order: u64,
+    /// Times the sequence was restarted by a session header.
+    pub sessions: u64,
+}
+
+impl IntegrityReport {
+    /// Returns true if nothing was corrupted or reordered. Missing samples are not errors by
+    /// themselves, compare them with the drop counter of the driver.
+    pub fn is_clean(&self) -> bool {
+        self.corrupt == 0 && self.out_of_order == 0
+    }
+}
+
+/// Follows the sequence of the samples of a stream.
+#[derive(Debug, Clone, Default)]
+pub struct IntegrityChecker {
+    expected: Option<u32>,
+    report: IntegrityReport,
+}
+
+impl IntegrityChecker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Checks the next sample of the stream.
+    pub fn push(&mut self, sample: &Adxl345Sample) {
+        let n = match decode(sample) {
+            Some(n) => n,
+            None => {
+                self.report.corrupt += 1;
+                return;
+            }
+        };
+        self.report.valid += 1;
+
+        if let Some(expected) = self.expected {
+            // Distance forward in the sequence, half a period ahead or more is going back
+            let ahead = n.wrapping_sub(expected) & (INTEGRITY_PERIOD - 1);
+            if ahead >= INTEGRITY_PERIOD / 2 {
+                self.report.out_of_order += 1;
+                return;
+            }
+            self.report.missing += ahead as u64;
+        }
+        self.expected = Some((n + 1) & (INTEGRITY_PERIOD - 1));
+    }
+
+    /// Restarts the sequence, at a session header: the samples of the previous session left in
+    /// the buffers are discarded by the driver.
+    pub fn restart(&mut self) {
+        self.expected = None;
+        self.report.sessions += 1;
+    }
+
+    /// Returns the outcome so far.
+    pub fn report(&self) -> IntegrityReport {
+        self.report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::abi::ADXL345_MARKER_TAG;
+
+    /// Returns `records` followed by their batch CRC, as the driver ends a read.
+    fn with_crc(records: &[Adxl345Sample]) -> Vec<Adxl345Sample> {
+        let bytes: Vec<u8> = records.iter().flat_map(|r| r.to_ne_bytes()).collect();
+        let crc = crc32(&bytes);
+        let marker = |word: u32| Adxl345Sample { x: ADXL345_MARKER_TAG, y: ADXL345_MARKER_CRC, z: word as u16 as i16 };
+        records.iter().copied().chain([marker(crc), marker(crc >> 16)]).collect()
+    }
+
+    #[test]
+    fn pattern_round_trips() {
+        for n in [0, 1, 4095, 4096, 123_456, INTEGRITY_PERIOD - 1] {
+            assert_eq!(decode(&encode(n)), Some(n), "index {}", n);
+        }
+    }
+
+    #[test]
+    fn corrupted_samples_are_rejected() {
+        let sample = encode(1000);
+        for corrupt in [
+            Adxl345Sample { x: sample.x | 1, ..sample },
+            Adxl345Sample { y: sample.y.wrapping_add(4), ..sample },
+            Adxl345Sample { z: sample.z.wrapping_add(4), ..sample },
+            Adxl345Sample { x: sample.x.swap_bytes(), ..sample },
+        ] {
+            assert_eq!(decode(&corrupt), None, "{:?}", corrupt);
+        }
+    }
+
+    #[test]
+    fn consecutive_samples_differ_beyond_the_filter() {
+        // x moves by 2731 raw counts, or -1365 when it wraps
+        for n in 0..4096 {
+            let step = (encode(n + 1).x as i32 - encode(n).x as i32).abs();
+            assert!(step >= 1365 << 2, "index {}: x moves by {}", n, step);
+        }
+    }
+
+    #[test]
+    fn crc32_is_the_ieee_one() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn batch_crc_is_verified() {
+        let records: Vec<Adxl345Sample> = (0..10).map(encode).collect();
+        let mut batch = with_crc(&records);
+        assert_eq!(verify_batch(&batch), Some(true));
+        assert_eq!(verify_batch(&with_crc(&[])), Some(true));
+
+        batch[3].y ^= 4;
+        assert_eq!(verify_batch(&batch), Some(false));
+        assert_eq!(verify_batch(&records), None);
+        assert_eq!(verify_batch(&batch[..1]), None);
+    }
+
+    #[test]
+    fn checker_counts_gaps_reordering_and_corruption() {
+        let mut checker = IntegrityChecker::new();
+        for n in [5, 6, 9, 8, 10] {
+            checker.push(&encode(n));
+        }
+        checker.push(&Adxl345Sample { x: 1, y: 0, z: 0 });
+        let report = checker.report();
+        assert_eq!(report, IntegrityReport { valid: 5, corrupt: 1, missing: 2, out_of_order: 1, sessions: 0 });
+        assert!(!report.is_clean());
+
+        // A new session may start anywhere in the sequence
+        let mut checker = IntegrityChecker::new();
+        checker.push(&encode(100));
+        checker.restart();
+        checker.push(&encode(7));
+        checker.push(&encode(8));
+        let report = checker.report();
+        assert_eq!(report, IntegrityReport { valid: 3, corrupt: 0, missing: 0, out_of_order: 0, sessions: 1 });
+        assert!(report.is_clean());
+    }
+
+    #[test]
+    fn checker_follows_the_wrap_of_the_index() {
+        let mut checker = IntegrityChecker::new();
+        for n in [INTEGRITY_PERIOD - 2, INTEGRITY_PERIOD - 1, 0, 2] {
+            checker.push(&encode(n));
+        }
+        assert_eq!(checker.report().missing, 1);
+        assert!(checker.report().is_clean());
+    }
+}
@@ -0,0 +1,49 @@
+//! Userspace interface to the ADXL345 Rust Linux driver.
+//!
+//! The driver streams 6-byte records from its character device (usually `/dev/adxl345`):
+//! acceleration samples and markers (sync pulses, session headers, clipped samples, range changes,
+//! batch CRCs), and is configured through
+//! ioctls. This crate holds that protocol, so applications don't reimplement it:
+//!
+//! - [`Adxl345Device`]: opens the device, configures it and iterates over the decoded stream.
+//! - [`Scale`], [`Milligee`], [`Mps2`]: physical units of the raw counts of a sample.
+//! - [`StreamDecoder`]: decodes records read from the device or from a capture file.
+//! - `AsyncAdxl345Device` (feature `tokio`): the same stream awaited on the tokio reactor.
+//! - `dsp` (feature `dsp`): filters, RMS and peak detection, `fft` adds an amplitude spectrum.
+//! - `motion` (feature `dsp`): velocity and displacement with drift corrections.
+//! - [`integrity`]: end-to-end check of the stream against the self-checking emulator pattern,
+//!   and of the batch CRC.
+//! - [`abi`]: the raw layout and ioctl numbers, for tools that need to issue them directly.
+//!
+//! ```no_run
+//! use libadxl345::{Adxl345Device, Config, Record};
+//!
+//! let device = Adxl345Device::open("/dev/adxl345")?;
+//! device.configure(&Config::new().rate_mhz(100_000).range_g(4))?;
+//! for record in device.samples().take(100) {
+//!     if let Record::Sample(sample) = record? {
+//!         let a = sample.acceleration();
+//!         println!("{} {} {}", a.x, a.y, a.z);
+//!     }
+//! }
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+pub mod abi;
+#[cfg(feature = "tokio")]
+mod async_device;
+mod device;
+#[cfg(feature = "dsp")]
+pub mod dsp;
+pub mod integrity;
+#[cfg(feature = "dsp")]
+pub mod motion;
+mod stream;
+mod units;
+
+pub use abi::{Adxl345Header, Adxl345Sample, Adxl345SyncInfo, Adxl345Version};
+#[cfg(feature = "tokio")]
+pub use async_device::AsyncAdxl345Device;
+pub use device::{Adxl345Device, Clock, Config, ErrorPolicy, OutputFormat, Param, PollMode, Samples};
+pub use stream::{Record, StreamDecoder, RECORD_SIZE};
+pub use units::{Acceleration, Milligee, Mps2, Scale, STANDARD_GRAVITY};
@@ -0,0 +1,267 @@
+//! Integration of the acceleration into velocity and displacement, for motion tracking
+//! prototypes (dead reckoning).
+//!
+//! Integrating a MEMS accelerometer drifts fast: a bias of 1 mg left in the signal is a velocity
+//! error of 1 cm/s after 1 s and a displacement error of 5 m after 100 s. [`Integrator`] offers
+//! the usual corrections, to be chosen for the motion at hand:
+//! - an offset removed before integrating: gravity and the bias measured at rest
+//!   ([`Integrator::offset_mg`], e.g. from [`Integrator::calibrate`]);
+//! - a high-pass filter on the acceleration and on the velocity ([`Integrator::high_pass`]),
+//!   which removes gravity and the slow drift but also any motion slower than the cutoff: it
+//!   suits oscillations and short moves that end at rest, not a constant velocity;
+//! - zero-velocity updates ([`Integrator::zero_velocity`]): while the magnitude stays within a
+//!   threshold of 1 g, the sensor is taken as still and the velocity is reset to zero. It suits
+//!   stop-and-go motion, e.g. the foot of a walker or a machine axis between moves.
+//!
+//! Axes are the ones of the sensor: rotating it mixes gravity into the other axes, which only
+//! the high-pass filter partly copes with. Values are in m/s and m.
+//!
+//! Every sample is taken to last one period of the rate. The read filter of the driver drops the
+//! samples that barely change, which breaks that: integrate the stream of a driver built without
+//! it (`ADXL345_CAP_FILTER` clear in [`crate::Adxl345Device::capabilities`]).
+//!
+//! ```
+//! use libadxl345::motion::Integrator;
+//!
+//! // 1 m/s² along x for one second, at 100 Hz, with no correction
+//! let mut integrator = Integrator::new(100.0);
+//! let a = [1000.0 / libadxl345::STANDARD_GRAVITY, 0.0, 0.0];
+//! for _ in 0..100 {
+//!     integrator.push_mg(a);
+//! }
+//! let state = integrator.state();
+//! assert!((state.velocity[0] - 1.0).abs() < 0.02);
+//! assert!((state.displacement[0] - 0.5).abs() < 0.02);
+//! ```
+
+use crate::abi::Adxl345Sample;
+use crate::dsp::{Butterworth, Pass};
+use crate::units::STANDARD_GRAVITY;
+
+/// Order of the high-pass filters of [`Integrator::high_pass`].
+const HIGH_PASS_ORDER: usize = 2;
+
+/// Velocity and displacement integrated so far.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MotionState {
+    /// Velocity on each axis, in m/s.
+    pub velocity: [f64; 3],
+    /// Displacement on each axis since the start or the last reset, in m.
+    pub displacement: [f64; 3],
+    /// Whether the last sample was taken as still by the zero-velocity updates.
+    pub stationary: bool,
+}
+
+/// Zero-velocity update: the sensor is still after `hold` samples within `threshold_mg` of 1 g.
+#[derive(Debug, Clone, Copy)]
+struct ZeroVelocity {
+    threshold_mg: f64,
+    hold: usize,
+    still: usize,
+}
+
+/// Integrates the samples of a stream into velocity and displacement.
+#[derive(Debug, Clone)]
+pub struct Integrator {
+    dt: f64,
+    rate_hz: f64,
+    offset_mg: [f64; 3],
+    acceleration_filter: Option<[Butterworth; 3]>,
+    velocity_filter: Option<[Butterworth; 3]>,
+    zero_velocity: Option<ZeroVelocity>,
+    last_acceleration: [f64; 3],
+    state: MotionState,
+}
+
+impl Integrator {
+    /// Creates an integrator without any correction, for a stream at `rate_hz`.
+    pub fn new(rate_hz: f64) -> Self {
+        Integrator {
+            dt: 1.0 / rate_hz,
+            rate_hz,
+            offset_mg: [0.0; 3],
+            acceleration_filter: None,
+            velocity_filter: None,
+            zero_velocity: None,
+            last_acceleration: [0.0; 3],
+            state: MotionState::default(),
+        }
+    }
+
+    /// Subtracts `offset` (in mg) from every sample before integrating, e.g. gravity and the
+    /// bias of a sensor at rest.
+    pub fn offset_mg(mut self, offset: [f64; 3]) -> Self {
+        self.offset_mg = offset;
+        self
+    }
+
+    /// Sets the offset to the mean of `samples`, taken with the sensor at rest in the
+    /// orientation it keeps while moving. Does nothing without samples.
+    pub fn calibrate(mut self, samples: &[Adxl345Sample]) -> Self {
+        if samples.is_empty() {
+            return self;
+        }
+        let mut sum = [0.0; 3];
+        for sample in samples {
+            let mg = sample.to_mg();
+            (0..3).for_each(|i| sum[i] += mg[i]);
+        }
+        self.offset_mg = sum.map(|s| s / samples.len() as f64);
+        self
+    }
+
+    /// High-passes the acceleration and the velocity at `cutoff_hz`, below half the rate.
+    pub fn high_pass(mut self, cutoff_hz: f64) -> Self {
+        let filter = Butterworth::new(Pass::High, HIGH_PASS_ORDER, cutoff_hz, self.rate_hz);
+        self.acceleration_filter = Some([filter.clone(), filter.clone(), filter.clone()]);
+        self.velocity_filter = Some([filter.clone(), filter.clone(), filter]);
+        self
+    }
+
+    /// Resets the velocity to zero once the magnitude stayed within `threshold_mg` of 1 g for
+    /// `hold` samples (at least 1).
+    pub fn zero_velocity(mut self, threshold_mg: f64, hold: usize) -> Self {
+        self.zero_velocity = Some(ZeroVelocity { threshold_mg, hold: hold.max(1), still: 0 });
+        self
+    }
+
+    /// Integrates the next sample.
+    pub fn push(&mut self, sample: &Adxl345Sample) -> MotionState {
+        self.push_mg(sample.to_mg())
+    }
+
+    /// Integrates the next acceleration, in mg.
+    ///
+    /// Both integrals use the trapezoidal rule.
+    pub fn push_mg(&mut self, mg: [f64; 3]) -> MotionState {
+        let mut acceleration = [0, 1, 2].map(|i| (mg[i] - self.offset_mg[i]) / 1000.0 * STANDARD_GRAVITY);
+        if let Some(filters) = &mut self.acceleration_filter {
+            acceleration = [0, 1, 2].map(|i| filters[i].process(acceleration[i]));
+        }
+
+        let mut velocity = [0, 1, 2].map(|i| {
+            self.state.velocity[i] + (self.last_acceleration[i] + acceleration[i]) / 2.0 * self.dt
+        });
+        if let Some(filters) = &mut self.velocity_filter {
+            velocity = [0, 1, 2].map(|i| filters[i].process(velocity[i]));
+        }
+
+        self.state.stationary = match &mut self.zero_velocity {
+            Some(zupt) => {
+                let magnitude = mg.iter().map(|v| v * v).sum::<f64>().sqrt();
+                if (magnitude - 1000.0).abs() < zupt.threshold_mg {
+                    zupt.still += 1;
+                } else {
+                    zupt.still = 0;
+                }
+                zupt.still >= zupt.hold
+            }
+            None => false,
+        };
+        if self.state.stationary {
+            velocity = [0.0; 3];
+        }
+
+        let step = [0, 1, 2].map(|i| (self.state.velocity[i] + velocity[i]) / 2.0 * self.dt);
+        self.state.displacement = [0, 1, 2].map(|i| self.state.displacement[i] + step[i]);
+        self.state.velocity = velocity;
+        self.last_acceleration = acceleration;
+        self.state
+    }
+
+    /// Returns the velocity and displacement integrated so far.
+    pub fn state(&self) -> MotionState {
+        self.state
+    }
+
+    /// Starts again from rest at the origin, e.g. at a new session header. The corrections are
+    /// kept.
+    pub fn reset(&mut self) {
+        self.state = MotionState::default();
+        self.last_acceleration = [0.0; 3];
+        for filters in [&mut self.acceleration_filter, &mut self.velocity_filter].into_iter().flatten() {
+            filters.iter_mut().for_each(Butterworth::reset);
+        }
+        if let Some(zupt) = &mut self.zero_velocity {
+            zupt.still = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::abi::ADXL345_MG_PER_UNIT;
+
+    /// mg of 1 m/s².
+    const MG_PER_MPS2: f64 = 1000.0 / STANDARD_GRAVITY;
+
+    #[test]
+    fn offset_removes_gravity() {
+        let mut integrator = Integrator::new(100.0).offset_mg([0.0, 0.0, 1000.0]);
+        for _ in 0..1000 {
+            integrator.push_mg([0.0, 0.0, 1000.0]);
+        }
+        assert_eq!(integrator.state().velocity, [0.0; 3]);
+        assert_eq!(integrator.state().displacement, [0.0; 3]);
+    }
+
+    #[test]
+    fn calibrate_takes_the_mean_at_rest() {
+        let samples = [Adxl345Sample { x: 10, y: -20, z: 1020 }, Adxl345Sample { x: 30, y: -40, z: 1030 }];
+        let mut integrator = Integrator::new(100.0).calibrate(&samples);
+        for sample in samples.iter().cycle().take(100) {
+            integrator.push(sample);
+        }
+        // The two samples alternate around the mean, the velocity stays within one step of 0
+        let bound = 10.0 * ADXL345_MG_PER_UNIT / 1000.0 * STANDARD_GRAVITY / 100.0;
+        assert!(integrator.state().velocity.iter().all(|v| v.abs() <= bound));
+
+        let unchanged = Integrator::new(100.0).offset_mg([1.0, 2.0, 3.0]).calibrate(&[]);
+        assert_eq!(unchanged.offset_mg, [1.0, 2.0, 3.0]);
+    }
+
+    #[test]
+    fn zero_velocity_resets_after_the_hold() {
+        let mut integrator = Integrator::new(100.0).offset_mg([0.0, 0.0, 1000.0]).zero_velocity(20.0, 3);
+        // 5 m/s² along x for 0.5 s
+        for _ in 0..50 {
+            integrator.push_mg([5.0 * MG_PER_MPS2, 0.0, 1000.0]);
+        }
+        assert!(!integrator.state().stationary);
+        assert!(integrator.state().velocity[0] > 2.4);
+
+        // Still: the magnitude is 1 g, the velocity is kept until the third sample
+        let states: Vec<MotionState> = (0..3).map(|_| integrator.push_mg([0.0, 0.0, 1000.0])).collect();
+        assert!(!states[1].stationary && states[1].velocity[0] > 2.4);
+        assert!(states[2].stationary);
+        assert_eq!(states[2].velocity, [0.0; 3]);
+    }
+
+    #[test]
+    fn high_pass_bounds_the_drift_of_a_bias() {
+        let mut plain = Integrator::new(100.0);
+        let mut filtered = Integrator::new(100.0).high_pass(0.5);
+        for _ in 0..6000 {
+            plain.push_mg([10.0, 0.0, 0.0]);
+            filtered.push_mg([10.0, 0.0, 0.0]);
+        }
+        assert!(plain.state().velocity[0] > 5.0);
+        assert!(filtered.state().velocity[0].abs() < 0.01);
+        assert!(filtered.state().displacement[0].abs() < plain.state().displacement[0] / 1000.0);
+    }
+
+    #[test]
+    fn reset_starts_again_from_rest() {
+        let mut integrator = Integrator::new(100.0).zero_velocity(20.0, 5);
+        for _ in 0..10 {
+            integrator.push_mg([MG_PER_MPS2, 0.0, 0.0]);
+        }
+        integrator.reset();
+        assert_eq!(integrator.state(), MotionState::default());
+
+        // Same as a new integrator, the first step integrates from zero acceleration
+        let state = integrator.push_mg([MG_PER_MPS2, 0.0, 0.0]);
+        assert_eq!(state, Integrator::new(100.0).push_mg([MG_PER_MPS2, 0.0, 0.0]));
+    }
+}
@@ -0,0 +1,268 @@
+//! Decoding of the record stream: samples, sync markers, session headers, clip, range, CRC,
+//! error, tap and burst markers.
+
+use std::mem;
+
+use crate::abi::*;
+use crate::units::{Acceleration, Scale};
+
+/// Size of a record in the stream, in bytes.
+pub const RECORD_SIZE: usize = mem::size_of::<Adxl345Sample>();
+
+/// A decoded element of the stream.
+#[derive(Debug, Clone, Copy)]
+pub enum Record {
+    /// An acceleration sample, in shifted LSBs (see [`ADXL345_MG_PER_UNIT`]).
+    Sample(Adxl345Sample),
+    /// A sync pulse arrived before the next sample, with its sequence number.
+    Sync(u16),
+    /// A new session starts, described by its header.
+    Header(Adxl345Header),
+    /// The next sample sits on a rail of the range, with the mask of the clipped axes (bit 0 x,
+    /// bit 1 y, bit 2 z).
+    Clip(u8),
+    /// Auto-ranging changed the range, in g, for the samples that follow. The scale is unchanged.
+    Range(u16),
+    /// The batch returned by a read ends here, with the CRC32 of the records before it (see
+    /// [`crate::integrity::verify_batch`]).
+    Crc(u32),
+    /// A bus error was met by a read in best-effort mode, with its errno: samples may be missing
+    /// here (see [`crate::Adxl345Device::set_error_policy`]).
+    Error(i32),
+    /// The device detected a tap before the next sample (see
+    /// [`crate::Adxl345Device::set_tap`]), with the mask of the axes involved (bit 0 x, bit 1 y,
+    /// bit 2 z). The second tap of a double tap is reported as both.
+    Tap { single: bool, double: bool, axes: u8 },
+    /// A burst ends here, with its number of samples saturated at 65535 (see
+    /// [`crate::Adxl345Device::set_burst`]). The burst started at the previous header.
+    BurstEnd(u16),
+    /// One or more events (tap, threshold, overrun, motion) were seen here, with their
+    /// correlation ID: [`crate::Adxl345Device::event`] returns which ones and when. After an
+    /// overrun it comes before the first sample following the gap.
+    Event(u16),
+    /// The next sample reached the limit of the limit stage of the read filter (see
+    /// [`crate::Adxl345Device::set_pipeline`]) on the axes of the mask (bit 0 x, bit 1 y, bit 2
+    /// z).
+    Limit(u8),
+    /// A marker this version of the library doesn't know.
+    Unknown { kind: i16, value: i16 },
+}
+
+impl Adxl345Sample {
+    /// Builds a record from its bytes as returned by the device, native endian.
+    pub fn from_ne_bytes(bytes: [u8; RECORD_SIZE]) -> Self {
+        let field = |i: usize| i16::from_ne_bytes([bytes[2 * i], bytes[2 * i + 1]]);
+        Adxl345Sample { x: field(0), y: field(1), z: field(2) }
+    }
+
+    /// Builds a record from its bytes as saved in a capture, little endian.
+    pub fn from_le_bytes(bytes: [u8; RECORD_SIZE]) -> Self {
+        let field = |i: usize| i16::from_le_bytes([bytes[2 * i], bytes[2 * i + 1]]);
+        Adxl345Sample { x: field(0), y: field(1), z: field(2) }
+    }
+
+    /// Returns the bytes of the record as returned by the device, native endian.
+    pub fn to_ne_bytes(&self) -> [u8; RECORD_SIZE] {
+        let mut bytes = [0; RECORD_SIZE];
+        for (i, field) in [self.x, self.y, self.z].into_iter().enumerate() {
+            bytes[2 * i..2 * i + 2].copy_from_slice(&field.to_ne_bytes());
+        }
+        bytes
+    }
+
+    /// Returns true if the record is a marker rather than a sample.
+    pub fn is_marker(&self) -> bool {
+        self.x == ADXL345_MARKER_TAG
+    }
+
+    /// Returns the acceleration in mg on the three axes, as bare numbers for computations.
+    pub fn to_mg(&self) -> [f64; 3] {
+        [self.x, self.y, self.z].map(|v| v as f64 * ADXL345_MG_PER_UNIT)
+    }
+
+    /// Returns the acceleration, in full resolution scale.
+    ///
+    /// Use [`StreamDecoder::scale`] with [`Scale::acceleration`] to follow the scale of the session.
+    pub fn acceleration(&self) -> Acceleration {
+        Scale::FULL_RESOLUTION.acceleration(self)
+    }
+}
+
+impl Adxl345ScaledSample {
+    /// Returns true if the record is a marker rather than a sample.
+    pub fn is_marker(&self) -> bool {
+        self.x == ADXL345_SCALED_MARKER_TAG
+    }
+
+    /// Returns a marker as the raw record carrying it, to be decoded by a [`StreamDecoder`];
+    /// `None` for a sample.
+    pub fn to_marker(&self) -> Option<Adxl345Sample> {
+        self.is_marker().then_some(Adxl345Sample { x: ADXL345_MARKER_TAG, y: self.y as i16, z: self.z as i16 })
+    }
+}
+
+impl Adxl345StampedSample {
+    /// Returns the raw record, a sample or a marker, without its timestamp.
+    pub fn record(&self) -> Adxl345Sample {
+        Adxl345Sample { x: self.x, y: self.y, z: self.z }
+    }
+}
+
+/// Turns raw records into [`Record`]s.
+///
+/// A header spans several records, so the decoder keeps the words seen so far and yields the
+/// header once it is complete, and the same for the two words of a batch CRC. Any stream version is accepted: headers longer than the version 1
+/// layout are decoded up to the fields this library knows.
+#[derive(Debug, Default)]
+pub struct StreamDecoder {
+    header_words: Vec<u16>,
+    crc_low: Option<u16>,
+    scale: Scale,
+}
+
+impl StreamDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the scale of the samples of the current session, taken from its header.
+    pub fn scale(&self) -> Scale {
+        self.scale
+    }
+
+    /// Decodes the next raw record, returns `None` while a header or a CRC is being collected.
+    pub fn push(&mut self, raw: Adxl345Sample) -> Option<Record> {
+        if !raw.is_marker() {
+            return Some(Record::Sample(raw));
+        }
+        match raw.y {
+            ADXL345_MARKER_SYNC => Some(Record::Sync(raw.z as u16)),
+            ADXL345_MARKER_HEADER => {
+                self.header_words.push(raw.z as u16);
+                let header = Adxl345Header::decode(&self.header_words)?;
+                self.header_words.clear();
+                self.scale = Scale::from_header(&header);
+                Some(Record::Header(header))
+            }
+            ADXL345_MARKER_CLIP => Some(Record::Clip(raw.z as u8)),
+            ADXL345_MARKER_RANGE => Some(Record::Range(raw.z as u16)),
+            ADXL345_MARKER_CRC => match self.crc_low.take() {
+                Some(low) => Some(Record::Crc(low as u32 | (raw.z as u16 as u32) << 16)),
+                None => {
+                    self.crc_low = Some(raw.z as u16);
+                    None
+                }
+            },
+            ADXL345_MARKER_ERROR => Some(Record::Error(raw.z as i32)),
+            ADXL345_MARKER_TAP => Some(Record::Tap {
+                single: raw.z & ADXL345_TAP_MARKER_SINGLE != 0,
+                double: raw.z & ADXL345_TAP_MARKER_DOUBLE != 0,
+                axes: (raw.z & 7) as u8,
+            }),
+            ADXL345_MARKER_BURST => Some(Record::BurstEnd(raw.z as u16)),
+            ADXL345_MARKER_EVENT => Some(Record::Event(raw.z as u16)),
+            ADXL345_MARKER_LIMIT => Some(Record::Limit(raw.z as u8)),
+            kind => Some(Record::Unknown { kind, value: raw.z }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn marker(kind: i16, value: i16) -> Adxl345Sample {
+        Adxl345Sample { x: ADXL345_MARKER_TAG, y: kind, z: value }
+    }
+
+    /// Returns the header markers of a version 1 session.
+    fn header(range_g: u16, rate_mhz: u32, start_ns: u64, filter: i16) -> Vec<Adxl345Sample> {
+        let mut words = vec![1, ADXL345_HEADER_WORDS as u16, range_g, 0, rate_mhz as u16, (rate_mhz >> 16) as u16];
+        words.extend((0..4).map(|i| (start_ns >> (16 * i)) as u16));
+        words.push(filter as u16);
+        words.into_iter().map(|w| marker(ADXL345_MARKER_HEADER, w as i16)).collect()
+    }
+
+    #[test]
+    fn record_bytes_round_trip() {
+        let sample = Adxl345Sample { x: -2, y: 0x1234, z: i16::MIN };
+        let back = Adxl345Sample::from_ne_bytes(sample.to_ne_bytes());
+        assert_eq!((back.x, back.y, back.z), (sample.x, sample.y, sample.z));
+
+        let le = Adxl345Sample::from_le_bytes([0x34, 0x12, 0xFE, 0xFF, 0x00, 0x80]);
+        assert_eq!((le.x, le.y, le.z), (0x1234, -2, i16::MIN));
+    }
+
+    #[test]
+    fn samples_pass_through() {
+        let mut decoder = StreamDecoder::new();
+        match decoder.push(Adxl345Sample { x: 1, y: -2, z: 1024 }) {
+            Some(Record::Sample(s)) => assert_eq!((s.x, s.y, s.z), (1, -2, 1024)),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn header_is_yielded_once_complete() {
+        let mut decoder = StreamDecoder::new();
+        let words = header(4, 3_200_000, 0x0123_4567_89AB_CDEF, -1);
+        for word in &words[..words.len() - 1] {
+            assert!(decoder.push(*word).is_none());
+        }
+        match decoder.push(words[words.len() - 1]) {
+            Some(Record::Header(h)) => {
+                assert_eq!((h.version, h.range_g, h.clock, h.rate_mhz), (1, 4, 0, 3_200_000));
+                assert_eq!((h.start_ns, h.filter), (0x0123_4567_89AB_CDEF, -1));
+            }
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(decoder.scale(), Scale::FULL_RESOLUTION);
+
+        // The next header starts from scratch
+        let words = header(16, 100_000, 0, 50);
+        let last = words.iter().filter_map(|w| decoder.push(*w)).last();
+        assert!(matches!(last, Some(Record::Header(h)) if h.range_g == 16 && h.filter == 50));
+    }
+
+    #[test]
+    fn crc_is_assembled_from_two_words() {
+        let mut decoder = StreamDecoder::new();
+        assert!(decoder.push(marker(ADXL345_MARKER_CRC, 0x3926)).is_none());
+        assert!(matches!(decoder.push(marker(ADXL345_MARKER_CRC, 0xCBF4u16 as i16)), Some(Record::Crc(0xCBF4_3926))));
+    }
+
+    #[test]
+    fn markers_are_decoded() {
+        let mut decoder = StreamDecoder::new();
+        let tap = ADXL345_TAP_MARKER_SINGLE | ADXL345_TAP_MARKER_DOUBLE | 0b101;
+        let records: Vec<Record> = [
+            marker(ADXL345_MARKER_SYNC, -1),
+            marker(ADXL345_MARKER_CLIP, 0b110),
+            marker(ADXL345_MARKER_RANGE, 8),
+            marker(ADXL345_MARKER_ERROR, 5),
+            marker(ADXL345_MARKER_TAP, tap),
+            marker(ADXL345_MARKER_BURST, -1),
+            marker(ADXL345_MARKER_EVENT, 42),
+            marker(ADXL345_MARKER_LIMIT, 0b001),
+            marker(99, 7),
+        ]
+        .into_iter()
+        .filter_map(|raw| decoder.push(raw))
+        .collect();
+        let expected = "[Sync(65535), Clip(6), Range(8), Error(5), Tap { single: true, double: true, axes: 5 }, \
+                        BurstEnd(65535), Event(42), Limit(1), Unknown { kind: 99, value: 7 }]";
+        assert_eq!(format!("{:?}", records), expected);
+    }
+
+    #[test]
+    fn scaled_and_stamped_records_carry_the_raw_one() {
+        let scaled = Adxl345ScaledSample { x: ADXL345_SCALED_MARKER_TAG, y: ADXL345_MARKER_SYNC as i32, z: 3 };
+        let raw = scaled.to_marker().unwrap();
+        assert!(matches!(StreamDecoder::new().push(raw), Some(Record::Sync(3))));
+        assert!(Adxl345ScaledSample { x: 1_000_000, y: 0, z: 0 }.to_marker().is_none());
+
+        let stamped = Adxl345StampedSample { x: 4, y: 5, z: 6, reserved: 0, timestamp_ns: 7 };
+        let record = stamped.record();
+        assert_eq!((record.x, record.y, record.z), (4, 5, 6));
+    }
+}
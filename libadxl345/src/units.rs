@@ -0,0 +1,196 @@
+//! Physical units of the samples.
+//!
+//! Samples hold raw counts of the device, [`Scale`] turns them into [`Milligee`], which convert to
+//! [`Mps2`]. Keeping the units in the types means counts, mg and m/s² can't be mixed by mistake.
+
+use std::fmt;
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
+use crate::abi::{Adxl345Header, Adxl345Sample, ADXL345_MG_PER_UNIT};
+
+/// Standard gravity, in m/s².
+pub const STANDARD_GRAVITY: f64 = 9.806_65;
+
+/// Defines an acceleration newtype over `f64` with the arithmetic that keeps its unit.
+macro_rules! unit {
+    ($(#[$doc:meta])* $name:ident, $suffix:expr) => {
+        $(#[$doc])*
+        #[derive(Debug, Clone, Copy, Default, PartialEq, PartialOrd)]
+        pub struct $name(pub f64);
+
+        impl $name {
+            /// Returns the absolute value.
+            pub fn abs(self) -> Self {
+                $name(self.0.abs())
+            }
+        }
+
+        impl Add for $name {
+            type Output = Self;
+            fn add(self, rhs: Self) -> Self {
+                $name(self.0 + rhs.0)
+            }
+        }
+
+        impl Sub for $name {
+            type Output = Self;
+            fn sub(self, rhs: Self) -> Self {
+                $name(self.0 - rhs.0)
+            }
+        }
+
+        impl Neg for $name {
+            type Output = Self;
+            fn neg(self) -> Self {
+                $name(-self.0)
+            }
+        }
+
+        impl Mul<f64> for $name {
+            type Output = Self;
+            fn mul(self, rhs: f64) -> Self {
+                $name(self.0 * rhs)
+            }
+        }
+
+        impl Div<f64> for $name {
+            type Output = Self;
+            fn div(self, rhs: f64) -> Self {
+                $name(self.0 / rhs)
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                fmt::Display::fmt(&self.0, f)?;
+                f.write_str($suffix)
+            }
+        }
+    };
+}
+
+unit!(
+    /// An acceleration in thousandths of standard gravity.
+    Milligee,
+    " mg"
+);
+
+unit!(
+    /// An acceleration in metres per second squared.
+    Mps2,
+    " m/s\u{b2}"
+);
+
+impl From<Milligee> for Mps2 {
+    fn from(mg: Milligee) -> Self {
+        Mps2(mg.0 / 1000.0 * STANDARD_GRAVITY)
+    }
+}
+
+impl From<Mps2> for Milligee {
+    fn from(mps2: Mps2) -> Self {
+        Milligee(mps2.0 / STANDARD_GRAVITY * 1000.0)
+    }
+}
+
+/// Conversion factor from raw counts to mg.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Scale {
+    mg_per_count: f64,
+}
+
+impl Scale {
+    /// Full resolution, the only mode of the driver: 3.9 mg per LSB, whatever the range.
+    pub const FULL_RESOLUTION: Scale = Scale { mg_per_count: ADXL345_MG_PER_UNIT };
+
+    /// Returns the scale of the session described by `header`.
+    ///
+    /// Version 1 streams are always full resolution, the range changes the limits of the
+    /// samples but not their scale.
+    pub fn from_header(_header: &Adxl345Header) -> Self {
+        Scale::FULL_RESOLUTION
+    }
+
+    /// Returns the mg of one count.
+    pub fn mg_per_count(self) -> f64 {
+        self.mg_per_count
+    }
+
+    /// Converts a raw count.
+    pub fn to_mg(self, count: i16) -> Milligee {
+        Milligee(count as f64 * self.mg_per_count)
+    }
+
+    /// Converts a sample.
+    pub fn acceleration(self, sample: &Adxl345Sample) -> Acceleration {
+        Acceleration { x: self.to_mg(sample.x), y: self.to_mg(sample.y), z: self.to_mg(sample.z) }
+    }
+}
+
+impl Default for Scale {
+    fn default() -> Self {
+        Scale::FULL_RESOLUTION
+    }
+}
+
+/// An acceleration on the three axes.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Acceleration {
+    pub x: Milligee,
+    pub y: Milligee,
+    pub z: Milligee,
+}
+
+impl Acceleration {
+    /// Returns the norm of the vector.
+    pub fn magnitude(&self) -> Milligee {
+        Milligee((self.x.0 * self.x.0 + self.y.0 * self.y.0 + self.z.0 * self.z.0).sqrt())
+    }
+
+    /// Returns the axes as an array, x first.
+    pub fn to_array(self) -> [Milligee; 3] {
+        [self.x, self.y, self.z]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn milligee_and_mps2_convert_both_ways() {
+        assert!((Mps2::from(Milligee(1000.0)).0 - STANDARD_GRAVITY).abs() < 1e-12);
+        assert!((Milligee::from(Mps2(STANDARD_GRAVITY)).0 - 1000.0).abs() < 1e-9);
+        let mg = Milligee(-123.4);
+        assert!((Milligee::from(Mps2::from(mg)).0 - mg.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn arithmetic_keeps_the_unit() {
+        assert_eq!(Milligee(3.0) + Milligee(2.0), Milligee(5.0));
+        assert_eq!(Milligee(3.0) - Milligee(5.0), Milligee(-2.0));
+        assert_eq!(-Milligee(3.0), Milligee(-3.0));
+        assert_eq!(Milligee(3.0) * 2.0, Milligee(6.0));
+        assert_eq!(Milligee(3.0) / 2.0, Milligee(1.5));
+        assert_eq!(Milligee(-3.0).abs(), Milligee(3.0));
+        assert_eq!(Milligee(1.5).to_string(), "1.5 mg");
+        assert_eq!(Mps2(2.0).to_string(), "2 m/s\u{b2}");
+    }
+
+    #[test]
+    fn full_resolution_scale_matches_the_driver() {
+        let scale = Scale::default();
+        assert_eq!(scale, Scale::FULL_RESOLUTION);
+        assert_eq!(scale.mg_per_count(), 0.975);
+        // 975 µg per count, as ADXL345_UG_PER_UNIT in the driver
+        assert!((scale.to_mg(1024).0 - 998.4).abs() < 1e-9);
+        assert!((scale.to_mg(-4).0 + 3.9).abs() < 1e-12);
+    }
+
+    #[test]
+    fn acceleration_of_a_sample() {
+        let acceleration = Scale::FULL_RESOLUTION.acceleration(&Adxl345Sample { x: 120, y: 160, z: 0 });
+        assert_eq!(acceleration.to_array(), [Milligee(117.0), Milligee(156.0), Milligee(0.0)]);
+        assert!((acceleration.magnitude().0 - 195.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,169 @@
+//! Python bindings of libadxl345.
+//!
+//! Samples are returned in batches as NumPy arrays, one row per sample and one column per axis, so
+//! analysis pipelines get them without a Python loop per sample. Markers (sync pulses, session
+//! headers) are consumed by the decoder: the last header is available as a dictionary.
+
+use std::collections::HashMap;
+
+use numpy::ndarray::Array2;
+use numpy::{IntoPyArray, PyArray2};
+use pyo3::exceptions::{PyIOError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+use libadxl345::abi::{ADXL345_CRC_WORDS, ADXL345_MG_PER_UNIT};
+use libadxl345::integrity::verify_batch;
+use libadxl345::{Adxl345Device, Adxl345Header, Adxl345Sample, Param, Record, StreamDecoder};
+
+/// Records read at most by a batch when the caller doesn't choose.
+const DEFAULT_BATCH: usize = 64;
+
+/// An open ADXL345 character device.
+///
+/// ``Device("/dev/adxl345")`` opens it; iterating over it yields batches of samples until an error.
+#[pyclass(module = "adxl345")]
+struct Device {
+    device: Adxl345Device,
+    decoder: StreamDecoder,
+    header: Option<Adxl345Header>,
+    syncs: u64,
+    batch_crc: bool,
+}
+
+impl Device {
+    /// Reads until at least one sample is decoded, returns at most `max_samples` of them.
+    fn read(&mut self, py: Python<'_>, max_samples: usize) -> PyResult<Vec<Adxl345Sample>> {
+        // Room for the batch CRC, if enabled, on top of the samples
+        let crc_words = if self.batch_crc { ADXL345_CRC_WORDS } else { 0 };
+        let mut buf = vec![Adxl345Sample::default(); max_samples.max(1) + crc_words];
+        loop {
+            // The read blocks, let other Python threads run meanwhile
+            let device = &self.device;
+            let n = py.allow_threads(|| device.read_records(&mut buf))?;
+            if verify_batch(&buf[..n]) == Some(false) {
+                return Err(PyIOError::new_err("batch CRC mismatch"));
+            }
+
+            let mut samples = Vec::with_capacity(n);
+            for &raw in &buf[..n] {
+                match self.decoder.push(raw) {
+                    Some(Record::Sample(sample)) => samples.push(sample),
+                    Some(Record::Sync(_)) => self.syncs += 1,
+                    Some(Record::Header(header)) => self.header = Some(header),
+                    Some(Record::Clip(_)) | Some(Record::Range(_)) | Some(Record::Crc(_)) | Some(Record::Error(_)) | Some(Record::Tap { .. }) | Some(Record::BurstEnd(_)) | Some(Record::Event(_)) | Some(Record::Limit(_)) | Some(Record::Unknown { .. }) | None => {}
+                }
+            }
+            if !samples.is_empty() {
+                return Ok(samples);
+            }
+            py.check_signals()?;
+        }
+    }
+}
+
+#[pymethods]
+impl Device {
+    #[new]
+    #[pyo3(signature = (path = "/dev/adxl345", nonblocking = false))]
+    fn new(path: &str, nonblocking: bool) -> PyResult<Self> {
+        let device = if nonblocking { Adxl345Device::open_nonblocking(path)? } else { Adxl345Device::open(path)? };
+        Ok(Device { device, decoder: StreamDecoder::new(), header: None, syncs: 0, batch_crc: false })
+    }
+
+    /// Applies parameters in human units, e.g. ``configure(rate=100000, range=4)``.
+    ///
+    /// Returns the achieved value of each parameter, after the driver rounded it.
+    #[pyo3(signature = (**params))]
+    fn configure(&self, params: Option<&Bound<'_, PyDict>>) -> PyResult<HashMap<String, u32>> {
+        let mut achieved = HashMap::new();
+        for (name, value) in params.into_iter().flatten() {
+            let name: String = name.extract()?;
+            let param = Param::from_name(&name)
+                .ok_or_else(|| PyValueError::new_err(format!("unknown parameter {}", name)))?;
+            achieved.insert(name, self.device.set_param(param, value.extract()?)?);
+        }
+        Ok(achieved)
+    }
+
+    /// Returns a parameter in human units.
+    fn param(&self, name: &str) -> PyResult<u32> {
+        let param = Param::from_name(name).ok_or_else(|| PyValueError::new_err(format!("unknown parameter {}", name)))?;
+        Ok(self.device.param(param)?)
+    }
+
+    /// Reads the next batch, an ``(n, 3)`` int16 array of at most ``max_samples`` samples.
+    ///
+    /// Values are in the driver units, ``MG_PER_UNIT`` converts them to mg.
+    #[pyo3(signature = (max_samples = DEFAULT_BATCH))]
+    fn read_batch<'py>(&mut self, py: Python<'py>, max_samples: usize) -> PyResult<Bound<'py, PyArray2<i16>>> {
+        let samples = self.read(py, max_samples)?;
+        let rows = samples.iter().flat_map(|s| [s.x, s.y, s.z]).collect();
+        let array = Array2::from_shape_vec((samples.len(), 3), rows).expect("three values per sample");
+        Ok(array.into_pyarray(py))
+    }
+
+    /// Discards the buffered samples.
+    fn flush(&self) -> PyResult<()> {
+        Ok(self.device.flush()?)
+    }
+
+    /// Starts a measurement session, a no-op if one is running.
+    fn start(&self) -> PyResult<()> {
+        Ok(self.device.start()?)
+    }
+
+    /// Stops the measurement session.
+    fn stop(&self) -> PyResult<()> {
+        Ok(self.device.stop()?)
+    }
+
+    /// Makes the sessions started from now on begin with a header.
+    fn set_header(&self, enabled: bool) -> PyResult<()> {
+        Ok(self.device.set_header(enabled)?)
+    }
+
+    /// Makes every read end with a CRC of its records, checked by this object.
+    fn set_batch_crc(&mut self, enabled: bool) -> PyResult<()> {
+        self.device.set_batch_crc(enabled)?;
+        self.batch_crc = enabled;
+        Ok(())
+    }
+
+    /// The last session header read, as a dictionary, ``None`` before the first one.
+    #[getter]
+    fn header<'py>(&self, py: Python<'py>) -> PyResult<Option<Bound<'py, PyDict>>> {
+        let Some(header) = self.header else {
+            return Ok(None);
+        };
+        let dict = PyDict::new(py);
+        dict.set_item("version", header.version)?;
+        dict.set_item("range_g", header.range_g)?;
+        dict.set_item("clock", header.clock)?;
+        dict.set_item("rate_mhz", header.rate_mhz)?;
+        dict.set_item("start_ns", header.start_ns)?;
+        dict.set_item("filter", header.filter)?;
+        Ok(Some(dict))
+    }
+
+    /// Sync pulses read so far.
+    #[getter]
+    fn syncs(&self) -> u64 {
+        self.syncs
+    }
+
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__<'py>(&mut self, py: Python<'py>) -> PyResult<Bound<'py, PyArray2<i16>>> {
+        self.read_batch(py, DEFAULT_BATCH)
+    }
+}
+
+#[pymodule]
+fn adxl345(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<Device>()?;
+    m.add("MG_PER_UNIT", ADXL345_MG_PER_UNIT)?;
+    Ok(())
+}
@@ -41,6 +41,33 @@
 #include <linux/i2c.h>
 #include <linux/module.h>
 
+// Added for timestamp support
+#include <linux/timekeeping.h>
+
+// Added for gpio consumer support
+#include <linux/gpio.h>
+
+// Added for debugfs support
+#include <linux/debugfs.h>
+
+// Added for uevent support
+#include <linux/kobject.h>
+
+// Added for configfs support
+#include <linux/configfs.h>
+
+// Added for the batch CRC
+#include <linux/crc32.h>
+
+// Added for thermal zone support
+#include <linux/thermal.h>
+
+// Added for SPI support
+#include <linux/spi/spi.h>
+
+// Added for the GPIOs of device tree nodes
+#include <linux/of_gpio.h>
+
 /* `bindgen` gets confused at certain things. */
 const gfp_t BINDINGS_GFP_KERNEL = GFP_KERNEL;
 const gfp_t BINDINGS___GFP_ZERO = __GFP_ZERO;
@@ -50,3 +77,6 @@ const __poll_t BINDINGS_EPOLLERR = EPOLLERR;
 const __poll_t BINDINGS_EPOLLHUP = EPOLLHUP;
 
 const loff_t BINDINGS_MAX_LFS_FILESIZE = MAX_LFS_FILESIZE;
+
+// Added for fasync support
+#include <linux/signal.h>
@@ -31,6 +31,7 @@
 #include <linux/irqdomain.h>
 #include <linux/irq.h>
 #include <linux/mutex.h>
+#include <linux/rtmutex.h>
 #include <linux/netdevice.h>
 #include <linux/of_device.h>
 #include <linux/platform_device.h>
@@ -45,6 +46,13 @@
 #include <linux/i2c.h>
 #include <linux/module.h>
 
+// Added for timestamp support
+#include <linux/timekeeping.h>
+#include <linux/jiffies.h>
+
+// Added for gpio consumer support
+#include <linux/gpio.h>
+
 __noreturn void rust_helper_BUG(void)
 {
 	BUG();
@@ -578,12 +586,24 @@ const struct of_device_id *rust_helper_of_match_device(
 }
 EXPORT_SYMBOL_GPL(rust_helper_of_match_device);
 
+void rust_helper_rt_mutex_lock(struct rt_mutex *lock)
+{
+	rt_mutex_lock(lock);
+}
+EXPORT_SYMBOL_GPL(rust_helper_rt_mutex_lock);
+
 void rust_helper_init_completion(struct completion *c)
 {
 	init_completion(c);
 }
 EXPORT_SYMBOL_GPL(rust_helper_init_completion);
 
+void rust_helper_reinit_completion(struct completion *c)
+{
+	reinit_completion(c);
+}
+EXPORT_SYMBOL_GPL(rust_helper_reinit_completion);
+
 struct sk_buff *rust_helper_skb_get(struct sk_buff *skb)
 {
 	return skb_get(skb);
@@ -633,6 +653,14 @@ void rust_helper___INIT_WORK_WITH_KEY(struct work_struct *work,
 }
 EXPORT_SYMBOL_GPL(rust_helper___INIT_WORK_WITH_KEY);
 
+void rust_helper___INIT_DELAYED_WORK_WITH_KEY(struct delayed_work *dwork,
+		work_func_t func, bool on_stack, struct lock_class_key *key)
+{
+	__INIT_WORK_WITH_KEY(&dwork->work, func, on_stack, key);
+	__init_timer(&dwork->timer, delayed_work_timer_fn, TIMER_IRQSAFE);
+}
+EXPORT_SYMBOL_GPL(rust_helper___INIT_DELAYED_WORK_WITH_KEY);
+
 struct dentry *rust_helper_dget(struct dentry *dentry)
 {
 	return dget(dentry);
@@ -673,9 +701,9 @@ void rust_helper_i2c_set_clientdata(struct i2c_client *client, void *data) {
 EXPORT_SYMBOL_GPL(rust_helper_i2c_set_clientdata);
 
 // Helper for i2c_add_adapter
-void rust_helper_i2c_add_adapter(struct i2c_adapter *adapter)
+int rust_helper_i2c_add_adapter(struct i2c_adapter *adapter)
 {
-    i2c_add_adapter(adapter);
+    return i2c_add_adapter(adapter);
 }
 EXPORT_SYMBOL_GPL(rust_helper_i2c_add_adapter);
 
@@ -814,6 +842,74 @@ EXPORT_SYMBOL_GPL(rust_helper_i2c_smbus_read_i2c_block_data);
 
 //------------ END HELPERS FOR I2C.H -----------------
 
+//------------ START HELPERS FOR TIMEKEEPING.H -----------------
+
+// Helper for ktime_get_ns
+u64 rust_helper_ktime_get_ns(void)
+{
+    return ktime_get_ns();
+}
+EXPORT_SYMBOL_GPL(rust_helper_ktime_get_ns);
+
+// Helper for ktime_get_boottime_ns
+u64 rust_helper_ktime_get_boottime_ns(void)
+{
+    return ktime_get_boottime_ns();
+}
+EXPORT_SYMBOL_GPL(rust_helper_ktime_get_boottime_ns);
+
+// Helper for ktime_get_real_ns
+u64 rust_helper_ktime_get_real_ns(void)
+{
+    return ktime_get_real_ns();
+}
+EXPORT_SYMBOL_GPL(rust_helper_ktime_get_real_ns);
+
+// Helper for msecs_to_jiffies
+unsigned long rust_helper_msecs_to_jiffies(const unsigned int m)
+{
+    return msecs_to_jiffies(m);
+}
+EXPORT_SYMBOL_GPL(rust_helper_msecs_to_jiffies);
+
+//------------ END HELPERS FOR TIMEKEEPING.H -----------------
+
+//------------ START HELPERS FOR GPIO.H -----------------
+
+// Helper for gpio_to_irq
+int rust_helper_gpio_to_irq(unsigned int gpio)
+{
+    return gpio_to_irq(gpio);
+}
+EXPORT_SYMBOL_GPL(rust_helper_gpio_to_irq);
+
+// Helper for gpio_get_value
+int rust_helper_gpio_get_value(unsigned int gpio)
+{
+    return gpio_get_value(gpio);
+}
+EXPORT_SYMBOL_GPL(rust_helper_gpio_get_value);
+
+// Helper for gpio_set_value_cansleep
+void rust_helper_gpio_set_value_cansleep(unsigned int gpio, int value)
+{
+    gpio_set_value_cansleep(gpio, value);
+}
+EXPORT_SYMBOL_GPL(rust_helper_gpio_set_value_cansleep);
+
+//------------ END HELPERS FOR GPIO.H -----------------
+
+//------------ START HELPERS FOR MODULE.H -----------------
+
+// Helper for try_module_get
+bool rust_helper_try_module_get(struct module *module)
+{
+    return try_module_get(module);
+}
+EXPORT_SYMBOL_GPL(rust_helper_try_module_get);
+
+//------------ END HELPERS FOR MODULE.H -----------------
+
 
 
 
@@ -0,0 +1,160 @@
+// debugfs.rs
+
+//! Debugfs support.
+//!
+//! This module provides a minimal abstraction over debugfs, enough for a driver to publish its
+//! debug knobs and counters: directories, integer/boolean files backed by static atomics, and
+//! files implemented through [`file::Operations`].
+//!
+//! C header: [`include/linux/debugfs.h`](../../../../include/linux/debugfs.h)
+
+use crate::bindings;
+use crate::error::{from_kernel_err_ptr, Result};
+use crate::file;
+use crate::io_buffer::IoBufferWriter;
+use crate::str::CStr;
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64};
+
+/// A debugfs directory.
+///
+/// The directory and everything created inside of it is removed when the `Dir` is dropped.
+///
+/// # Invariants
+/// - `dentry` is a valid pointer returned by `debugfs_create_dir`.
+pub struct Dir {
+    dentry: *mut bindings::dentry,
+}
+
+// SAFETY: The dentry is only used to create and remove debugfs entries, the debugfs core
+// serializes these operations internally.
+unsafe impl Send for Dir {}
+
+// SAFETY: `Dir` exposes no interior mutability, all the operations are serialized by debugfs.
+unsafe impl Sync for Dir {}
+
+impl Dir {
+    /// Creates a new directory.
+    ///
+    /// # Parameters
+    /// - `name`: The name of the directory.
+    /// - `parent`: The parent directory, `None` to create it in the debugfs root.
+    ///
+    /// # Returns
+    /// - `Ok(Dir)` if the directory is created.
+    /// - `Err(Error)` if debugfs is not available or the creation fails.
+    pub fn new(name: &CStr, parent: Option<&Dir>) -> Result<Self> {
+        let parent = parent.map_or(core::ptr::null_mut(), |p| p.dentry);
+        // SAFETY: `name` is a valid null-terminated string and `parent` is either null or a valid
+        // dentry by the type invariants.
+        let dentry = from_kernel_err_ptr(unsafe {
+            bindings::debugfs_create_dir(name.as_char_ptr(), parent)
+        })?;
+        Ok(Self { dentry })
+    }
+
+    /// Creates a file exposing a `u32` counter or knob.
+    pub fn create_u32(&self, name: &CStr, mode: u16, value: &'static AtomicU32) {
+        // SAFETY: `AtomicU32` has the same in-memory representation as `u32` and the value is
+        // `'static`, so it outlives the file.
+        unsafe {
+            bindings::debugfs_create_u32(
+                name.as_char_ptr(),
+                mode,
+                self.dentry,
+                value as *const AtomicU32 as *mut u32,
+            )
+        };
+    }
+
+    /// Creates a file exposing a `u64` counter or knob.
+    pub fn create_u64(&self, name: &CStr, mode: u16, value: &'static AtomicU64) {
+        // SAFETY: `AtomicU64` has the same in-memory representation as `u64` and the value is
+        // `'static`, so it outlives the file.
+        unsafe {
+            bindings::debugfs_create_u64(
+                name.as_char_ptr(),
+                mode,
+                self.dentry,
+                value as *const AtomicU64 as *mut u64,
+            )
+        };
+    }
+
+    /// Creates a file exposing a boolean flag.
+    pub fn create_bool(&self, name: &CStr, mode: u16, value: &'static AtomicBool) {
+        // SAFETY: `AtomicBool` has the same in-memory representation as `bool` and the value is
+        // `'static`, so it outlives the file.
+        unsafe {
+            bindings::debugfs_create_bool(
+                name.as_char_ptr(),
+                mode,
+                self.dentry,
+                value as *const AtomicBool as *mut bool,
+            )
+        };
+    }
+
+    /// Creates a file whose operations are implemented by `T`.
+    ///
+    /// `data` is passed to [`file::Operations::open`] every time the file is opened.
+    pub fn create_file<T: file::Operations>(
+        &self,
+        name: &CStr,
+        mode: u16,
+        data: &'static T::OpenData,
+    ) -> Result {
+        // SAFETY: `FileAdapter` retrieves the open data from the inode private field, which is
+        // where `debugfs_create_file` stores `data`.
+        let fops = unsafe { file::OperationsVtable::<FileAdapter<T>, T>::build() };
+        // SAFETY: All pointers are valid, `data` is `'static` so it outlives the file.
+        from_kernel_err_ptr(unsafe {
+            bindings::debugfs_create_file(
+                name.as_char_ptr(),
+                mode,
+                self.dentry,
+                data as *const T::OpenData as *mut core::ffi::c_void,
+                fops,
+            )
+        })?;
+        Ok(())
+    }
+}
+
+impl Drop for Dir {
+    fn drop(&mut self) {
+        // SAFETY: `dentry` is valid by the type invariants, the directory is removed recursively.
+        unsafe { bindings::debugfs_remove(self.dentry) };
+    }
+}
+
+/// Retrieves the open data of debugfs files created with [`Dir::create_file`].
+struct FileAdapter<T: file::Operations>(PhantomData<T>);
+
+impl<T: file::Operations> file::OpenAdapter<T::OpenData> for FileAdapter<T> {
+    unsafe fn convert(
+        inode: *mut bindings::inode,
+        _file: *mut bindings::file,
+    ) -> *const T::OpenData {
+        // SAFETY: The caller guarantees that `inode` is valid, debugfs stores the data passed to
+        // `debugfs_create_file` in `i_private`.
+        unsafe { (*inode).i_private as *const T::OpenData }
+    }
+}
+
+/// Copies the part of `data` starting at `offset` into `writer`.
+///
+/// This is the equivalent of `simple_read_from_buffer`, it lets text files be read with any
+/// buffer size (e.g. by `cat`).
+///
+/// # Returns
+/// The number of bytes copied, `0` once the end of `data` is reached.
+pub fn simple_read(writer: &mut impl IoBufferWriter, offset: u64, data: &[u8]) -> Result<usize> {
+    let offset = match usize::try_from(offset) {
+        Ok(offset) if offset < data.len() => offset,
+        _ => return Ok(0),
+    };
+    let len = core::cmp::min(writer.len(), data.len() - offset);
+    writer.write_slice(&data[offset..offset + len])?;
+    Ok(len)
+}
@@ -9,15 +9,18 @@ use crate::{clk::Clk, error::from_kernel_err_ptr};
 
 use crate::{
     bindings,
+    error::to_result,
     revocable::{Revocable, RevocableGuard},
     str::CStr,
     sync::{LockClassKey, NeedsLockClass, RevocableMutex, RevocableMutexGuard, UniqueArc},
     Result,
 };
+use alloc::vec::Vec;
 use core::{
     fmt,
     ops::{Deref, DerefMut},
     pin::Pin,
+    ptr,
 };
 
 #[cfg(CONFIG_PRINTK)]
@@ -70,6 +73,26 @@ pub unsafe trait RawDevice {
         unsafe { Ok(Clk::new(clk_ptr)) }
     }
 
+    /// Sends a uevent about this device, with `env` added to the environment of the event.
+    ///
+    /// Each entry of `env` is a `KEY=value` string, as seen by udev rules and by the listeners
+    /// of the kobject uevent netlink socket. It may sleep, so it must not be called in atomic
+    /// context.
+    fn uevent(&self, action: UeventAction, env: &[&CStr]) -> Result {
+        // The C side expects a NULL-terminated array of pointers
+        let mut envp = Vec::try_with_capacity(env.len() + 1)?;
+        for var in env {
+            envp.try_push(var.as_char_ptr() as *mut core::ffi::c_char)?;
+        }
+        envp.try_push(ptr::null_mut())?;
+
+        // SAFETY: `self.raw_device()` is valid because `self` keeps it alive, `envp` is
+        // NULL-terminated and the strings it points to outlive the call, which copies them.
+        to_result(unsafe {
+            bindings::kobject_uevent_env(&mut (*self.raw_device()).kobj, action as _, envp.as_mut_ptr())
+        })
+    }
+
     /// Prints an emergency-level message (level 0) prefixed with device information.
     ///
     /// More details are available from [`dev_emerg`].
@@ -175,6 +198,18 @@ pub unsafe trait RawDevice {
     }
 }
 
+/// Action reported by a uevent, see [`RawDevice::uevent`].
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UeventAction {
+    /// The state of the device changed (`KOBJ_CHANGE`).
+    Change = bindings::kobject_action_KOBJ_CHANGE,
+    /// The device went online (`KOBJ_ONLINE`).
+    Online = bindings::kobject_action_KOBJ_ONLINE,
+    /// The device went offline (`KOBJ_OFFLINE`).
+    Offline = bindings::kobject_action_KOBJ_OFFLINE,
+}
+
 /// A ref-counted device.
 ///
 /// # Invariants
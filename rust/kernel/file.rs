@@ -12,7 +12,7 @@ use crate::{
     io_buffer::{IoBufferReader, IoBufferWriter},
     iov_iter::IovIter,
     mm,
-    sync::CondVar,
+    sync::{CondVar, WaitQueue},
     types::ForeignOwnable,
     user_ptr::{UserSlicePtr, UserSlicePtrReader, UserSlicePtrWriter},
     ARef, AlwaysRefCounted,
@@ -185,6 +185,16 @@ impl File {
         }
     }
 
+    /// Returns the minor number of the device node the file was opened through, 0 if it is
+    /// not a device node.
+    pub fn minor(&self) -> u32 {
+        // SAFETY: The file is valid because the shared reference guarantees a nonzero refcount,
+        // and its inode lives as long as the file.
+        let rdev = unsafe { (*(*self.0.get()).f_inode).i_rdev };
+        // `MINOR()` is a macro, `dev_t` keeps the minor in its low 20 bits
+        rdev & ((1 << 20) - 1)
+    }
+
 }
 
 // SAFETY: The type invariants guarantee that `File` is always ref-counted.
@@ -289,6 +299,76 @@ impl PollTable {
             unsafe { proc(file.0.get() as _, cv.wait_list.get(), self.ptr) }
         }
     }
+
+    /// Associates the given file and wait queue to this poll table, like `poll_wait`: waking up
+    /// the wait queue notifies the poll table as well. The association is undone by the kernel
+    /// when the file is destructed.
+    ///
+    /// # Safety
+    ///
+    /// The wait queue must not be destroyed before the file.
+    pub unsafe fn register_wait_queue<'a>(&self, file: &'a File, queue: &'a WaitQueue) {
+        if self.ptr.is_null() {
+            return;
+        }
+
+        // SAFETY: `PollTable::ptr` is guaranteed to be valid by the type invariants and the null
+        // check above.
+        let table = unsafe { &*self.ptr };
+        if let Some(proc) = table._qproc {
+            // SAFETY: All pointers are known to be valid.
+            unsafe { proc(file.0.get() as _, queue.wait_list.get(), self.ptr) }
+        }
+    }
+}
+
+/// Wraps the kernel's `struct fasync_struct` list: the files that asked for signal-driven I/O
+/// (`O_ASYNC`) on a driver.
+///
+/// `fasync_helper` and `kill_fasync` do their own locking, so the list can be shared.
+pub struct FasyncList {
+    head: UnsafeCell<*mut bindings::fasync_struct>,
+}
+
+// SAFETY: The list is only accessed through `fasync_helper` and `kill_fasync`, which serialize
+// the changes and read it under RCU.
+unsafe impl Sync for FasyncList {}
+// SAFETY: The list holds no reference tied to a thread.
+unsafe impl Send for FasyncList {}
+
+impl FasyncList {
+    /// Creates an empty list.
+    pub const fn new() -> Self {
+        Self {
+            head: UnsafeCell::new(ptr::null_mut()),
+        }
+    }
+
+    /// Adds `file` to the list (`on`) or removes it, like `fasync_helper`.
+    ///
+    /// Called from [`Operations::fasync`] with its arguments, and with `fd` -1 and `on` false
+    /// when the file is released.
+    pub fn update(&self, fd: i32, file: &File, on: bool) -> Result<i32> {
+        // SAFETY: The file is valid and `head` is only changed by `fasync_helper`.
+        let ret = unsafe { bindings::fasync_helper(fd, file.0.get(), on as _, self.head.get()) };
+        if ret < 0 {
+            return Err(Error::from_kernel_errno(ret));
+        }
+        Ok(ret)
+    }
+
+    /// Sends `SIGIO` to the owners of the files in the list, `band` being one of the `POLL_*`
+    /// codes (e.g. `bindings::POLL_IN`). It can be called from interrupt context.
+    pub fn kill(&self, band: u32) {
+        // SAFETY: `kill_fasync` reads the list under RCU.
+        unsafe { bindings::kill_fasync(self.head.get(), bindings::SIGIO as _, band as _) };
+    }
+}
+
+impl Default for FasyncList {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 /// Equivalent to [`std::io::SeekFrom`].
@@ -591,6 +671,22 @@ impl<A: OpenAdapter<T::OpenData>, T: Operations> OperationsVtable<A, T> {
         }
     }
 
+    unsafe extern "C" fn fasync_callback(
+        fd: core::ffi::c_int,
+        file: *mut bindings::file,
+        on: core::ffi::c_int,
+    ) -> core::ffi::c_int {
+        from_kernel_result! {
+            // SAFETY: `private_data` was initialised by `open_callback` with a value returned by
+            // `T::Data::into_foreign`. `T::Data::from_foreign` is only called by the `release`
+            // callback, which the C API guarantees that will be called only when all references
+            // to `file` have been released, so we know it can't be called while this function is
+            // running.
+            let f = unsafe { T::Data::borrow((*file).private_data) };
+            T::fasync(f, unsafe { File::from_ptr(file) }, fd, on != 0)
+        }
+    }
+
     unsafe extern "C" fn poll_callback(
         file: *mut bindings::file,
         wait: *mut bindings::poll_table_struct,
@@ -636,7 +732,11 @@ impl<A: OpenAdapter<T::OpenData>, T: Operations> OperationsVtable<A, T> {
         copy_file_range: None,
         fallocate: None,
         fadvise: None,
-        fasync: None,
+        fasync: if T::HAS_FASYNC {
+            Some(Self::fasync_callback)
+        } else {
+            None
+        },
         flock: None,
         flush: None,
         fsync: if T::HAS_FSYNC {
@@ -926,6 +1026,19 @@ pub trait Operations {
         Err(EINVAL)
     }
 
+    /// Turns signal-driven I/O on or off for the file, when `O_ASYNC` changes or the owner is
+    /// set. Usually forwards its arguments to [`FasyncList::update`].
+    ///
+    /// Corresponds to the `fasync` function pointer in `struct file_operations`.
+    fn fasync(
+        _data: <Self::Data as ForeignOwnable>::Borrowed<'_>,
+        _file: &File,
+        _fd: i32,
+        _on: bool,
+    ) -> Result<i32> {
+        Err(EINVAL)
+    }
+
     /// Checks the state of the file and optionally registers for notification when the state
     /// changes.
     ///
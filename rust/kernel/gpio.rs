@@ -229,6 +229,21 @@ impl<T: Chip> Drop for Registration<T> {
     }
 }
 
+// Added for gpio consumer support
+
+/// Returns the irq number associated with the given legacy gpio number.
+///
+/// This is used by drivers that receive a gpio line from user space (e.g. an external sync
+/// input) and need to request its interrupt.
+pub fn to_irq(gpio: u32) -> Result<u32> {
+    // SAFETY: `gpio_to_irq` validates the gpio number and returns a negative errno on failure.
+    let ret = unsafe { bindings::gpio_to_irq(gpio) };
+    if ret < 0 {
+        return Err(Error::from_kernel_errno(ret));
+    }
+    Ok(ret as u32)
+}
+
 /// Registers a gpio chip with the rest of the kernel.
 ///
 /// It automatically defines the required lock classes.
@@ -0,0 +1,211 @@
+// gpio_irq.rs
+
+//! GPIO lines used as interrupt sources.
+//!
+//! This module provides what a driver needs to turn a GPIO line into an interrupt: it requests
+//! the line as an input, requests its interrupt with a closure as handler (in interrupt context
+//! or in a thread) and releases both, in the right order, when the owner goes away. A line can
+//! also be requested as an output, for a driver signalling to simple hardware.
+//!
+//! Freeing an interrupt sleeps, so the types of this module must be dropped outside of any
+//! spinlock.
+//!
+//! C header: [`include/linux/gpio.h`](../../../../include/linux/gpio.h)
+
+use crate::bindings;
+use crate::error::{to_result, Error, Result};
+use crate::irq;
+use crate::str::CStr;
+use alloc::boxed::Box;
+use core::fmt;
+use core::marker::PhantomData;
+
+/// A legacy GPIO line requested as an input.
+///
+/// The line is released when the `GpioLine` is dropped.
+///
+/// # Invariants
+/// - `gpio` was successfully requested with `gpio_request_one`.
+pub struct GpioLine {
+    gpio: u32,
+}
+
+impl GpioLine {
+    /// Requests `gpio` as an input.
+    ///
+    /// # Parameters
+    /// - `gpio`: The legacy GPIO number.
+    /// - `label`: The consumer name, shown in `/sys/kernel/debug/gpio`.
+    ///
+    /// # Returns
+    /// - `Ok(GpioLine)` if the line is requested.
+    /// - `Err(Error)` if the number is invalid or the line is already in use.
+    pub fn request_input(gpio: u32, label: &'static CStr) -> Result<Self> {
+        // SAFETY: `label` is a valid null-terminated string that outlives the request.
+        to_result(unsafe {
+            bindings::gpio_request_one(gpio, bindings::GPIOF_IN as _, label.as_char_ptr())
+        })?;
+        Ok(Self { gpio })
+    }
+
+    /// Requests `gpio` as an output, driven at `high` from the start.
+    ///
+    /// # Parameters
+    /// - `gpio`: The legacy GPIO number.
+    /// - `label`: The consumer name, shown in `/sys/kernel/debug/gpio`.
+    /// - `high`: The initial level of the line.
+    ///
+    /// # Returns
+    /// - `Ok(GpioLine)` if the line is requested.
+    /// - `Err(Error)` if the number is invalid or the line is already in use.
+    pub fn request_output(gpio: u32, label: &'static CStr, high: bool) -> Result<Self> {
+        let flags = if high { bindings::GPIOF_OUT_INIT_HIGH } else { bindings::GPIOF_OUT_INIT_LOW };
+        // SAFETY: `label` is a valid null-terminated string that outlives the request.
+        to_result(unsafe { bindings::gpio_request_one(gpio, flags as _, label.as_char_ptr()) })?;
+        Ok(Self { gpio })
+    }
+
+    /// Drives the line of an output at `high`.
+    ///
+    /// It may sleep, for lines behind a bus such as an I2C expander.
+    pub fn set_value(&self, high: bool) {
+        // SAFETY: The line is requested by the type invariants.
+        unsafe { bindings::gpio_set_value_cansleep(self.gpio, high as _) };
+    }
+
+    /// Returns the GPIO number of the line.
+    pub fn number(&self) -> u32 {
+        self.gpio
+    }
+
+    /// Returns the current level of the line.
+    pub fn value(&self) -> bool {
+        // SAFETY: The line is requested by the type invariants.
+        unsafe { bindings::gpio_get_value(self.gpio) != 0 }
+    }
+
+    /// Returns the interrupt number of the line.
+    pub fn to_irq(&self) -> Result<u32> {
+        // SAFETY: The line is requested by the type invariants.
+        let ret = unsafe { bindings::gpio_to_irq(self.gpio) };
+        if ret < 0 {
+            return Err(Error::from_kernel_errno(ret));
+        }
+        Ok(ret as u32)
+    }
+}
+
+impl Drop for GpioLine {
+    fn drop(&mut self) {
+        // SAFETY: The line is requested by the type invariants.
+        unsafe { bindings::gpio_free(self.gpio) };
+    }
+}
+
+/// Runs a closure as interrupt handler, in interrupt context.
+pub struct ClosureHandler<F>(PhantomData<F>);
+
+impl<F: Fn() -> irq::Return + Send + Sync + 'static> irq::Handler for ClosureHandler<F> {
+    type Data = Box<F>;
+
+    fn handle_irq(handler: &F) -> irq::Return {
+        handler()
+    }
+}
+
+/// Runs a closure as threaded interrupt handler, in the interrupt thread.
+pub struct ThreadedClosureHandler<F>(PhantomData<F>);
+
+impl<F: Fn() -> irq::Return + Send + Sync + 'static> irq::ThreadedHandler for ThreadedClosureHandler<F> {
+    type Data = Box<F>;
+
+    fn handle_threaded_irq(handler: &F) -> irq::Return {
+        handler()
+    }
+}
+
+/// Requests an interrupt handled by `handler` in interrupt context.
+///
+/// The valid values of `flags` come from the [`irq::flags`] module.
+pub fn request_irq<F>(
+    irq: u32,
+    flags: usize,
+    name: fmt::Arguments<'_>,
+    handler: F,
+) -> Result<irq::Registration<ClosureHandler<F>>>
+where
+    F: Fn() -> irq::Return + Send + Sync + 'static,
+{
+    irq::Registration::try_new(irq, Box::try_new(handler)?, flags, name)
+}
+
+/// Requests an interrupt handled by `handler` in a dedicated thread, where it may sleep (e.g. to
+/// access an I2C device).
+///
+/// `irq::flags::ONESHOT` is always added, so a level interrupt stays masked until the handler
+/// returns.
+pub fn request_threaded_irq<F>(
+    irq: u32,
+    flags: usize,
+    name: fmt::Arguments<'_>,
+    handler: F,
+) -> Result<irq::ThreadedRegistration<ThreadedClosureHandler<F>>>
+where
+    F: Fn() -> irq::Return + Send + Sync + 'static,
+{
+    irq::ThreadedRegistration::try_new(irq, Box::try_new(handler)?, flags | irq::flags::ONESHOT, name)
+}
+
+/// A GPIO line together with the interrupt requested on it.
+///
+/// Dropping it frees the interrupt first and then releases the line, like the device-managed
+/// resources of a C driver do when the device goes away.
+pub struct GpioIrq<R> {
+    // Declared first, so the interrupt is freed before the line is released
+    registration: R,
+    line: GpioLine,
+    irq: u32,
+}
+
+impl<R> GpioIrq<R> {
+    /// Requests `gpio` as an input and then its interrupt.
+    ///
+    /// # Parameters
+    /// - `gpio`: The legacy GPIO number.
+    /// - `label`: The consumer name of the line.
+    /// - `register`: Requests the interrupt given its number, e.g. with [`request_irq`] or
+    ///   [`request_threaded_irq`].
+    ///
+    /// # Returns
+    /// - `Ok(GpioIrq)` if both the line and the interrupt are acquired.
+    /// - `Err(Error)` otherwise, in which case the line is released.
+    pub fn request(
+        gpio: u32,
+        label: &'static CStr,
+        register: impl FnOnce(u32) -> Result<R>,
+    ) -> Result<Self> {
+        let line = GpioLine::request_input(gpio, label)?;
+        let irq = line.to_irq()?;
+        let registration = register(irq)?;
+        Ok(Self {
+            registration,
+            line,
+            irq,
+        })
+    }
+
+    /// Returns the GPIO line.
+    pub fn line(&self) -> &GpioLine {
+        &self.line
+    }
+
+    /// Returns the interrupt number.
+    pub fn irq(&self) -> u32 {
+        self.irq
+    }
+
+    /// Returns the interrupt registration.
+    pub fn registration(&self) -> &R {
+        &self.registration
+    }
+}
@@ -7,8 +7,10 @@
 
 pub mod msg;
 pub mod adapter;
+pub mod algorithm;
 pub mod board_info;
 pub mod device_id;
+pub mod of_device_id;
 pub mod client;
 pub mod driver;
 pub mod i2c_macros;
@@ -17,8 +19,10 @@ pub mod utils;
 // Re-exporting the main types and macros for ease of use
 pub use msg::I2CMsg;
 pub use adapter::I2CAdapter;
+pub use algorithm::{I2CAdapterRegistration, I2CAlgorithm};
 pub use board_info::I2CBoardInfo;
 pub use device_id::I2CDeviceID;
+pub use of_device_id::I2COfDeviceID;
 pub use client::I2CClient;
 pub use driver::{I2CDriver, I2CDriverBuilder, I2CDriverCallbacks};
 
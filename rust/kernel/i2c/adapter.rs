@@ -31,16 +31,25 @@ impl I2CAdapter {
     /// * `Ok(I2CAdapter)` if successful.
     /// * `Err(Error)` if the adapter cannot be found.
     pub fn get_from_bus_number(bus_number: i32) -> Result<Self> {
+        Self::try_get_from_bus_number(bus_number).ok_or_else(|| {
+            pr_err!("Can't take the adapter");
+            EINVAL
+        })
+    }
+
+    /// Obtains the `I2CAdapter` of a given bus number, if the bus exists.
+    ///
+    /// Unlike [`I2CAdapter::get_from_bus_number`], a missing bus is not logged, so it can be used
+    /// to probe which buses exist.
+    pub fn try_get_from_bus_number(bus_number: i32) -> Option<Self> {
         // Safety: Calling the C API `i2c_get_adapter` which returns a pointer to `i2c_adapter` or null.
         let adapter_ptr = unsafe { bindings::i2c_get_adapter(bus_number) };
 
         if adapter_ptr.is_null() {
-            pr_err!("Can't take the adapter");
-            Err(EINVAL)
+            None
         } else {
             // Safety: The pointer is non-null and valid
-            //pr_err!("Adapter pointer: {:p}", adapter_ptr);
-            Ok(Self { ptr: adapter_ptr })
+            Some(Self { ptr: adapter_ptr })
         }
     }
 
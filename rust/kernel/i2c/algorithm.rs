@@ -0,0 +1,174 @@
+// algorithm.rs
+
+//! Module for I2C bus emulation.
+//!
+//! This module lets a Rust module register its own I2C adapter, whose SMBus transfers are
+//! served by an implementation of the `I2CAlgorithm` trait instead of by a bus controller.
+//! It is meant for device emulators, so drivers can be exercised without the hardware.
+
+use crate::prelude::*;
+use crate::bindings;
+use crate::error::to_result;
+use crate::str::CStr;
+use crate::i2c::adapter::I2CAdapter;
+use core::ffi::{c_char, c_int, c_ushort};
+use core::marker::PhantomPinned;
+
+/// SMBus transfers served by an emulated I2C adapter.
+///
+/// Only the byte data and I2C block read transfers are supported; the adapter advertises
+/// exactly this functionality, so clients won't issue the others.
+pub trait I2CAlgorithm: Send + Sync + 'static {
+    /// Reads the register `command` of the device at `addr`.
+    ///
+    /// Returning `Err(ENXIO)` emulates a device that doesn't acknowledge its address.
+    fn read_byte_data(&self, addr: u16, command: u8) -> Result<u8>;
+
+    /// Writes `value` into the register `command` of the device at `addr`.
+    fn write_byte_data(&self, addr: u16, command: u8, value: u8) -> Result;
+
+    /// Reads `buf.len()` consecutive registers starting at `command`.
+    ///
+    /// The default implementation reads one register at a time.
+    fn read_i2c_block_data(&self, addr: u16, command: u8, buf: &mut [u8]) -> Result {
+        for (i, byte) in buf.iter_mut().enumerate() {
+            *byte = self.read_byte_data(addr, command.wrapping_add(i as u8))?;
+        }
+        Ok(())
+    }
+}
+
+/// An emulated I2C adapter, registered with the kernel for as long as it exists.
+///
+/// # Invariants
+///
+/// - The structure is pinned, `adapter.algo` points to `algo` and `adapter.algo_data` points to
+///   the structure itself while the adapter is registered.
+pub struct I2CAdapterRegistration<T: I2CAlgorithm> {
+    adapter: bindings::i2c_adapter,
+    algo: bindings::i2c_algorithm,
+    data: T,
+    registered: bool,
+    _pin: PhantomPinned,
+}
+
+impl<T: I2CAlgorithm> I2CAdapterRegistration<T> {
+    /// Creates and registers a new adapter, with a bus number chosen by the kernel.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the adapter, shown in `/sys/bus/i2c/devices/i2c-N/name`.
+    /// * `data` - The implementation of the transfers.
+    /// * `module` - The module owning the adapter.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Pin<Box<Self>>)` if the adapter is registered.
+    /// * `Err(Error)` if the allocation or the registration fails.
+    pub fn new_pinned(name: &CStr, data: T, module: &'static ThisModule) -> Result<Pin<Box<Self>>> {
+        let mut reg = Pin::from(Box::try_new(Self {
+            adapter: bindings::i2c_adapter::default(),
+            algo: bindings::i2c_algorithm {
+                smbus_xfer: Some(smbus_xfer_callback::<T>),
+                functionality: Some(functionality_callback),
+                ..Default::default()
+            },
+            data,
+            registered: false,
+            _pin: PhantomPinned,
+        })?);
+
+        // SAFETY: The structure is not moved out of the pin, only its fields are initialized.
+        let this = unsafe { reg.as_mut().get_unchecked_mut() };
+        this.adapter.owner = module.as_ptr();
+        // The name is truncated to fit, the last byte stays null
+        let len = core::cmp::min(name.len(), this.adapter.name.len() - 1);
+        for (dst, src) in this.adapter.name.iter_mut().zip(&name.as_bytes()[..len]) {
+            *dst = *src as c_char;
+        }
+        this.adapter.nr = -1;
+        this.adapter.algo = &this.algo;
+        this.adapter.algo_data = this as *mut Self as *mut core::ffi::c_void;
+
+        // SAFETY: The adapter is fully initialized and pinned, so the pointers stored in it
+        // remain valid until it is deleted in `drop`.
+        to_result(unsafe { bindings::i2c_add_adapter(&mut this.adapter) })?;
+        this.registered = true;
+
+        Ok(reg)
+    }
+
+    /// Returns the bus number assigned to the adapter.
+    pub fn bus_number(&self) -> i32 {
+        self.adapter.nr
+    }
+
+    /// Returns a new reference to the adapter, e.g. to instantiate clients on it.
+    pub fn adapter(&self) -> Result<I2CAdapter> {
+        I2CAdapter::get_from_bus_number(self.adapter.nr)
+    }
+
+    /// Returns the implementation of the transfers.
+    pub fn data(&self) -> &T {
+        &self.data
+    }
+}
+
+impl<T: I2CAlgorithm> Drop for I2CAdapterRegistration<T> {
+    fn drop(&mut self) {
+        if self.registered {
+            // SAFETY: The adapter was registered in `new_pinned`, deleting it waits for the
+            // transfers in progress and unregisters all of its clients.
+            unsafe { bindings::i2c_del_adapter(&mut self.adapter) };
+        }
+    }
+}
+
+// SAFETY: The C structures are only modified before registration and by the I2C core, the data
+// is `Send`.
+unsafe impl<T: I2CAlgorithm> Send for I2CAdapterRegistration<T> {}
+
+// SAFETY: Transfers only get shared references to the data, which is `Sync`.
+unsafe impl<T: I2CAlgorithm> Sync for I2CAdapterRegistration<T> {}
+
+/// Returns the functionality advertised by emulated adapters.
+unsafe extern "C" fn functionality_callback(_adap: *mut bindings::i2c_adapter) -> u32 {
+    bindings::I2C_FUNC_SMBUS_BYTE_DATA | bindings::I2C_FUNC_SMBUS_READ_I2C_BLOCK
+}
+
+/// Dispatches an SMBus transfer to the `I2CAlgorithm` implementation.
+unsafe extern "C" fn smbus_xfer_callback<T: I2CAlgorithm>(
+    adap: *mut bindings::i2c_adapter,
+    addr: u16,
+    _flags: c_ushort,
+    read_write: c_char,
+    command: u8,
+    size: c_int,
+    data: *mut bindings::i2c_smbus_data,
+) -> c_int {
+    // SAFETY: `algo_data` was set to the pinned registration, which outlives the adapter.
+    let reg = unsafe { &*((*adap).algo_data as *const I2CAdapterRegistration<T>) };
+    let read = read_write as u32 == bindings::I2C_SMBUS_READ;
+
+    let ret = match size as u32 {
+        bindings::I2C_SMBUS_BYTE_DATA if read => reg.data.read_byte_data(addr, command).map(|value| {
+            // SAFETY: The I2C core passes a valid `data` for byte data transfers.
+            unsafe { (*data).byte = value };
+        }),
+        // SAFETY: The I2C core passes a valid `data` for byte data transfers.
+        bindings::I2C_SMBUS_BYTE_DATA => reg.data.write_byte_data(addr, command, unsafe { (*data).byte }),
+        bindings::I2C_SMBUS_I2C_BLOCK_DATA if read => {
+            // SAFETY: For I2C block reads, `block[0]` holds the length (at most
+            // I2C_SMBUS_BLOCK_MAX) and the data is stored right after it.
+            let block = unsafe { &mut (*data).block };
+            let len = core::cmp::min(block[0] as usize, bindings::I2C_SMBUS_BLOCK_MAX as usize);
+            reg.data.read_i2c_block_data(addr, command, &mut block[1..=len])
+        }
+        _ => Err(EOPNOTSUPP),
+    };
+
+    match ret {
+        Ok(()) => 0,
+        Err(e) => e.to_kernel_errno(),
+    }
+}
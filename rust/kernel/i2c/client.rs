@@ -7,7 +7,7 @@
 
 use crate::prelude::*;
 use crate::bindings;
-use core::ffi::{c_char};
+use core::ffi::{c_char, c_int};
 use crate::i2c::adapter::I2CAdapter;
 use crate::i2c::board_info::I2CBoardInfo;
 use crate::error::{to_result,from_kernel_err_ptr};
@@ -43,6 +43,15 @@ unsafe impl Send for I2CClient {}
 //   the `I2CClient` implementation.
 unsafe impl Sync for I2CClient {}
 
+// SAFETY: The device returned is the one embedded in the `i2c_client`, so actions on it affect
+// the client. I2C clients are never renamed.
+unsafe impl crate::device::RawDevice for I2CClient {
+    fn raw_device(&self) -> *mut bindings::device {
+        // SAFETY: By the type invariants, `self.ptr` is non-null and valid.
+        unsafe { &mut (*self.ptr).dev }
+    }
+}
+
 
 impl I2CClient {
     /// Attempts to create a new `I2CClient` device for the specified adapter and board info.
@@ -75,6 +84,47 @@ impl I2CClient {
         })
     }
     
+    /// Attempts to create a new `I2CClient` device at the first address of `addr_list` where a
+    /// device is detected, as `i2c_new_scanned_device`.
+    ///
+    /// The addresses already used by a client of the adapter are skipped without any transfer.
+    /// The others are checked with `probe`, or with a quick read without it: `probe` returns 1
+    /// when a device is found at the address.
+    ///
+    /// # Parameters
+    /// - `adapter`: Reference to the I2C adapter scanned.
+    /// - `board_info`: Information about the board, its address is ignored.
+    /// - `addr_list`: The addresses scanned, terminated by `I2C_CLIENT_END`.
+    /// - `probe`: The detection function, or `None` for a quick read.
+    ///
+    /// # Returns
+    /// A result containing either a new `I2CClient` instance, `ENODEV` if no device is found,
+    /// `EINVAL` if `addr_list` is not terminated, or another error.
+    ///
+    /// # Constraint
+    /// As with [`I2CClient::new_client_device`], the client is unregistered on drop.
+    pub fn new_scanned_device(
+        adapter: &I2CAdapter,
+        board_info: &I2CBoardInfo,
+        addr_list: &[u16],
+        probe: Option<unsafe extern "C" fn(*mut bindings::i2c_adapter, u16) -> c_int>,
+    ) -> Result<Self> {
+        if addr_list.last() != Some(&(bindings::I2C_CLIENT_END as u16)) {
+            return Err(EINVAL);
+        }
+        // SAFETY: The adapter and the board info are valid, the list is terminated and `probe`
+        // is only called during this call.
+        let ptr = unsafe {
+            bindings::i2c_new_scanned_device(adapter.as_ptr(), board_info.as_ptr() as *mut _, addr_list.as_ptr(), probe)
+        };
+        // SAFETY: `i2c_new_scanned_device` returns a client or an error pointer.
+        let client_ptr = unsafe { from_kernel_err_ptr(ptr) }?;
+
+        Ok(Self {
+            ptr: client_ptr,
+            owned: true,
+        })
+    }
 
     /// Creates an `I2CClient` from a raw pointer.
     ///
@@ -86,6 +136,14 @@ impl I2CClient {
     }
 
 
+    /// Returns the raw pointer to the client.
+    ///
+    /// A client not owned by Rust, e.g. the one passed to a driver callback, can be kept as
+    /// `from_raw_ptr(client.as_ptr())` while the driver is bound to it.
+    pub fn as_ptr(&self) -> *mut bindings::i2c_client {
+        self.ptr
+    }
+
     /// Sets the client data for this `I2CClient`.
     /// # Example
     /// 
@@ -77,6 +77,8 @@ pub struct I2CDriverBuilder<T: I2CDriverCallbacks> {
     class: Option<u32>,
     driver: bindings::device_driver,
     id_table: *const bindings::i2c_device_id,
+    of_match_table: Option<*const bindings::of_device_id>,
+    pm: Option<*const bindings::dev_pm_ops>,
     address_list: Option<*const u16>,
     clients: Option<bindings::list_head>,
     flags: Option<u32>,
@@ -106,6 +108,8 @@ impl<T: I2CDriverCallbacks> I2CDriverBuilder<T> {
                 ..Default::default()
             },
             id_table,
+            of_match_table: None,
+            pm: None,
             address_list: None,
             clients: None,
             flags: None,
@@ -119,6 +123,23 @@ impl<T: I2CDriverCallbacks> I2CDriverBuilder<T> {
         self
     }
 
+    /// Sets the device tree match table, see `I2COfDeviceID::table_ptr`.
+    ///
+    /// The clients created by the I2C core from the matching nodes are then bound to the driver.
+    pub fn of_match_table(mut self, of_match_table: *const bindings::of_device_id) -> Self {
+        self.of_match_table = Some(of_match_table);
+        self
+    }
+
+    /// Sets the power management operations of the driver, which run the `suspend` and `resume`
+    /// callbacks of `T` on system sleep and hibernation.
+    ///
+    /// Without them the PM core leaves the bound devices alone across a system suspend.
+    pub fn pm_ops(mut self) -> Self {
+        self.pm = Some(&I2CDriverVtable::<T>::PM_OPS);
+        self
+    }
+
     /// Sets the address list for device detection.
     pub fn address_list(mut self, address_list: *const u16) -> Self {
         self.address_list = Some(address_list);
@@ -143,7 +164,14 @@ impl<T: I2CDriverCallbacks> I2CDriverBuilder<T> {
     ///
     /// * `Ok(I2CDriver)` if the driver is successfully built.
     /// * `Err(Error)` if driver creation fails.
-    pub fn build(self) -> Result<I2CDriver> {
+    pub fn build(mut self) -> Result<I2CDriver> {
+        if let Some(of_match_table) = self.of_match_table {
+            self.driver.of_match_table = of_match_table;
+        }
+        if let Some(pm) = self.pm {
+            self.driver.pm = pm;
+        }
+
         // Use `I2CDriverVtable` to obtain the C-compatible callbacks
         let driver = bindings::i2c_driver {
             driver: self.driver,
@@ -203,6 +231,31 @@ pub trait I2CDriverCallbacks: Send + Sync {
     /// * `client` - The `I2CClient` representing the device.
     fn remove(&self, client: &I2CClient);
 
+    /// Optional: Called before `probe` for a client without client data, e.g. one created by
+    /// the I2C core from a device tree node or by `new_client_device` once the driver is
+    /// registered.
+    ///
+    /// Returns the instance handling the client, which is set as its client data. It must stay
+    /// valid until `release` is called for the client.
+    ///
+    /// Default implementation refuses the client.
+    fn instantiate(_client: &I2CClient) -> Result<*mut Self>
+    where
+        Self: Sized,
+    {
+        Err(ENODEV)
+    }
+
+    /// Optional: Called once `remove` returned and the client data is cleared, to free the
+    /// instance returned by `instantiate`.
+    ///
+    /// Default implementation does nothing.
+    fn release(_client: &I2CClient)
+    where
+        Self: Sized,
+    {
+    }
+
     /// Optional: Called during device shutdown.
     ///
     /// Default implementation does nothing.
@@ -210,6 +263,23 @@ pub trait I2CDriverCallbacks: Send + Sync {
         pr_info!("I2C Shutdown called\n");
     }
 
+    /// Optional: Called before the system goes to sleep, is hibernated or powered off, when the
+    /// driver was built with `I2CDriverBuilder::pm_ops`. The device may lose power until
+    /// `resume` is called.
+    ///
+    /// An error aborts the transition to sleep. Default implementation does nothing.
+    fn suspend(&self, _client: &I2CClient) -> Result<()> {
+        Ok(())
+    }
+
+    /// Optional: Called after the system woke up, or was restored from or created a hibernation
+    /// image, when the driver was built with `I2CDriverBuilder::pm_ops`.
+    ///
+    /// Default implementation does nothing.
+    fn resume(&self, _client: &I2CClient) -> Result<()> {
+        Ok(())
+    }
+
     /// Optional: Called on I2C alerts.
     ///
     /// Default implementation does nothing.
@@ -273,6 +343,35 @@ pub struct I2CDriverVtable<T: I2CDriverCallbacks> {
 
 
 impl<T: I2CDriverCallbacks> I2CDriverVtable<T> {
+    /// Power management operations set by `I2CDriverBuilder::pm_ops`. Suspend, freeze (before a
+    /// hibernation image is created) and poweroff run `suspend`; resume, thaw and restore run
+    /// `resume`.
+    const PM_OPS: bindings::dev_pm_ops = bindings::dev_pm_ops {
+        prepare: None,
+        complete: None,
+        suspend: Some(Self::suspend_callback),
+        resume: Some(Self::resume_callback),
+        freeze: Some(Self::suspend_callback),
+        thaw: Some(Self::resume_callback),
+        poweroff: Some(Self::suspend_callback),
+        restore: Some(Self::resume_callback),
+        suspend_late: None,
+        resume_early: None,
+        freeze_late: None,
+        thaw_early: None,
+        poweroff_late: None,
+        restore_early: None,
+        suspend_noirq: None,
+        resume_noirq: None,
+        freeze_noirq: None,
+        thaw_noirq: None,
+        poweroff_noirq: None,
+        restore_noirq: None,
+        runtime_suspend: None,
+        runtime_resume: None,
+        runtime_idle: None,
+    };
+
     /// Helper function to retrieve the driver instance stored in `clientdata`.
     ///
     /// # Safety
@@ -302,6 +401,15 @@ impl<T: I2CDriverCallbacks> I2CDriverVtable<T> {
             let client = unsafe{I2CClient::from_raw_ptr(client)};
             //pr_info!("Called the C probe callback");
 
+            // A client without client data gets its instance from the driver
+            if unsafe { client.get_clientdata::<T>() }.is_null() {
+                match T::instantiate(&client) {
+                    // SAFETY: `instantiate` returns an instance valid until `release`.
+                    Ok(instance) => client.set_clientdata::<T>(unsafe { &mut *instance }),
+                    Err(e) => return e.to_kernel_errno(),
+                }
+            }
+
             // Retrieve the driver instance from clientdata
             match Self::get_driver_instance(&client) {
                 Ok(driver_instance) => {
@@ -334,6 +442,7 @@ impl<T: I2CDriverCallbacks> I2CDriverVtable<T> {
 
                 // Clear the `i2cclient data` to avoid any dangling pointers.
                 client.free_clientdata();
+                T::release(&client);
             }
             Err(err) => {
                 pr_err!("Failed to retrieve driver instance in remove callback: {:?}", err);
@@ -425,4 +534,48 @@ impl<T: I2CDriverCallbacks> I2CDriverVtable<T> {
             }
         }
     }
-}
\ No newline at end of file
+
+    /// Returns the client embedding `dev`, a device bound to this driver.
+    ///
+    /// # Safety
+    /// `dev` must be the `dev` field of a valid `i2c_client`, as passed to the PM callbacks.
+    unsafe fn client_of(dev: *mut bindings::device) -> I2CClient {
+        let client = crate::container_of!(dev, bindings::i2c_client, dev) as *mut bindings::i2c_client;
+        // SAFETY: `client` is valid by the safety requirements, the returned value doesn't own it.
+        unsafe { I2CClient::from_raw_ptr(client) }
+    }
+
+    /// Extern "C" suspend callback of the power management operations, that calls the Rust
+    /// `suspend` method of the driver instance bound to the client.
+    unsafe extern "C" fn suspend_callback(dev: *mut bindings::device) -> c_int {
+        // SAFETY: The PM core passes the device of a client bound to this driver.
+        let client = unsafe { Self::client_of(dev) };
+        match Self::get_driver_instance(&client) {
+            Ok(driver_instance) => match driver_instance.suspend(&client) {
+                Ok(_) => 0,
+                Err(e) => e.to_kernel_errno(),
+            },
+            Err(err) => {
+                pr_err!("Failed to retrieve driver instance in suspend callback: {:?}", err);
+                err.to_kernel_errno()
+            }
+        }
+    }
+
+    /// Extern "C" resume callback of the power management operations, that calls the Rust
+    /// `resume` method of the driver instance bound to the client.
+    unsafe extern "C" fn resume_callback(dev: *mut bindings::device) -> c_int {
+        // SAFETY: The PM core passes the device of a client bound to this driver.
+        let client = unsafe { Self::client_of(dev) };
+        match Self::get_driver_instance(&client) {
+            Ok(driver_instance) => match driver_instance.resume(&client) {
+                Ok(_) => 0,
+                Err(e) => e.to_kernel_errno(),
+            },
+            Err(err) => {
+                pr_err!("Failed to retrieve driver instance in resume callback: {:?}", err);
+                err.to_kernel_errno()
+            }
+        }
+    }
+}
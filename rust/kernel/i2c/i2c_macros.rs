@@ -38,6 +38,26 @@ macro_rules! module_device_table {
     };
 }
 
+/// Exposes the device tree match table of an I2C driver to the kernel module loader.
+///
+/// The modules are then loaded for the device tree nodes matching a compatible string of the
+/// table.
+///
+/// # Parameters
+///
+/// * `$name` - The name of your `I2COfDeviceID` table.
+/// * `$len` - The length of your `I2COfDeviceID` table array.
+#[macro_export]
+macro_rules! i2c_of_module_device_table {
+    ($name:ident, $len:expr) => {
+        #[no_mangle]
+        #[link_section = ".modinfo"]
+        #[export_name = concat!("__mod_of__", stringify!($name), "_device_table")]
+        /// The array exposed to modinfo
+        pub static __OF_DEVICE_TABLE_ALIAS: [kernel::i2c::I2COfDeviceID; $len] = $name;
+    };
+}
+
 /// Exposes the I2C device table to the kernel module loader.
 ///
 /// Converts an array of `I2CDeviceID` to an array of `bindings::i2c_device_id`
@@ -0,0 +1,65 @@
+// of_device_id.rs
+
+//! Module for I2C device tree match tables.
+//!
+//! This module provides the `I2COfDeviceID` struct, an entry of the table matching the
+//! `compatible` property of the device tree nodes a driver handles. The I2C core creates a client
+//! for each child node of a bus and binds it to the driver whose table matches.
+
+use crate::bindings;
+
+/// Maximum size of a compatible string, including the terminating NUL.
+pub const OF_COMPATIBLE_SIZE: usize = 128;
+
+/// Represents an entry of an open firmware (device tree) match table.
+///
+/// This struct wraps the kernel's `of_device_id` struct.
+#[repr(transparent)]
+#[derive(Clone, Copy)]
+pub struct I2COfDeviceID {
+    /// The inner `of_device_id` struct.
+    inner: bindings::of_device_id,
+}
+
+impl I2COfDeviceID {
+    /// Creates a new `I2COfDeviceID` instance.
+    ///
+    /// # Arguments
+    ///
+    /// * `compatible` - The compatible string, e.g. `b"vendor,device"`, truncated to
+    ///   `OF_COMPATIBLE_SIZE - 1` bytes.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// static OF_TABLE: [I2COfDeviceID; 2] = [
+    ///     I2COfDeviceID::new(b"vendor,my-device"),
+    ///     I2COfDeviceID::new(b""), // Terminating entry
+    /// ];
+    /// ```
+    pub const fn new(compatible: &[u8]) -> Self {
+        let mut inner = bindings::of_device_id {
+            name: [0; 32],
+            type_: [0; 32],
+            compatible: [0; OF_COMPATIBLE_SIZE],
+            data: core::ptr::null(),
+        };
+        let mut i = 0;
+        while i < compatible.len() && i < OF_COMPATIBLE_SIZE - 1 {
+            inner.compatible[i] = compatible[i] as _;
+            i += 1;
+        }
+        Self { inner }
+    }
+
+    /// Returns the table as the pointer expected by `I2CDriverBuilder::of_match_table`.
+    ///
+    /// The last entry of `table` must be the terminating one, with an empty compatible string.
+    pub const fn table_ptr(table: &'static [I2COfDeviceID]) -> *const bindings::of_device_id {
+        table.as_ptr() as *const bindings::of_device_id
+    }
+}
+
+/// # Safety: The `data` pointer of the wrapped `of_device_id` is always null, the entries are
+/// built at compile time and only read afterward.
+unsafe impl Sync for I2COfDeviceID {}
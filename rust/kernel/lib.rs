@@ -78,6 +78,23 @@ pub mod workqueue;
 //Added for i2c 
 pub mod i2c;
 
+//Added for timestamps
+pub mod time;
+
+//Added for debugfs
+pub mod debugfs;
+
+//Added for gpio interrupts
+pub mod gpio_irq;
+
+//Added for thermal zones
+#[cfg(CONFIG_THERMAL)]
+pub mod thermal;
+
+//Added for the SPI transport
+#[cfg(CONFIG_SPI)]
+pub mod spi;
+
 pub mod linked_list;
 mod raw_list;
 pub mod rbtree;
@@ -161,6 +178,26 @@ impl ThisModule {
         self.0
     }
 
+    /// Takes a reference to the module, so it can't be unloaded until [`ThisModule::put`].
+    ///
+    /// Returns `false` if the module is being unloaded, no reference is taken then.
+    pub fn try_get(&self) -> bool {
+        // SAFETY: `try_module_get` accepts a null pointer (built-in code) and otherwise a valid
+        // module, guaranteed by the invariant of `ThisModule`.
+        unsafe { bindings::try_module_get(self.0) }
+    }
+
+    /// Drops a reference taken with [`ThisModule::try_get`].
+    ///
+    /// The module may be unloaded as soon as it returns, so code of the module calling it must
+    /// hold another reference to it across the return, e.g. the one the VFS holds on the owner
+    /// of a character device until `release` returns.
+    pub fn put(&self) {
+        // SAFETY: `module_put` accepts a null pointer and otherwise a valid module, guaranteed
+        // by the invariant of `ThisModule`.
+        unsafe { bindings::module_put(self.0) }
+    }
+
     /// Locks the module parameters to access them.
     ///
     /// Returns a [`KParamGuard`] that will release the lock when dropped.
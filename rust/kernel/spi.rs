@@ -0,0 +1,304 @@
+// spi.rs
+
+//! SPI devices and drivers.
+//!
+//! This module provides what a protocol driver needs on SPI: the device it is bound to, with the
+//! half-duplex `spi_write_then_read` transfer most register based chips use, and the registration
+//! of a driver whose `probe`, `remove`, `suspend` and `resume` run Rust callbacks. The devices
+//! themselves come from the firmware (device tree, ACPI) or from board code; the driver matches
+//! them by name.
+//!
+//! C header: [`include/linux/spi/spi.h`](../../../../include/linux/spi/spi.h)
+
+use crate::bindings;
+use crate::device::RawDevice;
+use crate::error::{to_result, Result};
+use crate::prelude::*;
+use crate::str::CStr;
+use crate::ThisModule;
+use core::ffi::{c_char, c_int};
+use core::marker::PhantomPinned;
+
+/// Maximum size of an SPI device name.
+pub const SPI_NAME_SIZE: usize = bindings::SPI_NAME_SIZE as usize;
+
+/// Clock phase: data is sampled on the trailing clock edge.
+pub const SPI_CPHA: u32 = 0x1;
+
+/// Clock polarity: the clock idles high.
+pub const SPI_CPOL: u32 = 0x2;
+
+/// SPI mode 3, the clock idles high and data is sampled on its rising edge.
+pub const SPI_MODE_3: u32 = SPI_CPOL | SPI_CPHA;
+
+/// An SPI device, holding a reference to it.
+///
+/// # Invariants
+/// - `spi` is a valid pointer to an `spi_device` on which a reference is held.
+pub struct SpiDevice {
+    spi: *mut bindings::spi_device,
+}
+
+// SAFETY: The SPI core serializes the transfers on the controller, any thread may issue them.
+unsafe impl Send for SpiDevice {}
+
+// SAFETY: `SpiDevice` exposes no interior mutability, see above.
+unsafe impl Sync for SpiDevice {}
+
+impl SpiDevice {
+    /// Takes a reference to the device pointed to by `spi`.
+    ///
+    /// # Safety
+    /// `spi` must be a valid pointer to an `spi_device`, e.g. the one passed to `probe`.
+    pub unsafe fn from_raw(spi: *mut bindings::spi_device) -> Self {
+        // SAFETY: `spi` is valid by the safety requirements.
+        unsafe { bindings::get_device(&mut (*spi).dev) };
+        Self { spi }
+    }
+
+    /// Sets the clock mode (`SPI_MODE_*`) and the highest clock rate of the transfers, and applies
+    /// them to the controller. It may sleep.
+    pub fn setup(&self, mode: u32, max_speed_hz: u32) -> Result {
+        // SAFETY: `spi` is valid by the type invariants, and the driver bound to the device owns
+        // its settings.
+        to_result(unsafe {
+            (*self.spi).mode = mode as _;
+            (*self.spi).max_speed_hz = max_speed_hz;
+            bindings::spi_setup(self.spi)
+        })
+    }
+
+    /// Sets the time the chip select stays inactive after each transfer, in microseconds, for
+    /// chips that need a gap between two transfers. It applies from the next `setup()`.
+    pub fn set_cs_inactive_us(&self, us: u16) {
+        // SAFETY: `spi` is valid by the type invariants, and the driver bound to the device owns
+        // its settings.
+        unsafe {
+            (*self.spi).cs_inactive.value = us;
+            (*self.spi).cs_inactive.unit = bindings::SPI_DELAY_UNIT_USECS as _;
+        }
+    }
+
+    /// Writes `tx`, then reads `rx.len()` bytes, with the chip select held across both.
+    ///
+    /// The transfers go through a bounce buffer of the SPI core, so the buffers may live on the
+    /// stack. It may sleep.
+    pub fn write_then_read(&self, tx: &[u8], rx: &mut [u8]) -> Result {
+        // SAFETY: `spi` is valid by the type invariants, and the buffers are valid for their
+        // lengths.
+        to_result(unsafe {
+            bindings::spi_write_then_read(
+                self.spi,
+                tx.as_ptr() as _,
+                tx.len() as u32,
+                rx.as_mut_ptr() as _,
+                rx.len() as u32,
+            )
+        })
+    }
+
+    /// Writes `tx`, it may sleep.
+    pub fn write(&self, tx: &[u8]) -> Result {
+        self.write_then_read(tx, &mut [])
+    }
+
+    /// Returns the raw pointer to the device.
+    pub fn as_ptr(&self) -> *mut bindings::spi_device {
+        self.spi
+    }
+}
+
+impl Clone for SpiDevice {
+    fn clone(&self) -> Self {
+        // SAFETY: `spi` is valid by the type invariants.
+        unsafe { Self::from_raw(self.spi) }
+    }
+}
+
+impl Drop for SpiDevice {
+    fn drop(&mut self) {
+        // SAFETY: A reference is held by the type invariants.
+        unsafe { bindings::put_device(&mut (*self.spi).dev) };
+    }
+}
+
+// SAFETY: The embedded device is valid as long as `self`, a reference is held on it.
+unsafe impl RawDevice for SpiDevice {
+    fn raw_device(&self) -> *mut bindings::device {
+        // SAFETY: `spi` is valid by the type invariants.
+        unsafe { &mut (*self.spi).dev }
+    }
+}
+
+/// Returns an entry of the id table of a driver, `name` is truncated to `SPI_NAME_SIZE - 1`.
+///
+/// The table ends with an entry of an empty name.
+pub const fn spi_device_id(name: &[u8], driver_data: u64) -> bindings::spi_device_id {
+    let mut id = [0 as c_char; SPI_NAME_SIZE];
+    let mut i = 0;
+    while i < name.len() && i < SPI_NAME_SIZE - 1 {
+        id[i] = name[i] as c_char;
+        i += 1;
+    }
+    bindings::spi_device_id { name: id, driver_data: driver_data as _ }
+}
+
+/// Callbacks of an SPI driver.
+pub trait SpiDriverCallbacks: Send + Sync + 'static {
+    /// Called when the driver is bound to `spi`.
+    fn probe(&self, spi: &SpiDevice) -> Result;
+
+    /// Called when the driver is unbound from `spi`.
+    fn remove(&self, spi: &SpiDevice);
+
+    /// Called before the system goes to sleep, is hibernated or powered off. The device may lose
+    /// power until `resume` is called; an error aborts the transition to sleep.
+    fn suspend(&self, _spi: &SpiDevice) -> Result {
+        Ok(())
+    }
+
+    /// Called after the system woke up, or was restored from or created a hibernation image.
+    fn resume(&self, _spi: &SpiDevice) -> Result {
+        Ok(())
+    }
+}
+
+/// A registered SPI driver, unregistered on drop.
+///
+/// # Invariants
+/// - `driver` is registered if `registered` is true, and doesn't move until it is unregistered.
+pub struct SpiDriverRegistration<T: SpiDriverCallbacks> {
+    driver: bindings::spi_driver,
+    data: T,
+    registered: bool,
+    _pin: PhantomPinned,
+}
+
+// SAFETY: The SPI core serializes the callbacks of a device, `data` is `Send + Sync`.
+unsafe impl<T: SpiDriverCallbacks> Send for SpiDriverRegistration<T> {}
+
+// SAFETY: See above.
+unsafe impl<T: SpiDriverCallbacks> Sync for SpiDriverRegistration<T> {}
+
+impl<T: SpiDriverCallbacks> SpiDriverRegistration<T> {
+    /// Power management operations of the driver: suspend, freeze and poweroff run `suspend`,
+    /// resume, thaw and restore run `resume`.
+    const PM_OPS: bindings::dev_pm_ops = bindings::dev_pm_ops {
+        prepare: None,
+        complete: None,
+        suspend: Some(Self::suspend_callback),
+        resume: Some(Self::resume_callback),
+        freeze: Some(Self::suspend_callback),
+        thaw: Some(Self::resume_callback),
+        poweroff: Some(Self::suspend_callback),
+        restore: Some(Self::resume_callback),
+        suspend_late: None,
+        resume_early: None,
+        freeze_late: None,
+        thaw_early: None,
+        poweroff_late: None,
+        restore_early: None,
+        suspend_noirq: None,
+        resume_noirq: None,
+        freeze_noirq: None,
+        thaw_noirq: None,
+        poweroff_noirq: None,
+        restore_noirq: None,
+        runtime_suspend: None,
+        runtime_resume: None,
+        runtime_idle: None,
+    };
+
+    /// Registers a driver named `name` with the callbacks of `data`, for the devices named in
+    /// `id_table`. The devices already present are probed before it returns.
+    pub fn new_pinned(
+        name: &'static CStr,
+        id_table: &'static [bindings::spi_device_id],
+        data: T,
+        module: &'static ThisModule,
+    ) -> Result<Pin<Box<Self>>> {
+        let mut registration = Pin::from(Box::try_new(Self {
+            // SAFETY: An all-zero `spi_driver` is a valid driver without callbacks.
+            driver: unsafe { core::mem::zeroed() },
+            data,
+            registered: false,
+            _pin: PhantomPinned,
+        })?);
+
+        // SAFETY: The registration is not moved out of the box.
+        let this = unsafe { registration.as_mut().get_unchecked_mut() };
+        this.driver.driver.name = name.as_char_ptr();
+        this.driver.id_table = id_table.as_ptr();
+        this.driver.probe = Some(Self::probe_callback);
+        this.driver.remove = Some(Self::remove_callback);
+        this.driver.driver.pm = &Self::PM_OPS;
+
+        // SAFETY: `driver` is initialized and pinned.
+        to_result(unsafe { bindings::__spi_register_driver(module.as_ptr(), &mut this.driver) })?;
+        this.registered = true;
+        Ok(registration)
+    }
+
+    /// Returns the callbacks of the driver.
+    pub fn data(&self) -> &T {
+        &self.data
+    }
+
+    /// Returns the registration of the driver bound to `spi`.
+    ///
+    /// # Safety
+    /// `spi` must be bound to a driver registered by `new_pinned` with the same `T`.
+    unsafe fn from_device<'a>(spi: *mut bindings::spi_device) -> &'a Self {
+        // SAFETY: The driver of the device is the `device_driver` embedded in `driver`, by the
+        // safety requirements.
+        unsafe {
+            let driver = crate::container_of!((*spi).dev.driver, bindings::spi_driver, driver);
+            &*crate::container_of!(driver, Self, driver)
+        }
+    }
+
+    unsafe extern "C" fn probe_callback(spi: *mut bindings::spi_device) -> c_int {
+        // SAFETY: The SPI core passes a valid device bound to this driver.
+        let (this, device) = unsafe { (Self::from_device(spi), SpiDevice::from_raw(spi)) };
+        match this.data.probe(&device) {
+            Ok(()) => 0,
+            Err(e) => e.to_kernel_errno(),
+        }
+    }
+
+    unsafe extern "C" fn remove_callback(spi: *mut bindings::spi_device) {
+        // SAFETY: The SPI core passes a valid device bound to this driver.
+        let (this, device) = unsafe { (Self::from_device(spi), SpiDevice::from_raw(spi)) };
+        this.data.remove(&device);
+    }
+
+    unsafe extern "C" fn suspend_callback(dev: *mut bindings::device) -> c_int {
+        let spi = crate::container_of!(dev, bindings::spi_device, dev) as *mut bindings::spi_device;
+        // SAFETY: The PM core passes the device of an SPI device bound to this driver.
+        let (this, device) = unsafe { (Self::from_device(spi), SpiDevice::from_raw(spi)) };
+        match this.data.suspend(&device) {
+            Ok(()) => 0,
+            Err(e) => e.to_kernel_errno(),
+        }
+    }
+
+    unsafe extern "C" fn resume_callback(dev: *mut bindings::device) -> c_int {
+        let spi = crate::container_of!(dev, bindings::spi_device, dev) as *mut bindings::spi_device;
+        // SAFETY: The PM core passes the device of an SPI device bound to this driver.
+        let (this, device) = unsafe { (Self::from_device(spi), SpiDevice::from_raw(spi)) };
+        match this.data.resume(&device) {
+            Ok(()) => 0,
+            Err(e) => e.to_kernel_errno(),
+        }
+    }
+}
+
+impl<T: SpiDriverCallbacks> Drop for SpiDriverRegistration<T> {
+    fn drop(&mut self) {
+        if self.registered {
+            // SAFETY: `driver` is registered by the type invariants. `spi_unregister_driver` is
+            // inline, this is its body.
+            unsafe { bindings::driver_unregister(&mut self.driver.driver) };
+        }
+    }
+}
@@ -25,6 +25,7 @@ use crate::{bindings, str::CStr};
 use core::{cell::UnsafeCell, mem::MaybeUninit, pin::Pin};
 
 mod arc;
+mod completion;
 mod condvar;
 mod guard;
 mod locked_by;
@@ -32,21 +33,26 @@ mod mutex;
 mod nowait;
 pub mod rcu;
 mod revocable;
+mod rtmutex;
 mod rwsem;
 mod seqlock;
 pub mod smutex;
 mod spinlock;
+mod waitqueue;
 
 pub use arc::{new_refcount, Arc, ArcBorrow, StaticArc, UniqueArc};
+pub use completion::Completion;
 pub use condvar::CondVar;
 pub use guard::{Guard, Lock, LockFactory, LockInfo, LockIniter, ReadLock, WriteLock};
 pub use locked_by::LockedBy;
 pub use mutex::{Mutex, RevocableMutex, RevocableMutexGuard};
 pub use nowait::{NoWaitLock, NoWaitLockGuard};
 pub use revocable::{Revocable, RevocableGuard};
+pub use rtmutex::RtMutex;
 pub use rwsem::{RevocableRwSemaphore, RevocableRwSemaphoreGuard, RwSemaphore};
 pub use seqlock::{SeqLock, SeqLockReadGuard};
 pub use spinlock::{RawSpinLock, SpinLock};
+pub use waitqueue::WaitQueue;
 
 /// Represents a lockdep class. It's a wrapper around C's `lock_class_key`.
 #[repr(transparent)]
@@ -0,0 +1,93 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! A completion.
+//!
+//! This module allows Rust code to use the kernel's [`struct completion`], a one-shot event that
+//! threads can wait for: once it is completed, all current and future waiters go through until it
+//! is reinitialised.
+//!
+//! [`struct completion`]: ../../../include/linux/completion.h
+
+use crate::error::{Error, Result};
+use crate::{bindings, Opaque};
+use core::{marker::PhantomPinned, pin::Pin};
+
+/// Exposes the kernel's [`struct completion`].
+///
+/// [`struct completion`]: ../../../include/linux/completion.h
+pub struct Completion {
+    completion: Opaque<bindings::completion>,
+
+    /// A completion contains a wait queue, which is self-referential, so it cannot be safely moved
+    /// once it is initialised.
+    _pin: PhantomPinned,
+}
+
+// SAFETY: `Completion` only uses a `struct completion`, which is safe to use on any thread.
+#[allow(clippy::non_send_fields_in_send_ty)]
+unsafe impl Send for Completion {}
+
+// SAFETY: `Completion` only uses a `struct completion`, which is safe to use on multiple threads
+// concurrently.
+unsafe impl Sync for Completion {}
+
+impl Completion {
+    /// Constructs a new completion.
+    ///
+    /// # Safety
+    ///
+    /// The caller must call `Completion::init` before using the completion.
+    pub const unsafe fn new() -> Self {
+        Self {
+            completion: Opaque::uninit(),
+            _pin: PhantomPinned,
+        }
+    }
+
+    /// Initialises the completion, in the "not done" state.
+    pub fn init(self: Pin<&mut Self>) {
+        // SAFETY: `completion` points to valid memory, the completion is pinned.
+        unsafe { bindings::init_completion(self.completion.get()) };
+    }
+
+    /// Marks the completion as done and wakes up all the waiters, like `complete_all`.
+    ///
+    /// Memory writes done before this call are visible to the threads that are let through.
+    pub fn complete_all(&self) {
+        // SAFETY: `completion` points to valid memory.
+        unsafe { bindings::complete_all(self.completion.get()) };
+    }
+
+    /// Resets the completion to the "not done" state, like `reinit_completion`.
+    ///
+    /// It must not be called while there are waiters.
+    pub fn reinit(&self) {
+        // SAFETY: `completion` points to valid memory.
+        unsafe { bindings::reinit_completion(self.completion.get()) };
+    }
+
+    /// Returns true if the completion is done, without waiting.
+    pub fn done(&self) -> bool {
+        // SAFETY: `completion` points to valid memory.
+        unsafe { bindings::completion_done(self.completion.get()) }
+    }
+
+    /// Waits for the completion for at most `timeout` jiffies, like
+    /// `wait_for_completion_interruptible_timeout`.
+    ///
+    /// # Returns
+    /// - `Ok(true)` if the completion is done.
+    /// - `Ok(false)` if the timeout elapsed first.
+    /// - `Err(ERESTARTSYS)` if a signal is received first.
+    pub fn wait_interruptible_timeout(&self, timeout: core::ffi::c_ulong) -> Result<bool> {
+        // SAFETY: `completion` points to valid memory.
+        let ret = unsafe {
+            bindings::wait_for_completion_interruptible_timeout(self.completion.get(), timeout)
+        };
+        match ret {
+            0 => Ok(false),
+            ret if ret > 0 => Ok(true),
+            ret => Err(Error::from_kernel_errno(ret as _)),
+        }
+    }
+}
@@ -0,0 +1,112 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! A kernel RT mutex.
+//!
+//! This module allows Rust code to use the kernel's [`struct rt_mutex`], a sleeping lock with
+//! priority inheritance: a task holding it is boosted to the priority of the highest priority
+//! waiter, so a low priority owner can't indefinitely delay a high priority one.
+//!
+//! [`struct rt_mutex`]: ../../../include/linux/rtmutex.h
+
+use super::{Guard, Lock, LockClassKey, LockFactory, LockIniter};
+use crate::{bindings, str::CStr, Opaque};
+use core::{cell::UnsafeCell, marker::PhantomPinned, pin::Pin};
+
+/// Safely initialises a [`RtMutex`] with the given name, generating a new lock class.
+#[macro_export]
+macro_rules! rt_mutex_init {
+    ($mutex:expr, $name:literal) => {
+        $crate::init_with_lockdep!($mutex, $name)
+    };
+}
+
+/// Exposes the kernel's [`struct rt_mutex`].
+///
+/// It is used like [`super::Mutex`], and like it, it may block. It must be initialised with a
+/// call to [`RtMutex::init_lock`] before it can be used, the [`rt_mutex_init`] macro assigns a
+/// new lock class to it.
+///
+/// [`struct rt_mutex`]: ../../../include/linux/rtmutex.h
+pub struct RtMutex<T: ?Sized> {
+    /// The kernel `struct rt_mutex` object.
+    mutex: Opaque<bindings::rt_mutex>,
+
+    /// An RT mutex needs to be pinned because it contains a self-referential tree of waiters, so
+    /// it cannot be safely moved once it is initialised.
+    _pin: PhantomPinned,
+
+    /// The data protected by the mutex.
+    data: UnsafeCell<T>,
+}
+
+// SAFETY: `RtMutex` can be transferred across thread boundaries iff the data it protects can.
+#[allow(clippy::non_send_fields_in_send_ty)]
+unsafe impl<T: ?Sized + Send> Send for RtMutex<T> {}
+
+// SAFETY: `RtMutex` serialises the interior mutability it provides, so it is `Sync` as long as the
+// data it protects is `Send`.
+unsafe impl<T: ?Sized + Send> Sync for RtMutex<T> {}
+
+impl<T> RtMutex<T> {
+    /// Constructs a new RT mutex.
+    ///
+    /// # Safety
+    ///
+    /// The caller must call [`RtMutex::init_lock`] before using the mutex.
+    pub const unsafe fn new(t: T) -> Self {
+        Self {
+            mutex: Opaque::uninit(),
+            data: UnsafeCell::new(t),
+            _pin: PhantomPinned,
+        }
+    }
+}
+
+impl<T: ?Sized> RtMutex<T> {
+    /// Locks the mutex and gives the caller access to the data protected by it. Only one thread at
+    /// a time is allowed to access the protected data.
+    pub fn lock(&self) -> Guard<'_, Self> {
+        let ctx = self.lock_noguard();
+        // SAFETY: The mutex was just acquired.
+        unsafe { Guard::new(self, ctx) }
+    }
+}
+
+impl<T> LockFactory for RtMutex<T> {
+    type LockedType<U> = RtMutex<U>;
+
+    unsafe fn new_lock<U>(data: U) -> RtMutex<U> {
+        // SAFETY: The safety requirements of `new_lock` also require that `init_lock` be called.
+        unsafe { RtMutex::new(data) }
+    }
+}
+
+impl<T> LockIniter for RtMutex<T> {
+    fn init_lock(self: Pin<&mut Self>, name: &'static CStr, key: &'static LockClassKey) {
+        unsafe { bindings::__rt_mutex_init(self.mutex.get(), name.as_char_ptr(), key.get()) };
+    }
+}
+
+pub struct EmptyGuardContext;
+
+// SAFETY: The underlying kernel `struct rt_mutex` object ensures mutual exclusion.
+unsafe impl<T: ?Sized> Lock for RtMutex<T> {
+    type Inner = T;
+    type GuardContext = EmptyGuardContext;
+
+    fn lock_noguard(&self) -> EmptyGuardContext {
+        // SAFETY: `mutex` points to valid memory.
+        unsafe { bindings::rt_mutex_lock(self.mutex.get()) };
+        EmptyGuardContext
+    }
+
+    unsafe fn unlock(&self, _: &mut EmptyGuardContext) {
+        // SAFETY: The safety requirements of the function ensure that the mutex is owned by the
+        // caller.
+        unsafe { bindings::rt_mutex_unlock(self.mutex.get()) };
+    }
+
+    fn locked_data(&self) -> &UnsafeCell<T> {
+        &self.data
+    }
+}
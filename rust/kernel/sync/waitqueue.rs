@@ -0,0 +1,169 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! A wait queue.
+//!
+//! This module allows Rust code to wait on the kernel's [`struct wait_queue_head`] until a
+//! condition becomes true, like the `wait_event_*` family of C macros. Unlike [`super::CondVar`],
+//! no lock is associated with the queue: the condition is re-evaluated by the waiter, which makes
+//! it suitable for conditions that are checked by polling the hardware or reading atomics.
+//!
+//! [`struct wait_queue_head`]: ../../../include/linux/wait.h
+
+use super::{LockClassKey, NeedsLockClass};
+use crate::error::{code::ERESTARTSYS, Result};
+use crate::{bindings, str::CStr, task::Task, Opaque};
+use core::{marker::PhantomPinned, pin::Pin};
+
+/// Safely initialises a [`WaitQueue`] with the given name, generating a new lock class.
+#[macro_export]
+macro_rules! waitqueue_init {
+    ($waitqueue:expr, $name:literal) => {
+        $crate::init_with_lockdep!($waitqueue, $name)
+    };
+}
+
+/// Exposes the kernel's [`struct wait_queue_head`].
+///
+/// Waiters sleep until a condition closure returns `true`; the condition is evaluated again every
+/// time the queue is woken up with [`WaitQueue::wake_up`] or [`WaitQueue::wake_up_all`].
+///
+/// As with `wait_event`, the condition is evaluated after the task state has been set, so it must
+/// not sleep (e.g. it can't access an I2C device or take a mutex).
+///
+/// [`struct wait_queue_head`]: ../../../include/linux/wait.h
+pub struct WaitQueue {
+    pub(crate) wait_list: Opaque<bindings::wait_queue_head>,
+
+    /// A wait queue needs to be pinned because it contains a [`struct list_head`] that is
+    /// self-referential, so it cannot be safely moved once it is initialised.
+    _pin: PhantomPinned,
+}
+
+// SAFETY: `WaitQueue` only uses a `struct wait_queue_head`, which is safe to use on any thread.
+#[allow(clippy::non_send_fields_in_send_ty)]
+unsafe impl Send for WaitQueue {}
+
+// SAFETY: `WaitQueue` only uses a `struct wait_queue_head`, which is safe to use on multiple
+// threads concurrently, including from interrupt context for wake-ups.
+unsafe impl Sync for WaitQueue {}
+
+impl WaitQueue {
+    /// Constructs a new wait queue.
+    ///
+    /// # Safety
+    ///
+    /// The caller must call `WaitQueue::init` before using the wait queue.
+    pub const unsafe fn new() -> Self {
+        Self {
+            wait_list: Opaque::uninit(),
+            _pin: PhantomPinned,
+        }
+    }
+
+    /// Sleeps until `condition` returns `true`, like `wait_event_interruptible`.
+    ///
+    /// # Returns
+    /// - `Ok(())` once the condition is true.
+    /// - `Err(ERESTARTSYS)` if a signal is received first.
+    pub fn wait_interruptible(&self, mut condition: impl FnMut() -> bool) -> Result {
+        // MAX_SCHEDULE_TIMEOUT, the sleep is only ended by a wake-up or a signal
+        self.wait(core::ffi::c_long::MAX, &mut condition).map(|_| ())
+    }
+
+    /// Sleeps until `condition` returns `true` or `timeout` jiffies elapse, like
+    /// `wait_event_interruptible_timeout`.
+    ///
+    /// # Returns
+    /// - `Ok(true)` if the condition is true.
+    /// - `Ok(false)` if the timeout elapsed with the condition still false.
+    /// - `Err(ERESTARTSYS)` if a signal is received first.
+    pub fn wait_interruptible_timeout(
+        &self,
+        timeout: core::ffi::c_long,
+        mut condition: impl FnMut() -> bool,
+    ) -> Result<bool> {
+        self.wait(timeout, &mut condition)
+    }
+
+    fn wait(
+        &self,
+        mut timeout: core::ffi::c_long,
+        condition: &mut dyn FnMut() -> bool,
+    ) -> Result<bool> {
+        if condition() {
+            return Ok(true);
+        }
+
+        let wait = Opaque::<bindings::wait_queue_entry>::uninit();
+        // SAFETY: `wait` points to valid memory.
+        unsafe { bindings::init_wait(wait.get()) };
+
+        let ret = loop {
+            // SAFETY: Both `wait` and `wait_list` point to valid memory.
+            unsafe {
+                bindings::prepare_to_wait(
+                    self.wait_list.get(),
+                    wait.get(),
+                    bindings::TASK_INTERRUPTIBLE as _,
+                )
+            };
+
+            // Checked after queueing, so a wake-up between the check and the sleep isn't lost
+            if condition() {
+                break Ok(true);
+            }
+            if Task::current().signal_pending() {
+                break Err(ERESTARTSYS);
+            }
+            if timeout == 0 {
+                break Ok(false);
+            }
+
+            // SAFETY: The task is queued and in TASK_INTERRUPTIBLE state.
+            timeout = unsafe { bindings::schedule_timeout(timeout) };
+        };
+
+        // SAFETY: Both `wait` and `wait_list` point to valid memory.
+        unsafe { bindings::finish_wait(self.wait_list.get(), wait.get()) };
+        ret
+    }
+
+    /// Calls the kernel function to wake up the appropriate number of waiters.
+    fn wake(&self, count: i32) {
+        // SAFETY: `wait_list` points to valid memory.
+        unsafe {
+            bindings::__wake_up(
+                self.wait_list.get(),
+                bindings::TASK_NORMAL,
+                count,
+                core::ptr::null_mut(),
+            )
+        };
+    }
+
+    /// Wakes up a single exclusive waiter and all non-exclusive ones, like `wake_up`.
+    ///
+    /// It can be called from interrupt context.
+    pub fn wake_up(&self) {
+        self.wake(1);
+    }
+
+    /// Wakes up all waiters, like `wake_up_all`.
+    pub fn wake_up_all(&self) {
+        self.wake(0);
+    }
+}
+
+impl NeedsLockClass for WaitQueue {
+    fn init(
+        self: Pin<&mut Self>,
+        name: &'static CStr,
+        key: &'static LockClassKey,
+        _: &'static LockClassKey,
+    ) {
+        // SAFETY: `wait_list` points to valid memory, it is initialised only once here.
+        unsafe {
+            bindings::__init_waitqueue_head(self.wait_list.get(), name.as_char_ptr(), key.get())
+        };
+    }
+}
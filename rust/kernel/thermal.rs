@@ -0,0 +1,53 @@
+// thermal.rs
+
+//! Thermal zones, as seen by a consumer.
+//!
+//! This module provides what a driver needs to follow the temperature of a thermal zone it
+//! doesn't own: looking the zone up by name and reading its temperature. Zones are not reference
+//! counted, a zone stays valid until its provider unregisters it, which for the usual providers
+//! (SoC sensors, ACPI) only happens at shutdown.
+//!
+//! C header: [`include/linux/thermal.h`](../../../../include/linux/thermal.h)
+
+use crate::bindings;
+use crate::error::{from_kernel_err_ptr, to_result, Result};
+use crate::str::CStr;
+
+/// A thermal zone registered by another driver.
+///
+/// # Invariants
+/// - `tz` is a valid pointer returned by `thermal_zone_get_zone_by_name`.
+pub struct ThermalZone {
+    tz: *mut bindings::thermal_zone_device,
+}
+
+// SAFETY: The thermal core serializes the accesses to a zone with its own lock.
+unsafe impl Send for ThermalZone {}
+
+// SAFETY: `ThermalZone` exposes no interior mutability, see above.
+unsafe impl Sync for ThermalZone {}
+
+impl ThermalZone {
+    /// Looks up the zone named `name`, e.g. `cpu-thermal`.
+    ///
+    /// # Returns
+    /// - `Ok(ThermalZone)` if a zone has this name.
+    /// - `Err(ENODEV)` if there is none, `Err(EINVAL)` if the name is empty.
+    pub fn by_name(name: &CStr) -> Result<Self> {
+        // SAFETY: `name` is a valid NUL terminated string.
+        let tz = from_kernel_err_ptr(unsafe {
+            bindings::thermal_zone_get_zone_by_name(name.as_char_ptr())
+        })?;
+        Ok(Self { tz })
+    }
+
+    /// Reads the temperature of the zone, in millidegrees Celsius.
+    ///
+    /// It may sleep, the zone lock is taken and the sensor may be read on a bus.
+    pub fn temperature(&self) -> Result<i32> {
+        let mut temp = 0;
+        // SAFETY: `tz` is valid by the type invariants.
+        to_result(unsafe { bindings::thermal_zone_get_temp(self.tz, &mut temp) })?;
+        Ok(temp)
+    }
+}
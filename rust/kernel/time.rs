@@ -0,0 +1,91 @@
+// time.rs
+
+//! Kernel time support.
+//!
+//! This module provides a minimal abstraction over the kernel timekeeping accessors, allowing
+//! drivers to timestamp events against one of the standard kernel clocks.
+//!
+//! C header: [`include/linux/timekeeping.h`](../../../../include/linux/timekeeping.h)
+
+use crate::bindings;
+
+/// Number of nanoseconds in one second.
+pub const NSEC_PER_SEC: u64 = 1_000_000_000;
+
+/// Number of nanoseconds in one millisecond.
+pub const NSEC_PER_MSEC: u64 = 1_000_000;
+
+/// Number of nanoseconds in one microsecond.
+pub const NSEC_PER_USEC: u64 = 1_000;
+
+/// The kernel clocks that can be sampled through [`ClockId::now_ns`].
+///
+/// The numeric values match the `CLOCK_*` ids used by user space (see `clock_gettime(2)`), so
+/// they can be exchanged directly through ioctls.
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ClockId {
+    /// Wall-clock time (`CLOCK_REALTIME`), it can jump when the system time is set.
+    Realtime = 0,
+    /// Monotonic time since boot, not counting suspend (`CLOCK_MONOTONIC`).
+    Monotonic = 1,
+    /// Monotonic time since boot, including time spent in suspend (`CLOCK_BOOTTIME`).
+    Boottime = 7,
+}
+
+impl ClockId {
+    /// Converts a raw `CLOCK_*` id into a [`ClockId`].
+    ///
+    /// # Returns
+    /// - `Some(ClockId)` if the id is one of the supported clocks.
+    /// - `None` otherwise.
+    pub const fn from_raw(id: u32) -> Option<Self> {
+        match id {
+            0 => Some(ClockId::Realtime),
+            1 => Some(ClockId::Monotonic),
+            7 => Some(ClockId::Boottime),
+            _ => None,
+        }
+    }
+
+    /// Returns the raw `CLOCK_*` id of this clock.
+    pub const fn as_raw(self) -> u32 {
+        self as u32
+    }
+
+    /// Reads the current time of this clock, in nanoseconds.
+    pub fn now_ns(self) -> u64 {
+        match self {
+            ClockId::Realtime => ktime_get_real_ns(),
+            ClockId::Monotonic => ktime_get_ns(),
+            ClockId::Boottime => ktime_get_boottime_ns(),
+        }
+    }
+}
+
+/// Returns the current `CLOCK_MONOTONIC` time in nanoseconds.
+pub fn ktime_get_ns() -> u64 {
+    // SAFETY: `ktime_get_ns` has no preconditions and can be called from any context.
+    unsafe { bindings::ktime_get_ns() }
+}
+
+/// Returns the current `CLOCK_BOOTTIME` time in nanoseconds.
+pub fn ktime_get_boottime_ns() -> u64 {
+    // SAFETY: `ktime_get_boottime_ns` has no preconditions and can be called from any context.
+    unsafe { bindings::ktime_get_boottime_ns() }
+}
+
+/// Returns the current `CLOCK_REALTIME` time in nanoseconds.
+pub fn ktime_get_real_ns() -> u64 {
+    // SAFETY: `ktime_get_real_ns` has no preconditions and can be called from any context.
+    unsafe { bindings::ktime_get_real_ns() }
+}
+
+/// Converts a duration in milliseconds to jiffies, rounding up.
+///
+/// The result is the timeout expected by the kernel sleeping functions, e.g.
+/// [`crate::sync::WaitQueue::wait_interruptible_timeout`].
+pub fn msecs_to_jiffies(ms: u32) -> core::ffi::c_ulong {
+    // SAFETY: `msecs_to_jiffies` has no preconditions and can be called from any context.
+    unsafe { bindings::msecs_to_jiffies(ms) }
+}
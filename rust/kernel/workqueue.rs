@@ -105,6 +105,64 @@ macro_rules! init_work_item_adapter {
     }};
 }
 
+/// Implements the [`DelayedWorkAdapter`] trait for a type where its [`DelayedWork`] instance is a
+/// field.
+///
+/// # Examples
+///
+/// ```
+/// # use kernel::workqueue::DelayedWork;
+///
+/// struct Example {
+///     dwork: DelayedWork,
+/// }
+///
+/// kernel::impl_self_delayed_work_adapter!(Example, dwork, |_| {});
+/// ```
+#[macro_export]
+macro_rules! impl_self_delayed_work_adapter {
+    ($work_type:ty, $field:ident, $closure:expr) => {
+        $crate::impl_delayed_work_adapter!($work_type, $work_type, $field, $closure);
+    };
+}
+
+/// Implements the [`DelayedWorkAdapter`] trait for an adapter type.
+#[macro_export]
+macro_rules! impl_delayed_work_adapter {
+    ($adapter:ty, $work_type:ty, $field:ident, $closure:expr) => {
+        // SAFETY: We use `offset_of` to ensure that the field is within the given type, and we
+        // also check its type is `DelayedWork`.
+        unsafe impl $crate::workqueue::DelayedWorkAdapter for $adapter {
+            type Target = $work_type;
+            const FIELD_OFFSET: isize = $crate::offset_of!(Self::Target, $field);
+            fn run(w: $crate::sync::Arc<Self::Target>) {
+                let closure: fn($crate::sync::Arc<Self::Target>) = $closure;
+                closure(w);
+                return;
+
+                // Checks that the type of the field is actually `DelayedWork`.
+                let tmp = core::mem::MaybeUninit::<$work_type>::uninit();
+                // SAFETY: The pointer is valid and aligned, just not initialised; `addr_of`
+                // ensures that we don't actually read from it (which would be UB) nor create an
+                // intermediate reference.
+                let _x: *const $crate::workqueue::DelayedWork =
+                    unsafe { core::ptr::addr_of!((*tmp.as_ptr()).$field) };
+            }
+        }
+    };
+}
+
+/// Initialises a delayed work item.
+///
+/// It automatically defines a new lockdep lock class for the work item.
+#[macro_export]
+macro_rules! init_delayed_work_item {
+    ($work_container:expr) => {{
+        static CLASS: $crate::sync::LockClassKey = $crate::sync::LockClassKey::new();
+        $crate::workqueue::DelayedWork::init($work_container, &CLASS)
+    }};
+}
+
 /// A kernel work queue.
 ///
 /// Wraps the kernel's C `struct workqueue_struct`.
@@ -264,6 +322,86 @@ impl Queue {
         ret
     }
 
+    /// Enqueues a delayed work item, to run after `delay` jiffies.
+    ///
+    /// Returns `true` if the work item was successfully enqueued; returns `false` if it had already
+    /// been (and continued to be) enqueued, in which case the pending delay is not modified.
+    pub fn enqueue_delayed<T: DelayedWorkAdapter<Target = T>>(
+        &self,
+        w: Arc<T>,
+        delay: core::ffi::c_ulong,
+    ) -> bool {
+        self.enqueue_delayed_adapter::<T>(w, delay)
+    }
+
+    /// Enqueues a delayed work item with an explicit adapter, to run after `delay` jiffies.
+    ///
+    /// Returns `true` if the work item was successfully enqueued; returns `false` if it had already
+    /// been (and continued to be) enqueued.
+    pub fn enqueue_delayed_adapter<A: DelayedWorkAdapter + ?Sized>(
+        &self,
+        w: Arc<A::Target>,
+        delay: core::ffi::c_ulong,
+    ) -> bool {
+        let ptr = Arc::into_raw(w);
+        let field_ptr =
+            (ptr as *const u8).wrapping_offset(A::FIELD_OFFSET) as *mut bindings::delayed_work;
+
+        // SAFETY: Same as in `enqueue_adapter`: the work item remains valid because we called
+        // `into_raw`, and `from_raw` is only called again if it was already queued, when it runs
+        // or when it is canceled.
+        let ret = unsafe {
+            bindings::queue_delayed_work_on(
+                bindings::WORK_CPU_UNBOUND as _,
+                self.0.get(),
+                field_ptr,
+                delay,
+            )
+        };
+
+        if !ret {
+            // SAFETY: `ptr` comes from a previous call to `into_raw` and `queue_delayed_work_on`
+            // returned `false`, so no-one is going to use it.
+            unsafe { Arc::from_raw(ptr) };
+        }
+
+        ret
+    }
+
+    /// Enqueues a delayed work item to run after `delay` jiffies, or changes the delay if it is
+    /// already enqueued, e.g. to run it right away.
+    ///
+    /// Returns `true` if the work item was already enqueued and its delay was modified; returns
+    /// `false` if it was idle and is now enqueued.
+    pub fn mod_delayed<T: DelayedWorkAdapter<Target = T>>(
+        &self,
+        w: Arc<T>,
+        delay: core::ffi::c_ulong,
+    ) -> bool {
+        let ptr = Arc::into_raw(w);
+        let field_ptr =
+            (ptr as *const u8).wrapping_offset(T::FIELD_OFFSET) as *mut bindings::delayed_work;
+
+        // SAFETY: As in `enqueue_delayed_adapter`, the work item remains valid because we called
+        // `into_raw`. `mod_delayed_work_on` can be called from interrupt context.
+        let pending = unsafe {
+            bindings::mod_delayed_work_on(
+                bindings::WORK_CPU_UNBOUND as _,
+                self.0.get(),
+                field_ptr,
+                delay,
+            )
+        };
+
+        if pending {
+            // SAFETY: `ptr` comes from a previous call to `into_raw`, and the work item already
+            // held a reference of its own, which is the one given back when it runs.
+            unsafe { Arc::from_raw(ptr) };
+        }
+
+        pending
+    }
+
     /// Tries to spawn the given function or closure as a work item.
     ///
     /// Users are encouraged to use [`spawn_work_item`] as it automatically defines the lock class
@@ -399,6 +537,100 @@ impl Work {
     }
 }
 
+/// An adapter for delayed work items.
+///
+/// It is the equivalent of [`WorkAdapter`] for [`DelayedWork`]; implementations should use the
+/// [`impl_self_delayed_work_adapter`] or [`impl_delayed_work_adapter`] macros.
+///
+/// # Safety
+///
+/// Implementers must ensure that there is a [`DelayedWork`] instance `FIELD_OFFSET` bytes from
+/// the beginning of a valid `Target` type.
+pub unsafe trait DelayedWorkAdapter {
+    /// The type that this work adapter is meant to use.
+    type Target;
+
+    /// The offset, in bytes, from the beginning of [`Self::Target`] to the instance of
+    /// [`DelayedWork`].
+    const FIELD_OFFSET: isize;
+
+    /// Runs when the work item is picked up for execution, once its delay has expired.
+    fn run(w: Arc<Self::Target>);
+}
+
+/// A delayed work item.
+///
+/// Wraps the kernel's C `struct delayed_work`, a work item queued once a timer expires.
+///
+/// While queued, the work item holds a reference to the object containing it, so the object can't
+/// be freed before the work item runs or is canceled. Owners that must not outlive their work
+/// items (e.g. a driver being removed) call [`DelayedWork::cancel`], which also waits for a
+/// running instance to complete.
+#[repr(transparent)]
+pub struct DelayedWork(Opaque<bindings::delayed_work>);
+
+impl DelayedWork {
+    /// Creates a new instance of [`DelayedWork`].
+    ///
+    /// # Safety
+    ///
+    /// Callers must call [`DelayedWork::init`] before the work item can be used.
+    pub unsafe fn new() -> Self {
+        Self(Opaque::uninit())
+    }
+
+    /// Initialises the delayed work item.
+    ///
+    /// Users should prefer the [`init_delayed_work_item`] macro because it automatically defines a
+    /// new lock class key.
+    pub fn init<T: DelayedWorkAdapter<Target = T>>(obj: &UniqueArc<T>, key: &'static LockClassKey) {
+        let ptr = &**obj as *const _ as *const u8;
+        let field_ptr = ptr.wrapping_offset(T::FIELD_OFFSET) as *mut bindings::delayed_work;
+
+        // SAFETY: `field_ptr` is valid for writes -- the `UniqueArc` instance guarantees that it
+        // has been allocated and there is only one pointer to it. Additionally, `work_func` is a
+        // valid callback for the work item.
+        unsafe {
+            bindings::__INIT_DELAYED_WORK_WITH_KEY(
+                field_ptr,
+                Some(Self::work_func::<T>),
+                false,
+                key.get(),
+            )
+        };
+    }
+
+    /// Cancels the delayed work item and waits for it to complete if it is running.
+    ///
+    /// It is ok for this to be called when the work is not queued, and it also stops a work item
+    /// that queues itself again. `A` must be the adapter the work item was initialised with.
+    pub fn cancel<A: DelayedWorkAdapter>(&self) {
+        // SAFETY: The work is valid (we have a reference to it), and the function can be called
+        // whether the work is queued or not.
+        if unsafe { bindings::cancel_delayed_work_sync(self.0.get()) } {
+            let ptr = (self as *const Self as *const u8).wrapping_offset(-A::FIELD_OFFSET)
+                as *const A::Target;
+
+            // SAFETY: When the work was queued, a call to `into_raw` was made on the object
+            // containing it. We just canceled the work without it having the chance to run, so we
+            // need to explicitly destroy this reference (which would have happened in `work_func`
+            // if it did run).
+            unsafe { Arc::from_raw(ptr) };
+        }
+    }
+
+    unsafe extern "C" fn work_func<A: DelayedWorkAdapter>(work: *mut bindings::work_struct) {
+        // The `work_struct` is the first field of `delayed_work`, so both have the same address.
+        let field_ptr = work as *const _ as *const u8;
+        let ptr = field_ptr.wrapping_offset(-A::FIELD_OFFSET) as *const A::Target;
+
+        // SAFETY: This callback is only ever used by the `init` method, so it is always the case
+        // that the work item is embedded in a `DelayedWork` (Self) struct.
+        let w = unsafe { Arc::from_raw(ptr) };
+        A::run(w);
+    }
+}
+
 /// A boxed owned workqueue.
 ///
 /// # Invariants
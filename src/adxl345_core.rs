@@ -29,26 +29,196 @@ module! {
     type: Adxl345Module,
     name: "adxl345",
     author: "Luca Saverio Esposito",
-    description: "ADXL345 I2C driver in Rust",
+    description: "ADXL345 I2C/SPI driver in Rust",
     license: "GPL",
+    params: {
+        dry_run: bool {
+            default: false,
+            permissions: 0o444,
+            description: "Simulate the register map instead of accessing the I2C bus",
+        },
+        i2c_bus: i32 {
+            default: 1,
+            permissions: 0o444,
+            description: "I2C bus to create the device on if the firmware doesn't describe it, -1 to instantiate it from configfs",
+        },
+        i2c_addr: u32 {
+            default: 0x1d,
+            permissions: 0o444,
+            description: "I2C address of the device created on i2c_bus: 0x1d, or 0x53 with ALT ADDRESS low",
+        },
+        scan_buses: ArrayParam<i32, 8> {
+            default: [],
+            permissions: 0o444,
+            description: "I2C buses scanned by writing 1 to /sys/module/adxl345/scan, e.g. 1,2; empty scans i2c_bus",
+        },
+        rate: u32 {
+            default: 100000,
+            permissions: 0o444,
+            description: "Output data rate programmed at probe, in mHz (100 to 3200000, doubling from 100)",
+        },
+        range: u32 {
+            default: 16,
+            permissions: 0o444,
+            description: "Measurement range programmed at probe, in g: 2, 4, 8 or 16",
+        },
+        filter_threshold: u32 {
+            default: 50, // ADXL345_FILTER_DEFAULT
+            permissions: 0o444,
+            description: "Initial threshold of the read filter of every device, in shifted LSBs (0 to 32767); ignored without the read filter",
+        },
+        probe_samples: u32 {
+            default: 1,
+            permissions: 0o444,
+            description: "Samples read at probe, 2 to 32 log their statistics in debugfs probe_health",
+        },
+        profile: str {
+            default: b"",
+            permissions: 0o444,
+            description: "Startup profile, e.g. rate=400000,range=4,fifo_mode=stream (overrides the device tree)",
+        },
+        data_gpio: ArrayParam<i32, 4> {
+            default: [],
+            permissions: 0o444,
+            description: "GPIO lines wired to INT1 of the devices, by id, e.g. 17,-1; drains on the FIFO watermark interrupt, a device without a line (or -1) drains on a timer only",
+        },
+        write_control: bool {
+            default: false,
+            permissions: 0o444,
+            description: "Accept text commands written to the device, e.g. rate 400000",
+        },
+        alarm_gpio: ArrayParam<i32, 4> {
+            default: [],
+            permissions: 0o444,
+            description: "GPIO lines driven high while a vibration alarm of the device is raised, by id; a device without a line (or -1) has no alarm output",
+        },
+        thermal_zone: str {
+            default: b"",
+            permissions: 0o444,
+            description: "Thermal zone guarding every device, e.g. cpu-thermal; empty disables the guard",
+        },
+        spi: bool {
+            default: false,
+            permissions: 0o444,
+            description: "Register an SPI driver for a device described by the firmware (compatible adi,adxl345)",
+        },
+    },
 }
 
 mod fileops;
+mod ioctl;
+mod sync_input;
+mod config;
+mod debugfs;
+mod dry_run;
+mod bus_trace;
+mod bus_usage;
+mod fault;
+mod drain;
+mod stats;
+mod spsc;
+mod snapshot;
+mod session;
+mod uevent;
+mod probe_health;
+mod gravity_watch;
+mod clip;
+mod auto_range;
+mod noise;
+mod profile;
+mod preset;
+mod instance;
+mod scan;
+mod batch_crc;
+mod poll;
+mod error_policy;
+mod fair_share;
+mod version;
+mod capabilities;
+mod fasync;
+mod control;
+mod data_irq;
+mod alarm;
+mod sysfs;
+mod sysfs_abi;
+mod resample;
+mod context;
+mod shadow;
+mod of_node;
+mod copyout;
+mod tap;
+mod burst;
+mod motion;
+mod power;
+mod correlation;
+mod calibration;
+mod transport_guard;
+mod output;
+mod fixed;
+mod concurrency;
+#[cfg(not(adxl345_no_filter))]
+mod filter;
+#[cfg_attr(adxl345_no_filter, allow(dead_code))] // The helpers of the filter stages
+mod sample_math;
+#[cfg(CONFIG_CONFIGFS_FS)]
+mod configfs;
+#[cfg(CONFIG_THERMAL)]
+mod thermal_guard;
+#[cfg(CONFIG_SPI)]
+mod spi;
 pub(crate) mod utility;
 pub(crate) mod structures;
 pub(crate) mod constant;
 
 use kernel::prelude::*;
-use kernel::sync::{Arc,SpinLock};
 use kernel::i2c::*;
-use kernel::{i2c_module_device_table,spinlock_init};
+use kernel::sync::Arc;
+use kernel::{i2c_module_device_table,init_with_lockdep};
 use crate::constant::*;
-use crate::structures::{Adxl345Driver, Adxl345};
+use crate::structures::Adxl345Driver;
 use crate::utility::{adxl345_device_init,adxl345_device_clean};
-use crate::fileops::{adxl345_chardev_add, DEVICE_PTR};
+use crate::config::{adxl345_validate, Adxl345Param};
+#[cfg(not(adxl345_no_filter))]
+use crate::filter::adxl345_filter_initial_set;
+use crate::fileops::{adxl345_chardev_add, ADXL345_CHARDEV_NAMES, ADXL345_PROBED, ADXL345_MODULE};
+use crate::context::{adxl345_context, adxl345_context_publish, Adxl345Context};
+use crate::sync_input::adxl345_sync_detached;
+use crate::debugfs::adxl345_debugfs_create;
+use crate::dry_run::ADXL345_DRY_RUN;
+use crate::control::adxl345_control_enable;
+use crate::data_irq::adxl345_data_irq_attach;
+use crate::alarm::{adxl345_alarm, adxl345_alarm_attach};
+use crate::tap::adxl345_tap;
+use crate::motion::adxl345_motion;
+use crate::power::{adxl345_power, adxl345_power_resume, adxl345_power_suspend};
+use crate::calibration::adxl345_calibration;
+use crate::sysfs::adxl345_device_sysfs_create;
+use crate::shadow::adxl345_shadow;
+use crate::drain::Adxl345Drain;
+use crate::snapshot::{adxl345_snapshot, adxl345_snapshot_clear_all, adxl345_snapshot_initial_set, adxl345_snapshot_refresh};
+use crate::profile::Adxl345Profile;
+use crate::ioctl::ADXL345_CONFIG_LOCK;
+use crate::instance::{adxl345_bind, adxl345_bound, adxl345_instance_create, adxl345_instance_destroy_all, adxl345_release, ADXL345_INSTANCE_LOCK};
+use crate::of_node::adxl345_of_gpio;
+use crate::version::{adxl345_sysfs_create, Adxl345Sysfs};
+use crate::scan::adxl345_scan_buses_set;
+use crate::capabilities::{adxl345_caps_set, adxl345_device_caps_clear, adxl345_device_caps_set};
+use crate::capabilities::{ADXL345_CAP_ALARM_GPIO, ADXL345_CAP_DATA_IRQ, ADXL345_CAP_DEBUGFS};
+#[cfg(CONFIG_CONFIGFS_FS)]
+use crate::capabilities::ADXL345_CAP_CONFIGFS;
+#[cfg(CONFIG_CONFIGFS_FS)]
+use crate::configfs::{adxl345_configfs_register, Adxl345Configfs};
+#[cfg(CONFIG_THERMAL)]
+use crate::capabilities::ADXL345_CAP_THERMAL_GUARD;
+#[cfg(CONFIG_THERMAL)]
+use crate::thermal_guard::{Adxl345ThermalGuard, ADXL345_THERMAL};
+#[cfg(CONFIG_SPI)]
+use crate::capabilities::ADXL345_CAP_SPI;
+#[cfg(CONFIG_SPI)]
+use crate::spi::{adxl345_spi_register, Adxl345Spi};
+#[cfg(CONFIG_SPI)]
+use kernel::spi::SpiDriverRegistration;
 
-// Define the I2C board information with device name and address.
-static ADXL345_BOARD_INFO: I2CBoardInfo = I2CBoardInfo::new(DR_NAME, ADXL345_I2C_ADDR); // 0x1D is the address for ADXL345
 
 
 // Define the I2C device ID table for this driver.
@@ -63,24 +233,140 @@ static ADXL345_ID_TABLE: [I2CDeviceID; ID_TABLE_LEN] = [
 i2c_module_device_table!(ADXL345_ID_TABLE, ID_TABLE_LEN);
 
 
+// Define the device tree match table, the I2C core creates a client for each matching node.
+#[cfg(CONFIG_OF)]
+const OF_TABLE_LEN: usize = 2;
+#[cfg(CONFIG_OF)]
+static ADXL345_OF_TABLE: [I2COfDeviceID; OF_TABLE_LEN] = [
+    I2COfDeviceID::new(b"adi,adxl345"),
+    I2COfDeviceID::new(b""), // Empty entry to mark the end of the table
+];
+
+// Expose the match table to the kernel module loader, to load the module for these nodes.
+#[cfg(CONFIG_OF)]
+kernel::i2c_of_module_device_table!(ADXL345_OF_TABLE, OF_TABLE_LEN);
+
+
+// The device state is built on its bus by instance.rs when a client is bound, probe and remove
+// are the same on I2C and on SPI (see spi.rs)
 impl I2CDriverCallbacks for Adxl345Driver{
     fn probe(&self, _client: &I2CClient) -> Result {
-        pr_info!("ADXL345 probe function called for device\n");
-        
+        self.probe_device()
+    }
+
+    fn remove(&self, _client: &I2CClient){
+        self.remove_device()
+    }
+
+    fn suspend(&self, _client: &I2CClient) -> Result {
+        adxl345_power_suspend(self.device())
+    }
+
+    fn resume(&self, _client: &I2CClient) -> Result {
+        adxl345_power_resume(self.device())
+    }
+
+    fn instantiate(client: &I2CClient) -> Result<*mut Self> {
+        // SAFETY: The client stays registered while the driver is bound to it, the state
+        // holding this copy is dropped by `release` once it is removed.
+        let client = unsafe { I2CClient::from_raw_ptr(client.as_ptr()) };
+        adxl345_bind(Box::try_new(client)?)
+    }
+
+    fn release(client: &I2CClient) {
+        adxl345_release(client.raw_device());
+    }
+}
+
+/// Registers the I2C driver, which binds the devices of the device tree and the clients created
+/// by instance.rs.
+fn adxl345_i2c_driver_register(module: &'static ThisModule) -> Result<I2CDriver> {
+    let driver_name = CStr::from_bytes_with_nul(DR_NAME_WN).unwrap().as_ptr() as *const i8;
+    let builder = I2CDriverBuilder::<Adxl345Driver>::new(
+        __I2C_DEVICE_TABLE_BINDINGS.as_ptr(),
+        driver_name,
+        module.as_ptr(),
+    );
+    #[cfg(CONFIG_OF)]
+    let builder = builder.of_match_table(I2COfDeviceID::table_ptr(&ADXL345_OF_TABLE));
+
+    // Stop the session and put the device in standby across system sleep, see power.rs
+    let builder = builder.pm_ops();
+
+    // Build driver structure, then add it
+    let driver = builder.build()?;
+    driver.add_driver()?;
+    Ok(driver)
+}
+
+impl Adxl345Driver {
+    /// Brings up the device whose state was built on its bus.
+    pub (crate) fn probe_device(&self) -> Result {
+        let id = self.device().lock().id();
+        pr_info!("ADXL345 probe function called for device {}\n", id);
+
+        // The shadow starts over with the writes of this device
+        adxl345_shadow(id).clear();
+
         {
             // Clone the Ref to the device (so increment the ref counter by one)
             let device = self.device().clone();   
             // Initialize the device (implement this method in `Adxl345`)
-            adxl345_device_init(device).map_err(|_| EIO).expect("Failed Device initialization");
+            adxl345_device_init(device, *probe_samples.read(), *rate.read(), *range.read())
+                .map_err(|_| EIO)?;
         }
+
+        // A failing step leaves the device in standby, without its character device
+        let (drain, context) = match self.probe_setup(id) {
+            Ok(setup) => setup,
+            Err(e) => {
+                let registration = self.device().lock().registration.take();
+                drop(registration);
+                if let Err(e) = adxl345_device_clean(self.device().clone()) {
+                    pr_err!("Failed to put the device in standby after a failed probe: {:?}\n", e);
+                }
+                return Err(e);
+            }
+        };
+
+        // The interrupt lines and the thermal guard of the device, if given
+        self.probe_lines(id, drain);
+
+        // Publish the context the files are opened on, see context.rs
+        adxl345_context_publish(id, Some(context));
+
+        // Configuration attributes of the client, their callbacks look the context up above
+        match adxl345_device_sysfs_create(self.device()) {
+            Ok(sysfs) => self.device().lock().sysfs = Some(sysfs),
+            Err(e) => pr_warn!("Failed to create the sysfs attributes: {:?}\n", e),
+        }
+
+        // Let open() through, the device state is now complete
+        unsafe{ADXL345_PROBED[id].complete_all()};
+        Ok(())
+    }
+
+    /// Configures the initialized device, registers its character device and creates its drain
+    /// and context, the fallible steps of probe. `probe_device` undoes them on error.
+    fn probe_setup(&self, id: usize) -> Result<(Arc<Adxl345Drain>, Arc<Adxl345Context>)> {
+        // The offsets calibrated or set before a rebind still hold for the same mounting
+        adxl345_calibration(id).apply(&self.device().lock())?;
+
+        // Apply the startup profile in one go, before the configuration is published
+        if let Some(profile) = Adxl345Profile::load(profile.read(), self.device()) {
+            profile.apply(&self.device().lock())?;
+        }
+
+        // Publish the configuration used by the data path
+        adxl345_snapshot_refresh(self.device())?;
         
 
-        // Register the character device
+        // Register the character device, its minor is the id of the device (see instance.rs)
         let registration = adxl345_chardev_add(
-            CStr::from_bytes_with_nul(DR_NAME_WN).unwrap(),
-            0, // Starting minor number
+            ADXL345_CHARDEV_NAMES[id],
+            id as u16,
             self.this_module(),
-        ).expect("Failed during chard dev registration");
+        )?;
 
         pr_info!("adxl345driver address {:p} \n", self);
 
@@ -92,14 +378,122 @@ impl I2CDriverCallbacks for Adxl345Driver{
             device_lock.registration = Some(registration);
         }
 
-        let device_arc = self.device.clone();
-        // Save into the global variable for fileops
-        unsafe{DEVICE_PTR =  Some(device_arc)};
-        Ok(())
+        // Create the drain before the device is published, open starts it
+        let drain = Adxl345Drain::try_new(self.device().clone())?;
+        let context = Adxl345Context::try_new(self.device().clone(), drain.clone())?;
+        Ok((drain, context))
     }
 
-    fn remove(&self, _client: &I2CClient){
-        pr_info!("ADXL345 remove function called for device\n");
+    /// Attaches the data interrupt, alarm line and thermal guard of device `id` if given, their
+    /// handlers hold a reference to its drain.
+    fn probe_lines(&self, id: usize, drain: Arc<Adxl345Drain>) {
+        // Drain on the watermark interrupt if a data line is given, else on the timer only. The
+        // lines come from the module parameters, by id, or else from the device tree node
+        let data_line = data_gpio.read().get(id).and_then(|&gpio| u32::try_from(gpio).ok())
+            .or_else(|| adxl345_of_gpio(self.device(), kernel::c_str!("int1-gpios")));
+        if let Some(gpio) = data_line {
+            match adxl345_data_irq_attach(gpio, self.device(), &drain) {
+                Ok(registration) => {
+                    self.device().lock().data_irq = Some(registration);
+                    adxl345_device_caps_set(id, ADXL345_CAP_DATA_IRQ);
+                }
+                Err(e) => pr_warn!("Data interrupt not available, draining on the timer: {:?}\n", e),
+            }
+        }
+
+        // Drive the alarm line from the drain if one is given
+        let alarm_line = alarm_gpio.read().get(id).and_then(|&gpio| u32::try_from(gpio).ok())
+            .or_else(|| adxl345_of_gpio(self.device(), kernel::c_str!("alarm-gpios")));
+        if let Some(gpio) = alarm_line {
+            match adxl345_alarm_attach(gpio, self.device()) {
+                Ok(line) => {
+                    // SAFETY: The drain of the device is not started before probe completes.
+                    unsafe { adxl345_alarm(id).set_line(Some(line)) };
+                    adxl345_device_caps_set(id, ADXL345_CAP_ALARM_GPIO);
+                }
+                Err(e) => pr_warn!("Alarm line not available: {:?}\n", e),
+            }
+        }
+
+        // Guard the sensor against a hot enclosure if a thermal zone is given
+        #[cfg(CONFIG_THERMAL)]
+        if !thermal_zone.read().is_empty() {
+            match Adxl345ThermalGuard::start(thermal_zone.read(), self.device().clone(), drain) {
+                Ok(guard) => {
+                    unsafe{ADXL345_THERMAL[id] = Some(guard)};
+                    adxl345_device_caps_set(id, ADXL345_CAP_THERMAL_GUARD);
+                }
+                Err(e) => pr_warn!("Thermal zone not available, the guard is off: {:?}\n", e),
+            }
+        }
+    }
+
+    /// Tears the device down, before its state is dropped.
+    pub (crate) fn remove_device(&self) {
+        let id = self.device().lock().id();
+        pr_info!("ADXL345 remove function called for device {}\n", id);
+
+        // The teardown goes from the producers of events to their consumers, so nothing is
+        // left that could wake up or feed a reader once the device is gone:
+        // 1. no new open, 2. no thermal guard, sysfs attribute, sync or data interrupt, 3. no drain
+        // work and every reader woken up, 4. device in standby, 5. char device deregistered, 6. globals cleared.
+        // Open files keep working on their own references and fail with ENODEV.
+
+        // New opens wait again, until a new probe publishes the device state
+        unsafe{ADXL345_PROBED[id].reinit()};
+
+        // Stop the thermal guard and remove the sysfs attributes before taking the configuration
+        // lock, the guard check and the attribute writes take it too. Removing the attributes
+        // sleeps until their callbacks return, which take the device lock, so the group is dropped
+        // outside of it
+        #[cfg(CONFIG_THERMAL)]
+        if let Some(guard) = unsafe { ADXL345_THERMAL[id].take() } {
+            guard.stop();
+        }
+        let sysfs = self.device().lock().sysfs.take();
+        drop(sysfs);
+
+        {
+            // The configuration lock keeps open() and the ioctls from starting a session or
+            // attaching a sync input again once they are torn down
+            // SAFETY: The lock is initialized at module init.
+            let _config = unsafe { ADXL345_CONFIG_LOCK.lock() };
+
+            // Detach the sync input, the interrupt is freed outside of the device lock as in
+            // ADXL345_IOC_SET_SYNC.
+            // Freeing it waits for a running handler, so no pulse is reported from now on
+            let device = self.device().clone();
+            let sync_irq = device.lock().sync_irq.take();
+            drop(sync_irq);
+            adxl345_sync_detached(id);
+
+            // Free the data interrupt the same way, so the drain is no longer queued by it
+            let data_irq = device.lock().data_irq.take();
+            drop(data_irq);
+
+            // Cancel the drain and wait for it, so no work item touches the device from now on,
+            // and wake up the blocked readers
+            if let Ok(context) = adxl345_context(id) {
+                context.drain.shutdown();
+            }
+
+            // Release the alarm line, nothing drives it once the drain is gone
+            let alarm = adxl345_alarm(id);
+            alarm.release();
+            // SAFETY: The drain was shut down above.
+            unsafe { alarm.set_line(None) };
+
+            // The next device in this slot starts without tap reporting nor motion events, as
+            // its INT_ENABLE does, and at full power
+            adxl345_tap(id).reset();
+            adxl345_motion(id).reset();
+            adxl345_power(id).reset();
+            adxl345_device_caps_clear(id);
+
+            // Its data path starts from the module parameters too, not from the rate, range and
+            // read filter of this one. It sleeps for a grace period
+            adxl345_snapshot(id).clear();
+        }
 
         // Clone the Ref to the device (so take a increment the ref counter by one)
         {
@@ -124,81 +518,185 @@ impl I2CDriverCallbacks for Adxl345Driver{
         
         // The data inside i2c-client are automatically dropped by the remove_callback
         
-        // Clean up the global pointer:
-        unsafe {
-            DEVICE_PTR = None;
-        }
+        // Clean up the global pointers, open files keep the context they were opened on
+        adxl345_context_publish(id, None);
         pr_info!("ADXL345 device successfully removed\n");
     }
 }
 
 struct Adxl345Module{
-    the_driver: Pin<Box<Adxl345Driver>>,
+    i2c_driver: I2CDriver,
+    _debugfs: Option<kernel::debugfs::Dir>,
+    sysfs: Option<Box<Adxl345Sysfs>>,
+    #[cfg(CONFIG_CONFIGFS_FS)]
+    configfs: Option<Box<Adxl345Configfs>>,
+    #[cfg(CONFIG_SPI)]
+    spi_driver: Option<Pin<Box<SpiDriverRegistration<Adxl345Spi>>>>,
 }
 
 impl kernel::Module for Adxl345Module {
     fn init(_name: &'static CStr, module: &'static ThisModule) -> Result<Self> {
         pr_info!("ADXL345 Rust driver initializing\n");
 
-        // Initialize I2C adapter and create a new device
-        let i2c_adapter = I2CAdapter::get_from_bus_number(ADXL345_I2C_ADAPTER).expect("Can't get the adapter"); 
-        
-        // This i2c_client instance is owned by Rust subsystem, so will be dropped
-        // automatically when the module will be removed by the drop trait of I2CClient struct.
-        let i2c_client = I2CClient::new_client_device(&i2c_adapter, &ADXL345_BOARD_INFO).expect("Cant take I2C-Client");
-        
-        let mut spin_adxl345 = unsafe{SpinLock::new(Adxl345::new(i2c_client))};
-
-        // Init the spinlock
-        spinlock_init!(unsafe { Pin::new_unchecked(&mut spin_adxl345)}, "adxl345");
-
-        // Create the shared `Adxl345` instance wrapped in an `Arc`
-        let device = Arc::try_new(spin_adxl345).expect("Failed during Arc creation");
-
-        // Pin ensure that the driver doesn't move, this constraint is mandatory due the 
-        // necessity of retrieving driver with i2c_get_clientdata.
-        let mut adxl345driver = Pin::from(Box::try_new(Adxl345Driver::new(device, module))
-        .expect("Failed to allocate Adxl345Driver"));
-
-        {    
-            // Is mandatory to take all the steps separately, otherwise the borrow checker cries :/
-            //let adxl_device = adxl345driver.device.clone();
-            let adxl_device = adxl345driver.device.clone();
-            let adxl_lock = adxl_device.lock();
-            let i2c_client = adxl_lock.client();
-            // Set the `clientdata` to point to the `adxl345driver` instance
-            // This will be freed automatically by remove callback (see i2c/driver.rs/remove_callback)
-            i2c_client.set_clientdata::<Adxl345Driver>(unsafe{adxl345driver.as_mut().get_unchecked_mut()});
-        }
-
-        // Use I2CDriverBuilder to create and register the driver with probe and remove callbacks
-        let driver_name = CStr::from_bytes_with_nul(DR_NAME_WN).unwrap().as_ptr() as *const i8;
-
-        let builder = I2CDriverBuilder::<Adxl345Driver>::new(
-            __I2C_DEVICE_TABLE_BINDINGS.as_ptr(),
-            driver_name, 
-            module.as_ptr(), 
-        );
-
-        // Build driver structure, then add it
-        let driver = builder.build().expect("Failed I2C Driver build");
-
-        driver.add_driver().expect("Failed when adding driver");
-    
-        // Store I2CDriver structure inside Adxl345Driver
-        adxl345driver.as_mut().set_driver_pinned(driver);
+        // The parameters of the device are checked before anything is set up, a device bound
+        // while the driver registers is programmed with them
+        if !ADXL345_I2C_ADDRS.iter().any(|&addr| addr as u32 == *i2c_addr.read()) {
+            pr_err!("i2c_addr {:#x} is not an address of the ADXL345, 0x1d or 0x53\n", *i2c_addr.read());
+            return Err(EINVAL);
+        }
+        if adxl345_validate(Adxl345Param::Rate, *rate.read()).is_err() {
+            pr_err!("rate {} mHz is not supported by the device\n", *rate.read());
+            return Err(EINVAL);
+        }
+        if adxl345_validate(Adxl345Param::Range, *range.read()).is_err() {
+            pr_err!("range {} g is not supported by the device\n", *range.read());
+            return Err(EINVAL);
+        }
+        adxl345_snapshot_initial_set(*rate.read(), *range.read());
+        #[cfg(not(adxl345_no_filter))]
+        if adxl345_filter_initial_set(*filter_threshold.read()).is_err() {
+            pr_err!("filter_threshold {} is above {}\n", *filter_threshold.read(), i16::MAX);
+            return Err(EINVAL);
+        }
+        #[cfg(adxl345_no_filter)]
+        if *filter_threshold.read() != ADXL345_FILTER_DEFAULT {
+            pr_warn!("The read filter is not built in, the filter_threshold parameter is ignored\n");
+        }
+
+        // In dry-run mode the client is still created, but no transfer is ever issued on it
+        if *dry_run.read() {
+            ADXL345_DRY_RUN.enable();
+        }
+
+        // Write access to the device, see control.rs
+        if *write_control.read() {
+            adxl345_control_enable();
+        }
+
+        // Buses of the runtime scan, see scan.rs
+        match scan_buses.read() {
+            [] => adxl345_scan_buses_set(&[*i2c_bus.read()]),
+            buses => adxl345_scan_buses_set(buses),
+        }
+
+        // Open files hold a reference to the module, see fileops.rs
+        unsafe { ADXL345_MODULE = Some(module) };
+
+        // Init the completion open() waits on, the configuration lock and the instance lock,
+        // before the device can be opened
+        for probed in unsafe { ADXL345_PROBED.iter_mut() } {
+            unsafe { Pin::new_unchecked(probed) }.init();
+        }
+        init_with_lockdep!(unsafe { Pin::new_unchecked(&mut ADXL345_CONFIG_LOCK) }, "adxl345_config");
+        init_with_lockdep!(unsafe { Pin::new_unchecked(&mut ADXL345_INSTANCE_LOCK) }, "adxl345_instance");
+
+        // The I2C core binds the device described by the device tree while the driver
+        // registers, as the SPI core does on SPI
+        let i2c_driver = adxl345_i2c_driver_register(module)?;
+
+        #[cfg(CONFIG_SPI)]
+        let spi_driver = if *spi.read() {
+            match adxl345_spi_register(module) {
+                Ok(registration) => {
+                    adxl345_caps_set(ADXL345_CAP_SPI);
+                    Some(registration)
+                }
+                Err(e) => {
+                    i2c_driver.remove_driver();
+                    return Err(e);
+                }
+            }
+        } else {
+            None
+        };
+        #[cfg(not(CONFIG_SPI))]
+        if *spi.read() {
+            pr_warn!("SPI is not enabled in this kernel, the spi parameter is ignored\n");
+        }
+
+        // Otherwise the client is created on the bus given, without a bus the device is
+        // instantiated later from configfs
+        if *i2c_bus.read() >= 0 && adxl345_bound() {
+            pr_info!("ADXL345 bound from the firmware, no client created on I2C bus {}\n", *i2c_bus.read());
+        } else if *i2c_bus.read() >= 0 {
+            if let Err(e) = adxl345_instance_create(*i2c_bus.read(), *i2c_addr.read() as u16) {
+                #[cfg(CONFIG_SPI)]
+                drop(spi_driver);
+                i2c_driver.remove_driver();
+                return Err(e);
+            }
+        }
         pr_info!("Adxl345 Driver correctly initialzied");
 
-        Ok(Adxl345Module{the_driver: adxl345driver})
+        // Debugfs is optional, the driver works without it
+        let debugfs = match adxl345_debugfs_create() {
+            Ok(dir) => {
+                adxl345_caps_set(ADXL345_CAP_DEBUGFS);
+                Some(dir)
+            }
+            Err(e) => {
+                pr_warn!("Debugfs entries not available: {:?}\n", e);
+                None
+            }
+        };
+
+        // The versions are returned by the ioctl anyway, sysfs only shows them and the scan
+        let sysfs = match adxl345_sysfs_create(module) {
+            Ok(sysfs) => Some(sysfs),
+            Err(e) => {
+                pr_warn!("Sysfs module attributes not available: {:?}\n", e);
+                None
+            }
+        };
+
+        // configfs is optional too, the device can then only be created at load
+        #[cfg(CONFIG_CONFIGFS_FS)]
+        let configfs = match adxl345_configfs_register(module) {
+            Ok(configfs) => {
+                adxl345_caps_set(ADXL345_CAP_CONFIGFS);
+                Some(configfs)
+            }
+            Err(e) => {
+                pr_warn!("Configfs interface not available: {:?}\n", e);
+                None
+            }
+        };
+
+        Ok(Adxl345Module{
+            i2c_driver,
+            _debugfs: debugfs,
+            sysfs,
+            #[cfg(CONFIG_CONFIGFS_FS)]
+            configfs,
+            #[cfg(CONFIG_SPI)]
+            spi_driver,
+        })
     }
 }
 
 impl Drop for Adxl345Module {
     fn drop(&mut self) {
-        // Call `remove_driver` to unregister the driver
-        self.the_driver.as_ref().driver().expect("Driver not initialized").remove_driver();
+        // No scan can create an instance anymore, removing the attributes waits for a running one
+        drop(self.sysfs.take());
+
+        // No instance can be created from configfs anymore. Its items hold a reference to the
+        // module, so none is left at this point
+        #[cfg(CONFIG_CONFIGFS_FS)]
+        drop(self.configfs.take());
+
+        // Unregistering the SPI driver removes the device bound on SPI, if any
+        #[cfg(CONFIG_SPI)]
+        drop(self.spi_driver.take());
+
+        // Delete the clients created on a bus, if any, which removes their devices
+        adxl345_instance_destroy_all();
+
+        // Unregister the I2C driver, which removes the device of the device tree, if any
+        self.i2c_driver.remove_driver();
+
+        // Free the configuration snapshots, the driver is gone so nobody reads them anymore
+        adxl345_snapshot_clear_all();
 
-        // i2c client is dropped automatically by its own trait.
         pr_info!("Adxl345 driver unloaded\n");
     }
 }
\ No newline at end of file
@@ -0,0 +1,204 @@
+/*
+ * Copyright 2024 Luca Saverio Esposito, Università di Roma, Tor Vergata
+ * email: <lucasaverioesposito@gmail.com>
+ *
+ * This file is part of an "Rust Linux driver for the ADXL345 device".
+ *
+ * This driver is free software: you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * This driver is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with Foobar.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+
+// alarm.rs
+
+//! Vibration alarm output.
+//!
+//! When the driver is loaded with `alarm_gpio=<n>`, the GPIO line is requested as an output and
+//! driven high while an alarm is raised, so a buzzer, a relay or a PLC input reacts to the
+//! sensor without any userspace. The events raising the alarm are chosen in debugfs with the
+//! `alarm_events` bitmask:
+//! - `ADXL345_ALARM_THRESHOLD` (1, the default): the magnitude of a sample drained from the device
+//!   deviates from 1 g by more than `alarm_threshold_mg`;
+//! - `ADXL345_ALARM_TAP` (2): the device detected a single or a double tap;
+//! - `ADXL345_ALARM_ACTIVITY` (4): the device detected activity.
+//!
+//! Tap and activity are detected by the sensor with the thresholds of `ADXL345_IOC_SET_PARAM`
+//! and the axes enabled in TAP_AXES and ACT_INACT_CTL (see tap.rs and motion.rs); their interrupts are enabled when the line
+//! is attached, and the drain reads INT_SOURCE once per pass while they are selected.
+//!
+//! The drain checks the events and drives the line right after each pass, outside of the device
+//! lock since the line may sit behind a sleeping bus: the latency is the drain period (10 ms), or
+//! the interrupt latency with `data_gpio`, which tap and activity interrupts on INT1 wake up too.
+//! The line stays high for at least `alarm_hold_ms` after the last event and is released when the
+//! session stops. Alarms are counted in `alarm_count`.
+//!
+//! Each device has its own alarm, selected by its id (see instance.rs): `alarm_gpio` takes a line
+//! per device, in the order of the ids, and the knobs are in the debugfs directory of the device.
+
+use kernel::prelude::*;
+use kernel::c_str;
+use kernel::gpio_irq::GpioLine;
+use kernel::sync::{Arc, Mutex};
+use kernel::time::ktime_get_ns;
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use crate::constant::ADXL345_REG_INT_ENABLE;
+use crate::fixed::adxl345_magnitude_mg;
+use crate::structures::{Adxl345, Adxl345Sample};
+use crate::instance::ADXL345_DEVICES_MAX;
+
+/// Events raising the alarm, bits of `alarm_events`.
+pub (crate) const ADXL345_ALARM_THRESHOLD: u32 = 1 << 0;
+pub (crate) const ADXL345_ALARM_TAP: u32 = 1 << 1;
+pub (crate) const ADXL345_ALARM_ACTIVITY: u32 = 1 << 2;
+
+/// SINGLE_TAP and DOUBLE_TAP bits of INT_ENABLE and INT_SOURCE.
+const ADXL345_INT_TAP: u8 = (1 << 6) | (1 << 5);
+
+/// ACTIVITY bit of INT_ENABLE and INT_SOURCE.
+const ADXL345_INT_ACTIVITY: u8 = 1 << 4;
+
+/// Alarm state and knobs of a device, the knobs are debugfs files.
+pub (crate) struct Adxl345Alarm {
+    pub (crate) events: AtomicU32,        // ADXL345_ALARM_* bits
+    pub (crate) threshold_mg: AtomicU32,  // Largest deviation from 1 g
+    pub (crate) hold_ms: AtomicU32,       // Shortest time the line stays high
+    pub (crate) count: AtomicU64,         // Alarms raised
+    triggered: AtomicBool,                // An event occurred since the last output update
+    raised_at: AtomicU64,                 // Time of the last event while high, 0 if low
+    line: UnsafeCell<Option<GpioLine>>,   // The alarm line, see `set_line()`
+}
+
+// SAFETY: `line` is only replaced by `set_line()`, while the drain of the device is stopped,
+// which is the only other user of the line.
+unsafe impl Sync for Adxl345Alarm {}
+
+/// Alarms of the devices, indexed by id.
+static ADXL345_ALARMS: [Adxl345Alarm; ADXL345_DEVICES_MAX] = [Adxl345Alarm::EMPTY; ADXL345_DEVICES_MAX];
+
+/// Returns the alarm of device `id`.
+pub (crate) fn adxl345_alarm(id: usize) -> &'static Adxl345Alarm {
+    &ADXL345_ALARMS[id]
+}
+
+impl Adxl345Alarm {
+    /// An alarm without a line, to initialize the array of alarms.
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY: Self = Self {
+        events: AtomicU32::new(ADXL345_ALARM_THRESHOLD),
+        threshold_mg: AtomicU32::new(2000),
+        hold_ms: AtomicU32::new(500),
+        count: AtomicU64::new(0),
+        triggered: AtomicBool::new(false),
+        raised_at: AtomicU64::new(0),
+        line: UnsafeCell::new(None),
+    };
+
+    /// Returns the line, if attached.
+    fn line(&self) -> Option<&GpioLine> {
+        // SAFETY: The line only changes while the drain is stopped, see `set_line()`.
+        unsafe { (*self.line.get()).as_ref() }
+    }
+
+    /// Publishes the line attached by probe, or drops it in remove.
+    ///
+    /// # Safety
+    /// The drain of the device must not be running: set in probe before the device is published,
+    /// cleared in remove once the drain is shut down.
+    pub (crate) unsafe fn set_line(&self, line: Option<GpioLine>) {
+        // SAFETY: Nothing else uses the line while the drain is stopped, per the contract above.
+        unsafe { *self.line.get() = line };
+    }
+
+    /// Returns true if the line is attached, its tap and activity interrupts are enabled.
+    pub (crate) fn attached(&self) -> bool {
+        self.line().is_some()
+    }
+
+    /// Returns true if the drain must read INT_SOURCE for the selected events.
+    pub (crate) fn wants_source(&self) -> bool {
+        self.attached()
+            && self.events.load(Ordering::Relaxed) & (ADXL345_ALARM_TAP | ADXL345_ALARM_ACTIVITY) != 0
+    }
+
+    /// Checks the INT_SOURCE value read by the drain for a tap or an activity event.
+    pub (crate) fn push_source(&self, source: u8) {
+        let events = self.events.load(Ordering::Relaxed);
+        if (events & ADXL345_ALARM_TAP != 0 && source & ADXL345_INT_TAP != 0)
+            || (events & ADXL345_ALARM_ACTIVITY != 0 && source & ADXL345_INT_ACTIVITY != 0)
+        {
+            self.triggered.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Checks a sample drained from the device against the threshold, with the device lock held.
+    ///
+    /// # Returns
+    /// `true` if the sample starts an episode: the line was low and no event was pending.
+    pub (crate) fn push(&self, sample: &Adxl345Sample) -> bool {
+        if self.events.load(Ordering::Relaxed) & ADXL345_ALARM_THRESHOLD == 0 {
+            return false;
+        }
+        let deviation = (adxl345_magnitude_mg(sample) as i64 - 1000).abs();
+        if deviation <= self.threshold_mg.load(Ordering::Relaxed) as i64 {
+            return false;
+        }
+        !self.triggered.swap(true, Ordering::Relaxed) && self.raised_at.load(Ordering::Relaxed) == 0
+    }
+
+    /// Drives the line after a drain pass: high on a new event, low once the hold time elapsed
+    /// since the last one. Called by the drain work item, outside of the device lock.
+    pub (crate) fn update(&self) {
+        let line = match self.line() {
+            Some(line) => line,
+            None => return,
+        };
+
+        let now = ktime_get_ns().max(1);
+        if self.triggered.swap(false, Ordering::Relaxed) {
+            if self.raised_at.swap(now, Ordering::Relaxed) == 0 {
+                line.set_value(true);
+                self.count.fetch_add(1, Ordering::Relaxed);
+            }
+            return;
+        }
+
+        let raised_at = self.raised_at.load(Ordering::Relaxed);
+        if raised_at != 0 && now - raised_at >= self.hold_ms.load(Ordering::Relaxed) as u64 * 1_000_000 {
+            self.release();
+        }
+    }
+
+    /// Drives the line low, called when the session stops or the hold time elapsed.
+    pub (crate) fn release(&self) {
+        self.triggered.store(false, Ordering::Relaxed);
+        if self.raised_at.swap(0, Ordering::Relaxed) == 0 {
+            return;
+        }
+        if let Some(line) = self.line() {
+            line.set_value(false);
+        }
+    }
+}
+
+/// Requests `gpio` as the alarm line, driven low, and enables the tap and activity interrupts.
+///
+/// # Returns
+/// - `Ok(GpioLine)` with the line, to be published with `Adxl345Alarm::set_line()`.
+/// - `Err(Error)` if the GPIO is in use or INT_ENABLE can't be written.
+pub (crate) fn adxl345_alarm_attach(gpio: u32, device: &Arc<Mutex<Adxl345>>) -> Result<GpioLine> {
+    let line = GpioLine::request_output(gpio, c_str!("adxl345_alarm"), false)?;
+    let interrupts = ADXL345_INT_TAP | ADXL345_INT_ACTIVITY;
+    device.lock().update_register(ADXL345_REG_INT_ENABLE, interrupts, interrupts)?;
+    Ok(line)
+}
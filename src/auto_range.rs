@@ -0,0 +1,153 @@
+/*
+ * Copyright 2024 Luca Saverio Esposito, Università di Roma, Tor Vergata
+ * email: <lucasaverioesposito@gmail.com>
+ *
+ * This file is part of an "Rust Linux driver for the ADXL345 device".
+ *
+ * This driver is free software: you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * This driver is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with Foobar.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+
+
+// auto_range.rs
+
+//! Automatic range selection.
+//!
+//! When enabled with `ADXL345_IOC_SET_AUTO_RANGE`, the drain moves the range one step up
+//! (2, 4, 8, 16 g) when clipping persists, and one step down once the signal would fit the lower
+//! range with a margin. In full resolution the scale of the samples is 3.9 mg/LSB at every range,
+//! so a change only moves the rails; it is reported in the stream by a range marker (kind
+//! `ADXL345_MARKER_RANGE`) holding the new range in g, queued before the first sample acquired
+//! with it.
+//!
+//! Each device has its own state, selected by its id (see instance.rs). Only the drain of the
+//! device, serialized by the device lock, feeds it, so plain atomics with relaxed ordering are
+//! enough.
+
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use crate::config::ADXL345_RANGES_G;
+use crate::structures::Adxl345Sample;
+use crate::instance::ADXL345_DEVICES_MAX;
+
+/// Number of samples over which clipped samples are counted.
+const ADXL345_AUTO_RANGE_WINDOW: u32 = 64;
+
+/// Clipped samples in a window that move the range up.
+const ADXL345_AUTO_RANGE_UP_CLIPS: u32 = 8;
+
+/// Consecutive samples within half the rails of the lower range that move the range down.
+const ADXL345_AUTO_RANGE_DOWN_SAMPLES: u32 = 1024;
+
+/// Auto-ranging state.
+pub (crate) struct Adxl345AutoRange {
+    enabled: AtomicBool,
+    pub (crate) switches: AtomicU64,   // Range changes made, a debugfs file
+    seen: AtomicU32,                   // Samples in the current window
+    clips: AtomicU32,                  // Clipped samples in the current window
+    quiet: AtomicU32,                  // Consecutive samples fitting the lower range
+}
+
+/// Auto-ranging states of the devices, indexed by id.
+static ADXL345_AUTO_RANGES: [Adxl345AutoRange; ADXL345_DEVICES_MAX] =
+    [Adxl345AutoRange::EMPTY; ADXL345_DEVICES_MAX];
+
+/// Returns the auto-ranging state of device `id`.
+pub (crate) fn adxl345_auto_range(id: usize) -> &'static Adxl345AutoRange {
+    &ADXL345_AUTO_RANGES[id]
+}
+
+impl Adxl345AutoRange {
+    /// A disabled state, to initialize the array of states.
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY: Self = Self {
+        enabled: AtomicBool::new(false),
+        switches: AtomicU64::new(0),
+        seen: AtomicU32::new(0),
+        clips: AtomicU32::new(0),
+        quiet: AtomicU32::new(0),
+    };
+
+    /// Enables or disables auto-ranging, the range in use is kept when disabled.
+    pub (crate) fn set_enabled(&self, enabled: bool) {
+        self.reset();
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Returns true if auto-ranging is enabled.
+    pub (crate) fn enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Feeds a sample drained from the device at `range_g`, with the device lock held.
+    ///
+    /// # Parameters
+    /// - `clipped`: The clipped axes of the sample (see clip.rs).
+    ///
+    /// # Returns
+    /// The range to switch to, in g, if the range should change.
+    pub (crate) fn push(&self, sample: &Adxl345Sample, clipped: i16, range_g: u32) -> Option<u32> {
+        if !self.enabled() {
+            return None;
+        }
+        let index = ADXL345_RANGES_G.iter().position(|&range| range == range_g)?;
+
+        let clips = self.clips.load(Ordering::Relaxed) + (clipped != 0) as u32;
+        let seen = self.seen.load(Ordering::Relaxed) + 1;
+        if clips >= ADXL345_AUTO_RANGE_UP_CLIPS && index + 1 < ADXL345_RANGES_G.len() {
+            return self.switch(ADXL345_RANGES_G[index + 1]);
+        }
+        if seen == ADXL345_AUTO_RANGE_WINDOW {
+            self.seen.store(0, Ordering::Relaxed);
+            self.clips.store(0, Ordering::Relaxed);
+        } else {
+            self.seen.store(seen, Ordering::Relaxed);
+            self.clips.store(clips, Ordering::Relaxed);
+        }
+
+        if index == 0 {
+            return None;
+        }
+        // Half the rails of the lower range, in shifted LSBs: lower range * 256 / 2, shifted by 2
+        let margin = (ADXL345_RANGES_G[index - 1] as i32) << 9;
+        let peak = [sample.x, sample.y, sample.z].iter().map(|&v| (v as i32).abs()).max().unwrap_or(0);
+        if peak >= margin {
+            self.quiet.store(0, Ordering::Relaxed);
+            return None;
+        }
+        let quiet = self.quiet.load(Ordering::Relaxed) + 1;
+        if quiet >= ADXL345_AUTO_RANGE_DOWN_SAMPLES {
+            return self.switch(ADXL345_RANGES_G[index - 1]);
+        }
+        self.quiet.store(quiet, Ordering::Relaxed);
+        None
+    }
+
+    /// Counts a range change applied by the drain.
+    pub (crate) fn switched(&self) {
+        self.switches.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Requests a change to `range_g`, the new range is observed from scratch.
+    fn switch(&self, range_g: u32) -> Option<u32> {
+        self.reset();
+        Some(range_g)
+    }
+
+    /// Forgets the samples observed so far.
+    fn reset(&self) {
+        self.seen.store(0, Ordering::Relaxed);
+        self.clips.store(0, Ordering::Relaxed);
+        self.quiet.store(0, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,74 @@
+/*
+ * Copyright 2024 Luca Saverio Esposito, Università di Roma, Tor Vergata
+ * email: <lucasaverioesposito@gmail.com>
+ *
+ * This file is part of an "Rust Linux driver for the ADXL345 device".
+ *
+ * This driver is free software: you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * This driver is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with Foobar.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+// batch_crc.rs
+
+//! CRC of the batches delivered to userspace.
+//!
+//! When enabled with `ADXL345_IOC_SET_CRC`, every `read()` ends with the CRC32 of the records it
+//! returned before it, so corruption anywhere between the kernel buffer and the consumer (the
+//! copy to userspace, the user buffer, the decoding) can be detected batch by batch.
+//!
+//! The CRC is the standard CRC-32 (IEEE 802.3, as zlib) of the records as written to the user
+//! buffer, native endian and in the output format of the file (see output.rs). It is carried by `ADXL345_CRC_WORDS` marker records of kind
+//! `ADXL345_MARKER_CRC`, each holding one 16-bit word in its z field, least significant word
+//! first. A read reserves room for them, so it needs room for at least one more record.
+
+use kernel::bindings;
+use core::sync::atomic::{AtomicBool, Ordering};
+use crate::constant::ADXL345_MARKER_CRC;
+use crate::structures::Adxl345Sample;
+
+/// Number of records carrying the CRC of a batch.
+pub (crate) const ADXL345_CRC_WORDS: usize = 2;
+
+/// Enables the batch CRC, for every reader.
+pub (crate) static ADXL345_BATCH_CRC: AtomicBool = AtomicBool::new(false);
+
+/// Returns the number of records a read must reserve for the CRC, 0 when disabled.
+pub (crate) fn adxl345_crc_words() -> usize {
+    if ADXL345_BATCH_CRC.load(Ordering::Relaxed) { ADXL345_CRC_WORDS } else { 0 }
+}
+
+/// CRC of the records written so far by a read.
+pub (crate) struct Adxl345Crc {
+    crc: u32,
+}
+
+impl Adxl345Crc {
+    pub (crate) const fn new() -> Self {
+        Self { crc: !0 }
+    }
+
+    /// Adds a record, the bytes written to the user buffer.
+    pub (crate) fn update(&mut self, bytes: &[u8]) {
+        // SAFETY: `bytes` is valid for reads of its length.
+        self.crc = unsafe { bindings::crc32_le(self.crc, bytes.as_ptr(), bytes.len()) };
+    }
+
+    /// Returns the marker records carrying the CRC of the records added so far.
+    pub (crate) fn records(&self) -> [Adxl345Sample; ADXL345_CRC_WORDS] {
+        let crc = !self.crc;
+        [
+            Adxl345Sample::marker(ADXL345_MARKER_CRC, crc as u16 as i16),
+            Adxl345Sample::marker(ADXL345_MARKER_CRC, (crc >> 16) as u16 as i16),
+        ]
+    }
+}
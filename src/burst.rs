@@ -0,0 +1,217 @@
+/*
+ * Copyright 2024 Luca Saverio Esposito, Università di Roma, Tor Vergata
+ * email: <lucasaverioesposito@gmail.com>
+ *
+ * This file is part of an "Rust Linux driver for the ADXL345 device".
+ *
+ * This driver is free software: you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * This driver is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with Foobar.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+
+// burst.rs
+
+//! Duty-cycled burst sampling.
+//!
+//! For battery powered monitoring of machinery, a short capture at a high rate every now and
+//! then tells as much as a continuous stream at a fraction of the power: e.g. 1 s at 800 Hz every
+//! 60 s. `ADXL345_IOC_SET_BURST` sets the period and the length of the bursts, in milliseconds;
+//! a length of 0 (the default) streams continuously. The rate and the range are the ones of
+//! `ADXL345_IOC_SET_PARAM`.
+//!
+//! The drain runs the schedule. At the start of a burst it enables measurement and queues a
+//! session header (see session.rs), holding the rate, the range and the start timestamp, so each
+//! burst is a self-describing block: its samples follow the header, evenly spaced at the rate.
+//! Once the length has elapsed it drains the last samples, puts the device in standby and queues
+//! a burst marker (kind `ADXL345_MARKER_BURST`) whose z field is the number of samples of the
+//! burst, saturated at 65535. Then it sleeps until the next burst, with no timer tick nor bus
+//! traffic in between. Bursts start every period from the first one, at the drain period
+//! resolution (10 ms at most), and end within a drain period of their length.
+//!
+//! The header must fit whole in the kernel buffer: a burst starting while readers left less room
+//! is skipped, and counted in `bursts_skipped`. A burst marker that doesn't fit is dropped like a
+//! sample, the next header still starts a new block. Bursts are counted in `bursts`.
+//!
+//! Each device has its own schedule, selected by its id (see instance.rs), run by its own drain.
+
+use kernel::prelude::*;
+use kernel::error::code::{EINVAL, ERANGE};
+use kernel::io_buffer::{ReadableFromBytes, WritableToBytes};
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use crate::instance::ADXL345_DEVICES_MAX;
+
+/// Longest burst period, in milliseconds: a day.
+const ADXL345_BURST_PERIOD_MAX_MS: u32 = 86_400_000;
+
+/// Argument of `ADXL345_IOC_SET_BURST` and `ADXL345_IOC_GET_BURST`.
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub (crate) struct Adxl345BurstArg {
+    pub (crate) period_ms: u32, // Time from the start of a burst to the start of the next one
+    pub (crate) length_ms: u32, // Length of a burst, 0 streams continuously
+}
+
+// SAFETY: `Adxl345BurstArg` is `repr(C)`, made only of integers and has no padding, so any byte
+// pattern is a valid value.
+unsafe impl ReadableFromBytes for Adxl345BurstArg {}
+unsafe impl WritableToBytes for Adxl345BurstArg {}
+
+/// What the drain does next in burst mode.
+pub (crate) enum Adxl345BurstStep {
+    /// A burst is running, or bursts are off: the drain pass goes on.
+    Measure,
+    /// A burst starts now.
+    Start,
+    /// The burst ends now.
+    End,
+    /// The device sleeps, the next burst starts in that many milliseconds.
+    Sleep(u32),
+}
+
+/// Burst schedule and counters.
+pub (crate) struct Adxl345Burst {
+    period_ms: AtomicU32,
+    length_ms: AtomicU32,
+    measuring: AtomicBool,           // A burst is running
+    start_ns: AtomicU64,             // Start of the running or of the next burst, 0 for now
+    samples: AtomicU32,              // Samples of the running burst
+    pub (crate) bursts: AtomicU64,   // Bursts started
+    pub (crate) skipped: AtomicU64,  // Bursts skipped for lack of room in the buffer
+}
+
+/// Burst states of the devices, indexed by id.
+static ADXL345_BURSTS: [Adxl345Burst; ADXL345_DEVICES_MAX] = [Adxl345Burst::EMPTY; ADXL345_DEVICES_MAX];
+
+/// Returns the burst state of device `id`.
+pub (crate) fn adxl345_burst(id: usize) -> &'static Adxl345Burst {
+    &ADXL345_BURSTS[id]
+}
+
+impl Adxl345Burst {
+    /// A state streaming continuously, to initialize the array of states.
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY: Self = Self {
+        period_ms: AtomicU32::new(0),
+        length_ms: AtomicU32::new(0),
+        measuring: AtomicBool::new(false),
+        start_ns: AtomicU64::new(0),
+        samples: AtomicU32::new(0),
+        bursts: AtomicU64::new(0),
+        skipped: AtomicU64::new(0),
+    };
+
+    /// Returns true if the device samples in bursts rather than continuously.
+    pub (crate) fn enabled(&self) -> bool {
+        self.length_ms.load(Ordering::Relaxed) != 0
+    }
+
+    /// Returns the period and the length of the bursts.
+    pub (crate) fn get(&self) -> Adxl345BurstArg {
+        Adxl345BurstArg {
+            period_ms: self.period_ms.load(Ordering::Relaxed),
+            length_ms: self.length_ms.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Sets the period and the length of the bursts, with the drain stopped.
+    ///
+    /// # Returns
+    /// - `Ok(())` if the schedule is valid, the first burst starts with the drain.
+    /// - `Err(ERANGE)` if the period is above a day.
+    /// - `Err(EINVAL)` if a burst lasts as long as its period or longer.
+    pub (crate) fn set(&self, arg: Adxl345BurstArg) -> Result {
+        if arg.length_ms != 0 {
+            if arg.period_ms > ADXL345_BURST_PERIOD_MAX_MS {
+                return Err(ERANGE);
+            }
+            if arg.length_ms >= arg.period_ms {
+                return Err(EINVAL);
+            }
+        }
+        self.period_ms.store(arg.period_ms, Ordering::Relaxed);
+        self.length_ms.store(arg.length_ms, Ordering::Relaxed);
+        self.restart();
+        Ok(())
+    }
+
+    /// Starts the schedule again, the first burst starts at the next drain pass. Called when the
+    /// drain starts.
+    pub (crate) fn restart(&self) {
+        self.measuring.store(false, Ordering::Relaxed);
+        self.start_ns.store(0, Ordering::Relaxed);
+    }
+
+    /// Returns what the drain does next, at `now_ns` in the monotonic clock.
+    pub (crate) fn step(&self, now_ns: u64) -> Adxl345BurstStep {
+        if !self.enabled() {
+            return Adxl345BurstStep::Measure;
+        }
+        let start_ns = self.start_ns.load(Ordering::Relaxed);
+        if self.measuring.load(Ordering::Relaxed) {
+            let length_ns = self.length_ms.load(Ordering::Relaxed) as u64 * 1_000_000;
+            return match now_ns >= start_ns + length_ns {
+                true => Adxl345BurstStep::End,
+                false => Adxl345BurstStep::Measure,
+            };
+        }
+        match now_ns >= start_ns {
+            true => Adxl345BurstStep::Start,
+            false => Adxl345BurstStep::Sleep(((start_ns - now_ns + 999_999) / 1_000_000) as u32),
+        }
+    }
+
+    /// Records that the burst starting at `now_ns` is running.
+    pub (crate) fn started(&self, now_ns: u64) {
+        self.start_ns.store(now_ns, Ordering::Relaxed);
+        self.samples.store(0, Ordering::Relaxed);
+        self.measuring.store(true, Ordering::Relaxed);
+        self.bursts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Skips the burst due at `now_ns`.
+    pub (crate) fn skip(&self, now_ns: u64) {
+        self.skipped.fetch_add(1, Ordering::Relaxed);
+        self.schedule_next(now_ns);
+    }
+
+    /// Records that the running burst ended at `now_ns`.
+    ///
+    /// # Returns
+    /// The number of samples of the burst, saturated at 65535, for its burst marker.
+    pub (crate) fn ended(&self, now_ns: u64) -> u16 {
+        self.measuring.store(false, Ordering::Relaxed);
+        self.schedule_next(now_ns);
+        self.samples.load(Ordering::Relaxed).min(u16::MAX as u32) as u16
+    }
+
+    /// Counts the samples drained during a burst, with the device lock held.
+    pub (crate) fn drained(&self, samples: usize) {
+        if self.measuring.load(Ordering::Relaxed) {
+            self.samples.fetch_add(samples as u32, Ordering::Relaxed);
+        }
+    }
+
+    /// Moves the start to the first period boundary after `now_ns`, so a late drain doesn't
+    /// shift the schedule.
+    fn schedule_next(&self, now_ns: u64) {
+        let period_ns = (self.period_ms.load(Ordering::Relaxed) as u64 * 1_000_000).max(1);
+        let mut start_ns = match self.start_ns.load(Ordering::Relaxed) {
+            0 => now_ns,
+            start_ns => start_ns,
+        };
+        if start_ns <= now_ns {
+            start_ns += ((now_ns - start_ns) / period_ns + 1) * period_ns;
+        }
+        self.start_ns.store(start_ns, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,172 @@
+/*
+ * Copyright 2024 Luca Saverio Esposito, Università di Roma, Tor Vergata
+ * email: <lucasaverioesposito@gmail.com>
+ *
+ * This file is part of an "Rust Linux driver for the ADXL345 device".
+ *
+ * This driver is free software: you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * This driver is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with Foobar.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+
+// bus_trace.rs
+
+//! Register transaction trace.
+//!
+//! The last register transactions (operation, register, value, result and timestamp) are kept
+//! in a ring readable from `/sys/kernel/debug/adxl345/bus_trace`, and dumped to the kernel log
+//! when a transaction fails, so a precise bus trace can be attached to bug reports.
+
+use kernel::prelude::*;
+use kernel::str::CString;
+use kernel::time::ktime_get_ns;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+
+/// Number of transactions kept in the ring.
+pub (crate) const ADXL345_BUS_TRACE_LEN: usize = 128;
+
+/// Kind of register transaction.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub (crate) enum Adxl345BusOp {
+    /// Single register read.
+    Read = 0,
+    /// Single register write.
+    Write = 1,
+    /// Block read, the traced value is the number of bytes.
+    BlockRead = 2,
+}
+
+impl Adxl345BusOp {
+    fn from_raw(raw: u8) -> Self {
+        match raw {
+            1 => Self::Write,
+            2 => Self::BlockRead,
+            _ => Self::Read,
+        }
+    }
+
+    /// Short name used in the trace.
+    fn name(self) -> &'static str {
+        match self {
+            Self::Read => "R",
+            Self::Write => "W",
+            Self::BlockRead => "B",
+        }
+    }
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const ENTRY_INIT: AtomicU64 = AtomicU64::new(0);
+
+/// Ring of the last register transactions.
+///
+/// Each entry is made of two words: the transaction (operation, register, value and errno
+/// packed together) and its monotonic timestamp.
+pub (crate) struct Adxl345BusTrace {
+    transactions: [AtomicU64; ADXL345_BUS_TRACE_LEN],
+    timestamps: [AtomicU64; ADXL345_BUS_TRACE_LEN],
+    count: AtomicU32,
+    failing: AtomicBool,
+    /// Dump the ring to the kernel log when a transaction fails, writable from debugfs.
+    pub (crate) dump_on_error: AtomicBool,
+}
+
+/// Global transaction trace.
+pub (crate) static ADXL345_BUS_TRACE: Adxl345BusTrace = Adxl345BusTrace::new();
+
+impl Adxl345BusTrace {
+    const fn new() -> Self {
+        Self {
+            transactions: [ENTRY_INIT; ADXL345_BUS_TRACE_LEN],
+            timestamps: [ENTRY_INIT; ADXL345_BUS_TRACE_LEN],
+            count: AtomicU32::new(0),
+            failing: AtomicBool::new(false),
+            dump_on_error: AtomicBool::new(true),
+        }
+    }
+
+    /// Records a transaction.
+    ///
+    /// The first failure after a successful transaction dumps the ring, consecutive failures
+    /// are only recorded.
+    ///
+    /// # Parameters
+    /// - `op`: The kind of transaction.
+    /// - `reg`: The (first) register accessed.
+    /// - `value`: The byte read or written, the length for block reads.
+    /// - `result`: The outcome of the transaction.
+    pub (crate) fn record<T>(&self, op: Adxl345BusOp, reg: u8, value: u8, result: &Result<T>) {
+        let errno = match result {
+            Ok(_) => 0,
+            Err(e) => e.to_kernel_errno(),
+        };
+        let transaction = (op as u64) << 56 | (reg as u64) << 48 | (value as u64) << 40 | errno as u32 as u64;
+
+        let index = self.count.fetch_add(1, Ordering::AcqRel) as usize % ADXL345_BUS_TRACE_LEN;
+        self.timestamps[index].store(ktime_get_ns(), Ordering::Relaxed);
+        self.transactions[index].store(transaction, Ordering::Release);
+
+        let was_failing = self.failing.swap(errno != 0, Ordering::AcqRel);
+        if errno != 0 && !was_failing && self.dump_on_error.load(Ordering::Relaxed) {
+            self.dump();
+        }
+    }
+
+    /// Formats a single entry of the ring, without the trailing newline.
+    fn format(&self, n: usize) -> Result<CString> {
+        let transaction = self.transactions[n % ADXL345_BUS_TRACE_LEN].load(Ordering::Acquire);
+        let timestamp = self.timestamps[n % ADXL345_BUS_TRACE_LEN].load(Ordering::Relaxed);
+        let op = Adxl345BusOp::from_raw((transaction >> 56) as u8);
+        let reg = (transaction >> 48) as u8;
+        let value = (transaction >> 40) as u8;
+        let errno = transaction as u32 as i32;
+
+        CString::try_from_fmt(fmt!(
+            "{:6} {:>20} {} 0x{:02x} 0x{:02x} {}",
+            n,
+            timestamp,
+            op.name(),
+            reg,
+            value,
+            errno
+        ))
+    }
+
+    /// Returns the range of the entries still in the ring, oldest first.
+    fn entries(&self) -> core::ops::Range<usize> {
+        let count = self.count.load(Ordering::Acquire) as usize;
+        count.saturating_sub(ADXL345_BUS_TRACE_LEN)..count
+    }
+
+    /// Formats the whole ring, oldest entry first, with a header line.
+    pub (crate) fn text(&self) -> Result<Vec<u8>> {
+        let mut text = Vec::new();
+        text.try_extend_from_slice(b"#  seq         timestamp_ns op reg  value result\n")?;
+        for n in self.entries() {
+            text.try_extend_from_slice(self.format(n)?.as_bytes())?;
+            text.try_push(b'\n')?;
+        }
+        Ok(text)
+    }
+
+    /// Dumps the ring to the kernel log.
+    pub (crate) fn dump(&self) {
+        pr_err!("I2C transaction failed, last register transactions:\n");
+        for n in self.entries() {
+            match self.format(n) {
+                Ok(line) => pr_err!("{}\n", &*line),
+                Err(_) => break,
+            }
+        }
+    }
+}
@@ -0,0 +1,125 @@
+/*
+ * Copyright 2024 Luca Saverio Esposito, Università di Roma, Tor Vergata
+ * email: <lucasaverioesposito@gmail.com>
+ *
+ * This file is part of an "Rust Linux driver for the ADXL345 device".
+ *
+ * This driver is free software: you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * This driver is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with Foobar.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+// bus_usage.rs
+
+//! Bus usage accounting.
+//!
+//! Every successful register transaction is counted twice: the bytes of data it moved, and the
+//! bytes it put on the bus, address and command bytes included. Their ratio, and both rates per
+//! second, are readable from `/sys/kernel/debug/adxl345/bus_usage`, to quantify what the block
+//! reads and the FIFO_STATUS-driven drain save over single register transactions.
+//!
+//! The bus bytes follow the framing of each transaction on its bus (see structures/bus.rs), START,
+//! STOP and ACK bits aside. On I2C it is the SMBus one:
+//!
+//! | Transaction | Bytes on the bus |
+//! |-------------|------------------|
+//! | read byte   | address (W), command, address (R), data |
+//! | write byte  | address (W), command, data |
+//! | block read  | address (W), command, address (R), n data |
+//!
+//! On SPI every transaction is a command byte followed by its data.
+//!
+//! In dry-run mode the transactions are counted as if they reached the bus.
+
+use kernel::prelude::*;
+use kernel::str::CString;
+use kernel::time::ktime_get_ns;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// Bus usage counters, since the first transaction or the last reset.
+pub (crate) struct Adxl345BusUsage {
+    transactions: AtomicU64,
+    data_bytes: AtomicU64,
+    bus_bytes: AtomicU64,
+    since_ns: AtomicU64, // Start of the measurement, 0 until the first transaction
+}
+
+/// Global bus usage counters.
+pub (crate) static ADXL345_BUS_USAGE: Adxl345BusUsage = Adxl345BusUsage::new();
+
+impl Adxl345BusUsage {
+    const fn new() -> Self {
+        Self {
+            transactions: AtomicU64::new(0),
+            data_bytes: AtomicU64::new(0),
+            bus_bytes: AtomicU64::new(0),
+            since_ns: AtomicU64::new(0),
+        }
+    }
+
+    /// Counts a transaction moving `len` bytes of data and `overhead` bytes of framing, if it
+    /// succeeded.
+    pub (crate) fn record<T>(&self, len: usize, overhead: usize, result: &Result<T>) {
+        if result.is_err() {
+            return;
+        }
+        if self.since_ns.load(Ordering::Relaxed) == 0 {
+            let _ = self.since_ns.compare_exchange(0, ktime_get_ns(), Ordering::Relaxed, Ordering::Relaxed);
+        }
+        self.transactions.fetch_add(1, Ordering::Relaxed);
+        self.data_bytes.fetch_add(len as u64, Ordering::Relaxed);
+        self.bus_bytes.fetch_add((overhead + len) as u64, Ordering::Relaxed);
+    }
+
+    /// Clears the counters, the next transaction starts a new measurement.
+    pub (crate) fn reset(&self) {
+        self.since_ns.store(0, Ordering::Relaxed);
+        self.transactions.store(0, Ordering::Relaxed);
+        self.data_bytes.store(0, Ordering::Relaxed);
+        self.bus_bytes.store(0, Ordering::Relaxed);
+    }
+
+    /// Formats the counters, the share of data in the bus bytes and both rates per second.
+    ///
+    /// The counters are read one at a time while transactions go on, the figures of a
+    /// measurement still running are consistent to a transaction or so.
+    pub (crate) fn text(&self) -> Result<Vec<u8>> {
+        let since_ns = self.since_ns.load(Ordering::Relaxed);
+        let transactions = self.transactions.load(Ordering::Relaxed);
+        let data_bytes = self.data_bytes.load(Ordering::Relaxed);
+        let bus_bytes = self.bus_bytes.load(Ordering::Relaxed);
+
+        let elapsed_ms = match since_ns {
+            0 => 0,
+            since_ns => ktime_get_ns().saturating_sub(since_ns) / 1_000_000,
+        };
+        // Share of data in the bus bytes, in tenths of a percent
+        let efficiency = (data_bytes * 1000).checked_div(bus_bytes).unwrap_or(0);
+        let per_second = |bytes: u64| (bytes * 1000).checked_div(elapsed_ms).unwrap_or(0);
+
+        let text = CString::try_from_fmt(fmt!(
+            "transactions {}\ndata_bytes {}\nbus_bytes {}\nefficiency {}.{}%\nelapsed_ms {}\n\
+             data_bytes_per_s {}\nbus_bytes_per_s {}\n",
+            transactions,
+            data_bytes,
+            bus_bytes,
+            efficiency / 10,
+            efficiency % 10,
+            elapsed_ms,
+            per_second(data_bytes),
+            per_second(bus_bytes)
+        ))?;
+        let mut ret = Vec::new();
+        ret.try_extend_from_slice(text.as_bytes())?;
+        Ok(ret)
+    }
+}
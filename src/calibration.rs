@@ -0,0 +1,279 @@
+/*
+ * Copyright 2024 Luca Saverio Esposito, Università di Roma, Tor Vergata
+ * email: <lucasaverioesposito@gmail.com>
+ *
+ * This file is part of an "Rust Linux driver for the ADXL345 device".
+ *
+ * This driver is free software: you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * This driver is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with Foobar.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+
+// calibration.rs
+
+//! Offset calibration.
+//!
+//! The OFSX, OFSY and OFSZ registers hold offsets the device adds to every sample, in two's
+//! complement at 15.6 mg per unit (16 units of the stream): they compensate the zero-g bias of
+//! the chip and of its mounting without any work on the data path.
+//!
+//! `ADXL345_IOC_CALIBRATE`, with the device held still and one axis pointing along gravity (up or
+//! down), averages a number of samples at the current rate and corrects the offsets so the axis
+//! along gravity reads ±1 g and the other two read 0. The axis along gravity is the one whose
+//! mean is the largest. It fails with `EAGAIN` if an axis moved by more than
+//! `ADXL345_CALIBRATION_STILL_MG` during the capture, and with `EBUSY` while a measurement
+//! session is running: like the noise routine (see noise.rs) it needs the device for itself and
+//! holds the configuration lock until it is done. A correction beyond the range of the registers
+//! is saturated.
+//!
+//! `ADXL345_IOC_SET_OFFSETS` and `ADXL345_IOC_GET_OFFSETS` write and read the raw registers, for
+//! offsets computed elsewhere; the `offset_x`, `offset_y` and `offset_z` sysfs attributes do the
+//! same one axis at a time (see sysfs.rs).
+//!
+//! The offsets are kept in the driver and written at probe, so they survive a rebind of the
+//! device or a reload of its bus driver, and the register shadow restores them after a brown-out
+//! (see shadow.rs). Calibrations are counted in `calibrations` in debugfs.
+//!
+//! Each device has its own offsets, kept in the slot of its id (see instance.rs): they are
+//! written back to the next device probed in that slot.
+
+use kernel::prelude::*;
+use kernel::delay::coarse_sleep;
+use kernel::error::code::{EAGAIN, EBUSY, EINVAL, ETIMEDOUT};
+use kernel::io_buffer::{ReadableFromBytes, WritableToBytes};
+use kernel::sync::{Arc, Mutex};
+use kernel::time::ktime_get_ns;
+use core::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use core::time::Duration;
+use crate::config::Adxl345Param;
+use crate::constant::ADXL345_REG_OFSX;
+use crate::drain::Adxl345Drain;
+use crate::structures::Adxl345;
+use crate::instance::ADXL345_DEVICES_MAX;
+use crate::fixed::{adxl345_round_div, adxl345_units_to_mg, ADXL345_UG_PER_UNIT};
+use crate::sample_math::{adxl345_calibration_spread, adxl345_calibration_sum};
+
+/// Samples averaged when none is given, and the most accepted.
+const ADXL345_CALIBRATION_SAMPLES_DEFAULT: u32 = 100;
+const ADXL345_CALIBRATION_SAMPLES_MAX: u32 = 1000;
+
+/// Largest spread of an axis during the capture, in mg, for the device to count as still.
+const ADXL345_CALIBRATION_STILL_MG: i64 = 100;
+
+/// 1 g in units of the stream, 1026.
+const ADXL345_CALIBRATION_1G: i64 = adxl345_round_div(1_000_000, ADXL345_UG_PER_UNIT);
+
+/// Units of the stream per unit of the offset registers (15.6 mg).
+const ADXL345_CALIBRATION_OFS_UNIT: i64 = 16;
+
+/// Argument of `ADXL345_IOC_SET_OFFSETS` and `ADXL345_IOC_GET_OFFSETS`: the raw registers, from
+/// -128 to 127.
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub (crate) struct Adxl345OffsetArg {
+    pub (crate) x: i32,
+    pub (crate) y: i32,
+    pub (crate) z: i32,
+}
+
+// SAFETY: `Adxl345OffsetArg` is `repr(C)`, made only of integers and has no padding, so any byte
+// pattern is a valid value.
+unsafe impl ReadableFromBytes for Adxl345OffsetArg {}
+unsafe impl WritableToBytes for Adxl345OffsetArg {}
+
+/// Argument of `ADXL345_IOC_CALIBRATE`: the number of samples is given, the offsets written are
+/// returned.
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub (crate) struct Adxl345CalibrateArg {
+    pub (crate) samples: u32,               // Samples averaged, 0 for 100
+    pub (crate) offsets: Adxl345OffsetArg,  // Registers written
+}
+
+// SAFETY: `Adxl345CalibrateArg` is `repr(C)`, made only of integers and has no padding, so any
+// byte pattern is a valid value.
+unsafe impl ReadableFromBytes for Adxl345CalibrateArg {}
+unsafe impl WritableToBytes for Adxl345CalibrateArg {}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const ADXL345_CALIBRATION_ZERO: AtomicU8 = AtomicU8::new(0);
+
+/// Offsets kept in the driver, the counter is a debugfs file.
+pub (crate) struct Adxl345Calibration {
+    offsets: [AtomicU8; 3],         // OFSX, OFSY and OFSZ as last written
+    pub (crate) calibrations: AtomicU64,
+}
+
+/// Calibration states of the devices, indexed by id.
+static ADXL345_CALIBRATIONS: [Adxl345Calibration; ADXL345_DEVICES_MAX] =
+    [Adxl345Calibration::EMPTY; ADXL345_DEVICES_MAX];
+
+/// Returns the calibration state of device `id`.
+pub (crate) fn adxl345_calibration(id: usize) -> &'static Adxl345Calibration {
+    &ADXL345_CALIBRATIONS[id]
+}
+
+impl Adxl345Calibration {
+    /// A state with null offsets, to initialize the array of states.
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY: Self = Self {
+        offsets: [ADXL345_CALIBRATION_ZERO; 3],
+        calibrations: AtomicU64::new(0),
+    };
+
+    /// Writes the offset of `axis` (0 for x) to the device and keeps it, with the device lock
+    /// held.
+    pub (crate) fn set_axis(&self, adxl: &Adxl345, axis: usize, offset: i8) -> Result {
+        adxl.write_register(ADXL345_REG_OFSX + axis as u8, offset as u8)?;
+        self.offsets[axis].store(offset as u8, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Writes the offsets of `arg`, as `ADXL345_IOC_SET_OFFSETS`, with the device lock held.
+    ///
+    /// # Returns
+    /// - `Ok(())` once the three registers are written.
+    /// - `Err(EINVAL)` if an offset is outside -128 to 127, nothing is written.
+    /// - `Err(Error)` if a register can't be written, the ones before it are.
+    pub (crate) fn set(&self, adxl: &Adxl345, arg: Adxl345OffsetArg) -> Result {
+        let mut offsets = [0i8; 3];
+        for (offset, value) in offsets.iter_mut().zip([arg.x, arg.y, arg.z]) {
+            *offset = i8::try_from(value).map_err(|_| EINVAL)?;
+        }
+        for (axis, offset) in offsets.into_iter().enumerate() {
+            self.set_axis(adxl, axis, offset)?;
+        }
+        Ok(())
+    }
+
+    /// Reads the offsets from the device, as `ADXL345_IOC_GET_OFFSETS`, with the device lock held.
+    pub (crate) fn get(&self, adxl: &Adxl345) -> Result<Adxl345OffsetArg> {
+        let mut offsets = [0i32; 3];
+        for (axis, offset) in offsets.iter_mut().enumerate() {
+            *offset = adxl.read_register(ADXL345_REG_OFSX + axis as u8)? as i8 as i32;
+        }
+        Ok(Adxl345OffsetArg { x: offsets[0], y: offsets[1], z: offsets[2] })
+    }
+
+    /// Writes the offsets kept to the device being probed, with the device lock held.
+    pub (crate) fn apply(&self, adxl: &Adxl345) -> Result {
+        for (axis, offset) in self.offsets.iter().enumerate() {
+            adxl.write_register(ADXL345_REG_OFSX + axis as u8, offset.load(Ordering::Relaxed))?;
+        }
+        Ok(())
+    }
+}
+
+/// Calibrates the offsets from `samples` samples, as `ADXL345_IOC_CALIBRATE`, with the
+/// configuration lock held.
+///
+/// The device lock is only held for the register transactions, never while waiting.
+///
+/// # Returns
+/// - `Ok(Adxl345OffsetArg)` with the offsets written.
+/// - `Err(EINVAL)` if more than 1000 samples are asked for.
+/// - `Err(EBUSY)` if a measurement session is running.
+/// - `Err(EAGAIN)` if the device moved during the capture, the offsets are kept.
+/// - `Err(ETIMEDOUT)` if the samples didn't arrive in twice the time the rate promises.
+/// - `Err(Error)` if a register transaction failed.
+pub (crate) fn adxl345_calibrate(
+    device: &Arc<Mutex<Adxl345>>,
+    drain: &Adxl345Drain,
+    samples: u32,
+) -> Result<Adxl345OffsetArg> {
+    let samples = match samples {
+        0 => ADXL345_CALIBRATION_SAMPLES_DEFAULT,
+        samples if samples <= ADXL345_CALIBRATION_SAMPLES_MAX => samples,
+        _ => return Err(EINVAL),
+    };
+    if drain.is_running() {
+        return Err(EBUSY);
+    }
+
+    let rate_mhz = device.lock().get_param(Adxl345Param::Rate)? as u64;
+    device.lock().enable_measure()?;
+    let ret = adxl345_calibration_capture(device, samples, rate_mhz);
+    // Standby is restored whatever the outcome
+    let disabled = device.lock().disable_measure();
+    let (sum, spread) = ret?;
+    disabled?;
+
+    if spread.iter().any(|&spread| adxl345_units_to_mg(spread) > ADXL345_CALIBRATION_STILL_MG) {
+        return Err(EAGAIN);
+    }
+    let mean = sum.map(|sum| adxl345_round_div(sum, samples as i64));
+    let gravity = (0..3).max_by_key(|&axis| mean[axis].abs()).unwrap_or(2);
+
+    let adxl = device.lock();
+    let calibration = adxl345_calibration(adxl.id());
+    let current = calibration.get(&adxl)?;
+    let current = [current.x, current.y, current.z];
+    let mut offsets = [0i32; 3];
+    for axis in 0..3 {
+        let expected = if axis == gravity { ADXL345_CALIBRATION_1G * mean[axis].signum() } else { 0 };
+        let correction = adxl345_round_div(mean[axis] - expected, ADXL345_CALIBRATION_OFS_UNIT);
+        let wanted = current[axis] as i64 - correction;
+        let offset = wanted.clamp(i8::MIN as i64, i8::MAX as i64);
+        if offset != wanted {
+            pr_warn!("Offset of axis {} saturated, the bias is out of range\n", axis);
+        }
+        offsets[axis] = offset as i32;
+    }
+    let arg = Adxl345OffsetArg { x: offsets[0], y: offsets[1], z: offsets[2] };
+    calibration.set(&adxl, arg)?;
+    calibration.calibrations.fetch_add(1, Ordering::Relaxed);
+    pr_info!("Offsets calibrated from {} samples: {} {} {}\n", samples, arg.x, arg.y, arg.z);
+    Ok(arg)
+}
+
+/// Reads `samples` samples at `rate_mhz`.
+///
+/// # Returns
+/// The sum of each axis, and its spread (largest minus smallest value).
+fn adxl345_calibration_capture(
+    device: &Arc<Mutex<Adxl345>>,
+    samples: u32,
+    rate_mhz: u64,
+) -> Result<([i64; 3], [i64; 3])> {
+    let period_ns = 1_000_000_000_000 / rate_mhz.max(1);
+    let deadline = ktime_get_ns() + 2 * samples as u64 * period_ns + 1_000_000_000;
+    // Poll about four times per sample period, within 1 and 100 ms
+    let poll_ms = (250_000 / rate_mhz).clamp(1, 100);
+
+    let mut sum = [0i64; 3];
+    let mut min = [i64::MAX; 3];
+    let mut max = [i64::MIN; 3];
+    let mut count = 0;
+    while count < samples {
+        let sample = {
+            let adxl = device.lock();
+            if adxl.data_ready()? != 0 { Some(adxl.read_data()?) } else { None }
+        };
+        let sample = match sample {
+            Some(sample) => sample,
+            None if ktime_get_ns() < deadline => {
+                coarse_sleep(Duration::from_millis(poll_ms));
+                continue;
+            }
+            None => return Err(ETIMEDOUT),
+        };
+
+        for (axis, value) in [sample.x, sample.y, sample.z].into_iter().enumerate() {
+            sum[axis] = adxl345_calibration_sum(sum[axis], value);
+            min[axis] = min[axis].min(value as i64);
+            max[axis] = max[axis].max(value as i64);
+        }
+        count += 1;
+    }
+    Ok((sum, [0, 1, 2].map(|axis| adxl345_calibration_spread(min[axis], max[axis]))))
+}
@@ -0,0 +1,160 @@
+/*
+ * Copyright 2024 Luca Saverio Esposito, Università di Roma, Tor Vergata
+ * email: <lucasaverioesposito@gmail.com>
+ *
+ * This file is part of an "Rust Linux driver for the ADXL345 device".
+ *
+ * This driver is free software: you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * This driver is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with Foobar.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+// capabilities.rs
+
+//! Features of this build of the driver, returned by `ADXL345_IOC_GET_CAPS` as a bitmask.
+//!
+//! One user space binary can then adapt to kernels built with different options, or to a module
+//! loaded without some optional interface, instead of probing each ioctl for `ENOTTY` or
+//! `EINVAL`. The bits are part of the ABI (see version.rs): a bit keeps its meaning once
+//! assigned, new features take new bits. Some are known at compile time, the others are set at
+//! module init once the interface they stand for is registered.
+//!
+//! The bits are returned for the device of the file: the data interrupt, the alarm line and the
+//! thermal guard are attached to each device at probe (see instance.rs), so they are reported on
+//! the files of the devices that have them.
+
+use crate::dry_run::ADXL345_DRY_RUN;
+use crate::instance::ADXL345_DEVICES_MAX;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// The device FIFO is drained by the count in FIFO_STATUS, in bursts.
+pub (crate) const ADXL345_CAP_FIFO: u64 = 1 << 0;
+/// A GPIO line can be attached as sync input, its interrupt embeds markers in the stream.
+pub (crate) const ADXL345_CAP_SYNC_IRQ: u64 = 1 << 1;
+/// State changes are reported with uevents.
+pub (crate) const ADXL345_CAP_UEVENTS: u64 = 1 << 2;
+/// `ADXL345_IOC_SET_AUTO_RANGE`.
+pub (crate) const ADXL345_CAP_AUTO_RANGE: u64 = 1 << 3;
+/// Samples that barely change are filtered out; not set when built with `ADXL345_NO_FILTER=1`.
+pub (crate) const ADXL345_CAP_FILTER: u64 = 1 << 4;
+/// `ADXL345_IOC_SET_HEADER`.
+pub (crate) const ADXL345_CAP_SESSION_HEADER: u64 = 1 << 5;
+/// The preset ioctls.
+pub (crate) const ADXL345_CAP_PRESETS: u64 = 1 << 6;
+/// `ADXL345_IOC_SET_CRC`.
+pub (crate) const ADXL345_CAP_BATCH_CRC: u64 = 1 << 7;
+/// `ADXL345_IOC_SET_POLL_MODE`.
+pub (crate) const ADXL345_CAP_POLL_EDGE: u64 = 1 << 8;
+/// The configuration lock is an `rt_mutex`, built with `ADXL345_RT_MUTEX=1`.
+pub (crate) const ADXL345_CAP_RT_MUTEX: u64 = 1 << 9;
+/// The debugfs entries were created.
+pub (crate) const ADXL345_CAP_DEBUGFS: u64 = 1 << 10;
+/// The configfs subsystem is registered, instances can be created at runtime.
+pub (crate) const ADXL345_CAP_CONFIGFS: u64 = 1 << 11;
+/// The register map is simulated (`dry_run=1`), no sample comes from a sensor.
+pub (crate) const ADXL345_CAP_DRY_RUN: u64 = 1 << 12;
+/// `SIGIO` on new data and events, with `ADXL345_IOC_SET_SIGIO_THRESHOLD`.
+pub (crate) const ADXL345_CAP_FASYNC: u64 = 1 << 13;
+/// Text commands can be written to the device (`write_control=1`).
+pub (crate) const ADXL345_CAP_WRITE_CONTROL: u64 = 1 << 14;
+/// `ADXL345_IOC_SET_ERROR_POLICY`.
+pub (crate) const ADXL345_CAP_ERROR_POLICY: u64 = 1 << 15;
+/// The drain runs on the FIFO watermark interrupt (`data_gpio`), not only on its timer.
+pub (crate) const ADXL345_CAP_DATA_IRQ: u64 = 1 << 16;
+/// The thermal guard is bound to a thermal zone (`thermal_zone`).
+pub (crate) const ADXL345_CAP_THERMAL_GUARD: u64 = 1 << 17;
+/// A GPIO line is driven by the vibration alarm (`alarm_gpio`).
+pub (crate) const ADXL345_CAP_ALARM_GPIO: u64 = 1 << 18;
+/// `ADXL345_IOC_SET_RESAMPLE`.
+pub (crate) const ADXL345_CAP_RESAMPLE: u64 = 1 << 19;
+/// The SPI driver is registered (`spi`), the device may be bound on SPI.
+pub (crate) const ADXL345_CAP_SPI: u64 = 1 << 20;
+/// `ADXL345_IOC_SET_TAP` and the tap markers.
+pub (crate) const ADXL345_CAP_TAP: u64 = 1 << 21;
+/// `ADXL345_IOC_SET_BURST` and the burst markers.
+pub (crate) const ADXL345_CAP_BURST: u64 = 1 << 22;
+/// `ADXL345_IOC_SET_MOTION` and the motion events.
+pub (crate) const ADXL345_CAP_MOTION: u64 = 1 << 23;
+/// `ADXL345_IOC_SET_POWER`, idle standby and system sleep support.
+pub (crate) const ADXL345_CAP_POWER: u64 = 1 << 24;
+/// The event markers and `ADXL345_IOC_GET_EVENT`.
+pub (crate) const ADXL345_CAP_CORRELATION: u64 = 1 << 25;
+/// `ADXL345_IOC_CALIBRATE` and the offset ioctls.
+pub (crate) const ADXL345_CAP_CALIBRATION: u64 = 1 << 26;
+/// `ADXL345_IOC_SET_PIPELINE`; not set when built with `ADXL345_NO_FILTER=1`.
+pub (crate) const ADXL345_CAP_PIPELINE: u64 = 1 << 27;
+/// `ADXL345_IOC_SET_OUTPUT`.
+pub (crate) const ADXL345_CAP_SCALED_OUTPUT: u64 = 1 << 28;
+/// `ADXL345_OUTPUT_TIMESTAMPED`, samples stamped with the time they were read.
+pub (crate) const ADXL345_CAP_TIMESTAMPS: u64 = 1 << 29;
+/// `ADXL345_IOC_SET_BUFFER`, `ADXL345_IOC_GET_BUFFER` and the overrun count.
+pub (crate) const ADXL345_CAP_BUFFER: u64 = 1 << 30;
+
+/// Capabilities fixed when the driver is built.
+const ADXL345_CAPS_BUILD: u64 = ADXL345_CAP_FIFO
+    | ADXL345_CAP_SYNC_IRQ
+    | ADXL345_CAP_UEVENTS
+    | ADXL345_CAP_AUTO_RANGE
+    | if cfg!(adxl345_no_filter) { 0 } else { ADXL345_CAP_FILTER }
+    | ADXL345_CAP_SESSION_HEADER
+    | ADXL345_CAP_PRESETS
+    | ADXL345_CAP_BATCH_CRC
+    | ADXL345_CAP_POLL_EDGE
+    | ADXL345_CAP_FASYNC
+    | ADXL345_CAP_ERROR_POLICY
+    | ADXL345_CAP_RESAMPLE
+    | ADXL345_CAP_TAP
+    | ADXL345_CAP_BURST
+    | ADXL345_CAP_MOTION
+    | ADXL345_CAP_POWER
+    | ADXL345_CAP_CORRELATION
+    | ADXL345_CAP_CALIBRATION
+    | if cfg!(adxl345_no_filter) { 0 } else { ADXL345_CAP_PIPELINE }
+    | ADXL345_CAP_SCALED_OUTPUT
+    | ADXL345_CAP_TIMESTAMPS
+    | ADXL345_CAP_BUFFER
+    | if cfg!(adxl345_rt_mutex) { ADXL345_CAP_RT_MUTEX } else { 0 };
+
+/// Capabilities set at module init.
+static ADXL345_CAPS_INIT: AtomicU64 = AtomicU64::new(0);
+
+/// Marks the interfaces registered at module init as available.
+pub (crate) fn adxl345_caps_set(caps: u64) {
+    ADXL345_CAPS_INIT.fetch_or(caps, Ordering::Relaxed);
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const ADXL345_CAPS_NONE: AtomicU64 = AtomicU64::new(0);
+
+/// Capabilities set at probe, indexed by the id of the device.
+static ADXL345_CAPS_DEVICE: [AtomicU64; ADXL345_DEVICES_MAX] = [ADXL345_CAPS_NONE; ADXL345_DEVICES_MAX];
+
+/// Marks the lines attached to device `id` at probe as available on its files.
+pub (crate) fn adxl345_device_caps_set(id: usize, caps: u64) {
+    ADXL345_CAPS_DEVICE[id].fetch_or(caps, Ordering::Relaxed);
+}
+
+/// Forgets the lines of device `id`, called when it is removed.
+pub (crate) fn adxl345_device_caps_clear(id: usize) {
+    ADXL345_CAPS_DEVICE[id].store(0, Ordering::Relaxed);
+}
+
+/// Returns the capabilities of the driver.
+fn adxl345_caps() -> u64 {
+    let dry_run = if ADXL345_DRY_RUN.enabled() { ADXL345_CAP_DRY_RUN } else { 0 };
+    ADXL345_CAPS_BUILD | ADXL345_CAPS_INIT.load(Ordering::Relaxed) | dry_run
+}
+
+/// Returns the capabilities available on the files of device `id`.
+pub (crate) fn adxl345_caps_of(id: usize) -> u64 {
+    adxl345_caps() | ADXL345_CAPS_DEVICE[id].load(Ordering::Relaxed)
+}
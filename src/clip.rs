@@ -0,0 +1,52 @@
+/*
+ * Copyright 2024 Luca Saverio Esposito, Università di Roma, Tor Vergata
+ * email: <lucasaverioesposito@gmail.com>
+ *
+ * This file is part of an "Rust Linux driver for the ADXL345 device".
+ *
+ * This driver is free software: you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * This driver is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with Foobar.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+
+
+// clip.rs
+
+//! Saturation detection.
+//!
+//! In full resolution the device keeps 3.9 mg/LSB at every range, so its output saturates at
+//! `range_g * 256` LSBs. A sample sitting on that rail on some axis was clipped: the real
+//! acceleration was beyond the range, and any analysis of it is wrong. The drain checks every
+//! sample against the rails of the range in use and queues a clip marker (kind
+//! `ADXL345_MARKER_CLIP`) right before a clipped sample, its z field holding the mask of the
+//! clipped axes. Clipped samples are counted in `samples_clipped`, so a range too small for the
+//! application shows up without reading the stream.
+
+use crate::structures::Adxl345Sample;
+
+/// Returns the axes of `sample` sitting on a rail of the `range_g` range, as a mask (bit 0 x,
+/// bit 1 y, bit 2 z), 0 if the sample is not clipped.
+pub (crate) fn adxl345_clip_axes(sample: &Adxl345Sample, range_g: u32) -> i16 {
+    // Full resolution data spans -range_g * 256 to range_g * 256 - 1 LSBs, shifted by 2
+    let low = -(range_g as i32 * 256) << 2;
+    let high = (range_g as i32 * 256 - 1) << 2;
+
+    let mut axes = 0;
+    for (bit, value) in [sample.x, sample.y, sample.z].iter().enumerate() {
+        let value = *value as i32;
+        if value <= low || value >= high {
+            axes |= 1 << bit;
+        }
+    }
+    axes
+}
@@ -0,0 +1,168 @@
+/*
+ * Copyright 2024 Luca Saverio Esposito, Università di Roma, Tor Vergata
+ * email: <lucasaverioesposito@gmail.com>
+ *
+ * This file is part of an "Rust Linux driver for the ADXL345 device".
+ *
+ * This driver is free software: you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * This driver is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with Foobar.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+
+
+// concurrency.rs
+
+//! Lock hold times, buffer occupancy and wakeups, for spotting regressions of the data path.
+//!
+//! Three locks are timed where the data path takes them:
+//! - `config`: the configuration lock, held by a configuration ioctl (see ioctl.rs);
+//! - `device`: the device lock, held by a drain pass while it reads the device and fills
+//!   the buffer (see drain.rs);
+//! - `consumer`: the consumer mutex, held by a read while it copies the buffered records.
+//!
+//! Each keeps the number of holds, their total and the longest one, so `average = total /
+//! holds`. The buffer occupancy is sampled by the drain after each pass, its highest value is
+//! kept; `wakeups` counts the drain waking up the readers and `sleeps` the reads that waited
+//! for data. Everything is a relaxed atomic, the cost on the hot paths is two `ktime_get_ns()`
+//! per hold. `/sys/kernel/debug/adxl345/concurrency` shows them, writing 0 to it clears them.
+
+use kernel::prelude::*;
+use kernel::str::CString;
+use kernel::time::ktime_get_ns;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// Hold times of a lock.
+pub (crate) struct Adxl345HoldStat {
+    holds: AtomicU64,
+    total_ns: AtomicU64,
+    max_ns: AtomicU64,
+}
+
+/// A timed hold, recorded when dropped. Declared after the lock guard, it is dropped before it.
+pub (crate) struct Adxl345Hold<'a> {
+    stat: &'a Adxl345HoldStat,
+    start_ns: u64,
+}
+
+impl Adxl345HoldStat {
+    const fn new() -> Self {
+        Self {
+            holds: AtomicU64::new(0),
+            total_ns: AtomicU64::new(0),
+            max_ns: AtomicU64::new(0),
+        }
+    }
+
+    /// Starts timing a hold of the lock, just taken.
+    pub (crate) fn hold(&self) -> Adxl345Hold<'_> {
+        Adxl345Hold { stat: self, start_ns: ktime_get_ns() }
+    }
+
+    fn reset(&self) {
+        self.holds.store(0, Ordering::Relaxed);
+        self.total_ns.store(0, Ordering::Relaxed);
+        self.max_ns.store(0, Ordering::Relaxed);
+    }
+}
+
+impl Drop for Adxl345Hold<'_> {
+    fn drop(&mut self) {
+        let ns = ktime_get_ns().saturating_sub(self.start_ns);
+        self.stat.holds.fetch_add(1, Ordering::Relaxed);
+        self.stat.total_ns.fetch_add(ns, Ordering::Relaxed);
+        self.stat.max_ns.fetch_max(ns, Ordering::Relaxed);
+    }
+}
+
+/// Concurrency metrics of the data path.
+pub (crate) struct Adxl345Concurrency {
+    pub (crate) config: Adxl345HoldStat,
+    pub (crate) device: Adxl345HoldStat,
+    pub (crate) consumer: Adxl345HoldStat,
+    occupancy_max: AtomicU64, // Highest number of buffered records seen by the drain
+    wakeups: AtomicU64,       // Readers woken up by the drain
+    sleeps: AtomicU64,        // Reads that waited for data
+}
+
+/// Global concurrency metrics.
+pub (crate) static ADXL345_CONCURRENCY: Adxl345Concurrency = Adxl345Concurrency::new();
+
+impl Adxl345Concurrency {
+    const fn new() -> Self {
+        Self {
+            config: Adxl345HoldStat::new(),
+            device: Adxl345HoldStat::new(),
+            consumer: Adxl345HoldStat::new(),
+            occupancy_max: AtomicU64::new(0),
+            wakeups: AtomicU64::new(0),
+            sleeps: AtomicU64::new(0),
+        }
+    }
+
+    /// Records the buffer holding `buffered` records.
+    pub (crate) fn occupancy(&self, buffered: usize) {
+        self.occupancy_max.fetch_max(buffered as u64, Ordering::Relaxed);
+    }
+
+    /// Counts the drain waking up the readers.
+    pub (crate) fn wakeup(&self) {
+        self.wakeups.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a read waiting for data.
+    pub (crate) fn sleep(&self) {
+        self.sleeps.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Clears the metrics, the buffer occupancy starts again from what it holds now.
+    pub (crate) fn reset(&self) {
+        for stat in [&self.config, &self.device, &self.consumer] {
+            stat.reset();
+        }
+        self.occupancy_max.store(0, Ordering::Relaxed);
+        self.wakeups.store(0, Ordering::Relaxed);
+        self.sleeps.store(0, Ordering::Relaxed);
+    }
+
+    /// Formats the metrics, with `buffered` and `capacity` the buffer now.
+    ///
+    /// The counters are read one at a time while the data path runs, a hold may be counted in
+    /// `holds` and not yet in `total_ns`.
+    pub (crate) fn text(&self, buffered: usize, capacity: usize) -> Result<Vec<u8>> {
+        let mut ret = Vec::new();
+        for (name, stat) in [("config", &self.config), ("device", &self.device), ("consumer", &self.consumer)] {
+            let holds = stat.holds.load(Ordering::Relaxed);
+            let total_ns = stat.total_ns.load(Ordering::Relaxed);
+            let text = CString::try_from_fmt(fmt!(
+                "{}_holds {}\n{}_hold_avg_ns {}\n{}_hold_max_ns {}\n",
+                name,
+                holds,
+                name,
+                total_ns.checked_div(holds).unwrap_or(0),
+                name,
+                stat.max_ns.load(Ordering::Relaxed)
+            ))?;
+            ret.try_extend_from_slice(text.as_bytes())?;
+        }
+        let text = CString::try_from_fmt(fmt!(
+            "buffered {}\nbuffer_capacity {}\nbuffered_max {}\nwakeups {}\nsleeps {}\n",
+            buffered,
+            capacity,
+            self.occupancy_max.load(Ordering::Relaxed),
+            self.wakeups.load(Ordering::Relaxed),
+            self.sleeps.load(Ordering::Relaxed)
+        ))?;
+        ret.try_extend_from_slice(text.as_bytes())?;
+        Ok(ret)
+    }
+}
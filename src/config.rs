@@ -0,0 +1,314 @@
+/*
+ * Copyright 2024 Luca Saverio Esposito, Università di Roma, Tor Vergata
+ * email: <lucasaverioesposito@gmail.com>
+ *
+ * This file is part of an "Rust Linux driver for the ADXL345 device".
+ *
+ * This driver is free software: you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * This driver is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with Foobar.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+
+// config.rs
+
+//! Validation of user-provided configuration values.
+//!
+//! Every configuration value coming from user space goes through [`adxl345_validate`] before
+//! being written to the device. Out-of-range values are rejected with `ERANGE` instead of being
+//! silently truncated to the register width, and the reason of the last rejection is kept so it
+//! can be read back from debugfs.
+
+use kernel::prelude::*;
+use kernel::error::code::{EINVAL, ERANGE};
+use kernel::io_buffer::{ReadableFromBytes, WritableToBytes};
+use kernel::str::CString;
+use core::sync::atomic::{AtomicU64, Ordering};
+use crate::constant::*;
+
+/// Output data rates supported by the device, in mHz, indexed by the BW_RATE rate code.
+pub (crate) const ADXL345_RATES_MHZ: [u32; 16] = [
+    100, 200, 390, 780, 1_560, 3_130, 6_250, 12_500,
+    25_000, 50_000, 100_000, 200_000, 400_000, 800_000, 1_600_000, 3_200_000,
+];
+
+/// Measurement ranges supported by the device, in g, indexed by the DATA_FORMAT range code.
+pub (crate) const ADXL345_RANGES_G: [u32; 4] = [2, 4, 8, 16];
+
+/// Largest FIFO watermark, the FIFO_CTL samples field is 5 bits wide.
+pub (crate) const ADXL345_MAX_WATERMARK: u32 = 31;
+
+/// Number of configurable parameters, the ids of `Adxl345Param` go from 0 to this value excluded.
+pub (crate) const ADXL345_PARAMS: usize = 12;
+
+/// Configurable parameters of the device.
+///
+/// Thresholds and durations are expressed in register LSBs.
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub (crate) enum Adxl345Param {
+    Rate = 0,        // Output data rate, in mHz
+    Range = 1,       // Measurement range, in g
+    Watermark = 2,   // FIFO watermark, in entries (1 .. 31)
+    ThreshTap = 3,   // Tap threshold
+    Dur = 4,         // Tap duration
+    Latent = 5,      // Tap latency
+    Window = 6,      // Tap window
+    ThreshAct = 7,   // Activity threshold
+    ThreshInact = 8, // Inactivity threshold
+    TimeInact = 9,   // Inactivity time
+    ThreshFf = 10,   // Free-fall threshold
+    TimeFf = 11,     // Free-fall time
+}
+
+impl Adxl345Param {
+    /// Converts the raw parameter id received from user space.
+    pub (crate) fn from_raw(id: u32) -> Result<Self> {
+        let param = match id {
+            0 => Adxl345Param::Rate,
+            1 => Adxl345Param::Range,
+            2 => Adxl345Param::Watermark,
+            3 => Adxl345Param::ThreshTap,
+            4 => Adxl345Param::Dur,
+            5 => Adxl345Param::Latent,
+            6 => Adxl345Param::Window,
+            7 => Adxl345Param::ThreshAct,
+            8 => Adxl345Param::ThreshInact,
+            9 => Adxl345Param::TimeInact,
+            10 => Adxl345Param::ThreshFf,
+            11 => Adxl345Param::TimeFf,
+            _ => return Err(EINVAL),
+        };
+        Ok(param)
+    }
+
+    /// Looks a parameter up by its name, e.g. `thresh_tap`.
+    pub (crate) fn from_name(name: &str) -> Option<Self> {
+        (0..ADXL345_PARAMS as u32)
+            .filter_map(|id| Self::from_raw(id).ok())
+            .find(|param| param.name() == name)
+    }
+
+    /// Returns the parameter name, as shown in debugfs.
+    pub (crate) const fn name(self) -> &'static str {
+        match self {
+            Adxl345Param::Rate => "rate",
+            Adxl345Param::Range => "range",
+            Adxl345Param::Watermark => "watermark",
+            Adxl345Param::ThreshTap => "thresh_tap",
+            Adxl345Param::Dur => "dur",
+            Adxl345Param::Latent => "latent",
+            Adxl345Param::Window => "window",
+            Adxl345Param::ThreshAct => "thresh_act",
+            Adxl345Param::ThreshInact => "thresh_inact",
+            Adxl345Param::TimeInact => "time_inact",
+            Adxl345Param::ThreshFf => "thresh_ff",
+            Adxl345Param::TimeFf => "time_ff",
+        }
+    }
+
+    /// Returns the scale of the parameter in human units: one register LSB is worth
+    /// `num / den` mg for thresholds and `num / den` µs for durations.
+    ///
+    /// Rate, range and watermark are already expressed in human units, so their scale is 1.
+    pub (crate) const fn scale(self) -> (u64, u64) {
+        match self {
+            // 62.5 mg/LSB
+            Adxl345Param::ThreshTap
+            | Adxl345Param::ThreshAct
+            | Adxl345Param::ThreshInact
+            | Adxl345Param::ThreshFf => (125, 2),
+            // 625 µs/LSB
+            Adxl345Param::Dur => (625, 1),
+            // 1.25 ms/LSB
+            Adxl345Param::Latent | Adxl345Param::Window => (1_250, 1),
+            // 1 s/LSB
+            Adxl345Param::TimeInact => (1_000_000, 1),
+            // 5 ms/LSB
+            Adxl345Param::TimeFf => (5_000, 1),
+            _ => (1, 1),
+        }
+    }
+
+    /// Returns the register holding an 8-bit threshold or duration, `None` for the
+    /// parameters stored in a bit field of a shared register.
+    pub (crate) const fn register(self) -> Option<u8> {
+        match self {
+            Adxl345Param::ThreshTap => Some(ADXL345_REG_THRESH_TAP),
+            Adxl345Param::Dur => Some(ADXL345_REG_DUR),
+            Adxl345Param::Latent => Some(ADXL345_REG_LATENT),
+            Adxl345Param::Window => Some(ADXL345_REG_WINDOW),
+            Adxl345Param::ThreshAct => Some(ADXL345_REG_THRES_ACT),
+            Adxl345Param::ThreshInact => Some(ADXL345_REG_THRES_INACT),
+            Adxl345Param::TimeInact => Some(ADXL345_REG_TIME_INACT),
+            Adxl345Param::ThreshFf => Some(ADXL345_REG_THRES_FF),
+            Adxl345Param::TimeFf => Some(ADXL345_REG_TIME_FF),
+            _ => None,
+        }
+    }
+}
+
+/// Argument of the `ADXL345_IOC_SET_PARAM` and `ADXL345_IOC_GET_PARAM` ioctls.
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub (crate) struct Adxl345ParamArg {
+    pub (crate) param: u32, // Parameter id, see `Adxl345Param`
+    pub (crate) value: u32, // Parameter value
+}
+
+// SAFETY: `Adxl345ParamArg` is `repr(C)`, made only of integers and has no padding, so any
+// byte pattern is a valid value.
+unsafe impl ReadableFromBytes for Adxl345ParamArg {}
+unsafe impl WritableToBytes for Adxl345ParamArg {}
+
+/// Reasons why a configuration value can be rejected.
+#[repr(u32)]
+#[derive(Copy, Clone)]
+enum Adxl345ConfigError {
+    None = 0,
+    UnsupportedRate = 1,
+    UnsupportedRange = 2,
+    WatermarkRange = 3,
+    RegisterRange = 4,
+    ScaledRange = 5,
+}
+
+impl Adxl345ConfigError {
+    fn from_raw(raw: u32) -> Self {
+        match raw {
+            1 => Adxl345ConfigError::UnsupportedRate,
+            2 => Adxl345ConfigError::UnsupportedRange,
+            3 => Adxl345ConfigError::WatermarkRange,
+            4 => Adxl345ConfigError::RegisterRange,
+            5 => Adxl345ConfigError::ScaledRange,
+            _ => Adxl345ConfigError::None,
+        }
+    }
+
+    fn reason(self) -> &'static str {
+        match self {
+            Adxl345ConfigError::None => "no error",
+            Adxl345ConfigError::UnsupportedRate =>
+                "not a supported output data rate (100 mHz .. 3200000 mHz, see BW_RATE)",
+            Adxl345ConfigError::UnsupportedRange => "not a supported range (2, 4, 8 or 16 g)",
+            Adxl345ConfigError::WatermarkRange =>
+                "watermark must be between 1 and 31 entries (5-bit samples field of FIFO_CTL)",
+            Adxl345ConfigError::RegisterRange => "exceeds the 8-bit register range (0 .. 255 LSB)",
+            Adxl345ConfigError::ScaledRange =>
+                "exceeds the register range (thresholds 0 .. 15938 mg; durations 0 .. 159375 us for dur, \
+                 318750 us for latent and window, 1275000 us for time_ff, 255000000 us for time_inact)",
+        }
+    }
+}
+
+/// Last rejected configuration value, kept lock-free so any path can record it. The reason, the
+/// parameter and the value are packed in one word, see `adxl345_config_error_pack`, so a reader
+/// never mixes the fields of two rejections racing with each other. 0 is no error.
+static ADXL345_LAST_CONFIG_ERROR: AtomicU64 = AtomicU64::new(0);
+
+/// Packs a rejection: the value in bits 0-31, the parameter in bits 32-39 and the reason in
+/// bits 40-47.
+const fn adxl345_config_error_pack(error: Adxl345ConfigError, param: Adxl345Param, value: u32) -> u64 {
+    (error as u64) << 40 | (param as u64) << 32 | value as u64
+}
+
+/// Records the rejection of `value` for `param` and returns `ERANGE`.
+fn adxl345_reject(param: Adxl345Param, value: u32, error: Adxl345ConfigError) -> Error {
+    ADXL345_LAST_CONFIG_ERROR.store(adxl345_config_error_pack(error, param, value), Ordering::Relaxed);
+    pr_debug!("rejected {} = {}: {}\n", param.name(), value, error.reason());
+    ERANGE
+}
+
+/// Validates a configuration value and converts it into its register field.
+///
+/// # Parameters
+/// - `param`: The parameter being configured.
+/// - `value`: The value provided by user space, in the unit of the parameter.
+///
+/// # Returns
+/// - `Ok(u8)` containing the register field encoding `value`.
+/// - `Err(ERANGE)` if `value` can't be represented, the reason is recorded for debugfs.
+pub (crate) fn adxl345_validate(param: Adxl345Param, value: u32) -> Result<u8> {
+    match param {
+        Adxl345Param::Rate => ADXL345_RATES_MHZ
+            .iter()
+            .position(|&rate| rate == value)
+            .map(|code| code as u8)
+            .ok_or_else(|| adxl345_reject(param, value, Adxl345ConfigError::UnsupportedRate)),
+        Adxl345Param::Range => ADXL345_RANGES_G
+            .iter()
+            .position(|&range| range == value)
+            .map(|code| code as u8)
+            .ok_or_else(|| adxl345_reject(param, value, Adxl345ConfigError::UnsupportedRange)),
+        Adxl345Param::Watermark => {
+            if value == 0 || value > ADXL345_MAX_WATERMARK {
+                return Err(adxl345_reject(param, value, Adxl345ConfigError::WatermarkRange));
+            }
+            Ok(value as u8)
+        }
+        _ => u8::try_from(value)
+            .map_err(|_| adxl345_reject(param, value, Adxl345ConfigError::RegisterRange)),
+    }
+}
+
+/// Converts a value in human units (mg or µs) into register LSBs, rounding to the nearest LSB.
+///
+/// # Parameters
+/// - `param`: The parameter being configured.
+/// - `value`: The value in the human unit of the parameter.
+///
+/// # Returns
+/// - `Ok(u32)` containing the value in register LSBs, ready for [`adxl345_validate`].
+/// - `Err(ERANGE)` if the value is above the one 255 LSB reads back as, the reason is recorded.
+pub (crate) fn adxl345_from_scaled(param: Adxl345Param, value: u32) -> Result<u32> {
+    let (num, den) = param.scale();
+    if num == den {
+        return Ok(value);
+    }
+
+    // The bound is the largest value reported by GET_PARAM_SCALED, the one of the reason, and
+    // the values up to it round to at most 255
+    if value > adxl345_scaled_max(param) {
+        return Err(adxl345_reject(param, value, Adxl345ConfigError::ScaledRange));
+    }
+    // Widened arithmetic, `value * den` can't overflow a u64
+    Ok(((value as u64 * den + num / 2) / num) as u32)
+}
+
+/// Returns the largest value in human units (mg or µs) of a scaled parameter, the one of 255 LSB.
+pub (crate) const fn adxl345_scaled_max(param: Adxl345Param) -> u32 {
+    adxl345_to_scaled(param, u8::MAX as u32)
+}
+
+/// Converts a value in register LSBs into human units (mg or µs), rounding to the nearest unit.
+///
+/// This is the value actually achieved by the device once `raw` is written.
+pub (crate) const fn adxl345_to_scaled(param: Adxl345Param, raw: u32) -> u32 {
+    let (num, den) = param.scale();
+    ((raw as u64 * num + den / 2) / den) as u32
+}
+
+/// Describes the last rejected configuration value.
+///
+/// # Returns
+/// The formatted `CString`, e.g. `rate = 123: not a supported output data rate ...`.
+pub (crate) fn adxl345_last_config_error() -> Result<CString> {
+    let packed = ADXL345_LAST_CONFIG_ERROR.load(Ordering::Relaxed);
+    let error = Adxl345ConfigError::from_raw((packed >> 40) as u8 as u32);
+    if let Adxl345ConfigError::None = error {
+        return CString::try_from_fmt(fmt!("{}\n", error.reason()));
+    }
+
+    let param = Adxl345Param::from_raw((packed >> 32) as u8 as u32)?;
+    let value = packed as u32;
+    CString::try_from_fmt(fmt!("{} = {}: {} (ERANGE)\n", param.name(), value, error.reason()))
+}
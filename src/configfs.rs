@@ -0,0 +1,317 @@
+/*
+ * Copyright 2024 Luca Saverio Esposito, Università di Roma, Tor Vergata
+ * email: <lucasaverioesposito@gmail.com>
+ *
+ * This file is part of an "Rust Linux driver for the ADXL345 device".
+ *
+ * This driver is free software: you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * This driver is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with Foobar.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+
+// configfs.rs
+
+//! configfs interface instantiating the device at runtime.
+//!
+//! On systems without a device tree, the bus and address of the ADXL345 may not be known when
+//! the module is loaded (`i2c_bus=-1` then creates no device). An administrator composes the
+//! instance in configfs instead, as for i2c-stub or USB gadgets:
+//!
+//! ```text
+//! mkdir /sys/kernel/config/adxl345/board0
+//! echo 2 > /sys/kernel/config/adxl345/board0/bus
+//! echo 0x53 > /sys/kernel/config/adxl345/board0/address
+//! echo 1 > /sys/kernel/config/adxl345/board0/enable
+//! ```
+//!
+//! Enabling an item creates the I2C client, which the driver binds and probes; writing `0` to
+//! `enable` or removing the directory tears it down cleanly. Each item is one device, on its own
+//! bus or address: enabling fails with `EBUSY` if a client already exists there, or once
+//! `ADXL345_DEVICES_MAX` devices are bound (items, the client created at load on the `i2c_bus`
+//! module parameter, and the devices bound from the device tree or on SPI, see instance.rs).
+//! `bus` and `address` can't be changed while the item is enabled.
+
+use kernel::prelude::*;
+use kernel::bindings;
+use kernel::c_str;
+use kernel::error::code::{EBUSY, EINVAL};
+use kernel::error::to_result;
+use kernel::str::{CStr, CString};
+use kernel::ThisModule;
+use core::ffi::c_char;
+use core::mem::MaybeUninit;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicI32, AtomicU32, Ordering};
+use crate::constant::{ADXL345_I2C_ADDR, ADXL345_I2C_ADDR_MAX};
+use crate::instance::{adxl345_instance_create, adxl345_instance_destroy};
+
+/// Default bus of a new item.
+const ADXL345_CONFIGFS_BUS: i32 = 1;
+
+/// A directory of the tree, one candidate instance.
+#[repr(C)]
+struct Adxl345ConfigfsItem {
+    item: bindings::config_item,    // Must stay first, the callbacks cast the item back
+    bus: AtomicI32,                 // I2C bus number
+    addr: AtomicU32,                // 7-bit I2C address
+    enabled: AtomicBool,            // Whether this item created the instance
+}
+
+impl Adxl345ConfigfsItem {
+    /// Returns the item embedding `item`.
+    ///
+    /// # Safety
+    /// `item` is the `config_item` of a live `Adxl345ConfigfsItem`, created by `make_item`.
+    unsafe fn from_item<'a>(item: *mut bindings::config_item) -> &'a Self {
+        unsafe { &*(item as *const Self) }
+    }
+
+    /// Deletes the client this item created, if it is enabled.
+    fn disable(&self) {
+        if self.enabled.swap(false, Ordering::Relaxed) {
+            adxl345_instance_destroy(self.bus.load(Ordering::Relaxed), self.addr.load(Ordering::Relaxed) as u16);
+        }
+    }
+}
+
+/// The registered subsystem and the operations of its items, in one allocation that outlives
+/// every item: configfs holds a reference to the module while items exist.
+#[repr(C)]
+pub (crate) struct Adxl345Configfs {
+    subsys: bindings::configfs_subsystem,           // Must stay first, make_item casts the group back
+    group_type: bindings::config_item_type,
+    group_ops: bindings::configfs_group_operations,
+    item_type: bindings::config_item_type,
+    item_ops: bindings::configfs_item_operations,
+    attrs: [bindings::configfs_attribute; 3],
+    attr_ptrs: [*mut bindings::configfs_attribute; 4], // NULL terminated
+}
+
+/// Lock class of the subsystem mutex.
+static mut ADXL345_CONFIGFS_KEY: MaybeUninit<bindings::lock_class_key> = MaybeUninit::uninit();
+
+/// Registers the `adxl345` configfs subsystem.
+///
+/// # Returns
+/// - `Ok(Box<Adxl345Configfs>)` if the subsystem is registered, it is unregistered on drop.
+/// - `Err(Error)` if configfs is not available, the driver works without it.
+pub (crate) fn adxl345_configfs_register(module: &'static ThisModule) -> Result<Box<Adxl345Configfs>> {
+    // SAFETY: The C structures are valid when zeroed, the pointers are set below.
+    let mut configfs = Box::try_new(unsafe { core::mem::zeroed::<Adxl345Configfs>() })?;
+    let cfs = &mut *configfs;
+
+    let attrs: [(&CStr, u16, _, _); 3] = [
+        (c_str!("bus"), 0o644, adxl345_configfs_bus_show as ShowFn, adxl345_configfs_bus_store as StoreFn),
+        (c_str!("address"), 0o644, adxl345_configfs_addr_show, adxl345_configfs_addr_store),
+        (c_str!("enable"), 0o644, adxl345_configfs_enable_show, adxl345_configfs_enable_store),
+    ];
+    for (index, (name, mode, show, store)) in attrs.into_iter().enumerate() {
+        cfs.attrs[index].ca_name = name.as_char_ptr();
+        cfs.attrs[index].ca_owner = module.as_ptr();
+        cfs.attrs[index].ca_mode = mode;
+        cfs.attrs[index].show = Some(show);
+        cfs.attrs[index].store = Some(store);
+        cfs.attr_ptrs[index] = &mut cfs.attrs[index];
+    }
+
+    cfs.item_ops.release = Some(adxl345_configfs_release);
+    cfs.item_type.ct_owner = module.as_ptr();
+    cfs.item_type.ct_item_ops = &mut cfs.item_ops;
+    cfs.item_type.ct_attrs = cfs.attr_ptrs.as_mut_ptr();
+
+    cfs.group_ops.make_item = Some(adxl345_configfs_make_item);
+    cfs.group_ops.drop_item = Some(adxl345_configfs_drop_item);
+    cfs.group_type.ct_owner = module.as_ptr();
+    cfs.group_type.ct_group_ops = &mut cfs.group_ops;
+
+    // SAFETY: The structures are allocated for as long as the subsystem is registered, the name
+    // and the lock class key are static.
+    unsafe {
+        bindings::config_group_init_type_name(
+            &mut cfs.subsys.su_group,
+            c_str!("adxl345").as_char_ptr(),
+            &mut cfs.group_type,
+        );
+        bindings::__mutex_init(
+            &mut cfs.subsys.su_mutex,
+            c_str!("adxl345_configfs").as_char_ptr(),
+            ADXL345_CONFIGFS_KEY.as_mut_ptr(),
+        );
+        to_result(bindings::configfs_register_subsystem(&mut cfs.subsys))?;
+    }
+    Ok(configfs)
+}
+
+impl Drop for Adxl345Configfs {
+    fn drop(&mut self) {
+        // SAFETY: The subsystem was registered by `adxl345_configfs_register`.
+        unsafe { bindings::configfs_unregister_subsystem(&mut self.subsys) };
+    }
+}
+
+type ShowFn = unsafe extern "C" fn(*mut bindings::config_item, *mut c_char) -> isize;
+type StoreFn = unsafe extern "C" fn(*mut bindings::config_item, *const c_char, usize) -> isize;
+
+/// Creates the item for a new directory, with the default bus and address.
+unsafe extern "C" fn adxl345_configfs_make_item(
+    group: *mut bindings::config_group,
+    name: *const c_char,
+) -> *mut bindings::config_item {
+    // SAFETY: The only group is the one of the subsystem, first field of `Adxl345Configfs`.
+    let configfs = unsafe { &mut *(group as *mut Adxl345Configfs) };
+
+    // SAFETY: The C structure is valid when zeroed, it is initialized below.
+    let item = match Box::try_new(unsafe { core::mem::zeroed::<Adxl345ConfigfsItem>() }) {
+        Ok(item) => Box::into_raw(item),
+        Err(_) => return ptr::null_mut(), // configfs reports ENOMEM
+    };
+    // SAFETY: `item` was just allocated, its last reference is dropped by `release`.
+    unsafe {
+        (*item).bus.store(ADXL345_CONFIGFS_BUS, Ordering::Relaxed);
+        (*item).addr.store(ADXL345_I2C_ADDR as u32, Ordering::Relaxed);
+        bindings::config_item_init_type_name(&mut (*item).item, name, &mut configfs.item_type);
+        &mut (*item).item
+    }
+}
+
+/// Tears the instance down when the directory of the item that created it is removed.
+unsafe extern "C" fn adxl345_configfs_drop_item(
+    _group: *mut bindings::config_group,
+    item: *mut bindings::config_item,
+) {
+    // SAFETY: Every item of the group is created by `make_item`.
+    let cfs_item = unsafe { Adxl345ConfigfsItem::from_item(item) };
+    cfs_item.disable();
+    // SAFETY: Drops the reference taken by `config_item_init_type_name`.
+    unsafe { bindings::config_item_put(item) };
+}
+
+/// Frees an item once its last reference is dropped.
+unsafe extern "C" fn adxl345_configfs_release(item: *mut bindings::config_item) {
+    // SAFETY: The item was allocated by `make_item` with `Box::into_raw`.
+    drop(unsafe { Box::from_raw(item as *mut Adxl345ConfigfsItem) });
+}
+
+/// Formats an attribute into the page provided by configfs.
+fn adxl345_configfs_show(page: *mut c_char, args: core::fmt::Arguments<'_>) -> isize {
+    let text = match CString::try_from_fmt(args) {
+        Ok(text) => text,
+        Err(e) => return e.to_errno() as isize,
+    };
+    let bytes = text.as_bytes();
+    // SAFETY: configfs provides a page, much larger than the short values shown here.
+    unsafe { ptr::copy_nonoverlapping(bytes.as_ptr(), page as *mut u8, bytes.len()) };
+    bytes.len() as isize
+}
+
+/// Parses the value written to an attribute, decimal or `0x` hexadecimal.
+fn adxl345_configfs_parse(page: *const c_char, count: usize) -> Result<u32> {
+    // SAFETY: configfs provides `count` bytes written by user space.
+    let bytes = unsafe { core::slice::from_raw_parts(page as *const u8, count) };
+    let text = core::str::from_utf8(bytes).map_err(|_| EINVAL)?.trim();
+    match text.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => text.parse(),
+    }
+    .map_err(|_| EINVAL)
+}
+
+/// Returns the number of bytes consumed by a store, or the errno.
+fn adxl345_configfs_stored(result: Result, count: usize) -> isize {
+    match result {
+        Ok(()) => count as isize,
+        Err(e) => e.to_errno() as isize,
+    }
+}
+
+unsafe extern "C" fn adxl345_configfs_bus_show(item: *mut bindings::config_item, page: *mut c_char) -> isize {
+    // SAFETY: Every item of the group is created by `make_item`.
+    let cfs_item = unsafe { Adxl345ConfigfsItem::from_item(item) };
+    adxl345_configfs_show(page, fmt!("{}\n", cfs_item.bus.load(Ordering::Relaxed)))
+}
+
+unsafe extern "C" fn adxl345_configfs_bus_store(
+    item: *mut bindings::config_item,
+    page: *const c_char,
+    count: usize,
+) -> isize {
+    // SAFETY: Every item of the group is created by `make_item`.
+    let cfs_item = unsafe { Adxl345ConfigfsItem::from_item(item) };
+    let result = adxl345_configfs_parse(page, count).and_then(|bus| {
+        if cfs_item.enabled.load(Ordering::Relaxed) {
+            return Err(EBUSY);
+        }
+        cfs_item.bus.store(i32::try_from(bus).map_err(|_| EINVAL)?, Ordering::Relaxed);
+        Ok(())
+    });
+    adxl345_configfs_stored(result, count)
+}
+
+unsafe extern "C" fn adxl345_configfs_addr_show(item: *mut bindings::config_item, page: *mut c_char) -> isize {
+    // SAFETY: Every item of the group is created by `make_item`.
+    let cfs_item = unsafe { Adxl345ConfigfsItem::from_item(item) };
+    adxl345_configfs_show(page, fmt!("0x{:02x}\n", cfs_item.addr.load(Ordering::Relaxed)))
+}
+
+unsafe extern "C" fn adxl345_configfs_addr_store(
+    item: *mut bindings::config_item,
+    page: *const c_char,
+    count: usize,
+) -> isize {
+    // SAFETY: Every item of the group is created by `make_item`.
+    let cfs_item = unsafe { Adxl345ConfigfsItem::from_item(item) };
+    let result = adxl345_configfs_parse(page, count).and_then(|addr| {
+        if addr > ADXL345_I2C_ADDR_MAX {
+            return Err(EINVAL);
+        }
+        if cfs_item.enabled.load(Ordering::Relaxed) {
+            return Err(EBUSY);
+        }
+        cfs_item.addr.store(addr, Ordering::Relaxed);
+        Ok(())
+    });
+    adxl345_configfs_stored(result, count)
+}
+
+unsafe extern "C" fn adxl345_configfs_enable_show(item: *mut bindings::config_item, page: *mut c_char) -> isize {
+    // SAFETY: Every item of the group is created by `make_item`.
+    let cfs_item = unsafe { Adxl345ConfigfsItem::from_item(item) };
+    adxl345_configfs_show(page, fmt!("{}\n", cfs_item.enabled.load(Ordering::Relaxed) as u32))
+}
+
+unsafe extern "C" fn adxl345_configfs_enable_store(
+    item: *mut bindings::config_item,
+    page: *const c_char,
+    count: usize,
+) -> isize {
+    // SAFETY: Every item of the group is created by `make_item`.
+    let cfs_item = unsafe { Adxl345ConfigfsItem::from_item(item) };
+    let result = adxl345_configfs_parse(page, count).and_then(|enable| match enable {
+        0 => {
+            cfs_item.disable();
+            Ok(())
+        }
+        1 => {
+            if cfs_item.enabled.load(Ordering::Relaxed) {
+                return Ok(());
+            }
+            let bus = cfs_item.bus.load(Ordering::Relaxed);
+            let addr = cfs_item.addr.load(Ordering::Relaxed) as u16;
+            adxl345_instance_create(bus, addr)?;
+            cfs_item.enabled.store(true, Ordering::Relaxed);
+            Ok(())
+        }
+        _ => Err(EINVAL),
+    });
+    adxl345_configfs_stored(result, count)
+}
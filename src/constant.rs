@@ -35,12 +35,31 @@ pub (crate) const DR_NAME: &[u8] = b"adxl345";
 pub (crate) const DR_NAME_WN: &[u8] = b"adxl345\0";
 
 
+// Default I2C bus, it can be overridden with the `i2c_bus` module parameter
 #[allow(dead_code)]
 pub (crate) const ADXL345_I2C_ADAPTER: i32 = 1;
 
+// Default I2C address, it can be overridden with the `i2c_addr` module parameter
 #[allow(dead_code)]
 pub (crate) const ADXL345_I2C_ADDR: u16 = 0x1D;
 
+// Address with the ALT ADDRESS pin low, ADXL345_I2C_ADDR is the one with it high
+#[allow(dead_code)]
+pub (crate) const ADXL345_I2C_ADDR_ALT: u16 = 0x53;
+
+// The two addresses the device answers at
+#[allow(dead_code)]
+pub (crate) const ADXL345_I2C_ADDRS: [u16; 2] = [ADXL345_I2C_ADDR, ADXL345_I2C_ADDR_ALT];
+
+// Highest 7-bit I2C address
+#[allow(dead_code)]
+pub (crate) const ADXL345_I2C_ADDR_MAX: u32 = 0x7F;
+
+// Default threshold of the read filter, in shifted LSBs, and of the `filter_threshold` module
+// parameter: module! only takes a literal default, keep the two equal
+#[allow(dead_code)]
+pub (crate) const ADXL345_FILTER_DEFAULT: u32 = 50;
+
 // Fixed device ID code
 #[allow(dead_code)]
 pub (crate) const ADXL345_DEVID: u8 = 0xE5;
@@ -105,4 +124,35 @@ pub (crate) const ADXL345_REG_DATAZ1: u8 = 0x37;
 #[allow(dead_code)]
 pub (crate) const ADXL345_REG_FIFO_CTL: u8 = 0x38;
 #[allow(dead_code)]
-pub (crate) const ADXL345_REG_FIFO_STATUS: u8 = 0x39;
\ No newline at end of file
+pub (crate) const ADXL345_REG_FIFO_STATUS: u8 = 0x39;
+
+// FIFO_CTL set at probe: stream mode (the FIFO keeps the newest 32 samples), watermark at half
+// the FIFO, trigger on INT1
+pub (crate) const ADXL345_FIFO_CTL_DEFAULT: u8 = (2 << 6) | 16;
+
+// Stream markers
+// A record whose x field holds ADXL345_MARKER_TAG is not an acceleration sample: raw data is
+// 13 bits wide (shifted by 2), so i16::MIN can never be produced by the device.
+#[allow(dead_code)]
+pub (crate) const ADXL345_MARKER_TAG: i16 = i16::MIN;
+// Marker kinds, stored in the y field of a marker record
+#[allow(dead_code)]
+pub (crate) const ADXL345_MARKER_SYNC: i16 = 1;
+#[allow(dead_code)]
+pub (crate) const ADXL345_MARKER_HEADER: i16 = 2;
+#[allow(dead_code)]
+pub (crate) const ADXL345_MARKER_CLIP: i16 = 3;
+#[allow(dead_code)]
+pub (crate) const ADXL345_MARKER_RANGE: i16 = 4;
+#[allow(dead_code)]
+pub (crate) const ADXL345_MARKER_CRC: i16 = 5;
+#[allow(dead_code)]
+pub (crate) const ADXL345_MARKER_ERROR: i16 = 6;
+#[allow(dead_code)]
+pub (crate) const ADXL345_MARKER_TAP: i16 = 7;
+#[allow(dead_code)]
+pub (crate) const ADXL345_MARKER_BURST: i16 = 8;
+#[allow(dead_code)]
+pub (crate) const ADXL345_MARKER_EVENT: i16 = 9;
+#[allow(dead_code)]
+pub (crate) const ADXL345_MARKER_LIMIT: i16 = 10;
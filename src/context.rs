@@ -0,0 +1,146 @@
+/*
+ * Copyright 2024 Luca Saverio Esposito, Università di Roma, Tor Vergata
+ * email: <lucasaverioesposito@gmail.com>
+ *
+ * This file is part of an "Rust Linux driver for the ADXL345 device".
+ *
+ * This driver is free software: you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * This driver is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with Foobar.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+
+// context.rs
+
+//! Per-device context of the open files.
+//!
+//! Probe gathers what the file operations need of the device, the device state and its drain,
+//! into an `Adxl345Context` and publishes it. open() takes a reference to the context into the
+//! private data of the file (see poll.rs), and every later operation on the file works on that
+//! reference: a file never sees another device, even if the device is removed and probed again
+//! while it is open. Once remove shut the drain down, the context of the old device fails the
+//! operations that need the device with `ENODEV`. The operations that drain the device on demand,
+//! fsync(), the read-ahead of a large read and `ADXL345_IOC_FLUSH`, go through the context too:
+//! it checks the device under the configuration lock, which remove takes to shut the drain down.
+//!
+//! Each device publishes its context in the slot of its id (see instance.rs). The published
+//! contexts are only needed where there is no file: open() itself, which looks the context up
+//! by the minor of the node, the sysfs attributes, by the device they belong to, and the debugfs
+//! entries. They are behind a sleeping lock, held only to take or replace a reference. The
+//! interrupt handlers of a device hold a reference to its drain instead (see data_irq.rs).
+
+use kernel::prelude::*;
+use kernel::bindings;
+use kernel::device::RawDevice;
+use kernel::sync::{smutex, Arc, Mutex};
+use kernel::error::code::ENODEV;
+use crate::structures::Adxl345;
+use crate::drain::Adxl345Drain;
+use crate::instance::ADXL345_DEVICES_MAX;
+use crate::ioctl::ADXL345_CONFIG_LOCK;
+
+/// What the file operations need of a probed device.
+pub (crate) struct Adxl345Context {
+    device: Arc<Mutex<Adxl345>>,
+    pub (crate) drain: Arc<Adxl345Drain>,
+}
+
+impl Adxl345Context {
+    pub (crate) fn try_new(device: Arc<Mutex<Adxl345>>, drain: Arc<Adxl345Drain>) -> Result<Arc<Self>> {
+        Arc::try_new(Self { device, drain })
+    }
+
+    /// Returns the device state.
+    ///
+    /// Remove shuts the drain down with the configuration lock held: called with the lock held,
+    /// the device stays usable until it is released.
+    ///
+    /// # Returns
+    /// `Err(ENODEV)` once the device was removed.
+    pub (crate) fn device(&self) -> Result<&Arc<Mutex<Adxl345>>> {
+        if self.drain.is_removed() {
+            return Err(ENODEV);
+        }
+        Ok(&self.device)
+    }
+
+    /// Drains the device now, for fsync() (see `Adxl345Drain::flush`).
+    ///
+    /// # Returns
+    /// `Err(ENODEV)` once the device was removed, checked under the configuration lock so
+    /// remove can't unregister the client during the transfers.
+    pub (crate) fn flush(&self) -> Result<usize> {
+        // SAFETY: The lock is initialized at module init.
+        let _config = unsafe { ADXL345_CONFIG_LOCK.lock() };
+        self.device()?;
+        self.drain.flush()
+    }
+
+    /// Drains up to `wanted` samples now, for a large read (see `Adxl345Drain::read_ahead`).
+    ///
+    /// # Returns
+    /// `Err(ENODEV)` once the device was removed, checked as in `flush()`.
+    pub (crate) fn read_ahead(&self, wanted: usize) -> Result<usize> {
+        // SAFETY: The lock is initialized at module init.
+        let _config = unsafe { ADXL345_CONFIG_LOCK.lock() };
+        self.device()?;
+        self.drain.read_ahead(wanted)
+    }
+
+    /// Discards everything buffered, in the kernel buffer and in the device, for
+    /// `ADXL345_IOC_FLUSH` and the `flush` control command (see `Adxl345Drain::discard`).
+    ///
+    /// # Returns
+    /// `Err(ENODEV)` once the device was removed, checked as in `flush()`.
+    pub (crate) fn discard(&self) -> Result<usize> {
+        // SAFETY: The lock is initialized at module init.
+        let _config = unsafe { ADXL345_CONFIG_LOCK.lock() };
+        self.device()?;
+        self.drain.discard()
+    }
+}
+
+const ADXL345_NO_CONTEXT: Option<Arc<Adxl345Context>> = None;
+
+/// Contexts of the probed devices, indexed by id, set by probe and cleared by remove.
+static ADXL345_CONTEXTS: smutex::Mutex<[Option<Arc<Adxl345Context>>; ADXL345_DEVICES_MAX]> =
+    smutex::Mutex::new([ADXL345_NO_CONTEXT; ADXL345_DEVICES_MAX]);
+
+/// Publishes the context of the probed device `id`, `None` once it is removed.
+pub (crate) fn adxl345_context_publish(id: usize, context: Option<Arc<Adxl345Context>>) {
+    // The old context is dropped once the lock is released
+    let old = core::mem::replace(&mut ADXL345_CONTEXTS.lock()[id], context);
+    drop(old);
+}
+
+/// Returns the context of the probed device `id`.
+///
+/// # Returns
+/// `Err(ENODEV)` if no device is probed in this slot.
+pub (crate) fn adxl345_context(id: usize) -> Result<Arc<Adxl345Context>> {
+    ADXL345_CONTEXTS.lock().get(id).and_then(|context| context.clone()).ok_or(ENODEV)
+}
+
+/// Returns the context of the probed device sitting on the bus device `dev`, for the sysfs
+/// attributes of that device.
+///
+/// # Returns
+/// `Err(ENODEV)` if no probed device sits on it.
+pub (crate) fn adxl345_context_of(dev: *mut bindings::device) -> Result<Arc<Adxl345Context>> {
+    let contexts = ADXL345_CONTEXTS.lock();
+    contexts
+        .iter()
+        .flatten()
+        .find(|context| context.device.lock().bus().device().raw_device() == dev)
+        .cloned()
+        .ok_or(ENODEV)
+}
@@ -0,0 +1,156 @@
+/*
+ * Copyright 2024 Luca Saverio Esposito, Università di Roma, Tor Vergata
+ * email: <lucasaverioesposito@gmail.com>
+ *
+ * This file is part of an "Rust Linux driver for the ADXL345 device".
+ *
+ * This driver is free software: you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * This driver is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with Foobar.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+
+// control.rs
+
+//! Text control channel on `write()`, for environments that can't issue ioctls (a busybox shell,
+//! an init script).
+//!
+//! It is off by default: open() rejects write access with `EPERM` unless the driver is loaded
+//! with `write_control=1`. The permissions of the device node then decide who may configure the
+//! device, a write-only node group keeps readers and controllers apart. A write holds one command
+//! per line:
+//!
+//! ```text
+//! echo "rate 400000" > /dev/adxl345
+//! printf 'stop\nrange 8\nthresh_act 250\nstart\n' > /dev/adxl345
+//! ```
+//!
+//! - `<param> <value>` sets a parameter by its debugfs name, in human units as with
+//!   `ADXL345_IOC_SET_PARAM_SCALED` (rate in mHz, range in g, thresholds in mg, durations in µs);
+//!   values are decimal or `0x` hexadecimal;
+//! - `start` and `stop` control the session, as `ADXL345_IOC_START` and `ADXL345_IOC_STOP`;
+//! - `flush` discards the buffered samples, as `ADXL345_IOC_FLUSH`.
+//!
+//! Empty lines and lines starting with `#` are ignored. Every line is validated before any is
+//! run, so a typo rejects the whole write with `EINVAL`; a command that fails once running (a
+//! parameter that can't change during a session, a removed device) stops the write with its error
+//! and leaves the commands before it applied. A write-only open doesn't start a session and its
+//! release doesn't stop one while other files are open: a controller doesn't disturb the
+//! readers. Released last, it puts the device in standby like any file (see power.rs).
+
+use kernel::prelude::*;
+use kernel::error::code::EINVAL;
+use kernel::io_buffer::IoBufferReader;
+use core::sync::atomic::{AtomicBool, Ordering};
+use crate::config::{Adxl345Param, adxl345_from_scaled, adxl345_validate};
+use crate::context::Adxl345Context;
+use crate::ioctl::{adxl345_session_control, ADXL345_CONFIG_LOCK};
+use crate::snapshot::adxl345_snapshot_refresh;
+use crate::capabilities::{adxl345_caps_set, ADXL345_CAP_WRITE_CONTROL};
+
+/// Largest write accepted, in bytes.
+pub (crate) const ADXL345_CONTROL_MAX: usize = 256;
+
+/// Whether open() grants write access, set at module init from the `write_control` parameter.
+pub (crate) static ADXL345_WRITE_CONTROL: AtomicBool = AtomicBool::new(false);
+
+/// Grants write access to the device, called at module init with `write_control=1`.
+pub (crate) fn adxl345_control_enable() {
+    ADXL345_WRITE_CONTROL.store(true, Ordering::Relaxed);
+    adxl345_caps_set(ADXL345_CAP_WRITE_CONTROL);
+}
+
+/// A parsed control command.
+#[derive(Clone, Copy)]
+enum Adxl345Command {
+    Start,
+    Stop,
+    Flush,
+    Set(Adxl345Param, u32),     // Value in human units
+}
+
+impl Adxl345Command {
+    /// Parses and validates a command line.
+    fn parse(line: &str) -> Result<Self> {
+        let mut words = line.split_whitespace();
+        let command = match (words.next(), words.next()) {
+            (Some("start"), None) => Self::Start,
+            (Some("stop"), None) => Self::Stop,
+            (Some("flush"), None) => Self::Flush,
+            (Some(name), Some(value)) => {
+                let param = Adxl345Param::from_name(name).ok_or(EINVAL)?;
+                let value = match value.strip_prefix("0x") {
+                    Some(hex) => u32::from_str_radix(hex, 16),
+                    None => value.parse(),
+                }
+                .map_err(|_| EINVAL)?;
+                adxl345_validate(param, adxl345_from_scaled(param, value)?)?;
+                Self::Set(param, value)
+            }
+            _ => return Err(EINVAL),
+        };
+        if words.next().is_some() {
+            return Err(EINVAL);
+        }
+        Ok(command)
+    }
+
+    /// Runs the command on the device of `context`.
+    fn run(self, context: &Adxl345Context) -> Result {
+        match self {
+            Self::Start => adxl345_session_control(context, true),
+            Self::Stop => adxl345_session_control(context, false),
+            Self::Flush => context.discard().map(|_| ()),
+            Self::Set(param, value) => {
+                // SAFETY: The lock is initialized at module init.
+                let _config = unsafe { ADXL345_CONFIG_LOCK.lock() };
+                let device = context.device()?;
+                device.lock().set_param_scaled(param, value)?;
+                if param == Adxl345Param::Rate || param == Adxl345Param::Range {
+                    adxl345_snapshot_refresh(device)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Runs the commands of a write on the device of `context`, the one the file was opened on.
+///
+/// # Returns
+/// The size of the write once every command ran, `EINVAL` if it is larger than
+/// `ADXL345_CONTROL_MAX` or holds an invalid line.
+pub (crate) fn adxl345_control_write(
+    context: &Adxl345Context,
+    reader: &mut impl IoBufferReader,
+) -> Result<usize> {
+    let len = reader.len();
+    if len > ADXL345_CONTROL_MAX {
+        return Err(EINVAL);
+    }
+    let mut buf = [0u8; ADXL345_CONTROL_MAX];
+    reader.read_slice(&mut buf[..len])?;
+    let text = core::str::from_utf8(&buf[..len]).map_err(|_| EINVAL)?;
+
+    let lines = || {
+        text.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+    };
+    for line in lines() {
+        Adxl345Command::parse(line)?;
+    }
+    for line in lines() {
+        Adxl345Command::parse(line)?.run(context)?;
+    }
+    Ok(len)
+}
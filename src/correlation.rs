@@ -0,0 +1,181 @@
+/*
+ * Copyright 2024 Luca Saverio Esposito, Università di Roma, Tor Vergata
+ * email: <lucasaverioesposito@gmail.com>
+ *
+ * This file is part of an "Rust Linux driver for the ADXL345 device".
+ *
+ * This driver is free software: you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * This driver is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with Foobar.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+
+// correlation.rs
+
+//! Correlation of the events with the sample stream.
+//!
+//! Events flagged while draining the device get a correlation ID, queued in the stream as a
+//! marker of kind `ADXL345_MARKER_EVENT` whose z field is the ID, right where the event was seen:
+//! - `ADXL345_EVENT_TAP`: a single or double tap (see tap.rs), before the samples of the drain
+//!   pass that read it, with the tap marker;
+//! - `ADXL345_EVENT_THRESHOLD`: the sample that raised the alarm line (see alarm.rs), right
+//!   before it;
+//! - `ADXL345_EVENT_OVERRUN`: samples dropped because the kernel buffer was full, before the
+//!   first sample queued after the gap;
+//! - `ADXL345_EVENT_MOTION`: an activity, inactivity or free-fall event (see motion.rs), before
+//!   the samples of the drain pass that read it.
+//!
+//! Events flagged before their marker could be queued share it, and its ID: the marker stands for
+//! one position in the stream. IDs count from 1 and wrap at 65535, 0 is never used.
+//!
+//! The metadata of the last `ADXL345_EVENT_LOG_LEN` markers is kept: `ADXL345_IOC_GET_EVENT`
+//! takes an ID read from the stream and returns the events it stands for with the time they
+//! were flagged, in the clock of the samples, or fails with `ENOENT` once it is forgotten. The
+//! `overrun` uevent carries the ID of its marker in `ADXL345_EVENT_ID`. The log is written by the
+//! drain and read by the ioctl with the device lock held.
+//!
+//! Each device has its own IDs and log, selected by its id (see instance.rs): an ID only stands
+//! for a marker in the stream of the device it was read from.
+
+use kernel::prelude::*;
+use kernel::error::code::ENOENT;
+use kernel::io_buffer::{ReadableFromBytes, WritableToBytes};
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use crate::structures::Adxl345;
+use crate::instance::ADXL345_DEVICES_MAX;
+
+/// Kinds of events, bits of `Adxl345EventInfo::events`.
+pub (crate) const ADXL345_EVENT_TAP: u32 = 1 << 0;
+pub (crate) const ADXL345_EVENT_THRESHOLD: u32 = 1 << 1;
+pub (crate) const ADXL345_EVENT_OVERRUN: u32 = 1 << 2;
+pub (crate) const ADXL345_EVENT_MOTION: u32 = 1 << 3;
+
+/// Number of markers whose metadata is kept.
+const ADXL345_EVENT_LOG_LEN: usize = 16;
+
+/// Argument of `ADXL345_IOC_GET_EVENT`: the ID is given, the rest is returned.
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub (crate) struct Adxl345EventInfo {
+    pub (crate) id: u32,            // Correlation ID, the z field of the marker
+    pub (crate) events: u32,        // ADXL345_EVENT_* bits of the events at the marker
+    pub (crate) timestamp_ns: u64,  // Time the first of them was flagged
+}
+
+// SAFETY: `Adxl345EventInfo` is `repr(C)`, made only of integers and has no padding, so any byte
+// pattern is a valid value.
+unsafe impl ReadableFromBytes for Adxl345EventInfo {}
+unsafe impl WritableToBytes for Adxl345EventInfo {}
+
+/// An entry of the log.
+struct Adxl345EventEntry {
+    id: AtomicU32,
+    events: AtomicU32,
+    timestamp_ns: AtomicU64,
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const ADXL345_EVENT_ENTRY_EMPTY: Adxl345EventEntry = Adxl345EventEntry {
+    id: AtomicU32::new(0),
+    events: AtomicU32::new(0),
+    timestamp_ns: AtomicU64::new(0),
+};
+
+/// Correlation IDs and the log of their metadata.
+pub (crate) struct Adxl345Correlation {
+    last_id: AtomicU32,             // Last ID given
+    pending: AtomicU32,             // ID of the marker not queued yet, 0 if none
+    last_overrun: AtomicU32,        // ID of the marker of the last overrun
+    log: [Adxl345EventEntry; ADXL345_EVENT_LOG_LEN],
+}
+
+/// Correlation states of the devices, indexed by id.
+static ADXL345_CORRELATIONS: [Adxl345Correlation; ADXL345_DEVICES_MAX] =
+    [Adxl345Correlation::EMPTY; ADXL345_DEVICES_MAX];
+
+/// Returns the correlation state of device `id`.
+pub (crate) fn adxl345_correlation(id: usize) -> &'static Adxl345Correlation {
+    &ADXL345_CORRELATIONS[id]
+}
+
+impl Adxl345Correlation {
+    /// A state with an empty log, to initialize the array of states.
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY: Self = Self {
+        last_id: AtomicU32::new(0),
+        pending: AtomicU32::new(0),
+        last_overrun: AtomicU32::new(0),
+        log: [ADXL345_EVENT_ENTRY_EMPTY; ADXL345_EVENT_LOG_LEN],
+    };
+
+    /// Returns the log entry of `id`.
+    fn entry(&self, id: u32) -> &Adxl345EventEntry {
+        &self.log[id as usize % ADXL345_EVENT_LOG_LEN]
+    }
+
+    /// Records an event of kind `event` seen by the drain, with the device lock held.
+    ///
+    /// It is added to the marker not queued yet, or to a new one.
+    pub (crate) fn flag(&self, adxl: &Adxl345, event: u32) {
+        let mut id = self.pending.load(Ordering::Relaxed);
+        if id == 0 {
+            id = self.last_id.load(Ordering::Relaxed) % u16::MAX as u32 + 1;
+            self.last_id.store(id, Ordering::Relaxed);
+            self.pending.store(id, Ordering::Relaxed);
+            let entry = self.entry(id);
+            entry.id.store(id, Ordering::Relaxed);
+            entry.events.store(0, Ordering::Relaxed);
+            entry.timestamp_ns.store(adxl.clock().now_ns(), Ordering::Relaxed);
+        }
+        self.entry(id).events.fetch_or(event, Ordering::Relaxed);
+        if event == ADXL345_EVENT_OVERRUN {
+            self.last_overrun.store(id, Ordering::Relaxed);
+        }
+    }
+
+    /// Returns the ID of the marker to queue before the next sample, 0 if none.
+    pub (crate) fn pending(&self) -> u32 {
+        self.pending.load(Ordering::Relaxed)
+    }
+
+    /// Notes that the marker of `id` was queued, with the device lock held.
+    pub (crate) fn queued(&self, id: u32) {
+        let _ = self.pending.compare_exchange(id, 0, Ordering::Relaxed, Ordering::Relaxed);
+    }
+
+    /// Returns the ID of the marker of the last overrun, 0 if none.
+    pub (crate) fn last_overrun(&self) -> u32 {
+        self.last_overrun.load(Ordering::Relaxed)
+    }
+
+    /// Forgets the marker not queued yet, when the stream starts again from an empty buffer.
+    pub (crate) fn cancel(&self) {
+        self.pending.store(0, Ordering::Relaxed);
+    }
+
+    /// Returns the metadata of the marker of `id`, as `ADXL345_IOC_GET_EVENT`, with the device
+    /// lock held.
+    ///
+    /// # Returns
+    /// `Err(ENOENT)` if no marker has this ID in the log.
+    pub (crate) fn get(&self, _adxl: &Adxl345, id: u32) -> Result<Adxl345EventInfo> {
+        let entry = self.entry(id);
+        if id == 0 || entry.id.load(Ordering::Relaxed) != id {
+            return Err(ENOENT);
+        }
+        Ok(Adxl345EventInfo {
+            id,
+            events: entry.events.load(Ordering::Relaxed),
+            timestamp_ns: entry.timestamp_ns.load(Ordering::Relaxed),
+        })
+    }
+}
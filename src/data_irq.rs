@@ -0,0 +1,112 @@
+/*
+ * Copyright 2024 Luca Saverio Esposito, Università di Roma, Tor Vergata
+ * email: <lucasaverioesposito@gmail.com>
+ *
+ * This file is part of an "Rust Linux driver for the ADXL345 device".
+ *
+ * This driver is free software: you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * This driver is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with Foobar.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+
+// data_irq.rs
+
+//! Interrupt-driven drain.
+//!
+//! Without an interrupt the drain runs every 10 ms (5 ms at 3200 Hz, see drain.rs), whether the
+//! FIFO holds one sample or is about to overflow. When the driver is loaded with `data_gpio=<n>`, the GPIO line
+//! wired to the INT1 pin of the sensor is requested and the WATERMARK interrupt is enabled: once
+//! the FIFO holds the watermark (`watermark` parameter, 16 by default), the handler queues the
+//! drain right away instead of at the end of its period. A higher watermark means fewer, larger
+//! drains; a lower one, less latency.
+//!
+//! The handler only queues the work item, the bus is read from process context as before. The
+//! periodic drain stays as a fallback: it drains the FIFO in bypass mode, where the watermark
+//! never fires, and empties it if an edge was missed while the line stayed high. The watermark
+//! must stay routed to INT1 (the `int_map` default).
+//!
+//! Each device has its own data line: `data_gpio` takes a line per device, in the order of the
+//! ids (see instance.rs), and a device without one drains on the timer only. The interrupts of
+//! each device are counted in `data_irqs`, in its debugfs directory.
+
+use kernel::prelude::*;
+use kernel::irq;
+use kernel::c_str;
+use kernel::sync::{Arc, Mutex};
+use kernel::gpio_irq::{ClosureHandler, GpioIrq, request_irq};
+use core::sync::atomic::{AtomicU64, Ordering};
+use crate::constant::ADXL345_REG_INT_ENABLE;
+use crate::drain::Adxl345Drain;
+use crate::structures::Adxl345;
+use crate::instance::ADXL345_DEVICES_MAX;
+
+/// WATERMARK bit of INT_ENABLE.
+const ADXL345_INT_WATERMARK: u8 = 1 << 1;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const ADXL345_DATA_IRQS_NONE: AtomicU64 = AtomicU64::new(0);
+
+/// Data interrupts received by each device, indexed by id, shown in debugfs as `data_irqs`.
+pub (crate) static ADXL345_DATA_IRQS: [AtomicU64; ADXL345_DEVICES_MAX] = [ADXL345_DATA_IRQS_NONE; ADXL345_DEVICES_MAX];
+
+/// Handler of the interrupts of a device, holding what it needs of the device.
+pub (crate) type Adxl345IrqHandler = Box<dyn Fn() -> irq::Return + Send + Sync>;
+
+/// The data line and its interrupt, released when dropped.
+pub (crate) type Adxl345DataIrq = GpioIrq<irq::Registration<ClosureHandler<Adxl345IrqHandler>>>;
+
+/// Interrupt handler of the data line of the device of `drain`: queues the drain now, the bus
+/// can't be used here.
+fn adxl345_data_watermark(drain: &Arc<Adxl345Drain>) -> irq::Return {
+    ADXL345_DATA_IRQS[drain.id()].fetch_add(1, Ordering::Relaxed);
+    Adxl345Drain::kick(drain);
+    irq::Return::Handled
+}
+
+/// Requests the interrupt of the data line and enables the WATERMARK interrupt of the device.
+///
+/// The returned value frees the interrupt and the line when dropped, so it must be dropped
+/// outside of any spinlock. Its handler holds a reference to the drain, remove drops it.
+///
+/// # Parameters
+/// - `gpio`: The legacy GPIO number of the line wired to INT1.
+/// - `device`: The device, not locked by the caller.
+/// - `drain`: The drain of the device, queued by the interrupt.
+///
+/// # Returns
+/// - `Ok(Adxl345DataIrq)` if the line and its interrupt are acquired.
+/// - `Err(Error)` if the GPIO is in use, has no interrupt, the request fails or INT_ENABLE can't
+///   be written.
+pub (crate) fn adxl345_data_irq_attach(
+    gpio: u32,
+    device: &Arc<Mutex<Adxl345>>,
+    drain: &Arc<Adxl345Drain>,
+) -> Result<Adxl345DataIrq> {
+    let drain = drain.clone();
+    let handler: Adxl345IrqHandler = Box::try_new(move || adxl345_data_watermark(&drain))?;
+    let registration = GpioIrq::request(gpio, c_str!("adxl345_data"), |irq_number| {
+        request_irq(irq_number, irq::flags::TRIGGER_RISING, fmt!("adxl345_data"), handler)
+    })
+    .map_err(|e| {
+        pr_err!("GPIO {} can't be used as data interrupt\n", gpio);
+        e
+    })?;
+
+    // On failure the registration is dropped here, once the device lock is released
+    let enabled = device.lock().update_register(ADXL345_REG_INT_ENABLE, ADXL345_INT_WATERMARK, ADXL345_INT_WATERMARK);
+    if let Err(e) = enabled {
+        pr_err!("failed to enable the WATERMARK interrupt\n");
+        return Err(e);
+    }
+    Ok(registration)
+}
@@ -0,0 +1,492 @@
+/*
+ * Copyright 2024 Luca Saverio Esposito, Università di Roma, Tor Vergata
+ * email: <lucasaverioesposito@gmail.com>
+ *
+ * This file is part of an "Rust Linux driver for the ADXL345 device".
+ *
+ * This driver is free software: you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * This driver is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with Foobar.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+
+// debugfs.rs
+
+//! Debugfs entries of the driver, created under `/sys/kernel/debug/adxl345/`.
+//!
+//! The counters and knobs of the features kept per device (alarm, taps, bursts, motion, power,
+//! calibration, gravity watch, auto range and shadow) are in a directory per device id,
+//! `device0/` to `device3/`; the others are shared by the devices.
+
+use kernel::prelude::*;
+use kernel::c_str;
+use kernel::debugfs::{Dir, simple_read};
+use kernel::file::{File, Operations};
+use kernel::io_buffer::{IoBufferReader, IoBufferWriter};
+use kernel::error::code::EINVAL;
+use kernel::ForeignOwnable;
+use crate::config::adxl345_last_config_error;
+use crate::dry_run::ADXL345_DRY_RUN;
+use crate::bus_trace::ADXL345_BUS_TRACE;
+use crate::bus_usage::ADXL345_BUS_USAGE;
+use crate::concurrency::ADXL345_CONCURRENCY;
+use crate::context::adxl345_context;
+use crate::instance::{ADXL345_DEVICE_PRIMARY, ADXL345_DEVICES_MAX};
+use crate::fault::ADXL345_FAULT;
+use crate::stats::ADXL345_STATS;
+use crate::data_irq::ADXL345_DATA_IRQS;
+use crate::probe_health::ADXL345_PROBE_HEALTH;
+use crate::gravity_watch::adxl345_gravity_watch;
+use crate::shadow::adxl345_shadow;
+use crate::alarm::adxl345_alarm;
+use crate::tap::adxl345_tap;
+use crate::burst::adxl345_burst;
+use crate::motion::adxl345_motion;
+use crate::power::adxl345_power;
+use crate::calibration::adxl345_calibration;
+use crate::transport_guard::ADXL345_TRANSPORT_GUARD;
+#[cfg(CONFIG_THERMAL)]
+use crate::thermal_guard::ADXL345_THERMAL_KNOBS;
+use crate::auto_range::adxl345_auto_range;
+use crate::noise::{adxl345_noise_run, ADXL345_NOISE_FLOOR, ADXL345_NOISE_SECONDS_MAX};
+use crate::preset::adxl345_presets_text;
+use crate::sysfs::adxl345_sysfs_abi_text;
+
+/// Read-only `config_error` file, describing the last rejected configuration value.
+struct Adxl345ConfigErrorFile;
+
+impl Operations for Adxl345ConfigErrorFile {
+    type Data = ();
+    type OpenData = ();
+
+    const HAS_READ: bool = true;
+    // Required constant to indicate that the vtable should be used
+    const USE_VTABLE_ATTR: () = ();
+
+    fn open(_context: &Self::OpenData, _file: &File) -> Result<Self::Data> {
+        Ok(())
+    }
+
+    fn read(
+        _data: <Self::Data as ForeignOwnable>::Borrowed<'_>,
+        _file: &File,
+        writer: &mut impl IoBufferWriter,
+        offset: u64,
+    ) -> Result<usize> {
+        let text = adxl345_last_config_error()?;
+        simple_read(writer, offset, text.as_bytes())
+    }
+}
+
+/// Read-only `transport_guard` file, describing the last write refused by the transport guard.
+struct Adxl345TransportGuardFile;
+
+impl Operations for Adxl345TransportGuardFile {
+    type Data = ();
+    type OpenData = ();
+
+    const HAS_READ: bool = true;
+    // Required constant to indicate that the vtable should be used
+    const USE_VTABLE_ATTR: () = ();
+
+    fn open(_context: &Self::OpenData, _file: &File) -> Result<Self::Data> {
+        Ok(())
+    }
+
+    fn read(
+        _data: <Self::Data as ForeignOwnable>::Borrowed<'_>,
+        _file: &File,
+        writer: &mut impl IoBufferWriter,
+        offset: u64,
+    ) -> Result<usize> {
+        let text = ADXL345_TRANSPORT_GUARD.text()?;
+        simple_read(writer, offset, text.as_bytes())
+    }
+}
+
+/// Read-only `dry_run_trace` file, listing the last register writes in dry-run mode.
+struct Adxl345DryRunTraceFile;
+
+impl Operations for Adxl345DryRunTraceFile {
+    type Data = ();
+    type OpenData = ();
+
+    const HAS_READ: bool = true;
+    // Required constant to indicate that the vtable should be used
+    const USE_VTABLE_ATTR: () = ();
+
+    fn open(_context: &Self::OpenData, _file: &File) -> Result<Self::Data> {
+        Ok(())
+    }
+
+    fn read(
+        _data: <Self::Data as ForeignOwnable>::Borrowed<'_>,
+        _file: &File,
+        writer: &mut impl IoBufferWriter,
+        offset: u64,
+    ) -> Result<usize> {
+        let text = ADXL345_DRY_RUN.trace()?;
+        simple_read(writer, offset, &text)
+    }
+}
+
+/// Read-only `bus_trace` file, listing the last register transactions.
+struct Adxl345BusTraceFile;
+
+impl Operations for Adxl345BusTraceFile {
+    type Data = ();
+    type OpenData = ();
+
+    const HAS_READ: bool = true;
+    // Required constant to indicate that the vtable should be used
+    const USE_VTABLE_ATTR: () = ();
+
+    fn open(_context: &Self::OpenData, _file: &File) -> Result<Self::Data> {
+        Ok(())
+    }
+
+    fn read(
+        _data: <Self::Data as ForeignOwnable>::Borrowed<'_>,
+        _file: &File,
+        writer: &mut impl IoBufferWriter,
+        offset: u64,
+    ) -> Result<usize> {
+        let text = ADXL345_BUS_TRACE.text()?;
+        simple_read(writer, offset, &text)
+    }
+}
+
+/// `bus_usage` file: reading it reports the data and bus bytes of the register transactions,
+/// writing `0` starts a new measurement.
+struct Adxl345BusUsageFile;
+
+impl Operations for Adxl345BusUsageFile {
+    type Data = ();
+    type OpenData = ();
+
+    const HAS_READ: bool = true;
+    const HAS_WRITE: bool = true;
+    // Required constant to indicate that the vtable should be used
+    const USE_VTABLE_ATTR: () = ();
+
+    fn open(_context: &Self::OpenData, _file: &File) -> Result<Self::Data> {
+        Ok(())
+    }
+
+    fn read(
+        _data: <Self::Data as ForeignOwnable>::Borrowed<'_>,
+        _file: &File,
+        writer: &mut impl IoBufferWriter,
+        offset: u64,
+    ) -> Result<usize> {
+        let text = ADXL345_BUS_USAGE.text()?;
+        simple_read(writer, offset, &text)
+    }
+
+    fn write(
+        _data: <Self::Data as ForeignOwnable>::Borrowed<'_>,
+        _file: &File,
+        reader: &mut impl IoBufferReader,
+        _offset: u64,
+    ) -> Result<usize> {
+        let len = reader.len();
+        let text = reader.read_all()?;
+        if core::str::from_utf8(&text).map(str::trim) != Ok("0") {
+            return Err(EINVAL);
+        }
+        ADXL345_BUS_USAGE.reset();
+        Ok(len)
+    }
+}
+
+/// `concurrency` file: lock hold times, buffer occupancy and wakeups, cleared by writing 0.
+struct Adxl345ConcurrencyFile;
+
+impl Operations for Adxl345ConcurrencyFile {
+    type Data = ();
+    type OpenData = ();
+
+    const HAS_READ: bool = true;
+    const HAS_WRITE: bool = true;
+    // Required constant to indicate that the vtable should be used
+    const USE_VTABLE_ATTR: () = ();
+
+    fn open(_context: &Self::OpenData, _file: &File) -> Result<Self::Data> {
+        Ok(())
+    }
+
+    fn read(
+        _data: <Self::Data as ForeignOwnable>::Borrowed<'_>,
+        _file: &File,
+        writer: &mut impl IoBufferWriter,
+        offset: u64,
+    ) -> Result<usize> {
+        // The buffer is the one of the primary device, empty without it
+        let (buffered, capacity) = match adxl345_context(ADXL345_DEVICE_PRIMARY) {
+            Ok(context) => {
+                let info = context.drain.buffer_info();
+                (info.buffered as usize, info.capacity as usize)
+            }
+            Err(_) => (0, 0),
+        };
+        let text = ADXL345_CONCURRENCY.text(buffered, capacity)?;
+        simple_read(writer, offset, &text)
+    }
+
+    fn write(
+        _data: <Self::Data as ForeignOwnable>::Borrowed<'_>,
+        _file: &File,
+        reader: &mut impl IoBufferReader,
+        _offset: u64,
+    ) -> Result<usize> {
+        let len = reader.len();
+        let text = reader.read_all()?;
+        if core::str::from_utf8(&text).map(str::trim) != Ok("0") {
+            return Err(EINVAL);
+        }
+        ADXL345_CONCURRENCY.reset();
+        Ok(len)
+    }
+}
+
+/// Read-only `probe_health` file, the outcome of the probe-time acquisition.
+struct Adxl345ProbeHealthFile;
+
+impl Operations for Adxl345ProbeHealthFile {
+    type Data = ();
+    type OpenData = ();
+
+    const HAS_READ: bool = true;
+    // Required constant to indicate that the vtable should be used
+    const USE_VTABLE_ATTR: () = ();
+
+    fn open(_context: &Self::OpenData, _file: &File) -> Result<Self::Data> {
+        Ok(())
+    }
+
+    fn read(
+        _data: <Self::Data as ForeignOwnable>::Borrowed<'_>,
+        _file: &File,
+        writer: &mut impl IoBufferWriter,
+        offset: u64,
+    ) -> Result<usize> {
+        let text = ADXL345_PROBE_HEALTH.text()?;
+        simple_read(writer, offset, text.as_bytes())
+    }
+}
+
+/// Read-only `noise_floor` file, the RMS noise measured at each rate.
+struct Adxl345NoiseFloorFile;
+
+impl Operations for Adxl345NoiseFloorFile {
+    type Data = ();
+    type OpenData = ();
+
+    const HAS_READ: bool = true;
+    // Required constant to indicate that the vtable should be used
+    const USE_VTABLE_ATTR: () = ();
+
+    fn open(_context: &Self::OpenData, _file: &File) -> Result<Self::Data> {
+        Ok(())
+    }
+
+    fn read(
+        _data: <Self::Data as ForeignOwnable>::Borrowed<'_>,
+        _file: &File,
+        writer: &mut impl IoBufferWriter,
+        offset: u64,
+    ) -> Result<usize> {
+        let text = ADXL345_NOISE_FLOOR.text()?;
+        simple_read(writer, offset, &text)
+    }
+}
+
+/// Write-only `noise_run` file, runs the noise floor characterization for the number of seconds
+/// per rate written into it. The write returns once the table is updated.
+struct Adxl345NoiseRunFile;
+
+impl Operations for Adxl345NoiseRunFile {
+    type Data = ();
+    type OpenData = ();
+
+    const HAS_WRITE: bool = true;
+    // Required constant to indicate that the vtable should be used
+    const USE_VTABLE_ATTR: () = ();
+
+    fn open(_context: &Self::OpenData, _file: &File) -> Result<Self::Data> {
+        Ok(())
+    }
+
+    fn write(
+        _data: <Self::Data as ForeignOwnable>::Borrowed<'_>,
+        _file: &File,
+        reader: &mut impl IoBufferReader,
+        _offset: u64,
+    ) -> Result<usize> {
+        let len = reader.len();
+        let text = reader.read_all()?;
+        let seconds: u32 = core::str::from_utf8(&text)
+            .ok()
+            .and_then(|text| text.trim().parse().ok())
+            .ok_or(EINVAL)?;
+        if seconds == 0 || seconds > ADXL345_NOISE_SECONDS_MAX {
+            return Err(EINVAL);
+        }
+        adxl345_noise_run(seconds)?;
+        Ok(len)
+    }
+}
+
+/// Read-only `presets` file, the saved configuration presets.
+struct Adxl345PresetsFile;
+
+impl Operations for Adxl345PresetsFile {
+    type Data = ();
+    type OpenData = ();
+
+    const HAS_READ: bool = true;
+    // Required constant to indicate that the vtable should be used
+    const USE_VTABLE_ATTR: () = ();
+
+    fn open(_context: &Self::OpenData, _file: &File) -> Result<Self::Data> {
+        Ok(())
+    }
+
+    fn read(
+        _data: <Self::Data as ForeignOwnable>::Borrowed<'_>,
+        _file: &File,
+        writer: &mut impl IoBufferWriter,
+        offset: u64,
+    ) -> Result<usize> {
+        let text = adxl345_presets_text()?;
+        simple_read(writer, offset, &text)
+    }
+}
+
+/// Read-only `sysfs_abi` file, the ABI documentation of the sysfs attributes of this build.
+struct Adxl345SysfsAbiFile;
+
+impl Operations for Adxl345SysfsAbiFile {
+    type Data = ();
+    type OpenData = ();
+
+    const HAS_READ: bool = true;
+    // Required constant to indicate that the vtable should be used
+    const USE_VTABLE_ATTR: () = ();
+
+    fn open(_context: &Self::OpenData, _file: &File) -> Result<Self::Data> {
+        Ok(())
+    }
+
+    fn read(
+        _data: <Self::Data as ForeignOwnable>::Borrowed<'_>,
+        _file: &File,
+        writer: &mut impl IoBufferWriter,
+        offset: u64,
+    ) -> Result<usize> {
+        let text = adxl345_sysfs_abi_text()?;
+        simple_read(writer, offset, &text)
+    }
+}
+
+/// Names of the directories of the devices, by id.
+const ADXL345_DEBUGFS_DEVICE_NAMES: [&CStr; ADXL345_DEVICES_MAX] =
+    [c_str!("device0"), c_str!("device1"), c_str!("device2"), c_str!("device3")];
+
+/// Creates the directory of device `id` under `dir`, with the counters and knobs of its features.
+fn adxl345_debugfs_device(dir: &Dir, id: usize) -> Result {
+    let device_dir = Dir::new(ADXL345_DEBUGFS_DEVICE_NAMES[id], Some(dir))?;
+
+    let (gravity_watch, alarm, shadow) = (adxl345_gravity_watch(id), adxl345_alarm(id), adxl345_shadow(id));
+    let (motion, power) = (adxl345_motion(id), adxl345_power(id));
+    device_dir.create_u64(c_str!("auto_range_switches"), 0o444, &adxl345_auto_range(id).switches);
+    device_dir.create_u64(c_str!("data_irqs"), 0o444, &ADXL345_DATA_IRQS[id]);
+    device_dir.create_bool(c_str!("gravity_watch"), 0o644, &gravity_watch.enabled);
+    device_dir.create_u32(c_str!("gravity_tolerance_mg"), 0o644, &gravity_watch.tolerance_mg);
+    device_dir.create_u32(c_str!("gravity_hold_ms"), 0o644, &gravity_watch.hold_ms);
+    device_dir.create_u32(c_str!("gravity_mg"), 0o444, &gravity_watch.magnitude_mg);
+    device_dir.create_u64(c_str!("gravity_alarms"), 0o444, &gravity_watch.alarms);
+    device_dir.create_u32(c_str!("alarm_events"), 0o644, &alarm.events);
+    device_dir.create_u32(c_str!("alarm_threshold_mg"), 0o644, &alarm.threshold_mg);
+    device_dir.create_u32(c_str!("alarm_hold_ms"), 0o644, &alarm.hold_ms);
+    device_dir.create_u64(c_str!("alarm_count"), 0o444, &alarm.count);
+    device_dir.create_u64(c_str!("taps"), 0o444, &adxl345_tap(id).taps);
+    device_dir.create_u64(c_str!("bursts"), 0o444, &adxl345_burst(id).bursts);
+    device_dir.create_u64(c_str!("bursts_skipped"), 0o444, &adxl345_burst(id).skipped);
+    device_dir.create_u64(c_str!("activity_events"), 0o444, &motion.activity);
+    device_dir.create_u64(c_str!("inactivity_events"), 0o444, &motion.inactivity);
+    device_dir.create_u64(c_str!("free_fall_events"), 0o444, &motion.free_fall);
+    device_dir.create_u64(c_str!("suspends"), 0o444, &power.suspends);
+    device_dir.create_u64(c_str!("idle_standbys"), 0o444, &power.idle_standbys);
+    device_dir.create_u64(c_str!("calibrations"), 0o444, &adxl345_calibration(id).calibrations);
+    device_dir.create_bool(c_str!("shadow_check"), 0o644, &shadow.enabled);
+    device_dir.create_u32(c_str!("shadow_period_ms"), 0o644, &shadow.period_ms);
+    device_dir.create_u64(c_str!("shadow_checks"), 0o444, &shadow.checks);
+    device_dir.create_u64(c_str!("shadow_recoveries"), 0o444, &shadow.recoveries);
+
+    // Removing the directory of the driver removes this one with it.
+    core::mem::forget(device_dir);
+    Ok(())
+}
+
+/// Creates the debugfs directory of the driver and all of its entries.
+///
+/// The entries are removed when the returned `Dir` is dropped.
+///
+/// # Returns
+/// - `Ok(Dir)` if the directory is created.
+/// - `Err(Error)` if debugfs is not available, the driver works without it.
+pub (crate) fn adxl345_debugfs_create() -> Result<Dir> {
+    let dir = Dir::new(c_str!("adxl345"), None)?;
+
+    dir.create_file::<Adxl345ConfigErrorFile>(c_str!("config_error"), 0o444, &())?;
+    dir.create_file::<Adxl345TransportGuardFile>(c_str!("transport_guard"), 0o444, &())?;
+    dir.create_file::<Adxl345DryRunTraceFile>(c_str!("dry_run_trace"), 0o444, &())?;
+    dir.create_file::<Adxl345BusTraceFile>(c_str!("bus_trace"), 0o444, &())?;
+    dir.create_file::<Adxl345BusUsageFile>(c_str!("bus_usage"), 0o644, &())?;
+    dir.create_file::<Adxl345ConcurrencyFile>(c_str!("concurrency"), 0o644, &())?;
+    dir.create_file::<Adxl345ProbeHealthFile>(c_str!("probe_health"), 0o444, &())?;
+    dir.create_file::<Adxl345NoiseFloorFile>(c_str!("noise_floor"), 0o444, &())?;
+    dir.create_file::<Adxl345NoiseRunFile>(c_str!("noise_run"), 0o200, &())?;
+    dir.create_file::<Adxl345PresetsFile>(c_str!("presets"), 0o444, &())?;
+    dir.create_file::<Adxl345SysfsAbiFile>(c_str!("sysfs_abi"), 0o444, &())?;
+    dir.create_bool(c_str!("bus_trace_dump_on_error"), 0o644, &ADXL345_BUS_TRACE.dump_on_error);
+    dir.create_u32(c_str!("inject_mode"), 0o644, &ADXL345_FAULT.mode);
+    dir.create_u32(c_str!("inject_skip"), 0o644, &ADXL345_FAULT.skip);
+    dir.create_u32(c_str!("inject_times"), 0o644, &ADXL345_FAULT.times);
+    dir.create_u32(c_str!("injected"), 0o444, &ADXL345_FAULT.injected);
+    dir.create_u64(c_str!("samples_drained"), 0o444, &ADXL345_STATS.drained);
+    dir.create_u64(c_str!("samples_dropped"), 0o444, &ADXL345_STATS.dropped);
+    dir.create_u64(c_str!("samples_delivered"), 0o444, &ADXL345_STATS.delivered);
+    #[cfg(not(adxl345_no_filter))]
+    dir.create_u64(c_str!("samples_filtered"), 0o444, &ADXL345_STATS.filtered);
+    dir.create_u64(c_str!("samples_clipped"), 0o444, &ADXL345_STATS.clipped);
+    dir.create_u64(c_str!("markers"), 0o444, &ADXL345_STATS.markers);
+    dir.create_u64(c_str!("bus_errors"), 0o444, &ADXL345_STATS.bus_errors);
+    dir.create_u64(c_str!("push_max_ns"), 0o644, &ADXL345_STATS.push_max_ns);
+    dir.create_u64(c_str!("fifo_full"), 0o444, &ADXL345_STATS.fifo_full);
+    dir.create_u64(c_str!("transport_rejections"), 0o444, &ADXL345_TRANSPORT_GUARD.rejections);
+    #[cfg(CONFIG_THERMAL)]
+    {
+        dir.create_u32(c_str!("thermal_limit_mc"), 0o644, &ADXL345_THERMAL_KNOBS.limit_mc);
+        dir.create_u32(c_str!("thermal_hysteresis_mc"), 0o644, &ADXL345_THERMAL_KNOBS.hysteresis_mc);
+        dir.create_u32(c_str!("thermal_rate_mhz"), 0o644, &ADXL345_THERMAL_KNOBS.rate_mhz);
+        dir.create_bool(c_str!("thermal_suspend"), 0o644, &ADXL345_THERMAL_KNOBS.suspend);
+        dir.create_u32(c_str!("thermal_mc"), 0o444, &ADXL345_THERMAL_KNOBS.temperature_mc);
+        dir.create_u64(c_str!("thermal_trips"), 0o444, &ADXL345_THERMAL_KNOBS.trips);
+    }
+    for id in 0..ADXL345_DEVICES_MAX {
+        adxl345_debugfs_device(&dir, id)?;
+    }
+
+    Ok(dir)
+}
@@ -0,0 +1,776 @@
+/*
+ * Copyright 2024 Luca Saverio Esposito, Università di Roma, Tor Vergata
+ * email: <lucasaverioesposito@gmail.com>
+ *
+ * This file is part of an "Rust Linux driver for the ADXL345 device".
+ *
+ * This driver is free software: you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * This driver is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with Foobar.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+
+
+// drain.rs
+
+//! Deferred draining of the device into a kernel buffer.
+//!
+//! While the device is open, a delayed work item periodically moves the samples ready in the
+//! device into a small kernel buffer and wakes up the readers, so `read()` never polls. The
+//! buffer is a lock-free SPSC queue (see `spsc.rs`): the work item is the producer and never waits
+//! for a reader, readers take turns as consumer. When the buffer is full the newest samples are
+//! dropped, so what is delivered stays contiguous, and counted as an overrun. The readers of the
+//! device sleep on the wait queue of its drain, next to the buffer, and the overruns are counted
+//! there too, so a device doesn't wake up the readers of another one nor count its drops.
+//!
+//! The buffer holds 256 samples by default, 80 ms at the highest rate. `ADXL345_IOC_SET_BUFFER`
+//! and the `buffer_capacity` attribute (see sysfs.rs) change it from 64 to 1024 samples, for a
+//! reader that comes late (a larger buffer) or that wants old samples dropped early (a smaller
+//! one); `ADXL345_IOC_GET_BUFFER` returns it with the samples buffered and the overruns.
+//!
+//! Each drain learns how many samples the device holds from a single FIFO_STATUS read, followed
+//! by a DATA_READY check in bypass mode only, and reads exactly that many rather than checking
+//! DATA_READY before every sample. Probe sets the FIFO in stream mode, so the device holds up to
+//! 33 samples (32 queued and the data registers) between drains.
+//!
+//! fsync() drains the device on demand through `flush()`, and a large `read()` through
+//! `read_ahead()` before it sleeps; neither drops a sample, both are serialized with the work item
+//! by the device lock. Both, and `discard()`, are reached through the context of the file (see
+//! context.rs), which fails them with `ENODEV` once the device is removed.
+//!
+//! The work item holds a reference to the drain state while it is queued
+//! or running, and it is canceled synchronously on release and on remove, so it can't run once
+//! the device is gone. Remove also marks the drain as removed and wakes up the readers, which
+//! keep their own reference to it and fail with `ENODEV` instead of sleeping forever.
+//!
+//! The work item also reports overruns and bus errors, and the recovery from them, with uevents
+//! (see `uevent.rs`).
+//!
+//! A clipped sample is queued together with its clip marker (see `clip.rs`), both or none, so a
+//! reader never gets one without the other. With auto-ranging (see `auto_range.rs`) the drain
+//! also changes the range, and queues a range marker before the first sample drained after it.
+//! A tap reported by the device (see `tap.rs`) is queued the same way, as a tap marker.
+//!
+//! With an alarm line (see `alarm.rs`) each pass checks the samples and the tap and activity
+//! events, and drives the line once the device lock is released. The motion events (see
+//! `motion.rs`) wake up the readers the same way.
+//!
+//! At the highest rates the 33 samples of the device last 10 ms at 3200 Hz, as long as the
+//! drain period. The period is therefore shortened to the time the FIFO takes to fill half, 5 ms
+//! at 3200 Hz, and a drain that moved half a FIFO or more queues the next one right away, as the
+//! device filled up again while it was read. The period is rounded up to the timer tick, a
+//! whole 10 ms with HZ=100: the watermark interrupt (see data_irq.rs) doesn't depend on it. A
+//! drain that finds the FIFO full counts it in `fifo_full`: the device may have overwritten
+//! samples before they were read.
+//!
+//! The work item also watches for a chip that lost its configuration, e.g. after a brown-out,
+//! and reprograms it from the register shadow before it drains (see `shadow.rs`).
+//!
+//! In burst mode (see `burst.rs`) the work item runs the schedule: it starts and ends the bursts
+//! around its passes, and sleeps with the device between them.
+//!
+//! Every device has its own drain, buffer and snapshot (see `instance.rs`), and each drain runs
+//! the alarm, gravity watch, tap, motion, correlation, auto-ranging, burst and shadow checks
+//! above on the state of its own device, selected by its id.
+
+use kernel::prelude::*;
+use kernel::bindings;
+use kernel::error::code::{EINVAL, EIO};
+use kernel::device::Device;
+use kernel::io_buffer::WritableToBytes;
+use kernel::sync::{Arc, Guard, Mutex, UniqueArc, WaitQueue};
+use kernel::time::{ktime_get_ns, msecs_to_jiffies};
+use kernel::workqueue::{self, DelayedWork};
+use kernel::{impl_self_delayed_work_adapter, init_delayed_work_item, mutex_init, waitqueue_init};
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use crate::structures::{Adxl345, Adxl345Sample};
+use crate::spsc::Adxl345Spsc;
+use crate::concurrency::ADXL345_CONCURRENCY;
+use crate::poll::adxl345_data_event;
+use crate::fair_share::Adxl345Readers;
+use crate::fasync::{adxl345_sigio, adxl345_sigio_data};
+use crate::stats::{Adxl345Stats, ADXL345_STATS};
+use crate::uevent::{adxl345_uevent, Adxl345Event};
+use crate::gravity_watch::adxl345_gravity_watch;
+use crate::alarm::adxl345_alarm;
+use crate::shadow::adxl345_shadow;
+use crate::sysfs::adxl345_latest_sample_store;
+use crate::clip::adxl345_clip_axes;
+use crate::snapshot::{adxl345_snapshot, Adxl345SnapshotCell};
+use crate::constant::{ADXL345_MARKER_CLIP, ADXL345_MARKER_RANGE, ADXL345_MARKER_TAP, ADXL345_REG_INT_SOURCE};
+use crate::tap::adxl345_tap;
+use crate::motion::adxl345_motion;
+use crate::burst::{adxl345_burst, Adxl345Burst, Adxl345BurstStep};
+use crate::session::{adxl345_header, ADXL345_HEADER_WORDS};
+use crate::constant::{ADXL345_MARKER_BURST, ADXL345_MARKER_EVENT};
+use crate::correlation::{
+    adxl345_correlation, ADXL345_EVENT_MOTION, ADXL345_EVENT_OVERRUN, ADXL345_EVENT_TAP,
+    ADXL345_EVENT_THRESHOLD,
+};
+use crate::auto_range::adxl345_auto_range;
+use crate::config::Adxl345Param;
+use crate::snapshot::adxl345_snapshot_refresh;
+
+/// Longest interval between two drains, in milliseconds.
+const ADXL345_DRAIN_PERIOD_MS: u32 = 10;
+
+/// Entries of the FIFO, FIFO_STATUS reports at most as many.
+const ADXL345_FIFO_LEN: usize = 32;
+
+/// Samples in half the FIFO: the period lets the device fill that many at most, and a drain
+/// that moved as many runs again at once.
+const ADXL345_FIFO_HALF: usize = ADXL345_FIFO_LEN / 2;
+
+/// Largest capacity of the kernel buffer, in samples: 320 ms of data at the highest rate.
+pub (crate) const ADXL345_BUFFER_LEN: usize = 1024;
+
+/// Default capacity of the kernel buffer, in samples: 80 ms of data at the highest rate.
+const ADXL345_BUFFER_DEFAULT: usize = 256;
+
+/// Smallest capacity of the kernel buffer, in samples: a session header and a full device.
+pub (crate) const ADXL345_BUFFER_MIN: usize = 64;
+
+const _: () = assert!(ADXL345_BUFFER_MIN >= ADXL345_HEADER_WORDS + ADXL345_DEVICE_SAMPLES);
+
+/// Maximum number of samples held by the device: the FIFO plus the data registers.
+const ADXL345_DEVICE_SAMPLES: usize = 33;
+
+/// Most records queued for one sample: the sample and its event, range, tap and clip markers.
+const ADXL345_SAMPLE_RECORDS: usize = 5;
+
+/// Smallest read, in samples, for which the reader drains the device itself before sleeping.
+pub (crate) const ADXL345_READ_AHEAD_MIN: usize = 8;
+
+/// Argument of `ADXL345_IOC_GET_BUFFER`.
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub (crate) struct Adxl345BufferArg {
+    pub (crate) capacity: u32,  // Samples the buffer holds at most
+    pub (crate) buffered: u32,  // Samples (and markers) buffered now
+    pub (crate) overruns: u64,  // Samples dropped because the buffer was full, since probe
+}
+
+// SAFETY: `Adxl345BufferArg` is `repr(C)`, made only of integers and has no padding, so any byte
+// pattern is a valid value.
+unsafe impl WritableToBytes for Adxl345BufferArg {}
+
+/// Held by the reader acting as consumer of the buffer.
+pub (crate) type Adxl345Consumer<'a> = Guard<'a, Mutex<()>>;
+
+/// Drain state, shared by the work item and the readers.
+pub (crate) struct Adxl345Drain {
+    device: Arc<Mutex<Adxl345>>,
+    buffer: Adxl345Spsc<ADXL345_BUFFER_LEN>,
+    consumer: Mutex<()>,   // Serializes the readers, the producer never takes it
+    wait: WaitQueue,       // Readers waiting for data sleep here
+    overruns: AtomicU64,   // Samples dropped because the buffer was full, since probe
+    running: AtomicBool,   // Cleared to stop the work item from queueing itself again
+    failed: AtomicBool,    // Set when a bus error occurred, reported by the next read
+    overrun: AtomicBool,   // Set while the drain drops samples, for the uevents
+    bus_down: AtomicBool,  // Set from a failing drain to the next successful one, for the uevents
+    removed: AtomicBool,   // Set by remove, the device is gone for good
+    pending_range: AtomicU32, // Range marker to queue before the next sample, 0 if none
+    pending_tap: AtomicU32,   // Tap marker to queue before the next sample, 0 if none
+    stale_range: AtomicBool,  // Set when the drain changed the range, until the snapshot follows
+    data_events: AtomicU64,   // Data events so far, see poll.rs
+    readers: Adxl345Readers,  // Readers inside read(), see fair_share.rs
+    id: usize,                // Id of the device, see instance.rs
+    work: DelayedWork,
+}
+
+impl_self_delayed_work_adapter!(Adxl345Drain, work, Adxl345Drain::run);
+
+impl Adxl345Drain {
+    /// Creates the drain state of `device`, the work item is not queued yet.
+    pub (crate) fn try_new(device: Arc<Mutex<Adxl345>>) -> Result<Arc<Self>> {
+        let id = device.lock().id();
+        let drain = UniqueArc::try_new(Self {
+            device,
+            buffer: Adxl345Spsc::new(),
+            // SAFETY: `mutex_init` is called below.
+            consumer: unsafe { Mutex::new(()) },
+            // SAFETY: `waitqueue_init` is called below.
+            wait: unsafe { WaitQueue::new() },
+            overruns: AtomicU64::new(0),
+            running: AtomicBool::new(false),
+            failed: AtomicBool::new(false),
+            overrun: AtomicBool::new(false),
+            bus_down: AtomicBool::new(false),
+            removed: AtomicBool::new(false),
+            pending_range: AtomicU32::new(0),
+            pending_tap: AtomicU32::new(0),
+            stale_range: AtomicBool::new(false),
+            data_events: AtomicU64::new(0),
+            readers: Adxl345Readers::new(),
+            id,
+            // SAFETY: `init_delayed_work_item` is called below.
+            work: unsafe { DelayedWork::new() },
+        })?;
+        drain.buffer.set_capacity(ADXL345_BUFFER_DEFAULT);
+        init_delayed_work_item!(&drain);
+
+        let mut drain = Pin::from(drain);
+        // SAFETY: `consumer` is pinned when `drain` is.
+        let consumer = unsafe { drain.as_mut().map_unchecked_mut(|d| &mut d.consumer) };
+        mutex_init!(consumer, "adxl345_consumer");
+        // SAFETY: `wait` is pinned when `drain` is.
+        let wait = unsafe { drain.as_mut().map_unchecked_mut(|d| &mut d.wait) };
+        waitqueue_init!(wait, "adxl345_data_wait");
+
+        Ok(drain.into())
+    }
+
+    /// Discards the stale samples and starts draining the device.
+    pub (crate) fn start(drain: &Arc<Self>) {
+        drain.clear(&drain.consumer());
+        drain.failed.store(false, Ordering::Relaxed);
+        drain.overrun.store(false, Ordering::Relaxed);
+        drain.pending_range.store(0, Ordering::Relaxed);
+        drain.pending_tap.store(0, Ordering::Relaxed);
+        adxl345_correlation(drain.id).cancel();
+        drain.burst().restart();
+        Self::resume(drain);
+    }
+
+    /// Starts draining the device again after `stop()`, keeping the buffered samples.
+    pub (crate) fn resume(drain: &Arc<Self>) {
+        drain.running.store(true, Ordering::Release);
+        workqueue::system().enqueue_delayed(drain.clone(), 0);
+    }
+
+    /// Runs the drain now rather than at the end of its period, called from the data interrupt
+    /// (see data_irq.rs). Does nothing while the drain is stopped.
+    pub (crate) fn kick(drain: &Arc<Self>) {
+        if drain.running.load(Ordering::Acquire) {
+            workqueue::system().mod_delayed(drain.clone(), 0);
+        }
+    }
+
+    /// Stops draining and waits for a running drain to complete.
+    ///
+    /// Once it returns the work item is neither queued nor running, so the device can be
+    /// released safely.
+    pub (crate) fn stop(&self) {
+        self.running.store(false, Ordering::Release);
+        self.work.cancel::<Self>();
+    }
+
+    /// Stops draining for good, used by remove with the configuration lock held.
+    ///
+    /// Once it returns the work item is neither queued nor running, `adxl345_stream_start`
+    /// refuses to start it again, and every reader has been woken up to notice the device is
+    /// gone.
+    pub (crate) fn shutdown(&self) {
+        self.removed.store(true, Ordering::Release);
+        self.stop();
+        self.wake_up();
+        adxl345_sigio(bindings::POLL_HUP);
+    }
+
+    /// Returns the wait queue the readers of the device sleep on until data is buffered.
+    pub (crate) fn wait_queue(&self) -> &WaitQueue {
+        &self.wait
+    }
+
+    /// Wakes up the readers of the device.
+    pub (crate) fn wake_up(&self) {
+        self.wait.wake_up_all();
+    }
+
+    /// Returns true once the device has been removed.
+    pub (crate) fn is_removed(&self) -> bool {
+        self.removed.load(Ordering::Acquire)
+    }
+
+    /// Returns true between `start()` and `stop()`.
+    pub (crate) fn is_running(&self) -> bool {
+        self.running.load(Ordering::Acquire)
+    }
+
+    /// Returns the id of the device.
+    pub (crate) fn id(&self) -> usize {
+        self.id
+    }
+
+    /// Returns the readers inside `read()` on the files of the device, see fair_share.rs.
+    pub (crate) fn readers(&self) -> &Adxl345Readers {
+        &self.readers
+    }
+
+    /// Returns the count of data events of the device, see `adxl345_data_event()`.
+    pub (crate) fn data_events(&self) -> &AtomicU64 {
+        &self.data_events
+    }
+
+    /// Returns the configuration snapshot of the device.
+    pub (crate) fn snapshot(&self) -> &'static Adxl345SnapshotCell {
+        adxl345_snapshot(self.id)
+    }
+
+    /// Returns the burst schedule of the device.
+    fn burst(&self) -> &'static Adxl345Burst {
+        adxl345_burst(self.id)
+    }
+
+    /// Queues the header of a stream going on after a gap, with the drain stopped, e.g. on
+    /// resume from system sleep (see power.rs).
+    ///
+    /// # Returns
+    /// `false` if it doesn't fit in the buffer, the stream goes on without it.
+    pub (crate) fn push_header(&self) -> bool {
+        let adxl = self.device.lock();
+        if self.buffer.free() < ADXL345_HEADER_WORDS {
+            return false;
+        }
+        // SAFETY: The device lock is held, so there is a single producer.
+        unsafe { self.buffer.push_all(&adxl345_header(self.id, adxl.clock()), 0) }
+    }
+
+    /// Body of the work item: moves the ready samples into the buffer and queues itself again.
+    fn run(drain: Arc<Self>) {
+        if !drain.running.load(Ordering::Acquire) {
+            return;
+        }
+
+        // In burst mode the device sleeps between bursts, and the drain with it
+        let now = ktime_get_ns();
+        match drain.burst().step(now) {
+            Adxl345BurstStep::Sleep(ms) => {
+                workqueue::system().enqueue_delayed(drain, msecs_to_jiffies(ms));
+                return;
+            }
+            Adxl345BurstStep::Start => {
+                // A skipped burst leaves the device asleep until the next one
+                if let Ok(false) = drain.burst_start(now) {
+                    workqueue::system().enqueue_delayed(drain, 0);
+                    return;
+                }
+            }
+            Adxl345BurstStep::Measure | Adxl345BurstStep::End => {}
+        }
+
+        // A chip that lost its configuration is reprogrammed before it is drained, a bus error
+        // shows in the drain below
+        let shadow = adxl345_shadow(drain.id);
+        let rate_mhz = drain.snapshot().get().rate_mhz;
+        let reprogrammed = shadow.poll(&drain.device, rate_mhz);
+
+        let result = drain.fill(false, None);
+        drain.refresh_range();
+        let ended = matches!(drain.burst().step(ktime_get_ns()), Adxl345BurstStep::End)
+            && drain.burst_end().is_ok();
+        let catch_up = !ended && matches!(result, Ok((moved, _)) if moved >= ADXL345_FIFO_HALF);
+        let drained = match result {
+            Ok((moved, _)) => {
+                shadow.drained(moved);
+                moved > 0
+            }
+            Err(_) => {
+                drain.failed.store(true, Ordering::Release);
+                true
+            }
+        };
+
+        // The burst marker wakes up the readers too
+        if drained || ended {
+            adxl345_data_event(drain);
+            ADXL345_CONCURRENCY.wakeup();
+            drain.wake_up();
+            match result {
+                Ok(_) => adxl345_sigio_data(drain.buffered()),
+                Err(_) => adxl345_sigio(bindings::POLL_ERR),
+            }
+        }
+
+        // Outside of the device lock, sending a uevent may sleep
+        if let Some(event) = drain.transition(&result) {
+            if event == Adxl345Event::Recovered {
+                shadow.request();
+            }
+            drain.notify(event);
+        }
+        if let Ok(true) = reprogrammed {
+            drain.notify(Adxl345Event::Reprogrammed);
+        }
+        if let Some(event) = adxl345_gravity_watch(drain.id).take_change() {
+            drain.notify(event);
+        }
+        adxl345_alarm(drain.id).update();
+        adxl345_motion(drain.id).notify(&drain);
+
+        if drain.running.load(Ordering::Acquire) {
+            let delay = match catch_up {
+                true => 0,
+                false => msecs_to_jiffies(adxl345_drain_period_ms(drain.snapshot().get().rate_mhz)),
+            };
+            workqueue::system().enqueue_delayed(drain, delay);
+        }
+    }
+
+    /// Starts a burst: discards the samples left in the device, enables measurement and queues
+    /// the header of the burst.
+    ///
+    /// # Returns
+    /// - `Ok(true)` once the burst is running.
+    /// - `Ok(false)` if the header doesn't fit in the buffer, the burst is skipped.
+    /// - `Err` if a bus error occurred, the burst starts at the next pass.
+    fn burst_start(&self, now_ns: u64) -> Result<bool> {
+        let adxl = self.device.lock();
+        if self.buffer.free() < ADXL345_HEADER_WORDS {
+            self.burst().skip(now_ns);
+            return Ok(false);
+        }
+
+        // The FIFO keeps its samples in standby, they belong to no burst
+        let stale = adxl.pending_samples()?.min(ADXL345_DEVICE_SAMPLES);
+        for _ in 0..stale {
+            adxl.read_data()?;
+        }
+        adxl.enable_measure()?;
+
+        // SAFETY: The device lock is held, so there is a single producer.
+        unsafe { self.buffer.push_all(&adxl345_header(self.id, adxl.clock()), 0) };
+        self.burst().started(now_ns);
+        Ok(true)
+    }
+
+    /// Ends the running burst: queues its burst marker and puts the device in standby.
+    ///
+    /// # Returns
+    /// - `Ok(())` once the device is in standby.
+    /// - `Err` if a bus error occurred, the next burst starts on schedule anyway.
+    fn burst_end(&self) -> Result {
+        let adxl = self.device.lock();
+        let samples = self.burst().ended(ktime_get_ns());
+        let marker = Adxl345Sample::marker(ADXL345_MARKER_BURST, samples as i16);
+        // SAFETY: The device lock is held, so there is a single producer.
+        if !unsafe { self.buffer.push_all(&[marker], 0) } {
+            self.count_overrun();
+        }
+        adxl.disable_measure()
+    }
+
+    /// Drains the device now, without waiting for the work item, used by fsync().
+    ///
+    /// Called through `Adxl345Context::flush`, with the configuration lock held and the device
+    /// checked present.
+    ///
+    /// Nothing is discarded: when the buffer is full the remaining samples are left in the
+    /// device, for the readers to make room first.
+    ///
+    /// # Returns
+    /// - `Ok(usize)` with the number of samples moved into the buffer.
+    /// - `Err(EIO)` if a bus error occurred, the samples moved before it are kept.
+    pub (crate) fn flush(&self) -> Result<usize> {
+        let ret = self.fill(true, None);
+        self.refresh_range();
+        if !matches!(ret, Ok((0, _))) {
+            adxl345_data_event(self);
+        }
+        self.wake_up();
+        match ret {
+            Ok((0, _)) => {}
+            Ok(_) => adxl345_sigio_data(self.buffered()),
+            Err(_) => adxl345_sigio(bindings::POLL_ERR),
+        }
+        ret.map(|(moved, _)| moved).map_err(|_| EIO)
+    }
+
+    /// Drains up to `wanted` samples now, on behalf of a reader asking for a large buffer.
+    ///
+    /// The drain is bounded by the samples held by the device, counted once from FIFO_STATUS, so
+    /// the reader fills its buffer in one pass instead of sleeping until the work item runs. As
+    /// `flush()`, nothing is discarded, and it is called through the context
+    /// (`Adxl345Context::read_ahead`).
+    ///
+    /// # Returns
+    /// - `Ok(usize)` with the number of samples moved into the buffer.
+    /// - `Err(EIO)` if a bus error occurred, the samples moved before it are kept.
+    pub (crate) fn read_ahead(&self, wanted: usize) -> Result<usize> {
+        let ret = self.fill(true, Some(wanted));
+        self.refresh_range();
+        ret.map(|(moved, _)| moved).map_err(|_| EIO)
+    }
+
+    /// Returns the state transition caused by the drain that returned `result`, if any.
+    ///
+    /// Only the work item calls it, so each episode is reported once: an overrun lasts until a
+    /// drain drops nothing, a bus error until a drain succeeds.
+    fn transition(&self, result: &Result<(usize, bool)>) -> Option<Adxl345Event> {
+        match result {
+            Err(_) if !self.bus_down.swap(true, Ordering::Relaxed) => Some(Adxl345Event::BusError),
+            Err(_) => None,
+            Ok(_) if self.bus_down.swap(false, Ordering::Relaxed) => Some(Adxl345Event::Recovered),
+            Ok((_, true)) if !self.overrun.swap(true, Ordering::Relaxed) => Some(Adxl345Event::Overrun),
+            Ok((_, dropped)) => {
+                if !dropped {
+                    self.overrun.store(false, Ordering::Relaxed);
+                }
+                None
+            }
+        }
+    }
+
+    /// Publishes the range changed by auto-ranging in the configuration snapshot.
+    ///
+    /// Called after `fill()`, outside of the device lock since the publication may sleep.
+    fn refresh_range(&self) {
+        if self.stale_range.swap(false, Ordering::Relaxed) && adxl345_snapshot_refresh(&self.device).is_err() {
+            self.stale_range.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Sends `event` as a uevent of the device on its bus.
+    ///
+    /// The device lock is only held to take a reference to the device, the event itself is sent
+    /// without it.
+    fn notify(&self, event: Adxl345Event) {
+        let device = Device::from_dev(self.device.lock().bus().device());
+        if adxl345_uevent(&device, self.id, event).is_err() {
+            pr_err!("Failed to send the {:?} uevent\n", event);
+        }
+    }
+
+    /// Discards everything buffered, in the kernel buffer and in the device, used by
+    /// `ADXL345_IOC_FLUSH`.
+    ///
+    /// The device lock is held while both are emptied, so no sample acquired before the call
+    /// can be pushed in between. As `flush()`, it is called through the context
+    /// (`Adxl345Context::discard`).
+    ///
+    /// # Returns
+    /// - `Ok(usize)` with the number of samples discarded.
+    /// - `Err(EIO)` if a bus error occurred while emptying the device.
+    pub (crate) fn discard(&self) -> Result<usize> {
+        let _consumer = self.consumer();
+        let adxl = self.device.lock();
+
+        // The data registers and the FIFO hold at most 33 samples, the bound only guards
+        // against a corrupted FIFO_STATUS
+        let pending = adxl.pending_samples().map_err(|_| EIO)?.min(ADXL345_DEVICE_SAMPLES);
+        let mut discarded = 0;
+        for _ in 0..pending {
+            adxl.read_data().map_err(|_| EIO)?;
+            discarded += 1;
+        }
+
+        discarded += self.buffered();
+        // SAFETY: The consumer lock is held, as proven by the guard.
+        unsafe { self.buffer.clear() };
+        Ok(discarded)
+    }
+
+    /// Moves the samples ready in the device into the buffer, under the device lock.
+    ///
+    /// The device lock also serializes the producers, the work item, `flush()` and
+    /// `read_ahead()`. The samples held by the device are counted once, up front, and exactly
+    /// that many are read, at most `limit` if given; the ones acquired meanwhile are left for the
+    /// next drain. If `lossless` is set it stops as soon as the buffer has no room for a sample
+    /// with its event, range, tap and clip markers, otherwise the sample that doesn't fit is
+    /// dropped.
+    ///
+    /// # Returns
+    /// - `Ok((usize, bool))` with the number of samples moved into the buffer, and whether a
+    ///   sample was dropped.
+    /// - `Err` if a bus error occurred, the samples moved before it are kept.
+    fn fill(&self, lossless: bool, limit: Option<usize>) -> Result<(usize, bool)> {
+        let mut moved = 0;
+        let mut dropped = false;
+        let mut range_g = self.snapshot().get().range_g;
+        let (alarm, tap, motion) = (adxl345_alarm(self.id), adxl345_tap(self.id), adxl345_motion(self.id));
+        let (correlation, auto_range) = (adxl345_correlation(self.id), adxl345_auto_range(self.id));
+        let gravity_watch = adxl345_gravity_watch(self.id);
+        let adxl = self.device.lock();
+        let _held = ADXL345_CONCURRENCY.device.hold();
+        if alarm.wants_source() || tap.wants_source() || motion.wants_source() {
+            let source = adxl.read_register(ADXL345_REG_INT_SOURCE)?;
+            alarm.push_source(source);
+            if motion.push_source(&adxl, source) {
+                correlation.flag(&adxl, ADXL345_EVENT_MOTION);
+            }
+            let tap = tap.push_source(&adxl, source)?;
+            if tap != 0 {
+                // A tap not queued yet is merged, there is one marker per sample at most
+                self.pending_tap.fetch_or(tap as u16 as u32, Ordering::Relaxed);
+                correlation.flag(&adxl, ADXL345_EVENT_TAP);
+            }
+        }
+        let pending = adxl.pending_samples()?;
+        if pending >= ADXL345_FIFO_LEN {
+            Adxl345Stats::add(&ADXL345_STATS.fifo_full, 1);
+        }
+        let pending = pending.min(ADXL345_DEVICE_SAMPLES);
+        let limit = limit.map_or(pending, |wanted| wanted.min(pending));
+        while moved < limit {
+            if lossless && self.buffer.free() < ADXL345_SAMPLE_RECORDS {
+                break;
+            }
+            let sample = adxl.read_data()?;
+            let read_ns = adxl.clock().now_ns();
+            gravity_watch.push(&sample);
+            if alarm.push(&sample) {
+                correlation.flag(&adxl, ADXL345_EVENT_THRESHOLD);
+            }
+            adxl345_latest_sample_store(self.id, &sample);
+            let clipped = adxl345_clip_axes(&sample, range_g);
+            if clipped != 0 {
+                Adxl345Stats::add(&ADXL345_STATS.clipped, 1);
+            }
+
+            // The pending event, range and tap markers and the clip marker go with the sample,
+            // all or none
+            let mut records = [Adxl345Sample::new(0, 0, 0); ADXL345_SAMPLE_RECORDS];
+            let mut len = 0;
+            let pending_event = correlation.pending();
+            if pending_event != 0 {
+                records[len] = Adxl345Sample::marker(ADXL345_MARKER_EVENT, pending_event as u16 as i16);
+                len += 1;
+            }
+            let pending_range = self.pending_range.load(Ordering::Relaxed);
+            if pending_range != 0 {
+                records[len] = Adxl345Sample::marker(ADXL345_MARKER_RANGE, pending_range as i16);
+                len += 1;
+            }
+            let pending_tap = self.pending_tap.load(Ordering::Relaxed);
+            if pending_tap != 0 {
+                records[len] = Adxl345Sample::marker(ADXL345_MARKER_TAP, pending_tap as i16);
+                len += 1;
+            }
+            if clipped != 0 {
+                records[len] = Adxl345Sample::marker(ADXL345_MARKER_CLIP, clipped);
+                len += 1;
+            }
+            records[len] = sample;
+            len += 1;
+
+            let begin = ktime_get_ns();
+            // SAFETY: The device lock is held, so there is a single producer.
+            let pushed = unsafe { self.buffer.push_all(&records[..len], read_ns) };
+            Adxl345Stats::max(&ADXL345_STATS.push_max_ns, ktime_get_ns() - begin);
+            if pushed && pending_range != 0 {
+                self.pending_range.store(0, Ordering::Relaxed);
+            }
+            if pushed && pending_tap != 0 {
+                self.pending_tap.store(0, Ordering::Relaxed);
+            }
+            if pushed && pending_event != 0 {
+                correlation.queued(pending_event);
+            }
+
+            // The next samples are acquired at the new range, the marker goes before them
+            if let Some(new_range) = auto_range.push(&sample, clipped, range_g) {
+                adxl.set_param(Adxl345Param::Range, new_range)?;
+                auto_range.switched();
+                pr_info!("Auto-ranging: range set to {} g\n", new_range);
+                range_g = new_range;
+                self.pending_range.store(new_range, Ordering::Relaxed);
+                self.stale_range.store(true, Ordering::Relaxed);
+            }
+            if !pushed {
+                self.count_overrun();
+                // The marker goes before the first sample queued after the gap
+                correlation.flag(&adxl, ADXL345_EVENT_OVERRUN);
+                dropped = true;
+                break;
+            }
+            Adxl345Stats::add(&ADXL345_STATS.drained, 1);
+            moved += 1;
+        }
+        self.burst().drained(moved);
+        ADXL345_CONCURRENCY.occupancy(self.buffer.len());
+        Ok((moved, dropped))
+    }
+
+    /// Makes the caller the consumer of the buffer until the returned guard is dropped.
+    ///
+    /// It may sleep, the producer is never blocked by it.
+    pub (crate) fn consumer(&self) -> Adxl345Consumer<'_> {
+        self.consumer.lock()
+    }
+
+    /// Removes the oldest buffered sample.
+    pub (crate) fn pop(&self, consumer: &Adxl345Consumer<'_>) -> Option<Adxl345Sample> {
+        self.pop_stamped(consumer).map(|(sample, _)| sample)
+    }
+
+    /// Removes the oldest buffered sample, with the time it was read from the device in the
+    /// clock of the samples, 0 for a marker.
+    pub (crate) fn pop_stamped(&self, _consumer: &Adxl345Consumer<'_>) -> Option<(Adxl345Sample, u64)> {
+        // SAFETY: The consumer lock is held, as proven by the guard.
+        unsafe { self.buffer.pop() }
+    }
+
+    /// Returns the oldest buffered sample without removing it.
+    pub (crate) fn peek(&self, _consumer: &Adxl345Consumer<'_>) -> Option<Adxl345Sample> {
+        // SAFETY: The consumer lock is held, as proven by the guard.
+        unsafe { self.buffer.peek() }
+    }
+
+    /// Discards the buffered samples, the device is not touched.
+    pub (crate) fn clear(&self, _consumer: &Adxl345Consumer<'_>) {
+        // SAFETY: The consumer lock is held, as proven by the guard.
+        unsafe { self.buffer.clear() };
+    }
+
+    /// Returns the number of buffered samples.
+    pub (crate) fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Sets the number of samples the buffer holds at most. The samples buffered beyond it are
+    /// kept, the drain drops the new ones until the readers catch up.
+    ///
+    /// # Returns
+    /// `Err(EINVAL)` below `ADXL345_BUFFER_MIN` or above `ADXL345_BUFFER_LEN`.
+    pub (crate) fn set_capacity(&self, capacity: u32) -> Result {
+        let capacity = capacity as usize;
+        if !(ADXL345_BUFFER_MIN..=ADXL345_BUFFER_LEN).contains(&capacity) {
+            return Err(EINVAL);
+        }
+        self.buffer.set_capacity(capacity);
+        Ok(())
+    }
+
+    /// Counts a record dropped because the buffer was full, for the device and in the
+    /// statistics of all the devices.
+    fn count_overrun(&self) {
+        self.overruns.fetch_add(1, Ordering::Relaxed);
+        Adxl345Stats::add(&ADXL345_STATS.dropped, 1);
+    }
+
+    /// Returns the capacity of the buffer, what it holds and the overruns of the device so far.
+    pub (crate) fn buffer_info(&self) -> Adxl345BufferArg {
+        Adxl345BufferArg {
+            capacity: self.buffer.capacity() as u32,
+            buffered: self.buffer.len() as u32,
+            overruns: self.overruns.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Returns true if a read would not block: samples are buffered, an error is pending or the
+    /// device was removed.
+    pub (crate) fn readable(&self) -> bool {
+        self.failed.load(Ordering::Acquire) || self.is_removed() || self.buffer.len() > 0
+    }
+
+    /// Returns true, once, if a bus error occurred since the last call.
+    pub (crate) fn take_error(&self) -> bool {
+        self.failed.swap(false, Ordering::AcqRel)
+    }
+}
+
+/// Returns the drain period at `rate_mhz`, in milliseconds: the time the device takes to fill
+/// half its FIFO, 5 ms at 3200 Hz, at most `ADXL345_DRAIN_PERIOD_MS`.
+fn adxl345_drain_period_ms(rate_mhz: u32) -> u32 {
+    let half_fifo_ms = ADXL345_FIFO_HALF as u64 * 1_000_000 / (rate_mhz as u64).max(1);
+    half_fifo_ms.clamp(1, ADXL345_DRAIN_PERIOD_MS as u64) as u32
+}
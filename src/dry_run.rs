@@ -0,0 +1,190 @@
+/*
+ * Copyright 2024 Luca Saverio Esposito, Università di Roma, Tor Vergata
+ * email: <lucasaverioesposito@gmail.com>
+ *
+ * This file is part of an "Rust Linux driver for the ADXL345 device".
+ *
+ * This driver is free software: you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * This driver is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with Foobar.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+
+// dry_run.rs
+
+//! I2C dry-run mode.
+//!
+//! When the driver is loaded with `dry_run=1`, no transfer reaches the bus: register writes are
+//! applied to a simulated register map and recorded in a trace (`/sys/kernel/debug/adxl345/
+//! dry_run_trace`), while reads are served from the map. This lets the configuration logic be
+//! validated on a machine without an ADXL345 attached.
+
+use kernel::prelude::*;
+use kernel::str::CString;
+use core::sync::atomic::{AtomicBool, AtomicU8, AtomicU16, AtomicU32, Ordering};
+use crate::constant::*;
+
+/// Number of register writes kept in the trace.
+pub (crate) const ADXL345_DRY_RUN_TRACE_LEN: usize = 64;
+
+/// Number of registers of the device.
+const ADXL345_REG_COUNT: usize = 0x40;
+
+/// Step of the simulated x axis waveform, in LSB. It exceeds the read filter threshold so every
+/// simulated sample reaches userspace.
+const ADXL345_DRY_RUN_STEP: i16 = 16;
+
+/// Amplitude of the simulated x axis waveform, in LSB.
+const ADXL345_DRY_RUN_AMPLITUDE: i16 = 256;
+
+/// Simulated z axis value, 1 g in full resolution (3.9 mg/LSB).
+const ADXL345_DRY_RUN_ONE_G: i16 = 256;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const REG_INIT: AtomicU8 = AtomicU8::new(0);
+#[allow(clippy::declare_interior_mutable_const)]
+const TRACE_INIT: AtomicU16 = AtomicU16::new(0);
+
+/// Simulated register map and write trace.
+///
+/// Only atomics are used so the state can be a plain static, it is accessed with the device
+/// lock held anyway.
+pub (crate) struct Adxl345DryRun {
+    enabled: AtomicBool,
+    regs: [AtomicU8; ADXL345_REG_COUNT],
+    // Each entry packs the register (high byte) and the written value (low byte)
+    trace: [AtomicU16; ADXL345_DRY_RUN_TRACE_LEN],
+    writes: AtomicU32,
+    phase: AtomicU32,
+}
+
+/// Global dry-run state.
+pub (crate) static ADXL345_DRY_RUN: Adxl345DryRun = Adxl345DryRun::new();
+
+impl Adxl345DryRun {
+    const fn new() -> Self {
+        Self {
+            enabled: AtomicBool::new(false),
+            regs: [REG_INIT; ADXL345_REG_COUNT],
+            trace: [TRACE_INIT; ADXL345_DRY_RUN_TRACE_LEN],
+            writes: AtomicU32::new(0),
+            phase: AtomicU32::new(0),
+        }
+    }
+
+    /// Enables the dry-run mode, loading the power-on values of the registers.
+    pub (crate) fn enable(&self) {
+        for reg in &self.regs {
+            reg.store(0, Ordering::Relaxed);
+        }
+        self.regs[ADXL345_REG_DEVID as usize].store(ADXL345_DEVID, Ordering::Relaxed);
+        self.regs[ADXL345_REG_BW_RATE as usize].store(0x0A, Ordering::Relaxed);
+        self.regs[ADXL345_REG_INT_SOURCE as usize].store(0x02, Ordering::Relaxed);
+        self.writes.store(0, Ordering::Relaxed);
+        self.enabled.store(true, Ordering::Release);
+        pr_info!("Dry-run mode, no I2C transfer will be issued\n");
+    }
+
+    /// Returns `true` if register accesses must be simulated.
+    pub (crate) fn enabled(&self) -> bool {
+        self.enabled.load(Ordering::Acquire)
+    }
+
+    /// Reads a simulated register.
+    ///
+    /// New data is always reported as ready in `INT_SOURCE`.
+    pub (crate) fn read(&self, reg: u8) -> Result<u8> {
+        let value = self.regs.get(reg as usize).ok_or(EINVAL)?.load(Ordering::Relaxed);
+        if reg == ADXL345_REG_INT_SOURCE {
+            return Ok(value | 0x80);
+        }
+        Ok(value)
+    }
+
+    /// Records a register write in the trace and applies it to the map.
+    ///
+    /// Writes to read-only registers are traced but have no effect, as on the device.
+    pub (crate) fn write(&self, reg: u8, value: u8) -> Result<()> {
+        let slot = self.regs.get(reg as usize).ok_or(EINVAL)?;
+
+        let index = self.writes.fetch_add(1, Ordering::AcqRel) as usize % ADXL345_DRY_RUN_TRACE_LEN;
+        self.trace[index].store(((reg as u16) << 8) | value as u16, Ordering::Relaxed);
+        pr_debug!("dry-run: write 0x{:02x} = 0x{:02x}\n", reg, value);
+
+        match reg {
+            ADXL345_REG_DEVID
+            | ADXL345_REG_ACT_TAP_STATUS
+            | ADXL345_REG_INT_SOURCE
+            | ADXL345_REG_DATAX0..=ADXL345_REG_DATAZ1
+            | ADXL345_REG_FIFO_STATUS => {}
+            _ => slot.store(value, Ordering::Relaxed),
+        }
+        Ok(())
+    }
+
+    /// Generates the next simulated sample and reads `data.len()` registers starting at `reg`.
+    ///
+    /// The x axis follows a triangle wave, y stays at 0 g and z at 1 g.
+    pub (crate) fn read_block(&self, reg: u8, data: &mut [u8]) -> Result<usize> {
+        if reg == ADXL345_REG_DATAX0 {
+            self.next_sample();
+        }
+        for (i, byte) in data.iter_mut().enumerate() {
+            *byte = self.read(reg.checked_add(i as u8).ok_or(EINVAL)?)?;
+        }
+        Ok(data.len())
+    }
+
+    /// Updates the data registers with the next point of the simulated waveform.
+    fn next_sample(&self) {
+        let period = (4 * ADXL345_DRY_RUN_AMPLITUDE / ADXL345_DRY_RUN_STEP) as u32;
+        let phase = (self.phase.fetch_add(1, Ordering::Relaxed) % period) as i16;
+        let quarter = ADXL345_DRY_RUN_AMPLITUDE / ADXL345_DRY_RUN_STEP;
+
+        // Triangle wave between -AMPLITUDE and +AMPLITUDE
+        let x = if phase < 2 * quarter {
+            -ADXL345_DRY_RUN_AMPLITUDE + phase * ADXL345_DRY_RUN_STEP
+        } else {
+            3 * ADXL345_DRY_RUN_AMPLITUDE - phase * ADXL345_DRY_RUN_STEP
+        };
+
+        for (offset, value) in [x, 0, ADXL345_DRY_RUN_ONE_G].iter().enumerate() {
+            let reg = ADXL345_REG_DATAX0 as usize + 2 * offset;
+            let bytes = value.to_le_bytes();
+            self.regs[reg].store(bytes[0], Ordering::Relaxed);
+            self.regs[reg + 1].store(bytes[1], Ordering::Relaxed);
+        }
+    }
+
+    /// Formats the traced writes, oldest first, one per line.
+    pub (crate) fn trace(&self) -> Result<Vec<u8>> {
+        let mut text = Vec::new();
+        if !self.enabled() {
+            text.try_extend_from_slice(b"dry-run disabled\n")?;
+            return Ok(text);
+        }
+
+        let writes = self.writes.load(Ordering::Acquire) as usize;
+        let first = writes.saturating_sub(ADXL345_DRY_RUN_TRACE_LEN);
+        for n in first..writes {
+            let entry = self.trace[n % ADXL345_DRY_RUN_TRACE_LEN].load(Ordering::Relaxed);
+            let line = CString::try_from_fmt(fmt!(
+                "{:6} W 0x{:02x} = 0x{:02x}\n",
+                n,
+                entry >> 8,
+                entry & 0xFF
+            ))?;
+            text.try_extend_from_slice(line.as_bytes())?;
+        }
+        Ok(text)
+    }
+}
@@ -0,0 +1,98 @@
+/*
+ * Copyright 2024 Luca Saverio Esposito, Università di Roma, Tor Vergata
+ * email: <lucasaverioesposito@gmail.com>
+ *
+ * This file is part of an "Rust Linux driver for the ADXL345 device".
+ *
+ * This driver is free software: you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * This driver is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with Foobar.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+
+// error_policy.rs
+
+//! What a read does when the bus fails under it, chosen per open file with
+//! `ADXL345_IOC_SET_ERROR_POLICY`:
+//! - **fail-fast** (the default): the read fails with `EIO`, the samples already buffered stay
+//!   for the next read.
+//! - **best-effort**: the read goes on with the samples gathered so far and reports the error
+//!   in the stream, as a marker of kind `ADXL345_MARKER_ERROR` whose z field is the errno.
+//!   Samples acquired while the bus was failing are lost at the marker. A long-running logger
+//!   then keeps its data and its read loop, and can still tell where the gaps are.
+//!
+//! The errors concerned are the bus errors of the drain and of read-ahead; a fault in the user
+//! buffer still fails the read. A drain failure is reported to a single reader, as before.
+
+use kernel::prelude::*;
+use kernel::error::code::EINVAL;
+use core::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+
+/// Error policy failing the read with the error.
+pub (crate) const ADXL345_ERRORS_FAIL_FAST: u32 = 0;
+
+/// Error policy reporting the error with a marker and returning the samples.
+pub (crate) const ADXL345_ERRORS_BEST_EFFORT: u32 = 1;
+
+/// Error policy of an open file, and the error waiting to be reported in its stream.
+pub (crate) struct Adxl345ErrorPolicy {
+    best_effort: AtomicBool,
+    pending: AtomicI32,         // Positive errno of the error to report, 0 if none
+}
+
+impl Adxl345ErrorPolicy {
+    pub (crate) const fn new() -> Self {
+        Self {
+            best_effort: AtomicBool::new(false),
+            pending: AtomicI32::new(0),
+        }
+    }
+
+    /// Selects the policy, `ADXL345_ERRORS_FAIL_FAST` or `ADXL345_ERRORS_BEST_EFFORT`.
+    pub (crate) fn set(&self, policy: u32) -> Result {
+        match policy {
+            ADXL345_ERRORS_FAIL_FAST => {
+                self.best_effort.store(false, Ordering::Relaxed);
+                self.pending.store(0, Ordering::Relaxed);
+            }
+            ADXL345_ERRORS_BEST_EFFORT => self.best_effort.store(true, Ordering::Relaxed),
+            _ => return Err(EINVAL),
+        }
+        Ok(())
+    }
+
+    /// Handles a bus error met by a read.
+    ///
+    /// # Returns
+    /// `Err(error)` to fail the read in fail-fast mode, `Ok(())` in best-effort mode once the
+    /// error is recorded for `take_pending()`.
+    pub (crate) fn fail(&self, error: Error) -> Result {
+        if !self.best_effort.load(Ordering::Relaxed) {
+            return Err(error);
+        }
+        self.pending.store(-error.to_kernel_errno(), Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Returns true if an error waits to be reported in the stream.
+    pub (crate) fn has_pending(&self) -> bool {
+        self.pending.load(Ordering::Relaxed) != 0
+    }
+
+    /// Takes the errno of the error to report, if any.
+    pub (crate) fn take_pending(&self) -> Option<i16> {
+        match self.pending.swap(0, Ordering::Relaxed) {
+            0 => None,
+            errno => Some(errno as i16),
+        }
+    }
+}
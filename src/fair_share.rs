@@ -0,0 +1,79 @@
+/*
+ * Copyright 2024 Luca Saverio Esposito, Università di Roma, Tor Vergata
+ * email: <lucasaverioesposito@gmail.com>
+ *
+ * This file is part of an "Rust Linux driver for the ADXL345 device".
+ *
+ * This driver is free software: you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * This driver is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with Foobar.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+// fair_share.rs
+
+//! Fair service of the readers sharing the drain.
+//!
+//! Each buffered sample goes to exactly one reader. Without a bound, a reader asking for large
+//! batches empties the buffer on every wakeup and a reader asking for one sample at a time
+//! rarely finds anything left: it is starved even though it reads as fast as the data comes.
+//!
+//! There are no per-file cursors: the buffer of a device holds each sample once, and a reader
+//! wanting every sample must be the only one reading the device.
+//!
+//! Readers inside `read()` are counted per drain, so the readers of one device don't shrink the
+//! reads of another. While more than one is, a read is served at most its
+//! share of what is buffered when it takes the consumer, `buffered / readers` rounded up and
+//! never less than `ADXL345_BATCH_MIN` samples, and returns short; the rest is left to the
+//! others. A reader alone is served up to the size of its buffer, as before. Headers and
+//! markers are not counted, only the samples taken from the buffer, filtered out or not.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Samples a read is always allowed to take, however many readers are waiting.
+pub (crate) const ADXL345_BATCH_MIN: usize = 4;
+
+/// Readers currently inside `read()` on the files of a device, kept in its drain.
+pub (crate) struct Adxl345Readers {
+    active: AtomicUsize,
+}
+
+/// Keeps a reader counted until it returns from `read()`.
+pub (crate) struct Adxl345ReaderGuard<'a> {
+    readers: &'a Adxl345Readers,
+}
+
+impl Adxl345Readers {
+    pub (crate) const fn new() -> Self {
+        Self { active: AtomicUsize::new(0) }
+    }
+
+    /// Counts the caller as a reader until the returned guard is dropped.
+    pub (crate) fn enter(&self) -> Adxl345ReaderGuard<'_> {
+        self.active.fetch_add(1, Ordering::Relaxed);
+        Adxl345ReaderGuard { readers: self }
+    }
+
+    /// Returns the samples a read may take out of `buffered`, at most `wanted`.
+    pub (crate) fn share(&self, buffered: usize, wanted: usize) -> usize {
+        let active = self.active.load(Ordering::Relaxed);
+        if active <= 1 {
+            return wanted;
+        }
+        ((buffered + active - 1) / active).max(ADXL345_BATCH_MIN).min(wanted)
+    }
+}
+
+impl Drop for Adxl345ReaderGuard<'_> {
+    fn drop(&mut self) {
+        self.readers.active.fetch_sub(1, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,72 @@
+/*
+ * Copyright 2024 Luca Saverio Esposito, Università di Roma, Tor Vergata
+ * email: <lucasaverioesposito@gmail.com>
+ *
+ * This file is part of an "Rust Linux driver for the ADXL345 device".
+ *
+ * This driver is free software: you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * This driver is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with Foobar.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+// fasync.rs
+
+//! Signal-driven I/O.
+//!
+//! A file opened with `O_ASYNC` (`fcntl(F_SETFL, O_ASYNC)` after `F_SETOWN`) receives `SIGIO`
+//! instead of polling, for applications built around signal handlers:
+//! - `POLL_IN` when the drain brings new samples and the buffer holds at least the SIGIO
+//!   threshold, so a handler can read a whole batch instead of being signalled every drain period;
+//! - `POLL_PRI` on a sync pulse, whatever is buffered;
+//! - `POLL_ERR` when a drain fails on the bus, the next read returns the error;
+//! - `POLL_HUP` when the device is removed.
+//!
+//! The threshold, in buffered records, is set with `ADXL345_IOC_SET_SIGIO_THRESHOLD` and is the
+//! same for every file: `SIGIO` goes to every owner in the list. While the buffer stays above it,
+//! every drain signals again. The signal only tells that something happened, the reader still
+//! finds out what with a nonblocking read.
+
+use kernel::prelude::*;
+use kernel::bindings;
+use kernel::error::code::ERANGE;
+use kernel::file::FasyncList;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use crate::drain::ADXL345_BUFFER_LEN;
+
+/// Files that asked for `SIGIO`.
+pub (crate) static ADXL345_FASYNC: FasyncList = FasyncList::new();
+
+/// Buffered records needed for a data `SIGIO`.
+static ADXL345_SIGIO_THRESHOLD: AtomicUsize = AtomicUsize::new(1);
+
+/// Sets the buffered records needed for a data `SIGIO`, from 1 to the size of the buffer.
+pub (crate) fn adxl345_sigio_set_threshold(records: u32) -> Result {
+    let records = records as usize;
+    if records == 0 || records > ADXL345_BUFFER_LEN {
+        return Err(ERANGE);
+    }
+    ADXL345_SIGIO_THRESHOLD.store(records, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Signals new data, if `buffered` records reach the threshold.
+pub (crate) fn adxl345_sigio_data(buffered: usize) {
+    if buffered >= ADXL345_SIGIO_THRESHOLD.load(Ordering::Relaxed) {
+        ADXL345_FASYNC.kill(bindings::POLL_IN);
+    }
+}
+
+/// Signals an event, `band` is `POLL_PRI`, `POLL_ERR` or `POLL_HUP`. Callable from interrupt
+/// context.
+pub (crate) fn adxl345_sigio(band: u32) {
+    ADXL345_FASYNC.kill(band);
+}
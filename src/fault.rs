@@ -0,0 +1,101 @@
+/*
+ * Copyright 2024 Luca Saverio Esposito, Università di Roma, Tor Vergata
+ * email: <lucasaverioesposito@gmail.com>
+ *
+ * This file is part of an "Rust Linux driver for the ADXL345 device".
+ *
+ * This driver is free software: you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * This driver is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with Foobar.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+
+// fault.rs
+
+//! Error injection in the read path.
+//!
+//! Debugfs knobs under `/sys/kernel/debug/adxl345/` force `read()` to return a short read,
+//! `EAGAIN` or `EIO` at controlled points, so userspace built on the device can test its retry
+//! logic deterministically:
+//! - `inject_mode`: `0` none, `1` short read (a single record), `2` EAGAIN, `3` EIO.
+//! - `inject_skip`: number of reads served normally before the first injection.
+//! - `inject_times`: number of injections, `0` to inject on every read once `inject_skip` reads
+//!   went through.
+//! - `injected`: number of faults injected so far.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+/// Fault injected in a read.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub (crate) enum Adxl345Fault {
+    /// Return a single record, whatever the buffer size.
+    ShortRead,
+    /// Fail with EAGAIN, as if no data was ready in non-blocking mode.
+    Again,
+    /// Fail with EIO, as if the bus failed.
+    Io,
+}
+
+/// Injection knobs, each of them is a debugfs file.
+pub (crate) struct Adxl345FaultState {
+    pub (crate) mode: AtomicU32,
+    pub (crate) skip: AtomicU32,
+    pub (crate) times: AtomicU32,
+    pub (crate) injected: AtomicU32,
+}
+
+/// Global injection state.
+pub (crate) static ADXL345_FAULT: Adxl345FaultState = Adxl345FaultState::new();
+
+impl Adxl345FaultState {
+    const fn new() -> Self {
+        Self {
+            mode: AtomicU32::new(0),
+            skip: AtomicU32::new(0),
+            times: AtomicU32::new(0),
+            injected: AtomicU32::new(0),
+        }
+    }
+
+    /// Returns the fault to inject in the current read, if any.
+    ///
+    /// Each call accounts for one read: it consumes either one skipped read or one injection.
+    pub (crate) fn next(&self) -> Option<Adxl345Fault> {
+        let fault = match self.mode.load(Ordering::Acquire) {
+            1 => Adxl345Fault::ShortRead,
+            2 => Adxl345Fault::Again,
+            3 => Adxl345Fault::Io,
+            _ => return None,
+        };
+
+        // Let the first `skip` reads through
+        if self
+            .skip
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |skip| skip.checked_sub(1))
+            .is_ok()
+        {
+            return None;
+        }
+
+        // A limited number of injections disables the mode once exhausted
+        let times = self.times.load(Ordering::Acquire);
+        if times != 0 {
+            if times == 1 {
+                self.mode.store(0, Ordering::Release);
+            }
+            self.times.store(times - 1, Ordering::Release);
+        }
+
+        self.injected.fetch_add(1, Ordering::Relaxed);
+        Some(fault)
+    }
+}
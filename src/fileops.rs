@@ -23,60 +23,74 @@
 
 
 use kernel::prelude::*;
-use kernel::sync::{Mutex, SpinLock, Arc};
-use kernel::file::{File, Operations};
+use kernel::bindings;
+use kernel::sync::Completion;
+use kernel::file::{File, Operations, IoctlCommand, PollTable, SeekFrom};
 use kernel::file::flags::*;
 use kernel::chrdev::{Registration};
 use kernel::error::{Result};
-use kernel::error::code::{EINVAL, EAGAIN, EIO};
+use kernel::error::code::{EINVAL, EAGAIN, EIO, ENODEV, ESPIPE};
 use kernel::ForeignOwnable;
-use core::time::Duration;
-use crate::structures::{Adxl345Sample, Adxl345};
-use crate::utility::{adxl345_device_init_at_open,adxl345_device_clean_at_release};
-use kernel::delay::coarse_sleep;
-use kernel::io_buffer::IoBufferWriter;
-use kernel::{mutex_init};
-
-
-///  Global variable to hold the last measurement, protected by a mutex
-static mut ADXL345_LAST_SAMPLE: Mutex<Adxl345Sample> = unsafe{Mutex::new(Adxl345Sample::new(0, 0, 0))};
-pub(crate) static mut DEVICE_PTR: Option<Arc<SpinLock<Adxl345>>> = None;
-
-/// Minimum change required to capture acceleration on any axis.
-/// This constant defines the threshold for filtering out small changes in acceleration
-/// to prevent capturing insignificant movements or noise. 
-const ADXL345_FILTER: i16 = 50;
-
-/// Check on all the axys if the movement is greater than the minimun designed to take the sample.
-fn adxl345_filter_out(new_sample: &Adxl345Sample) -> bool {
-    // Lock the global filter state to read and update the last sample
-    let mut last_sample = unsafe{ADXL345_LAST_SAMPLE.lock()};
-
-    // Calculate absolute differences for x, y, and z axes
-    let diff_x = (new_sample.x - last_sample.x).abs();
-    if diff_x > ADXL345_FILTER {
-        *last_sample = *new_sample; // Update last sample
-        return false;
-    }
-
-    let diff_y = (new_sample.y - last_sample.y).abs();
-    if diff_y > ADXL345_FILTER {
-        *last_sample = *new_sample; // Update last sample
-        return false;
-    }
-
-    let diff_z = (new_sample.z - last_sample.z).abs();
-    if diff_z > ADXL345_FILTER {
-        *last_sample = *new_sample; // Update last sample
-        return false;
-    }
-
-    // Update last sample and return true if all diffs are within the threshold
-    *last_sample = *new_sample;
-    true
+use crate::structures::Adxl345Sample;
+use crate::utility::{adxl345_stream_start,adxl345_stream_stop};
+use crate::sync_input::adxl345_sync;
+use crate::concurrency::ADXL345_CONCURRENCY;
+use crate::drain::{Adxl345Drain, ADXL345_READ_AHEAD_MIN};
+use crate::fault::{Adxl345Fault, ADXL345_FAULT};
+use crate::stats::{Adxl345Stats, ADXL345_STATS};
+use crate::session::{adxl345_session, ADXL345_HEADER_WORDS};
+use crate::constant::{ADXL345_MARKER_SYNC, ADXL345_MARKER_CLIP, ADXL345_MARKER_ERROR, ADXL345_MARKER_LIMIT};
+use crate::ioctl::ADXL345_CONFIG_LOCK;
+use crate::batch_crc::{Adxl345Crc, adxl345_crc_words};
+use crate::copyout::Adxl345Copyout;
+use crate::output::adxl345_record_size;
+use crate::poll::Adxl345Reader;
+use crate::fasync::ADXL345_FASYNC;
+use crate::control::{adxl345_control_write, ADXL345_WRITE_CONTROL};
+use kernel::io_buffer::{IoBufferReader, IoBufferWriter};
+use core::sync::atomic::Ordering;
+use kernel::time::msecs_to_jiffies;
+use crate::context::adxl345_context;
+use crate::power::adxl345_power;
+use crate::instance::ADXL345_DEVICES_MAX;
+use kernel::c_str;
+
+
+#[allow(clippy::declare_interior_mutable_const)]
+const ADXL345_NOT_PROBED: Completion = unsafe { Completion::new() };
+
+/// Completed by probe once the state of the device of each id is published, reinitialized by
+/// remove. The char device is registered before the state is published, so open() waits on it.
+pub(crate) static mut ADXL345_PROBED: [Completion; ADXL345_DEVICES_MAX] = [ADXL345_NOT_PROBED; ADXL345_DEVICES_MAX];
+
+/// Names of the char devices, by id: the primary device keeps the name of the single device
+/// driver.
+pub(crate) const ADXL345_CHARDEV_NAMES: [&CStr; ADXL345_DEVICES_MAX] =
+    [c_str!("adxl345"), c_str!("adxl345-1"), c_str!("adxl345-2"), c_str!("adxl345-3")];
+
+/// The driver module, set at module init. Every open file holds a reference to it, so the
+/// module can't be unloaded while a reader may still run its code.
+pub(crate) static mut ADXL345_MODULE: Option<&'static ThisModule> = None;
+
+/// How long open() waits for probe to complete, in milliseconds.
+const ADXL345_PROBE_TIMEOUT_MS: u32 = 1000;
+
+/// Returns the number of bytes a read would return at most without blocking: the buffered
+/// samples, the pending sync marker, the pending session header and the batch CRC. Samples
+/// discarded by the filter make reads shorter.
+pub(crate) fn adxl345_readable_bytes(drain: &Adxl345Drain, size: usize) -> usize {
+    let marker = if adxl345_sync(drain.id()).has_pending() { 1 } else { 0 };
+    let header = if adxl345_session(drain.id()).has_pending() { ADXL345_HEADER_WORDS } else { 0 };
+    let records = drain.buffered() + marker + header;
+    let crc = if records > 0 { adxl345_crc_words() } else { 0 };
+    (records + crc) * size
 }
 
-
+/// Returns true if a read would not block: data is buffered, a sync pulse arrived, a session
+/// header is ready or the drain failed.
+fn adxl345_would_not_block(drain: &Adxl345Drain) -> bool {
+    drain.readable() || adxl345_sync(drain.id()).has_pending() || adxl345_session(drain.id()).has_pending()
+}
 
 pub (crate) struct Adxl345FileOps {
 }
@@ -85,60 +99,116 @@ unsafe impl Send for Adxl345FileOps{}
 unsafe impl Sync for Adxl345FileOps{}
 
 impl Operations for Adxl345FileOps {
-    type Data: = ();
+    type Data = Box<Adxl345Reader>;
     type OpenData = ();
 
     const HAS_READ: bool = true;
+    const HAS_WRITE: bool = true;
+    const HAS_IOCTL: bool = true;
+    const HAS_SEEK: bool = true;
+    const HAS_FSYNC: bool = true;
+    const HAS_POLL: bool = true;
+    const HAS_FASYNC: bool = true;
     // Required constant to indicate that the vtable should be used
     const USE_VTABLE_ATTR: () = ();
 
-    // Open the char device, can't be open in write mode
+    // Open the char device, write access only with the control channel enabled
     fn open(_context: &Self::OpenData, file: &File) -> Result<Self::Data> {
 
-        // Check if the file was opened with write access and deny it if so
-        let access_mode = file.flags() & O_ACCMODE;
-        if access_mode == O_WRONLY || access_mode == O_RDWR {
-            return Err(EPERM);
-        }
+        // Deny write access unless the control channel is enabled, and the invalid access mode
+        let reads = match file.flags() & O_ACCMODE {
+            O_RDONLY => true,
+            O_WRONLY | O_RDWR if !ADXL345_WRITE_CONTROL.load(Ordering::Relaxed) => return Err(EPERM),
+            O_WRONLY => false,
+            O_RDWR => true,
+            _ => return Err(EINVAL),
+        };
+
+        // The device is a stream, set it as non-seekable before anything is started so a
+        // failure leaves nothing to undo
+        file.set_nonseekable().map_err(|e| {
+            pr_err!("Can't set file as not seekable: {:?}\n", e);
+            e
+        })?;
         
-        {
-            // Access the global pointer
-            let device = unsafe {
-                DEVICE_PTR.as_ref().expect("Driver not initialized").clone()
-            };
-            // Initialize at open, enabling measurement mode
-            adxl345_device_init_at_open(device).map_err(|_| EIO)?;
-        }
+        let reader = {
+            // The minor of the node is the id of the device, see instance.rs
+            let id = file.minor() as usize;
+            if id >= ADXL345_DEVICES_MAX {
+                return Err(ENODEV);
+            }
 
-        //Initialize the global Mutex.
-        mutex_init!(unsafe { Pin::new_unchecked(&mut ADXL345_LAST_SAMPLE)}, "adxl345_last_sample");
+            // Wait for probe to publish the device state
+            // SAFETY: The completions are initialized at module init.
+            let probed = unsafe { &ADXL345_PROBED[id] };
+            if !probed.wait_interruptible_timeout(msecs_to_jiffies(ADXL345_PROBE_TIMEOUT_MS))? {
+                pr_warn!("Device not probed yet\n");
+                return Err(ENODEV);
+            }
 
-        // Reset the global filter state
-        let mut filter_last = unsafe{ADXL345_LAST_SAMPLE.lock()};
-        *filter_last = Adxl345Sample { x: 0, y: 0, z: 0 };
+            // The file works on the device published now until it is released, see context.rs
+            let reader = Box::try_new(Adxl345Reader::new(adxl345_context(id)?))?;
 
-        // Private data are automatically set to point to `dev`, see open_callback in file.rs
-        
-        // Set file as non-seekable
-        file.set_nonseekable().expect("Can't set file as not seekeable");
+            // Pin the module until release. The VFS holds the owner of the char device too, the
+            // driver doesn't rely on it; taking the reference only fails during unload
+            let module = unsafe { ADXL345_MODULE }.ok_or(ENODEV)?;
+            if !module.try_get() {
+                return Err(ENODEV);
+            }
+
+            // Start a measurement session: enable measurement mode and move the samples into
+            // the kernel buffer. ADXL345_IOC_STOP/START control it from now on. The configuration
+            // lock serializes it with the session ioctls and with remove. A write-only file only
+            // controls the device, see control.rs
+            // SAFETY: The lock is initialized at module init.
+            let _config = unsafe { ADXL345_CONFIG_LOCK.lock() };
+            if reads {
+                let context = &reader.context;
+                let started = context.device().and_then(|device| {
+                    adxl345_stream_start(device.clone(), &context.drain)
+                });
+                if let Err(e) = started {
+                    module.put();
+                    return Err(e);
+                }
+            }
+            adxl345_power(reader.context.drain.id()).opened();
+            reader
+        };
+
+        // Private data are automatically set to point to `reader`, see open_callback in file.rs
 
         pr_info!("File open correctly executed \n");
 
-        // Return a reference counted pointer of device
-        Ok(())
+        // Return the per-file state of the reader
+        Ok(reader)
     }
 
     /// Calls device clean at release and frees private date inside the file pointer
-    fn release(_data: Self::Data, _file: &File){
-        
-        {    
-             // Access the global pointer
-            let device = unsafe {
-                DEVICE_PTR.as_ref().expect("Driver not initialized").clone()
-            };
-
-            // Clean up at release (disable measurements)
-            adxl345_device_clean_at_release(device);
+    fn release(data: Self::Data, file: &File){
+
+        // Stop signalling the owner of the file, if it asked for SIGIO
+        let _ = ADXL345_FASYNC.update(-1, file, false);
+
+        {
+            // SAFETY: The lock is initialized at module init.
+            let _config = unsafe { ADXL345_CONFIG_LOCK.lock() };
+
+            // The session runs while any file of the device is open, the last one puts it in standby
+            // whichever file started it (see power.rs). The device may have been removed while
+            // the file was open: remove already stopped the session then
+            if adxl345_power(data.context.drain.id()).released() {
+                if let Ok(device) = data.context.device() {
+                    // End the measurement session, if still running (disable measurements)
+                    adxl345_stream_stop(device.clone(), &data.context.drain);
+                }
+            }
+        }
+
+        // Drop the reference taken by open. The VFS still holds the owner of the char device
+        // until this function has returned, so the module can't go away under it
+        if let Some(module) = unsafe { ADXL345_MODULE } {
+            module.put();
         }
 
         // Private data are automatically set to null`, see release_callback in file.rs
@@ -146,91 +216,328 @@ impl Operations for Adxl345FileOps {
 
     /// Reads accelerometer data into the user's buffer, ensuring only one process reads at a time.
     fn read(
-        _data: <Self::Data as ForeignOwnable>::Borrowed<'_>, // Use ArcBorrow<'_, SpinLock<Adxl345>>        
+        data: <Self::Data as ForeignOwnable>::Borrowed<'_>,
         file: &File,
         writer: &mut impl IoBufferWriter,
         _offset: u64,
     ) -> Result<usize> {
         
         let mut count = 0;
+        // The output format is read once, the whole read uses its record size
+        let mode = data.output.get();
+        let size = adxl345_record_size(mode);
 
         {
-            // Access the global pointer
-            let device = unsafe {
-                DEVICE_PTR.as_ref().expect("Driver not initialized").clone()
-            };
-
-            // Lock the entire `Adxl345` instance
-            let adxl = device.lock();
+            // The drain of the device, which moves the samples from the device into a kernel buffer
+            let drain = &data.context.drain;
 
-            // Calculate the number of items based on the size of `Adxl345Sample`.
-            let items = writer.len() / core::mem::size_of::<Adxl345Sample>();
+            // Calculate the number of items based on the size of a record, leaving room
+            // for the batch CRC if enabled
+            let crc_words = adxl345_crc_words();
+            let mut items = (writer.len() / size).saturating_sub(crc_words);
             if items == 0 {
                 return Err(EINVAL);
             }
+            let mut crc = (crc_words > 0).then(Adxl345Crc::new);
+            let mut out = Adxl345Copyout::new(writer, mode);
+
+            // Counted until the read returns, the other readers leave it a share of the buffer
+            let _reader = drain.readers().enter();
+
+            // Inject the fault requested from debugfs, if any
+            match ADXL345_FAULT.next() {
+                Some(Adxl345Fault::ShortRead) => items = 1,
+                Some(Adxl345Fault::Again) => return Err(EAGAIN),
+                Some(Adxl345Fault::Io) => data.errors.fail(EIO)?,
+                None => {}
+            }
 
-            // Wait until data is ready or handle non-blocking mode.
             loop {
-                // Check if data is ready
-                match adxl.data_ready() {
-                    Ok(ready) if ready > 0 => break,
-                    /* data_ready == 0 and flags  */
-                    Ok(_) if file.flags() & O_NONBLOCK != 0 => {
+                // A large read drains the device itself rather than waking up once per drain
+                // period, the work item keeps draining on its own
+                let buffered = drain.buffered();
+                if items >= ADXL345_READ_AHEAD_MIN && buffered < items {
+                    match data.context.read_ahead(items - buffered) {
+                        Ok(_) => {}
+                        // Reported by the check after the wait, which doesn't sleep then
+                        Err(e) if e == ENODEV => {}
+                        Err(e) => data.errors.fail(e)?,
+                    }
+                }
+
+                // Wait until data is buffered, a sync pulse arrives, a session header is ready,
+                // the drain fails or, in best-effort mode, an error waits to be reported
+                let ready = || adxl345_would_not_block(drain) || data.errors.has_pending();
+                if !ready() {
+                    if file.flags() & O_NONBLOCK != 0 {
                         /* O_NONBLOCK == O_NDELAY */
                         return Err(EAGAIN);
                     }
-                    // just sleep
-                    Ok(_) => coarse_sleep(Duration::from_millis(10)),
-                    // return error
-                    Err(_) => return Err(EIO),
+                    ADXL345_CONCURRENCY.sleep();
+                    drain.wait_queue().wait_interruptible(ready)?;
                 }
-            }
 
-            // Begin reading measurements until the buffer is full.
-            // for 0 .. items ensure that the loop stops when the space on the buffer ends.
-            for _ in 0..items {
-                // Read measurement data
-                let acc = match adxl.read_data() {
-                    Ok(sample) => sample,
-                    Err(_) => return Err(EIO),
-                };
-
-                // Apply filtering: discard the misuration if the changes are to small
-                if adxl345_filter_out(&acc) {
-                    continue;
+                // Remove wakes up the readers, the drain they hold will never be filled again
+                if drain.is_removed() {
+                    return Err(ENODEV);
                 }
 
-                // Attempt to write each field to the user buffer, checking for errors on each operation
-                if let Err(e) = writer.write(&acc.x) {
-                    pr_err!("Failed to write X-axis data to user buffer: {:?}", e);
-                    return Err(e);
+                // The error policy of the file decides whether a bus error fails the read, see
+                // error_policy.rs
+                if drain.take_error() {
+                    data.errors.fail(EIO)?;
                 }
 
-                if let Err(e) = writer.write(&acc.y) {
-                    pr_err!("Failed to write Y-axis data to user buffer: {:?}", e);
-                    return Err(e);
-                }
+                // Copy the buffered records until the user buffer is full.
+                let snapshot = drain.snapshot().get();
+                let (session, sync) = (adxl345_session(drain.id()), adxl345_sync(drain.id()));
+                // Room kept for the limit marker a sample may come out of the pipeline with
+                #[cfg(not(adxl345_no_filter))]
+                let limit_records = snapshot.pipeline.flags_limit() as usize;
+                #[cfg(adxl345_no_filter)]
+                let limit_records = 0;
+                let resampling = data.resample.is_active();
+                let consumer = drain.consumer();
+                let held = ADXL345_CONCURRENCY.consumer.hold();
+                let share = drain.readers().share(drain.buffered(), items);
+                let mut served = 0;
+                while count < items * size && served < share {
+                    // A new session starts with its header, written whole before any record
+                    if session.has_pending() {
+                        if items * size - count < ADXL345_HEADER_WORDS * size {
+                            if count == 0 {
+                                return Err(EINVAL);
+                            }
+                            break;
+                        }
+                        if let Some(header) = session.take_header() {
+                            for record in header.iter() {
+                                out.write(record, &mut crc)?;
+                            }
+                            Adxl345Stats::add(&ADXL345_STATS.markers, ADXL345_HEADER_WORDS as u64);
+                            count += ADXL345_HEADER_WORDS * size;
+                            data.resample.reset(&consumer);
+                            continue;
+                        }
+                    }
 
-                if let Err(e) = writer.write(&acc.z) {
-                    pr_err!("Failed to write Z-axis data to user buffer: {:?}", e);
-                    return Err(e);
+                    // Report a bus error met in best-effort mode, ahead of the samples of this read
+                    if let Some(errno) = data.errors.take_pending() {
+                        let marker = Adxl345Sample::marker(ADXL345_MARKER_ERROR, errno);
+                        out.write(&marker, &mut crc)?;
+                        Adxl345Stats::add(&ADXL345_STATS.markers, 1);
+                        count += size;
+                        continue;
+                    }
+
+                    // Embed a sync marker if a sync pulse arrived since the last record
+                    if let Some(sequence) = sync.take_pending() {
+                        let marker = Adxl345Sample::marker(ADXL345_MARKER_SYNC, sequence as i16);
+                        out.write(&marker, &mut crc)?;
+                        Adxl345Stats::add(&ADXL345_STATS.markers, 1);
+                        count += size;
+                        continue;
+                    }
+
+                    // A resampling file writes the samples interpolated up to the last device
+                    // sample before taking the next one
+                    if resampling {
+                        if let Some(acc) = data.resample.take(snapshot.rate_mhz, &consumer) {
+                            out.write(&acc, &mut crc)?;
+                            Adxl345Stats::add(&ADXL345_STATS.delivered, 1);
+                            count += size;
+                            continue;
+                        }
+                    }
+
+                    // A clip marker is queued together with its sample. Both are written, a
+                    // clipped sample is never filtered out; the marker is left out only if the
+                    // caller can't take two records at all
+                    match drain.peek(&consumer) {
+                        Some(record) if record.is_marker() && record.y == ADXL345_MARKER_CLIP => {
+                            let room = items * size - count;
+                            if room < (2 + limit_records) * size && count > 0 {
+                                break;
+                            }
+                            drain.pop(&consumer);
+                            let (acc, ns) = match drain.pop_stamped(&consumer) {
+                                Some(stamped) => stamped,
+                                None => break,
+                            };
+                            served += 1;
+                            if resampling {
+                                out.write(&record, &mut crc)?;
+                                Adxl345Stats::add(&ADXL345_STATS.markers, 1);
+                                count += size;
+                                data.resample.push(acc, snapshot.rate_mhz, &consumer);
+                                continue;
+                            }
+                            #[cfg(not(adxl345_no_filter))]
+                            let (acc, limit) = data.filter.run(&acc, &snapshot, true, &consumer).unwrap_or((acc, 0));
+                            #[cfg(adxl345_no_filter)]
+                            let limit = 0;
+                            if room >= 2 * size {
+                                out.write(&record, &mut crc)?;
+                                Adxl345Stats::add(&ADXL345_STATS.markers, 1);
+                                count += size;
+                            }
+                            if limit != 0 && room >= 3 * size {
+                                out.write(&Adxl345Sample::marker(ADXL345_MARKER_LIMIT, limit as i16), &mut crc)?;
+                                Adxl345Stats::add(&ADXL345_STATS.markers, 1);
+                                count += size;
+                            }
+                            out.write_stamped(&acc, ns, &mut crc)?;
+                            Adxl345Stats::add(&ADXL345_STATS.delivered, 1);
+                            count += size;
+                            continue;
+                        }
+                        // A range, tap or event marker stands alone
+                        Some(record) if record.is_marker() => {
+                            drain.pop(&consumer);
+                            out.write(&record, &mut crc)?;
+                            Adxl345Stats::add(&ADXL345_STATS.markers, 1);
+                            count += size;
+                            continue;
+                        }
+                        Some(_) if limit_records > 0 && items * size - count < 2 * size && count > 0 => break,
+                        Some(_) => {}
+                        None => break,
+                    }
+
+                    let (acc, ns) = match drain.pop_stamped(&consumer) {
+                        Some(stamped) => stamped,
+                        None => break,
+                    };
+                    served += 1;
+
+                    // A resampling file bypasses the filter, its output comes from `take()`
+                    if resampling {
+                        data.resample.push(acc, snapshot.rate_mhz, &consumer);
+                        continue;
+                    }
+
+                    // Run the filter pipeline, a sample it drops is counted as filtered
+                    #[cfg(not(adxl345_no_filter))]
+                    let acc = match data.filter.run(&acc, &snapshot, false, &consumer) {
+                        Some((acc, limit)) => {
+                            if limit != 0 && items * size - count >= 2 * size {
+                                out.write(&Adxl345Sample::marker(ADXL345_MARKER_LIMIT, limit as i16), &mut crc)?;
+                                Adxl345Stats::add(&ADXL345_STATS.markers, 1);
+                                count += size;
+                            }
+                            acc
+                        }
+                        None => {
+                            Adxl345Stats::add(&ADXL345_STATS.filtered, 1);
+                            continue;
+                        }
+                    };
+
+                    // Copy the sample into the user buffer, with the time it was read
+                    out.write_stamped(&acc, ns, &mut crc)?;
+                    Adxl345Stats::add(&ADXL345_STATS.delivered, 1);
+                    count += size;
                 }
-            
+                drop(held);
+                drop(consumer);
 
-                count += core::mem::size_of::<Adxl345Sample>();
+                // Everything buffered may have been filtered out, in that case wait for more
+                if count > 0 {
+                    break;
+                }
+            }
 
-                // Check if more data is ready
-                match adxl.data_ready() {
-                    Ok(ready) if ready == 0 => break,
-                    Ok(_) => continue,
-                    Err(_) => return Err(EIO),
+            // End the batch with the CRC of the records written before it
+            if let Some(batch) = crc.take() {
+                for record in batch.records().iter() {
+                    out.write(record, &mut crc)?;
                 }
+                count += crc_words * size;
             }
+            out.flush()?;
         }
 
         Ok(count)
     }
-    
+
+    /// Runs the control commands written to the file, only reachable with `write_control=1`
+    /// (see control.rs).
+    fn write(
+        data: <Self::Data as ForeignOwnable>::Borrowed<'_>,
+        _file: &File,
+        reader: &mut impl IoBufferReader,
+        _offset: u64,
+    ) -> Result<usize> {
+        adxl345_control_write(&data.context, reader)
+    }
+
+    /// Rejects any seek, samples are a stream and have no offset.
+    ///
+    /// `nonseekable_open` already makes the VFS fail lseek, pread and pwrite with ESPIPE, this
+    /// keeps the answer the same if the file is reached without going through open().
+    fn seek(
+        _data: <Self::Data as ForeignOwnable>::Borrowed<'_>,
+        _file: &File,
+        _offset: SeekFrom,
+    ) -> Result<u64> {
+        Err(ESPIPE)
+    }
+
+    /// Moves the samples still in the device into the kernel buffer before returning, so a
+    /// following read sees everything acquired up to now. Nothing is discarded.
+    fn fsync(
+        data: <Self::Data as ForeignOwnable>::Borrowed<'_>,
+        _file: &File,
+        _start: u64,
+        _end: u64,
+        _datasync: bool,
+    ) -> Result<u32> {
+        data.context.flush()?;
+        Ok(0)
+    }
+
+    /// Dispatches the ioctl commands to the handlers defined in ioctl.rs.
+    fn ioctl(
+        data: <Self::Data as ForeignOwnable>::Borrowed<'_>,
+        file: &File,
+        cmd: &mut IoctlCommand,
+    ) -> Result<i32> {
+        cmd.dispatch::<Self>(data, file)
+    }
+
+    /// Reports the file readable in the poll mode of the reader (see poll.rs), and writable if
+    /// it was opened for writing: control writes never block (see control.rs).
+    fn poll(
+        data: <Self::Data as ForeignOwnable>::Borrowed<'_>,
+        file: &File,
+        table: &PollTable,
+    ) -> Result<u32> {
+        // Registered first, so data arriving after the check below wakes up the poll again
+        // SAFETY: The wait queue is in the drain of the file, which the file holds until release.
+        unsafe { table.register_wait_queue(file, data.context.drain.wait_queue()) };
+
+        let writable = match file.flags() & O_ACCMODE {
+            O_RDONLY => 0,
+            _ => bindings::POLLOUT | bindings::POLLWRNORM,
+        };
+
+        // Remove shuts the drain of the file down, the device is gone for good then
+        let drain = &data.context.drain;
+        let mask = data.poll_mask(adxl345_would_not_block(drain), drain.is_removed());
+        Ok(mask | data.motion.poll_mask() | writable)
+    }
+
+    /// Adds the file to the SIGIO list when `O_ASYNC` is set, removes it when cleared (see
+    /// fasync.rs).
+    fn fasync(
+        _data: <Self::Data as ForeignOwnable>::Borrowed<'_>,
+        file: &File,
+        fd: i32,
+        on: bool,
+    ) -> Result<i32> {
+        ADXL345_FASYNC.update(fd, file, on)
+    }
+
 }
 
 /// Registers a character device for the ADXL345 accelerometer.
@@ -259,7 +566,7 @@ pub (crate) fn adxl345_chardev_add(
     // Create a new pinned `Registration` object for the character device
     let mut registration = Registration::new_pinned(name, minors_start, module)?;
     
-    registration.as_mut().register::<Adxl345FileOps>().expect("Registration failed");
+    registration.as_mut().register::<Adxl345FileOps>()?;
 
     Ok(registration)
 }
@@ -0,0 +1,338 @@
+/*
+ * Copyright 2024 Luca Saverio Esposito, Università di Roma, Tor Vergata
+ * email: <lucasaverioesposito@gmail.com>
+ *
+ * This file is part of an "Rust Linux driver for the ADXL345 device".
+ *
+ * This driver is free software: you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * This driver is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with Foobar.  If not, see <http://www.gnu.org/licenses/>.
+ */
+// filter.rs
+
+//! Read filter pipeline.
+//!
+//! `read()` passes every sample through an ordered list of stages before copying it out. A stage
+//! may change the sample, drop it or flag it; the list is set with `ADXL345_IOC_SET_PIPELINE`,
+//! for every reader, from the next sample read:
+//! - `ADXL345_STAGE_THRESHOLD`: drops a sample whose change from the previous one is within the
+//!   filter threshold on every axis, to skip insignificant movements and noise. The threshold is
+//!   part of the configuration snapshot (see snapshot.rs) and starts at the `filter_threshold`
+//!   module parameter (`ADXL345_FILTER` by default), it is changed with `ADXL345_IOC_SET_FILTER`,
+//!   0 delivering every sample that differs from the previous one. The parameter is unused.
+//! - `ADXL345_STAGE_AVERAGE`: replaces a sample with the mean of the last `param` samples it saw,
+//!   2 to 16, fewer until as many went through.
+//! - `ADXL345_STAGE_DECIMATE`: keeps the first sample of every `param`, 2 to 1000.
+//! - `ADXL345_STAGE_SCALE`: multiplies the sample by `param` / 1000, 1 to 100000, saturated to
+//!   the range of the record.
+//! - `ADXL345_STAGE_LIMIT`: flags a sample whose absolute value is at least `param` on an axis,
+//!   1 to 32767, with a limit marker before it (see fileops.rs).
+//!
+//! A list holds up to `ADXL345_PIPELINE_LEN` stages, each kind at most once. The default list is
+//! the threshold stage alone, the former read filter. A sample that comes with a clip marker (see
+//! clip.rs) is never dropped: the stages that drop samples only update their state with it.
+//!
+//! The state of the stages (the previous sample, the averaging window, the decimation count)
+//! belongs to the open file: readers sharing the stream each see the samples they read
+//! themselves. It starts over when the list changes. A new kind of stage takes a constant, its
+//! check in `adxl345_stage_check()`, its state in `Adxl345StageState` and its step in
+//! `Adxl345Pipeline::run()`.
+//!
+//! The pipeline is optional: building with `make ADXL345_NO_FILTER=1` leaves this module, its
+//! state, its statistics and its per-sample branch out of the driver, for minimal builds that
+//! filter in post-processing (e.g. data loggers). Every sample is then delivered, and the session
+//! header reports the threshold as -1.
+
+use kernel::prelude::*;
+use kernel::error::code::{EINVAL, ERANGE};
+use kernel::io_buffer::{ReadableFromBytes, WritableToBytes};
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicU32, Ordering};
+use crate::structures::Adxl345Sample;
+use crate::constant::ADXL345_FILTER_DEFAULT;
+use crate::snapshot::{Adxl345Snapshot, Adxl345SnapshotCell};
+use crate::drain::Adxl345Consumer;
+use crate::sample_math::{adxl345_axis_change, adxl345_decimate_next, adxl345_window_mean, adxl345_window_sum};
+
+/// Minimum change required to capture acceleration on any axis.
+/// This constant defines the threshold for filtering out small changes in acceleration
+/// to prevent capturing insignificant movements or noise.
+pub (crate) const ADXL345_FILTER: i16 = ADXL345_FILTER_DEFAULT as i16;
+
+/// Threshold the snapshots start from, set at load by the `filter_threshold` module parameter.
+static ADXL345_FILTER_INITIAL: AtomicU32 = AtomicU32::new(ADXL345_FILTER as u32);
+
+/// Sets the threshold the snapshots start from, called at load before any device is bound.
+///
+/// # Returns
+/// `Err(ERANGE)` above `i16::MAX`.
+pub (crate) fn adxl345_filter_initial_set(threshold: u32) -> Result {
+    i16::try_from(threshold).map_err(|_| ERANGE)?;
+    ADXL345_FILTER_INITIAL.store(threshold, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Returns the threshold the snapshots start from.
+pub (crate) fn adxl345_filter_initial() -> i16 {
+    ADXL345_FILTER_INITIAL.load(Ordering::Relaxed) as i16
+}
+
+/// Kinds of stages.
+pub (crate) const ADXL345_STAGE_THRESHOLD: u32 = 1;
+pub (crate) const ADXL345_STAGE_AVERAGE: u32 = 2;
+pub (crate) const ADXL345_STAGE_DECIMATE: u32 = 3;
+pub (crate) const ADXL345_STAGE_SCALE: u32 = 4;
+pub (crate) const ADXL345_STAGE_LIMIT: u32 = 5;
+
+/// Longest list of stages.
+pub (crate) const ADXL345_PIPELINE_LEN: usize = 8;
+
+/// Largest averaging window, decimation factor and scale, in thousandths.
+const ADXL345_AVERAGE_MAX: usize = 16;
+const ADXL345_DECIMATE_MAX: u32 = 1000;
+const ADXL345_SCALE_MAX: u32 = 100_000;
+
+/// A stage of `Adxl345PipelineArg`.
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub (crate) struct Adxl345StageArg {
+    pub (crate) kind: u32,      // ADXL345_STAGE_*
+    pub (crate) param: u32,     // Parameter of the stage, see above
+}
+
+/// Argument of `ADXL345_IOC_SET_PIPELINE` and `ADXL345_IOC_GET_PIPELINE`: the stages in the order
+/// they run, the ones past `len` are ignored.
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub (crate) struct Adxl345PipelineArg {
+    pub (crate) len: u32,
+    pub (crate) stages: [Adxl345StageArg; ADXL345_PIPELINE_LEN],
+}
+
+// SAFETY: Both types are `repr(C)`, made only of integers and have no padding, so any byte
+// pattern is a valid value.
+unsafe impl ReadableFromBytes for Adxl345PipelineArg {}
+unsafe impl WritableToBytes for Adxl345PipelineArg {}
+
+const ADXL345_STAGE_NONE: Adxl345StageArg = Adxl345StageArg { kind: 0, param: 0 };
+
+/// List of stages published in the configuration snapshot.
+#[derive(Copy, Clone)]
+pub (crate) struct Adxl345PipelineConfig {
+    pub (crate) arg: Adxl345PipelineArg,
+    generation: u32,    // Changes with the list, the files then reset their state
+}
+
+impl Adxl345PipelineConfig {
+    /// The threshold stage alone.
+    pub (crate) const DEFAULT: Self = {
+        let mut stages = [ADXL345_STAGE_NONE; ADXL345_PIPELINE_LEN];
+        stages[0] = Adxl345StageArg { kind: ADXL345_STAGE_THRESHOLD, param: 0 };
+        Self { arg: Adxl345PipelineArg { len: 1, stages }, generation: 0 }
+    };
+
+    /// Returns the stages in use.
+    fn stages(&self) -> &[Adxl345StageArg] {
+        &self.arg.stages[..self.arg.len as usize]
+    }
+
+    /// Returns true if a sample may come out with a limit marker.
+    pub (crate) fn flags_limit(&self) -> bool {
+        self.stages().iter().any(|stage| stage.kind == ADXL345_STAGE_LIMIT)
+    }
+}
+
+/// Checks a stage of a new list.
+///
+/// # Returns
+/// `Err(EINVAL)` for an unknown kind or a parameter out of its range.
+fn adxl345_stage_check(stage: &Adxl345StageArg) -> Result {
+    let valid = match stage.kind {
+        ADXL345_STAGE_THRESHOLD => true,
+        ADXL345_STAGE_AVERAGE => (2..=ADXL345_AVERAGE_MAX as u32).contains(&stage.param),
+        ADXL345_STAGE_DECIMATE => (2..=ADXL345_DECIMATE_MAX).contains(&stage.param),
+        ADXL345_STAGE_SCALE => (1..=ADXL345_SCALE_MAX).contains(&stage.param),
+        ADXL345_STAGE_LIMIT => (1..=i16::MAX as u32).contains(&stage.param),
+        _ => false,
+    };
+    if !valid {
+        return Err(EINVAL);
+    }
+    Ok(())
+}
+
+/// Sets the list of stages in `snapshot`, the one of a device, used from the next sample read.
+///
+/// # Returns
+/// - `Ok(())` once the list is published.
+/// - `Err(EINVAL)` if it is longer than `ADXL345_PIPELINE_LEN`, holds a kind twice or a stage
+///   fails its check.
+pub (crate) fn adxl345_pipeline_set(snapshot: &Adxl345SnapshotCell, mut arg: Adxl345PipelineArg) -> Result {
+    let len = arg.len as usize;
+    if len > ADXL345_PIPELINE_LEN {
+        return Err(EINVAL);
+    }
+    for (index, stage) in arg.stages[..len].iter().enumerate() {
+        adxl345_stage_check(stage)?;
+        if arg.stages[..index].iter().any(|other| other.kind == stage.kind) {
+            return Err(EINVAL);
+        }
+    }
+    arg.stages[len..].fill(ADXL345_STAGE_NONE);
+    snapshot.update(|snapshot| {
+        let generation = snapshot.pipeline.generation.wrapping_add(1);
+        snapshot.pipeline = Adxl345PipelineConfig { arg, generation };
+    })
+}
+
+/// Sets the threshold of the threshold stage in `snapshot`, the one of a device, used from the
+/// next sample read.
+///
+/// # Returns
+/// - `Ok(())` once the threshold is published.
+/// - `Err(ERANGE)` above `i16::MAX`.
+pub (crate) fn adxl345_filter_set(snapshot: &Adxl345SnapshotCell, threshold: u32) -> Result {
+    let threshold = i16::try_from(threshold).map_err(|_| ERANGE)?;
+    snapshot.update(|snapshot| snapshot.filter = threshold)
+}
+
+/// State of a stage, for one file.
+#[derive(Copy, Clone)]
+enum Adxl345StageState {
+    Threshold { last: [i16; 3] },
+    Average { window: [[i16; 3]; ADXL345_AVERAGE_MAX], sum: [i32; 3], next: usize, filled: usize },
+    Decimate { seen: u32 },
+    Scale,
+    Limit,
+}
+
+impl Adxl345StageState {
+    /// Returns the initial state of `stage`, the first sample is compared with a zero sample.
+    fn new(stage: &Adxl345StageArg) -> Self {
+        match stage.kind {
+            ADXL345_STAGE_THRESHOLD => Adxl345StageState::Threshold { last: [0; 3] },
+            ADXL345_STAGE_AVERAGE => Adxl345StageState::Average {
+                window: [[0; 3]; ADXL345_AVERAGE_MAX],
+                sum: [0; 3],
+                next: 0,
+                filled: 0,
+            },
+            ADXL345_STAGE_DECIMATE => Adxl345StageState::Decimate { seen: 0 },
+            ADXL345_STAGE_SCALE => Adxl345StageState::Scale,
+            _ => Adxl345StageState::Limit,
+        }
+    }
+}
+
+/// State of the stages of a file.
+struct Adxl345PipelineState {
+    generation: u32,
+    stages: [Adxl345StageState; ADXL345_PIPELINE_LEN],
+}
+
+/// Pipeline state of an open file.
+///
+/// It is only touched by a read holding the consumer lock of the drain, which serializes every
+/// reader.
+pub (crate) struct Adxl345Pipeline {
+    state: UnsafeCell<Adxl345PipelineState>,
+}
+
+// SAFETY: `state` is only accessed with the consumer lock held, as proven by the guard
+// `Adxl345Pipeline::run()` takes.
+unsafe impl Sync for Adxl345Pipeline {}
+
+impl Adxl345Pipeline {
+    /// Creates the state of a new file, set up for the list at its first read.
+    pub (crate) const fn new() -> Self {
+        Self {
+            state: UnsafeCell::new(Adxl345PipelineState {
+                generation: u32::MAX,
+                stages: [Adxl345StageState::Scale; ADXL345_PIPELINE_LEN],
+            }),
+        }
+    }
+
+    /// Runs the stages of the snapshot on `sample`. With `keep` set the sample is never dropped,
+    /// for a sample that comes with a clip marker.
+    ///
+    /// # Returns
+    /// - `Some((Adxl345Sample, u8))` with the sample to deliver and the axes flagged by the limit
+    ///   stage (bit 0 x, bit 1 y, bit 2 z).
+    /// - `None` if a stage dropped it.
+    pub (crate) fn run(
+        &self,
+        sample: &Adxl345Sample,
+        snapshot: &Adxl345Snapshot,
+        keep: bool,
+        _consumer: &Adxl345Consumer<'_>,
+    ) -> Option<(Adxl345Sample, u8)> {
+        // SAFETY: The consumer lock is held, as proven by the guard.
+        let state = unsafe { &mut *self.state.get() };
+        let config = &snapshot.pipeline;
+        if state.generation != config.generation {
+            for (stage, stage_state) in config.stages().iter().zip(state.stages.iter_mut()) {
+                *stage_state = Adxl345StageState::new(stage);
+            }
+            state.generation = config.generation;
+        }
+
+        let mut axes = [sample.x, sample.y, sample.z];
+        let mut limit = 0;
+        for (stage, stage_state) in config.stages().iter().zip(state.stages.iter_mut()) {
+            let kept = match stage_state {
+                Adxl345StageState::Threshold { last } => {
+                    // Kept if the change is above the threshold on any axis
+                    let filter = snapshot.filter as i32;
+                    let moved = (0..3).any(|axis| adxl345_axis_change(axes[axis], last[axis]) > filter);
+                    *last = axes;
+                    moved
+                }
+                Adxl345StageState::Average { window, sum, next, filled } => {
+                    let len = stage.param as usize;
+                    for axis in 0..3 {
+                        sum[axis] = adxl345_window_sum(sum[axis], axes[axis], window[*next][axis]);
+                        window[*next][axis] = axes[axis];
+                    }
+                    *next = (*next + 1) % len;
+                    *filled = (*filled + 1).min(len);
+                    axes = [0, 1, 2].map(|axis| adxl345_window_mean(sum[axis], *filled));
+                    true
+                }
+                Adxl345StageState::Decimate { seen } => {
+                    let first = *seen == 0;
+                    *seen = adxl345_decimate_next(*seen, stage.param);
+                    first
+                }
+                Adxl345StageState::Scale => {
+                    axes = axes.map(|value| {
+                        let scaled = value as i64 * stage.param as i64 / 1000;
+                        scaled.clamp(i16::MIN as i64, i16::MAX as i64) as i16
+                    });
+                    true
+                }
+                Adxl345StageState::Limit => {
+                    for (axis, value) in axes.iter().enumerate() {
+                        if (*value as i32).abs() >= stage.param as i32 {
+                            limit |= 1 << axis;
+                        }
+                    }
+                    true
+                }
+            };
+            if !kept && !keep {
+                return None;
+            }
+        }
+        Some((Adxl345Sample::new(axes[0], axes[1], axes[2]), limit))
+    }
+}
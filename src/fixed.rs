@@ -0,0 +1,179 @@
+/*
+ * Copyright 2024 Luca Saverio Esposito, Università di Roma, Tor Vergata
+ * email: <lucasaverioesposito@gmail.com>
+ *
+ * This file is part of an "Rust Linux driver for the ADXL345 device".
+ *
+ * This driver is free software: you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * This driver is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with Foobar.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+
+// fixed.rs
+
+//! Fixed-point math shared by the features that compute on samples.
+//!
+//! The kernel has no floating point, so magnitudes, RMS values, unit conversions and angles are
+//! computed in integers. The helpers live here, each with its accuracy, instead of being written
+//! again in every feature:
+//! - `adxl345_isqrt()`: square root of a `u64`, exact (the floor of the real root).
+//! - `adxl345_round_div()`: division rounded to the nearest integer, half away from zero.
+//! - `adxl345_mul_q()`: product of two fixed-point values with `frac` fractional bits, rounded to
+//!   the nearest, half up.
+//! - `adxl345_units_to_mg()`: shifted counts (see regmap.rs) to mg at `ADXL345_UG_PER_UNIT`,
+//!   truncated toward zero.
+//! - `adxl345_magnitude_mg()`: magnitude of a sample in mg, within 1 mg of the real magnitude of
+//!   the converted axes.
+//! - `adxl345_atan2_mdeg()`: angle of a vector in millidegrees, in (-180000, 180000], within
+//!   1 millidegree of the real angle over the whole `i32` range.
+//!
+//! Every helper is a `const fn` checked at build time by the assertions below each of them, on
+//! the host compiler as on the kernel one: a change breaking a bound fails the build.
+
+use crate::structures::Adxl345Sample;
+
+/// Returns the square root of `value`, rounded down.
+pub (crate) const fn adxl345_isqrt(value: u64) -> u64 {
+    let mut root = 0u64;
+    let mut bit = 1u64 << 62;
+    let mut rest = value;
+    while bit > value {
+        bit >>= 2;
+    }
+    while bit != 0 {
+        if rest >= root + bit {
+            rest -= root + bit;
+            root = (root >> 1) + bit;
+        } else {
+            root >>= 1;
+        }
+        bit >>= 2;
+    }
+    root
+}
+
+// The ends of the type, a perfect square and its neighbours
+const _: () = assert!(adxl345_isqrt(0) == 0);
+const _: () = assert!(adxl345_isqrt(1) == 1);
+const _: () = assert!(adxl345_isqrt(1_000_000) == 1000);
+const _: () = assert!(adxl345_isqrt(999_999) == 999);
+const _: () = assert!(adxl345_isqrt(1_002_000) == 1000);
+const _: () = assert!(adxl345_isqrt(u64::MAX) == u32::MAX as u64);
+
+/// Divides `n` by `d`, rounding half away from zero. `d` must be positive.
+pub (crate) const fn adxl345_round_div(n: i64, d: i64) -> i64 {
+    if n < 0 { (n - d / 2) / d } else { (n + d / 2) / d }
+}
+
+const _: () = assert!(adxl345_round_div(5, 2) == 3);
+const _: () = assert!(adxl345_round_div(-5, 2) == -3);
+const _: () = assert!(adxl345_round_div(7, 3) == 2);
+const _: () = assert!(adxl345_round_div(-7, 3) == -2);
+
+/// Multiplies two fixed-point values with `frac` fractional bits (1 to 62), rounding half up.
+/// The product must fit an `i64` before the shift.
+pub (crate) const fn adxl345_mul_q(a: i64, b: i64, frac: u32) -> i64 {
+    (a * b + (1 << (frac - 1))) >> frac
+}
+
+// Q16: 1.5 * 2.5, -1.5 * 2.5, and half an LSB rounding up
+const _: () = assert!(adxl345_mul_q(3 << 15, 5 << 15, 16) == 15 << 14);
+const _: () = assert!(adxl345_mul_q(-(3 << 15), 5 << 15, 16) == -(15 << 14));
+const _: () = assert!(adxl345_mul_q(1, 1 << 15, 16) == 1);
+
+/// Scale of a shifted count, in µg: the typical 3.9 mg per LSB of the datasheet in full
+/// resolution, shifted by 2 (see regmap.rs). Every conversion of samples to units uses it, as
+/// libadxl345 does with `ADXL345_MG_PER_UNIT`.
+pub (crate) const ADXL345_UG_PER_UNIT: i64 = 975;
+
+/// Converts shifted counts to mg, truncated toward zero.
+pub (crate) const fn adxl345_units_to_mg(units: i64) -> i64 {
+    units * ADXL345_UG_PER_UNIT / 1000
+}
+
+const _: () = assert!(adxl345_units_to_mg(1024) == 998);
+const _: () = assert!(adxl345_units_to_mg(-16384) == -15974);
+
+/// Returns the magnitude of `sample` in mg, each axis converted with `adxl345_units_to_mg()`.
+pub (crate) const fn adxl345_magnitude_mg(sample: &Adxl345Sample) -> u32 {
+    let axes = [sample.x, sample.y, sample.z];
+    let mut sum = 0u64;
+    let mut axis = 0;
+    while axis < 3 {
+        let mg = adxl345_units_to_mg(axes[axis] as i64);
+        sum += (mg * mg) as u64;
+        axis += 1;
+    }
+    adxl345_isqrt(sum) as u32
+}
+
+// 1 g on one axis, and the largest magnitude a sample can have
+const _: () = assert!(adxl345_magnitude_mg(&Adxl345Sample::new(0, 0, 1026)) == 1000);
+const _: () = assert!(adxl345_magnitude_mg(&Adxl345Sample::new(-32768, -32768, -32768)) == 55335);
+
+/// Number of CORDIC iterations of `adxl345_atan2_mdeg()`.
+const ADXL345_CORDIC_STEPS: usize = 24;
+
+/// `atan(2^-i)` in microdegrees, the rotations of the CORDIC iterations.
+const ADXL345_CORDIC_ANGLES_UDEG: [i64; ADXL345_CORDIC_STEPS] = [
+    45000000, 26565051, 14036243, 7125016, 3576334, 1789911, 895174, 447614,
+    223811, 111906, 55953, 27976, 13988, 6994, 3497, 1749,
+    874, 437, 219, 109, 55, 27, 14, 7,
+];
+
+/// Returns the angle of the vector (`x`, `y`) from the x axis, in millidegrees, in
+/// (-180000, 180000]; 0 for the null vector.
+///
+/// The vector is brought in the right half plane, then rotated onto the x axis by CORDIC
+/// iterations in 16 more bits than the inputs, summing the rotations in microdegrees: the result
+/// is within 1 millidegree of the real angle.
+pub (crate) const fn adxl345_atan2_mdeg(y: i32, x: i32) -> i32 {
+    if x == 0 {
+        return if y > 0 { 90_000 } else if y < 0 { -90_000 } else { 0 };
+    }
+    let mut x = (x as i64) << 16;
+    let mut y = (y as i64) << 16;
+    let mut angle = 0i64;
+    if x < 0 {
+        angle = if y >= 0 { 180_000_000 } else { -180_000_000 };
+        x = -x;
+        y = -y;
+    }
+    let mut step = 0;
+    while step < ADXL345_CORDIC_STEPS && y != 0 {
+        let (dx, dy) = (y >> step, x >> step);
+        if y > 0 {
+            x += dx;
+            y -= dy;
+            angle += ADXL345_CORDIC_ANGLES_UDEG[step];
+        } else {
+            x -= dx;
+            y += dy;
+            angle -= ADXL345_CORDIC_ANGLES_UDEG[step];
+        }
+        step += 1;
+    }
+    adxl345_round_div(angle, 1000) as i32
+}
+
+// The axes, the diagonals, 30 degrees and the ends of the type
+const _: () = assert!(adxl345_atan2_mdeg(0, 1) == 0);
+const _: () = assert!(adxl345_atan2_mdeg(1, 0) == 90_000);
+const _: () = assert!(adxl345_atan2_mdeg(0, -1) == 180_000);
+const _: () = assert!(adxl345_atan2_mdeg(-1, 0) == -90_000);
+const _: () = assert!(adxl345_atan2_mdeg(1, 1) == 45_000);
+const _: () = assert!(adxl345_atan2_mdeg(-1, -1) == -135_000);
+const _: () = assert!(adxl345_atan2_mdeg(1, -1) == 135_000);
+const _: () = assert!((adxl345_atan2_mdeg(500_000, 866_025) - 30_000).abs() <= 1);
+const _: () = assert!(adxl345_atan2_mdeg(i32::MIN, i32::MIN) == -135_000);
+const _: () = assert!(adxl345_atan2_mdeg(i32::MAX, i32::MIN) == 135_000);
@@ -0,0 +1,143 @@
+/*
+ * Copyright 2024 Luca Saverio Esposito, Università di Roma, Tor Vergata
+ * email: <lucasaverioesposito@gmail.com>
+ *
+ * This file is part of an "Rust Linux driver for the ADXL345 device".
+ *
+ * This driver is free software: you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * This driver is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with Foobar.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+
+
+
+
+// gravity_watch.rs
+
+//! Gravity plausibility watchdog.
+//!
+//! At rest the sensor measures gravity only, so the magnitude of the low-passed acceleration must
+//! stay close to 1 g. When userspace declares the system stationary (`gravity_watch` in debugfs),
+//! the drain checks every sample: a deviation beyond `gravity_tolerance_mg` lasting
+//! `gravity_hold_ms` raises an alarm, the sensor was detached, is saturated or is failing. The
+//! alarm is reported with a `gravity` uevent and counted, and cleared with a `gravity_ok` uevent
+//! once the magnitude is back within half the tolerance.
+//!
+//! Each device has its own watchdog, whose knobs are in the debugfs directory of the device. Only
+//! the drain of the device, serialized by the device lock, feeds it, so plain atomics with
+//! relaxed ordering are enough.
+
+use kernel::time::ktime_get_ns;
+use core::sync::atomic::{AtomicBool, AtomicI32, AtomicU32, AtomicU64, Ordering};
+use crate::fixed::{adxl345_isqrt, adxl345_units_to_mg};
+use crate::structures::Adxl345Sample;
+use crate::uevent::Adxl345Event;
+use crate::instance::ADXL345_DEVICES_MAX;
+
+/// Weight of a new sample in the low-pass filter, as a shift: 1/16.
+const ADXL345_GRAVITY_SHIFT: u32 = 4;
+
+/// Watchdog state and knobs, the knobs are debugfs files.
+pub (crate) struct Adxl345GravityWatch {
+    pub (crate) enabled: AtomicBool,        // Set by userspace while the system is stationary
+    pub (crate) tolerance_mg: AtomicU32,    // Largest deviation from 1 g
+    pub (crate) hold_ms: AtomicU32,         // How long a deviation must last to raise the alarm
+    pub (crate) magnitude_mg: AtomicU32,    // Last low-passed magnitude
+    pub (crate) alarms: AtomicU64,          // Alarms raised
+    filtered: [AtomicI32; 3],               // Low-passed axes, in shifted LSBs << ADXL345_GRAVITY_SHIFT
+    primed: AtomicBool,                     // The filter holds a sample
+    deviating_since: AtomicU64,             // Start of the current deviation, 0 if none
+    alarmed: AtomicBool,                    // An alarm is raised
+    reported: AtomicBool,                   // Alarm state last reported with a uevent
+}
+
+/// Watchdogs of the devices, indexed by id.
+static ADXL345_GRAVITY_WATCHES: [Adxl345GravityWatch; ADXL345_DEVICES_MAX] =
+    [Adxl345GravityWatch::EMPTY; ADXL345_DEVICES_MAX];
+
+/// Returns the watchdog of device `id`.
+pub (crate) fn adxl345_gravity_watch(id: usize) -> &'static Adxl345GravityWatch {
+    &ADXL345_GRAVITY_WATCHES[id]
+}
+
+impl Adxl345GravityWatch {
+    /// A disabled watchdog, to initialize the array of watchdogs.
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY: Self = Self {
+        enabled: AtomicBool::new(false),
+        tolerance_mg: AtomicU32::new(150),
+        hold_ms: AtomicU32::new(1000),
+        magnitude_mg: AtomicU32::new(0),
+        alarms: AtomicU64::new(0),
+        filtered: [AtomicI32::new(0), AtomicI32::new(0), AtomicI32::new(0)],
+        primed: AtomicBool::new(false),
+        deviating_since: AtomicU64::new(0),
+        alarmed: AtomicBool::new(false),
+        reported: AtomicBool::new(false),
+    };
+
+    /// Feeds a sample drained from the device, with the device lock held.
+    pub (crate) fn push(&self, sample: &Adxl345Sample) {
+        if !self.enabled.load(Ordering::Relaxed) {
+            // Start from scratch when enabled again, the system may have moved meanwhile
+            self.primed.store(false, Ordering::Relaxed);
+            self.deviating_since.store(0, Ordering::Relaxed);
+            self.alarmed.store(false, Ordering::Relaxed);
+            return;
+        }
+
+        let axes = [sample.x as i32, sample.y as i32, sample.z as i32];
+        let primed = self.primed.swap(true, Ordering::Relaxed);
+        let mut magnitude_sq = 0u64;
+        for axis in 0..3 {
+            let new = axes[axis] << ADXL345_GRAVITY_SHIFT;
+            let old = self.filtered[axis].load(Ordering::Relaxed);
+            let filtered = if primed { old + ((new - old) >> ADXL345_GRAVITY_SHIFT) } else { new };
+            self.filtered[axis].store(filtered, Ordering::Relaxed);
+
+            let mg = adxl345_units_to_mg((filtered >> ADXL345_GRAVITY_SHIFT) as i64);
+            magnitude_sq += (mg * mg) as u64;
+        }
+        let magnitude = adxl345_isqrt(magnitude_sq) as i64;
+        self.magnitude_mg.store(magnitude as u32, Ordering::Relaxed);
+
+        let tolerance = self.tolerance_mg.load(Ordering::Relaxed) as i64;
+        let deviation = (magnitude - 1000).abs();
+        if deviation > tolerance {
+            let now = ktime_get_ns().max(1);
+            let since = self.deviating_since.load(Ordering::Relaxed);
+            if since == 0 {
+                self.deviating_since.store(now, Ordering::Relaxed);
+            } else if !self.alarmed.load(Ordering::Relaxed)
+                && now - since >= self.hold_ms.load(Ordering::Relaxed) as u64 * 1_000_000
+            {
+                self.alarmed.store(true, Ordering::Relaxed);
+                self.alarms.fetch_add(1, Ordering::Relaxed);
+            }
+        } else if deviation <= tolerance / 2 {
+            self.deviating_since.store(0, Ordering::Relaxed);
+            self.alarmed.store(false, Ordering::Relaxed);
+        }
+    }
+
+    /// Returns the event to report if the alarm was raised or cleared since the last call.
+    ///
+    /// Called by the drain work item, which sends the event outside of the device lock.
+    pub (crate) fn take_change(&self) -> Option<Adxl345Event> {
+        let alarmed = self.alarmed.load(Ordering::Relaxed);
+        if self.reported.swap(alarmed, Ordering::Relaxed) == alarmed {
+            return None;
+        }
+        Some(if alarmed { Adxl345Event::Gravity } else { Adxl345Event::GravityOk })
+    }
+}
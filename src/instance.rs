@@ -0,0 +1,340 @@
+/*
+ * Copyright 2024 Luca Saverio Esposito, Università di Roma, Tor Vergata
+ * email: <lucasaverioesposito@gmail.com>
+ *
+ * This file is part of an "Rust Linux driver for the ADXL345 device".
+ *
+ * This driver is free software: you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * This driver is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with Foobar.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+
+// instance.rs
+
+//! Instantiation of the devices.
+//!
+//! The driver handles up to `ADXL345_DEVICES_MAX` ADXL345s at once, e.g. two on the same bus at
+//! both addresses selectable with the ALT ADDRESS pin. The instance of a device is the driver
+//! state built for it when it is bound, whose probe brings the device up:
+//! - on I2C, by the driver registered at module init (see adxl345_core.rs). It binds the clients
+//!   created by the I2C core from the device tree nodes compatible with `adi,adxl345`, and the
+//!   clients created here on a bus and address: at module init on the `i2c_bus` module parameter,
+//!   at runtime from configfs (see configfs.rs) or by a bus scan (see scan.rs), for boards that
+//!   don't describe the devices;
+//! - on SPI, by the SPI driver registered with the `spi` parameter (see spi.rs).
+//!
+//! A bound device takes the first free slot, whose index is its id for as long as it is bound:
+//! the minor of its character device (see fileops.rs), its context (see context.rs) and its
+//! configuration snapshot (see snapshot.rs), and the state of its features: the session header,
+//! the sync input, tap, motion, burst and power modes, event correlation, auto-ranging, the alarm
+//! and gravity watch, the register shadow and the saved offsets, each kept in an array indexed
+//! by id. The counters and knobs of those features are in the debugfs directory of the id (see
+//! debugfs.rs), and the `data_gpio` and `alarm_gpio` module parameters take a line per id. The
+//! device in slot `ADXL345_DEVICE_PRIMARY` is the one the debugfs entries without a device
+//! directory act on, e.g. `noise_run`.
+//!
+//! The state is built on the bus of the device (see structures/bus.rs), and dropped once the
+//! device is removed. Destroying a client created here unregisters it, which removes the device.
+
+use kernel::prelude::*;
+use kernel::bindings;
+use kernel::device::RawDevice;
+use kernel::error::code::{EBUSY, EINVAL, ENODEV, ENXIO};
+use kernel::i2c::*;
+use kernel::mutex_init;
+use kernel::sync::{smutex, Arc, Mutex};
+use crate::constant::DR_NAME;
+use crate::structures::{Adxl345, Adxl345Bus, Adxl345Driver};
+use crate::fileops::ADXL345_MODULE;
+
+/// Number of devices handled at once.
+pub (crate) const ADXL345_DEVICES_MAX: usize = 4;
+
+/// Slot of the primary device, the one the debugfs entries shared by the devices act on.
+pub (crate) const ADXL345_DEVICE_PRIMARY: usize = 0;
+
+/// Detection function of the scan, called with the adapter and the address to check, returning 1
+/// when an ADXL345 answers there (see scan.rs).
+pub (crate) type Adxl345DetectFn = unsafe extern "C" fn(*mut bindings::i2c_adapter, u16) -> core::ffi::c_int;
+
+/// A client created on a bus and address, with them.
+struct Adxl345Instance {
+    _client: I2CClient,
+    bus: i32,
+    addr: u16,
+}
+
+/// Lock serializing the creation and destruction of the clients, initialized at module init.
+///
+/// It is never taken with `ADXL345_CONFIG_LOCK` held: remove takes that lock.
+pub (crate) static mut ADXL345_INSTANCE_LOCK: Mutex<()> = unsafe { Mutex::new(()) };
+
+const ADXL345_NO_INSTANCE: Option<Adxl345Instance> = None;
+
+/// The clients created here, protected by `ADXL345_INSTANCE_LOCK`. Each one is bound, so there
+/// are no more of them than slots.
+static mut ADXL345_INSTANCES: [Option<Adxl345Instance>; ADXL345_DEVICES_MAX] = [ADXL345_NO_INSTANCE; ADXL345_DEVICES_MAX];
+
+/// A slot, holding the state of the device bound in it.
+enum Adxl345Slot {
+    /// No device.
+    Free,
+    /// Taken by a device being probed, outside of the lock.
+    Probing,
+    /// The state of a probed device.
+    Bound(Pin<Box<Adxl345Driver>>),
+}
+
+impl Adxl345Slot {
+    /// Returns the state of the device bound in the slot, if any.
+    fn driver(&self) -> Option<&Pin<Box<Adxl345Driver>>> {
+        match self {
+            Adxl345Slot::Bound(driver) => Some(driver),
+            _ => None,
+        }
+    }
+
+    /// Frees the slot, returning the state of the device bound in it.
+    fn take(&mut self) -> Option<Pin<Box<Adxl345Driver>>> {
+        match core::mem::replace(self, Adxl345Slot::Free) {
+            Adxl345Slot::Bound(driver) => Some(driver),
+            slot => {
+                *self = slot;
+                None
+            }
+        }
+    }
+}
+
+const ADXL345_SLOT_FREE: Adxl345Slot = Adxl345Slot::Free;
+
+/// The states of the bound devices, whatever their bus, indexed by id.
+///
+/// Binding a client created here happens while it is created, so this lock is taken within
+/// `ADXL345_INSTANCE_LOCK`, never the other way around. It is only held to take, fill or free a
+/// slot: the probe of a device runs outside of it, on a slot marked `Probing`.
+static ADXL345_BOUND: smutex::Mutex<[Adxl345Slot; ADXL345_DEVICES_MAX]> =
+    smutex::Mutex::new([ADXL345_SLOT_FREE; ADXL345_DEVICES_MAX]);
+
+/// Returns the slot of the device bound on the bus device `dev`, if any.
+fn adxl345_slot_of(slots: &[Adxl345Slot], dev: *mut bindings::device) -> Option<usize> {
+    slots.iter().position(|slot| {
+        slot.driver().map_or(false, |driver| driver.device().lock().bus().device().raw_device() == dev)
+    })
+}
+
+/// Builds the driver state on `bus` in the first free slot and brings the device up, from the
+/// probe of the driver of its bus.
+///
+/// # Returns
+/// - `Ok(*mut Adxl345Driver)` once the device is up, valid until `adxl345_release()`.
+/// - `Err(EBUSY)` if every slot holds a device already.
+/// - `Err(Error)` if the device can't be set up.
+pub (crate) fn adxl345_bind(bus: Box<dyn Adxl345Bus>) -> Result<*mut Adxl345Driver> {
+    let module = unsafe { ADXL345_MODULE }.ok_or(EINVAL)?;
+    let id = {
+        let mut bound = ADXL345_BOUND.lock();
+        let id = match bound.iter().position(|slot| matches!(slot, Adxl345Slot::Free)) {
+            Some(id) => id,
+            None => {
+                pr_warn!("No free slot, {} devices are bound already\n", ADXL345_DEVICES_MAX);
+                return Err(EBUSY);
+            }
+        };
+        bound[id] = Adxl345Slot::Probing;
+        id
+    };
+
+    let result = adxl345_bind_probe(bus, id, module);
+    let mut bound = ADXL345_BOUND.lock();
+    match result {
+        Ok(mut adxl345driver) => {
+            // SAFETY: The driver state is not moved out of the box, which stays in `ADXL345_BOUND`
+            // until it is released.
+            let instance = unsafe { adxl345driver.as_mut().get_unchecked_mut() } as *mut Adxl345Driver;
+            bound[id] = Adxl345Slot::Bound(adxl345driver);
+            Ok(instance)
+        }
+        Err(e) => {
+            bound[id] = Adxl345Slot::Free;
+            Err(e)
+        }
+    }
+}
+
+/// Builds the driver state on `bus` with id `id`, whose slot is taken, and probes the device.
+fn adxl345_bind_probe(bus: Box<dyn Adxl345Bus>, id: usize, module: &'static ThisModule) -> Result<Pin<Box<Adxl345Driver>>> {
+    // A sleeping lock, the register transfers made under it sleep on I2C and SPI
+    let mut locked_adxl345 = unsafe{Mutex::new(Adxl345::new(bus, id))};
+
+    // Init the mutex
+    mutex_init!(unsafe { Pin::new_unchecked(&mut locked_adxl345)}, "adxl345");
+
+    // Create the shared `Adxl345` instance wrapped in an `Arc`
+    let device = Arc::try_new(locked_adxl345)?;
+
+    // Pin ensure that the driver doesn't move, this constraint is mandatory due the
+    // necessity of retrieving driver with i2c_get_clientdata.
+    let adxl345driver = Pin::from(Box::try_new(Adxl345Driver::new(device, module))?);
+    adxl345driver.probe_device()?;
+    Ok(adxl345driver)
+}
+
+/// Drops the driver state of the device bound on the bus device `dev`, once it is removed.
+pub (crate) fn adxl345_release(dev: *mut bindings::device) {
+    let driver = {
+        let mut bound = ADXL345_BOUND.lock();
+        adxl345_slot_of(&*bound, dev).and_then(|id| bound[id].take())
+    };
+    drop(driver);
+}
+
+/// Removes the device bound on the bus device `dev` and drops its driver state, from the remove
+/// of a driver whose core doesn't hold the state (see spi.rs).
+pub (crate) fn adxl345_unbind(dev: *mut bindings::device) {
+    let driver = {
+        let mut bound = ADXL345_BOUND.lock();
+        adxl345_slot_of(&*bound, dev).and_then(|id| bound[id].take())
+    };
+    if let Some(driver) = driver {
+        driver.remove_device();
+    }
+}
+
+/// Returns true if a device is bound.
+pub (crate) fn adxl345_bound() -> bool {
+    ADXL345_BOUND.lock().iter().any(|slot| slot.driver().is_some())
+}
+
+/// Returns the state of the device bound on the bus device `dev`, for the callbacks of a driver
+/// whose core doesn't hold it (see spi.rs).
+pub (crate) fn adxl345_bound_device(dev: *mut bindings::device) -> Option<Arc<Mutex<Adxl345>>> {
+    let bound = ADXL345_BOUND.lock();
+    adxl345_slot_of(&*bound, dev).and_then(|id| bound[id].driver()).map(|driver| driver.device().clone())
+}
+
+/// Creates the client at `addr` on I2C bus `bus`, which the driver registered at module init
+/// binds at once.
+///
+/// # Returns
+/// - `Ok(())` once the device is bound.
+/// - `Err(EBUSY)` if this client exists already, or every slot holds a device.
+/// - `Err(ENODEV)` if the client was created but its probe failed, it is deleted again.
+/// - `Err(Error)` if the adapter or the client can't be set up.
+pub (crate) fn adxl345_instance_create(bus: i32, addr: u16) -> Result {
+    adxl345_instance_add(bus, addr, I2CClient::new_client_device)
+}
+
+/// Creates the client at `addr` on I2C bus `bus` if `detect` finds an ADXL345 there, as the
+/// detect() flow of a driver: the address is skipped without any transfer if another client
+/// uses it (see `I2CClient::new_scanned_device`).
+///
+/// # Returns
+/// As `adxl345_instance_create()`, and `Err(ENXIO)` if no device is detected or the address is
+/// used by another client.
+pub (crate) fn adxl345_instance_detect(bus: i32, addr: u16, detect: Adxl345DetectFn) -> Result {
+    adxl345_instance_add(bus, addr, |adapter, board_info| {
+        let addr_list = [addr, bindings::I2C_CLIENT_END as u16];
+        I2CClient::new_scanned_device(adapter, board_info, &addr_list, Some(detect))
+            .map_err(|e| if e == ENODEV { ENXIO } else { e })
+    })
+}
+
+/// Creates the client of an instance at `addr` on I2C bus `bus` with `new_client`, and keeps it
+/// once the device is bound.
+fn adxl345_instance_add(
+    bus: i32,
+    addr: u16,
+    new_client: impl FnOnce(&I2CAdapter, &I2CBoardInfo) -> Result<I2CClient>,
+) -> Result {
+    // SAFETY: The lock is initialized at module init.
+    let _instance = unsafe { ADXL345_INSTANCE_LOCK.lock() };
+    // SAFETY: The instance lock is held.
+    let instances = unsafe { &mut ADXL345_INSTANCES };
+    if instances.iter().flatten().any(|instance| instance.bus == bus && instance.addr == addr) {
+        return Err(EBUSY);
+    }
+    let free = instances.iter().position(|instance| instance.is_none()).ok_or(EBUSY)?;
+    if ADXL345_BOUND.lock().iter().all(|slot| !matches!(slot, Adxl345Slot::Free)) {
+        return Err(EBUSY);
+    }
+
+    // Initialize I2C adapter and create a new device
+    let i2c_adapter = I2CAdapter::get_from_bus_number(bus)?;
+
+    // This i2c_client instance is owned by Rust subsystem, so will be dropped
+    // automatically when the instance is destroyed by the drop trait of I2CClient struct.
+    let board_info = I2CBoardInfo::new(DR_NAME, addr);
+    let client = new_client(&i2c_adapter, &board_info)?;
+
+    // The probe ran while the client was added, a failed one leaves it unbound
+    if adxl345_bound_device(client.raw_device()).is_none() {
+        return Err(ENODEV);
+    }
+    pr_info!("ADXL345 instantiated at 0x{:02x} on I2C bus {}\n", addr, bus);
+
+    instances[free] = Some(Adxl345Instance { _client: client, bus, addr });
+    Ok(())
+}
+
+/// Destroys the client created here at `addr` on I2C bus `bus`, if any: unregistering it
+/// removes the device.
+///
+/// # Returns
+/// `true` if there was such a client.
+pub (crate) fn adxl345_instance_destroy(bus: i32, addr: u16) -> bool {
+    // SAFETY: The lock is initialized at module init.
+    let _instance = unsafe { ADXL345_INSTANCE_LOCK.lock() };
+    // SAFETY: The instance lock is held.
+    let instances = unsafe { &mut ADXL345_INSTANCES };
+    let instance = instances
+        .iter_mut()
+        .find(|instance| matches!(instance, Some(i) if i.bus == bus && i.addr == addr))
+        .and_then(|instance| instance.take());
+    match instance {
+        Some(instance) => {
+            // The i2c client is unregistered by its own trait
+            drop(instance);
+            pr_info!("ADXL345 at 0x{:02x} on I2C bus {} destroyed\n", addr, bus);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Destroys every client created here, at module exit.
+pub (crate) fn adxl345_instance_destroy_all() {
+    for slot in 0..ADXL345_DEVICES_MAX {
+        // SAFETY: The lock is initialized at module init, and the slot is read under it.
+        let location = {
+            let _instance = unsafe { ADXL345_INSTANCE_LOCK.lock() };
+            unsafe { ADXL345_INSTANCES[slot].as_ref() }.map(|instance| (instance.bus, instance.addr))
+        };
+        if let Some((bus, addr)) = location {
+            adxl345_instance_destroy(bus, addr);
+        }
+    }
+}
+
+/// Returns true if a device is bound at `addr` on I2C bus `bus`, whoever created its client:
+/// this module, the I2C core from the device tree, or user space through `new_device`.
+pub (crate) fn adxl345_bound_at(bus: i32, addr: u16) -> bool {
+    ADXL345_BOUND.lock().iter().filter_map(Adxl345Slot::driver).any(|driver| {
+        driver.device().lock().bus().i2c_client().map_or(false, |client| {
+            let client = client.as_ptr();
+            // SAFETY: The client is bound, so it and its adapter stay valid while its slot is
+            // held, which the lock guarantees.
+            unsafe { (*client).addr == addr && (*(*client).adapter).nr == bus }
+        })
+    })
+}
@@ -0,0 +1,693 @@
+/*
+ * Copyright 2024 Luca Saverio Esposito, Università di Roma, Tor Vergata
+ * email: <lucasaverioesposito@gmail.com>
+ *
+ * This file is part of an "Rust Linux driver for the ADXL345 device".
+ *
+ * This driver is free software: you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * This driver is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with Foobar.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+
+// ioctl.rs
+
+use kernel::prelude::*;
+use kernel::file::{File, IoctlHandler};
+use kernel::user_ptr::{UserSlicePtr, UserSlicePtrReader, UserSlicePtrWriter};
+use kernel::io_buffer::{IoBufferReader, IoBufferWriter};
+use kernel::error::code::{EINVAL, ENOTTY};
+use kernel::time::ClockId;
+#[cfg(adxl345_rt_mutex)]
+use kernel::sync::RtMutex;
+#[cfg(not(adxl345_rt_mutex))]
+use kernel::sync::Mutex;
+use crate::fileops::{Adxl345FileOps, adxl345_readable_bytes};
+use crate::context::Adxl345Context;
+use crate::config::{Adxl345Param, Adxl345ParamArg};
+use crate::snapshot::adxl345_snapshot_refresh;
+#[cfg(not(adxl345_no_filter))]
+use crate::filter::{Adxl345PipelineArg, adxl345_filter_set, adxl345_pipeline_set};
+use crate::utility::{adxl345_stream_start, adxl345_stream_stop};
+use crate::session::{adxl345_header, adxl345_session};
+use crate::auto_range::adxl345_auto_range;
+use crate::preset::{Adxl345PresetName, adxl345_preset_apply, adxl345_preset_delete, adxl345_preset_save};
+use crate::sync_input::{Adxl345SyncInfo, adxl345_sync, adxl345_sync_attach, adxl345_sync_detached};
+use crate::batch_crc::ADXL345_BATCH_CRC;
+use crate::poll::Adxl345Reader;
+use crate::output::adxl345_record_size;
+use crate::version::Adxl345Version;
+use crate::capabilities::adxl345_caps_of;
+use crate::fasync::adxl345_sigio_set_threshold;
+use crate::tap::{Adxl345TapArg, adxl345_tap, adxl345_tap_set};
+use crate::burst::{Adxl345BurstArg, adxl345_burst};
+use crate::drain::{Adxl345BufferArg, Adxl345Drain};
+use crate::concurrency::ADXL345_CONCURRENCY;
+use crate::motion::{Adxl345MotionArg, Adxl345MotionEvent, adxl345_motion, adxl345_motion_set};
+use crate::power::{Adxl345PowerArg, adxl345_power, adxl345_power_set};
+use crate::correlation::{Adxl345EventInfo, adxl345_correlation};
+use crate::calibration::{Adxl345CalibrateArg, Adxl345OffsetArg, adxl345_calibration, adxl345_calibrate};
+use core::sync::atomic::Ordering;
+
+/// Lock serializing the configuration changes, so a change and the snapshot publication that
+/// follows it are atomic with respect to other changes.
+///
+/// Built as an `rt_mutex` with `make ADXL345_RT_MUTEX=1`: on PREEMPT_RT a low priority task
+/// changing the configuration is then boosted while a high priority one waits for it.
+#[cfg(adxl345_rt_mutex)]
+pub (crate) type Adxl345ConfigLock = RtMutex<()>;
+#[cfg(not(adxl345_rt_mutex))]
+pub (crate) type Adxl345ConfigLock = Mutex<()>;
+
+/// The configuration lock, initialized once at module init.
+pub (crate) static mut ADXL345_CONFIG_LOCK: Adxl345ConfigLock = unsafe { Adxl345ConfigLock::new(()) };
+
+/// Standard ioctl returning the number of bytes that can be read without blocking, as an `int`.
+/// Its value is the asm-generic one, used by ARM and x86.
+pub (crate) const FIONREAD: u32 = 0x541B;
+
+/// Magic number shared by all the ADXL345 ioctl commands.
+const ADXL345_IOC_MAGIC: u32 = b'A' as u32;
+
+// Direction bits, as defined in include/uapi/asm-generic/ioctl.h
+const IOC_NONE: u32 = 0;
+const IOC_WRITE: u32 = 1;
+const IOC_READ: u32 = 2;
+const IOC_READ_WRITE: u32 = IOC_READ | IOC_WRITE;
+
+/// Builds an ioctl command number, equivalent to the `_IOC` C macro.
+const fn ioc(dir: u32, nr: u32, size: usize) -> u32 {
+    (dir << 30) | ((size as u32) << 16) | (ADXL345_IOC_MAGIC << 8) | nr
+}
+
+/// Equivalent to the `_IO` C macro for the ADXL345 magic number.
+const fn io(nr: u32) -> u32 {
+    ioc(IOC_NONE, nr, 0)
+}
+
+/// Equivalent to the `_IOW` C macro for the ADXL345 magic number.
+const fn iow<T>(nr: u32) -> u32 {
+    ioc(IOC_WRITE, nr, core::mem::size_of::<T>())
+}
+
+/// Equivalent to the `_IOR` C macro for the ADXL345 magic number.
+const fn ior<T>(nr: u32) -> u32 {
+    ioc(IOC_READ, nr, core::mem::size_of::<T>())
+}
+
+/// Equivalent to the `_IOWR` C macro for the ADXL345 magic number.
+const fn iowr<T>(nr: u32) -> u32 {
+    ioc(IOC_READ_WRITE, nr, core::mem::size_of::<T>())
+}
+
+/// Selects the clock used for sample and event timestamps.
+/// The argument is a `u32` holding a `CLOCK_*` id (REALTIME, MONOTONIC or BOOTTIME).
+pub (crate) const ADXL345_IOC_SET_CLOCK: u32 = iow::<u32>(0x01);
+
+/// Returns the `CLOCK_*` id of the clock currently used for timestamps.
+pub (crate) const ADXL345_IOC_GET_CLOCK: u32 = ior::<u32>(0x02);
+
+/// Attaches a GPIO line as external sync input (rising edge).
+/// The argument is a `u32` holding the GPIO number, `u32::MAX` detaches the current input.
+pub (crate) const ADXL345_IOC_SET_SYNC: u32 = iow::<u32>(0x03);
+
+/// Returns an `Adxl345SyncInfo` describing the last sync pulse.
+pub (crate) const ADXL345_IOC_GET_SYNC: u32 = ior::<Adxl345SyncInfo>(0x04);
+
+/// Sets a configuration parameter, the argument is an `Adxl345ParamArg`.
+/// Out-of-range values fail with ERANGE, the reason is shown in debugfs (`config_error`). The
+/// watermark goes from 1 to 31 entries, the samples field of FIFO_CTL being 5 bits wide.
+pub (crate) const ADXL345_IOC_SET_PARAM: u32 = iow::<Adxl345ParamArg>(0x05);
+
+/// Reads a configuration parameter, user space fills `param` and the driver fills `value`.
+pub (crate) const ADXL345_IOC_GET_PARAM: u32 = iowr::<Adxl345ParamArg>(0x06);
+
+/// Sets a configuration parameter in human units: thresholds in mg, durations in µs.
+/// The value is rounded to the register resolution and the achieved value is returned
+/// in the same `Adxl345ParamArg`. Durations are in µs rather than ms because DUR has a
+/// resolution of 625 µs and LATENT and WINDOW of 1.25 ms, which whole milliseconds can't
+/// express. Values above the one of 255 LSB fail with ERANGE, e.g. 15938 mg for thresholds;
+/// the sysfs attributes of the same names take the same units and bounds.
+pub (crate) const ADXL345_IOC_SET_PARAM_SCALED: u32 = iowr::<Adxl345ParamArg>(0x07);
+
+/// Reads a configuration parameter in human units: thresholds in mg, durations in µs.
+pub (crate) const ADXL345_IOC_GET_PARAM_SCALED: u32 = iowr::<Adxl345ParamArg>(0x08);
+
+/// Discards the samples buffered in the kernel and in the device, so a new measurement run
+/// doesn't start with stale data. It takes no argument.
+pub (crate) const ADXL345_IOC_FLUSH: u32 = io(0x09);
+
+/// Starts a measurement session: enables measurement mode and drains the device into an emptied
+/// kernel buffer. It takes no argument and does nothing if a session is running.
+/// open() starts a session too, so this is only needed after `ADXL345_IOC_STOP`.
+/// If enabled with `ADXL345_IOC_SET_HEADER`, the session starts with a header (see session.rs).
+pub (crate) const ADXL345_IOC_START: u32 = io(0x0A);
+
+/// Stops the measurement session, the device goes in standby and keeps its configuration.
+/// It takes no argument, the samples already buffered can still be read.
+pub (crate) const ADXL345_IOC_STOP: u32 = io(0x0B);
+
+/// Enables the session header emitted by `ADXL345_IOC_START`.
+/// The argument is a `u32`, 1 enables the header and 0 disables it.
+pub (crate) const ADXL345_IOC_SET_HEADER: u32 = iow::<u32>(0x0C);
+
+/// Enables auto-ranging (see auto_range.rs).
+/// The argument is a `u32`, 1 enables it and 0 disables it, keeping the range in use.
+pub (crate) const ADXL345_IOC_SET_AUTO_RANGE: u32 = iow::<u32>(0x0D);
+
+/// Saves the configuration in use as a named preset (see preset.rs), replacing the preset with
+/// the same name. The argument is an `Adxl345PresetName`, ENOSPC means no more presets fit.
+pub (crate) const ADXL345_IOC_PRESET_SAVE: u32 = iow::<Adxl345PresetName>(0x0E);
+
+/// Applies a named preset, the argument is an `Adxl345PresetName`.
+/// It fails with ENOENT if there is no such preset.
+pub (crate) const ADXL345_IOC_PRESET_APPLY: u32 = iow::<Adxl345PresetName>(0x0F);
+
+/// Deletes a named preset, the argument is an `Adxl345PresetName`.
+pub (crate) const ADXL345_IOC_PRESET_DELETE: u32 = iow::<Adxl345PresetName>(0x10);
+
+/// Enables the CRC appended to every read (see batch_crc.rs), for every reader.
+/// The argument is a `u32`, 1 enables it and 0 disables it.
+pub (crate) const ADXL345_IOC_SET_CRC: u32 = iow::<u32>(0x11);
+
+/// Selects the poll semantics of the open file (see poll.rs), the other files keep theirs.
+/// The argument is a `u32`, 0 for level (the default) and 1 for edge.
+pub (crate) const ADXL345_IOC_SET_POLL_MODE: u32 = iow::<u32>(0x12);
+
+/// Returns the versions of the driver and of its ABI (see version.rs), as an `Adxl345Version`.
+pub (crate) const ADXL345_IOC_GET_VERSION: u32 = ior::<Adxl345Version>(0x13);
+
+/// Returns the features of this build of the driver available on the device of the file (see
+/// capabilities.rs), as a `u64` bitmask.
+pub (crate) const ADXL345_IOC_GET_CAPS: u32 = ior::<u64>(0x14);
+
+/// Sets the buffered records needed for a data SIGIO (see fasync.rs), for every file.
+/// The argument is a `u32` from 1 to the size of the buffer, ERANGE otherwise.
+pub (crate) const ADXL345_IOC_SET_SIGIO_THRESHOLD: u32 = iow::<u32>(0x15);
+
+/// Selects what a read of the open file does on a bus error (see error_policy.rs), the other
+/// files keep theirs. The argument is a `u32`, 0 for fail-fast (the default) and 1 for
+/// best-effort.
+pub (crate) const ADXL345_IOC_SET_ERROR_POLICY: u32 = iow::<u32>(0x16);
+
+/// Sets the threshold of the threshold stage of the read filter (see filter.rs), for every
+/// reader of the device.
+/// The argument is a `u32` up to 32767, ERANGE otherwise. A driver built without the filter
+/// fails it with ENOTTY.
+pub (crate) const ADXL345_IOC_SET_FILTER: u32 = iow::<u32>(0x17);
+
+/// Returns the threshold of the read filter, as a `u32`.
+pub (crate) const ADXL345_IOC_GET_FILTER: u32 = ior::<u32>(0x18);
+
+/// Resamples the samples of the open file to a fixed output rate (see resample.rs), the other
+/// files keep the device rate. The argument is a `u32` in mHz up to 3200000, ERANGE otherwise,
+/// 0 turns it off.
+pub (crate) const ADXL345_IOC_SET_RESAMPLE: u32 = iow::<u32>(0x19);
+
+/// Selects the tap events reported in the stream and the axes taking part (see tap.rs), for
+/// every reader. The argument is an `Adxl345TapArg`, EINVAL for unknown bits or events without
+/// any axis.
+pub (crate) const ADXL345_IOC_SET_TAP: u32 = iow::<Adxl345TapArg>(0x1A);
+
+/// Returns the tap events reported and the axes taking part, as an `Adxl345TapArg`.
+pub (crate) const ADXL345_IOC_GET_TAP: u32 = ior::<Adxl345TapArg>(0x1B);
+
+/// Sets the schedule of burst sampling (see burst.rs), for every reader: the device measures
+/// `length_ms` every `period_ms` and sleeps in between. The argument is an `Adxl345BurstArg`,
+/// ERANGE for a period above a day, EINVAL for a burst as long as its period. A length of 0
+/// measures continuously.
+pub (crate) const ADXL345_IOC_SET_BURST: u32 = iow::<Adxl345BurstArg>(0x1C);
+
+/// Returns the schedule of burst sampling, as an `Adxl345BurstArg`.
+pub (crate) const ADXL345_IOC_GET_BURST: u32 = ior::<Adxl345BurstArg>(0x1D);
+
+/// Arms the activity, inactivity and free-fall events (see motion.rs) and sets ACT_INACT_CTL,
+/// for every reader. The argument is an `Adxl345MotionArg`, EINVAL for unknown events or an
+/// ACT_INACT_CTL value above 0xFF.
+pub (crate) const ADXL345_IOC_SET_MOTION: u32 = iow::<Adxl345MotionArg>(0x1E);
+
+/// Returns the armed motion events and ACT_INACT_CTL, as an `Adxl345MotionArg`.
+pub (crate) const ADXL345_IOC_GET_MOTION: u32 = ior::<Adxl345MotionArg>(0x1F);
+
+/// Returns the last motion event, as an `Adxl345MotionEvent`, and clears `POLLPRI` for the open
+/// file until the next one.
+pub (crate) const ADXL345_IOC_GET_MOTION_EVENT: u32 = ior::<Adxl345MotionEvent>(0x20);
+
+/// Sets the low power mode and auto sleep of the device (see power.rs), for every reader. The
+/// argument is an `Adxl345PowerArg`, EINVAL for unknown modes or a sleep rate other than 8, 4, 2
+/// or 1 Hz.
+pub (crate) const ADXL345_IOC_SET_POWER: u32 = iow::<Adxl345PowerArg>(0x21);
+
+/// Returns the power modes and the sleep rate, as an `Adxl345PowerArg`.
+pub (crate) const ADXL345_IOC_GET_POWER: u32 = ior::<Adxl345PowerArg>(0x22);
+
+/// Returns the events of an event marker (see correlation.rs). The argument is an
+/// `Adxl345EventInfo` whose ID is given, ENOENT once the marker is no longer in the log.
+pub (crate) const ADXL345_IOC_GET_EVENT: u32 = iowr::<Adxl345EventInfo>(0x23);
+
+/// Calibrates the offsets with the device held still (see calibration.rs). The argument is an
+/// `Adxl345CalibrateArg`: the number of samples is given, the offsets written are returned.
+/// EBUSY while a session runs.
+pub (crate) const ADXL345_IOC_CALIBRATE: u32 = iowr::<Adxl345CalibrateArg>(0x24);
+
+/// Writes the OFSX, OFSY and OFSZ registers, as an `Adxl345OffsetArg` of values from -128 to 127
+/// (15.6 mg per unit). They are kept across a rebind of the device.
+pub (crate) const ADXL345_IOC_SET_OFFSETS: u32 = iow::<Adxl345OffsetArg>(0x25);
+
+/// Reads the OFSX, OFSY and OFSZ registers, as an `Adxl345OffsetArg`.
+pub (crate) const ADXL345_IOC_GET_OFFSETS: u32 = ior::<Adxl345OffsetArg>(0x26);
+
+/// Sets the stages of the read filter (see filter.rs), for every reader of the device. The
+/// argument is an `Adxl345PipelineArg`, EINVAL for an unknown stage, a parameter out of range or
+/// a stage given twice. A driver built without the filter fails it with ENOTTY.
+#[cfg(not(adxl345_no_filter))]
+pub (crate) const ADXL345_IOC_SET_PIPELINE: u32 = iow::<Adxl345PipelineArg>(0x27);
+
+/// Returns the stages of the read filter, as an `Adxl345PipelineArg`.
+#[cfg(not(adxl345_no_filter))]
+pub (crate) const ADXL345_IOC_GET_PIPELINE: u32 = ior::<Adxl345PipelineArg>(0x28);
+
+/// Selects the format of the records read from the open file (see output.rs), the other files
+/// keep theirs. The argument is a `u32`, 0 for raw (the default), 1 for µg, 2 for µm/s² and 3 for
+/// raw records stamped with the time they were read.
+pub (crate) const ADXL345_IOC_SET_OUTPUT: u32 = iow::<u32>(0x29);
+
+/// Returns the format of the records read from the open file, as a `u32`.
+pub (crate) const ADXL345_IOC_GET_OUTPUT: u32 = ior::<u32>(0x2A);
+
+/// Sets the capacity of the kernel buffer (see drain.rs), for every reader of the device. The
+/// argument is a `u32` from 64 to 1024 samples, EINVAL otherwise.
+pub (crate) const ADXL345_IOC_SET_BUFFER: u32 = iow::<u32>(0x2B);
+
+/// Returns the capacity of the kernel buffer, the samples it holds and the overruns, as an
+/// `Adxl345BufferArg`.
+pub (crate) const ADXL345_IOC_GET_BUFFER: u32 = ior::<Adxl345BufferArg>(0x2C);
+
+/// Sets the output data rate, the argument is a `u32` in mHz (100 to 3200000, doubling from
+/// 100), ERANGE otherwise. The same as `ADXL345_IOC_SET_PARAM` with the rate.
+pub (crate) const ADXL345_IOC_SET_RATE: u32 = iow::<u32>(0x2D);
+
+/// Returns the output data rate, as a `u32` in mHz.
+pub (crate) const ADXL345_IOC_GET_RATE: u32 = ior::<u32>(0x2E);
+
+/// Sets the measurement range, the argument is a `u32` in g (2, 4, 8 or 16), ERANGE otherwise.
+/// The same as `ADXL345_IOC_SET_PARAM` with the range.
+pub (crate) const ADXL345_IOC_SET_RANGE: u32 = iow::<u32>(0x2F);
+
+/// Returns the measurement range, as a `u32` in g.
+pub (crate) const ADXL345_IOC_GET_RANGE: u32 = ior::<u32>(0x30);
+
+/// Starts or stops the measurement session of the device of `context`, as `ADXL345_IOC_START`
+/// and `ADXL345_IOC_STOP`.
+pub (crate) fn adxl345_session_control(context: &Adxl345Context, start: bool) -> Result {
+    // SAFETY: The lock is initialized at module init.
+    let _config = unsafe { ADXL345_CONFIG_LOCK.lock() };
+    let device = context.device()?.clone();
+    let drain = &context.drain;
+    if !start {
+        adxl345_stream_stop(device, drain);
+        return Ok(());
+    }
+
+    // The drain is stopped, so once the old samples are discarded the header is
+    // followed only by samples of the new session
+    let session = adxl345_session(drain.id());
+    if !drain.is_running() && session.header_enabled() {
+        let clock = device.lock().clock();
+        let consumer = drain.consumer();
+        drain.clear(&consumer);
+        session.arm(adxl345_header(drain.id(), clock));
+    }
+    adxl345_stream_start(device, drain).map_err(|e| {
+        session.cancel();
+        e
+    })
+}
+
+impl IoctlHandler for Adxl345FileOps {
+    type Target<'a> = &'a Adxl345Reader;
+
+    /// Handles the commands without a typed argument, like the standard `FIONREAD`,
+    /// `ADXL345_IOC_FLUSH` and the session control.
+    fn pure(this: Self::Target<'_>, _file: &File, cmd: u32, arg: usize) -> Result<i32> {
+        match cmd {
+            FIONREAD => {
+                let size = adxl345_record_size(this.output.get());
+                let bytes = adxl345_readable_bytes(&this.context.drain, size) as core::ffi::c_int;
+                // SAFETY: The pointer is checked when the data is copied to user space.
+                let mut writer = unsafe {
+                    UserSlicePtr::new(arg as _, core::mem::size_of::<core::ffi::c_int>())
+                }
+                .writer();
+                writer.write(&bytes)?;
+                Ok(0)
+            }
+            ADXL345_IOC_FLUSH => {
+                let discarded = this.context.discard()?;
+                pr_debug!("Flushed {} samples\n", discarded);
+                Ok(0)
+            }
+            ADXL345_IOC_START | ADXL345_IOC_STOP => {
+                adxl345_session_control(&this.context, cmd == ADXL345_IOC_START)?;
+                Ok(0)
+            }
+            _ => Err(ENOTTY),
+        }
+    }
+
+    /// Handles the `_IOW` commands, where user space provides the argument.
+    fn write(
+        this: Self::Target<'_>,
+        _file: &File,
+        cmd: u32,
+        reader: &mut UserSlicePtrReader,
+    ) -> Result<i32> {
+        // The poll mode, the error policy, the resampling and the output format belong to the
+        // file, neither the device nor the lock is needed
+        if cmd == ADXL345_IOC_SET_POLL_MODE {
+            this.set_poll_mode(reader.read()?)?;
+            return Ok(0);
+        }
+        if cmd == ADXL345_IOC_SET_ERROR_POLICY {
+            this.errors.set(reader.read()?)?;
+            return Ok(0);
+        }
+        if cmd == ADXL345_IOC_SET_RESAMPLE {
+            this.resample.set_rate(reader.read()?)?;
+            return Ok(0);
+        }
+        if cmd == ADXL345_IOC_SET_OUTPUT {
+            this.output.set(reader.read()?)?;
+            return Ok(0);
+        }
+
+        // The SIGIO threshold is global state of its own, as the list of signalled files
+        if cmd == ADXL345_IOC_SET_SIGIO_THRESHOLD {
+            adxl345_sigio_set_threshold(reader.read()?)?;
+            return Ok(0);
+        }
+
+        // SAFETY: The lock is initialized at module init.
+        let _config = unsafe { ADXL345_CONFIG_LOCK.lock() };
+        let _held = ADXL345_CONCURRENCY.config.hold();
+
+        // Remove tears the device down under the lock, nothing may be attached to it afterwards
+        let device = this.context.device()?;
+
+        match cmd {
+            ADXL345_IOC_SET_CLOCK => {
+                let raw: u32 = reader.read()?;
+                let clock = ClockId::from_raw(raw).ok_or(EINVAL)?;
+                device.lock().set_clock(clock);
+                pr_info!("Timestamp clock set to {:?}\n", clock);
+                Ok(0)
+            }
+            ADXL345_IOC_SET_SYNC => {
+                let gpio: u32 = reader.read()?;
+
+                // The interrupt is freed and requested outside of the device lock, both may sleep
+                // and the drain would wait for them
+                let (old, clock) = {
+                    let mut adxl = device.lock();
+                    (adxl.sync_irq.take(), adxl.clock())
+                };
+                drop(old);
+                adxl345_sync_detached(this.context.drain.id());
+
+                if gpio != u32::MAX {
+                    let registration = adxl345_sync_attach(&this.context.drain, gpio, clock)?;
+                    device.lock().sync_irq = Some(registration);
+                    pr_info!("GPIO {} attached as sync input\n", gpio);
+                }
+                Ok(0)
+            }
+            ADXL345_IOC_SET_HEADER => {
+                let enabled: u32 = reader.read()?;
+                match enabled {
+                    0 => adxl345_session(this.context.drain.id()).set_header(false),
+                    1 => adxl345_session(this.context.drain.id()).set_header(true),
+                    _ => return Err(EINVAL),
+                }
+                Ok(0)
+            }
+            ADXL345_IOC_SET_AUTO_RANGE => {
+                let enabled: u32 = reader.read()?;
+                match enabled {
+                    0 => adxl345_auto_range(this.context.drain.id()).set_enabled(false),
+                    1 => adxl345_auto_range(this.context.drain.id()).set_enabled(true),
+                    _ => return Err(EINVAL),
+                }
+                Ok(0)
+            }
+            ADXL345_IOC_SET_CRC => {
+                let enabled: u32 = reader.read()?;
+                match enabled {
+                    0 => ADXL345_BATCH_CRC.store(false, Ordering::Relaxed),
+                    1 => ADXL345_BATCH_CRC.store(true, Ordering::Relaxed),
+                    _ => return Err(EINVAL),
+                }
+                Ok(0)
+            }
+            ADXL345_IOC_SET_TAP => {
+                adxl345_tap_set(device, reader.read()?)?;
+                Ok(0)
+            }
+            ADXL345_IOC_SET_MOTION => {
+                adxl345_motion_set(device, reader.read()?)?;
+                Ok(0)
+            }
+            ADXL345_IOC_SET_POWER => {
+                adxl345_power_set(device, reader.read()?)?;
+                Ok(0)
+            }
+            ADXL345_IOC_SET_OFFSETS => {
+                let offsets = reader.read()?;
+                let adxl = device.lock();
+                adxl345_calibration(adxl.id()).set(&adxl, offsets)?;
+                Ok(0)
+            }
+            ADXL345_IOC_SET_BUFFER => {
+                this.context.drain.set_capacity(reader.read()?)?;
+                Ok(0)
+            }
+            ADXL345_IOC_SET_BURST => {
+                let arg: Adxl345BurstArg = reader.read()?;
+
+                // The schedule changes with the drain stopped, the buffered samples are kept
+                let drain = &this.context.drain;
+                let running = drain.is_running();
+                drain.stop();
+                let ret = adxl345_burst(drain.id()).set(arg);
+                if running {
+                    // A device left in standby between two bursts measures again
+                    let _ = device.lock().enable_measure();
+                    Adxl345Drain::resume(drain);
+                }
+                ret?;
+                Ok(0)
+            }
+            #[cfg(not(adxl345_no_filter))]
+            ADXL345_IOC_SET_PIPELINE => {
+                adxl345_pipeline_set(this.context.drain.snapshot(), reader.read()?)?;
+                Ok(0)
+            }
+            #[cfg(not(adxl345_no_filter))]
+            ADXL345_IOC_SET_FILTER => {
+                adxl345_filter_set(this.context.drain.snapshot(), reader.read()?)?;
+                Ok(0)
+            }
+            ADXL345_IOC_SET_PARAM => {
+                let arg: Adxl345ParamArg = reader.read()?;
+                let param = Adxl345Param::from_raw(arg.param)?;
+                device.lock().set_param(param, arg.value)?;
+                if param == Adxl345Param::Rate || param == Adxl345Param::Range {
+                    adxl345_snapshot_refresh(device)?;
+                }
+                Ok(0)
+            }
+            ADXL345_IOC_SET_RATE | ADXL345_IOC_SET_RANGE => {
+                let param = if cmd == ADXL345_IOC_SET_RATE { Adxl345Param::Rate } else { Adxl345Param::Range };
+                device.lock().set_param(param, reader.read()?)?;
+                adxl345_snapshot_refresh(device)?;
+                Ok(0)
+            }
+            ADXL345_IOC_PRESET_SAVE | ADXL345_IOC_PRESET_APPLY | ADXL345_IOC_PRESET_DELETE => {
+                let name = reader.read::<Adxl345PresetName>()?.validate()?;
+                // SAFETY: The configuration lock is held above.
+                unsafe {
+                    match cmd {
+                        ADXL345_IOC_PRESET_SAVE => adxl345_preset_save(device, name)?,
+                        ADXL345_IOC_PRESET_APPLY => adxl345_preset_apply(device, name)?,
+                        _ => adxl345_preset_delete(name)?,
+                    }
+                }
+                Ok(0)
+            }
+            _ => Err(ENOTTY),
+        }
+    }
+
+    /// Handles the `_IOR` commands, where the driver returns a value to user space.
+    fn read(
+        this: Self::Target<'_>,
+        _file: &File,
+        cmd: u32,
+        writer: &mut UserSlicePtrWriter,
+    ) -> Result<i32> {
+        // The versions, the capabilities, the last motion event and the output format are known
+        // without a device
+        match cmd {
+            ADXL345_IOC_GET_VERSION => {
+                writer.write(&Adxl345Version::current())?;
+                return Ok(0);
+            }
+            ADXL345_IOC_GET_CAPS => {
+                writer.write(&adxl345_caps_of(this.context.drain.id()))?;
+                return Ok(0);
+            }
+            ADXL345_IOC_GET_MOTION_EVENT => {
+                writer.write(&this.motion.fetch())?;
+                return Ok(0);
+            }
+            ADXL345_IOC_GET_OUTPUT => {
+                writer.write(&this.output.get())?;
+                return Ok(0);
+            }
+            _ => {}
+        }
+
+        let device = this.context.device()?;
+        let id = this.context.drain.id();
+
+        match cmd {
+            ADXL345_IOC_GET_CLOCK => {
+                let raw = device.lock().clock().as_raw();
+                writer.write(&raw)?;
+                Ok(0)
+            }
+            ADXL345_IOC_GET_RATE => {
+                writer.write(&device.lock().get_param(Adxl345Param::Rate)?)?;
+                Ok(0)
+            }
+            ADXL345_IOC_GET_RANGE => {
+                writer.write(&device.lock().get_param(Adxl345Param::Range)?)?;
+                Ok(0)
+            }
+            ADXL345_IOC_GET_SYNC => {
+                writer.write(&adxl345_sync(id).info())?;
+                Ok(0)
+            }
+            ADXL345_IOC_GET_TAP => {
+                writer.write(&adxl345_tap(id).get())?;
+                Ok(0)
+            }
+            ADXL345_IOC_GET_BURST => {
+                writer.write(&adxl345_burst(id).get())?;
+                Ok(0)
+            }
+            ADXL345_IOC_GET_MOTION => {
+                writer.write(&adxl345_motion(id).get())?;
+                Ok(0)
+            }
+            ADXL345_IOC_GET_POWER => {
+                writer.write(&adxl345_power(id).get())?;
+                Ok(0)
+            }
+            ADXL345_IOC_GET_OFFSETS => {
+                writer.write(&adxl345_calibration(id).get(&device.lock())?)?;
+                Ok(0)
+            }
+            ADXL345_IOC_GET_BUFFER => {
+                writer.write(&this.context.drain.buffer_info())?;
+                Ok(0)
+            }
+            #[cfg(not(adxl345_no_filter))]
+            ADXL345_IOC_GET_FILTER => {
+                writer.write(&(this.context.drain.snapshot().get().filter as u32))?;
+                Ok(0)
+            }
+            #[cfg(not(adxl345_no_filter))]
+            ADXL345_IOC_GET_PIPELINE => {
+                writer.write(&this.context.drain.snapshot().get().pipeline.arg)?;
+                Ok(0)
+            }
+            _ => Err(ENOTTY),
+        }
+    }
+
+    /// Handles the `_IOWR` commands, where user space provides an argument and the driver
+    /// fills in the result.
+    fn read_write(
+        this: Self::Target<'_>,
+        _file: &File,
+        cmd: u32,
+        data: UserSlicePtr,
+    ) -> Result<i32> {
+        let (mut reader, mut writer) = data.reader_writer();
+
+        // The commands changing the configuration take the lock first and resolve the device
+        // under it, as `write` does: remove tears the device down under the lock
+        match cmd {
+            ADXL345_IOC_SET_PARAM_SCALED => {
+                // SAFETY: The lock is initialized at module init.
+                let _config = unsafe { ADXL345_CONFIG_LOCK.lock() };
+                let _held = ADXL345_CONCURRENCY.config.hold();
+                let device = this.context.device()?;
+                let mut arg: Adxl345ParamArg = reader.read()?;
+                let param = Adxl345Param::from_raw(arg.param)?;
+                arg.value = device.lock().set_param_scaled(param, arg.value)?;
+                if param == Adxl345Param::Rate || param == Adxl345Param::Range {
+                    adxl345_snapshot_refresh(device)?;
+                }
+                writer.write(&arg)?;
+                return Ok(0);
+            }
+            ADXL345_IOC_CALIBRATE => {
+                // SAFETY: The lock is initialized at module init.
+                let _config = unsafe { ADXL345_CONFIG_LOCK.lock() };
+                let _held = ADXL345_CONCURRENCY.config.hold();
+                let device = this.context.device()?;
+                let mut arg: Adxl345CalibrateArg = reader.read()?;
+                arg.offsets = adxl345_calibrate(device, &this.context.drain, arg.samples)?;
+                writer.write(&arg)?;
+                return Ok(0);
+            }
+            _ => {}
+        }
+
+        let device = this.context.device()?;
+
+        match cmd {
+            ADXL345_IOC_GET_PARAM => {
+                let mut arg: Adxl345ParamArg = reader.read()?;
+                let param = Adxl345Param::from_raw(arg.param)?;
+                arg.value = device.lock().get_param(param)?;
+                writer.write(&arg)?;
+                Ok(0)
+            }
+            ADXL345_IOC_GET_PARAM_SCALED => {
+                let mut arg: Adxl345ParamArg = reader.read()?;
+                let param = Adxl345Param::from_raw(arg.param)?;
+                arg.value = device.lock().get_param_scaled(param)?;
+                writer.write(&arg)?;
+                Ok(0)
+            }
+            ADXL345_IOC_GET_EVENT => {
+                let arg: Adxl345EventInfo = reader.read()?;
+                let info = adxl345_correlation(this.context.drain.id()).get(&device.lock(), arg.id)?;
+                writer.write(&info)?;
+                Ok(0)
+            }
+            _ => Err(ENOTTY),
+        }
+    }
+}
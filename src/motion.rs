@@ -0,0 +1,268 @@
+/*
+ * Copyright 2024 Luca Saverio Esposito, Università di Roma, Tor Vergata
+ * email: <lucasaverioesposito@gmail.com>
+ *
+ * This file is part of an "Rust Linux driver for the ADXL345 device".
+ *
+ * This driver is free software: you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * This driver is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with Foobar.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+
+// motion.rs
+
+//! Activity, inactivity and free-fall events.
+//!
+//! The device flags them itself, with the thresholds and times of `ADXL345_IOC_SET_PARAM`:
+//! - activity: an axis enabled in ACT_INACT_CTL exceeds `thresh_act`;
+//! - inactivity: the enabled axes stay below `thresh_inact` for `time_inact`;
+//! - free fall: all the axes stay below `thresh_ff` for `time_ff`.
+//!
+//! `ADXL345_IOC_SET_MOTION` arms the events and writes ACT_INACT_CTL, whose bits select the axes
+//! and the ac or dc coupling of activity (bits 7-4) and inactivity (bits 3-0), then enables the
+//! matching interrupts. While an event is armed the drain reads INT_SOURCE once per pass; each
+//! pass that finds armed events is one motion event, counted in a sequence number and timestamped
+//! in the clock of the samples. The readers are then woken up and `poll()` reports `POLLPRI` on
+//! every file until it fetches the event with `ADXL345_IOC_GET_MOTION_EVENT`; files opened with
+//! `O_ASYNC` receive `SIGIO` with `POLL_PRI`. The latency is the drain period (10 ms), or the
+//! interrupt latency with `data_gpio` while the events go to INT1 with the watermark.
+//!
+//! Only the last event is kept: a reader slower than the events sees the sequence skip, and the
+//! events of each kind are counted in `activity_events`, `inactivity_events` and
+//! `free_fall_events`.
+//!
+//! Each device has its own motion state, selected by its id (see instance.rs): `POLLPRI` is only
+//! reported on the files of the device that recorded the event.
+
+use kernel::prelude::*;
+use kernel::bindings;
+use kernel::error::code::EINVAL;
+use kernel::io_buffer::{ReadableFromBytes, WritableToBytes};
+use kernel::sync::{Arc, Mutex};
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use crate::alarm::adxl345_alarm;
+use crate::constant::{ADXL345_REG_ACT_INACT_CTL, ADXL345_REG_INT_ENABLE};
+use crate::fasync::adxl345_sigio;
+use crate::drain::Adxl345Drain;
+use crate::structures::Adxl345;
+use crate::instance::ADXL345_DEVICES_MAX;
+
+/// Events armed, bits of `Adxl345MotionArg::events` and `Adxl345MotionEvent::events`.
+pub (crate) const ADXL345_MOTION_ACTIVITY: u32 = 1 << 0;
+pub (crate) const ADXL345_MOTION_INACTIVITY: u32 = 1 << 1;
+pub (crate) const ADXL345_MOTION_FREE_FALL: u32 = 1 << 2;
+
+/// ACTIVITY, INACTIVITY and FREE_FALL bits of INT_ENABLE and INT_SOURCE.
+const ADXL345_INT_ACTIVITY: u8 = 1 << 4;
+const ADXL345_INT_INACTIVITY: u8 = 1 << 3;
+const ADXL345_INT_FREE_FALL: u8 = 1 << 2;
+
+/// Argument of `ADXL345_IOC_SET_MOTION` and `ADXL345_IOC_GET_MOTION`.
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub (crate) struct Adxl345MotionArg {
+    pub (crate) events: u32,        // ADXL345_MOTION_* bits, 0 arms none
+    pub (crate) act_inact_ctl: u32, // Value of the ACT_INACT_CTL register
+}
+
+// SAFETY: `Adxl345MotionArg` is `repr(C)`, made only of integers and has no padding, so any byte
+// pattern is a valid value.
+unsafe impl ReadableFromBytes for Adxl345MotionArg {}
+unsafe impl WritableToBytes for Adxl345MotionArg {}
+
+/// Last motion event, returned by `ADXL345_IOC_GET_MOTION_EVENT`.
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub (crate) struct Adxl345MotionEvent {
+    pub (crate) sequence: u32,      // Number of events since the module was loaded, 0 if none
+    pub (crate) events: u32,        // ADXL345_MOTION_* bits flagged by the event
+    pub (crate) timestamp_ns: u64,  // Timestamp of the event, in the clock of the samples
+}
+
+// SAFETY: `Adxl345MotionEvent` is `repr(C)`, made only of integers and has no padding.
+unsafe impl WritableToBytes for Adxl345MotionEvent {}
+
+/// Motion events state, the counters are debugfs files.
+pub (crate) struct Adxl345Motion {
+    events: AtomicU32,              // ADXL345_MOTION_* bits armed
+    act_inact_ctl: AtomicU32,       // ACT_INACT_CTL written with them
+    sequence: AtomicU32,            // Events so far
+    flagged: AtomicU32,             // ADXL345_MOTION_* bits of the last event
+    timestamp_ns: AtomicU64,        // Timestamp of the last event
+    notify: AtomicBool,             // An event occurred since the readers were last woken up
+    pub (crate) activity: AtomicU64,
+    pub (crate) inactivity: AtomicU64,
+    pub (crate) free_fall: AtomicU64,
+}
+
+/// Motion states of the devices, indexed by id.
+static ADXL345_MOTIONS: [Adxl345Motion; ADXL345_DEVICES_MAX] = [Adxl345Motion::EMPTY; ADXL345_DEVICES_MAX];
+
+/// Returns the motion state of device `id`.
+pub (crate) fn adxl345_motion(id: usize) -> &'static Adxl345Motion {
+    &ADXL345_MOTIONS[id]
+}
+
+impl Adxl345Motion {
+    /// A state with no event armed, to initialize the array of states.
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY: Self = Self {
+        events: AtomicU32::new(0),
+        act_inact_ctl: AtomicU32::new(0),
+        sequence: AtomicU32::new(0),
+        flagged: AtomicU32::new(0),
+        timestamp_ns: AtomicU64::new(0),
+        notify: AtomicBool::new(false),
+        activity: AtomicU64::new(0),
+        inactivity: AtomicU64::new(0),
+        free_fall: AtomicU64::new(0),
+    };
+
+    /// Returns true if the drain must read INT_SOURCE for the armed events.
+    pub (crate) fn wants_source(&self) -> bool {
+        self.events.load(Ordering::Relaxed) != 0
+    }
+
+    /// Returns the armed events and the ACT_INACT_CTL value written with them.
+    pub (crate) fn get(&self) -> Adxl345MotionArg {
+        Adxl345MotionArg {
+            events: self.events.load(Ordering::Relaxed),
+            act_inact_ctl: self.act_inact_ctl.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Returns the last event.
+    pub (crate) fn last(&self) -> Adxl345MotionEvent {
+        Adxl345MotionEvent {
+            sequence: self.sequence.load(Ordering::Acquire),
+            events: self.flagged.load(Ordering::Relaxed),
+            timestamp_ns: self.timestamp_ns.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Returns the sequence number of the last event.
+    pub (crate) fn sequence(&self) -> u32 {
+        self.sequence.load(Ordering::Acquire)
+    }
+
+    /// Disarms the events, called when the device is removed.
+    pub (crate) fn reset(&self) {
+        self.events.store(0, Ordering::Relaxed);
+        self.act_inact_ctl.store(0, Ordering::Relaxed);
+    }
+
+    /// Checks the INT_SOURCE value read by the drain for an armed event, with the device lock
+    /// held, and records it.
+    ///
+    /// # Returns
+    /// `true` if an event was recorded.
+    pub (crate) fn push_source(&self, adxl: &Adxl345, source: u8) -> bool {
+        let armed = self.events.load(Ordering::Relaxed);
+        let mut flagged = 0;
+        for (event, bit, count) in [
+            (ADXL345_MOTION_ACTIVITY, ADXL345_INT_ACTIVITY, &self.activity),
+            (ADXL345_MOTION_INACTIVITY, ADXL345_INT_INACTIVITY, &self.inactivity),
+            (ADXL345_MOTION_FREE_FALL, ADXL345_INT_FREE_FALL, &self.free_fall),
+        ] {
+            if armed & event != 0 && source & bit != 0 {
+                flagged |= event;
+                count.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        if flagged == 0 {
+            return false;
+        }
+
+        self.flagged.store(flagged, Ordering::Relaxed);
+        self.timestamp_ns.store(adxl.clock().now_ns(), Ordering::Relaxed);
+        self.sequence.fetch_add(1, Ordering::Release);
+        self.notify.store(true, Ordering::Relaxed);
+        true
+    }
+
+    /// Wakes up the readers of `drain` after a pass that recorded an event, outside of the
+    /// device lock.
+    pub (crate) fn notify(&self, drain: &Adxl345Drain) {
+        if self.notify.swap(false, Ordering::Relaxed) {
+            drain.wake_up();
+            adxl345_sigio(bindings::POLL_PRI);
+        }
+    }
+}
+
+/// Motion events already fetched by an open file, for its `POLLPRI`.
+pub (crate) struct Adxl345MotionSeen {
+    id: usize,             // Device the file was opened on
+    sequence: AtomicU32,
+}
+
+impl Adxl345MotionSeen {
+    /// Starts at the last event of device `id`: a new file only reports the next ones.
+    pub (crate) fn new(id: usize) -> Self {
+        Self { id, sequence: AtomicU32::new(adxl345_motion(id).sequence()) }
+    }
+
+    /// Returns `POLLPRI` if an event occurred that the file didn't fetch yet.
+    pub (crate) fn poll_mask(&self) -> u32 {
+        match self.sequence.load(Ordering::Relaxed) == adxl345_motion(self.id).sequence() {
+            true => 0,
+            false => bindings::POLLPRI,
+        }
+    }
+
+    /// Returns the last event and marks it as fetched by the file.
+    pub (crate) fn fetch(&self) -> Adxl345MotionEvent {
+        let event = adxl345_motion(self.id).last();
+        self.sequence.store(event.sequence, Ordering::Relaxed);
+        event
+    }
+}
+
+/// Arms the motion events of `arg`, as `ADXL345_IOC_SET_MOTION`, with the configuration lock
+/// held.
+///
+/// # Returns
+/// - `Ok(())` once ACT_INACT_CTL and INT_ENABLE are written.
+/// - `Err(EINVAL)` if `arg` holds unknown events or an ACT_INACT_CTL value above 0xFF.
+/// - `Err(Error)` if a register can't be written.
+pub (crate) fn adxl345_motion_set(device: &Arc<Mutex<Adxl345>>, arg: Adxl345MotionArg) -> Result {
+    let known = ADXL345_MOTION_ACTIVITY | ADXL345_MOTION_INACTIVITY | ADXL345_MOTION_FREE_FALL;
+    if arg.events & !known != 0 || arg.act_inact_ctl > 0xFF {
+        return Err(EINVAL);
+    }
+
+    let adxl = device.lock();
+    let motion = adxl345_motion(adxl.id());
+
+    // The alarm line of the device keeps the activity interrupt it enabled
+    let mut interrupts = 0;
+    if adxl345_alarm(adxl.id()).attached() || arg.events & ADXL345_MOTION_ACTIVITY != 0 {
+        interrupts |= ADXL345_INT_ACTIVITY;
+    }
+    if arg.events & ADXL345_MOTION_INACTIVITY != 0 {
+        interrupts |= ADXL345_INT_INACTIVITY;
+    }
+    if arg.events & ADXL345_MOTION_FREE_FALL != 0 {
+        interrupts |= ADXL345_INT_FREE_FALL;
+    }
+
+    adxl.write_register(ADXL345_REG_ACT_INACT_CTL, arg.act_inact_ctl as u8)?;
+    adxl.update_register(
+        ADXL345_REG_INT_ENABLE,
+        ADXL345_INT_ACTIVITY | ADXL345_INT_INACTIVITY | ADXL345_INT_FREE_FALL,
+        interrupts,
+    )?;
+    motion.act_inact_ctl.store(arg.act_inact_ctl, Ordering::Relaxed);
+    motion.events.store(arg.events, Ordering::Relaxed);
+    Ok(())
+}
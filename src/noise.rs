@@ -0,0 +1,193 @@
+/*
+ * Copyright 2024 Luca Saverio Esposito, Università di Roma, Tor Vergata
+ * email: <lucasaverioesposito@gmail.com>
+ *
+ * This file is part of an "Rust Linux driver for the ADXL345 device".
+ *
+ * This driver is free software: you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * This driver is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with Foobar.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+
+
+// noise.rs
+
+//! Noise floor self-characterization.
+//!
+//! Writing a number of seconds to the `noise_run` debugfs file, with the device stationary,
+//! captures that long at each of the 16 output data rates and computes the RMS noise of each axis
+//! (its standard deviation around the mean, gravity excluded). The table is kept in the driver
+//! and shown by `noise_floor`, for consumers to derive detection thresholds from the noise
+//! actually measured on this sensor and mounting.
+//!
+//! The routine needs the device for itself: it fails with `EBUSY` while a measurement session is
+//! running, and holds the configuration lock until it is done, so no session starts meanwhile.
+//! The rate in use is restored and the device put back in standby afterwards.
+
+use kernel::prelude::*;
+use kernel::delay::coarse_sleep;
+use kernel::error::code::EBUSY;
+use kernel::str::CString;
+use kernel::sync::{Arc, Mutex};
+use kernel::time::ktime_get_ns;
+use core::sync::atomic::{AtomicU32, Ordering};
+use core::time::Duration;
+use crate::config::{Adxl345Param, ADXL345_RATES_MHZ};
+use crate::context::adxl345_context;
+use crate::instance::ADXL345_DEVICE_PRIMARY;
+use crate::ioctl::ADXL345_CONFIG_LOCK;
+use crate::fixed::{adxl345_isqrt, ADXL345_UG_PER_UNIT};
+use crate::structures::Adxl345;
+
+/// Longest capture per rate, in seconds.
+pub (crate) const ADXL345_NOISE_SECONDS_MAX: u32 = 60;
+
+/// Largest number of samples used per rate, it keeps the sums within 64 bits.
+const ADXL345_NOISE_SAMPLES_MAX: u32 = 4096;
+
+/// Number of output data rates.
+const ADXL345_NOISE_RATES: usize = ADXL345_RATES_MHZ.len();
+
+/// µg per shifted LSB, squared.
+const ADXL345_NOISE_UG2_PER_UNIT2: u64 = (ADXL345_UG_PER_UNIT * ADXL345_UG_PER_UNIT) as u64;
+
+/// Noise floor table, one row per output data rate.
+pub (crate) struct Adxl345NoiseFloor {
+    samples: [AtomicU32; ADXL345_NOISE_RATES],        // Samples measured at each rate, 0 if none
+    rms_ug: [[AtomicU32; 3]; ADXL345_NOISE_RATES],    // RMS noise of each axis, in µg
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const ADXL345_NOISE_ZERO: AtomicU32 = AtomicU32::new(0);
+#[allow(clippy::declare_interior_mutable_const)]
+const ADXL345_NOISE_ZERO_ROW: [AtomicU32; 3] = [ADXL345_NOISE_ZERO; 3];
+
+/// Noise floor of the probed device, empty until the routine runs.
+pub (crate) static ADXL345_NOISE_FLOOR: Adxl345NoiseFloor = Adxl345NoiseFloor {
+    samples: [ADXL345_NOISE_ZERO; ADXL345_NOISE_RATES],
+    rms_ug: [ADXL345_NOISE_ZERO_ROW; ADXL345_NOISE_RATES],
+};
+
+impl Adxl345NoiseFloor {
+    /// Formats the table for the `noise_floor` debugfs file.
+    pub (crate) fn text(&self) -> Result<Vec<u8>> {
+        let mut text = Vec::new();
+        text.try_extend_from_slice(b"# rate_mhz samples rms_x_ug rms_y_ug rms_z_ug\n")?;
+        for (index, rate_mhz) in ADXL345_RATES_MHZ.iter().enumerate() {
+            let rms = [0, 1, 2].map(|axis| self.rms_ug[index][axis].load(Ordering::Relaxed));
+            let line = CString::try_from_fmt(fmt!(
+                "{} {} {} {} {}\n",
+                rate_mhz, self.samples[index].load(Ordering::Relaxed), rms[0], rms[1], rms[2]
+            ))?;
+            text.try_extend_from_slice(line.as_bytes())?;
+        }
+        Ok(text)
+    }
+}
+
+/// Runs the characterization, `seconds` per rate, on the primary device (see instance.rs).
+///
+/// # Returns
+/// - `Ok(())` once the table is updated.
+/// - `Err(EBUSY)` if a measurement session is running.
+/// - `Err(ENODEV)` if the primary device is not probed.
+/// - `Err(Error)` if a register transaction failed, the rows measured before it are kept.
+pub (crate) fn adxl345_noise_run(seconds: u32) -> Result {
+    let context = adxl345_context(ADXL345_DEVICE_PRIMARY)?;
+
+    // SAFETY: The lock is initialized at module init.
+    let _config = unsafe { ADXL345_CONFIG_LOCK.lock() };
+    let device = context.device()?;
+    if context.drain.is_running() {
+        return Err(EBUSY);
+    }
+
+    let rate_mhz = device.lock().get_param(Adxl345Param::Rate)?;
+    device.lock().enable_measure()?;
+
+    let mut ret = Ok(());
+    for (index, &rate) in ADXL345_RATES_MHZ.iter().enumerate() {
+        ret = device.lock().set_param(Adxl345Param::Rate, rate)
+            .and_then(|_| adxl345_noise_measure(device, index, rate, seconds));
+        if ret.is_err() {
+            break;
+        }
+    }
+
+    // The rate in use and the standby mode are restored whatever the outcome
+    let restored = {
+        let adxl = device.lock();
+        adxl.set_param(Adxl345Param::Rate, rate_mhz).and_then(|_| adxl.disable_measure())
+    };
+    ret?;
+    restored?;
+    pr_info!("Noise floor measured, {} s per rate\n", seconds);
+    Ok(())
+}
+
+/// Captures `seconds` at the current rate, `rate_mhz`, and stores the row `index` of the table.
+///
+/// The device lock is only held for the register transactions, never while waiting.
+fn adxl345_noise_measure(
+    device: &Arc<Mutex<Adxl345>>,
+    index: usize,
+    rate_mhz: u32,
+    seconds: u32,
+) -> Result {
+    let floor = &ADXL345_NOISE_FLOOR;
+    let deadline = ktime_get_ns() + seconds as u64 * 1_000_000_000;
+    // Poll about four times per sample period, within 1 and 100 ms
+    let poll_ms = (250_000 / rate_mhz as u64).clamp(1, 100);
+
+    // Deviations from the first sample, which keeps the sums small at rest
+    let mut first = None;
+    let mut sum = [0i64; 3];
+    let mut sum_sq = [0i64; 3];
+    let mut count = 0u32;
+    while count < ADXL345_NOISE_SAMPLES_MAX && ktime_get_ns() < deadline {
+        let sample = {
+            let adxl = device.lock();
+            if adxl.data_ready()? != 0 { Some(adxl.read_data()?) } else { None }
+        };
+        let sample = match sample {
+            Some(sample) => sample,
+            None => {
+                coarse_sleep(Duration::from_millis(poll_ms));
+                continue;
+            }
+        };
+
+        let axes = [sample.x as i64, sample.y as i64, sample.z as i64];
+        let origin = *first.get_or_insert(axes);
+        for axis in 0..3 {
+            let deviation = axes[axis] - origin[axis];
+            sum[axis] += deviation;
+            sum_sq[axis] += deviation * deviation;
+        }
+        count += 1;
+    }
+
+    floor.samples[index].store(count, Ordering::Relaxed);
+    for axis in 0..3 {
+        let rms = if count > 1 {
+            // Variance in units², (n Σd² - (Σd)²) / n², scaled to µg² before the last division
+            let n = count as i64;
+            let spread = ((n * sum_sq[axis] - sum[axis] * sum[axis]) / n) as u64;
+            adxl345_isqrt(spread * ADXL345_NOISE_UG2_PER_UNIT2 / n as u64) as u32
+        } else {
+            0
+        };
+        floor.rms_ug[index][axis].store(rms, Ordering::Relaxed);
+    }
+    Ok(())
+}
@@ -0,0 +1,128 @@
+/*
+ * Copyright 2024 Luca Saverio Esposito, Università di Roma, Tor Vergata
+ * email: <lucasaverioesposito@gmail.com>
+ *
+ * This file is part of an "Rust Linux driver for the ADXL345 device".
+ *
+ * This driver is free software: you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * This driver is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with Foobar.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+
+// of_node.rs
+
+//! Properties of the device tree node of the device.
+//!
+//! A device described in the device tree, on I2C or on SPI, carries its wiring and its startup
+//! configuration in its node, so nothing has to be passed at load:
+//!
+//! ```text
+//! &i2c2 {
+//!     accelerometer@53 {
+//!         compatible = "adi,adxl345";
+//!         reg = <0x53>;
+//!         int1-gpios = <&gpio1 16 GPIO_ACTIVE_HIGH>;
+//!         alarm-gpios = <&gpio1 17 GPIO_ACTIVE_HIGH>;
+//!         rate = <400000>;
+//!         range = <4>;
+//!     };
+//! };
+//! ```
+//!
+//! `int1-gpios` and `alarm-gpios` stand for the `data_gpio` and `alarm_gpio` module parameters,
+//! which win when they are set. The startup profile properties (see profile.rs) are read from
+//! the node as well. A client created on a bus and address has no node, only the parameters
+//! apply.
+
+#[cfg(CONFIG_OF)]
+use kernel::bindings;
+use kernel::str::CStr;
+use kernel::sync::Mutex;
+use crate::structures::Adxl345;
+
+/// A device tree node, holding a reference to it.
+///
+/// # Invariants
+/// - `node` is a valid pointer to a `device_node` on which a reference is held.
+#[cfg(CONFIG_OF)]
+pub (crate) struct Adxl345OfNode {
+    node: *mut bindings::device_node,
+}
+
+#[cfg(CONFIG_OF)]
+impl Adxl345OfNode {
+    /// Returns the node of the device the state of `device` is built on, if it has one.
+    pub (crate) fn of_device(device: &Mutex<Adxl345>) -> Option<Self> {
+        let dev = device.lock().bus().device().raw_device();
+        // SAFETY: The device is valid while its driver state is, taking a reference on its node
+        // keeps the node valid.
+        let node = unsafe { bindings::of_node_get((*dev).of_node) };
+        (!node.is_null()).then_some(Self { node })
+    }
+
+    /// Returns the first node compatible with `compatible`.
+    pub (crate) fn find_compatible(compatible: &CStr) -> Option<Self> {
+        // SAFETY: A null `from` starts the search at the root, the strings are NUL-terminated.
+        let node = unsafe {
+            bindings::of_find_compatible_node(core::ptr::null_mut(), core::ptr::null(), compatible.as_char_ptr())
+        };
+        (!node.is_null()).then_some(Self { node })
+    }
+
+    /// Reads a `u32` property, `None` if it is missing.
+    pub (crate) fn read_u32(&self, name: &CStr) -> Option<u32> {
+        let mut value = 0u32;
+        // SAFETY: `node` is valid by the type invariants, `value` is valid.
+        let ret = unsafe {
+            bindings::of_property_read_variable_u32_array(self.node, name.as_char_ptr(), &mut value, 1, 0)
+        };
+        (ret == 0).then_some(value)
+    }
+
+    /// Reads a string property, `None` if it is missing.
+    pub (crate) fn read_str(&self, name: &CStr) -> Option<&CStr> {
+        let mut value = core::ptr::null();
+        // SAFETY: `node` is valid by the type invariants, `value` is valid.
+        let ret = unsafe { bindings::of_property_read_string(self.node, name.as_char_ptr(), &mut value) };
+        // SAFETY: On success `value` points to a NUL-terminated string owned by the node, which
+        // outlives the borrow of `self`.
+        (ret == 0).then(|| unsafe { CStr::from_char_ptr(value) })
+    }
+
+    /// Returns the number of the first GPIO of property `name`, e.g. `int1-gpios`.
+    pub (crate) fn gpio(&self, name: &CStr) -> Option<u32> {
+        // SAFETY: `node` is valid by the type invariants.
+        let gpio = unsafe { bindings::of_get_named_gpio(self.node, name.as_char_ptr(), 0) };
+        u32::try_from(gpio).ok()
+    }
+}
+
+#[cfg(CONFIG_OF)]
+impl Drop for Adxl345OfNode {
+    fn drop(&mut self) {
+        // SAFETY: A reference is held by the type invariants.
+        unsafe { bindings::of_node_put(self.node) };
+    }
+}
+
+/// Returns the GPIO line of property `name` in the node of the device, if any.
+#[cfg(CONFIG_OF)]
+pub (crate) fn adxl345_of_gpio(device: &Mutex<Adxl345>, name: &CStr) -> Option<u32> {
+    Adxl345OfNode::of_device(device)?.gpio(name)
+}
+
+/// Without device tree support, the lines can only come from the module parameters.
+#[cfg(not(CONFIG_OF))]
+pub (crate) fn adxl345_of_gpio(_device: &Mutex<Adxl345>, _name: &CStr) -> Option<u32> {
+    None
+}
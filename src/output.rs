@@ -0,0 +1,192 @@
+/*
+ * Copyright 2024 Luca Saverio Esposito, Università di Roma, Tor Vergata
+ * email: <lucasaverioesposito@gmail.com>
+ *
+ * This file is part of an "Rust Linux driver for the ADXL345 device".
+ *
+ * This driver is free software: you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * This driver is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with Foobar.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+
+// output.rs
+
+//! Output format of the records, chosen per open file with `ADXL345_IOC_SET_OUTPUT`:
+//! - **raw** (the default): the `i16` records of `Adxl345Sample`, samples in full resolution
+//!   counts shifted by 2 (see regmap.rs). The shift makes them roughly mg, 0.975 mg per unit
+//!   rather than 1, which is close but not a unit.
+//! - **micro-g** (`ADXL345_OUTPUT_MICRO_G`): records of three `i32`, samples in µg.
+//! - **micro-m/s²** (`ADXL345_OUTPUT_MICRO_MS2`): the same records, samples in µm/s², with the
+//!   standard gravity 9.80665 m/s².
+//! - **timestamped** (`ADXL345_OUTPUT_TIMESTAMPED`): the raw record, 2 bytes of padding and a
+//!   `u64`, 16 bytes. The `u64` of a sample is the time it was read from the device or its FIFO
+//!   by the drain, in ns of the clock of the samples (`ADXL345_IOC_SET_CLOCK`, monotonic by
+//!   default); it is 0 for a marker. A FIFO read in one pass stamps its samples a few µs apart,
+//!   not one period apart: the stamps place a batch in time, the rate spaces its samples. A
+//!   resampling file (see resample.rs) computes its samples between two device samples, they
+//!   are stamped 0.
+//!
+//! The conversion is done in the kernel, rounded to the nearest unit, from the range in use: in
+//! full resolution, which the driver always programs, a shifted count is `ADXL345_UG_PER_UNIT`
+//! (see fixed.rs) at every range, so a range change (see auto_range.rs) never changes the scale, only the largest value.
+//!
+//! A marker keeps its kind and value in the wider record: `x` is `i32::MIN`, which no sample
+//! reaches (16 g is 156906500 µm/s²), `y` the kind and `z` the value, sign extended. The batch CRC
+//! (see batch_crc.rs) covers the records as written, at their size. A read returns whole records
+//! of the format in use when it starts, and `FIONREAD` counts in the same size.
+
+use kernel::prelude::*;
+use kernel::error::code::EINVAL;
+use core::sync::atomic::{AtomicU32, Ordering};
+use crate::structures::Adxl345Sample;
+use crate::fixed::{adxl345_round_div, ADXL345_UG_PER_UNIT};
+
+/// Records of `Adxl345Sample`, samples in shifted counts.
+pub (crate) const ADXL345_OUTPUT_RAW: u32 = 0;
+
+/// Records of `Adxl345ScaledSample`, samples in µg.
+pub (crate) const ADXL345_OUTPUT_MICRO_G: u32 = 1;
+
+/// Records of `Adxl345ScaledSample`, samples in µm/s².
+pub (crate) const ADXL345_OUTPUT_MICRO_MS2: u32 = 2;
+
+/// Records of `Adxl345StampedSample`, samples in shifted counts with the time they were read.
+pub (crate) const ADXL345_OUTPUT_TIMESTAMPED: u32 = 3;
+
+/// Marker tag of a scaled record, in its x field.
+const ADXL345_SCALED_MARKER_TAG: i32 = i32::MIN;
+
+/// Record of the scaled formats, as laid out in the user buffer.
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct Adxl345ScaledSample {
+    x: i32,
+    y: i32,
+    z: i32,
+}
+
+/// Record of the timestamped format, as laid out in the user buffer. Only its layout is used,
+/// `adxl345_encode()` writes the fields.
+#[repr(C)]
+#[allow(dead_code)]
+struct Adxl345StampedSample {
+    record: Adxl345Sample,
+    _pad: u16,
+    timestamp_ns: u64,
+}
+
+/// Largest record of any format, in bytes.
+pub (crate) const ADXL345_RECORD_MAX: usize = core::mem::size_of::<Adxl345StampedSample>();
+
+const _: () = assert!(ADXL345_RECORD_MAX == 16);
+const _: () = assert!(core::mem::size_of::<Adxl345ScaledSample>() <= ADXL345_RECORD_MAX);
+
+/// Converts a shifted count to µg, exactly.
+const fn adxl345_micro_g(value: i16) -> i32 {
+    (value as i64 * ADXL345_UG_PER_UNIT) as i32
+}
+
+/// Converts a shifted count to µm/s²: `ADXL345_UG_PER_UNIT` µg times 9.80665.
+const fn adxl345_micro_ms2(value: i16) -> i32 {
+    adxl345_round_div(value as i64 * ADXL345_UG_PER_UNIT * 980_665, 100_000) as i32
+}
+
+// One LSB, 1 g in counts (1024) and the ends of the type, which must stay clear of the marker tag
+const _: () = assert!(adxl345_micro_g(4) == 3900);
+const _: () = assert!(adxl345_micro_g(1024) == 998_400);
+const _: () = assert!(adxl345_micro_ms2(1024) == 9_790_959);
+const _: () = assert!(adxl345_micro_ms2(-1024) == -9_790_959);
+const _: () = assert!(adxl345_micro_ms2(i16::MIN) > ADXL345_SCALED_MARKER_TAG);
+
+/// Output format of an open file.
+pub (crate) struct Adxl345Output {
+    mode: AtomicU32,    // ADXL345_OUTPUT_*
+}
+
+impl Adxl345Output {
+    pub (crate) const fn new() -> Self {
+        Self { mode: AtomicU32::new(ADXL345_OUTPUT_RAW) }
+    }
+
+    /// Selects the format, from the next read.
+    ///
+    /// # Returns
+    /// `Err(EINVAL)` for an unknown format.
+    pub (crate) fn set(&self, mode: u32) -> Result {
+        match mode {
+            ADXL345_OUTPUT_RAW | ADXL345_OUTPUT_MICRO_G | ADXL345_OUTPUT_MICRO_MS2 | ADXL345_OUTPUT_TIMESTAMPED => {
+                self.mode.store(mode, Ordering::Relaxed);
+                Ok(())
+            }
+            _ => Err(EINVAL),
+        }
+    }
+
+    /// Returns the format, `ADXL345_OUTPUT_*`.
+    pub (crate) fn get(&self) -> u32 {
+        self.mode.load(Ordering::Relaxed)
+    }
+}
+
+/// Returns the size of a record of `mode`, in bytes.
+pub (crate) const fn adxl345_record_size(mode: u32) -> usize {
+    match mode {
+        ADXL345_OUTPUT_RAW => core::mem::size_of::<Adxl345Sample>(),
+        ADXL345_OUTPUT_TIMESTAMPED => core::mem::size_of::<Adxl345StampedSample>(),
+        _ => core::mem::size_of::<Adxl345ScaledSample>(),
+    }
+}
+
+/// Encodes `record`, read from the device at `ns` (0 for a marker), in the format `mode` into
+/// `bytes`, native endian.
+///
+/// # Returns
+/// The number of bytes written, the size of a record of `mode`.
+pub (crate) fn adxl345_encode(
+    record: &Adxl345Sample,
+    ns: u64,
+    mode: u32,
+    bytes: &mut [u8; ADXL345_RECORD_MAX],
+) -> usize {
+    let scaled = match mode {
+        ADXL345_OUTPUT_RAW | ADXL345_OUTPUT_TIMESTAMPED => {
+            for (i, value) in [record.x, record.y, record.z].iter().enumerate() {
+                bytes[2 * i..2 * i + 2].copy_from_slice(&value.to_ne_bytes());
+            }
+            if mode == ADXL345_OUTPUT_TIMESTAMPED {
+                bytes[6..8].fill(0);
+                bytes[8..16].copy_from_slice(&ns.to_ne_bytes());
+            }
+            return adxl345_record_size(mode);
+        }
+        _ if record.is_marker() => Adxl345ScaledSample {
+            x: ADXL345_SCALED_MARKER_TAG,
+            y: record.y as i32,
+            z: record.z as i32,
+        },
+        ADXL345_OUTPUT_MICRO_G => Adxl345ScaledSample {
+            x: adxl345_micro_g(record.x),
+            y: adxl345_micro_g(record.y),
+            z: adxl345_micro_g(record.z),
+        },
+        _ => Adxl345ScaledSample {
+            x: adxl345_micro_ms2(record.x),
+            y: adxl345_micro_ms2(record.y),
+            z: adxl345_micro_ms2(record.z),
+        },
+    };
+    for (i, value) in [scaled.x, scaled.y, scaled.z].iter().enumerate() {
+        bytes[4 * i..4 * i + 4].copy_from_slice(&value.to_ne_bytes());
+    }
+    adxl345_record_size(mode)
+}
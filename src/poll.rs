@@ -0,0 +1,139 @@
+/*
+ * Copyright 2024 Luca Saverio Esposito, Università di Roma, Tor Vergata
+ * email: <lucasaverioesposito@gmail.com>
+ *
+ * This file is part of an "Rust Linux driver for the ADXL345 device".
+ *
+ * This driver is free software: you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * This driver is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with Foobar.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+// poll.rs
+
+//! Poll semantics of the readers.
+//!
+//! `poll()` reports the device readable in one of two modes, chosen per open file with
+//! `ADXL345_IOC_SET_POLL_MODE`:
+//! - **level** (the default): readable as long as a read would not block, that is while samples,
+//!   a sync marker or a session header are buffered.
+//! - **edge**: readable once per new batch. Once `poll()` reported the file readable, it doesn't
+//!   again until the drain or a sync pulse brings new data, even if the previous data is still
+//!   buffered. An event loop reading less than what is buffered on each wakeup (or polling with
+//!   level-triggered epoll) is then woken up once per batch instead of spinning.
+//!
+//! A removed device is reported in both modes (`POLLHUP`), as are bus errors, which come with
+//! a new batch. A motion event not fetched yet is reported as `POLLPRI` (see motion.rs).
+//!
+//! Every wake-up of the readers for new data counts as a data event of the device, kept in its
+//! drain; in edge mode each file remembers the last event of its device it reported, so the
+//! batches of one device don't wake up the readers of another.
+
+use kernel::prelude::*;
+use kernel::bindings;
+use kernel::error::code::EINVAL;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use crate::error_policy::Adxl345ErrorPolicy;
+use crate::resample::Adxl345Resampler;
+use crate::motion::Adxl345MotionSeen;
+use crate::context::Adxl345Context;
+use crate::drain::Adxl345Drain;
+use crate::output::Adxl345Output;
+#[cfg(not(adxl345_no_filter))]
+use crate::filter::Adxl345Pipeline;
+use kernel::sync::Arc;
+
+/// Poll mode reporting the file readable while a read would not block.
+pub (crate) const ADXL345_POLL_LEVEL: u32 = 0;
+
+/// Poll mode reporting the file readable once per new batch.
+pub (crate) const ADXL345_POLL_EDGE: u32 = 1;
+
+/// Value of `Adxl345Reader::reported` before the first report in edge mode.
+const ADXL345_NEVER_REPORTED: u64 = u64::MAX;
+
+/// Counts a data event of the device of `drain`, to be called before its readers are woken up
+/// for new data.
+///
+/// It only updates an atomic, so it can be called from interrupt context.
+pub (crate) fn adxl345_data_event(drain: &Adxl345Drain) {
+    drain.data_events().fetch_add(1, Ordering::Release);
+}
+
+/// Per-file state of a reader, the private data of the open file.
+pub (crate) struct Adxl345Reader {
+    pub (crate) context: Arc<Adxl345Context>,   // Device the file was opened on, see context.rs
+    edge: AtomicBool,      // Edge poll mode, level otherwise
+    reported: AtomicU64,   // Last data event reported readable, in edge mode
+    pub (crate) errors: Adxl345ErrorPolicy,   // See error_policy.rs
+    pub (crate) resample: Adxl345Resampler,   // See resample.rs
+    pub (crate) motion: Adxl345MotionSeen,    // See motion.rs
+    pub (crate) output: Adxl345Output,        // See output.rs
+    #[cfg(not(adxl345_no_filter))]
+    pub (crate) filter: Adxl345Pipeline,   // See filter.rs
+}
+
+impl Adxl345Reader {
+    pub (crate) fn new(context: Arc<Adxl345Context>) -> Self {
+        let id = context.drain.id();
+        Self {
+            context,
+            edge: AtomicBool::new(false),
+            reported: AtomicU64::new(ADXL345_NEVER_REPORTED),
+            errors: Adxl345ErrorPolicy::new(),
+            resample: Adxl345Resampler::new(),
+            motion: Adxl345MotionSeen::new(id),
+            output: Adxl345Output::new(),
+            #[cfg(not(adxl345_no_filter))]
+            filter: Adxl345Pipeline::new(),
+        }
+    }
+
+    /// Selects the poll mode, `ADXL345_POLL_LEVEL` or `ADXL345_POLL_EDGE`.
+    ///
+    /// Switching to edge mode reports the data already buffered once more.
+    pub (crate) fn set_poll_mode(&self, mode: u32) -> Result {
+        match mode {
+            ADXL345_POLL_LEVEL => self.edge.store(false, Ordering::Relaxed),
+            ADXL345_POLL_EDGE => {
+                self.reported.store(ADXL345_NEVER_REPORTED, Ordering::Relaxed);
+                self.edge.store(true, Ordering::Relaxed);
+            }
+            _ => return Err(EINVAL),
+        }
+        Ok(())
+    }
+
+    /// Returns the poll mask of the file.
+    ///
+    /// The caller registers on the wait queue of the readers before checking whether a read
+    /// would block, so an event counted after the check wakes up the poll again.
+    ///
+    /// # Parameters
+    /// - `readable`: Whether a read would not block.
+    /// - `removed`: Whether the device was removed.
+    pub (crate) fn poll_mask(&self, readable: bool, removed: bool) -> u32 {
+        if removed {
+            return bindings::POLLIN | bindings::POLLRDNORM | bindings::POLLHUP;
+        }
+        if !readable {
+            return 0;
+        }
+        if self.edge.load(Ordering::Relaxed) {
+            let events = self.context.drain.data_events().load(Ordering::Acquire);
+            if self.reported.swap(events, Ordering::Relaxed) == events {
+                return 0;
+            }
+        }
+        bindings::POLLIN | bindings::POLLRDNORM
+    }
+}
@@ -0,0 +1,260 @@
+/*
+ * Copyright 2024 Luca Saverio Esposito, Università di Roma, Tor Vergata
+ * email: <lucasaverioesposito@gmail.com>
+ *
+ * This file is part of an "Rust Linux driver for the ADXL345 device".
+ *
+ * This driver is free software: you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * This driver is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with Foobar.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+
+// power.rs
+
+//! Power management.
+//!
+//! The ADXL345 draws about 140 µA while measuring at 100 Hz and 0.1 µA in standby. The driver
+//! keeps it in standby whenever nothing needs its samples, and lets userspace trade noise or
+//! latency for current while it measures:
+//! - idle standby: the open files are counted. A reader's release leaves the session running
+//!   while other files are open; once the last file is released the session is stopped and the
+//!   device put in standby, whichever file started it (a reader, or a controller with `start`,
+//!   see control.rs). Releases that leave the device idle are counted in `idle_standbys`.
+//! - `ADXL345_IOC_SET_POWER` sets two modes of the chip, kept until the device is removed:
+//!   - `ADXL345_POWER_LOW_POWER` sets LOW_POWER in BW_RATE. From 12.5 to 400 Hz the device
+//!     draws less (about 50 µA instead of 140 µA at 100 Hz) for somewhat more noise; other rates
+//!     are unaffected.
+//!   - `ADXL345_POWER_AUTO_SLEEP` sets LINK and AUTO_SLEEP in POWER_CTL: once inactivity is
+//!     detected the device samples at `wakeup_hz` (8, 4, 2 or 1 Hz) until activity is detected
+//!     again. It uses the `thresh_act`, `thresh_inact` and `time_inact` parameters and the axes
+//!     of ACT_INACT_CTL (see motion.rs); the samples taken asleep come at the sleep rate.
+//! - system sleep: suspend stops the drain and puts the device in standby. Resume compares the
+//!   chip with the register shadow and writes it back if the supply was cut (see shadow.rs),
+//!   then restarts a session that was running, keeping the buffered samples. The samples left
+//!   in the FIFO at suspend are lost; with the session header enabled a new header marks where
+//!   the stream goes on. Suspends are counted in `suspends`.
+//!
+//! Each device has its own power state, selected by its id (see instance.rs): its modes, its
+//! open files and its suspended session, so each device goes to standby and comes back on its
+//! own.
+//!
+//! Suspend and resume come from the driver of the bus (see adxl345_core.rs and spi.rs) and take
+//! the configuration lock, like the ioctls.
+
+use kernel::prelude::*;
+use kernel::error::code::{EINVAL, EIO};
+use kernel::io_buffer::{ReadableFromBytes, WritableToBytes};
+use kernel::sync::{Arc, Mutex};
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use crate::constant::{ADXL345_REG_BW_RATE, ADXL345_REG_POWER_CTL};
+use crate::context::adxl345_context;
+use crate::drain::Adxl345Drain;
+use crate::instance::ADXL345_DEVICES_MAX;
+use crate::ioctl::ADXL345_CONFIG_LOCK;
+use crate::session::adxl345_session;
+use crate::shadow::adxl345_shadow;
+use crate::structures::Adxl345;
+use crate::utility::adxl345_device_init_at_open;
+
+/// Modes, bits of `Adxl345PowerArg::flags`.
+pub (crate) const ADXL345_POWER_LOW_POWER: u32 = 1 << 0;
+pub (crate) const ADXL345_POWER_AUTO_SLEEP: u32 = 1 << 1;
+
+/// LOW_POWER bit of BW_RATE.
+const ADXL345_BW_RATE_LOW_POWER: u8 = 1 << 4;
+
+/// LINK, AUTO_SLEEP, MEASURE and WAKEUP bits of POWER_CTL.
+const ADXL345_POWER_CTL_LINK: u8 = 1 << 5;
+const ADXL345_POWER_CTL_AUTO_SLEEP: u8 = 1 << 4;
+const ADXL345_POWER_CTL_MEASURE: u8 = 1 << 3;
+const ADXL345_POWER_CTL_WAKEUP: u8 = 0x03;
+
+/// Sleep rate of auto sleep when none is given, in Hz.
+const ADXL345_WAKEUP_DEFAULT_HZ: u32 = 8;
+
+/// Argument of `ADXL345_IOC_SET_POWER` and `ADXL345_IOC_GET_POWER`.
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub (crate) struct Adxl345PowerArg {
+    pub (crate) flags: u32,         // ADXL345_POWER_* bits, 0 is full power
+    pub (crate) wakeup_hz: u32,     // Sampling rate while asleep: 8, 4, 2 or 1, 0 for 8
+}
+
+// SAFETY: `Adxl345PowerArg` is `repr(C)`, made only of integers and has no padding, so any byte
+// pattern is a valid value.
+unsafe impl ReadableFromBytes for Adxl345PowerArg {}
+unsafe impl WritableToBytes for Adxl345PowerArg {}
+
+/// Power state of a device, the counters are debugfs files.
+pub (crate) struct Adxl345Power {
+    flags: AtomicU32,               // ADXL345_POWER_* bits set
+    wakeup_hz: AtomicU32,           // Sleep rate of auto sleep
+    files: AtomicU32,               // Open files, protected by the configuration lock
+    resume_session: AtomicBool,     // A session was running when the system suspended
+    pub (crate) suspends: AtomicU64,
+    pub (crate) idle_standbys: AtomicU64,
+}
+
+/// Power states of the devices, indexed by id.
+static ADXL345_POWERS: [Adxl345Power; ADXL345_DEVICES_MAX] = [Adxl345Power::EMPTY; ADXL345_DEVICES_MAX];
+
+/// Returns the power state of device `id`.
+pub (crate) fn adxl345_power(id: usize) -> &'static Adxl345Power {
+    &ADXL345_POWERS[id]
+}
+
+impl Adxl345Power {
+    /// A state at full power with no open file, to initialize the array of states.
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY: Self = Self {
+        flags: AtomicU32::new(0),
+        wakeup_hz: AtomicU32::new(ADXL345_WAKEUP_DEFAULT_HZ),
+        files: AtomicU32::new(0),
+        resume_session: AtomicBool::new(false),
+        suspends: AtomicU64::new(0),
+        idle_standbys: AtomicU64::new(0),
+    };
+
+    /// Returns the modes set and the sleep rate.
+    pub (crate) fn get(&self) -> Adxl345PowerArg {
+        Adxl345PowerArg {
+            flags: self.flags.load(Ordering::Relaxed),
+            wakeup_hz: self.wakeup_hz.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Back to full power, called when the device is removed: the next one in its slot starts
+    /// with LOW_POWER cleared by its probe and POWER_CTL cleared.
+    pub (crate) fn reset(&self) {
+        self.flags.store(0, Ordering::Relaxed);
+        self.wakeup_hz.store(ADXL345_WAKEUP_DEFAULT_HZ, Ordering::Relaxed);
+        self.resume_session.store(false, Ordering::Relaxed);
+    }
+
+    /// Counts a file of the device opened, with the configuration lock held.
+    pub (crate) fn opened(&self) {
+        self.files.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a file of the device released, with the configuration lock held.
+    ///
+    /// # Returns
+    /// `true` if it was the last open file of the device, which then goes to standby.
+    pub (crate) fn released(&self) -> bool {
+        if self.files.fetch_sub(1, Ordering::Relaxed) != 1 {
+            return false;
+        }
+        self.idle_standbys.fetch_add(1, Ordering::Relaxed);
+        true
+    }
+}
+
+/// Sets the modes of `arg`, as `ADXL345_IOC_SET_POWER`, with the configuration lock held.
+///
+/// # Returns
+/// - `Ok(())` once BW_RATE and POWER_CTL are written.
+/// - `Err(EINVAL)` if `arg` holds unknown modes or a sleep rate other than 8, 4, 2 or 1 Hz.
+/// - `Err(Error)` if a register can't be written.
+pub (crate) fn adxl345_power_set(device: &Arc<Mutex<Adxl345>>, arg: Adxl345PowerArg) -> Result {
+    if arg.flags & !(ADXL345_POWER_LOW_POWER | ADXL345_POWER_AUTO_SLEEP) != 0 {
+        return Err(EINVAL);
+    }
+    let (wakeup_hz, wakeup) = match arg.wakeup_hz {
+        0 | 8 => (8, 0),
+        4 => (4, 1),
+        2 => (2, 2),
+        1 => (1, 3),
+        _ => return Err(EINVAL),
+    };
+
+    let adxl = device.lock();
+    let low_power = match arg.flags & ADXL345_POWER_LOW_POWER {
+        0 => 0,
+        _ => ADXL345_BW_RATE_LOW_POWER,
+    };
+    adxl.update_register(ADXL345_REG_BW_RATE, ADXL345_BW_RATE_LOW_POWER, low_power)?;
+
+    // The datasheet asks for standby while LINK and AUTO_SLEEP change, measurement is enabled
+    // again by a second write
+    let power_ctl = adxl.read_register(ADXL345_REG_POWER_CTL)?;
+    let mut value = power_ctl
+        & !(ADXL345_POWER_CTL_LINK | ADXL345_POWER_CTL_AUTO_SLEEP | ADXL345_POWER_CTL_MEASURE | ADXL345_POWER_CTL_WAKEUP);
+    if arg.flags & ADXL345_POWER_AUTO_SLEEP != 0 {
+        value |= ADXL345_POWER_CTL_LINK | ADXL345_POWER_CTL_AUTO_SLEEP | wakeup;
+    }
+    adxl.write_register(ADXL345_REG_POWER_CTL, value)?;
+    if power_ctl & ADXL345_POWER_CTL_MEASURE != 0 {
+        adxl.write_register(ADXL345_REG_POWER_CTL, value | ADXL345_POWER_CTL_MEASURE)?;
+    }
+
+    let power = adxl345_power(adxl.id());
+    power.flags.store(arg.flags, Ordering::Relaxed);
+    power.wakeup_hz.store(wakeup_hz, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Stops the session and puts the device in standby before the system sleeps.
+///
+/// A bus error doesn't hold the system back: it is logged, the device then keeps measuring
+/// until resume.
+pub (crate) fn adxl345_power_suspend(device: &Arc<Mutex<Adxl345>>) -> Result {
+    // SAFETY: The lock is initialized at module init.
+    let _config = unsafe { ADXL345_CONFIG_LOCK.lock() };
+
+    // The context is published by probe, before the device can be suspended
+    let id = device.lock().id();
+    let power = adxl345_power(id);
+    if let Ok(context) = adxl345_context(id) {
+        power.resume_session.store(context.drain.is_running(), Ordering::Relaxed);
+        context.drain.stop();
+    }
+    power.suspends.fetch_add(1, Ordering::Relaxed);
+
+    if let Err(e) = device.lock().disable_measure() {
+        pr_warn!("Failed to put the device in standby for suspend: {:?}\n", e);
+    }
+    Ok(())
+}
+
+/// Brings the device back after the system slept: restores its configuration if it was lost,
+/// and restarts the session that was running.
+///
+/// # Returns
+/// - `Ok(())` once the device is configured, and measures if a session was running.
+/// - `Err(EIO)` if measurement can't be enabled, the session stays stopped.
+pub (crate) fn adxl345_power_resume(device: &Arc<Mutex<Adxl345>>) -> Result {
+    // SAFETY: The lock is initialized at module init.
+    let _config = unsafe { ADXL345_CONFIG_LOCK.lock() };
+
+    // The supply may have been cut while the system slept. A chip not answering yet is
+    // compared again by the drain
+    let id = device.lock().id();
+    if adxl345_shadow(id).check(&device.lock()).is_err() {
+        adxl345_shadow(id).request();
+    }
+
+    if !adxl345_power(id).resume_session.swap(false, Ordering::Relaxed) {
+        return Ok(());
+    }
+    let context = match adxl345_context(id) {
+        Ok(context) => context,
+        Err(_) => return Ok(()),
+    };
+    let drain = &context.drain;
+    adxl345_device_init_at_open(device.clone()).map_err(|_| EIO)?;
+    if adxl345_session(id).header_enabled() && !drain.push_header() {
+        pr_warn!("No room for the header of the resumed session\n");
+    }
+    Adxl345Drain::resume(drain);
+    Ok(())
+}
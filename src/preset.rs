@@ -0,0 +1,174 @@
+/*
+ * Copyright 2024 Luca Saverio Esposito, Università di Roma, Tor Vergata
+ * email: <lucasaverioesposito@gmail.com>
+ *
+ * This file is part of an "Rust Linux driver for the ADXL345 device".
+ *
+ * This driver is free software: you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * This driver is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with Foobar.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+
+// preset.rs
+
+//! Named configuration presets.
+//!
+//! An application saves the configuration in use under a name (`ADXL345_IOC_PRESET_SAVE`), and
+//! later switches back to it with a single call (`ADXL345_IOC_PRESET_APPLY`), e.g. between a
+//! "low-power monitor" and a "high-rate capture" mode. A preset holds every configuration
+//! parameter, the FIFO mode and the interrupt routing, as a startup profile does.
+//!
+//! Presets live in the driver until they are deleted (`ADXL345_IOC_PRESET_DELETE`) or the module
+//! is unloaded. They are listed in the `presets` debugfs file.
+
+use kernel::prelude::*;
+use kernel::error::code::{EINVAL, ENOENT, ENOSPC};
+use kernel::io_buffer::ReadableFromBytes;
+use kernel::sync::{Arc, Mutex};
+use crate::ioctl::ADXL345_CONFIG_LOCK;
+use crate::profile::Adxl345Profile;
+use crate::snapshot::adxl345_snapshot_refresh;
+use crate::structures::Adxl345;
+
+/// Longest preset name, in bytes.
+const ADXL345_PRESET_NAME_LEN: usize = 16;
+
+/// Largest number of presets.
+const ADXL345_PRESETS_MAX: usize = 8;
+
+/// Argument of the preset ioctls: the name, padded with NUL bytes.
+///
+/// A name is made of printable ASCII characters other than space, and needs no terminating NUL
+/// when it is `ADXL345_PRESET_NAME_LEN` bytes long.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub (crate) struct Adxl345PresetName {
+    pub (crate) name: [u8; ADXL345_PRESET_NAME_LEN],
+}
+
+// SAFETY: Any byte pattern is a valid name buffer, the content is checked by `validate`.
+unsafe impl ReadableFromBytes for Adxl345PresetName {}
+
+impl Adxl345PresetName {
+    /// Checks the name provided by user space.
+    ///
+    /// # Returns
+    /// - `Ok(Self)` with the bytes after the name cleared, so names compare as arrays.
+    /// - `Err(EINVAL)` if the name is empty or holds other characters.
+    pub (crate) fn validate(mut self) -> Result<Self> {
+        let len = self.len();
+        if len == 0 || !self.name[..len].iter().all(|c| c.is_ascii_graphic()) {
+            return Err(EINVAL);
+        }
+        self.name[len..].fill(0);
+        Ok(self)
+    }
+
+    /// Returns the length of the name, up to the first NUL byte.
+    fn len(&self) -> usize {
+        self.name.iter().position(|&c| c == 0).unwrap_or(ADXL345_PRESET_NAME_LEN)
+    }
+
+    /// Returns the name, it is ASCII once validated.
+    fn as_str(&self) -> &str {
+        core::str::from_utf8(&self.name[..self.len()]).unwrap_or("?")
+    }
+}
+
+/// A saved configuration.
+#[derive(Clone, Copy)]
+struct Adxl345Preset {
+    name: Adxl345PresetName,
+    profile: Adxl345Profile,
+}
+
+/// Saved presets, protected by `ADXL345_CONFIG_LOCK`.
+static mut ADXL345_PRESETS: [Option<Adxl345Preset>; ADXL345_PRESETS_MAX] = [None; ADXL345_PRESETS_MAX];
+
+/// Returns the slot of the preset named `name`.
+///
+/// # Safety
+/// The caller holds `ADXL345_CONFIG_LOCK`.
+unsafe fn adxl345_preset_find(name: &Adxl345PresetName) -> Option<usize> {
+    unsafe { ADXL345_PRESETS.iter() }
+        .position(|preset| preset.map_or(false, |preset| preset.name.name == name.name))
+}
+
+/// Saves the configuration in use as `name`, replacing the preset with the same name if any.
+///
+/// # Safety
+/// The caller holds `ADXL345_CONFIG_LOCK`.
+///
+/// # Returns
+/// - `Ok(())` once the preset is saved.
+/// - `Err(ENOSPC)` if `ADXL345_PRESETS_MAX` other presets exist.
+/// - `Err(Error)` if a register transaction failed.
+pub (crate) unsafe fn adxl345_preset_save(device: &Arc<Mutex<Adxl345>>, name: Adxl345PresetName) -> Result {
+    let slot = match unsafe { adxl345_preset_find(&name) } {
+        Some(slot) => slot,
+        None => unsafe { ADXL345_PRESETS.iter() }.position(Option::is_none).ok_or(ENOSPC)?,
+    };
+    let profile = Adxl345Profile::capture(&device.lock())?;
+    unsafe { ADXL345_PRESETS[slot] = Some(Adxl345Preset { name, profile }) };
+    pr_info!("Preset {} saved\n", name.as_str());
+    Ok(())
+}
+
+/// Applies the preset `name` and publishes the new configuration.
+///
+/// # Safety
+/// The caller holds `ADXL345_CONFIG_LOCK`.
+///
+/// # Returns
+/// - `Ok(())` once the preset is applied.
+/// - `Err(ENOENT)` if there is no such preset.
+/// - `Err(Error)` if a register transaction failed, the preset may be partly applied.
+pub (crate) unsafe fn adxl345_preset_apply(device: &Arc<Mutex<Adxl345>>, name: Adxl345PresetName) -> Result {
+    let slot = unsafe { adxl345_preset_find(&name) }.ok_or(ENOENT)?;
+    let profile = unsafe { ADXL345_PRESETS[slot] }.ok_or(ENOENT)?.profile;
+    let applied = profile.apply(&device.lock());
+    adxl345_snapshot_refresh(device)?;
+    applied?;
+    pr_info!("Preset {} applied\n", name.as_str());
+    Ok(())
+}
+
+/// Deletes the preset `name`.
+///
+/// # Safety
+/// The caller holds `ADXL345_CONFIG_LOCK`.
+///
+/// # Returns
+/// - `Ok(())` once the preset is deleted.
+/// - `Err(ENOENT)` if there is no such preset.
+pub (crate) unsafe fn adxl345_preset_delete(name: Adxl345PresetName) -> Result {
+    let slot = unsafe { adxl345_preset_find(&name) }.ok_or(ENOENT)?;
+    unsafe { ADXL345_PRESETS[slot] = None };
+    Ok(())
+}
+
+/// Lists the presets for the `presets` debugfs file, one per line: the name followed by its
+/// entries, as `name=value` pairs in human units.
+pub (crate) fn adxl345_presets_text() -> Result<Vec<u8>> {
+    // SAFETY: The lock is initialized at module init.
+    let _config = unsafe { ADXL345_CONFIG_LOCK.lock() };
+
+    let mut text = Vec::new();
+    // SAFETY: The presets are protected by the configuration lock, held above.
+    for preset in unsafe { ADXL345_PRESETS.iter() }.flatten() {
+        text.try_extend_from_slice(preset.name.as_str().as_bytes())?;
+        preset.profile.text(&mut text)?;
+        text.try_push(b'\n')?;
+    }
+    Ok(text)
+}
@@ -0,0 +1,203 @@
+/*
+ * Copyright 2024 Luca Saverio Esposito, Università di Roma, Tor Vergata
+ * email: <lucasaverioesposito@gmail.com>
+ *
+ * This file is part of an "Rust Linux driver for the ADXL345 device".
+ *
+ * This driver is free software: you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * This driver is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with Foobar.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+
+
+
+
+// probe_health.rs
+
+//! Probe-time sanity acquisition.
+//!
+//! With `probe_samples=<n>` (2 to 32) the single test read of probe becomes a short acquisition:
+//! the mean and standard deviation of each axis are logged and kept in the `probe_health`
+//! debugfs file, with a verdict telling a sensor that works at rest from one that never converts,
+//! whose output is frozen or that reads an implausible gravity. The acquisition never makes probe
+//! fail by itself, only a bus error does, as for the single test read.
+
+use kernel::prelude::*;
+use kernel::delay::coarse_sleep;
+use kernel::str::CString;
+use kernel::sync::{Arc, Mutex};
+use core::sync::atomic::{AtomicI32, AtomicU32, Ordering};
+use core::time::Duration;
+use crate::structures::Adxl345;
+use crate::fixed::{adxl345_isqrt, adxl345_units_to_mg};
+
+/// Largest acquisition, the FIFO depth.
+pub (crate) const ADXL345_PROBE_SAMPLES_MAX: u32 = 32;
+
+/// How long a sample is waited for, enough for the rates of 10 Hz and more programmed at probe
+/// (the `rate` module parameter, 100 Hz by default): a lower rate reports no data.
+const ADXL345_PROBE_WAIT_MS: u32 = 100;
+
+/// Gravity at rest must read between these magnitudes, in mg.
+const ADXL345_PROBE_GRAVITY_MIN_MG: i64 = 500;
+const ADXL345_PROBE_GRAVITY_MAX_MG: i64 = 1500;
+
+/// Outcome of the acquisition.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub (crate) enum Adxl345ProbeVerdict {
+    Disabled = 0,     // Single test read, no acquisition
+    Ok = 1,           // Noisy samples around 1 g
+    NoData = 2,       // DATA_READY never set: the sensor doesn't convert
+    Stuck = 3,        // Every sample identical on every axis: the output is frozen
+    Implausible = 4,  // Gravity out of 0.5..1.5 g: moving, or failing
+    BusError = 5,     // A register transaction failed
+}
+
+impl Adxl345ProbeVerdict {
+    fn from_raw(raw: u32) -> Self {
+        match raw {
+            1 => Self::Ok,
+            2 => Self::NoData,
+            3 => Self::Stuck,
+            4 => Self::Implausible,
+            5 => Self::BusError,
+            _ => Self::Disabled,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Disabled => "disabled",
+            Self::Ok => "ok",
+            Self::NoData => "no_data",
+            Self::Stuck => "stuck",
+            Self::Implausible => "implausible",
+            Self::BusError => "bus_error",
+        }
+    }
+}
+
+/// Result of the last probe acquisition, read by debugfs.
+pub (crate) struct Adxl345ProbeHealth {
+    verdict: AtomicU32,
+    samples: AtomicU32,
+    mean_mg: [AtomicI32; 3],
+    stddev_mg: [AtomicU32; 3],
+}
+
+/// Health record of the probed device.
+pub (crate) static ADXL345_PROBE_HEALTH: Adxl345ProbeHealth = Adxl345ProbeHealth {
+    verdict: AtomicU32::new(Adxl345ProbeVerdict::Disabled as u32),
+    samples: AtomicU32::new(0),
+    mean_mg: [AtomicI32::new(0), AtomicI32::new(0), AtomicI32::new(0)],
+    stddev_mg: [AtomicU32::new(0), AtomicU32::new(0), AtomicU32::new(0)],
+};
+
+impl Adxl345ProbeHealth {
+    fn set_verdict(&self, verdict: Adxl345ProbeVerdict) {
+        self.verdict.store(verdict as u32, Ordering::Relaxed);
+    }
+
+    /// Formats the record for the `probe_health` debugfs file.
+    pub (crate) fn text(&self) -> Result<CString> {
+        let mean = [0, 1, 2].map(|axis| self.mean_mg[axis].load(Ordering::Relaxed));
+        let stddev = [0, 1, 2].map(|axis| self.stddev_mg[axis].load(Ordering::Relaxed));
+        CString::try_from_fmt(fmt!(
+            "verdict {}\nsamples {}\nmean_mg {} {} {}\nstddev_mg {} {} {}\n",
+            Adxl345ProbeVerdict::from_raw(self.verdict.load(Ordering::Relaxed)).name(),
+            self.samples.load(Ordering::Relaxed),
+            mean[0], mean[1], mean[2],
+            stddev[0], stddev[1], stddev[2]
+        ))
+    }
+}
+
+/// Reads `samples` samples from the measuring device and records their statistics.
+///
+/// The device lock is only held for the register transactions, never while waiting.
+///
+/// # Returns
+/// - `Ok(())` once the record is updated, whatever the verdict.
+/// - `Err(Error)` if a register transaction failed.
+pub (crate) fn adxl345_probe_acquire(device: &Arc<Mutex<Adxl345>>, samples: u32) -> Result {
+    let health = &ADXL345_PROBE_HEALTH;
+    let samples = samples.min(ADXL345_PROBE_SAMPLES_MAX);
+    let mut sum = [0i64; 3];
+    let mut sum_sq = [0i64; 3];
+    let mut first = None;
+    let mut identical = true;
+
+    health.samples.store(0, Ordering::Relaxed);
+    for n in 0..samples {
+        // Wait for the next conversion, sleeping without the lock
+        let mut waited = 0;
+        loop {
+            let ready = device.lock().data_ready().map_err(|e| {
+                health.set_verdict(Adxl345ProbeVerdict::BusError);
+                e
+            })?;
+            if ready != 0 {
+                break;
+            }
+            if waited == ADXL345_PROBE_WAIT_MS {
+                pr_warn!("Probe health: no data after {} samples, the sensor doesn't convert\n", n);
+                health.set_verdict(Adxl345ProbeVerdict::NoData);
+                return Ok(());
+            }
+            coarse_sleep(Duration::from_millis(1));
+            waited += 1;
+        }
+
+        let sample = device.lock().read_data().map_err(|e| {
+            health.set_verdict(Adxl345ProbeVerdict::BusError);
+            e
+        })?;
+        let axes = [sample.x as i64, sample.y as i64, sample.z as i64];
+        for axis in 0..3 {
+            sum[axis] += axes[axis];
+            sum_sq[axis] += axes[axis] * axes[axis];
+        }
+        identical &= *first.get_or_insert(axes) == axes;
+        health.samples.store(n + 1, Ordering::Relaxed);
+    }
+
+    let count = samples as i64;
+    let mut magnitude_sq = 0;
+    for axis in 0..3 {
+        let mean = adxl345_units_to_mg(sum[axis]) / count;
+        // Variance in units², exact with integers: (n Σx² - (Σx)²) / n²
+        let variance = (count * sum_sq[axis] - sum[axis] * sum[axis]) / (count * count);
+        health.mean_mg[axis].store(mean as i32, Ordering::Relaxed);
+        health.stddev_mg[axis].store(adxl345_units_to_mg(adxl345_isqrt(variance as u64) as i64) as u32, Ordering::Relaxed);
+        magnitude_sq += mean * mean;
+    }
+
+    let magnitude = adxl345_isqrt(magnitude_sq as u64) as i64;
+    let verdict = if identical {
+        Adxl345ProbeVerdict::Stuck
+    } else if !(ADXL345_PROBE_GRAVITY_MIN_MG..=ADXL345_PROBE_GRAVITY_MAX_MG).contains(&magnitude) {
+        Adxl345ProbeVerdict::Implausible
+    } else {
+        Adxl345ProbeVerdict::Ok
+    };
+    health.set_verdict(verdict);
+
+    let mean = [0, 1, 2].map(|axis| health.mean_mg[axis].load(Ordering::Relaxed));
+    let stddev = [0, 1, 2].map(|axis| health.stddev_mg[axis].load(Ordering::Relaxed));
+    pr_info!(
+        "Probe health: {} samples, mean {} {} {} mg, stddev {} {} {} mg, |g| {} mg: {}\n",
+        samples, mean[0], mean[1], mean[2], stddev[0], stddev[1], stddev[2], magnitude, verdict.name()
+    );
+    Ok(())
+}
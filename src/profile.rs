@@ -0,0 +1,268 @@
+/*
+ * Copyright 2024 Luca Saverio Esposito, Università di Roma, Tor Vergata
+ * email: <lucasaverioesposito@gmail.com>
+ *
+ * This file is part of an "Rust Linux driver for the ADXL345 device".
+ *
+ * This driver is free software: you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * This driver is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with Foobar.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+
+
+// profile.rs
+
+//! Startup configuration profile.
+//!
+//! Appliances can get a reproducible configuration from probe on, without init scripts: a
+//! complete profile (rate, range, FIFO mode and watermark, thresholds and durations, interrupt
+//! routing) is applied right after the defaults. It comes from the `profile` module parameter, a
+//! packed list of `name=value` pairs:
+//!
+//! ```text
+//! modprobe adxl345 profile=rate=400000,range=4,fifo_mode=stream,watermark=16,thresh_act=250,int_map=0x10
+//! ```
+//!
+//! or, if the parameter is not set, from the device tree: the node of the device if it has one
+//! of the properties (see of_node.rs), else a node compatible with `adi,adxl345-profile`. The
+//! properties are the same names with dashes (`thresh-act = <250>;`, `fifo-mode = "stream";`).
+//!
+//! Values are in human units, as with `ADXL345_IOC_SET_PARAM_SCALED`: rate in mHz, range in g,
+//! thresholds in mg and durations in µs. `fifo_mode` is `bypass`, `fifo`, `stream` or `trigger`,
+//! `int_map` the INT_MAP register (decimal or `0x` hexadecimal).
+//!
+//! The profile is parsed and validated whole before anything is written: a single invalid entry
+//! rejects it and probe goes on with the defaults. A valid profile is written under the device
+//! lock and before the configuration snapshot is published, so nobody sees it half applied.
+
+use kernel::prelude::*;
+use kernel::error::code::EINVAL;
+use kernel::str::CString;
+use crate::config::{Adxl345Param, adxl345_from_scaled, adxl345_to_scaled, adxl345_validate, ADXL345_PARAMS};
+use crate::constant::{ADXL345_REG_FIFO_CTL, ADXL345_REG_INT_MAP};
+use crate::structures::Adxl345;
+use kernel::sync::Mutex;
+#[cfg(CONFIG_OF)]
+use crate::of_node::Adxl345OfNode;
+
+/// FIFO modes, indexed by the FIFO_CTL mode field.
+const ADXL345_FIFO_MODES: [&str; 4] = ["bypass", "fifo", "stream", "trigger"];
+
+/// A validated startup profile, entries left out keep their default.
+#[derive(Clone, Copy)]
+pub (crate) struct Adxl345Profile {
+    params: [Option<u32>; ADXL345_PARAMS],   // Values in register LSBs, indexed by parameter id
+    fifo_mode: Option<u8>,                   // FIFO_CTL mode field
+    int_map: Option<u8>,                     // INT_MAP register
+}
+
+impl Adxl345Profile {
+    const fn new() -> Self {
+        Self {
+            params: [None; ADXL345_PARAMS],
+            fifo_mode: None,
+            int_map: None,
+        }
+    }
+
+    /// Loads the profile to apply at probe, from the module parameter or else the device tree.
+    ///
+    /// # Parameters
+    /// - `param`: The value of the `profile` module parameter, empty if not set.
+    /// - `device`: The device being probed, whose device tree node may hold the profile.
+    ///
+    /// # Returns
+    /// The profile, or `None` if there is none or it is invalid (the reason is logged).
+    pub (crate) fn load(param: &[u8], device: &Mutex<Adxl345>) -> Option<Self> {
+        let (source, profile) = if !param.is_empty() {
+            ("module parameter", Self::parse(param))
+        } else {
+            match Self::from_device_tree(device) {
+                Some(profile) => ("device tree", profile),
+                None => return None,
+            }
+        };
+        match profile {
+            Ok(profile) => {
+                pr_info!("Startup profile from the {}\n", source);
+                Some(profile)
+            }
+            Err(_) => {
+                pr_err!("Invalid startup profile from the {}, using the defaults\n", source);
+                None
+            }
+        }
+    }
+
+    /// Parses a packed `name=value,name=value` profile.
+    fn parse(text: &[u8]) -> Result<Self> {
+        let text = core::str::from_utf8(text).map_err(|_| EINVAL)?;
+        let mut profile = Self::new();
+        for entry in text.trim().split(',').filter(|entry| !entry.is_empty()) {
+            let (name, value) = entry.split_once('=').ok_or(EINVAL)?;
+            profile.set(name.trim(), value.trim())?;
+        }
+        Ok(profile)
+    }
+
+    /// Validates and records a profile entry.
+    fn set(&mut self, name: &str, value: &str) -> Result {
+        if name == "fifo_mode" {
+            let mode = ADXL345_FIFO_MODES.iter().position(|&mode| mode == value);
+            self.fifo_mode = Some(mode.ok_or_else(|| Self::reject(name, value))? as u8);
+            return Ok(());
+        }
+
+        let number = match value.strip_prefix("0x") {
+            Some(hex) => u32::from_str_radix(hex, 16),
+            None => value.parse(),
+        }
+        .map_err(|_| Self::reject(name, value))?;
+        if name == "int_map" {
+            self.int_map = Some(u8::try_from(number).map_err(|_| Self::reject(name, value))?);
+            return Ok(());
+        }
+
+        let param = Adxl345Param::from_name(name).ok_or_else(|| Self::reject(name, value))?;
+        self.set_param(param, number)
+    }
+
+    /// Validates and records a parameter, in human units.
+    fn set_param(&mut self, param: Adxl345Param, value: u32) -> Result {
+        let raw = adxl345_from_scaled(param, value)
+            .and_then(|raw| adxl345_validate(param, raw).map(|_| raw))
+            .map_err(|e| {
+                pr_err!("Startup profile: invalid {} = {}\n", param.name(), value);
+                e
+            })?;
+        self.params[param as usize] = Some(raw);
+        Ok(())
+    }
+
+    /// Logs an entry that is not understood and returns `EINVAL`.
+    fn reject(name: &str, value: &str) -> Error {
+        pr_err!("Startup profile: invalid entry {}={}\n", name, value);
+        EINVAL
+    }
+
+    /// Reads the profile from the node of `device`, or else from the first device tree node
+    /// compatible with `adi,adxl345-profile`.
+    ///
+    /// # Returns
+    /// `None` if neither holds a profile, otherwise the outcome of its validation.
+    #[cfg(CONFIG_OF)]
+    fn from_device_tree(device: &Mutex<Adxl345>) -> Option<Result<Self>> {
+        use kernel::c_str;
+        use kernel::str::CStr;
+
+        /// Property names, indexed by parameter id.
+        const NAMES: [&CStr; ADXL345_PARAMS] = [
+            c_str!("rate"), c_str!("range"), c_str!("watermark"), c_str!("thresh-tap"),
+            c_str!("dur"), c_str!("latent"), c_str!("window"), c_str!("thresh-act"),
+            c_str!("thresh-inact"), c_str!("time-inact"), c_str!("thresh-ff"), c_str!("time-ff"),
+        ];
+
+        let read = |node: &Adxl345OfNode| -> Result<Self> {
+            let mut profile = Self::new();
+            for (id, name) in NAMES.iter().enumerate() {
+                if let Some(value) = node.read_u32(name) {
+                    profile.set_param(Adxl345Param::from_raw(id as u32)?, value)?;
+                }
+            }
+            if let Some(value) = node.read_u32(c_str!("int-map")) {
+                profile.int_map = Some(u8::try_from(value).map_err(|_| EINVAL)?);
+            }
+            if let Some(mode) = node.read_str(c_str!("fifo-mode")) {
+                profile.set("fifo_mode", mode.to_str().map_err(|_| EINVAL)?)?;
+            }
+            Ok(profile)
+        };
+
+        // The node of the device only holds a profile if it sets one of the entries
+        if let Some(node) = Adxl345OfNode::of_device(device) {
+            match read(&node) {
+                Ok(profile) if profile.is_empty() => {}
+                profile => return Some(profile),
+            }
+        }
+        let node = Adxl345OfNode::find_compatible(c_str!("adi,adxl345-profile"))?;
+        Some(read(&node))
+    }
+
+    /// Without device tree support, the profile can only come from the module parameter.
+    #[cfg(not(CONFIG_OF))]
+    fn from_device_tree(_device: &Mutex<Adxl345>) -> Option<Result<Self>> {
+        None
+    }
+
+    /// Returns true if no entry is set.
+    #[cfg(CONFIG_OF)]
+    fn is_empty(&self) -> bool {
+        self.params.iter().all(Option::is_none) && self.fifo_mode.is_none() && self.int_map.is_none()
+    }
+
+    /// Captures the whole configuration of the device, with the device lock held by the caller.
+    ///
+    /// # Returns
+    /// - `Ok(Self)` with every entry set.
+    /// - `Err(Error)` if a register transaction failed.
+    pub (crate) fn capture(adxl: &Adxl345) -> Result<Self> {
+        let mut profile = Self::new();
+        for (id, value) in profile.params.iter_mut().enumerate() {
+            *value = Some(adxl.get_param(Adxl345Param::from_raw(id as u32)?)?);
+        }
+        profile.fifo_mode = Some(adxl.read_register(ADXL345_REG_FIFO_CTL)? >> 6);
+        profile.int_map = Some(adxl.read_register(ADXL345_REG_INT_MAP)?);
+        Ok(profile)
+    }
+
+    /// Formats the entries that are set as `name=value` pairs in human units, the syntax of the
+    /// `profile` module parameter.
+    pub (crate) fn text(&self, text: &mut Vec<u8>) -> Result {
+        for (id, value) in self.params.iter().enumerate() {
+            if let Some(value) = *value {
+                let param = Adxl345Param::from_raw(id as u32)?;
+                let value = adxl345_to_scaled(param, value);
+                text.try_extend_from_slice(CString::try_from_fmt(fmt!(" {}={}", param.name(), value))?.as_bytes())?;
+            }
+        }
+        if let Some(mode) = self.fifo_mode {
+            let mode = ADXL345_FIFO_MODES[mode as usize];
+            text.try_extend_from_slice(CString::try_from_fmt(fmt!(" fifo_mode={}", mode))?.as_bytes())?;
+        }
+        if let Some(map) = self.int_map {
+            text.try_extend_from_slice(CString::try_from_fmt(fmt!(" int_map=0x{:02x}", map))?.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Writes the profile to the device, with the device lock held by the caller.
+    ///
+    /// # Returns
+    /// - `Ok(())` once every entry is written.
+    /// - `Err(Error)` if a register transaction failed.
+    pub (crate) fn apply(&self, adxl: &Adxl345) -> Result {
+        for (id, value) in self.params.iter().enumerate() {
+            if let Some(value) = *value {
+                adxl.set_param(Adxl345Param::from_raw(id as u32)?, value)?;
+            }
+        }
+        if let Some(mode) = self.fifo_mode {
+            adxl.update_register(ADXL345_REG_FIFO_CTL, 0xC0, mode << 6)?;
+        }
+        if let Some(map) = self.int_map {
+            adxl.write_register(ADXL345_REG_INT_MAP, map)?;
+        }
+        Ok(())
+    }
+}
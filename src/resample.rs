@@ -0,0 +1,158 @@
+/*
+ * Copyright 2024 Luca Saverio Esposito, Università di Roma, Tor Vergata
+ * email: <lucasaverioesposito@gmail.com>
+ *
+ * This file is part of an "Rust Linux driver for the ADXL345 device".
+ *
+ * This driver is free software: you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * This driver is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with Foobar.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+
+// resample.rs
+
+//! Per-file resampling to a fixed output rate.
+//!
+//! A control loop wants its input at a fixed rate, e.g. exactly 100 Hz, whatever rate the device
+//! runs at for the other readers. With `ADXL345_IOC_SET_RESAMPLE` an open file asks for an
+//! output rate in mHz: its samples are then computed at that rate by linear interpolation
+//! between the two device samples around each output instant, up or down from the device rate.
+//! 0 turns it off, the other files are not affected.
+//!
+//! The output instants are kept exactly: with the device period `1 / r_in` and the output period
+//! `1 / r_out`, time is counted in units of `1 / (r_in * r_out)`, so a device period is `r_out`
+//! units and an output period `r_in` units, and no rounding accumulates over a long run.
+//!
+//! The interpolation assumes evenly spaced device samples: the read filter, which drops samples
+//! on purpose, is bypassed for a resampling file, and samples dropped on overrun show up as a
+//! longer gap squeezed into one period. Markers pass through unchanged, in stream order with
+//! the interpolated samples. An output sample needs the device sample after it, so the output
+//! lags the device by up to one device period. The state starts again when the device rate or
+//! the output rate changes, and at a session header.
+//!
+//! The state is only touched by a read holding the consumer lock of the drain, which serializes
+//! every reader.
+
+use kernel::prelude::*;
+use kernel::error::code::ERANGE;
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicU32, Ordering};
+use crate::drain::Adxl345Consumer;
+use crate::structures::Adxl345Sample;
+
+/// Highest output rate, in mHz: the highest device rate.
+const ADXL345_RESAMPLE_MAX_MHZ: u32 = 3_200_000;
+
+/// Interpolation state, see `Adxl345Resampler`.
+struct Adxl345ResampleState {
+    in_mhz: u32,                     // Device rate the state was built for, 0 to build it again
+    out_mhz: u32,                    // Output rate the state was built for
+    prev: Option<Adxl345Sample>,     // Device sample at the start of the current period
+    cur: Option<Adxl345Sample>,      // Device sample at its end
+    next: u64,                       // Next output instant, from `prev`, in 1 / (r_in * r_out)
+}
+
+/// Resampler of an open file.
+pub (crate) struct Adxl345Resampler {
+    out_mhz: AtomicU32,              // Output rate requested, 0 if off
+    state: UnsafeCell<Adxl345ResampleState>,
+}
+
+// SAFETY: `state` is only accessed with the consumer lock held, as proven by the guard each
+// method takes.
+unsafe impl Sync for Adxl345Resampler {}
+
+impl Adxl345Resampler {
+    pub (crate) const fn new() -> Self {
+        Self {
+            out_mhz: AtomicU32::new(0),
+            state: UnsafeCell::new(Adxl345ResampleState {
+                in_mhz: 0,
+                out_mhz: 0,
+                prev: None,
+                cur: None,
+                next: 0,
+            }),
+        }
+    }
+
+    /// Sets the output rate in mHz, 0 turns resampling off.
+    ///
+    /// # Returns
+    /// `Err(ERANGE)` above the highest device rate.
+    pub (crate) fn set_rate(&self, out_mhz: u32) -> Result {
+        if out_mhz > ADXL345_RESAMPLE_MAX_MHZ {
+            return Err(ERANGE);
+        }
+        self.out_mhz.store(out_mhz, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Returns true if the file resamples its samples.
+    pub (crate) fn is_active(&self) -> bool {
+        self.out_mhz.load(Ordering::Relaxed) != 0
+    }
+
+    /// Returns the state, started again if a rate changed since it was built.
+    #[allow(clippy::mut_from_ref)]
+    fn state(&self, in_mhz: u32, _consumer: &Adxl345Consumer<'_>) -> &mut Adxl345ResampleState {
+        // SAFETY: The consumer lock is held, as proven by the guard, and the reference doesn't
+        // outlive it.
+        let state = unsafe { &mut *self.state.get() };
+        let out_mhz = self.out_mhz.load(Ordering::Relaxed);
+        if state.in_mhz != in_mhz || state.out_mhz != out_mhz {
+            *state = Adxl345ResampleState { in_mhz, out_mhz, prev: None, cur: None, next: 0 };
+        }
+        state
+    }
+
+    /// Starts again from the next device sample, e.g. at a session header.
+    pub (crate) fn reset(&self, _consumer: &Adxl345Consumer<'_>) {
+        // SAFETY: The consumer lock is held, as proven by the guard. A state built for no device
+        // rate is built again by the next access.
+        unsafe { (*self.state.get()).in_mhz = 0 };
+    }
+
+    /// Feeds the next device sample, taken at `in_mhz`.
+    ///
+    /// It must be called only once `take()` returned `None`, the current period is done then.
+    pub (crate) fn push(&self, sample: Adxl345Sample, in_mhz: u32, consumer: &Adxl345Consumer<'_>) {
+        let state = self.state(in_mhz, consumer);
+        if let Some(cur) = state.cur.take() {
+            state.next = state.next.saturating_sub(state.out_mhz as u64);
+            state.prev = Some(cur);
+            state.cur = Some(sample);
+        } else if state.prev.is_some() {
+            state.cur = Some(sample);
+        } else {
+            state.prev = Some(sample);
+        }
+    }
+
+    /// Returns the next output sample if it lies within the current device period, `None` once
+    /// the next device sample is needed.
+    pub (crate) fn take(&self, in_mhz: u32, consumer: &Adxl345Consumer<'_>) -> Option<Adxl345Sample> {
+        let state = self.state(in_mhz, consumer);
+        let (prev, cur) = (state.prev?, state.cur?);
+        let period = state.out_mhz as u64;
+        if state.next >= period {
+            return None;
+        }
+
+        // `next` is below `period`, the result lies between `a` and `b`
+        let next = state.next as i64;
+        let lerp = |a: i16, b: i16| (a as i64 + (b as i64 - a as i64) * next / period as i64) as i16;
+        state.next += in_mhz as u64;
+        Some(Adxl345Sample::new(lerp(prev.x, cur.x), lerp(prev.y, cur.y), lerp(prev.z, cur.z)))
+    }
+}
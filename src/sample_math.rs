@@ -0,0 +1,201 @@
+/*
+ * Copyright 2024 Luca Saverio Esposito, Università di Roma, Tor Vergata
+ * email: <lucasaverioesposito@gmail.com>
+ *
+ * This file is part of an "Rust Linux driver for the ADXL345 device".
+ *
+ * This driver is free software: you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * This driver is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with Foobar.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+
+// sample_math.rs
+
+//! Saturating arithmetic on samples.
+//!
+//! The changes, sums and means of the filter stages (see filter.rs) and the sums of the offset
+//! calibration (see calibration.rs) are computed by these helpers in widened types, so a high-g
+//! shock, with samples at opposite full-scale values, can't overflow an `i16` and panic a kernel
+//! built with overflow checks.
+//!
+//! The module is pure, without kernel dependencies: `adxl345_test` includes it to run the tests
+//! at the bottom on the host (`cargo test` in adxl345_test), with inputs at both ends of the type.
+
+/// Returns the change of an axis between two samples. It is computed in `i32`: between samples
+/// of opposite signs near full scale it exceeds `i16::MAX`.
+pub (crate) const fn adxl345_axis_change(new: i16, last: i16) -> i32 {
+    (new as i32 - last as i32).abs()
+}
+
+/// Returns the sum of an axis over the averaging window once `new` replaces `old` in it. A full
+/// window at either rail sums to 16 times `i16::MIN`, far from the ends of `i32`, but the sum
+/// saturates anyway so that no window state can overflow.
+pub (crate) const fn adxl345_window_sum(sum: i32, new: i16, old: i16) -> i32 {
+    sum.saturating_add(new as i32 - old as i32)
+}
+
+/// Returns the mean of an axis over the `filled` samples of the averaging window, clamped to the
+/// range of a sample rather than truncated.
+pub (crate) const fn adxl345_window_mean(sum: i32, filled: usize) -> i16 {
+    let mean = sum / filled as i32;
+    if mean < i16::MIN as i32 {
+        i16::MIN
+    } else if mean > i16::MAX as i32 {
+        i16::MAX
+    } else {
+        mean as i16
+    }
+}
+
+/// Returns the count of the decimation stage after one more sample, back to 0 every `factor`
+/// samples. Compared rather than taken modulo, so it can't overflow whatever the count.
+pub (crate) const fn adxl345_decimate_next(seen: u32, factor: u32) -> u32 {
+    if seen.saturating_add(1) >= factor { 0 } else { seen + 1 }
+}
+
+/// Returns the sum of an axis of the calibration once `value` is added. At most 1000 samples are
+/// summed, far from the ends of `i64`, but the sum saturates like the other aggregates.
+pub (crate) const fn adxl345_calibration_sum(sum: i64, value: i16) -> i64 {
+    sum.saturating_add(value as i64)
+}
+
+/// Returns the spread of an axis, its largest minus its smallest value, or 0 before any sample,
+/// when the bounds are still `i64::MAX` and `i64::MIN` and their difference would overflow.
+pub (crate) const fn adxl345_calibration_spread(min: i64, max: i64) -> i64 {
+    if max < min { 0 } else { max.saturating_sub(min) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Averaging window of the largest average stage, `ADXL345_AVERAGE_MAX` in filter.rs.
+    const WINDOW: usize = 16;
+
+    /// Largest factor of the decimation stage, `ADXL345_DECIMATE_MAX` in filter.rs.
+    const DECIMATE_MAX: u32 = 1000;
+
+    /// Most samples of a calibration, `ADXL345_CALIBRATION_SAMPLES_MAX` in calibration.rs.
+    const CALIBRATION_MAX: i64 = 1000;
+
+    /// Rails of the 16 g range, in shifted LSBs.
+    const RAIL_LOW: i16 = -16384;
+    const RAIL_HIGH: i16 = 16380;
+
+    #[test]
+    fn axis_change_between_full_scale_values() {
+        assert_eq!(adxl345_axis_change(i16::MAX, i16::MIN), 65535);
+        assert_eq!(adxl345_axis_change(i16::MIN, i16::MAX), 65535);
+        assert_eq!(adxl345_axis_change(RAIL_LOW, RAIL_HIGH), 32764);
+        assert_eq!(adxl345_axis_change(RAIL_HIGH, RAIL_LOW), 32764);
+        assert_eq!(adxl345_axis_change(i16::MIN, i16::MIN), 0);
+        assert_eq!(adxl345_axis_change(0, 0), 0);
+    }
+
+    #[test]
+    fn window_sum_fills_and_empties_at_both_rails() {
+        for rail in [i16::MIN, i16::MAX] {
+            let mut sum = 0;
+            for _ in 0..WINDOW {
+                sum = adxl345_window_sum(sum, rail, 0);
+            }
+            assert_eq!(sum, WINDOW as i32 * rail as i32);
+            for _ in 0..WINDOW {
+                sum = adxl345_window_sum(sum, 0, rail);
+            }
+            assert_eq!(sum, 0);
+        }
+    }
+
+    #[test]
+    fn window_sum_swings_between_the_rails() {
+        // A full window at the low rail, replaced sample by sample by the high one
+        let mut sum = WINDOW as i32 * i16::MIN as i32;
+        for _ in 0..WINDOW {
+            sum = adxl345_window_sum(sum, i16::MAX, i16::MIN);
+        }
+        assert_eq!(sum, WINDOW as i32 * i16::MAX as i32);
+    }
+
+    #[test]
+    fn window_sum_saturates() {
+        assert_eq!(adxl345_window_sum(i32::MAX, i16::MAX, i16::MIN), i32::MAX);
+        assert_eq!(adxl345_window_sum(i32::MIN, i16::MIN, i16::MAX), i32::MIN);
+    }
+
+    #[test]
+    fn window_mean_of_full_scale_windows() {
+        assert_eq!(adxl345_window_mean(WINDOW as i32 * i16::MIN as i32, WINDOW), i16::MIN);
+        assert_eq!(adxl345_window_mean(WINDOW as i32 * i16::MAX as i32, WINDOW), i16::MAX);
+        assert_eq!(adxl345_window_mean(i16::MIN as i32 + i16::MAX as i32, 2), 0);
+        assert_eq!(adxl345_window_mean(-7, 2), -3);
+    }
+
+    #[test]
+    fn window_mean_clamps_to_a_sample() {
+        assert_eq!(adxl345_window_mean(i32::MAX, 1), i16::MAX);
+        assert_eq!(adxl345_window_mean(i32::MIN, 1), i16::MIN);
+        assert_eq!(adxl345_window_mean(i32::MIN, WINDOW), i16::MIN);
+    }
+
+    #[test]
+    fn decimate_keeps_one_sample_every_factor() {
+        for factor in [2, 3, DECIMATE_MAX] {
+            let mut seen = 0;
+            let mut kept = 0;
+            for _ in 0..factor * 4 {
+                kept += (seen == 0) as u32;
+                seen = adxl345_decimate_next(seen, factor);
+            }
+            assert_eq!(kept, 4);
+            assert_eq!(seen, 0);
+        }
+    }
+
+    #[test]
+    fn decimate_count_never_overflows() {
+        assert_eq!(adxl345_decimate_next(DECIMATE_MAX - 1, DECIMATE_MAX), 0);
+        assert_eq!(adxl345_decimate_next(u32::MAX, DECIMATE_MAX), 0);
+        assert_eq!(adxl345_decimate_next(u32::MAX - 1, u32::MAX), 0);
+    }
+
+    #[test]
+    fn calibration_sum_of_full_scale_samples() {
+        for rail in [i16::MIN, i16::MAX] {
+            let sum = (0..CALIBRATION_MAX).fold(0, |sum, _| adxl345_calibration_sum(sum, rail));
+            assert_eq!(sum, CALIBRATION_MAX * rail as i64);
+        }
+        let alternating = (0..CALIBRATION_MAX)
+            .fold(0, |sum, n| adxl345_calibration_sum(sum, if n % 2 == 0 { i16::MIN } else { i16::MAX }));
+        assert_eq!(alternating, -CALIBRATION_MAX / 2);
+    }
+
+    #[test]
+    fn calibration_sum_saturates() {
+        assert_eq!(adxl345_calibration_sum(i64::MAX, i16::MAX), i64::MAX);
+        assert_eq!(adxl345_calibration_sum(i64::MIN, i16::MIN), i64::MIN);
+    }
+
+    #[test]
+    fn calibration_spread_of_full_scale_samples() {
+        assert_eq!(adxl345_calibration_spread(i16::MIN as i64, i16::MAX as i64), 65535);
+        assert_eq!(adxl345_calibration_spread(RAIL_LOW as i64, RAIL_HIGH as i64), 32764);
+        assert_eq!(adxl345_calibration_spread(i16::MAX as i64, i16::MAX as i64), 0);
+    }
+
+    #[test]
+    fn calibration_spread_before_any_sample() {
+        assert_eq!(adxl345_calibration_spread(i64::MAX, i64::MIN), 0);
+        assert_eq!(adxl345_calibration_spread(i64::MIN, i64::MAX), i64::MAX);
+    }
+}
@@ -0,0 +1,153 @@
+/*
+ * Copyright 2024 Luca Saverio Esposito, Università di Roma, Tor Vergata
+ * email: <lucasaverioesposito@gmail.com>
+ *
+ * This file is part of an "Rust Linux driver for the ADXL345 device".
+ *
+ * This driver is free software: you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * This driver is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with Foobar.  If not, see <http://www.gnu.org/licenses/>.
+ */
+// scan.rs
+
+//! Runtime bus scan.
+//!
+//! Writing `1` to `/sys/module/adxl345/scan` looks for ADXL345s on the I2C buses of the
+//! `scan_buses` module parameter (the bus of `i2c_bus` when it is empty), at both addresses
+//! selectable with the ALT ADDRESS pin, as the `detect()` flow of a driver does: a device
+//! answering with the ADXL345 device id is one. Every device found is bound (see instance.rs)
+//! while slots are free, so hot-plugged evaluation boards are picked up without reloading the
+//! module.
+//!
+//! The client is created with `i2c_new_scanned_device`, which skips an address used by another
+//! client without any transfer: a device handled by another driver is never probed. The devices
+//! bound by this driver are not probed either, whoever created their client.
+//!
+//! Reading `scan` reports the devices found by the last scan, one per line:
+//! `bus address status`, where the status is `bound` (instantiated by this scan), `in_use` (a
+//! device bound there already, from the device tree, the module parameters, configfs, user space
+//! or an earlier scan), `busy` (not bound, all the slots are taken) or `error`.
+//!
+//! The scan issues transfers on the buses directly, it is refused in dry-run mode.
+
+use kernel::prelude::*;
+use kernel::bindings;
+use kernel::error::code::{EBUSY, EINVAL, ENXIO, EPERM};
+use kernel::i2c::I2CMsg;
+use kernel::str::CString;
+use kernel::sync::smutex::Mutex;
+use core::ffi::{c_char, c_int};
+use core::ptr;
+use core::sync::atomic::{AtomicI32, Ordering};
+use crate::constant::{ADXL345_DEVID, ADXL345_I2C_ADDRS, ADXL345_REG_DEVID};
+use crate::dry_run::ADXL345_DRY_RUN;
+use crate::instance::{adxl345_bound_at, adxl345_instance_detect};
+
+/// Largest number of buses scanned, the length of the `scan_buses` module parameter.
+const ADXL345_SCAN_BUSES_MAX: usize = 8;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const ADXL345_SCAN_NO_BUS: AtomicI32 = AtomicI32::new(-1);
+
+/// Buses scanned, set at module init, -1 past the last one.
+static ADXL345_SCAN_BUSES: [AtomicI32; ADXL345_SCAN_BUSES_MAX] = [ADXL345_SCAN_NO_BUS; ADXL345_SCAN_BUSES_MAX];
+
+/// Report of the last scan, empty until a scan runs.
+static ADXL345_SCAN_REPORT: Mutex<Vec<u8>> = Mutex::new(Vec::new());
+
+/// Sets the buses scanned, from the module parameters at module init.
+pub (crate) fn adxl345_scan_buses_set(buses: &[i32]) {
+    for (index, slot) in ADXL345_SCAN_BUSES.iter().enumerate() {
+        slot.store(buses.get(index).copied().unwrap_or(-1), Ordering::Relaxed);
+    }
+}
+
+/// Checks whether the device at `addr` on `adapter` answers with the ADXL345 device id, for
+/// `i2c_new_scanned_device`: returns 1 if it does.
+unsafe extern "C" fn adxl345_scan_detect(adapter: *mut bindings::i2c_adapter, addr: u16) -> c_int {
+    let mut reg = [ADXL345_REG_DEVID];
+    let mut id = [0u8];
+    let mut msgs = [
+        I2CMsg::new(addr, 0, &mut reg),
+        I2CMsg::new(addr, I2CMsg::I2C_M_RD, &mut id),
+    ];
+    // SAFETY: The adapter is valid during the scan, the messages and their buffers outlive the
+    // transfer. Nothing acknowledging the address fails it.
+    let ret = unsafe { bindings::i2c_transfer(adapter, msgs.as_mut_ptr() as *mut bindings::i2c_msg, 2) };
+    (ret == 2 && id[0] == ADXL345_DEVID) as c_int
+}
+
+/// Scans the buses and binds the devices found, as long as slots are free.
+///
+/// # Returns
+/// - `Ok(usize)` containing the number of devices found.
+/// - `Err(EPERM)` in dry-run mode.
+pub (crate) fn adxl345_scan() -> Result<usize> {
+    if ADXL345_DRY_RUN.enabled() {
+        return Err(EPERM);
+    }
+
+    let mut report = Vec::new();
+    let mut found = 0;
+    let buses = ADXL345_SCAN_BUSES.iter().map(|bus| bus.load(Ordering::Relaxed)).take_while(|&bus| bus >= 0);
+    for bus in buses {
+        for addr in ADXL345_I2C_ADDRS {
+            let status = if adxl345_bound_at(bus, addr) {
+                "in_use"
+            } else {
+                match adxl345_instance_detect(bus, addr, adxl345_scan_detect) {
+                    Ok(()) => "bound",
+                    Err(e) if e == ENXIO => continue,
+                    Err(e) if e == EBUSY => "busy",
+                    Err(_) => "error",
+                }
+            };
+            found += 1;
+            pr_info!("Scan: ADXL345 at 0x{:02x} on I2C bus {}, {}\n", addr, bus, status);
+            let line = CString::try_from_fmt(fmt!("{} 0x{:02x} {}\n", bus, addr, status))?;
+            report.try_extend_from_slice(line.as_bytes())?;
+        }
+    }
+
+    *ADXL345_SCAN_REPORT.lock() = report;
+    Ok(found)
+}
+
+/// Shows the report of the last scan, the `show()` of the `scan` module attribute.
+pub (crate) unsafe extern "C" fn adxl345_scan_show(
+    _attr: *mut bindings::module_attribute,
+    _mk: *mut bindings::module_kobject,
+    page: *mut c_char,
+) -> isize {
+    let report = ADXL345_SCAN_REPORT.lock();
+    // SAFETY: sysfs provides a page, the report has two short lines per bus scanned at most.
+    unsafe { ptr::copy_nonoverlapping(report.as_ptr(), page as *mut u8, report.len()) };
+    report.len() as isize
+}
+
+/// Runs a scan when `1` is written, the `store()` of the `scan` module attribute.
+pub (crate) unsafe extern "C" fn adxl345_scan_store(
+    _attr: *mut bindings::module_attribute,
+    _mk: *mut bindings::module_kobject,
+    buf: *const c_char,
+    count: usize,
+) -> isize {
+    // SAFETY: sysfs provides `count` bytes in `buf`.
+    let bytes = unsafe { core::slice::from_raw_parts(buf as *const u8, count) };
+    if core::str::from_utf8(bytes).map(str::trim) != Ok("1") {
+        return EINVAL.to_errno() as isize;
+    }
+    match adxl345_scan() {
+        Ok(_) => count as isize,
+        Err(e) => e.to_errno() as isize,
+    }
+}
@@ -0,0 +1,174 @@
+/*
+ * Copyright 2024 Luca Saverio Esposito, Università di Roma, Tor Vergata
+ * email: <lucasaverioesposito@gmail.com>
+ *
+ * This file is part of an "Rust Linux driver for the ADXL345 device".
+ *
+ * This driver is free software: you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * This driver is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with Foobar.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+
+
+// session.rs
+
+//! Self-describing stream header.
+//!
+//! When enabled with `ADXL345_IOC_SET_HEADER`, every session started with `ADXL345_IOC_START`
+//! begins with a header describing it, so a capture saved as is (e.g. with `dd` or `cat`) can be
+//! decoded without knowing how the device was configured.
+//! In burst mode (see burst.rs) every burst begins with a header too, queued with its samples.
+//!
+//! The header is a run of `ADXL345_HEADER_WORDS` marker records of kind `ADXL345_MARKER_HEADER`,
+//! each carrying one 16-bit word in its z field. Readers that skip markers skip the header too.
+//! The words are, in order:
+//!
+//! | Word | Content |
+//! |------|---------|
+//! | 0 | Stream version, `ADXL345_STREAM_VERSION` |
+//! | 1 | Number of words in the header, `ADXL345_HEADER_WORDS` |
+//! | 2 | Measurement range, in g |
+//! | 3 | `CLOCK_*` id of the timestamp clock |
+//! | 4-5 | Output data rate in mHz, least significant word first |
+//! | 6-9 | Start timestamp in ns, in the timestamp clock, least significant word first |
+//! | 10 | Threshold of the read filter, in shifted LSBs, -1 if built without it (see filter.rs) |
+//!
+//! Each device has its own session state, selected by its id (see instance.rs), and its header
+//! describes its own configuration.
+
+use kernel::sync::smutex;
+use kernel::time::ClockId;
+use core::sync::atomic::{AtomicBool, Ordering};
+use crate::constant::ADXL345_MARKER_HEADER;
+use crate::snapshot::{adxl345_snapshot, Adxl345Snapshot};
+use crate::instance::ADXL345_DEVICES_MAX;
+use crate::structures::Adxl345Sample;
+
+/// Version of the stream format described by the header.
+const ADXL345_STREAM_VERSION: u16 = 1;
+
+/// Number of records in the header.
+pub (crate) const ADXL345_HEADER_WORDS: usize = 11;
+
+/// Returns the threshold of the read filter reported by the header.
+#[cfg(not(adxl345_no_filter))]
+fn adxl345_header_filter(snapshot: &Adxl345Snapshot) -> i16 {
+    snapshot.filter
+}
+
+/// Without the read filter, the header reports -1.
+#[cfg(adxl345_no_filter)]
+fn adxl345_header_filter(_snapshot: &Adxl345Snapshot) -> i16 {
+    -1
+}
+
+/// The header, as written into the stream.
+pub (crate) type Adxl345Header = [Adxl345Sample; ADXL345_HEADER_WORDS];
+
+/// Builds the header of a session starting now, with the configuration of the snapshot of
+/// device `id`.
+///
+/// # Parameters
+/// - `id`: The id of the device (see instance.rs).
+/// - `clock`: The clock used for the timestamps of the session.
+pub (crate) fn adxl345_header(id: usize, clock: ClockId) -> Adxl345Header {
+    let snapshot = adxl345_snapshot(id).get();
+    let start_ns = clock.now_ns();
+    let words: [u16; ADXL345_HEADER_WORDS] = [
+        ADXL345_STREAM_VERSION,
+        ADXL345_HEADER_WORDS as u16,
+        snapshot.range_g as u16,
+        clock.as_raw() as u16,
+        snapshot.rate_mhz as u16,
+        (snapshot.rate_mhz >> 16) as u16,
+        start_ns as u16,
+        (start_ns >> 16) as u16,
+        (start_ns >> 32) as u16,
+        (start_ns >> 48) as u16,
+        adxl345_header_filter(&snapshot) as u16,
+    ];
+
+    let mut header = [Adxl345Sample::new(0, 0, 0); ADXL345_HEADER_WORDS];
+    for (record, word) in header.iter_mut().zip(words) {
+        *record = Adxl345Sample::marker(ADXL345_MARKER_HEADER, word as i16);
+    }
+    header
+}
+
+/// Session state of a device, shared by the session ioctls and the read path.
+pub (crate) struct Adxl345Session {
+    enabled: AtomicBool,   // Emit a header at each ADXL345_IOC_START
+    pending: AtomicBool,   // A header is ready and no reader took it yet
+    header: smutex::Mutex<Adxl345Header>,
+}
+
+/// Session states of the devices, indexed by id.
+static ADXL345_SESSIONS: [Adxl345Session; ADXL345_DEVICES_MAX] = [Adxl345Session::EMPTY; ADXL345_DEVICES_MAX];
+
+/// Returns the session state of device `id`.
+pub (crate) fn adxl345_session(id: usize) -> &'static Adxl345Session {
+    &ADXL345_SESSIONS[id]
+}
+
+impl Adxl345Session {
+    /// A state without header, to initialize the array of states.
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY: Self = Self::new();
+
+    const fn new() -> Self {
+        Self {
+            enabled: AtomicBool::new(false),
+            pending: AtomicBool::new(false),
+            header: smutex::Mutex::new([Adxl345Sample::new(0, 0, 0); ADXL345_HEADER_WORDS]),
+        }
+    }
+
+    /// Enables or disables the header for the next sessions.
+    pub (crate) fn set_header(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Returns true if the next session starts with a header.
+    pub (crate) fn header_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Makes `header`, built by `adxl345_header()` for a session starting now, the next record
+    /// of the stream.
+    pub (crate) fn arm(&self, header: Adxl345Header) {
+        *self.header.lock() = header;
+        self.pending.store(true, Ordering::Release);
+    }
+
+    /// Drops the header not delivered yet, if any.
+    pub (crate) fn cancel(&self) {
+        self.pending.store(false, Ordering::Release);
+    }
+
+    /// Returns true if a header still has to be delivered, it never blocks.
+    pub (crate) fn has_pending(&self) -> bool {
+        self.pending.load(Ordering::Acquire)
+    }
+
+    /// Returns the header still to be delivered, if any, and marks it as delivered.
+    ///
+    /// The caller must write it whole, so it must check first that it fits.
+    pub (crate) fn take_header(&self) -> Option<Adxl345Header> {
+        let header = self.header.lock();
+        if self.pending.swap(false, Ordering::AcqRel) {
+            Some(*header)
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,235 @@
+/*
+ * Copyright 2024 Luca Saverio Esposito, Università di Roma, Tor Vergata
+ * email: <lucasaverioesposito@gmail.com>
+ *
+ * This file is part of an "Rust Linux driver for the ADXL345 device".
+ *
+ * This driver is free software: you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * This driver is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with Foobar.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+
+// shadow.rs
+
+//! Register shadow and brown-out recovery.
+//!
+//! A brown-out resets the ADXL345 to its power-on defaults (standby, 100 Hz, ±2 g, FIFO in
+//! bypass) without the bus necessarily failing, so nothing else in the driver notices: the
+//! stream stops, or goes on with samples taken in the wrong configuration. Battery-powered
+//! boards with marginal supplies hit it whenever a radio or a motor draws a burst of current.
+//!
+//! Every successful write to a configuration register, THRESH_TAP to FIFO_CTL, is kept in a
+//! shadow. The drain compares the shadowed registers with the chip, before it reads the device:
+//! - automatically, when no sample arrived for two periods of the rate (a reset chip is in
+//!   standby), at most every `shadow_period_ms`, and once the bus works again after an error;
+//! - in test mode (`shadow_check` in debugfs), every `shadow_period_ms` as well.
+//!
+//! On a mismatch it logs the registers that differ and runs the recovery sequence:
+//! 1. DEVID is read again. A chip that doesn't answer 0xE5 yet is still powering up, or is not
+//!    the one probed: nothing is written and the sequence is retried on the next drain.
+//! 2. POWER_CTL is cleared, the chip is in standby while it is reconfigured.
+//! 3. The FIFO is reset by setting it in bypass, which empties it.
+//! 4. The shadow is written back, POWER_CTL last so the device measures once configured; this
+//!    also restores the FIFO mode and watermark.
+//!
+//! The recovery is counted (`recoveries` in sysfs, `shadow_recoveries` in debugfs) and reported
+//! with a `reprogrammed` uevent. Since it runs before the drain reads the device, no sample taken
+//! with the defaults is queued once the mismatch is seen. The shadow is only written with the
+//! device lock held, the knobs and counters are plain atomics.
+//!
+//! Each device has its own shadow, selected by its id (see instance.rs), checked by its own
+//! drain; the knobs and counters are in the debugfs directory of the device.
+
+use kernel::prelude::*;
+use kernel::sync::Mutex;
+use kernel::time::ktime_get_ns;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicU8, Ordering};
+use kernel::error::code::ENODEV;
+use crate::constant::{
+    ADXL345_DEVID, ADXL345_REG_ACT_TAP_STATUS, ADXL345_REG_DATAX0, ADXL345_REG_DATAZ1,
+    ADXL345_REG_DEVID, ADXL345_REG_FIFO_CTL, ADXL345_REG_INT_SOURCE, ADXL345_REG_POWER_CTL,
+    ADXL345_REG_THRESH_TAP,
+};
+use crate::structures::Adxl345;
+use crate::instance::ADXL345_DEVICES_MAX;
+
+/// Number of registers from THRESH_TAP to FIFO_CTL, the read-only ones included.
+const ADXL345_SHADOW_LEN: usize = (ADXL345_REG_FIFO_CTL - ADXL345_REG_THRESH_TAP + 1) as usize;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const ADXL345_SHADOW_UNWRITTEN: AtomicU8 = AtomicU8::new(0);
+
+/// Shadow of the configuration registers, with the knobs of the check.
+pub (crate) struct Adxl345Shadow {
+    pub (crate) enabled: AtomicBool,     // Test mode, the drain compares the chip periodically
+    pub (crate) period_ms: AtomicU32,    // Shortest interval between two comparisons
+    pub (crate) checks: AtomicU64,       // Comparisons made
+    pub (crate) recoveries: AtomicU64,   // Times the chip was reprogrammed
+    values: [AtomicU8; ADXL345_SHADOW_LEN],
+    written: AtomicU32,                  // Bit per register of `values` holding a written value
+    requested: AtomicBool,               // Compare on the next drain, whatever the interval
+    last_check_ns: AtomicU64,
+    last_data_ns: AtomicU64,             // Last drain that moved a sample
+}
+
+/// Shadows of the devices, indexed by id.
+static ADXL345_SHADOWS: [Adxl345Shadow; ADXL345_DEVICES_MAX] = [Adxl345Shadow::EMPTY; ADXL345_DEVICES_MAX];
+
+/// Returns the shadow of device `id`.
+pub (crate) fn adxl345_shadow(id: usize) -> &'static Adxl345Shadow {
+    &ADXL345_SHADOWS[id]
+}
+
+/// Returns the index of `reg` in the shadow, `None` if it is not a configuration register.
+const fn adxl345_shadow_index(reg: u8) -> Option<usize> {
+    match reg {
+        ADXL345_REG_ACT_TAP_STATUS | ADXL345_REG_INT_SOURCE => None,
+        ADXL345_REG_DATAX0..=ADXL345_REG_DATAZ1 => None,
+        ADXL345_REG_THRESH_TAP..=ADXL345_REG_FIFO_CTL => Some((reg - ADXL345_REG_THRESH_TAP) as usize),
+        _ => None,
+    }
+}
+
+impl Adxl345Shadow {
+    /// A shadow with nothing written, to initialize the array of shadows.
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY: Self = Self {
+        enabled: AtomicBool::new(false),
+        period_ms: AtomicU32::new(1000),
+        checks: AtomicU64::new(0),
+        recoveries: AtomicU64::new(0),
+        values: [ADXL345_SHADOW_UNWRITTEN; ADXL345_SHADOW_LEN],
+        written: AtomicU32::new(0),
+        requested: AtomicBool::new(false),
+        last_check_ns: AtomicU64::new(0),
+        last_data_ns: AtomicU64::new(0),
+    };
+
+    /// Keeps `value`, written to `reg`, in the shadow; other than configuration registers are
+    /// ignored.
+    pub (crate) fn record(&self, reg: u8, value: u8) {
+        if let Some(index) = adxl345_shadow_index(reg) {
+            self.values[index].store(value, Ordering::Relaxed);
+            self.written.fetch_or(1 << index, Ordering::Relaxed);
+        }
+    }
+
+    /// Forgets the values written, at probe: the chip starts from its defaults.
+    pub (crate) fn clear(&self) {
+        self.written.store(0, Ordering::Relaxed);
+    }
+
+    /// Asks for a comparison on the next drain, once the bus recovered from an error: a
+    /// brown-out often breaks a transfer too.
+    pub (crate) fn request(&self) {
+        self.requested.store(true, Ordering::Relaxed);
+    }
+
+    /// Notes that a drain moved `moved` samples.
+    pub (crate) fn drained(&self, moved: usize) {
+        if moved > 0 {
+            self.last_data_ns.store(ktime_get_ns(), Ordering::Relaxed);
+        }
+    }
+
+    /// Compares the chip with the shadow if a check is due, from the drain before it reads the
+    /// device. `rate_mhz` is the configured rate, for the check on silence.
+    ///
+    /// # Returns
+    /// - `Ok(true)` if the chip had lost its configuration and was reprogrammed.
+    /// - `Ok(false)` if no check was due, or the chip matched the shadow.
+    /// - `Err(ENODEV)` if the chip doesn't identify as an ADXL345, the check is retried on the
+    ///   next drain.
+    /// - `Err(Error)` if a bus error occurred.
+    pub (crate) fn poll(&self, device: &Mutex<Adxl345>, rate_mhz: u32) -> Result<bool> {
+        let now = ktime_get_ns();
+        let since_check = now.saturating_sub(self.last_check_ns.load(Ordering::Relaxed));
+        let since_data = now.saturating_sub(self.last_data_ns.load(Ordering::Relaxed));
+        let period_ns = u64::from(self.period_ms.load(Ordering::Relaxed)) * 1_000_000;
+        let silence_ns = 2_000_000_000_000 / u64::from(rate_mhz.max(1));
+        let due = self.requested.swap(false, Ordering::Relaxed)
+            || (since_check >= period_ns && self.enabled.load(Ordering::Relaxed))
+            || (since_data >= silence_ns && since_check >= silence_ns.max(period_ns));
+        if !due {
+            return Ok(false);
+        }
+        self.last_check_ns.store(now, Ordering::Relaxed);
+        let ret = self.check(&device.lock());
+        if ret.is_err() {
+            self.request();
+        }
+        ret
+    }
+
+    /// Compares the shadowed registers with the chip, and writes the shadow back if any differs.
+    pub (crate) fn check(&self, adxl: &Adxl345) -> Result<bool> {
+        self.checks.fetch_add(1, Ordering::Relaxed);
+        let written = self.written.load(Ordering::Relaxed);
+        let mut lost = false;
+        for index in (0..ADXL345_SHADOW_LEN).filter(|index| written & (1 << index) != 0) {
+            let reg = ADXL345_REG_THRESH_TAP + index as u8;
+            let expected = self.values[index].load(Ordering::Relaxed);
+            let actual = adxl.read_register(reg)?;
+            if actual != expected {
+                pr_warn!("Register 0x{:02x} reads 0x{:02x}, 0x{:02x} was written\n", reg, actual, expected);
+                lost = true;
+            }
+        }
+        if !lost {
+            return Ok(false);
+        }
+
+        self.recover(adxl)?;
+        self.recoveries.fetch_add(1, Ordering::Relaxed);
+        pr_warn!("ADXL345 lost its configuration, reprogrammed from the shadow\n");
+        Ok(true)
+    }
+
+    /// Runs the recovery sequence on a chip that lost its configuration, see the module
+    /// documentation.
+    fn recover(&self, adxl: &Adxl345) -> Result {
+        let devid = adxl.read_register(ADXL345_REG_DEVID)?;
+        if devid != ADXL345_DEVID {
+            pr_warn!("Recovery postponed, DEVID reads 0x{:02x}\n", devid);
+            return Err(ENODEV);
+        }
+
+        // The sequence writes through the shadow, which is put back whatever the outcome so a
+        // retry restores the same configuration
+        let written = self.written.load(Ordering::Relaxed);
+        let mut values = [0; ADXL345_SHADOW_LEN];
+        for (value, shadow) in values.iter_mut().zip(&self.values) {
+            *value = shadow.load(Ordering::Relaxed);
+        }
+        let ret = adxl345_shadow_reprogram(adxl, written, &values);
+        for (value, shadow) in values.iter().zip(&self.values) {
+            shadow.store(*value, Ordering::Relaxed);
+        }
+        self.written.store(written, Ordering::Relaxed);
+        ret
+    }
+}
+
+/// Steps 2 to 4 of the recovery sequence: standby, FIFO reset, then the `written` registers of
+/// `values` with POWER_CTL last.
+fn adxl345_shadow_reprogram(adxl: &Adxl345, written: u32, values: &[u8; ADXL345_SHADOW_LEN]) -> Result {
+    adxl.write_register(ADXL345_REG_POWER_CTL, 0)?;
+    adxl.write_register(ADXL345_REG_FIFO_CTL, 0)?;
+
+    let power = (ADXL345_REG_POWER_CTL - ADXL345_REG_THRESH_TAP) as usize;
+    let order = (0..ADXL345_SHADOW_LEN).filter(|&index| index != power).chain(core::iter::once(power));
+    for index in order.filter(|index| written & (1 << index) != 0) {
+        adxl.write_register(ADXL345_REG_THRESH_TAP + index as u8, values[index])?;
+    }
+    Ok(())
+}
@@ -0,0 +1,188 @@
+/*
+ * Copyright 2024 Luca Saverio Esposito, Università di Roma, Tor Vergata
+ * email: <lucasaverioesposito@gmail.com>
+ *
+ * This file is part of an "Rust Linux driver for the ADXL345 device".
+ *
+ * This driver is free software: you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * This driver is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with Foobar.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+
+
+// snapshot.rs
+
+//! Read-mostly configuration snapshot.
+//!
+//! The configuration used by the data path (range, rate, read filter) is read for every
+//! batch of samples but changes rarely. It is published as an immutable snapshot behind an atomic
+//! pointer: readers copy it inside an RCU read-side critical section and never take a lock,
+//! writers allocate a new snapshot, swap the pointer and free the old one after a grace period.
+//!
+//! Each device has its own snapshot, selected by its id (see instance.rs): the rate, the range
+//! and the read filter of a device don't change the data path of the others. Remove puts the
+//! snapshot of the id back to the defaults, so the next device bound with that id starts from
+//! the `rate`, `range` and `filter_threshold` module parameters rather than from the
+//! configuration of the removed one.
+
+use kernel::prelude::*;
+use kernel::bindings;
+use kernel::sync::{rcu, smutex, Arc, Mutex};
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, AtomicU32, Ordering};
+use crate::config::Adxl345Param;
+use crate::structures::Adxl345;
+use crate::instance::ADXL345_DEVICES_MAX;
+#[cfg(not(adxl345_no_filter))]
+use crate::filter::{adxl345_filter_initial, Adxl345PipelineConfig, ADXL345_FILTER};
+
+/// Configuration used by the data path.
+#[derive(Copy, Clone)]
+pub (crate) struct Adxl345Snapshot {
+    pub (crate) rate_mhz: u32,   // Output data rate, in mHz
+    pub (crate) range_g: u32,    // Measurement range, in g
+    #[cfg(not(adxl345_no_filter))]
+    pub (crate) filter: i16,     // Threshold of the read filter, in shifted LSBs
+    #[cfg(not(adxl345_no_filter))]
+    pub (crate) pipeline: Adxl345PipelineConfig,    // Stages of the read filter
+}
+
+/// Snapshot in use until the first publication, before the module parameters are applied.
+static ADXL345_SNAPSHOT_DEFAULT: Adxl345Snapshot = Adxl345Snapshot {
+    rate_mhz: 100_000,
+    range_g: 16,
+    #[cfg(not(adxl345_no_filter))]
+    filter: ADXL345_FILTER,
+    #[cfg(not(adxl345_no_filter))]
+    pipeline: Adxl345PipelineConfig::DEFAULT,
+};
+
+/// Rate and range the snapshots start from, set at load by the `rate` and `range` module
+/// parameters, the ones programmed at probe.
+static ADXL345_RATE_INITIAL: AtomicU32 = AtomicU32::new(ADXL345_SNAPSHOT_DEFAULT.rate_mhz);
+static ADXL345_RANGE_INITIAL: AtomicU32 = AtomicU32::new(ADXL345_SNAPSHOT_DEFAULT.range_g);
+
+/// Sets the rate and the range the snapshots start from, called at load once they are validated
+/// and before any device is bound.
+pub (crate) fn adxl345_snapshot_initial_set(rate_mhz: u32, range_g: u32) {
+    ADXL345_RATE_INITIAL.store(rate_mhz, Ordering::Relaxed);
+    ADXL345_RANGE_INITIAL.store(range_g, Ordering::Relaxed);
+}
+
+/// Returns the snapshot in use until the first publication, with the initial rate, range and
+/// threshold of the read filter (see filter.rs).
+fn adxl345_snapshot_default() -> Adxl345Snapshot {
+    Adxl345Snapshot {
+        rate_mhz: ADXL345_RATE_INITIAL.load(Ordering::Relaxed),
+        range_g: ADXL345_RANGE_INITIAL.load(Ordering::Relaxed),
+        #[cfg(not(adxl345_no_filter))]
+        filter: adxl345_filter_initial(),
+        ..ADXL345_SNAPSHOT_DEFAULT
+    }
+}
+
+/// Publication point of the snapshot.
+///
+/// # Invariants
+/// - `current` is either null (the defaults are in use) or comes from `Box::into_raw`.
+/// - `current` is only replaced with `writer` held, and a replaced snapshot is only freed after
+///   an RCU grace period.
+pub (crate) struct Adxl345SnapshotCell {
+    current: AtomicPtr<Adxl345Snapshot>,
+    writer: smutex::Mutex<()>,   // Serializes the writers, readers never take it
+}
+
+/// Configuration snapshots of the devices, indexed by id.
+static ADXL345_SNAPSHOTS: [Adxl345SnapshotCell; ADXL345_DEVICES_MAX] =
+    [Adxl345SnapshotCell::EMPTY; ADXL345_DEVICES_MAX];
+
+/// Returns the configuration snapshot of device `id`.
+pub (crate) fn adxl345_snapshot(id: usize) -> &'static Adxl345SnapshotCell {
+    &ADXL345_SNAPSHOTS[id]
+}
+
+/// Frees the published snapshots, called at module exit once no device is left.
+pub (crate) fn adxl345_snapshot_clear_all() {
+    ADXL345_SNAPSHOTS.iter().for_each(Adxl345SnapshotCell::clear);
+}
+
+impl Adxl345SnapshotCell {
+    /// A cell holding the defaults, to initialize the array of cells.
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY: Self = Self::new();
+
+    const fn new() -> Self {
+        Self {
+            current: AtomicPtr::new(ptr::null_mut()),
+            writer: smutex::Mutex::new(()),
+        }
+    }
+
+    /// Returns a copy of the current snapshot, it never blocks.
+    pub (crate) fn get(&self) -> Adxl345Snapshot {
+        let _rcu = rcu::read_lock();
+        let current = self.current.load(Ordering::Acquire);
+        if current.is_null() {
+            return adxl345_snapshot_default();
+        }
+        // SAFETY: The snapshot is not freed before the RCU read lock is released.
+        unsafe { *current }
+    }
+
+    /// Publishes a new snapshot derived from the current one.
+    ///
+    /// It sleeps waiting for the readers of the old snapshot, so it must not be called with a
+    /// spinlock held.
+    pub (crate) fn update(&self, f: impl FnOnce(&mut Adxl345Snapshot)) -> Result {
+        let _writer = self.writer.lock();
+
+        let mut new = Box::try_new(self.get())?;
+        f(&mut new);
+
+        let old = self.current.swap(Box::into_raw(new), Ordering::AcqRel);
+        self.retire(old);
+        Ok(())
+    }
+
+    /// Goes back to the defaults and frees the published snapshot.
+    pub (crate) fn clear(&self) {
+        let _writer = self.writer.lock();
+        let old = self.current.swap(ptr::null_mut(), Ordering::AcqRel);
+        self.retire(old);
+    }
+
+    /// Frees a snapshot that is no longer published, once its readers are gone.
+    fn retire(&self, old: *mut Adxl345Snapshot) {
+        if old.is_null() {
+            return;
+        }
+        // SAFETY: No RCU read-side critical section is running on any CPU when it returns.
+        unsafe { bindings::synchronize_rcu() };
+        // SAFETY: `old` comes from `Box::into_raw` and has no readers left.
+        drop(unsafe { Box::from_raw(old) });
+    }
+}
+
+/// Publishes the rate and the range programmed in the device, in its snapshot.
+///
+/// The values are read back under the device lock, the snapshot is published after releasing it.
+pub (crate) fn adxl345_snapshot_refresh(device: &Arc<Mutex<Adxl345>>) -> Result {
+    let (id, rate_mhz, range_g) = {
+        let adxl = device.lock();
+        (adxl.id(), adxl.get_param(Adxl345Param::Rate)?, adxl.get_param(Adxl345Param::Range)?)
+    };
+    adxl345_snapshot(id).update(|snapshot| {
+        snapshot.rate_mhz = rate_mhz;
+        snapshot.range_g = range_g;
+    })
+}
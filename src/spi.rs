@@ -0,0 +1,87 @@
+/*
+ * Copyright 2024 Luca Saverio Esposito, Università di Roma, Tor Vergata
+ * email: <lucasaverioesposito@gmail.com>
+ *
+ * This file is part of an "Rust Linux driver for the ADXL345 device".
+ *
+ * This driver is free software: you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * This driver is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with Foobar.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+
+// spi.rs
+
+//! SPI transport of the device.
+//!
+//! The ADXL345 also speaks 4-wire SPI, up to 5 MHz in mode 3. SPI devices are not created on a
+//! bus number like the I2C client: the firmware describes them (device tree, ACPI) and the SPI
+//! core binds them by name to a registered driver. With the `spi` module parameter, the driver
+//! below is registered at module init. Its probe sets the clock up and builds the driver state
+//! (see instance.rs), which reaches the chip through the SPI implementation of `Adxl345Bus` (see
+//! structures/bus.rs); everything above the bus is shared with I2C. Each SPI device takes a slot
+//! like an I2C client does (see instance.rs); the I2C client of `i2c_bus` is not created at load
+//! once a device is bound.
+
+use kernel::prelude::*;
+use kernel::bindings;
+use kernel::device::RawDevice;
+use kernel::c_str;
+use kernel::spi::{spi_device_id, SpiDevice, SpiDriverCallbacks, SpiDriverRegistration, SPI_MODE_3};
+use crate::constant::DR_NAME;
+use crate::instance::{adxl345_bind, adxl345_bound_device, adxl345_unbind};
+use crate::power::{adxl345_power_resume, adxl345_power_suspend};
+
+/// Highest SPI clock rate of the ADXL345.
+const ADXL345_SPI_MAX_HZ: u32 = 5_000_000;
+
+/// Gap needed between reading a FIFO entry and the next read of the FIFO or of FIFO_STATUS, in
+/// microseconds. Below 1.6 MHz the command byte of the next transfer lasts as long, at 5 MHz the
+/// chip select is held inactive for it.
+const ADXL345_SPI_FIFO_GAP_US: u16 = 5;
+
+/// Devices handled by the SPI driver, the device tree compatible `adi,adxl345` matches the name.
+static ADXL345_SPI_ID_TABLE: [bindings::spi_device_id; 2] = [
+    spi_device_id(DR_NAME, 0),
+    spi_device_id(b"", 0), // Empty entry to mark the end of the table
+];
+
+/// Callbacks of the SPI driver.
+pub (crate) struct Adxl345Spi;
+
+impl SpiDriverCallbacks for Adxl345Spi {
+    fn probe(&self, spi: &SpiDevice) -> Result {
+        spi.set_cs_inactive_us(ADXL345_SPI_FIFO_GAP_US);
+        spi.setup(SPI_MODE_3, ADXL345_SPI_MAX_HZ)?;
+        adxl345_bind(Box::try_new(spi.clone())?).map(|_| ())
+    }
+
+    fn remove(&self, spi: &SpiDevice) {
+        adxl345_unbind(spi.raw_device());
+    }
+
+    fn suspend(&self, spi: &SpiDevice) -> Result {
+        adxl345_bound_device(spi.raw_device()).map_or(Ok(()), |device| adxl345_power_suspend(&device))
+    }
+
+    fn resume(&self, spi: &SpiDevice) -> Result {
+        adxl345_bound_device(spi.raw_device()).map_or(Ok(()), |device| adxl345_power_resume(&device))
+    }
+}
+
+/// Registers the SPI driver, the device already described by the firmware is probed before it
+/// returns.
+pub (crate) fn adxl345_spi_register(
+    module: &'static ThisModule,
+) -> Result<Pin<Box<SpiDriverRegistration<Adxl345Spi>>>> {
+    SpiDriverRegistration::new_pinned(c_str!("adxl345"), &ADXL345_SPI_ID_TABLE, Adxl345Spi, module)
+}
@@ -0,0 +1,177 @@
+/*
+ * Copyright 2024 Luca Saverio Esposito, Università di Roma, Tor Vergata
+ * email: <lucasaverioesposito@gmail.com>
+ *
+ * This file is part of an "Rust Linux driver for the ADXL345 device".
+ *
+ * This driver is free software: you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * This driver is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with Foobar.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+
+
+// spsc.rs
+
+//! Lock-free single-producer single-consumer queue of samples.
+//!
+//! The producer (the drain work item) and the consumer (the reader) only share two free-running
+//! indices: the producer publishes a slot by advancing `tail` with release ordering after
+//! writing it, the consumer frees it by advancing `head` after reading it. Neither side ever
+//! waits for the other, a full queue simply rejects the new sample.
+//!
+//! The slots are allocated for `N` samples, the queue holds at most its capacity of them, which
+//! can be lowered at run time. Lowering it below the queued samples drops none: the producer
+//! is rejected until the consumer got under the new capacity.
+//!
+//! Each sample is queued with the time it was read from the device (see output.rs), markers
+//! with 0.
+
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use crate::structures::Adxl345Sample;
+
+/// A queued record and the time its sample was read, 0 for a marker.
+#[derive(Copy, Clone)]
+struct Adxl345Slot {
+    record: Adxl345Sample,
+    ns: u64,
+}
+
+/// Bounded SPSC queue of up to `N` samples, `N` must be a power of two.
+///
+/// # Invariants
+/// - `tail - head` (wrapping) is between 0 and `N`.
+/// - `capacity` is between 1 and `N`.
+/// - The slots between `head` and `tail` are only accessed by the consumer, the other ones only by
+///   the producer.
+pub (crate) struct Adxl345Spsc<const N: usize> {
+    slots: [UnsafeCell<Adxl345Slot>; N],
+    head: AtomicUsize,   // Next slot to read, written by the consumer only
+    tail: AtomicUsize,   // Next slot to write, written by the producer only
+    capacity: AtomicUsize, // Samples the queue holds at most
+}
+
+// SAFETY: The slots are handed over between the producer and the consumer through the indices,
+// with release/acquire ordering, so a slot is never accessed by both sides at the same time.
+unsafe impl<const N: usize> Sync for Adxl345Spsc<N> {}
+
+impl<const N: usize> Adxl345Spsc<N> {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const SLOT: UnsafeCell<Adxl345Slot> = UnsafeCell::new(Adxl345Slot {
+        record: Adxl345Sample::new(0, 0, 0),
+        ns: 0,
+    });
+
+    pub (crate) const fn new() -> Self {
+        assert!(N.is_power_of_two());
+        Self {
+            slots: [Self::SLOT; N],
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            capacity: AtomicUsize::new(N),
+        }
+    }
+
+    /// Sets the number of samples the queue holds at most, from 1 to `N`.
+    pub (crate) fn set_capacity(&self, capacity: usize) {
+        self.capacity.store(capacity.clamp(1, N), Ordering::Relaxed);
+    }
+
+    /// Returns the number of samples the queue holds at most.
+    pub (crate) fn capacity(&self) -> usize {
+        self.capacity.load(Ordering::Relaxed)
+    }
+
+    /// Queues all the `records` or none of them, returns false if they don't fit.
+    ///
+    /// They are published together, so the consumer never sees only a part of them. The samples
+    /// among them are stamped with `ns`, the time they were read.
+    ///
+    /// # Safety
+    /// Only one thread at a time may act as producer.
+    pub (crate) unsafe fn push_all(&self, records: &[Adxl345Sample], ns: u64) -> bool {
+        let tail = self.tail.load(Ordering::Relaxed);
+        let head = self.head.load(Ordering::Acquire);
+        if self.capacity().saturating_sub(tail.wrapping_sub(head)) < records.len() {
+            return false;
+        }
+
+        for (i, record) in records.iter().enumerate() {
+            // SAFETY: The slot is outside of `head..tail`, so the consumer doesn't access it.
+            unsafe {
+                *self.slots[tail.wrapping_add(i) % N].get() = Adxl345Slot {
+                    record: *record,
+                    ns: if record.is_marker() { 0 } else { ns },
+                }
+            };
+        }
+        self.tail.store(tail.wrapping_add(records.len()), Ordering::Release);
+        true
+    }
+
+    /// Removes the oldest sample, with the time it was read.
+    ///
+    /// # Safety
+    /// Only one thread at a time may act as consumer.
+    pub (crate) unsafe fn pop(&self) -> Option<(Adxl345Sample, u64)> {
+        let head = self.head.load(Ordering::Relaxed);
+        let tail = self.tail.load(Ordering::Acquire);
+        if head == tail {
+            return None;
+        }
+
+        // SAFETY: The slot is inside `head..tail`, so the producer doesn't access it.
+        let slot = unsafe { *self.slots[head % N].get() };
+        self.head.store(head.wrapping_add(1), Ordering::Release);
+        Some((slot.record, slot.ns))
+    }
+
+    /// Returns the oldest sample without removing it.
+    ///
+    /// # Safety
+    /// Only one thread at a time may act as consumer.
+    pub (crate) unsafe fn peek(&self) -> Option<Adxl345Sample> {
+        let head = self.head.load(Ordering::Relaxed);
+        let tail = self.tail.load(Ordering::Acquire);
+        if head == tail {
+            return None;
+        }
+
+        // SAFETY: The slot is inside `head..tail`, so the producer doesn't access it.
+        Some(unsafe { (*self.slots[head % N].get()).record })
+    }
+
+    /// Discards all the queued samples.
+    ///
+    /// # Safety
+    /// Only one thread at a time may act as consumer.
+    pub (crate) unsafe fn clear(&self) {
+        self.head.store(self.tail.load(Ordering::Acquire), Ordering::Release);
+    }
+
+    /// Returns the number of free slots, it can be called from any thread.
+    ///
+    /// The consumer may free slots at any time, so only the producer can rely on it, as a lower
+    /// bound.
+    pub (crate) fn free(&self) -> usize {
+        self.capacity().saturating_sub(self.len())
+    }
+
+    /// Returns the number of queued samples, it can be called from any thread.
+    pub (crate) fn len(&self) -> usize {
+        let head = self.head.load(Ordering::Acquire);
+        let tail = self.tail.load(Ordering::Acquire);
+        // The indices are read at different times, so the difference may briefly exceed `N`
+        core::cmp::min(tail.wrapping_sub(head), N)
+    }
+}
@@ -0,0 +1,87 @@
+/*
+ * Copyright 2024 Luca Saverio Esposito, Università di Roma, Tor Vergata
+ * email: <lucasaverioesposito@gmail.com>
+ *
+ * This file is part of an "Rust Linux driver for the ADXL345 device".
+ *
+ * This driver is free software: you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * This driver is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with Foobar.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+
+
+// stats.rs
+
+//! Statistics counters of the data path.
+//!
+//! The counters are plain atomics updated with relaxed ordering, so the hot paths (the drain,
+//! `read()` and the register accessors) never take a lock to account for what they do. They are
+//! exposed read-only under `/sys/kernel/debug/adxl345/`.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// Counters of the data path, each of them is a debugfs file.
+pub (crate) struct Adxl345Stats {
+    pub (crate) drained: AtomicU64,     // Samples moved from the device into the kernel buffer
+    pub (crate) dropped: AtomicU64,     // Samples lost because the kernel buffer was full
+    pub (crate) delivered: AtomicU64,   // Samples copied to userspace
+    #[cfg(not(adxl345_no_filter))]
+    pub (crate) filtered: AtomicU64,    // Samples discarded by the threshold filter
+    pub (crate) clipped: AtomicU64,     // Samples on a rail of the range (see clip.rs)
+    pub (crate) markers: AtomicU64,     // Markers embedded in the stream
+    pub (crate) bus_errors: AtomicU64,  // Failed register transactions
+    pub (crate) push_max_ns: AtomicU64, // Longest time the drain took to queue a sample
+    pub (crate) fifo_full: AtomicU64,   // Drains that found the FIFO full, the device may have lost samples
+}
+
+/// Global statistics.
+pub (crate) static ADXL345_STATS: Adxl345Stats = Adxl345Stats::new();
+
+impl Adxl345Stats {
+    const fn new() -> Self {
+        Self {
+            drained: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            delivered: AtomicU64::new(0),
+            #[cfg(not(adxl345_no_filter))]
+            filtered: AtomicU64::new(0),
+            clipped: AtomicU64::new(0),
+            markers: AtomicU64::new(0),
+            bus_errors: AtomicU64::new(0),
+            push_max_ns: AtomicU64::new(0),
+            fifo_full: AtomicU64::new(0),
+        }
+    }
+
+    /// Adds `count` to `counter`.
+    ///
+    /// The counters are independent, so no ordering with other memory accesses is needed.
+    #[inline]
+    pub (crate) fn add(counter: &AtomicU64, count: u64) {
+        counter.fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Raises `counter` to `value`, if it is larger.
+    #[inline]
+    pub (crate) fn max(counter: &AtomicU64, value: u64) {
+        counter.fetch_max(value, Ordering::Relaxed);
+    }
+
+    /// Counts a register transaction, if it failed.
+    #[inline]
+    pub (crate) fn bus<T>(&self, result: &kernel::error::Result<T>) {
+        if result.is_err() {
+            Self::add(&self.bus_errors, 1);
+        }
+    }
+}
@@ -0,0 +1,130 @@
+/*
+ * Copyright 2024 Luca Saverio Esposito, Università di Roma, Tor Vergata
+ * email: <lucasaverioesposito@gmail.com>
+ *
+ * This file is part of an "Rust Linux driver for the ADXL345 device".
+ *
+ * This driver is free software: you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * This driver is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with Foobar.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+
+// structures/bus.rs
+
+//! Register transports.
+//!
+//! The ADXL345 has the same register map on I2C and on SPI, only the framing of a transfer
+//! differs. `Adxl345Bus` is that framing: the register map (regmap.rs) issues every transfer
+//! through it and the rest of the driver doesn't know which bus the device sits on. The I2C
+//! client implements it with SMBus transfers, the SPI device with the command byte of the
+//! datasheet (bit 7 read, bit 6 multiple bytes, register in bits 5-0) in 4-wire mode.
+
+use kernel::prelude::*;
+use kernel::device::RawDevice;
+use kernel::i2c::I2CClient;
+#[cfg(CONFIG_SPI)]
+use kernel::spi::SpiDevice;
+use crate::bus_trace::Adxl345BusOp;
+
+/// A bus the registers of the device are reached on.
+pub (crate) trait Adxl345Bus: Send + Sync {
+    /// Reads the register `reg`.
+    fn read_reg(&self, reg: u8) -> Result<u8>;
+
+    /// Writes `value` to the register `reg`.
+    fn write_reg(&self, reg: u8, value: u8) -> Result;
+
+    /// Reads `data.len()` consecutive registers from `reg`, returns the number of bytes read.
+    fn read_block(&self, reg: u8, data: &mut [u8]) -> Result<usize>;
+
+    /// Returns the bytes a transfer puts on the bus besides its data, see bus_usage.rs.
+    fn overhead(&self, op: Adxl345BusOp) -> usize;
+
+    /// Returns the device of the bus, parent of the driver's own interfaces.
+    fn device(&self) -> &dyn RawDevice;
+
+    /// Returns the I2C client if the device sits on I2C.
+    fn i2c_client(&self) -> Option<&I2CClient> {
+        None
+    }
+}
+
+impl Adxl345Bus for I2CClient {
+    fn read_reg(&self, reg: u8) -> Result<u8> {
+        self.read_byte(reg)
+    }
+
+    fn write_reg(&self, reg: u8, value: u8) -> Result {
+        self.write_byte(reg, value)
+    }
+
+    fn read_block(&self, reg: u8, data: &mut [u8]) -> Result<usize> {
+        self.read_i2c_block(reg, data.len() as u8, data)
+    }
+
+    /// Address and command bytes, plus the repeated address of a read.
+    fn overhead(&self, op: Adxl345BusOp) -> usize {
+        match op {
+            Adxl345BusOp::Write => 2,
+            Adxl345BusOp::Read | Adxl345BusOp::BlockRead => 3,
+        }
+    }
+
+    fn device(&self) -> &dyn RawDevice {
+        self
+    }
+
+    fn i2c_client(&self) -> Option<&I2CClient> {
+        Some(self)
+    }
+}
+
+/// Read bit of the SPI command byte.
+#[cfg(CONFIG_SPI)]
+const ADXL345_SPI_READ: u8 = 0x80;
+
+/// Multiple-byte bit of the SPI command byte, the address increments after each byte.
+#[cfg(CONFIG_SPI)]
+const ADXL345_SPI_MULTI: u8 = 0x40;
+
+/// Register bits of the SPI command byte.
+#[cfg(CONFIG_SPI)]
+const ADXL345_SPI_REG_MASK: u8 = 0x3F;
+
+#[cfg(CONFIG_SPI)]
+impl Adxl345Bus for SpiDevice {
+    fn read_reg(&self, reg: u8) -> Result<u8> {
+        let mut value = [0u8; 1];
+        self.write_then_read(&[ADXL345_SPI_READ | (reg & ADXL345_SPI_REG_MASK)], &mut value)?;
+        Ok(value[0])
+    }
+
+    fn write_reg(&self, reg: u8, value: u8) -> Result {
+        self.write(&[reg & ADXL345_SPI_REG_MASK, value])
+    }
+
+    fn read_block(&self, reg: u8, data: &mut [u8]) -> Result<usize> {
+        let command = ADXL345_SPI_READ | ADXL345_SPI_MULTI | (reg & ADXL345_SPI_REG_MASK);
+        self.write_then_read(&[command], data)?;
+        Ok(data.len())
+    }
+
+    /// The command byte.
+    fn overhead(&self, _op: Adxl345BusOp) -> usize {
+        1
+    }
+
+    fn device(&self) -> &dyn RawDevice {
+        self
+    }
+}
@@ -0,0 +1,279 @@
+/* 
+ * Copyright 2024 Luca Saverio Esposito, Università di Roma, Tor Vergata 
+ * email: <lucasaverioesposito@gmail.com>
+ *
+ * This file is part of an "Rust Linux driver for the ADXL345 device".
+ *
+ * This driver is free software: you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * This driver is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with Foobar.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+// structures/device.rs
+
+//! Device operations, built on the register map.
+
+use kernel::prelude::*;
+use kernel::error::code::{EINVAL};
+use crate::constant::*; // Import the `constant` module for use in this file.
+use crate::config::{Adxl345Param, adxl345_validate, adxl345_from_scaled, adxl345_to_scaled};
+use crate::config::{ADXL345_RATES_MHZ, ADXL345_RANGES_G};
+use super::regmap::adxl345_decode_sample;
+use super::state::{Adxl345, Adxl345Sample};
+
+impl Adxl345 {
+    /// Checks if new data is ready from the ADXL345 device.
+    ///
+    /// # Returns
+    /// - `Ok(1)` if data is ready.
+    /// - `Ok(0)` if data is not ready.
+    /// - `Err(Error)` if there is an I/O error during the read operation.
+    pub (crate) fn data_ready(&self) -> Result<u8> {
+        match self.read_register(ADXL345_REG_INT_SOURCE) {
+            Ok(ret) if ret & 0x80 != 0 => Ok(1),
+            Ok(_) => Ok(0),
+            Err(e) => {
+                pr_err!("failed to read INT_SOURCE register\n");
+                Err(e)
+            }
+        }
+    }
+
+    /// Returns the number of samples queued in the FIFO, from FIFO_STATUS.
+    ///
+    /// The sample held by the data registers is not counted, and none is queued in bypass mode.
+    pub (crate) fn fifo_entries(&self) -> Result<usize> {
+        match self.read_register(ADXL345_REG_FIFO_STATUS) {
+            Ok(ret) => Ok((ret & 0x3F) as usize),
+            Err(e) => {
+                pr_err!("failed to read FIFO_STATUS register\n");
+                Err(e)
+            }
+        }
+    }
+
+    /// Returns the number of samples the device holds, with a single register read.
+    ///
+    /// FIFO_STATUS counts the samples queued in the FIFO modes. In bypass mode it stays at 0, so
+    /// DATA_READY tells whether the data registers hold a new sample instead.
+    ///
+    /// # Returns
+    /// - `Ok(usize)` with the number of samples that can be read back to back.
+    /// - `Err(Error)` if there is an I/O error during the read operation.
+    pub (crate) fn pending_samples(&self) -> Result<usize> {
+        match self.fifo_entries()? {
+            0 => Ok(self.data_ready()? as usize),
+            entries => Ok(entries),
+        }
+    }
+
+    /// Enables measurement mode on the ADXL345 device.
+    ///
+    /// # Returns
+    /// - `Ok(())` if measurement mode is successfully enabled.
+    /// - `Err(Error)` if an I/O error occurs during the process.
+    ///
+    /// Note: The device requires approximately 2ms to wake up after enabling.
+    pub (crate) fn enable_measure(&self) -> Result<()> {
+        // Read the current value of the POWER_CTL register
+        let mut ret = match self.read_register(ADXL345_REG_POWER_CTL) {
+            Ok(value) => value,
+            Err(e) => {
+                pr_err!("failed to enable measure\n");
+                return Err(e);
+            }
+        };
+
+        // Set the measurement bit (bit 3) to enable measurement mode
+        ret |= 1 << 3;
+        
+        // Write the updated value back to the POWER_CTL register
+        match self.write_register(ADXL345_REG_POWER_CTL, ret) {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                pr_err!("failed to enable measure\n");
+                Err(e)
+            }
+        }
+    }
+
+    /// Disables measurement mode on the ADXL345 device.
+    ///
+    /// # Returns
+    /// - `Ok(())` if measurement mode is successfully disabled.
+    /// - `Err(Error)` if an I/O error occurs during the process.
+    pub (crate) fn disable_measure(&self) -> Result<()> {
+        // Read the current value of the POWER_CTL register
+        let mut ret = match self.read_register(ADXL345_REG_POWER_CTL) {
+            Ok(value) => value,
+            Err(e) => {
+                pr_err!("failed to disable measure\n");
+                return Err(e);
+            }
+        };
+
+        // Clear the measurement bit (bit 3) to disable measurement mode
+        ret &= !(1 << 3);
+
+        // Write the updated value back to the POWER_CTL register
+        match self.write_register(ADXL345_REG_POWER_CTL, ret) {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                pr_err!("failed to disable measure\n");
+                Err(e)
+            }
+        }
+    }
+
+    /// Sets the default configuration for the ADXL345 device.
+    ///
+    /// # Parameters
+    /// - `rate_mhz`: Output data rate, in mHz (the `rate` module parameter).
+    /// - `range_g`: Measurement range, in g (the `range` module parameter).
+    ///
+    /// # Returns
+    /// - `Ok(())` if the default configuration is successfully set.
+    /// - `Err(ERANGE)` if the rate or the range is not supported by the device.
+    /// - `Err(Error)` if an I/O error occurs during the configuration process.
+    pub (crate) fn set_default_config(&self, rate_mhz: u32, range_g: u32) -> Result<()> {
+        let rate = adxl345_validate(Adxl345Param::Rate, rate_mhz)?;
+        let range = adxl345_validate(Adxl345Param::Range, range_g)?;
+
+
+        // Put device in standby mode
+        self.write_register(ADXL345_REG_POWER_CTL, 0x00)
+            .map_err(|e| {
+                // map err is just a construct to map the error into another one.
+                // It is used only to print some information message, and leave the original error.
+                pr_err!("failed to set POWER_CTL to standby\n");
+                e
+            })?;
+
+        // Disable device interrupts
+        self.write_register(ADXL345_REG_INT_ENABLE, 0x00)
+            .map_err(|e| {
+                pr_err!("failed to disable interrupts\n");
+                e
+            })?;
+
+        // Configure BW_RATE with the default rate, LOW_POWER bit clear
+        pr_debug!("Output data rate {} mHz\n", rate_mhz);
+        self.write_register(ADXL345_REG_BW_RATE, rate).map_err(|e| {
+            pr_err!("failed to configure BW_RATE register\n");
+            e
+        })?;
+
+        // Set data format (full resolution, right justified, default range)
+        self.write_register(ADXL345_REG_DATA_FORMAT, 0x08 | range).map_err(|e| {
+            pr_err!("failed to set DATA_FORMAT\n");
+            e
+        })?;
+
+        // Route all interrupts to INT1
+        self.write_register(ADXL345_REG_INT_MAP, 0x00).map_err(|e| {
+            pr_err!("failed to route interrupts to INT1\n");
+            e
+        })?;
+
+        // Stream mode: the FIFO buffers samples between drains, which then read them in bursts
+        // (see drain.rs). The watermark is configurable with `ADXL345_IOC_SET_PARAM`
+        self.write_register(ADXL345_REG_FIFO_CTL, ADXL345_FIFO_CTL_DEFAULT).map_err(|e| {
+            pr_err!("failed to configure FIFO_CTL register\n");
+            e
+        })?;
+
+        Ok(())
+    }
+
+    /// Validates and applies a configuration parameter.
+    ///
+    /// # Parameters
+    /// - `param`: The parameter to configure.
+    /// - `value`: The new value, in the unit of the parameter (see `Adxl345Param`).
+    ///
+    /// # Returns
+    /// - `Ok(())` if the value is valid and written to the device.
+    /// - `Err(ERANGE)` if the value is out of range, nothing is written in this case.
+    /// - `Err(Error)` if an I/O error occurs.
+    pub (crate) fn set_param(&self, param: Adxl345Param, value: u32) -> Result<()> {
+        let field = adxl345_validate(param, value)?;
+
+        match param {
+            Adxl345Param::Rate => self.update_register(ADXL345_REG_BW_RATE, 0x0F, field),
+            Adxl345Param::Range => self.update_register(ADXL345_REG_DATA_FORMAT, 0x03, field),
+            Adxl345Param::Watermark => self.update_register(ADXL345_REG_FIFO_CTL, 0x1F, field),
+            _ => self.write_register(param.register().ok_or(EINVAL)?, field),
+        }
+    }
+
+    /// Reads back a configuration parameter from the device.
+    ///
+    /// # Returns
+    /// - `Ok(u32)` containing the value, in the unit of the parameter.
+    /// - `Err(Error)` if an I/O error occurs.
+    pub (crate) fn get_param(&self, param: Adxl345Param) -> Result<u32> {
+        match param {
+            Adxl345Param::Rate => {
+                let code = self.read_register(ADXL345_REG_BW_RATE)? & 0x0F;
+                Ok(ADXL345_RATES_MHZ[code as usize])
+            }
+            Adxl345Param::Range => {
+                let code = self.read_register(ADXL345_REG_DATA_FORMAT)? & 0x03;
+                Ok(ADXL345_RANGES_G[code as usize])
+            }
+            Adxl345Param::Watermark => Ok((self.read_register(ADXL345_REG_FIFO_CTL)? & 0x1F) as u32),
+            _ => Ok(self.read_register(param.register().ok_or(EINVAL)?)? as u32),
+        }
+    }
+
+    /// Applies a configuration parameter expressed in human units.
+    ///
+    /// Thresholds are expressed in mg and durations in µs, the value is rounded to the
+    /// nearest register LSB.
+    ///
+    /// # Returns
+    /// - `Ok(u32)` containing the value actually achieved by the device, in the same unit.
+    /// - `Err(ERANGE)` if the value is out of range, nothing is written in this case.
+    /// - `Err(Error)` if an I/O error occurs.
+    pub (crate) fn set_param_scaled(&self, param: Adxl345Param, value: u32) -> Result<u32> {
+        let raw = adxl345_from_scaled(param, value)?;
+        self.set_param(param, raw)?;
+        Ok(adxl345_to_scaled(param, raw))
+    }
+
+    /// Reads back a configuration parameter, expressed in human units (mg or µs).
+    pub (crate) fn get_param_scaled(&self, param: Adxl345Param) -> Result<u32> {
+        Ok(adxl345_to_scaled(param, self.get_param(param)?))
+    }
+
+    /// Reads the x, y, and z axis data (6 bytes in total) from the ADXL345 device.
+    ///
+    /// # Returns
+    /// - `Ok(Adxl345Sample)` if the data is successfully read and parsed.
+    /// - `Err(Error)` if an I/O error occurs during the read operation.
+    pub (crate) fn read_data(&self) -> Result<Adxl345Sample> {
+        let mut data = [0u8; 6]; // Buffer to store the 6 bytes of data
+
+        // Read 6 bytes starting from DATAX0 register, from the simulated map in dry-run mode
+        match self.read_block(ADXL345_REG_DATAX0, &mut data) {
+            Ok(6) => Ok(adxl345_decode_sample(&data)),
+            Ok(_) => {
+                pr_err!("Incomplete data read\n");
+                Err(EINVAL)
+            }
+            Err(e) => {
+                pr_err!("Could not read block data\n");
+                Err(e)
+            }
+        }
+    }
+}
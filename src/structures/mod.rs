@@ -0,0 +1,42 @@
+/* 
+ * Copyright 2024 Luca Saverio Esposito, Università di Roma, Tor Vergata 
+ * email: <lucasaverioesposito@gmail.com>
+ *
+ * This file is part of an "Rust Linux driver for the ADXL345 device".
+ *
+ * This driver is free software: you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * This driver is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with Foobar.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+// structures/mod.rs
+
+//! Core structures of the driver and their internal API.
+//!
+//! The module is split by concern, the rest of the driver only sees the types re-exported here:
+//! - `state.rs`: the records and the driver state (`Adxl345Sample`, `Adxl345`, `Adxl345Driver`).
+//! - `bus.rs`: the transports, `Adxl345Bus` implemented by the I2C client and the SPI device.
+//! - `regmap.rs`: raw register access, the only code issuing bus transfers (or dry-run accesses)
+//!   and recording them in the bus trace and the statistics, plus the pure encoding helpers.
+//! - `device.rs`: device operations built on the register map: measurement mode, default and
+//!   per-parameter configuration, data reads.
+//!
+//! New features go through `device.rs` for device-level operations and `regmap.rs` for register
+//! access; they don't touch the bus directly.
+
+mod state;
+mod bus;
+mod regmap;
+mod device;
+
+pub (crate) use state::{Adxl345, Adxl345Driver, Adxl345Sample};
+pub (crate) use bus::Adxl345Bus;
@@ -0,0 +1,134 @@
+/* 
+ * Copyright 2024 Luca Saverio Esposito, Università di Roma, Tor Vergata 
+ * email: <lucasaverioesposito@gmail.com>
+ *
+ * This file is part of an "Rust Linux driver for the ADXL345 device".
+ *
+ * This driver is free software: you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * This driver is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with Foobar.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+// structures/regmap.rs
+
+//! Register map access.
+//!
+//! Every register transaction of the driver goes through these methods: they pick the bus (see
+//! bus.rs) or the dry-run register map, and record the transfer in the bus trace, the bus usage
+//! and the statistics. Successful writes are also kept in the register shadow (see shadow.rs),
+//! writes that would break the transport are refused (see transport_guard.rs).
+//! The encoding helpers at the end are pure functions of their arguments, usable without a
+//! device.
+
+use kernel::prelude::*;
+use crate::dry_run::ADXL345_DRY_RUN;
+use crate::bus_trace::{Adxl345BusOp, ADXL345_BUS_TRACE};
+use crate::stats::ADXL345_STATS;
+use crate::bus_usage::ADXL345_BUS_USAGE;
+use crate::shadow::adxl345_shadow;
+use crate::transport_guard::ADXL345_TRANSPORT_GUARD;
+use super::state::{Adxl345, Adxl345Sample};
+
+impl Adxl345 {
+    /// Reads a byte from a specific register of the ADXL345 device.
+    ///
+    /// # Parameters
+    /// - `reg_name`: The register name (or command) from which the byte should be read.
+    ///
+    /// # Returns
+    /// - `Ok(u8)` containing the byte read from the register.
+    /// - `Err(Error)` if an error occurs during the read operation.
+    pub (crate) fn read_register(&self, reg_name: u8) -> Result<u8> {
+        let ret = if ADXL345_DRY_RUN.enabled() {
+            ADXL345_DRY_RUN.read(reg_name)
+        } else {
+            self.bus.read_reg(reg_name)
+        };
+        ADXL345_BUS_TRACE.record(Adxl345BusOp::Read, reg_name, *ret.as_ref().unwrap_or(&0), &ret);
+        ADXL345_BUS_USAGE.record(1, self.bus.overhead(Adxl345BusOp::Read), &ret);
+        ADXL345_STATS.bus(&ret);
+        ret
+    }
+
+    /// Writes a byte to a specific register of the ADXL345 device.
+    ///
+    /// # Parameters
+    /// - `reg_name`: The register name (or command) to which the byte should be written.
+    /// - `value`: The byte value to be written to the register.
+    ///
+    /// # Returns
+    /// - `Ok(())` if the write operation is successful.
+    /// - `Err(EINVAL)` if the value would break the transport, nothing is written (see
+    ///   transport_guard.rs).
+    /// - `Err(Error)` if an error occurs during the write operation.
+    pub (crate) fn write_register(&self, reg_name: u8, value: u8) -> Result<()> {
+        ADXL345_TRANSPORT_GUARD.check(self.bus.i2c_client().is_some(), reg_name, value)?;
+        let ret = if ADXL345_DRY_RUN.enabled() {
+            ADXL345_DRY_RUN.write(reg_name, value)
+        } else {
+            self.bus.write_reg(reg_name, value)
+        };
+        if ret.is_ok() {
+            adxl345_shadow(self.id()).record(reg_name, value);
+        }
+        ADXL345_BUS_TRACE.record(Adxl345BusOp::Write, reg_name, value, &ret);
+        ADXL345_BUS_USAGE.record(1, self.bus.overhead(Adxl345BusOp::Write), &ret);
+        ADXL345_STATS.bus(&ret);
+        ret
+    }
+
+    /// Updates the bits selected by `mask` in a register, leaving the others untouched.
+    ///
+    /// # Parameters
+    /// - `reg_name`: The register to update.
+    /// - `mask`: The bits of the field being written.
+    /// - `value`: The new field value, already shifted into position.
+    pub (crate) fn update_register(&self, reg_name: u8, mask: u8, value: u8) -> Result<()> {
+        let current = self.read_register(reg_name)?;
+        self.write_register(reg_name, adxl345_field_update(current, mask, value))
+    }
+
+    /// Reads a block of consecutive registers, starting at `reg_name`.
+    ///
+    /// # Returns
+    /// - `Ok(usize)` containing the number of bytes read.
+    /// - `Err(Error)` if an error occurs during the read operation.
+    pub (crate) fn read_block(&self, reg_name: u8, data: &mut [u8]) -> Result<usize> {
+        let ret = if ADXL345_DRY_RUN.enabled() {
+            ADXL345_DRY_RUN.read_block(reg_name, data)
+        } else {
+            self.bus.read_block(reg_name, data)
+        };
+        ADXL345_BUS_TRACE.record(Adxl345BusOp::BlockRead, reg_name, data.len() as u8, &ret);
+        ADXL345_BUS_USAGE.record(data.len(), self.bus.overhead(Adxl345BusOp::BlockRead), &ret);
+        ADXL345_STATS.bus(&ret);
+        ret
+    }
+}
+
+/// Replaces the bits selected by `mask` in `current` with the ones of `value`.
+///
+/// `value` is the new field value, already shifted into position.
+pub (crate) const fn adxl345_field_update(current: u8, mask: u8, value: u8) -> u8 {
+    (current & !mask) | (value & mask)
+}
+
+/// Decodes the DATAX0..DATAZ1 registers into a sample.
+///
+/// The registers hold little-endian full resolution values, shifted by 2 as everywhere in the
+/// driver (0.975 mg per unit).
+pub (crate) const fn adxl345_decode_sample(data: &[u8; 6]) -> Adxl345Sample {
+    let x = i16::from_le_bytes([data[0], data[1]]) << 2;
+    let y = i16::from_le_bytes([data[2], data[3]]) << 2;
+    let z = i16::from_le_bytes([data[4], data[5]]) << 2;
+    Adxl345Sample::new(x, y, z)
+}
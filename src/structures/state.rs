@@ -0,0 +1,188 @@
+/* 
+ * Copyright 2024 Luca Saverio Esposito, Università di Roma, Tor Vergata 
+ * email: <lucasaverioesposito@gmail.com>
+ *
+ * This file is part of an "Rust Linux driver for the ADXL345 device".
+ *
+ * This driver is free software: you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * This driver is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with Foobar.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+// structures/state.rs
+
+//! Records and driver state.
+
+use kernel::prelude::*;
+use kernel::chrdev::{Registration};
+use kernel::sync::{Arc, Mutex};
+use kernel::time::ClockId;
+use crate::constant::ADXL345_MARKER_TAG;
+use crate::sync_input::{adxl345_sync, Adxl345SyncIrq};
+use crate::data_irq::Adxl345DataIrq;
+use crate::sysfs::Adxl345DeviceSysfs;
+use super::bus::Adxl345Bus;
+
+/// Represents a single sample from the ADXL345 accelerometer,
+/// containing X, Y, and Z axis data as 16-bit signed integers.
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub (crate) struct Adxl345Sample {
+    pub (crate) x: i16,
+    pub (crate) y: i16,
+    pub (crate) z: i16,
+}
+
+impl Adxl345Sample {
+    /// Creates a new `Adxl345Sample` with provided x, y, and z values.
+    ///
+    /// # Parameters
+    /// - `x`: X-axis sample value
+    /// - `y`: Y-axis sample value
+    /// - `z`: Z-axis sample value
+    ///
+    /// # Returns
+    /// A new instance of `Adxl345Sample`.
+    pub (crate) const fn new(x: i16, y: i16, z: i16) -> Self {
+        Adxl345Sample { x, y, z }
+    }
+
+    /// Creates a marker record, that is a record which is not an acceleration sample.
+    ///
+    /// # Parameters
+    /// - `kind`: The marker kind (e.g. `ADXL345_MARKER_SYNC`).
+    /// - `value`: A kind-specific value, e.g. the sync pulse sequence number.
+    pub (crate) const fn marker(kind: i16, value: i16) -> Self {
+        Adxl345Sample { x: ADXL345_MARKER_TAG, y: kind, z: value }
+    }
+
+    /// Returns true if the record is a marker rather than an acceleration sample.
+    pub (crate) const fn is_marker(&self) -> bool {
+        self.x == ADXL345_MARKER_TAG
+    }
+}
+
+/// Main structure for the ADXL345 accelerometer driver. It holds references to
+/// the bus of the device and device file state, as well as synchronization mechanisms
+/// to handle concurrent access.
+pub (crate) struct Adxl345 {
+    pub (crate) bus: Box<dyn Adxl345Bus>,          // I2C client or SPI device, see bus.rs
+    pub (crate) registration: Option<Pin<Box<Registration<1>>>>,  // Character device registration, minor `id`
+    clock: ClockId,                                // Clock used for sample and event timestamps
+    pub (crate) sync_irq: Option<Adxl345SyncIrq>, // External sync input
+    pub (crate) data_irq: Option<Adxl345DataIrq>, // Data interrupt, see data_irq.rs
+    pub (crate) sysfs: Option<Box<Adxl345DeviceSysfs>>, // Attributes of the client, see sysfs.rs
+    id: usize,                                     // Slot of the device, see instance.rs
+}
+
+unsafe impl Send for Adxl345 {}
+unsafe impl Sync for Adxl345 {}
+
+
+
+impl Adxl345 {
+    /// Creates a new `Adxl345` instance on the provided bus.
+    /// The char device driver isn't initialized here, it happens during device probe .
+    ///
+    /// # Parameters
+    /// - `bus`: I2C client or SPI device of the ADXL345 device.
+    /// - `id`: Slot the device is bound in, see instance.rs.
+    ///
+    /// # Returns
+    /// A new instance of `Adxl345`.
+    pub (crate) fn new(bus: Box<dyn Adxl345Bus>, id: usize) -> Self {
+        Adxl345 {
+            bus,
+            registration: None,
+            clock: ClockId::Monotonic,
+            sync_irq: None,
+            data_irq: None,
+            sysfs: None,
+            id,
+        }
+    }
+
+    /// Getter function for the `id` field.
+    pub (crate) fn id(&self) -> usize {
+        self.id
+    }
+
+    /// Getter function for the `clock` field.
+    pub (crate) fn clock(&self) -> ClockId {
+        self.clock
+    }
+
+    /// Selects the clock used to timestamp samples and events.
+    ///
+    /// # Parameters
+    /// - `clock`: The kernel clock to read for every new timestamp.
+    pub (crate) fn set_clock(&mut self, clock: ClockId) {
+        self.clock = clock;
+        adxl345_sync(self.id).set_clock(clock);
+    }
+
+    /// Returns the current time, in nanoseconds, of the selected timestamp clock.
+    #[allow(dead_code)]
+    pub (crate) fn timestamp_ns(&self) -> u64 {
+        self.clock.now_ns()
+    }
+
+    /// Getter function for the `bus` field.
+    pub (crate) fn bus(&self) -> &dyn Adxl345Bus {
+        &*self.bus
+    }
+}
+
+// Define the main driver structure for ADXL345
+pub (crate) struct Adxl345Driver {
+    pub(crate) device: Arc<Mutex<Adxl345>>,
+    this_module: &'static ThisModule,
+}
+
+impl Adxl345Driver {
+    /// Creates a new instance of `Adxl345Driver`.
+    ///
+    /// # Parameters
+    /// - `device`: An `Arc` of a `Mutex` containing an `Adxl345` instance,
+    ///    representing the main device state for the ADXL345 accelerometer.
+    /// - `module`: A reference to the current module (`ThisModule`) associated
+    ///    with this driver. This is required for registering the char device associated
+    ///    to the module.
+    ///
+    /// # Returns
+    /// Returns a new `Adxl345Driver` instance with the provided device state
+    /// and module reference.
+    pub (crate) fn new(device: Arc<Mutex<Adxl345>>, module: &'static ThisModule) -> Self {
+        // Create the new `Adxl345Driver` instance
+        let adxl345driver = Self {
+            device,
+            this_module: module,
+        };
+
+        // Return the driver instance
+        adxl345driver
+    }
+
+    /// Getter for the `device` field
+    pub (crate) fn device(&self) -> &Arc<Mutex<Adxl345>> {
+        &self.device
+    }
+
+    /// Returns a reference to the `ThisModule` instance associated with this driver.
+    ///
+    /// # Returns
+    /// A reference to `ThisModule`, providing access to module-specific information.
+    pub (crate) fn this_module(&self) -> &'static ThisModule {
+        self.this_module
+    }
+
+}
@@ -0,0 +1,192 @@
+/*
+ * Copyright 2024 Luca Saverio Esposito, Università di Roma, Tor Vergata
+ * email: <lucasaverioesposito@gmail.com>
+ *
+ * This file is part of an "Rust Linux driver for the ADXL345 device".
+ *
+ * This driver is free software: you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * This driver is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with Foobar.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+
+// sync_input.rs
+
+//! External synchronization input.
+//!
+//! A GPIO line (e.g. the output of a PPS source or of a rig-wide trigger) can be attached to the
+//! driver. Every rising edge is counted and timestamped in interrupt context; the read path then
+//! embeds a sync marker into the sample stream so recordings from several nodes can be aligned.
+//!
+//! Each device has its own sync input, selected by its id (see instance.rs): a pulse is marked in
+//! the stream of the device whose file attached the line.
+
+use kernel::prelude::*;
+use kernel::bindings;
+use kernel::irq;
+use kernel::c_str;
+use kernel::gpio_irq::{ClosureHandler, GpioIrq, request_irq};
+use kernel::io_buffer::WritableToBytes;
+use kernel::sync::Arc;
+use kernel::time::ClockId;
+use crate::data_irq::Adxl345IrqHandler;
+use crate::drain::Adxl345Drain;
+use crate::instance::ADXL345_DEVICES_MAX;
+use crate::poll::adxl345_data_event;
+use crate::fasync::adxl345_sigio;
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+/// Information about the last sync pulse, returned by `ADXL345_IOC_GET_SYNC`.
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub (crate) struct Adxl345SyncInfo {
+    pub (crate) sequence: u32,      // Number of pulses received since the input was attached
+    pub (crate) gpio: u32,          // GPIO line used as sync input, u32::MAX if detached
+    pub (crate) timestamp_ns: u64,  // Timestamp of the last pulse, in the selected clock
+}
+
+// SAFETY: `Adxl345SyncInfo` is `repr(C)`, made only of integers and has no padding.
+unsafe impl WritableToBytes for Adxl345SyncInfo {}
+
+/// State shared between the sync interrupt handler and the read path.
+///
+/// Only atomics are used, so the interrupt handler never contends with the device lock.
+pub (crate) struct Adxl345SyncState {
+    sequence: AtomicU32,
+    reported: AtomicU32,
+    timestamp_ns: AtomicU64,
+    clock: AtomicU32,
+    gpio: AtomicU32,
+}
+
+/// Sync states of the devices, indexed by id.
+static ADXL345_SYNCS: [Adxl345SyncState; ADXL345_DEVICES_MAX] = [Adxl345SyncState::EMPTY; ADXL345_DEVICES_MAX];
+
+/// Returns the sync state of device `id`.
+pub (crate) fn adxl345_sync(id: usize) -> &'static Adxl345SyncState {
+    &ADXL345_SYNCS[id]
+}
+
+impl Adxl345SyncState {
+    /// A detached input, to initialize the array of states.
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY: Self = Self::new();
+
+    const fn new() -> Self {
+        Self {
+            sequence: AtomicU32::new(0),
+            reported: AtomicU32::new(0),
+            timestamp_ns: AtomicU64::new(0),
+            clock: AtomicU32::new(ClockId::Monotonic as u32),
+            gpio: AtomicU32::new(u32::MAX),
+        }
+    }
+
+    /// Resets the counters and records the GPIO line and the clock used for timestamps.
+    fn reset(&self, gpio: u32, clock: ClockId) {
+        self.sequence.store(0, Ordering::Relaxed);
+        self.reported.store(0, Ordering::Relaxed);
+        self.timestamp_ns.store(0, Ordering::Relaxed);
+        self.clock.store(clock.as_raw(), Ordering::Relaxed);
+        self.gpio.store(gpio, Ordering::Release);
+    }
+
+    /// Keeps the pulse timestamps in the same clock as the sample timestamps.
+    pub (crate) fn set_clock(&self, clock: ClockId) {
+        self.clock.store(clock.as_raw(), Ordering::Relaxed);
+    }
+
+    /// Records a new pulse, called from interrupt context.
+    fn pulse(&self) {
+        let clock = ClockId::from_raw(self.clock.load(Ordering::Relaxed)).unwrap_or(ClockId::Monotonic);
+        self.timestamp_ns.store(clock.now_ns(), Ordering::Relaxed);
+        self.sequence.fetch_add(1, Ordering::Release);
+    }
+
+    /// Returns the sequence number of the next pulse that still has to be marked in the
+    /// stream, if any, and marks it as reported.
+    ///
+    /// When pulses arrive faster than samples are read, only the latest one is marked.
+    pub (crate) fn take_pending(&self) -> Option<u32> {
+        let sequence = self.sequence.load(Ordering::Acquire);
+        let reported = self.reported.swap(sequence, Ordering::AcqRel);
+        if sequence != reported {
+            Some(sequence)
+        } else {
+            None
+        }
+    }
+
+    /// Returns true if a pulse still has to be marked in the stream.
+    pub (crate) fn has_pending(&self) -> bool {
+        self.sequence.load(Ordering::Acquire) != self.reported.load(Ordering::Acquire)
+    }
+
+    /// Returns a snapshot of the last pulse.
+    pub (crate) fn info(&self) -> Adxl345SyncInfo {
+        Adxl345SyncInfo {
+            sequence: self.sequence.load(Ordering::Acquire),
+            gpio: self.gpio.load(Ordering::Acquire),
+            timestamp_ns: self.timestamp_ns.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// The sync input line and its interrupt, released when dropped.
+pub (crate) type Adxl345SyncIrq = GpioIrq<irq::Registration<ClosureHandler<Adxl345IrqHandler>>>;
+
+/// Interrupt handler of the sync input line of the device of `drain`, records the pulse and
+/// wakes up the readers, so the marker is delivered without waiting for the next sample.
+fn adxl345_sync_pulse(drain: &Adxl345Drain) -> irq::Return {
+    adxl345_sync(drain.id()).pulse();
+    adxl345_data_event(drain);
+    drain.wake_up();
+    adxl345_sigio(bindings::POLL_PRI);
+    irq::Return::Handled
+}
+
+/// Attaches the given GPIO line as sync input of the device of `drain`, triggering on its
+/// rising edge.
+///
+/// The line is requested as an input, so it can't be used by anyone else while attached.
+/// The returned value frees the interrupt and the line when dropped, so it must be dropped
+/// outside of any spinlock. Its handler holds a reference to the drain, remove drops it.
+///
+/// # Parameters
+/// - `drain`: The drain of the device, whose readers are woken up by a pulse.
+/// - `gpio`: The legacy GPIO number of the sync line.
+/// - `clock`: The clock used to timestamp the pulses.
+///
+/// # Returns
+/// - `Ok(Adxl345SyncIrq)` if the line and its interrupt are acquired.
+/// - `Err(Error)` if the GPIO is in use, has no interrupt or the request fails.
+pub (crate) fn adxl345_sync_attach(drain: &Arc<Adxl345Drain>, gpio: u32, clock: ClockId) -> Result<Adxl345SyncIrq> {
+    let sync = adxl345_sync(drain.id());
+    sync.reset(gpio, clock);
+
+    let drain = drain.clone();
+    let handler: Adxl345IrqHandler = Box::try_new(move || adxl345_sync_pulse(&drain))?;
+    GpioIrq::request(gpio, c_str!("adxl345_sync"), |irq_number| {
+        request_irq(irq_number, irq::flags::TRIGGER_RISING, fmt!("adxl345_sync"), handler)
+    })
+    .map_err(|e| {
+        pr_err!("GPIO {} can't be used as sync input\n", gpio);
+        sync.gpio.store(u32::MAX, Ordering::Release);
+        e
+    })
+}
+
+/// Marks the sync input of device `id` as detached, called after its registration has been
+/// dropped.
+pub (crate) fn adxl345_sync_detached(id: usize) {
+    adxl345_sync(id).gpio.store(u32::MAX, Ordering::Release);
+}
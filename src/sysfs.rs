@@ -0,0 +1,360 @@
+/*
+ * Copyright 2024 Luca Saverio Esposito, Università di Roma, Tor Vergata
+ * email: <lucasaverioesposito@gmail.com>
+ *
+ * This file is part of an "Rust Linux driver for the ADXL345 device".
+ *
+ * This driver is free software: you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * This driver is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with Foobar.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+
+// sysfs.rs
+
+//! Configuration and live readings in sysfs.
+//!
+//! Probe adds an attribute group to the I2C client device (the SPI device on SPI, under
+//! `/sys/bus/spi/devices/`), so shell scripts and udev rules can configure the sensor without
+//! opening the character device:
+//!
+//! ```text
+//! $ cd /sys/bus/i2c/devices/1-0053
+//! $ echo 400000 > rate            # output data rate, in mHz
+//! $ echo 4 > range                # measurement range, in g
+//! $ echo -3 > offset_z            # OFSZ register, 15.6 mg per unit, kept (see calibration.rs)
+//! $ cat sample                    # last sample drained, x y z in shifted LSBs
+//! 12 -8 1024
+//! $ cat recoveries                # times the chip was reprogrammed after losing its configuration
+//! 0
+//! $ echo 1024 > buffer_capacity   # kernel buffer, in samples (see drain.rs)
+//! $ cat overruns                  # samples dropped because the kernel buffer was full
+//! 0
+//! $ echo 3000 > thresh_tap        # tap threshold, in mg
+//! $ echo 10000 > dur              # longest tap, in us, rounded to the 625 us of DUR
+//! $ cat dur
+//! 10000
+//! ```
+//!
+//! The attributes are described in the registry of sysfs_abi.rs, which gives their names and
+//! modes and from which their ABI documentation is generated. A written value outside the range
+//! of the registry fails with `ERANGE`, text that is not a number with `EINVAL`; the others go
+//! through the same validation and the same configuration lock as the ioctls, and a rate or
+//! range change is published to the data path. The thresholds and durations (`thresh_tap`,
+//! `dur`, `latent`, `window`, `thresh_act`, `thresh_inact`, `time_inact`, `thresh_ff`,
+//! `time_ff`) are in mg and µs as `ADXL345_IOC_SET_PARAM_SCALED`, rounded to the register
+//! resolution and read back as achieved. `sample` is the last sample drained from
+//! the device: reading the data registers here would take a sample away from the readers, so
+//! it only changes while a session runs.
+//!
+//! Every device gets its own group, acting on that device. `recoveries` counts the
+//! reprogrammings of that device (see shadow.rs) and `overruns` the samples it dropped, both
+//! since it was probed.
+//!
+//! The callbacks make their transfers under the device lock, a mutex since I2C and SPI transfers
+//! sleep, as the ioctls do. Removing the group waits for a running `show()` or `store()`, which
+//! take the device lock and `store()` the configuration lock too, so remove drops the group
+//! outside of both.
+
+use kernel::prelude::*;
+use kernel::bindings;
+use kernel::device::RawDevice;
+use kernel::error::code::{EINVAL, ENOMEM, ERANGE};
+use kernel::error::to_result;
+use kernel::str::CString;
+use kernel::sync::{Arc, Mutex};
+use core::ffi::c_char;
+use core::fmt;
+use core::ptr;
+use core::sync::atomic::{AtomicU64, Ordering};
+use crate::config::{adxl345_scaled_max, Adxl345Param};
+use crate::calibration::adxl345_calibration;
+use crate::constant::ADXL345_REG_OFSX;
+use crate::context::adxl345_context_of;
+use crate::ioctl::ADXL345_CONFIG_LOCK;
+use crate::snapshot::adxl345_snapshot_refresh;
+use crate::shadow::adxl345_shadow;
+use crate::drain::{ADXL345_BUFFER_LEN, ADXL345_BUFFER_MIN};
+use crate::instance::ADXL345_DEVICES_MAX;
+use crate::sysfs_abi::{
+    adxl345_abi_doc, ADXL345_ATTR_SPECS, ADXL345_ATTRS_LEN, ADXL345_ATTR_RATE, ADXL345_ATTR_RANGE, ADXL345_ATTR_OFFSET_X,
+    ADXL345_ATTR_SAMPLE, ADXL345_ATTR_RECOVERIES, ADXL345_ATTR_BUFFER_CAPACITY, ADXL345_ATTR_OVERRUNS,
+    ADXL345_ATTR_THRESH_TAP, ADXL345_ATTR_TIME_FF,
+};
+use crate::structures::{Adxl345, Adxl345Sample};
+
+#[allow(clippy::declare_interior_mutable_const)]
+const ADXL345_NO_SAMPLE: AtomicU64 = AtomicU64::new(0);
+
+/// Last sample drained from each device, indexed by id, the three axes packed in 16 bits each,
+/// x lowest.
+static ADXL345_LATEST_SAMPLES: [AtomicU64; ADXL345_DEVICES_MAX] = [ADXL345_NO_SAMPLE; ADXL345_DEVICES_MAX];
+
+/// Records `sample` as the last one drained from device `id`, called by the drain with the
+/// device lock held.
+pub (crate) fn adxl345_latest_sample_store(id: usize, sample: &Adxl345Sample) {
+    let packed = sample.x as u16 as u64 | (sample.y as u16 as u64) << 16 | (sample.z as u16 as u64) << 32;
+    ADXL345_LATEST_SAMPLES[id].store(packed, Ordering::Relaxed);
+}
+
+/// Returns the last sample drained from device `id`.
+fn adxl345_latest_sample(id: usize) -> Adxl345Sample {
+    let packed = ADXL345_LATEST_SAMPLES[id].load(Ordering::Relaxed);
+    Adxl345Sample::new(packed as i16, (packed >> 16) as i16, (packed >> 32) as i16)
+}
+
+/// Formatted text collected in a `Vec`, failing when it can't grow.
+struct Adxl345AbiText(Vec<u8>);
+
+impl fmt::Write for Adxl345AbiText {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0.try_extend_from_slice(s.as_bytes()).map_err(|_| fmt::Error)
+    }
+}
+
+/// Returns the ABI documentation of the attributes, for the debugfs `sysfs_abi` file.
+pub (crate) fn adxl345_sysfs_abi_text() -> Result<Vec<u8>> {
+    let mut text = Adxl345AbiText(Vec::new());
+    adxl345_abi_doc(&mut text).map_err(|_| ENOMEM)?;
+    Ok(text.0)
+}
+
+/// The attribute group of the client device, removed on drop.
+pub (crate) struct Adxl345DeviceSysfs {
+    dev: *mut bindings::device,
+    attrs: [bindings::device_attribute; ADXL345_ATTRS_LEN],
+    attr_ptrs: [*mut bindings::attribute; ADXL345_ATTRS_LEN + 1], // NULL terminated
+    group: bindings::attribute_group,
+}
+
+// SAFETY: The structures are only written before the group is created, sysfs reads them.
+unsafe impl Send for Adxl345DeviceSysfs {}
+unsafe impl Sync for Adxl345DeviceSysfs {}
+
+type ShowFn = unsafe extern "C" fn(*mut bindings::device, *mut bindings::device_attribute, *mut c_char) -> isize;
+type StoreFn = unsafe extern "C" fn(*mut bindings::device, *mut bindings::device_attribute, *const c_char, usize) -> isize;
+
+/// Show and store of each attribute, in the order of `ADXL345_ATTR_SPECS` which gives their
+/// names and modes.
+const ADXL345_ATTR_CALLBACKS: [(ShowFn, Option<StoreFn>); ADXL345_ATTRS_LEN] = [
+    (adxl345_attr_show::<0>, Some(adxl345_attr_store::<0>)),
+    (adxl345_attr_show::<1>, Some(adxl345_attr_store::<1>)),
+    (adxl345_attr_show::<2>, Some(adxl345_attr_store::<2>)),
+    (adxl345_attr_show::<3>, Some(adxl345_attr_store::<3>)),
+    (adxl345_attr_show::<4>, Some(adxl345_attr_store::<4>)),
+    (adxl345_attr_show::<5>, None),
+    (adxl345_attr_show::<6>, None),
+    (adxl345_attr_show::<7>, Some(adxl345_attr_store::<7>)),
+    (adxl345_attr_show::<8>, None),
+    (adxl345_attr_show::<9>, Some(adxl345_attr_store::<9>)),
+    (adxl345_attr_show::<10>, Some(adxl345_attr_store::<10>)),
+    (adxl345_attr_show::<11>, Some(adxl345_attr_store::<11>)),
+    (adxl345_attr_show::<12>, Some(adxl345_attr_store::<12>)),
+    (adxl345_attr_show::<13>, Some(adxl345_attr_store::<13>)),
+    (adxl345_attr_show::<14>, Some(adxl345_attr_store::<14>)),
+    (adxl345_attr_show::<15>, Some(adxl345_attr_store::<15>)),
+    (adxl345_attr_show::<16>, Some(adxl345_attr_store::<16>)),
+    (adxl345_attr_show::<17>, Some(adxl345_attr_store::<17>)),
+];
+
+/// Parameters of the scaled attributes, from `ADXL345_ATTR_THRESH_TAP` to `ADXL345_ATTR_TIME_FF`.
+const ADXL345_ATTR_SCALED_PARAMS: [Adxl345Param; ADXL345_ATTR_TIME_FF - ADXL345_ATTR_THRESH_TAP + 1] = [
+    Adxl345Param::ThreshTap,
+    Adxl345Param::Dur,
+    Adxl345Param::Latent,
+    Adxl345Param::Window,
+    Adxl345Param::ThreshAct,
+    Adxl345Param::ThreshInact,
+    Adxl345Param::TimeInact,
+    Adxl345Param::ThreshFf,
+    Adxl345Param::TimeFf,
+];
+
+/// Returns true if exactly the writable attributes of the registry have a store.
+const fn adxl345_attr_callbacks_valid() -> bool {
+    let mut i = 0;
+    while i < ADXL345_ATTRS_LEN {
+        if ADXL345_ATTR_CALLBACKS[i].1.is_some() != ADXL345_ATTR_SPECS[i].writable() {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+const _: () = assert!(adxl345_attr_callbacks_valid());
+
+/// Returns true if the range of every scaled attribute is the one `adxl345_from_scaled` accepts.
+const fn adxl345_attr_scaled_valid() -> bool {
+    let mut attr = ADXL345_ATTR_THRESH_TAP;
+    while attr <= ADXL345_ATTR_TIME_FF {
+        let max = adxl345_scaled_max(adxl345_attr_param(attr)) as i64;
+        if !matches!(ADXL345_ATTR_SPECS[attr].range, Some((0, m)) if m == max) {
+            return false;
+        }
+        attr += 1;
+    }
+    true
+}
+
+const _: () = assert!(adxl345_attr_scaled_valid());
+
+// The range of buffer_capacity is the one set_capacity accepts
+const _: () = assert!(matches!(
+    ADXL345_ATTR_SPECS[ADXL345_ATTR_BUFFER_CAPACITY].range,
+    Some((min, max)) if min == ADXL345_BUFFER_MIN as i64 && max == ADXL345_BUFFER_LEN as i64
+));
+
+/// Adds the attribute group to the client device of `device`.
+///
+/// It sleeps, the device lock is only held to look the client up.
+///
+/// # Returns
+/// - `Ok(Box<Adxl345DeviceSysfs>)` if the attributes are created, to be kept in the device state.
+/// - `Err(Error)` if they are not, the character device works without them.
+pub (crate) fn adxl345_device_sysfs_create(device: &Arc<Mutex<Adxl345>>) -> Result<Box<Adxl345DeviceSysfs>> {
+    // SAFETY: The C structures are valid when zeroed, the pointers are set below.
+    let mut sysfs = Box::try_new(unsafe { core::mem::zeroed::<Adxl345DeviceSysfs>() })?;
+    let fs = &mut *sysfs;
+
+    for (index, (spec, (show, store))) in ADXL345_ATTR_SPECS.iter().zip(ADXL345_ATTR_CALLBACKS).enumerate() {
+        fs.attrs[index].attr.name = spec.name.as_ptr() as *const c_char;
+        fs.attrs[index].attr.mode = spec.mode;
+        fs.attrs[index].show = Some(show);
+        fs.attrs[index].store = store;
+        fs.attr_ptrs[index] = &mut fs.attrs[index].attr;
+    }
+    fs.group.attrs = fs.attr_ptrs.as_mut_ptr();
+
+    fs.dev = device.lock().bus().device().raw_device();
+    // SAFETY: The client device outlives the group, which is removed in remove; the attributes
+    // stay allocated until then.
+    to_result(unsafe { bindings::device_add_group(fs.dev, &fs.group) })?;
+    Ok(sysfs)
+}
+
+impl Drop for Adxl345DeviceSysfs {
+    fn drop(&mut self) {
+        // SAFETY: The group was added by `adxl345_device_sysfs_create`, removing it waits for
+        // the running `show()` and `store()`.
+        unsafe { bindings::device_remove_group(self.dev, &self.group) };
+    }
+}
+
+/// Returns the parameter behind a rate, range or scaled attribute.
+const fn adxl345_attr_param(attr: usize) -> Adxl345Param {
+    match attr {
+        ADXL345_ATTR_RATE => Adxl345Param::Rate,
+        ADXL345_ATTR_RANGE => Adxl345Param::Range,
+        _ => ADXL345_ATTR_SCALED_PARAMS[attr - ADXL345_ATTR_THRESH_TAP],
+    }
+}
+
+/// Formats the value of attribute `ATTR` of the client device `dev`.
+fn adxl345_attr_format<const ATTR: usize>(dev: *mut bindings::device) -> Result<CString> {
+    // The context is published by probe before the group is added and the group is removed
+    // before it is cleared
+    let context = adxl345_context_of(dev)?;
+    if ATTR == ADXL345_ATTR_SAMPLE {
+        let sample = adxl345_latest_sample(context.drain.id());
+        return CString::try_from_fmt(fmt!("{} {} {}\n", sample.x, sample.y, sample.z));
+    }
+    if ATTR == ADXL345_ATTR_RECOVERIES {
+        let recoveries = adxl345_shadow(context.drain.id()).recoveries.load(Ordering::Relaxed);
+        return CString::try_from_fmt(fmt!("{}\n", recoveries));
+    }
+    if ATTR == ADXL345_ATTR_BUFFER_CAPACITY {
+        return CString::try_from_fmt(fmt!("{}\n", context.drain.buffer_info().capacity));
+    }
+    if ATTR == ADXL345_ATTR_OVERRUNS {
+        return CString::try_from_fmt(fmt!("{}\n", context.drain.buffer_info().overruns));
+    }
+    let adxl = context.device()?.lock();
+    match ATTR {
+        ADXL345_ATTR_RATE | ADXL345_ATTR_RANGE => {
+            CString::try_from_fmt(fmt!("{}\n", adxl.get_param(adxl345_attr_param(ATTR))?))
+        }
+        ADXL345_ATTR_THRESH_TAP..=ADXL345_ATTR_TIME_FF => {
+            CString::try_from_fmt(fmt!("{}\n", adxl.get_param_scaled(adxl345_attr_param(ATTR))?))
+        }
+        _ => {
+            let offset = adxl.read_register(ADXL345_REG_OFSX + (ATTR - ADXL345_ATTR_OFFSET_X) as u8)?;
+            CString::try_from_fmt(fmt!("{}\n", offset as i8))
+        }
+    }
+}
+
+/// Applies the value written to attribute `ATTR` of the client device `dev`.
+///
+/// # Returns
+/// - `Err(EINVAL)` if `text` is not a number.
+/// - `Err(ERANGE)` if it is outside the range of the attribute in the registry, or a rate or
+///   range the device doesn't support.
+/// The scaled attributes are rounded to the register resolution, as
+/// `ADXL345_IOC_SET_PARAM_SCALED` does.
+fn adxl345_attr_apply<const ATTR: usize>(dev: *mut bindings::device, text: &str) -> Result {
+    let value = text.parse::<i64>().map_err(|_| EINVAL)?;
+    if !ADXL345_ATTR_SPECS[ATTR].accepts(value) {
+        return Err(ERANGE);
+    }
+    let context = adxl345_context_of(dev)?;
+
+    // SAFETY: The lock is initialized at module init.
+    let _config = unsafe { ADXL345_CONFIG_LOCK.lock() };
+    let device = context.device()?;
+    match ATTR {
+        ADXL345_ATTR_BUFFER_CAPACITY => context.drain.set_capacity(value as u32),
+        ADXL345_ATTR_RATE | ADXL345_ATTR_RANGE => {
+            device.lock().set_param(adxl345_attr_param(ATTR), value as u32)?;
+            adxl345_snapshot_refresh(device)
+        }
+        ADXL345_ATTR_THRESH_TAP..=ADXL345_ATTR_TIME_FF => {
+            device.lock().set_param_scaled(adxl345_attr_param(ATTR), value as u32).map(|_| ())
+        }
+        _ => {
+            let adxl = device.lock();
+            adxl345_calibration(adxl.id()).set_axis(&adxl, ATTR - ADXL345_ATTR_OFFSET_X, value as i8)
+        }
+    }
+}
+
+unsafe extern "C" fn adxl345_attr_show<const ATTR: usize>(
+    dev: *mut bindings::device,
+    _attr: *mut bindings::device_attribute,
+    page: *mut c_char,
+) -> isize {
+    let text = match adxl345_attr_format::<ATTR>(dev) {
+        Ok(text) => text,
+        Err(e) => return e.to_errno() as isize,
+    };
+    let bytes = text.as_bytes();
+    // SAFETY: sysfs provides a page, much larger than the short values shown here.
+    unsafe { ptr::copy_nonoverlapping(bytes.as_ptr(), page as *mut u8, bytes.len()) };
+    bytes.len() as isize
+}
+
+unsafe extern "C" fn adxl345_attr_store<const ATTR: usize>(
+    dev: *mut bindings::device,
+    _attr: *mut bindings::device_attribute,
+    buf: *const c_char,
+    count: usize,
+) -> isize {
+    // SAFETY: sysfs provides `count` bytes in `buf`.
+    let bytes = unsafe { core::slice::from_raw_parts(buf as *const u8, count) };
+    let result = core::str::from_utf8(bytes)
+        .map_err(|_| EINVAL)
+        .and_then(|text| adxl345_attr_apply::<ATTR>(dev, text.trim()));
+    match result {
+        Ok(()) => count as isize,
+        Err(e) => e.to_errno() as isize,
+    }
+}
@@ -0,0 +1,409 @@
+/*
+ * Copyright 2024 Luca Saverio Esposito, Università di Roma, Tor Vergata
+ * email: <lucasaverioesposito@gmail.com>
+ *
+ * This file is part of an "Rust Linux driver for the ADXL345 device".
+ *
+ * This driver is free software: you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * This driver is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with Foobar.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+
+// sysfs_abi.rs
+
+//! Registry of the sysfs attributes, and the ABI documentation generated from it.
+//!
+//! Every attribute of the group added by sysfs.rs is described once here: name, mode, type of
+//! value, accepted range, unit and description. sysfs.rs creates the files from the registry and
+//! rejects the written values outside the range with `ERANGE`, and `adxl345_abi_doc()` writes the
+//! entries of `Documentation/ABI/testing/sysfs-bus-i2c-devices-adxl345` from the same table, so
+//! an attribute can't be added, renamed or widened without its documentation following.
+//!
+//! The module is pure, without kernel dependencies: `adxl345_test abi-doc` includes it to
+//! regenerate the documentation file (`make abi-doc`) or check it is up to date
+//! (`adxl345_test abi-doc --check <file>`), and the driver exposes the same text in the debugfs
+//! `sysfs_abi` file, for the running build. The assertions at the bottom check the registry
+//! itself at build time.
+
+use core::fmt::{self, Write};
+
+/// Type of the value of an attribute.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub (crate) enum Adxl345AttrType {
+    /// An unsigned integer.
+    Unsigned,
+    /// A signed integer.
+    Signed,
+    /// Three signed integers, x y z.
+    Axes,
+    /// An unsigned 64-bit counter, only growing while the module is loaded.
+    Counter,
+}
+
+impl Adxl345AttrType {
+    /// Returns the description of the type in the documentation.
+    pub (crate) const fn name(self) -> &'static str {
+        match self {
+            Adxl345AttrType::Unsigned => "unsigned integer",
+            Adxl345AttrType::Signed => "signed integer",
+            Adxl345AttrType::Axes => "three signed integers \"x y z\"",
+            Adxl345AttrType::Counter => "unsigned 64-bit counter",
+        }
+    }
+}
+
+/// Description of an attribute.
+pub (crate) struct Adxl345AttrSpec {
+    /// Name of the file, NUL terminated for sysfs.
+    pub (crate) name: &'static str,
+    /// Permissions of the file, 0o644 for the writable attributes and 0o444 for the others.
+    pub (crate) mode: u16,
+    /// Type of the value.
+    pub (crate) kind: Adxl345AttrType,
+    /// Smallest and largest value, of each axis for `Axes`, `None` for counters.
+    pub (crate) range: Option<(i64, i64)>,
+    /// Unit of the value, empty if it has none.
+    pub (crate) unit: &'static str,
+    /// Description, a single paragraph.
+    pub (crate) description: &'static str,
+}
+
+impl Adxl345AttrSpec {
+    /// Returns the name without the terminating NUL.
+    pub (crate) fn name(&self) -> &'static str {
+        self.name.trim_end_matches('\0')
+    }
+
+    /// Returns true if the attribute can be written.
+    pub (crate) const fn writable(&self) -> bool {
+        self.mode & 0o200 != 0
+    }
+
+    /// Returns true if `value` is within the range of the attribute.
+    pub (crate) const fn accepts(&self, value: i64) -> bool {
+        match self.range {
+            Some((min, max)) => min <= value && value <= max,
+            None => true,
+        }
+    }
+}
+
+/// Attributes of the group, in the order of `ADXL345_ATTR_SPECS`.
+pub (crate) const ADXL345_ATTR_RATE: usize = 0;
+pub (crate) const ADXL345_ATTR_RANGE: usize = 1;
+pub (crate) const ADXL345_ATTR_OFFSET_X: usize = 2;
+pub (crate) const ADXL345_ATTR_SAMPLE: usize = 5;
+pub (crate) const ADXL345_ATTR_RECOVERIES: usize = 6;
+pub (crate) const ADXL345_ATTR_BUFFER_CAPACITY: usize = 7;
+pub (crate) const ADXL345_ATTR_OVERRUNS: usize = 8;
+pub (crate) const ADXL345_ATTR_THRESH_TAP: usize = 9;
+pub (crate) const ADXL345_ATTR_TIME_FF: usize = 17;
+
+/// Number of attributes.
+pub (crate) const ADXL345_ATTRS_LEN: usize = 18;
+
+/// The attributes of the group.
+pub (crate) const ADXL345_ATTR_SPECS: [Adxl345AttrSpec; ADXL345_ATTRS_LEN] = [
+    Adxl345AttrSpec {
+        name: "rate\0",
+        mode: 0o644,
+        kind: Adxl345AttrType::Unsigned,
+        range: Some((100, 3_200_000)),
+        unit: "mHz",
+        description: "Output data rate of the device. The rates of the BW_RATE register are \
+            accepted, from 100 mHz doubling up to 3200000 mHz (25 Hz is 25000); the other values \
+            fail with ERANGE, as ADXL345_IOC_SET_PARAM does. A change is published to the data \
+            path.",
+    },
+    Adxl345AttrSpec {
+        name: "range\0",
+        mode: 0o644,
+        kind: Adxl345AttrType::Unsigned,
+        range: Some((2, 16)),
+        unit: "g",
+        description: "Measurement range of the device: 2, 4, 8 or 16; the other values fail with \
+            ERANGE. A change is published to the data path.",
+    },
+    Adxl345AttrSpec {
+        name: "offset_x\0",
+        mode: 0o644,
+        kind: Adxl345AttrType::Signed,
+        range: Some((-128, 127)),
+        unit: "units of 15.6 mg",
+        description: "OFSX register, added by the device to every x sample. The offsets are \
+            kept by the driver and written again to the next device probed with the same id.",
+    },
+    Adxl345AttrSpec {
+        name: "offset_y\0",
+        mode: 0o644,
+        kind: Adxl345AttrType::Signed,
+        range: Some((-128, 127)),
+        unit: "units of 15.6 mg",
+        description: "OFSY register, added by the device to every y sample. The offsets are \
+            kept by the driver and written again to the next device probed with the same id.",
+    },
+    Adxl345AttrSpec {
+        name: "offset_z\0",
+        mode: 0o644,
+        kind: Adxl345AttrType::Signed,
+        range: Some((-128, 127)),
+        unit: "units of 15.6 mg",
+        description: "OFSZ register, added by the device to every z sample. The offsets are \
+            kept by the driver and written again to the next device probed with the same id.",
+    },
+    Adxl345AttrSpec {
+        name: "sample\0",
+        mode: 0o444,
+        kind: Adxl345AttrType::Axes,
+        range: Some((-32768, 32767)),
+        unit: "shifted LSB, 3.9 mg each",
+        description: "Last sample drained from the device. Reading the data registers here would \
+            take the sample away from the readers of the character device, so the value only \
+            changes while a measurement session runs.",
+    },
+    Adxl345AttrSpec {
+        name: "recoveries\0",
+        mode: 0o444,
+        kind: Adxl345AttrType::Counter,
+        range: None,
+        unit: "",
+        description: "Times the chip was reprogrammed after losing its configuration, since the \
+            device was probed.",
+    },
+    Adxl345AttrSpec {
+        name: "buffer_capacity\0",
+        mode: 0o644,
+        kind: Adxl345AttrType::Unsigned,
+        range: Some((64, 1024)),
+        unit: "samples",
+        description: "Capacity of the kernel buffer of the device, as ADXL345_IOC_SET_BUFFER. The \
+            samples buffered beyond a smaller capacity are kept, the new ones are dropped until \
+            the readers catch up.",
+    },
+    Adxl345AttrSpec {
+        name: "overruns\0",
+        mode: 0o444,
+        kind: Adxl345AttrType::Counter,
+        range: None,
+        unit: "",
+        description: "Samples dropped because the kernel buffer of the device was full, since \
+            it was probed. samples_dropped in debugfs counts those of all the devices.",
+    },
+    Adxl345AttrSpec {
+        name: "thresh_tap\0",
+        mode: 0o644,
+        kind: Adxl345AttrType::Unsigned,
+        range: Some((0, 15938)),
+        unit: "mg",
+        description: "Tap threshold, as ADXL345_IOC_SET_PARAM_SCALED. The value is rounded to the \
+            62.5 mg of a THRESH_TAP LSB and reads back as the achieved one.",
+    },
+    Adxl345AttrSpec {
+        name: "dur\0",
+        mode: 0o644,
+        kind: Adxl345AttrType::Unsigned,
+        range: Some((0, 159375)),
+        unit: "us",
+        description: "Longest tap duration, as ADXL345_IOC_SET_PARAM_SCALED. The value is rounded \
+            to the 625 us of a DUR LSB and reads back as the achieved one. The durations are in \
+            microseconds because that resolution, and the 1.25 ms of LATENT and WINDOW, have no \
+            whole millisecond value.",
+    },
+    Adxl345AttrSpec {
+        name: "latent\0",
+        mode: 0o644,
+        kind: Adxl345AttrType::Unsigned,
+        range: Some((0, 318750)),
+        unit: "us",
+        description: "Wait from a tap to the window of the second tap of a double tap, as ADXL345_IOC_SET_PARAM_SCALED. The value is rounded to the \
+            1.25 ms of a LATENT LSB and reads back as the achieved one.",
+    },
+    Adxl345AttrSpec {
+        name: "window\0",
+        mode: 0o644,
+        kind: Adxl345AttrType::Unsigned,
+        range: Some((0, 318750)),
+        unit: "us",
+        description: "Window of the second tap of a double tap, as ADXL345_IOC_SET_PARAM_SCALED. The value is rounded to the \
+            1.25 ms of a WINDOW LSB and reads back as the achieved one.",
+    },
+    Adxl345AttrSpec {
+        name: "thresh_act\0",
+        mode: 0o644,
+        kind: Adxl345AttrType::Unsigned,
+        range: Some((0, 15938)),
+        unit: "mg",
+        description: "Activity threshold, as ADXL345_IOC_SET_PARAM_SCALED. The value is rounded to the \
+            62.5 mg of a THRESH_ACT LSB and reads back as the achieved one.",
+    },
+    Adxl345AttrSpec {
+        name: "thresh_inact\0",
+        mode: 0o644,
+        kind: Adxl345AttrType::Unsigned,
+        range: Some((0, 15938)),
+        unit: "mg",
+        description: "Inactivity threshold, as ADXL345_IOC_SET_PARAM_SCALED. The value is rounded to the \
+            62.5 mg of a THRESH_INACT LSB and reads back as the achieved one.",
+    },
+    Adxl345AttrSpec {
+        name: "time_inact\0",
+        mode: 0o644,
+        kind: Adxl345AttrType::Unsigned,
+        range: Some((0, 255000000)),
+        unit: "us",
+        description: "Time below the inactivity threshold before inactivity is signalled, as ADXL345_IOC_SET_PARAM_SCALED. The value is rounded to the \
+            1 s of a TIME_INACT LSB and reads back as the achieved one.",
+    },
+    Adxl345AttrSpec {
+        name: "thresh_ff\0",
+        mode: 0o644,
+        kind: Adxl345AttrType::Unsigned,
+        range: Some((0, 15938)),
+        unit: "mg",
+        description: "Free-fall threshold, on all the axes, as ADXL345_IOC_SET_PARAM_SCALED. The value is rounded to the \
+            62.5 mg of a THRESH_FF LSB and reads back as the achieved one.",
+    },
+    Adxl345AttrSpec {
+        name: "time_ff\0",
+        mode: 0o644,
+        kind: Adxl345AttrType::Unsigned,
+        range: Some((0, 1275000)),
+        unit: "us",
+        description: "Time below the free-fall threshold before free fall is signalled, as ADXL345_IOC_SET_PARAM_SCALED. The value is rounded to the \
+            5 ms of a TIME_FF LSB and reads back as the achieved one.",
+    },
+];
+
+/// Directories of the devices holding the attributes, one `What:` line each.
+const ADXL345_ABI_DIRS: [&str; 2] = [
+    "/sys/bus/i2c/devices/<bus>-<addr>",
+    "/sys/bus/spi/devices/spi<bus>.<cs>",
+];
+
+/// Contact of the entries.
+const ADXL345_ABI_CONTACT: &str = "Luca Saverio Esposito <lucasaverioesposito@gmail.com>";
+
+/// Columns of the description text, after its two tabs.
+const ADXL345_ABI_WIDTH: usize = 64;
+
+/// Writes `text` wrapped at `ADXL345_ABI_WIDTH` columns, every line indented with two tabs.
+fn adxl345_abi_wrap(out: &mut impl Write, text: &str) -> fmt::Result {
+    let mut column = 0;
+    for word in text.split_whitespace() {
+        if column > 0 && column + 1 + word.len() > ADXL345_ABI_WIDTH {
+            out.write_str("\n")?;
+            column = 0;
+        }
+        if column == 0 {
+            out.write_str("\t\t")?;
+        } else {
+            out.write_str(" ")?;
+            column += 1;
+        }
+        out.write_str(word)?;
+        column += word.len();
+    }
+    out.write_str("\n")
+}
+
+/// Writes the entry of `spec`.
+fn adxl345_abi_entry(out: &mut impl Write, spec: &Adxl345AttrSpec) -> fmt::Result {
+    for dir in ADXL345_ABI_DIRS {
+        writeln!(out, "What:\t\t{}/{}", dir, spec.name())?;
+    }
+    writeln!(out, "Contact:\t{}", ADXL345_ABI_CONTACT)?;
+    writeln!(out, "Description:")?;
+    let access = if spec.writable() { "(RW)" } else { "(RO)" };
+    writeln!(out, "\t\t{}", access)?;
+    adxl345_abi_wrap(out, spec.description)?;
+    writeln!(out)?;
+    write!(out, "\t\tValue: {}", spec.kind.name())?;
+    if let Some((min, max)) = spec.range {
+        write!(out, ", {} to {}", min, max)?;
+    }
+    if !spec.unit.is_empty() {
+        write!(out, ", in {}", spec.unit)?;
+    }
+    writeln!(out, ".")
+}
+
+/// Writes the Documentation/ABI entries of every attribute, in the order of the registry,
+/// separated by blank lines.
+pub (crate) fn adxl345_abi_doc(out: &mut impl Write) -> fmt::Result {
+    for (index, spec) in ADXL345_ATTR_SPECS.iter().enumerate() {
+        if index > 0 {
+            writeln!(out)?;
+        }
+        adxl345_abi_entry(out, spec)?;
+    }
+    Ok(())
+}
+
+/// Returns true if the NUL terminated names `a` and `b` are equal.
+const fn adxl345_names_equal(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut i = 0;
+    while i < a.len() {
+        if a[i] != b[i] {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+/// Returns true if every attribute has a NUL terminated name used by no other one, one of the
+/// two modes, and a non-empty range if it is writable.
+const fn adxl345_attr_specs_valid() -> bool {
+    let mut i = 0;
+    while i < ADXL345_ATTRS_LEN {
+        let spec = &ADXL345_ATTR_SPECS[i];
+        let name = spec.name.as_bytes();
+        if name.len() < 2 || name[name.len() - 1] != 0 {
+            return false;
+        }
+        if spec.mode != 0o644 && spec.mode != 0o444 {
+            return false;
+        }
+        match spec.range {
+            Some((min, max)) if min > max => return false,
+            None if spec.writable() => return false,
+            _ => {}
+        }
+        let mut j = i + 1;
+        while j < ADXL345_ATTRS_LEN {
+            if adxl345_names_equal(spec.name, ADXL345_ATTR_SPECS[j].name) {
+                return false;
+            }
+            j += 1;
+        }
+        i += 1;
+    }
+    true
+}
+
+const _: () = assert!(adxl345_attr_specs_valid());
+
+// The indices name the attributes they are used for
+const _: () = assert!(adxl345_names_equal(ADXL345_ATTR_SPECS[ADXL345_ATTR_RATE].name, "rate\0"));
+const _: () = assert!(adxl345_names_equal(ADXL345_ATTR_SPECS[ADXL345_ATTR_RANGE].name, "range\0"));
+const _: () = assert!(adxl345_names_equal(ADXL345_ATTR_SPECS[ADXL345_ATTR_OFFSET_X].name, "offset_x\0"));
+const _: () = assert!(adxl345_names_equal(ADXL345_ATTR_SPECS[ADXL345_ATTR_SAMPLE].name, "sample\0"));
+const _: () = assert!(adxl345_names_equal(ADXL345_ATTR_SPECS[ADXL345_ATTR_RECOVERIES].name, "recoveries\0"));
+const _: () = assert!(adxl345_names_equal(ADXL345_ATTR_SPECS[ADXL345_ATTR_BUFFER_CAPACITY].name, "buffer_capacity\0"));
+const _: () = assert!(adxl345_names_equal(ADXL345_ATTR_SPECS[ADXL345_ATTR_OVERRUNS].name, "overruns\0"));
+const _: () = assert!(adxl345_names_equal(ADXL345_ATTR_SPECS[ADXL345_ATTR_THRESH_TAP].name, "thresh_tap\0"));
+const _: () = assert!(adxl345_names_equal(ADXL345_ATTR_SPECS[ADXL345_ATTR_TIME_FF].name, "time_ff\0"));
@@ -0,0 +1,192 @@
+/*
+ * Copyright 2024 Luca Saverio Esposito, Università di Roma, Tor Vergata
+ * email: <lucasaverioesposito@gmail.com>
+ *
+ * This file is part of an "Rust Linux driver for the ADXL345 device".
+ *
+ * This driver is free software: you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * This driver is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with Foobar.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+
+// tap.rs
+
+//! Tap and double-tap events.
+//!
+//! The device detects taps itself: an acceleration above THRESH_TAP for less than DUR is a single
+//! tap, and a second one starting after LATENT and within WINDOW makes it a double tap, on the
+//! axes enabled in TAP_AXES. The thresholds are the `thresh_tap`, `dur`, `latent` and `window`
+//! parameters of `ADXL345_IOC_SET_PARAM`; `ADXL345_IOC_SET_TAP` selects the events reported
+//! (`ADXL345_TAP_SINGLE`, `ADXL345_TAP_DOUBLE`) and the axes taking part, and enables the
+//! matching interrupts.
+//!
+//! While an event is selected the drain reads INT_SOURCE once per pass, then ACT_TAP_STATUS
+//! when a tap occurred: the device keeps the axes of the last tap there until the next one. The
+//! tap is queued as a marker of kind `ADXL345_MARKER_TAP` before the next sample, its z field
+//! holding the mask of the axes (bit 0 x, bit 1 y, bit 2 z, as the clip markers) with bit 8 set
+//! for a single tap and bit 9 for a double tap; the device reports both for the second tap of a
+//! double tap. The marker stands alone in the stream, like a range marker, and taps are counted
+//! in `taps`. The latency is the drain period, or the interrupt latency with `data_gpio`: the
+//! tap interrupts go to INT1 with the watermark unless a profile maps them elsewhere.
+//!
+//! Each device has its own tap state, selected by its id (see instance.rs).
+
+use kernel::prelude::*;
+use kernel::error::code::EINVAL;
+use kernel::io_buffer::{ReadableFromBytes, WritableToBytes};
+use kernel::sync::{Arc, Mutex};
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use crate::alarm::adxl345_alarm;
+use crate::constant::{ADXL345_REG_ACT_TAP_STATUS, ADXL345_REG_INT_ENABLE, ADXL345_REG_TAP_AXES};
+use crate::structures::Adxl345;
+use crate::instance::ADXL345_DEVICES_MAX;
+
+/// Events reported, bits of `Adxl345TapArg::events`.
+pub (crate) const ADXL345_TAP_SINGLE: u32 = 1 << 0;
+pub (crate) const ADXL345_TAP_DOUBLE: u32 = 1 << 1;
+
+/// SINGLE_TAP bit of INT_ENABLE and INT_SOURCE.
+const ADXL345_INT_SINGLE_TAP: u8 = 1 << 6;
+
+/// DOUBLE_TAP bit of INT_ENABLE and INT_SOURCE.
+const ADXL345_INT_DOUBLE_TAP: u8 = 1 << 5;
+
+/// Flags of the marker value, above the axes mask.
+const ADXL345_TAP_MARKER_SINGLE: i16 = 1 << 8;
+const ADXL345_TAP_MARKER_DOUBLE: i16 = 1 << 9;
+
+/// Argument of `ADXL345_IOC_SET_TAP` and `ADXL345_IOC_GET_TAP`.
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub (crate) struct Adxl345TapArg {
+    pub (crate) events: u32, // ADXL345_TAP_* bits, 0 reports no tap
+    pub (crate) axes: u32,   // Axes taking part, bit 0 x, bit 1 y, bit 2 z
+}
+
+// SAFETY: `Adxl345TapArg` is `repr(C)`, made only of integers and has no padding, so any byte
+// pattern is a valid value.
+unsafe impl ReadableFromBytes for Adxl345TapArg {}
+unsafe impl WritableToBytes for Adxl345TapArg {}
+
+/// Tap reporting state.
+pub (crate) struct Adxl345Tap {
+    events: AtomicU32,           // ADXL345_TAP_* bits
+    axes: AtomicU32,             // Axes taking part, as in `Adxl345TapArg`
+    pub (crate) taps: AtomicU64, // Taps queued in the stream
+}
+
+/// Tap states of the devices, indexed by id.
+static ADXL345_TAPS: [Adxl345Tap; ADXL345_DEVICES_MAX] = [Adxl345Tap::EMPTY; ADXL345_DEVICES_MAX];
+
+/// Returns the tap state of device `id`.
+pub (crate) fn adxl345_tap(id: usize) -> &'static Adxl345Tap {
+    &ADXL345_TAPS[id]
+}
+
+impl Adxl345Tap {
+    /// A state reporting no tap, to initialize the array of states.
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY: Self = Self {
+        events: AtomicU32::new(0),
+        axes: AtomicU32::new(0),
+        taps: AtomicU64::new(0),
+    };
+
+    /// Returns true if the drain must read INT_SOURCE for the selected events.
+    pub (crate) fn wants_source(&self) -> bool {
+        self.events.load(Ordering::Relaxed) != 0
+    }
+
+    /// Returns the events reported and the axes taking part.
+    pub (crate) fn get(&self) -> Adxl345TapArg {
+        Adxl345TapArg {
+            events: self.events.load(Ordering::Relaxed),
+            axes: self.axes.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Stops reporting taps, called when the device is removed.
+    pub (crate) fn reset(&self) {
+        self.events.store(0, Ordering::Relaxed);
+        self.axes.store(0, Ordering::Relaxed);
+    }
+
+    /// Checks the INT_SOURCE value read by the drain for a selected tap event, with the device
+    /// lock held, and reads the axes of the tap from ACT_TAP_STATUS.
+    ///
+    /// # Returns
+    /// - `Ok(i16)` with the value of the marker to queue, 0 if no selected event occurred.
+    /// - `Err(Error)` if ACT_TAP_STATUS can't be read.
+    pub (crate) fn push_source(&self, adxl: &Adxl345, source: u8) -> Result<i16> {
+        let events = self.events.load(Ordering::Relaxed);
+        let mut value = 0;
+        if events & ADXL345_TAP_SINGLE != 0 && source & ADXL345_INT_SINGLE_TAP != 0 {
+            value |= ADXL345_TAP_MARKER_SINGLE;
+        }
+        if events & ADXL345_TAP_DOUBLE != 0 && source & ADXL345_INT_DOUBLE_TAP != 0 {
+            value |= ADXL345_TAP_MARKER_DOUBLE;
+        }
+        if value == 0 {
+            return Ok(0);
+        }
+
+        // TAP_X, TAP_Y and TAP_Z are bits 2, 1 and 0 of ACT_TAP_STATUS
+        let status = adxl.read_register(ADXL345_REG_ACT_TAP_STATUS)?;
+        let axes = ((status >> 2) & 1) | (status & 2) | ((status & 1) << 2);
+        self.taps.fetch_add(1, Ordering::Relaxed);
+        Ok(value | axes as i16)
+    }
+}
+
+/// Selects the tap events reported and the axes taking part, as `ADXL345_IOC_SET_TAP`, with the
+/// configuration lock held.
+///
+/// # Returns
+/// - `Ok(())` once TAP_AXES and INT_ENABLE are written.
+/// - `Err(EINVAL)` if `arg` holds unknown bits, or events without any axis.
+/// - `Err(Error)` if a register can't be written.
+pub (crate) fn adxl345_tap_set(device: &Arc<Mutex<Adxl345>>, arg: Adxl345TapArg) -> Result {
+    if arg.events & !(ADXL345_TAP_SINGLE | ADXL345_TAP_DOUBLE) != 0 || arg.axes & !7 != 0 {
+        return Err(EINVAL);
+    }
+    if arg.events != 0 && arg.axes == 0 {
+        return Err(EINVAL);
+    }
+
+    // TAP_AXES orders the axes z, y, x from bit 0, the suppress bit is left clear
+    let axes = arg.axes as u8;
+    let tap_axes = ((axes >> 2) & 1) | (axes & 2) | ((axes & 1) << 2);
+
+    let adxl = device.lock();
+    let tap = adxl345_tap(adxl.id());
+
+    // The alarm line of the device keeps both interrupts it enabled
+    let alarm = adxl345_alarm(adxl.id()).attached();
+    let mut interrupts = 0;
+    if alarm || arg.events & ADXL345_TAP_SINGLE != 0 {
+        interrupts |= ADXL345_INT_SINGLE_TAP;
+    }
+    if alarm || arg.events & ADXL345_TAP_DOUBLE != 0 {
+        interrupts |= ADXL345_INT_DOUBLE_TAP;
+    }
+
+    adxl.update_register(ADXL345_REG_TAP_AXES, 0x07, tap_axes)?;
+    adxl.update_register(
+        ADXL345_REG_INT_ENABLE,
+        ADXL345_INT_SINGLE_TAP | ADXL345_INT_DOUBLE_TAP,
+        interrupts,
+    )?;
+    tap.axes.store(arg.axes, Ordering::Relaxed);
+    tap.events.store(arg.events, Ordering::Relaxed);
+    Ok(())
+}
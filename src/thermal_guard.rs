@@ -0,0 +1,235 @@
+/*
+ * Copyright 2024 Luca Saverio Esposito, Università di Roma, Tor Vergata
+ * email: <lucasaverioesposito@gmail.com>
+ *
+ * This file is part of an "Rust Linux driver for the ADXL345 device".
+ *
+ * This driver is free software: you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * This driver is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with Foobar.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+
+// thermal_guard.rs
+
+//! Temperature-of-operation guard.
+//!
+//! The ADXL345 is specified up to 85 °C, and its offset drifts well before that. When the driver
+//! is loaded with `thermal_zone=<name>` (e.g. the zone of the enclosure or of the SoC next to the
+//! sensor), a work item reads the temperature of that zone every second. Above
+//! `thermal_limit_mc` the guard trips:
+//! - by default it lowers the output data rate to `thermal_rate_mhz` (12.5 Hz unless changed),
+//!   the rate set before is restored when it clears, unless userspace changed it meanwhile;
+//! - with `thermal_suspend` set, it puts the device in standby instead, the session stays open
+//!   and delivers no sample until measurement resumes.
+//!
+//! The guard clears once the temperature is `thermal_hysteresis_mc` below the limit. Both edges
+//! are reported with a `thermal` and a `thermal_ok` uevent and trips are counted in
+//! `thermal_trips`; `thermal_mc` shows the last temperature read. While tripped the guard applies
+//! its action again at every check, so a rate raised or a session started meanwhile is brought
+//! back in line.
+//!
+//! Each device has its own guard on the zone, tripping and clearing on its own; the knobs and the
+//! counters are shared, the zone being the same.
+//!
+//! The work item takes the configuration lock, so it never races with an ioctl or with open()
+//! starting the session. Remove stops it before taking that lock.
+
+use kernel::prelude::*;
+use kernel::device::Device;
+use kernel::error::code::EINVAL;
+use kernel::str::CString;
+use kernel::sync::{Arc, Mutex, UniqueArc};
+use kernel::thermal::ThermalZone;
+use kernel::time::msecs_to_jiffies;
+use kernel::workqueue::{self, DelayedWork};
+use kernel::{impl_self_delayed_work_adapter, init_delayed_work_item};
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use crate::config::Adxl345Param;
+use crate::drain::Adxl345Drain;
+use crate::instance::ADXL345_DEVICES_MAX;
+use crate::ioctl::ADXL345_CONFIG_LOCK;
+use crate::snapshot::adxl345_snapshot_refresh;
+use crate::structures::Adxl345;
+use crate::uevent::{adxl345_uevent, Adxl345Event};
+
+/// Interval between two temperature checks, in milliseconds.
+const ADXL345_THERMAL_PERIOD_MS: u32 = 1000;
+
+/// Guard knobs and counters, the debugfs files.
+pub (crate) struct Adxl345ThermalKnobs {
+    pub (crate) limit_mc: AtomicU32,       // Temperature tripping the guard
+    pub (crate) hysteresis_mc: AtomicU32,  // How far below the limit the guard clears
+    pub (crate) rate_mhz: AtomicU32,       // Rate while tripped
+    pub (crate) suspend: AtomicBool,       // Standby instead of a lower rate while tripped
+    pub (crate) temperature_mc: AtomicU32, // Last temperature read, 0 if below 0 °C
+    pub (crate) trips: AtomicU64,          // Times the guard tripped
+}
+
+/// Knobs shared by the guards of the devices.
+pub (crate) static ADXL345_THERMAL_KNOBS: Adxl345ThermalKnobs = Adxl345ThermalKnobs {
+    limit_mc: AtomicU32::new(70_000),
+    hysteresis_mc: AtomicU32::new(5_000),
+    rate_mhz: AtomicU32::new(12_500),
+    suspend: AtomicBool::new(false),
+    temperature_mc: AtomicU32::new(0),
+    trips: AtomicU64::new(0),
+};
+
+/// Guard state, owned by the work item.
+pub (crate) struct Adxl345ThermalGuard {
+    device: Arc<Mutex<Adxl345>>,
+    drain: Arc<Adxl345Drain>,
+    zone: ThermalZone,
+    running: AtomicBool,    // Cleared to stop the work item from queueing itself again
+    tripped: AtomicBool,    // The temperature went above the limit and didn't clear yet
+    saved_rate: AtomicU32,  // Rate to restore when the guard clears, 0 if none
+    work: DelayedWork,
+}
+
+impl_self_delayed_work_adapter!(Adxl345ThermalGuard, work, Adxl345ThermalGuard::run);
+
+#[allow(clippy::declare_interior_mutable_const)]
+const ADXL345_THERMAL_NONE: Option<Arc<Adxl345ThermalGuard>> = None;
+
+/// The guards of the probed devices, indexed by id, set in probe and taken by remove.
+pub (crate) static mut ADXL345_THERMAL: [Option<Arc<Adxl345ThermalGuard>>; ADXL345_DEVICES_MAX] =
+    [ADXL345_THERMAL_NONE; ADXL345_DEVICES_MAX];
+
+impl Adxl345ThermalGuard {
+    /// Binds the guard of `device` to the thermal zone named `name` and starts checking it,
+    /// `drain` tells whether a session is running when the guard clears.
+    pub (crate) fn start(name: &[u8], device: Arc<Mutex<Adxl345>>, drain: Arc<Adxl345Drain>) -> Result<Arc<Self>> {
+        let name = core::str::from_utf8(name).map_err(|_| EINVAL)?;
+        let zone = ThermalZone::by_name(&CString::try_from_fmt(fmt!("{}", name))?)?;
+        let guard = UniqueArc::try_new(Self {
+            device,
+            drain,
+            zone,
+            running: AtomicBool::new(true),
+            tripped: AtomicBool::new(false),
+            saved_rate: AtomicU32::new(0),
+            // SAFETY: `init_delayed_work_item` is called below.
+            work: unsafe { DelayedWork::new() },
+        })?;
+        init_delayed_work_item!(&guard);
+
+        let guard: Arc<Self> = Pin::from(guard).into();
+        workqueue::system().enqueue_delayed(guard.clone(), 0);
+        Ok(guard)
+    }
+
+    /// Stops checking and waits for a running check to complete.
+    ///
+    /// It must be called without the configuration lock, the check takes it.
+    pub (crate) fn stop(&self) {
+        self.running.store(false, Ordering::Release);
+        self.work.cancel::<Self>();
+    }
+
+    /// Body of the work item: checks the temperature and queues itself again.
+    fn run(guard: Arc<Self>) {
+        if !guard.running.load(Ordering::Acquire) {
+            return;
+        }
+
+        match guard.zone.temperature() {
+            Ok(temperature) => {
+                ADXL345_THERMAL_KNOBS.temperature_mc.store(temperature.max(0) as u32, Ordering::Relaxed);
+                if let Some(event) = guard.check(temperature) {
+                    let device = Device::from_dev(guard.device.lock().bus().device());
+                    if adxl345_uevent(&device, guard.drain.id(), event).is_err() {
+                        pr_err!("Failed to send the {:?} uevent\n", event);
+                    }
+                }
+            }
+            Err(e) => pr_warn!("Failed to read the thermal zone: {:?}\n", e),
+        }
+
+        if guard.running.load(Ordering::Acquire) {
+            let delay = msecs_to_jiffies(ADXL345_THERMAL_PERIOD_MS);
+            workqueue::system().enqueue_delayed(guard, delay);
+        }
+    }
+
+    /// Trips or clears the guard for `temperature` and applies its action.
+    ///
+    /// Returns the event to report if the guard tripped or cleared.
+    fn check(&self, temperature: i32) -> Option<Adxl345Event> {
+        let limit = ADXL345_THERMAL_KNOBS.limit_mc.load(Ordering::Relaxed) as i32;
+        let clear = limit.saturating_sub(ADXL345_THERMAL_KNOBS.hysteresis_mc.load(Ordering::Relaxed) as i32);
+        let was_tripped = self.tripped.load(Ordering::Relaxed);
+        let tripped = if was_tripped { temperature > clear } else { temperature > limit };
+        self.tripped.store(tripped, Ordering::Relaxed);
+
+        // SAFETY: The lock is initialized at module init.
+        let _config = unsafe { ADXL345_CONFIG_LOCK.lock() };
+        let result = if tripped { self.protect() } else if was_tripped { self.restore() } else { Ok(()) };
+        if let Err(e) = result {
+            pr_err!("Thermal guard failed to configure the device: {:?}\n", e);
+        }
+
+        match (was_tripped, tripped) {
+            (false, true) => {
+                ADXL345_THERMAL_KNOBS.trips.fetch_add(1, Ordering::Relaxed);
+                pr_warn!("Thermal zone at {} m°C, above {} m°C\n", temperature, limit);
+                Some(Adxl345Event::Thermal)
+            }
+            (true, false) => {
+                pr_info!("Thermal zone back to {} m°C\n", temperature);
+                Some(Adxl345Event::ThermalOk)
+            }
+            _ => None,
+        }
+    }
+
+    /// Applies the action of a tripped guard, with the configuration lock held.
+    fn protect(&self) -> Result {
+        if ADXL345_THERMAL_KNOBS.suspend.load(Ordering::Relaxed) {
+            return self.device.lock().disable_measure();
+        }
+
+        let limit = ADXL345_THERMAL_KNOBS.rate_mhz.load(Ordering::Relaxed);
+        {
+            let adxl = self.device.lock();
+            let rate = adxl.get_param(Adxl345Param::Rate)?;
+            if rate <= limit {
+                return Ok(());
+            }
+            adxl.set_param(Adxl345Param::Rate, limit)?;
+            self.saved_rate.store(rate, Ordering::Relaxed);
+        }
+        adxl345_snapshot_refresh(&self.device)
+    }
+
+    /// Undoes the action of the guard once it cleared, with the configuration lock held.
+    fn restore(&self) -> Result {
+        // Measurement is only resumed for a running session, stop() left the device in standby
+        if self.drain.is_running() {
+            self.device.lock().enable_measure()?;
+        }
+
+        let saved = self.saved_rate.swap(0, Ordering::Relaxed);
+        if saved == 0 {
+            return Ok(());
+        }
+        {
+            let adxl = self.device.lock();
+            // A rate chosen by userspace while tripped is kept
+            if adxl.get_param(Adxl345Param::Rate)? != ADXL345_THERMAL_KNOBS.rate_mhz.load(Ordering::Relaxed) {
+                return Ok(());
+            }
+            adxl.set_param(Adxl345Param::Rate, saved)?;
+        }
+        adxl345_snapshot_refresh(&self.device)
+    }
+}
@@ -0,0 +1,125 @@
+/*
+ * Copyright 2024 Luca Saverio Esposito, Università di Roma, Tor Vergata
+ * email: <lucasaverioesposito@gmail.com>
+ *
+ * This file is part of an "Rust Linux driver for the ADXL345 device".
+ *
+ * This driver is free software: you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * This driver is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with Foobar.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+
+// transport_guard.rs
+
+//! Guard rails for the transport.
+//!
+//! Some register bits change how the chip talks on its bus rather than what it measures: once
+//! written, the driver loses the device until it is power cycled or reset. The register layer
+//! (see structures/regmap.rs) checks every write against them and fails it with `EINVAL`, without
+//! touching the bus:
+//! - SPI in DATA_FORMAT, 3-wire SPI: the chip then answers on SDA/SDI, which a 4-wire SPI
+//!   controller doesn't listen to, and the driver talks 4-wire SPI only. The bit has no use on
+//!   I2C either: it is refused there too, so a configuration carrying it (a preset, a shadow
+//!   copy) never reaches an SPI board.
+//!
+//! The last refused write and its reason are shown by the `transport_guard` debugfs file, and
+//! `transport_rejections` counts them.
+
+use kernel::prelude::*;
+use kernel::error::code::EINVAL;
+use kernel::str::CString;
+use core::sync::atomic::{AtomicU32, AtomicU64, AtomicU8, Ordering};
+use crate::constant::ADXL345_REG_DATA_FORMAT;
+
+/// SPI bit of DATA_FORMAT, 3-wire mode when set.
+const ADXL345_DATA_FORMAT_SPI: u8 = 1 << 6;
+
+/// Reasons a write is refused, `None` until one is.
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Adxl345TransportRule {
+    None = 0,
+    SpiOnI2c = 1,
+    SpiOnSpi = 2,
+}
+
+impl Adxl345TransportRule {
+    fn from_raw(raw: u32) -> Self {
+        match raw {
+            1 => Adxl345TransportRule::SpiOnI2c,
+            2 => Adxl345TransportRule::SpiOnSpi,
+            _ => Adxl345TransportRule::None,
+        }
+    }
+
+    fn reason(self) -> &'static str {
+        match self {
+            Adxl345TransportRule::None => "no write refused",
+            Adxl345TransportRule::SpiOnI2c => "SPI bit (3-wire SPI) set while attached over I2C",
+            Adxl345TransportRule::SpiOnSpi => "SPI bit (3-wire SPI) set while attached over 4-wire SPI",
+        }
+    }
+}
+
+/// Last refused write, the counter is a debugfs file.
+pub (crate) struct Adxl345TransportGuard {
+    rule: AtomicU32,
+    register: AtomicU8,
+    value: AtomicU8,
+    pub (crate) rejections: AtomicU64,
+}
+
+/// Global guard, there is a single device.
+pub (crate) static ADXL345_TRANSPORT_GUARD: Adxl345TransportGuard = Adxl345TransportGuard {
+    rule: AtomicU32::new(Adxl345TransportRule::None as u32),
+    register: AtomicU8::new(0),
+    value: AtomicU8::new(0),
+    rejections: AtomicU64::new(0),
+};
+
+impl Adxl345TransportGuard {
+    /// Checks a write of `value` to `reg` on a device attached over I2C if `i2c` is set, over SPI
+    /// otherwise.
+    ///
+    /// # Returns
+    /// - `Ok(())` if the write keeps the transport working.
+    /// - `Err(EINVAL)` if it would break it, the reason is recorded.
+    pub (crate) fn check(&self, i2c: bool, reg: u8, value: u8) -> Result {
+        let rule = match reg {
+            ADXL345_REG_DATA_FORMAT if value & ADXL345_DATA_FORMAT_SPI != 0 && i2c => {
+                Adxl345TransportRule::SpiOnI2c
+            }
+            ADXL345_REG_DATA_FORMAT if value & ADXL345_DATA_FORMAT_SPI != 0 => Adxl345TransportRule::SpiOnSpi,
+            _ => return Ok(()),
+        };
+        self.register.store(reg, Ordering::Relaxed);
+        self.value.store(value, Ordering::Relaxed);
+        self.rule.store(rule as u32, Ordering::Release);
+        self.rejections.fetch_add(1, Ordering::Relaxed);
+        pr_warn!("Refused writing {:#04x} to register {:#04x}: {}\n", value, reg, rule.reason());
+        Err(EINVAL)
+    }
+
+    /// Describes the last refused write, for the `transport_guard` debugfs file.
+    pub (crate) fn text(&self) -> Result<CString> {
+        let rule = Adxl345TransportRule::from_raw(self.rule.load(Ordering::Acquire));
+        if rule == Adxl345TransportRule::None {
+            return CString::try_from_fmt(fmt!("{}\n", rule.reason()));
+        }
+        CString::try_from_fmt(fmt!(
+            "register {:#04x} = {:#04x}: {} (EINVAL)\n",
+            self.register.load(Ordering::Relaxed),
+            self.value.load(Ordering::Relaxed),
+            rule.reason()
+        ))
+    }
+}
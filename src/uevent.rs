@@ -0,0 +1,105 @@
+/*
+ * Copyright 2024 Luca Saverio Esposito, Università di Roma, Tor Vergata
+ * email: <lucasaverioesposito@gmail.com>
+ *
+ * This file is part of an "Rust Linux driver for the ADXL345 device".
+ *
+ * This driver is free software: you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * This driver is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with Foobar.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+
+
+
+
+// uevent.rs
+
+//! State-change uevents.
+//!
+//! Notable transitions of the data path are reported with a `KOBJ_CHANGE` uevent on the I2C
+//! client (or the SPI device), so udev rules or daemons can react (e.g. restart a logger once the bus recovered)
+//! without polling debugfs. The environment of every event holds:
+//! - `ADXL345_EVENT`: `overrun`, `bus_error`, `recovered`, `gravity` and `gravity_ok` from the
+//!   gravity watchdog (see `gravity_watch.rs`), or `thermal` and `thermal_ok` from the thermal
+//!   guard (see `thermal_guard.rs`), or `reprogrammed` from the register shadow (see
+//!   `shadow.rs`).
+//! - `ADXL345_DROPPED`: total samples dropped because the kernel buffer was full.
+//! - `ADXL345_BUS_ERRORS`: total failed register transactions.
+//! - `ADXL345_EVENT_ID`, for `overrun` only: the correlation ID of the event marker queued after
+//!   the gap (see `correlation.rs`).
+//!
+//! Events are edge-triggered, one per episode: an overrun is reported by the first drop after a
+//! drain that dropped nothing, a bus error by the first failing drain after a successful one.
+//! They are sent by the drain work item, in process context and outside of the device lock,
+//! since sending a uevent may sleep.
+
+use kernel::prelude::*;
+use kernel::device::{Device, RawDevice, UeventAction};
+use kernel::str::CString;
+use crate::stats::ADXL345_STATS;
+use crate::correlation::adxl345_correlation;
+use core::sync::atomic::Ordering;
+
+/// State transition reported to userspace.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub (crate) enum Adxl345Event {
+    Overrun,    // The kernel buffer became full, samples are being dropped
+    BusError,   // A drain failed on the bus
+    Recovered,  // A drain succeeded after a bus error
+    Gravity,    // The gravity watchdog raised its alarm
+    GravityOk,  // The gravity watchdog cleared its alarm
+    Thermal,    // The thermal guard tripped
+    ThermalOk,  // The thermal guard cleared
+    Reprogrammed, // The chip lost its configuration and was reprogrammed
+}
+
+impl Adxl345Event {
+    /// Returns the value of `ADXL345_EVENT`.
+    fn name(self) -> &'static str {
+        match self {
+            Adxl345Event::Overrun => "overrun",
+            Adxl345Event::BusError => "bus_error",
+            Adxl345Event::Recovered => "recovered",
+            Adxl345Event::Gravity => "gravity",
+            Adxl345Event::GravityOk => "gravity_ok",
+            Adxl345Event::Thermal => "thermal",
+            Adxl345Event::ThermalOk => "thermal_ok",
+            Adxl345Event::Reprogrammed => "reprogrammed",
+        }
+    }
+}
+
+/// Sends `event` as a `KOBJ_CHANGE` uevent of `device`, whose id is `id`.
+///
+/// It may sleep, so it must be called in process context without holding a spinlock.
+pub (crate) fn adxl345_uevent(device: &Device, id: usize, event: Adxl345Event) -> Result {
+    let name = CString::try_from_fmt(fmt!("ADXL345_EVENT={}", event.name()))?;
+    let dropped = CString::try_from_fmt(fmt!(
+        "ADXL345_DROPPED={}",
+        ADXL345_STATS.dropped.load(Ordering::Relaxed)
+    ))?;
+    let bus_errors = CString::try_from_fmt(fmt!(
+        "ADXL345_BUS_ERRORS={}",
+        ADXL345_STATS.bus_errors.load(Ordering::Relaxed)
+    ))?;
+
+    // The marker of an overrun is queued once there is room again, its ID is given already
+    if event == Adxl345Event::Overrun {
+        let id = CString::try_from_fmt(fmt!(
+            "ADXL345_EVENT_ID={}",
+            adxl345_correlation(id).last_overrun()
+        ))?;
+        return device.uevent(UeventAction::Change, &[&name, &dropped, &bus_errors, &id]);
+    }
+    device.uevent(UeventAction::Change, &[&name, &dropped, &bus_errors])
+}
@@ -23,30 +23,37 @@
 
 use kernel::prelude::*;
 use core::time::Duration;
-use kernel::sync::{SpinLock, Arc};
+use kernel::sync::{Mutex, Arc};
 use kernel::delay::coarse_sleep;
 use kernel::error::Result;
+use kernel::error::code::{EIO, ENODEV};
 use crate::structures::*;
 use crate::constant::*;
+use crate::drain::Adxl345Drain;
+use crate::alarm::adxl345_alarm;
+use crate::probe_health::adxl345_probe_acquire;
 
 /// Function that initializes an ADXL345 device with default configuration and performs a test read.
 ///
-/// This function locks the provided `Spinlock<Adxl345>` as needed to manage concurrent access.
+/// This function locks the provided `Mutex<Adxl345>` as needed to manage concurrent access.
 ///
 /// # Parameters
-/// - `device`: A reference to the `Spinlock<Adxl345>` instance to initialize.
+/// - `device`: A reference to the `Mutex<Adxl345>` instance to initialize.
+/// - `samples`: Number of samples of the test acquisition, more than one records the probe
+///   health (see probe_health.rs).
+/// - `rate_mhz`, `range_g`: Default rate and range, see `Adxl345::set_default_config`.
 ///
 /// # Returns
 /// - `Ok(())` if initialization is successful.
 /// - `Err(Error)` if any I/O or configuration error occurs.
-pub (crate) fn adxl345_device_init(device: Arc<SpinLock<Adxl345>>) -> Result<()> {
+pub (crate) fn adxl345_device_init(device: Arc<Mutex<Adxl345>>, samples: u32, rate_mhz: u32, range_g: u32) -> Result<()> {
 
     {        
         // Acquire lock on the entire Adxl345 instance
         let adxl = device.lock();
 
         // Set default configuration
-        adxl.set_default_config().map_err(|e| {
+        adxl.set_default_config(rate_mhz, range_g).map_err(|e| {
             pr_err!("Failed to set default configuration: error code {:?} \n",e);
             e
         })?;
@@ -63,6 +70,18 @@ pub (crate) fn adxl345_device_init(device: Arc<SpinLock<Adxl345>>) -> Result<()>
     // Unlocking before sleep
     coarse_sleep(Duration::from_millis(2)); 
 
+    // A short acquisition instead of the single test read, when requested
+    if samples > 1 {
+        let ret = adxl345_probe_acquire(&device, samples);
+        // Measurement is disabled whatever the outcome
+        let disabled = device.lock().disable_measure();
+        ret?;
+        return disabled.map_err(|e| {
+            pr_err!("Failed to disable measurement\n");
+            e
+        });
+    }
+
     // Reacquire lock to perform data read
     let adxl = device.lock();
 
@@ -86,7 +105,7 @@ pub (crate) fn adxl345_device_init(device: Arc<SpinLock<Adxl345>>) -> Result<()>
         e
     })
 
-    // Here the spinlock guard will be automatically 
+    // Here the mutex guard will be automatically 
     // dropped cause goes out of scope
 }
 
@@ -95,15 +114,15 @@ pub (crate) fn adxl345_device_init(device: Arc<SpinLock<Adxl345>>) -> Result<()>
 /// Function that cleans up the ADXL345 device by disabling interrupts and
 /// setting it to standby mode.
 ///
-/// This function locks the provided `Spinlock<Adxl345>` as needed to manage concurrent access.
+/// This function locks the provided `Mutex<Adxl345>` as needed to manage concurrent access.
 ///
 /// # Parameters
-/// - `device`: A reference to the `Spinlock<Adxl345>` instance to clean up.
+/// - `device`: A reference to the `Mutex<Adxl345>` instance to clean up.
 ///
 /// # Returns
 /// - `Ok(())` if the cleanup operations complete without errors.
 /// - `Err(Error)` if any I/O error occurs during the cleanup process.
-pub (crate) fn adxl345_device_clean(device: Arc<SpinLock<Adxl345>>) -> Result<()> {
+pub (crate) fn adxl345_device_clean(device: Arc<Mutex<Adxl345>>) -> Result<()> {
     // Acquire lock on the entire Adxl345 instance
     let adxl = device.lock();
 
@@ -126,15 +145,15 @@ pub (crate) fn adxl345_device_clean(device: Arc<SpinLock<Adxl345>>) -> Result<()
 /// Function to initialize the ADXL345 device at file open time.
 /// This enables measurement mode and waits for the device wake-up time.
 ///
-/// This function locks the provided `Ref<Spinlock<Adxl345>>` as needed to manage concurrent access.
+/// This function locks the provided `Ref<Mutex<Adxl345>>` as needed to manage concurrent access.
 ///
 /// # Parameters
-/// - `device`: A reference to the `Spinlock<Adxl345>` instance to initialize at open time.
+/// - `device`: A reference to the `Mutex<Adxl345>` instance to initialize at open time.
 ///
 /// # Returns
 /// - `Ok(())` if the initialization is successful.
 /// - `Err(Error)` if enabling measurement fails.
-pub (crate) fn adxl345_device_init_at_open(device: Arc<SpinLock<Adxl345>>) -> Result<()> {
+pub (crate) fn adxl345_device_init_at_open(device: Arc<Mutex<Adxl345>>) -> Result<()> {
     // Acquire lock on the entire Adxl345 instance
     let adxl = device.lock();
 
@@ -155,11 +174,11 @@ pub (crate) fn adxl345_device_init_at_open(device: Arc<SpinLock<Adxl345>>) -> Re
 
 /// Function to clean up the ADXL345 device at file release time by disabling measurement mode.
 ///
-/// This function locks the provided `Spinlock<Adxl345>` as needed to manage concurrent access.
+/// This function locks the provided `Mutex<Adxl345>` as needed to manage concurrent access.
 ///
 /// # Parameters
-/// - `device`: A reference to the `Spinlock<Adxl345>` instance to clean up at release time.
-pub (crate) fn adxl345_device_clean_at_release(device: Arc<SpinLock<Adxl345>>) {
+/// - `device`: A reference to the `Mutex<Adxl345>` instance to clean up at release time.
+pub (crate) fn adxl345_device_clean_at_release(device: Arc<Mutex<Adxl345>>) {
     // Acquire lock on the entire Adxl345 instance
     let adxl = device.lock();
 
@@ -168,3 +187,44 @@ pub (crate) fn adxl345_device_clean_at_release(device: Arc<SpinLock<Adxl345>>) {
 
     // Lock is automatically dropped when `adxl` goes out of scope
 }
+
+/// Starts a measurement session: enables measurement mode, then drains the device into a
+/// freshly emptied kernel buffer.
+///
+/// Called by open() and by `ADXL345_IOC_START`, it does nothing if a session is running.
+///
+/// # Parameters
+/// - `device`: The device to enable.
+/// - `drain`: The drain of `device`.
+///
+/// # Returns
+/// - `Ok(())` if the session is running.
+/// - `Err(EIO)` if measurement mode can't be enabled, the drain is not started then.
+/// - `Err(ENODEV)` if the device was removed.
+pub (crate) fn adxl345_stream_start(device: Arc<Mutex<Adxl345>>, drain: &Arc<Adxl345Drain>) -> Result<()> {
+    if drain.is_removed() {
+        return Err(ENODEV);
+    }
+    if drain.is_running() {
+        return Ok(());
+    }
+
+    adxl345_device_init_at_open(device).map_err(|_| EIO)?;
+    Adxl345Drain::start(drain);
+    Ok(())
+}
+
+/// Stops the measurement session: stops the drain, then puts the device in standby.
+///
+/// Called by release and by `ADXL345_IOC_STOP`. The samples already buffered stay readable,
+/// the configuration is kept.
+///
+/// # Parameters
+/// - `device`: The device to put in standby.
+/// - `drain`: The drain of `device`.
+pub (crate) fn adxl345_stream_stop(device: Arc<Mutex<Adxl345>>, drain: &Adxl345Drain) {
+    // The drain must not touch the device once measurements are disabled
+    drain.stop();
+    adxl345_alarm(drain.id()).release();
+    adxl345_device_clean_at_release(device);
+}
@@ -0,0 +1,189 @@
+/*
+ * Copyright 2024 Luca Saverio Esposito, Università di Roma, Tor Vergata
+ * email: <lucasaverioesposito@gmail.com>
+ *
+ * This file is part of an "Rust Linux driver for the ADXL345 device".
+ *
+ * This driver is free software: you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * This driver is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with Foobar.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+// version.rs
+
+//! Version of the driver and of its user space ABI.
+//!
+//! The ABI is everything a library relies on: the ioctl commands and their arguments, the record
+//! layout and the stream markers. `ADXL345_ABI_VERSION` is raised whenever any of them grows, so
+//! a library can check that the driver is recent enough for a feature before using it. Changes
+//! are additive: a driver keeps serving the ABI of the lower versions.
+//!
+//! Both versions are returned by `ADXL345_IOC_GET_VERSION` and shown in sysfs, next to the module
+//! parameters:
+//!
+//! ```text
+//! $ cat /sys/module/adxl345/driver_version
+//! 0.1.0
+//! $ cat /sys/module/adxl345/abi_version
+//! 6
+//! ```
+//!
+//! A driver without this ioctl fails it with `ENOTTY`, libraries treat it as ABI version 0.
+
+use kernel::prelude::*;
+use kernel::bindings;
+use kernel::c_str;
+use kernel::error::code::ENODEV;
+use kernel::error::to_result;
+use kernel::io_buffer::WritableToBytes;
+use kernel::str::{CStr, CString};
+use kernel::ThisModule;
+use core::ffi::c_char;
+use core::ptr;
+use crate::scan::{adxl345_scan_show, adxl345_scan_store};
+
+/// Version of the driver, major, minor and patch.
+pub (crate) const ADXL345_DRIVER_VERSION: [u32; 3] = [0, 1, 0];
+
+/// Version of the user space ABI.
+///
+/// - 1: everything up to `ADXL345_IOC_GET_VERSION`.
+/// - 2: `ADXL345_IOC_GET_CAPS`.
+/// - 3: `ADXL345_IOC_SET_SIGIO_THRESHOLD`.
+/// - 4: `ADXL345_IOC_SET_ERROR_POLICY` and `ADXL345_MARKER_ERROR`.
+/// - 5: `ADXL345_IOC_SET_FILTER` and `ADXL345_IOC_GET_FILTER`.
+/// - 6: `ADXL345_IOC_SET_RESAMPLE`.
+/// - 7: `ADXL345_IOC_SET_TAP`, `ADXL345_IOC_GET_TAP` and `ADXL345_MARKER_TAP`.
+/// - 8: `ADXL345_IOC_SET_BURST`, `ADXL345_IOC_GET_BURST` and `ADXL345_MARKER_BURST`.
+/// - 9: `ADXL345_IOC_SET_MOTION`, `ADXL345_IOC_GET_MOTION`, `ADXL345_IOC_GET_MOTION_EVENT` and
+///   `POLLPRI` on motion events.
+/// - 10: `ADXL345_IOC_SET_POWER` and `ADXL345_IOC_GET_POWER`.
+/// - 11: `ADXL345_MARKER_EVENT`, `ADXL345_IOC_GET_EVENT` and `ADXL345_EVENT_ID` in uevents.
+/// - 12: `ADXL345_IOC_CALIBRATE`, `ADXL345_IOC_SET_OFFSETS` and `ADXL345_IOC_GET_OFFSETS`.
+/// - 13: `ADXL345_IOC_SET_PIPELINE`, `ADXL345_IOC_GET_PIPELINE` and `ADXL345_MARKER_LIMIT`.
+/// - 14: `ADXL345_IOC_SET_OUTPUT` and `ADXL345_IOC_GET_OUTPUT`.
+/// - 15: `ADXL345_OUTPUT_TIMESTAMPED`.
+/// - 16: `ADXL345_IOC_SET_BUFFER` and `ADXL345_IOC_GET_BUFFER`.
+/// - 17: `ADXL345_IOC_SET_RATE`, `ADXL345_IOC_GET_RATE`, `ADXL345_IOC_SET_RANGE` and
+///   `ADXL345_IOC_GET_RANGE`.
+pub (crate) const ADXL345_ABI_VERSION: u32 = 17;
+
+/// Versions returned by `ADXL345_IOC_GET_VERSION`.
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub (crate) struct Adxl345Version {
+    pub (crate) major: u32,     // Driver version
+    pub (crate) minor: u32,
+    pub (crate) patch: u32,
+    pub (crate) abi: u32,       // ABI version
+}
+
+// SAFETY: `Adxl345Version` is `repr(C)`, made only of integers and has no padding.
+unsafe impl WritableToBytes for Adxl345Version {}
+
+impl Adxl345Version {
+    /// Returns the versions of this driver.
+    pub (crate) const fn current() -> Self {
+        let [major, minor, patch] = ADXL345_DRIVER_VERSION;
+        Self { major, minor, patch, abi: ADXL345_ABI_VERSION }
+    }
+}
+
+/// The attributes added to the sysfs directory of the module, removed on drop.
+pub (crate) struct Adxl345Sysfs {
+    kobj: *mut bindings::kobject,
+    attrs: [bindings::module_attribute; 3],
+    attr_ptrs: [*mut bindings::attribute; 4], // NULL terminated
+    group: bindings::attribute_group,
+}
+
+// SAFETY: The structures are only written before the group is created, sysfs reads them.
+unsafe impl Sync for Adxl345Sysfs {}
+
+type ShowFn = unsafe extern "C" fn(*mut bindings::module_attribute, *mut bindings::module_kobject, *mut c_char) -> isize;
+type StoreFn = unsafe extern "C" fn(*mut bindings::module_attribute, *mut bindings::module_kobject, *const c_char, usize) -> isize;
+
+/// Adds `driver_version` and `abi_version` to `/sys/module/adxl345`, and `scan` (see scan.rs).
+///
+/// # Returns
+/// - `Ok(Box<Adxl345Sysfs>)` if the attributes are created.
+/// - `Err(Error)` if they are not, e.g. for a driver built in the kernel, which has no module
+///   directory; the versions are still returned by the ioctl.
+pub (crate) fn adxl345_sysfs_create(module: &'static ThisModule) -> Result<Box<Adxl345Sysfs>> {
+    let module = module.as_ptr();
+    if module.is_null() {
+        return Err(ENODEV);
+    }
+
+    // SAFETY: The C structures are valid when zeroed, the pointers are set below.
+    let mut sysfs = Box::try_new(unsafe { core::mem::zeroed::<Adxl345Sysfs>() })?;
+    let fs = &mut *sysfs;
+
+    let attrs: [(&CStr, u16, ShowFn, Option<StoreFn>); 3] = [
+        (c_str!("driver_version"), 0o444, adxl345_sysfs_driver_version_show, None),
+        (c_str!("abi_version"), 0o444, adxl345_sysfs_abi_version_show, None),
+        (c_str!("scan"), 0o600, adxl345_scan_show, Some(adxl345_scan_store)),
+    ];
+    for (index, (name, mode, show, store)) in attrs.into_iter().enumerate() {
+        fs.attrs[index].attr.name = name.as_char_ptr();
+        fs.attrs[index].attr.mode = mode;
+        fs.attrs[index].show = Some(show);
+        fs.attrs[index].store = store;
+        fs.attr_ptrs[index] = &mut fs.attrs[index].attr;
+    }
+    fs.group.attrs = fs.attr_ptrs.as_mut_ptr();
+
+    // SAFETY: The module outlives its own `Adxl345Module`, which owns the attributes; they stay
+    // allocated until they are removed on drop.
+    unsafe {
+        fs.kobj = &mut (*module).mkobj.kobj;
+        to_result(bindings::sysfs_create_group(fs.kobj, &fs.group))?;
+    }
+    Ok(sysfs)
+}
+
+impl Drop for Adxl345Sysfs {
+    fn drop(&mut self) {
+        // SAFETY: The group was created by `adxl345_sysfs_create`, removing it waits for the
+        // running `show()`.
+        unsafe { bindings::sysfs_remove_group(self.kobj, &self.group) };
+    }
+}
+
+/// Formats an attribute into the page provided by sysfs.
+fn adxl345_sysfs_show(page: *mut c_char, args: core::fmt::Arguments<'_>) -> isize {
+    let text = match CString::try_from_fmt(args) {
+        Ok(text) => text,
+        Err(e) => return e.to_errno() as isize,
+    };
+    let bytes = text.as_bytes();
+    // SAFETY: sysfs provides a page, much larger than the short values shown here.
+    unsafe { ptr::copy_nonoverlapping(bytes.as_ptr(), page as *mut u8, bytes.len()) };
+    bytes.len() as isize
+}
+
+unsafe extern "C" fn adxl345_sysfs_driver_version_show(
+    _attr: *mut bindings::module_attribute,
+    _mk: *mut bindings::module_kobject,
+    page: *mut c_char,
+) -> isize {
+    let [major, minor, patch] = ADXL345_DRIVER_VERSION;
+    adxl345_sysfs_show(page, fmt!("{}.{}.{}\n", major, minor, patch))
+}
+
+unsafe extern "C" fn adxl345_sysfs_abi_version_show(
+    _attr: *mut bindings::module_attribute,
+    _mk: *mut bindings::module_kobject,
+    page: *mut c_char,
+) -> isize {
+    adxl345_sysfs_show(page, fmt!("{}\n", ADXL345_ABI_VERSION))
+}